@@ -5,6 +5,7 @@ extern crate dustbox;
 
 use criterion::Criterion;
 
+use dustbox::cpu::{Decoder, Engine};
 use dustbox::machine::Machine;
 
 fn exec_simple_loop(c: &mut Criterion) {
@@ -20,6 +21,44 @@ fn exec_simple_loop(c: &mut Criterion) {
     c.bench_function("execute small jmp short loop", move |b| b.iter(|| machine.execute_instruction()));
 }
 
+fn exec_simple_loop_engines(c: &mut Criterion) {
+    let code: Vec<u8> = vec![
+        0xB9, 0xFF, 0xFF, // mov cx,0xffff
+        0x49,             // dec cx
+        0xEB, 0xFA,       // jmp short 0x100
+    ];
+
+    for &(name, engine) in &[("interpreter", Engine::Interpreter), ("threaded", Engine::Threaded)] {
+        let mut machine = Machine::default();
+        machine.engine = engine;
+        machine.load_executable(&code);
+
+        c.bench_function(&format!("execute 1000 instructions of small jmp short loop, {}", name), move |b| b.iter(|| machine.execute_instructions(1000)));
+    }
+}
+
+fn exec_loop_decoder_cache(c: &mut Criterion) {
+    let code: Vec<u8> = vec![
+        0xB9, 0x00, 0x01,               // mov cx,0x100
+        0xBE, 0x00, 0x02,               // mov si,0x200
+        0x8B, 0x04,                     // mov ax,[si]
+        0x26, 0x03, 0x84, 0x34, 0x12,   // add ax,[es:si+0x1234]
+        0x81, 0xC6, 0x02, 0x00,         // add si,0x2
+        0xE2, 0xF4,                     // loop 0x106
+        0xEB, 0xEB,                     // jmp short 0x100
+    ];
+
+    for &(name, cached) in &[("cached", true), ("uncached", false)] {
+        let mut machine = Machine::default();
+        if !cached {
+            machine.cpu.decoder = Decoder::default();
+        }
+        machine.load_executable(&code);
+
+        c.bench_function(&format!("execute 1000 instructions of memory operand loop, {} decoder", name), move |b| b.iter(|| machine.execute_instructions(1000)));
+    }
+}
+
 fn disasm_small_prog(c: &mut Criterion) {
     let mut machine = Machine::default();
     let code: Vec<u8> = vec![
@@ -37,5 +76,5 @@ fn disasm_small_prog(c: &mut Criterion) {
     c.bench_function("disasm small prog", move |b| b.iter(|| machine.cpu.decoder.disassemble_block_to_str(&mut machine.hw.mmu, 0x85F, 0x100, 8)));
 }
 
-criterion_group!(benches, exec_simple_loop, disasm_small_prog);
+criterion_group!(benches, exec_simple_loop, exec_simple_loop_engines, exec_loop_decoder_cache, disasm_small_prog);
 criterion_main!(benches);
@@ -2,9 +2,11 @@ use std::time::Instant;
 use std::num::ParseIntError;
 use std::io::Error as IoError;
 use std::process::exit;
+use std::path::Path;
+use std::collections::BTreeMap;
 
 use dustbox::machine::Machine;
-use dustbox::cpu::{R, RegisterSnapshot, Decoder};
+use dustbox::cpu::{R, RegisterSnapshot, Decoder, Engine, CpuModel};
 use dustbox::tools;
 use dustbox::memory::MemoryAddress;
 
@@ -18,7 +20,7 @@ mod debugger_test;
 pub struct Debugger {
     pub machine: Machine,
     pub prev_regs: RegisterSnapshot,
-    last_program: Option<String>,
+    last_program: Option<(String, String)>,
 
     /// break when IP reach these addresses
     ip_breakpoints: Breakpoints,
@@ -88,9 +90,10 @@ impl Debugger {
 
     pub fn step_over(&mut self) {
         let mut decoder = Decoder::default();
+        decoder.set_model(self.machine.cpu.model());
         let cs = self.machine.cpu.get_r16(R::CS);
         let op = decoder.get_instruction_info(&mut self.machine.hw.mmu, cs, self.machine.cpu.regs.ip);
-        let dst = MemoryAddress::RealSegmentOffset(cs, self.machine.cpu.regs.ip + op.bytes.len() as u16);
+        let dst = MemoryAddress::RealSegmentOffset(cs, self.machine.cpu.regs.ip as u16 + op.bytes.len() as u16);
         println!("Step-over running to {:04X}:{:04X}", dst.segment(), dst.offset());
 
         let mut cnt = 0;
@@ -115,6 +118,7 @@ impl Debugger {
 
     pub fn disasm_n_instructions_to_text(&mut self, n: usize) -> String {
         let mut decoder = Decoder::default();
+        decoder.set_model(self.machine.cpu.model());
         decoder.disassemble_block_to_str(&mut self.machine.hw.mmu, self.machine.cpu.get_r16(R::CS), self.machine.cpu.regs.ip, n)
     }
 
@@ -145,13 +149,15 @@ impl Debugger {
 
          match parts[0].as_ref() {
             "help" => {
-                println!("load <file>                      - load a binary (.com) file");
+                println!("load <file> [args]               - load a binary (.com) file, with optional arguments");
                 println!("load                             - load previous binary (.com) file");
                 println!("run                              - run until breakpoint");
                 println!("step into <n>                    - steps into n instructions");
                 println!("step over                        - steps over the next instruction");
                 println!("reset                            - resets the cpu");
                 println!("instcount                        - show number of instructions executed");
+                println!("engine <interpreter|threaded>    - select how run executes instructions");
+                println!("model <8086|186|286|386|486>     - select the emulated cpu");
                 println!("reg                              - show register values");
                 println!("bp add <seg:off>                 - add breakpoint");
                 println!("bp remove <seg:off>              - remove breakpoint");
@@ -165,6 +171,7 @@ impl Debugger {
                 println!("disasm                           - disasm instruction");
                 println!("hexdump <seg:off> <len>          - dumps len bytes of memory at given offset to the console");
                 println!("bindump <seg:off> <len> <file>   - writes memory dump to file");
+                println!("mcb                              - show the DOS memory control block chain");
                 println!("exit                             - exit");
             }
             "step" => {
@@ -190,6 +197,21 @@ impl Debugger {
                     }
                 }
             }
+            "mcb" => {
+                match self.machine.hw.dos.memory.blocks(&self.machine.hw.mmu) {
+                    Ok(blocks) => {
+                        for mcb in blocks {
+                            println!("MCB {:04X}: block {:04X}, size {:04X}, owner {}{}",
+                                     mcb.segment,
+                                     mcb.block_segment(),
+                                     mcb.size,
+                                     if mcb.is_free() { "free".to_string() } else { format!("{:04X}", mcb.owner) },
+                                     if mcb.last { ", last" } else { "" });
+                        }
+                    }
+                    Err(e) => println!("mcb chain error: {:?}", e),
+                }
+            }
             "reset" => {
                 println!("Resetting machine");
                 self.machine.hard_reset();
@@ -202,6 +224,40 @@ impl Debugger {
             "instcount" => {
                 println!("Executed {} instructions", self.machine.cpu.instruction_count);
             }
+            "engine" => {
+                if parts.len() < 2 {
+                    println!("Engine is {:?}", self.machine.engine);
+                    return;
+                }
+                match parts[1].as_ref() {
+                    "interpreter" => self.machine.engine = Engine::Interpreter,
+                    "threaded" => self.machine.engine = Engine::Threaded,
+                    _ => {
+                        println!("Unknown engine: {}", parts[1]);
+                        return;
+                    }
+                }
+                println!("Engine is {:?}", self.machine.engine);
+            }
+            "model" => {
+                if parts.len() < 2 {
+                    println!("CPU model is {:?}", self.machine.cpu.model());
+                    return;
+                }
+                let model = match parts[1].as_ref() {
+                    "8086" => CpuModel::I8086,
+                    "186" => CpuModel::I80186,
+                    "286" => CpuModel::I80286,
+                    "386" => CpuModel::I80386,
+                    "486" => CpuModel::I80486,
+                    _ => {
+                        println!("Unknown cpu model: {}", parts[1]);
+                        return;
+                    }
+                };
+                self.machine.set_cpu_model(model);
+                println!("CPU model is {:?}", self.machine.cpu.model());
+            }
             "reg" | "regs" | "registers" => {
                 self.print_registers();
             }
@@ -320,6 +376,7 @@ impl Debugger {
             }
             "d" | "disasm" => {
                 let mut decoder = Decoder::default();
+                decoder.set_model(self.machine.cpu.model());
                 let op = decoder.get_instruction_info(&mut self.machine.hw.mmu, self.machine.cpu.get_r16(R::CS), self.machine.cpu.regs.ip);
                 println!("{:?}", op);
                 println!("{}", op);
@@ -327,13 +384,14 @@ impl Debugger {
             "load" => {
                 if parts.len() < 2 {
                     match self.last_program.clone() {
-                        None       => println!("Filename not provided."),
-                        Some(path) => self.load_executable(&path),
+                        None               => println!("Filename not provided."),
+                        Some((path, args)) => self.load_executable(&path, &args),
                     }
                 } else {
-                    let path = parts[1..].join(" ").trim().to_string();
-                    self.load_executable(&path);
-                    self.last_program = Option::Some(path);
+                    let path = parts[1].to_string();
+                    let args = parts[2..].join(" ").trim().to_string();
+                    self.load_executable(&path, &args);
+                    self.last_program = Option::Some((path, args));
                 }
             }
             "hexdump" => {
@@ -416,13 +474,24 @@ impl Debugger {
         }
     }
 
-    /// loads a .com or .exe file
-    pub fn load_executable(&mut self, name: &str) {
+    /// loads a .com or .exe file, passing args on the command line
+    pub fn load_executable(&mut self, name: &str, args: &str) {
         println!("Reading executable from {}", name);
         match tools::read_binary(name) {
             Ok(data) => {
                 self.machine.hard_reset();
+                // mount the program directory as drive C:
+                let dir = match Path::new(name).parent() {
+                    Some(p) if !p.as_os_str().is_empty() => p,
+                    _ => Path::new("."),
+                };
+                self.machine.mount(dir);
                 self.machine.load_executable(&data);
+                let file_name = match Path::new(name).file_name() {
+                    Some(s) => s.to_string_lossy().into_owned(),
+                    None => name.to_string(),
+                };
+                self.machine.build_psp(format!("{} {}", file_name, args).trim_end(), &BTreeMap::new());
             }
             Err(what) => println!("error {}", what),
         };
@@ -17,7 +17,6 @@ use gdk_pixbuf;
 use cairo;
 
 use dustbox::cpu::{CPU, R};
-use dustbox::gpu::VideoModeBlock;
 
 use debugger;
 
@@ -87,7 +86,7 @@ impl Interface {
             canvas.connect_draw(move |_, ctx| {
                 let app = app.borrow();
                 let frame = app.machine.hw.gpu.render_frame(&app.machine.hw.mmu);
-                draw_canvas(ctx, frame, &app.machine.hw.gpu.mode);
+                draw_canvas(ctx, frame, app.machine.hw.gpu.frame_size());
                 ctx.paint();
                 Inhibit(false)
             });
@@ -285,7 +284,7 @@ impl Interface {
 }
 
 /// render video frame to canvas `c`
-fn draw_canvas(c: &cairo::Context, buf: Vec<u8>, mode: &VideoModeBlock) {
+fn draw_canvas(c: &cairo::Context, buf: Vec<u8>, (width, height): (u32, u32)) {
     if buf.is_empty() {
         // println!("draw_canvas: no buffer to draw!");
         return;
@@ -295,9 +294,9 @@ fn draw_canvas(c: &cairo::Context, buf: Vec<u8>, mode: &VideoModeBlock) {
         gdk_pixbuf::Colorspace::Rgb,
         false,
         8,
-        mode.swidth as i32,
-        mode.sheight as i32,
-        mode.swidth as i32 * 3);
+        width as i32,
+        height as i32,
+        width as i32 * 3);
     c.set_source_pixbuf(&pixbuf, 0., 0.);
 }
 
@@ -41,10 +41,10 @@ fn flat_disassembly(filename: &str) {
     }
 
     let mut decoder = Decoder::default();
-    let mut ma = MemoryAddress::RealSegmentOffset(machine.cpu.get_r16(R::CS), machine.cpu.regs.ip);
+    let mut ma = MemoryAddress::RealSegmentOffset(machine.cpu.get_r16(R::CS), machine.cpu.regs.ip as u16);
 
     loop {
-        let op = decoder.get_instruction_info(&mut machine.hw.mmu, ma.segment(), ma.offset());
+        let op = decoder.get_instruction_info(&mut machine.hw.mmu, ma.segment(), u32::from(ma.offset()));
         println!("{}", op);
         ma.inc_n(op.bytes.len() as u16);
         if ma.value() - machine.cpu.rom_base >= machine.cpu.rom_length {
@@ -30,7 +30,7 @@ impl Tracer {
 
     pub fn trace_execution(&mut self, machine: &mut Machine) {
         // tell tracer to start at CS:IP
-        let ma = MemoryAddress::RealSegmentOffset(machine.cpu.get_r16(R::CS), machine.cpu.regs.ip);
+        let ma = MemoryAddress::RealSegmentOffset(machine.cpu.get_r16(R::CS), machine.cpu.regs.ip as u16);
         self.seen_destinations.push(SeenDestination{address: ma, visited: false, sources: Vec::new()});
 
         loop {
@@ -51,7 +51,7 @@ impl Tracer {
         let mut decoder = Decoder::default();
         for ma in &self.visited_addresses {
             // decode op at this address and show it
-            let ii = decoder.get_instruction_info(&mut machine.hw.mmu, ma.segment(), ma.offset());
+            let ii = decoder.get_instruction_info(&mut machine.hw.mmu, ma.segment(), u32::from(ma.offset()));
 
             if prev_end != 0 {
                 if prev_end != ma.value() {
@@ -165,7 +165,7 @@ impl Tracer {
         let mut decoder = Decoder::default();
 
         loop {
-            let ii = decoder.get_instruction_info(&mut machine.hw.mmu, ma.segment(), ma.offset());
+            let ii = decoder.get_instruction_info(&mut machine.hw.mmu, ma.segment(), u32::from(ma.offset()));
             if DEBUG_TRACER {
                 println!("Found {}", ii);
             }
@@ -25,7 +25,7 @@ fn main() {
         Op::Shr8, Op::Sar8,
         Op::Cmp8, Op::And8, Op::Xor8, Op::Or8, Op::Add8, Op::Adc8, Op::Sub8, Op::Sbb8,
         Op::Test8, Op::Not8, Op::Mul8, Op::Imul8, Op::Xchg8,
-        Op::Div8, Op::Idiv8, // hard to fuzz due to input that triggers DIV0 exception
+        Op::Div8, Op::Idiv8,
         Op::Neg8, // mov ah,0; neg ah =   OVERFLOW flag differs vs winxp
         Op::Lahf,
         Op::Sahf, Op::Salc,
@@ -14,6 +14,11 @@ impl BIOS {
     pub const DATA_SEG: u16           = 0x0040; // bios data segment, 256 byte at 000400 to 0004FF
 
     pub const DATA_INITIAL_MODE: u16  = 0x0010;
+    pub const DATA_KBD_FLAGS1: u16    = 0x0017; // shift and lock state
+    pub const DATA_KBD_FLAGS2: u16    = 0x0018; // keys held down
+    pub const DATA_KBD_HEAD: u16      = 0x001A; // next key to read
+    pub const DATA_KBD_TAIL: u16      = 0x001C; // next free slot
+    pub const DATA_KBD_BUFFER: u16    = 0x001E; // 16 words keyboard circular buffer
     pub const DATA_CURRENT_MODE: u16  = 0x0049;
     pub const DATA_NB_COLS: u16       = 0x004A;
     pub const DATA_PAGE_SIZE: u16     = 0x004C;
@@ -24,6 +29,10 @@ impl BIOS {
     pub const DATA_CRTC_ADDRESS: u16  = 0x0063;
     pub const DATA_CURRENT_MSR: u16   = 0x0065;
     pub const DATA_CURRENT_PAL: u16   = 0x0066;
+    pub const DATA_TIMER_TICKS: u16   = 0x006C; // dword, timer ticks since midnight
+    pub const DATA_TIMER_OVERFLOW: u16 = 0x0070; // nonzero if midnight passed since last read
+    pub const DATA_KBD_START: u16     = 0x0080; // offset of keyboard buffer start
+    pub const DATA_KBD_END: u16       = 0x0082; // offset of keyboard buffer end
     pub const DATA_NB_ROWS: u16       = 0x0084;
     pub const DATA_CHAR_HEIGHT: u16   = 0x0085;
     pub const DATA_VIDEO_CTL: u16     = 0x0087;
@@ -31,10 +40,13 @@ impl BIOS {
     pub const DATA_MODESET_CTL: u16   = 0x0089;
     pub const DATA_DCC_INDEX: u16     = 0x008A;
     pub const DATA_CRTCPU_PAGE: u16   = 0x008A;
+    pub const DATA_KBD_FLAGS3: u16    = 0x0096; // E0/E1 prefix and right ctrl/alt state
+    pub const DATA_KBD_LEDS: u16      = 0x0097;
     pub const DATA_VS_POINTER: u16    = 0x00A8;
 
     const ROM_SEG: u16                = 0xF000; // bios rom segment, 64k at F_0000 to F_FFFF
     const ROM_EQUIPMENT_WORD: u16     = 0x0410;
+    pub const ROM_TIMER_CHAIN: u16    = 0xFEA5; // code of the IRQ 0 handler following the tick count
 
     pub fn default() -> Self {
         // XXX see ROMBIOS_Init in dosbox-x
@@ -84,7 +96,22 @@ impl BIOS {
 
     pub fn init(&mut self, mut mmu: &mut MMU) {
         self.init_ivt(&mut mmu);
+        self.write_timer_chain(&mut mmu);
         self.write_configuration_data_table(&mut mmu);
+        self.init_keyboard_buffer(&mut mmu);
+    }
+
+    fn init_keyboard_buffer(&self, mmu: &mut MMU) {
+        let start = BIOS::DATA_KBD_BUFFER;
+        let end = BIOS::DATA_KBD_BUFFER + 32;
+        mmu.write_u16(BIOS::DATA_SEG, BIOS::DATA_KBD_START, start);
+        mmu.write_u16(BIOS::DATA_SEG, BIOS::DATA_KBD_END, end);
+        mmu.write_u16(BIOS::DATA_SEG, BIOS::DATA_KBD_HEAD, start);
+        mmu.write_u16(BIOS::DATA_SEG, BIOS::DATA_KBD_TAIL, start);
+        mmu.write_u8(BIOS::DATA_SEG, BIOS::DATA_KBD_FLAGS1, 0);
+        mmu.write_u8(BIOS::DATA_SEG, BIOS::DATA_KBD_FLAGS2, 0);
+        mmu.write_u8(BIOS::DATA_SEG, BIOS::DATA_KBD_FLAGS3, 0x10); // 101/102-key keyboard installed
+        mmu.write_u8(BIOS::DATA_SEG, BIOS::DATA_KBD_LEDS, 0);
     }
 
     fn init_ivt(&mut self, mmu: &mut MMU) {
@@ -95,6 +122,24 @@ impl BIOS {
         }
     }
 
+    /// writes the code the IRQ 0 handler continues with after counting the tick. it calls the user timer
+    /// tick and acknowledges the interrupt. it is outside of the interrupt stubs, which run the high-level
+    /// handler of their vector
+    fn write_timer_chain(&self, mmu: &mut MMU) {
+        let code: [u8; 9] = [
+            0xCD, 0x1C, // int 0x1c
+            0x50,       // push ax
+            0xB0, 0x20, // mov al,0x20
+            0xE6, 0x20, // out 0x20,al
+            0x58,       // pop ax
+            0xCF,       // iret
+        ];
+        let mut addr = MemoryAddress::RealSegmentOffset(BIOS::ROM_SEG, BIOS::ROM_TIMER_CHAIN);
+        for b in code.iter() {
+            mmu.write_u8_inc(&mut addr, *b);
+        }
+    }
+
     fn write_ivt_entry(&self, mmu: &mut MMU, number: u8, seg: u16, offset: u16) {
         let _seg = 0;
         let _offset = u16::from(number) * 4;
@@ -127,3 +172,45 @@ pub fn cursor_pos_col(mmu: &MMU, page: u8) -> u8 {
 pub fn cursor_pos_row(mmu: &MMU, page: u8) -> u8 {
     mmu.read_u8(BIOS::DATA_SEG, BIOS::DATA_CURSOR_POS + (u16::from(page) * 2) + 1)
 }
+
+/// adds a key (scancode in high byte, ASCII in low byte) to the keyboard buffer.
+/// returns false if the buffer is full
+pub fn add_key_to_buffer(mmu: &mut MMU, key: u16) -> bool {
+    let start = mmu.read_u16(BIOS::DATA_SEG, BIOS::DATA_KBD_START);
+    let end = mmu.read_u16(BIOS::DATA_SEG, BIOS::DATA_KBD_END);
+    let head = mmu.read_u16(BIOS::DATA_SEG, BIOS::DATA_KBD_HEAD);
+    let tail = mmu.read_u16(BIOS::DATA_SEG, BIOS::DATA_KBD_TAIL);
+    let mut next = tail + 2;
+    if next >= end {
+        next = start;
+    }
+    if next == head {
+        return false;
+    }
+    mmu.write_u16(BIOS::DATA_SEG, tail, key);
+    mmu.write_u16(BIOS::DATA_SEG, BIOS::DATA_KBD_TAIL, next);
+    true
+}
+
+/// returns the next key in the keyboard buffer without removing it
+pub fn peek_key_from_buffer(mmu: &MMU) -> Option<u16> {
+    let head = mmu.read_u16(BIOS::DATA_SEG, BIOS::DATA_KBD_HEAD);
+    let tail = mmu.read_u16(BIOS::DATA_SEG, BIOS::DATA_KBD_TAIL);
+    if head == tail {
+        return None;
+    }
+    Some(mmu.read_u16(BIOS::DATA_SEG, head))
+}
+
+/// removes and returns the next key in the keyboard buffer
+pub fn get_key_from_buffer(mmu: &mut MMU) -> Option<u16> {
+    let key = peek_key_from_buffer(mmu)?;
+    let start = mmu.read_u16(BIOS::DATA_SEG, BIOS::DATA_KBD_START);
+    let end = mmu.read_u16(BIOS::DATA_SEG, BIOS::DATA_KBD_END);
+    let mut head = mmu.read_u16(BIOS::DATA_SEG, BIOS::DATA_KBD_HEAD) + 2;
+    if head >= end {
+        head = start;
+    }
+    mmu.write_u16(BIOS::DATA_SEG, BIOS::DATA_KBD_HEAD, head);
+    Some(key)
+}
@@ -0,0 +1,304 @@
+// basic block engine
+//
+// Straight-line runs of instructions are decoded once into blocks of micro-ops, with their
+// operands and cycle counts bound in advance. Common register operations run directly, all
+// other instructions fall back to CPU::execute. A block is checked against the decoded
+// instruction cache before it runs, so self-modifying code is seen as by the interpreter.
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+use cpu::CPU;
+use cpu::decoder::AddressSize;
+use cpu::instruction::{Instruction, RepeatMode};
+use cpu::parameter::Parameter;
+use cpu::op::Op;
+use cpu::register::R;
+use cpu::timing::{TimingProfile, instruction_cycles};
+use hardware::Hardware;
+
+#[cfg(test)]
+#[path = "./block_test.rs"]
+mod block_test;
+
+/// number of entries in the block cache, a power of 2
+const BLOCK_CACHE_SIZE: usize = 0x1000;
+
+/// most instructions in a block
+const MAX_BLOCK_LENGTH: usize = 32;
+
+/// selects how the machine runs instructions
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Engine {
+    /// decodes and executes one instruction at a time
+    Interpreter,
+
+    /// runs basic blocks of micro-ops
+    Threaded,
+}
+
+/// a operation with its operands bound
+#[derive(Clone, Debug, PartialEq)]
+enum MicroOp {
+    /// runs the instruction with CPU::execute
+    Execute,
+    Nop,
+    MovR8Imm(R, u8),
+    MovR8R8(R, R),
+    MovR16Imm(R, u16),
+    MovR16R16(R, R),
+    IncR16(R),
+    DecR16(R),
+    CmpR8Imm(R, u8),
+    CmpR16Imm(R, u16),
+    CmpR16R16(R, R),
+    Jump(u16),
+    /// conditional jump to offset
+    Branch(u16),
+    Loop(u16),
+}
+
+struct BlockOp {
+    micro_op: MicroOp,
+    instruction: Rc<Instruction>,
+
+    /// linear address and segment offset of the instruction
+    address: u32,
+    offset: u16,
+
+    /// cycles when the instruction continues with the next one, and when it branches
+    cycles: usize,
+    taken_cycles: usize,
+}
+
+pub struct BasicBlock {
+    address: u32,
+    offset: u16,
+    ops: Vec<BlockOp>,
+    timing: TimingProfile,
+
+    /// decoder cache generation the instructions were last checked against
+    generation: Cell<usize>,
+}
+
+#[derive(Default)]
+pub struct BlockCache {
+    /// allocated on first use
+    entries: Vec<Option<Rc<BasicBlock>>>,
+}
+
+impl BlockCache {
+    /// returns the block starting at linear address, from segment offset
+    fn get(&self, address: u32, offset: u16) -> Option<Rc<BasicBlock>> {
+        match self.entries.get(address as usize & (BLOCK_CACHE_SIZE - 1)) {
+            Some(Some(block)) if block.address == address && block.offset == offset => Some(Rc::clone(block)),
+            _ => None,
+        }
+    }
+
+    fn insert(&mut self, block: Rc<BasicBlock>) {
+        if self.entries.is_empty() {
+            self.entries = vec![None; BLOCK_CACHE_SIZE];
+        }
+        let index = block.address as usize & (BLOCK_CACHE_SIZE - 1);
+        self.entries[index] = Some(block);
+    }
+}
+
+impl CPU {
+    /// runs up to max instructions of the basic block at CS:IP. returns the number of instructions
+    /// executed, 0 if no block could be decoded at CS:IP. 32-bit code segments are left to the interpreter
+    pub fn execute_block(&mut self, hw: &mut Hardware, max: usize) -> usize {
+        if self.code32() || self.regs.ip > 0xFFFF {
+            return 0;
+        }
+        let cs = self.get_r16(R::CS);
+        let ip = self.regs.ip as u16;
+        let address = hw.mmu.linear(cs, u32::from(ip));
+        self.decoder.invalidate_modified(&hw.mmu);
+        let block = match self.blocks.get(address, ip) {
+            Some(ref block) if self.block_is_valid(block) => Rc::clone(block),
+            _ => {
+                let block = Rc::new(self.build_block(hw, cs, ip));
+                if block.ops.is_empty() {
+                    return 0;
+                }
+                self.blocks.insert(Rc::clone(&block));
+                block
+            }
+        };
+
+        let mut executed = 0;
+        for op in block.ops.iter().take(max) {
+            let next_ip = op.offset.wrapping_add(u16::from(op.instruction.length));
+            if op.micro_op == MicroOp::Execute {
+                self.execute(hw, &op.instruction);
+                if hw.mmu.memory.borrow().has_modified_code() {
+                    // the rest of the block may have been overwritten
+                    executed += 1;
+                    break;
+                }
+            } else {
+                self.execute_micro_op(op, next_ip);
+            }
+            executed += 1;
+            if self.regs.ip != u32::from(next_ip) || self.get_r16(R::CS) != cs || self.fatal_error {
+                break;
+            }
+        }
+        executed
+    }
+
+    /// runs a bound operation, with the effects of CPU::execute on the registers and counters
+    fn execute_micro_op(&mut self, op: &BlockOp, next_ip: u16) {
+        self.instruction_start = u32::from(op.offset);
+        self.regs.ip = u32::from(next_ip);
+        self.instruction_count += 1;
+        self.repeating = false;
+        match op.micro_op {
+            MicroOp::Execute | MicroOp::Nop => {}
+            MicroOp::MovR8Imm(r, v) => self.set_r8(r, v),
+            MicroOp::MovR8R8(dst, src) => {
+                let v = self.get_r8(src);
+                self.set_r8(dst, v);
+            }
+            MicroOp::MovR16Imm(r, v) => self.set_r16(r, v),
+            MicroOp::MovR16R16(dst, src) => {
+                let v = self.get_r16(src);
+                self.set_r16(dst, v);
+            }
+            MicroOp::IncR16(r) => {
+                let dst = usize::from(self.get_r16(r));
+                let res = self.inc16(dst);
+                self.set_r16(r, res);
+            }
+            MicroOp::DecR16(r) => {
+                let dst = usize::from(self.get_r16(r));
+                let res = self.dec16(dst);
+                self.set_r16(r, res);
+            }
+            MicroOp::CmpR8Imm(r, v) => {
+                let dst = usize::from(self.get_r8(r));
+                self.cmp8(dst, usize::from(v));
+            }
+            MicroOp::CmpR16Imm(r, v) => {
+                let dst = usize::from(self.get_r16(r));
+                self.cmp16(dst, usize::from(v));
+            }
+            MicroOp::CmpR16R16(dst, src) => {
+                let dst = usize::from(self.get_r16(dst));
+                let src = usize::from(self.get_r16(src));
+                self.cmp16(dst, src);
+            }
+            MicroOp::Jump(target) => self.regs.ip = u32::from(target),
+            MicroOp::Branch(target) => {
+                if self.jump_condition(&op.instruction.command) {
+                    self.regs.ip = u32::from(target);
+                }
+            }
+            MicroOp::Loop(target) => {
+                let cx = self.get_r16(R::CX).wrapping_sub(1);
+                self.set_r16(R::CX, cx);
+                if cx != 0 {
+                    self.regs.ip = u32::from(target);
+                }
+            }
+        }
+        self.cycle_count += if self.regs.ip == u32::from(next_ip) { op.cycles } else { op.taken_cycles };
+    }
+
+    /// returns true if the instructions of the block are unchanged
+    fn block_is_valid(&self, block: &BasicBlock) -> bool {
+        if block.timing != self.timing {
+            return false;
+        }
+        let generation = self.decoder.cache_generation();
+        if block.generation.get() != generation {
+            for op in &block.ops {
+                match self.decoder.cached_instruction(op.address, u32::from(op.offset)) {
+                    Some(ref instruction) if Rc::ptr_eq(instruction, &op.instruction) => {}
+                    _ => return false,
+                }
+            }
+            block.generation.set(generation);
+        }
+        true
+    }
+
+    /// decodes the block at cs:ip. it ends at the first instruction that can't be cached or
+    /// that changes the flow of execution
+    fn build_block(&mut self, hw: &mut Hardware, cs: u16, ip: u16) -> BasicBlock {
+        let address = hw.mmu.linear(cs, u32::from(ip));
+        let mut ops = Vec::new();
+        let mut offset = ip;
+        while ops.len() < MAX_BLOCK_LENGTH {
+            let instruction = self.decoder.get_shared_instruction(&mut hw.mmu, cs, u32::from(offset));
+            let linear = hw.mmu.linear(cs, u32::from(offset));
+            match self.decoder.cached_instruction(linear, u32::from(offset)) {
+                Some(ref cached) if Rc::ptr_eq(cached, &instruction) => {}
+                _ => break,
+            }
+            let ends = ends_block(&instruction);
+            ops.push(BlockOp {
+                micro_op: bind(&instruction),
+                address: linear,
+                offset,
+                cycles: instruction_cycles(self.timing, &instruction, false, true, 0),
+                taken_cycles: instruction_cycles(self.timing, &instruction, true, true, 0),
+                instruction: Rc::clone(&instruction),
+            });
+            if ends {
+                break;
+            }
+            offset = offset.wrapping_add(u16::from(instruction.length));
+        }
+        BasicBlock {
+            address,
+            offset: ip,
+            ops,
+            timing: self.timing,
+            generation: Cell::new(self.decoder.cache_generation()),
+        }
+    }
+}
+
+/// selects the micro-op running the instruction
+fn bind(op: &Instruction) -> MicroOp {
+    if op.repeat != RepeatMode::None {
+        return MicroOp::Execute;
+    }
+    match (&op.command, &op.params.dst, &op.params.src) {
+        (&Op::Nop, _, _) => MicroOp::Nop,
+        (&Op::Mov8, &Parameter::Reg8(dst), &Parameter::Imm8(v)) => MicroOp::MovR8Imm(dst, v),
+        (&Op::Mov8, &Parameter::Reg8(dst), &Parameter::Reg8(src)) => MicroOp::MovR8R8(dst, src),
+        (&Op::Mov16, &Parameter::Reg16(dst), &Parameter::Imm16(v)) => MicroOp::MovR16Imm(dst, v),
+        (&Op::Mov16, &Parameter::Reg16(dst), &Parameter::Reg16(src)) => MicroOp::MovR16R16(dst, src),
+        (&Op::Inc16, &Parameter::Reg16(r), _) => MicroOp::IncR16(r),
+        (&Op::Dec16, &Parameter::Reg16(r), _) => MicroOp::DecR16(r),
+        (&Op::Cmp8, &Parameter::Reg8(r), &Parameter::Imm8(v)) => MicroOp::CmpR8Imm(r, v),
+        (&Op::Cmp16, &Parameter::Reg16(r), &Parameter::Imm16(v)) => MicroOp::CmpR16Imm(r, v),
+        (&Op::Cmp16, &Parameter::Reg16(dst), &Parameter::Reg16(src)) => MicroOp::CmpR16R16(dst, src),
+        (&Op::JmpShort, &Parameter::Imm16(target), _) |
+        (&Op::JmpNear, &Parameter::Imm16(target), _) => MicroOp::Jump(target),
+        // LOOP with a 32-bit address size counts in ECX
+        (&Op::Loop, &Parameter::Imm16(target), _) if op.address_size == AddressSize::_16bit => MicroOp::Loop(target),
+        (cmd, &Parameter::Imm16(target), _) if cmd.is_conditional_jump() => MicroOp::Branch(target),
+        _ => MicroOp::Execute,
+    }
+}
+
+/// returns true for instructions that may transfer control, or change how the following
+/// instructions are run
+fn ends_block(op: &Instruction) -> bool {
+    match op.command {
+        Op::JmpShort | Op::JmpNear | Op::JmpFar | Op::CallNear | Op::CallFar |
+        Op::Retn | Op::Retf | Op::RetImm16 | Op::Iret | Op::Int | Op::Into |
+        Op::Jcxz | Op::Loop | Op::Loope | Op::Loopne |
+        Op::Hlt | Op::Cli | Op::Sti | Op::Popf | Op::Lmsw | Op::Clts => true,
+        ref cmd if cmd.is_conditional_jump() => true,
+        _ => match op.params.dst {
+            Parameter::CR(_) => true,
+            _ => false,
+        },
+    }
+}
@@ -0,0 +1,59 @@
+use machine::Machine;
+use cpu::{Engine, R};
+
+/// runs the code for n instructions with the engine
+fn run(engine: Engine, code: &[u8], n: usize) -> Machine {
+    let mut machine = Machine::default();
+    machine.cpu.deterministic = true;
+    machine.engine = engine;
+    machine.load_executable(code);
+    machine.execute_instructions(n);
+    machine
+}
+
+#[test]
+fn runs_blocks_like_the_interpreter() {
+    let code: Vec<u8> = vec![
+        0xB9, 0x10, 0x00,       // mov cx,0x10
+        0xBB, 0x00, 0x00,       // mov bx,0x0
+        0x43,                   // l_0x106: inc bx
+        0x88, 0xD8,             // mov al,bl
+        0x88, 0x87, 0x00, 0x02, // mov [bx+0x200],al
+        0x81, 0xFB, 0x08, 0x00, // cmp bx,0x8
+        0x75, 0x01,             // jnz l_0x114
+        0x4A,                   // dec dx
+        0xE2, 0xF0,             // l_0x114: loop l_0x106
+        0x66, 0xB9, 0x03, 0x00, 0x01, 0x00, // mov ecx,0x10003
+        0x67, 0xE2, 0xFD,       // l_0x11c: loop l_0x11c   ; counts in ECX
+        0xEB, 0xDF,             // jmp short 0x100
+    ];
+    let interpreted = run(Engine::Interpreter, &code, 300);
+    let threaded = run(Engine::Threaded, &code, 300);
+
+    for r in &[R::AX, R::BX, R::CX, R::DX, R::SP, R::CS, R::DS] {
+        assert_eq!(interpreted.cpu.get_r16(*r), threaded.cpu.get_r16(*r), "{:?}", r);
+    }
+    assert_eq!(interpreted.cpu.get_r32(R::ECX), threaded.cpu.get_r32(R::ECX));
+    assert_eq!(interpreted.cpu.regs.ip, threaded.cpu.regs.ip);
+    assert_eq!(interpreted.cpu.regs.flags, threaded.cpu.regs.flags);
+    assert_eq!(interpreted.cpu.instruction_count, threaded.cpu.instruction_count);
+    assert_eq!(interpreted.cpu.cycle_count, threaded.cpu.cycle_count);
+    let ds = threaded.cpu.get_r16(R::DS);
+    assert_eq!(interpreted.hw.mmu.read(ds, 0x200, 0x20), threaded.hw.mmu.read(ds, 0x200, 0x20));
+}
+
+#[test]
+fn sees_code_modified_inside_a_block() {
+    let code: Vec<u8> = vec![
+        0xC6, 0x06, 0x0A, 0x01, 0x05, // mov byte [0x10a],0x5
+        0x90,                         // nop
+        0x90,                         // nop
+        0x90,                         // nop
+        0x90,                         // nop
+        0xB9, 0x01, 0x00,             // mov cx,0x1
+        0xEB, 0xF2,                   // jmp short 0x100
+    ];
+    let machine = run(Engine::Threaded, &code, 6);
+    assert_eq!(0x010C, machine.cpu.regs.ip);
+    assert_eq!(0x0005, machine.cpu.get_r16(R::CX));
+}
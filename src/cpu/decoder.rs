@@ -1,12 +1,13 @@
 use std::cell::RefCell;
-use std::num::Wrapping;
 use std::rc::Rc;
 
 use cpu::instruction::{Instruction, InstructionInfo, ModRegRm, RepeatMode};
 use cpu::parameter::{Parameter, ParameterSet};
 use cpu::op::{Op, Invalid};
-use cpu::register::{R, r8, r16, r32, sr};
+use cpu::register::{R, AMode, r8, r16, r32, sr};
 use cpu::segment::Segment;
+use cpu::instruction_cache::InstructionCache;
+use cpu::model::CpuModel;
 use memory::{MMU, MemoryAddress};
 
 const DEBUG_DECODER: bool = false;
@@ -25,50 +26,198 @@ pub enum AddressSize {
     _16bit, _32bit,
 }
 
+/// a decoded memory operand, before it is sized into a Parameter
+enum MemoryOperand {
+    Direct(u16),
+    Amode(AMode),
+    AmodeS8(AMode, i8),
+    AmodeS16(AMode, i16),
+    AmodeS32(AMode, i32),
+}
+
+impl MemoryOperand {
+    fn ptr8(self, seg: Segment) -> Parameter {
+        match self {
+            MemoryOperand::Direct(imm) => Parameter::Ptr8(seg, imm),
+            MemoryOperand::Amode(amode) => Parameter::Ptr8Amode(seg, amode),
+            MemoryOperand::AmodeS8(amode, imm) => Parameter::Ptr8AmodeS8(seg, amode, imm),
+            MemoryOperand::AmodeS16(amode, imm) => Parameter::Ptr8AmodeS16(seg, amode, imm),
+            MemoryOperand::AmodeS32(amode, imm) => Parameter::Ptr8AmodeS32(seg, amode, imm),
+        }
+    }
+
+    fn ptr16(self, seg: Segment) -> Parameter {
+        match self {
+            MemoryOperand::Direct(imm) => Parameter::Ptr16(seg, imm),
+            MemoryOperand::Amode(amode) => Parameter::Ptr16Amode(seg, amode),
+            MemoryOperand::AmodeS8(amode, imm) => Parameter::Ptr16AmodeS8(seg, amode, imm),
+            MemoryOperand::AmodeS16(amode, imm) => Parameter::Ptr16AmodeS16(seg, amode, imm),
+            MemoryOperand::AmodeS32(amode, imm) => Parameter::Ptr16AmodeS32(seg, amode, imm),
+        }
+    }
+
+    fn ptr32(self, seg: Segment) -> Parameter {
+        match self {
+            MemoryOperand::Direct(imm) => Parameter::Ptr32(seg, imm),
+            MemoryOperand::Amode(amode) => Parameter::Ptr32Amode(seg, amode),
+            MemoryOperand::AmodeS8(amode, imm) => Parameter::Ptr32AmodeS8(seg, amode, imm),
+            MemoryOperand::AmodeS16(amode, imm) => Parameter::Ptr32AmodeS16(seg, amode, imm),
+            MemoryOperand::AmodeS32(amode, imm) => Parameter::Ptr32AmodeS32(seg, amode, imm),
+        }
+    }
+
+    fn ptr64(self, seg: Segment) -> Parameter {
+        match self {
+            MemoryOperand::Direct(imm) => Parameter::Ptr64(seg, imm),
+            MemoryOperand::Amode(amode) => Parameter::Ptr64Amode(seg, amode),
+            MemoryOperand::AmodeS8(amode, imm) => Parameter::Ptr64AmodeS8(seg, amode, imm),
+            MemoryOperand::AmodeS16(amode, imm) => Parameter::Ptr64AmodeS16(seg, amode, imm),
+            MemoryOperand::AmodeS32(amode, imm) => Parameter::Ptr64AmodeS32(seg, amode, imm),
+        }
+    }
+
+    fn ptr80(self, seg: Segment) -> Parameter {
+        match self {
+            MemoryOperand::Direct(imm) => Parameter::Ptr80(seg, imm),
+            MemoryOperand::Amode(amode) => Parameter::Ptr80Amode(seg, amode),
+            MemoryOperand::AmodeS8(amode, imm) => Parameter::Ptr80AmodeS8(seg, amode, imm),
+            MemoryOperand::AmodeS16(amode, imm) => Parameter::Ptr80AmodeS16(seg, amode, imm),
+            MemoryOperand::AmodeS32(amode, imm) => Parameter::Ptr80AmodeS32(seg, amode, imm),
+        }
+    }
+}
+
 #[derive(Clone, Default)]
 pub struct Decoder {
     current_seg: u16,
 
     /// starting instruction decoding offset
-    current_offset: u16,
+    current_offset: u32,
+
+    /// the code segment is a 32-bit segment, its offsets don't wrap at 64k
+    code32: bool,
+
+    /// decoded instructions, see with_cache
+    cache: Option<InstructionCache>,
+
+    /// the processor whose instruction set is decoded
+    model: CpuModel,
 }
 
 impl Decoder {
-    pub fn decode_to_block(&mut self, mut mmu: &mut MMU, seg: u16, offset: u16, n: usize) -> Vec<InstructionInfo> {
+    /// returns a decoder caching decoded instructions. it consumes the code writes recorded
+    /// by the memory, so only one caching decoder may be used per memory
+    pub fn with_cache() -> Self {
+        Decoder {
+            cache: Some(InstructionCache::default()),
+            ..Decoder::default()
+        }
+    }
+
+    /// decodes the instruction set of model. cached instructions are decoded again
+    pub fn set_model(&mut self, model: CpuModel) {
+        self.model = model;
+        if let Some(ref mut cache) = self.cache {
+            cache.clear();
+        }
+    }
+
+    /// decodes code of a 32-bit code segment
+    pub fn set_code32(&mut self, code32: bool) {
+        self.code32 = code32;
+    }
+
+    pub fn decode_to_block(&mut self, mut mmu: &mut MMU, seg: u16, offset: u32, n: usize) -> Vec<InstructionInfo> {
         let mut ops: Vec<InstructionInfo> = Vec::new();
         let mut inst_offset = 0;
         for _ in 0..n {
             let op = self.get_instruction_info(&mut mmu, seg, offset+inst_offset);
-            inst_offset += op.bytes.len() as u16;
+            inst_offset += op.bytes.len() as u32;
             ops.push(op);
         }
         ops
     }
 
-    pub fn disassemble_block_to_str(&mut self, mut mmu: &mut MMU, seg: u16, offset: u16, n: usize) -> String {
+    pub fn disassemble_block_to_str(&mut self, mut mmu: &mut MMU, seg: u16, offset: u32, n: usize) -> String {
         let ops = self.decode_to_block(&mut mmu, seg, offset, n);
         instruction_info_to_str(&ops)
     }
 
     /// decodes op at seg:offset into a InstructionInfo
-    pub fn get_instruction_info(&mut self, mut mmu: &mut MMU, seg: u16, offset: u16) -> InstructionInfo {
+    pub fn get_instruction_info(&mut self, mut mmu: &mut MMU, seg: u16, offset: u32) -> InstructionInfo {
         let instr = self.get_instruction(&mut mmu, seg, offset);
         if DEBUG_DECODER {
-            println!("get_instruction_info at {:06x}: {:?}", MemoryAddress::RealSegmentOffset(seg, offset).value(), instr);
+            println!("get_instruction_info at {:06x}: {:?}", mmu.linear(seg, offset), instr);
         }
         InstructionInfo {
             segment: seg as usize,
             offset: offset as usize,
-            bytes: mmu.read(seg, offset, instr.length as usize),
+            bytes: (0..u32::from(instr.length)).map(|i| mmu.fetch_u8(seg, offset.wrapping_add(i))).collect(),
             instruction: instr,
         }
     }
 
     /// decodes op at seg:offset into a Instruction
-    pub fn get_instruction(&mut self, mut mmu: &mut MMU, segment: u16, offset: u16) -> Instruction {
+    pub fn get_instruction(&mut self, mut mmu: &mut MMU, segment: u16, offset: u32) -> Instruction {
+        if self.cache.is_some() {
+            return (*self.get_shared_instruction(&mut mmu, segment, offset)).clone();
+        }
+        self.decode_at(&mut mmu, segment, offset)
+    }
+
+    /// decodes op at seg:offset, the instruction is shared with the cache of a caching decoder
+    pub fn get_shared_instruction(&mut self, mut mmu: &mut MMU, segment: u16, offset: u32) -> Rc<Instruction> {
+        // XXX the cache is bypassed while paging is enabled, as writes are only seen at their physical address
+        let cached = self.cache.is_some() && !mmu.paging_enabled();
+        let address = mmu.linear(segment, offset);
+        if cached {
+            self.invalidate_modified(mmu);
+            if let Some(op) = self.cached_instruction(address, offset) {
+                return op;
+            }
+        }
+
+        let op = Rc::new(self.decode_at(&mut mmu, segment, offset));
+
+        // instructions wrapping around the segment are not contiguous in memory
+        if cached && op.command.is_valid() && (self.code32 || offset + u32::from(op.length) <= 0x1_0000) {
+            self.cache.as_mut().unwrap().insert(&mut mmu.memory.borrow_mut(), address, offset, self.code32, Rc::clone(&op));
+        }
+        op
+    }
+
+    /// drops the cached instructions overwritten since the last call
+    pub fn invalidate_modified(&mut self, mmu: &MMU) {
+        if let Some(ref mut cache) = self.cache {
+            cache.invalidate_modified(&mut mmu.memory.borrow_mut());
+        }
+    }
+
+    /// returns the cached instruction at linear address, decoded from segment offset in the current code segment size
+    pub fn cached_instruction(&self, address: u32, offset: u32) -> Option<Rc<Instruction>> {
+        match self.cache {
+            Some(ref cache) => cache.get(address, offset, self.code32),
+            None => None,
+        }
+    }
+
+    /// changes when cached instructions are overwritten
+    pub fn cache_generation(&self) -> usize {
+        match self.cache {
+            Some(ref cache) => cache.generation(),
+            None => 0,
+        }
+    }
+
+    fn decode_at(&mut self, mut mmu: &mut MMU, segment: u16, offset: u32) -> Instruction {
         self.current_seg = segment;
         self.current_offset = offset;
         let mut op = Instruction::new(Op::Uninitialized);
+        if self.code32 {
+            // 32-bit code segments default to 32-bit operands and addresses
+            op.op_size = OperandSize::_32bit;
+            op.address_size = AddressSize::_32bit;
+        }
         self.decode(&mut mmu, &mut op);
         op
     }
@@ -76,16 +225,24 @@ impl Decoder {
     /// decodes the next instruction
     fn decode(&mut self, mut mmu: &mut MMU, mut op: &mut Instruction) {
         let start_offset = self.current_offset;
-        let b = self.read_u8(mmu);
+        let mut b = self.read_u8(mmu);
         if DEBUG_DECODER {
             println!("decode op start {:?}", op);
         }
+        if self.model == CpuModel::I8086 {
+            b = CpuModel::alias_8086(b);
+        } else if !self.model.has_opcode(b, mmu.fetch_u8(self.current_seg, self.current_offset)) {
+            // raises the invalid opcode exception
+            op.command = Op::Invalid(vec!(b), Invalid::Op);
+            op.length = 1;
+            return;
+        }
 
         match b {
             0x00 => {
                 // add r/m8, r8
                 op.command = Op::Add8;
-                op.params = self.rm8_r8(&mut mmu, op.segment_prefix);
+                op.params = self.rm8_r8(&mut mmu, op);
             }
             0x01 => {
                 // add r/m16, r16
@@ -95,7 +252,7 @@ impl Decoder {
             0x02 => {
                 // add r8, r/m8
                 op.command = Op::Add8;
-                op.params = self.r8_rm8(&mut mmu, op.segment_prefix);
+                op.params = self.r8_rm8(&mut mmu, op);
             }
             0x03 => {
                 // add r16, r/m16
@@ -126,33 +283,33 @@ impl Decoder {
             }
             0x06 => {
                 // push es
-                op.command = Op::Push16;
+                op.command = self.sized_op(op, Op::Push16, Op::Push32);
                 op.params.dst = Parameter::SReg16(R::ES);
             }
             0x07 => {
                 // pop es
-                op.command = Op::Pop16;
+                op.command = self.sized_op(op, Op::Pop16, Op::Pop32);
                 op.params.dst = Parameter::SReg16(R::ES);
             }
             0x08 => {
                 // or r/m8, r8
                 op.command = Op::Or8;
-                op.params = self.rm8_r8(&mut mmu, op.segment_prefix);
+                op.params = self.rm8_r8(&mut mmu, op);
             }
             0x09 => {
                 // or r/m16, r16
-                op.command = Op::Or16;
-                op.params = self.rm16_r16(&mut mmu, op);
+                // or r/m32, r32
+                self.prefixed_16_32_rm_r(&mut mmu, &mut op, Op::Or16, Op::Or32)
             }
             0x0A => {
                 // or r8, r/m8
                 op.command = Op::Or8;
-                op.params = self.r8_rm8(&mut mmu, op.segment_prefix);
+                op.params = self.r8_rm8(&mut mmu, op);
             }
             0x0B => {
                 // or r16, r/m16
-                op.command = Op::Or16;
-                op.params = self.r16_rm16(&mut mmu, op);
+                // or r32, r/m32
+                self.prefixed_16_32_r_rm(&mut mmu, &mut op, Op::Or16, Op::Or32)
             }
             0x0C => {
                 // or AL, imm8
@@ -161,14 +318,29 @@ impl Decoder {
                 op.params.src = Parameter::Imm8(self.read_u8(mmu));
             }
             0x0D => {
-                // or AX, imm16
-                op.command = Op::Or16;
-                op.params.dst = Parameter::Reg16(R::AX);
-                op.params.src = Parameter::Imm16(self.read_u16(mmu));
+                match op.op_size {
+                    OperandSize::_16bit => {
+                        // or AX, imm16
+                        op.command = Op::Or16;
+                        op.params.dst = Parameter::Reg16(R::AX);
+                        op.params.src = Parameter::Imm16(self.read_u16(mmu));
+                    }
+                    OperandSize::_32bit => {
+                        // or EAX, imm32
+                        op.command = Op::Or32;
+                        op.params.dst = Parameter::Reg32(R::EAX);
+                        op.params.src = Parameter::Imm32(self.read_u32(mmu));
+                    }
+                }
             }
             0x0E => {
                 // push cs
-                op.command = Op::Push16;
+                op.command = self.sized_op(op, Op::Push16, Op::Push32);
+                op.params.dst = Parameter::SReg16(R::CS);
+            }
+            0x0F if self.model == CpuModel::I8086 => {
+                // pop cs
+                op.command = Op::Pop16;
                 op.params.dst = Parameter::SReg16(R::CS);
             }
             0x0F => {
@@ -179,96 +351,163 @@ impl Decoder {
                         op.params.dst = self.rm16(&mut mmu, op, x.rm, x.md);
                         op.command = match x.reg {
                             0 => Op::Sldt, // sldt r/m16
+                            1 => Op::Str,  // str r/m16
+                            2 => Op::Lldt, // lldt r/m16
+                            3 => Op::Ltr,  // ltr r/m16
+                            4 => Op::Verr, // verr r/m16
+                            5 => Op::Verw, // verw r/m16
                             _ => Op::Invalid(vec!(b, b2), Invalid::Reg(x.reg)),
                         };
                     }
+                    0x01 => {
+                        let x = self.read_mod_reg_rm(mmu);
+                        op.params.dst = self.rm16(&mut mmu, op, x.rm, x.md);
+                        op.command = match x.reg {
+                            0 => Op::Sgdt, // sgdt m
+                            1 => Op::Sidt, // sidt m
+                            2 => Op::Lgdt, // lgdt m
+                            3 => Op::Lidt, // lidt m
+                            4 => Op::Smsw, // smsw r/m16
+                            6 => Op::Lmsw, // lmsw r/m16
+                            _ => Op::Invalid(vec!(b, b2), Invalid::Reg(x.reg)),
+                        };
+                    }
+                    0x06 => {
+                        // clts
+                        op.command = Op::Clts;
+                    }
+                    0x20 => {
+                        // mov r32, CRn
+                        let x = self.read_mod_reg_rm(mmu);
+                        op.command = Op::Mov32;
+                        op.params.dst = Parameter::Reg32(r32(x.rm));
+                        op.params.src = Parameter::CR(x.reg);
+                    }
+                    0x22 => {
+                        // mov CRn, r32
+                        let x = self.read_mod_reg_rm(mmu);
+                        op.command = Op::Mov32;
+                        op.params.dst = Parameter::CR(x.reg);
+                        op.params.src = Parameter::Reg32(r32(x.rm));
+                    }
+                    0x80 => {
+                        // jo rel16, rel32
+                        op.command = Op::Jo;
+                        op.params.dst = self.read_rel(mmu, op);
+                    }
+                    0x81 => {
+                        // jno rel16, rel32
+                        op.command = Op::Jno;
+                        op.params.dst = self.read_rel(mmu, op);
+                    }
                     0x82 => {
-                        // jc rel16
+                        // jc rel16, rel32
                         op.command = Op::Jc;
-                        op.params.dst = Parameter::Imm16(self.read_rel16(mmu));
+                        op.params.dst = self.read_rel(mmu, op);
                     }
                     0x83 => {
-                        // jnc rel16
+                        // jnc rel16, rel32
                         op.command = Op::Jnc;
-                        op.params.dst = Parameter::Imm16(self.read_rel16(mmu));
+                        op.params.dst = self.read_rel(mmu, op);
                     }
                     0x84 => {
-                        // jz rel16
+                        // jz rel16, rel32
                         op.command = Op::Jz;
-                        op.params.dst = Parameter::Imm16(self.read_rel16(mmu));
+                        op.params.dst = self.read_rel(mmu, op);
                     }
                     0x85 => {
-                        // jnz rel16
+                        // jnz rel16, rel32
                         op.command = Op::Jnz;
-                        op.params.dst = Parameter::Imm16(self.read_rel16(mmu));
+                        op.params.dst = self.read_rel(mmu, op);
                     }
                     0x86 => {
-                        // jna rel16
+                        // jna rel16, rel32
                         op.command = Op::Jna;
-                        op.params.dst = Parameter::Imm16(self.read_rel16(mmu));
+                        op.params.dst = self.read_rel(mmu, op);
                     }
                     0x87 => {
-                        // ja rel16
+                        // ja rel16, rel32
                         op.command = Op::Ja;
-                        op.params.dst = Parameter::Imm16(self.read_rel16(mmu));
+                        op.params.dst = self.read_rel(mmu, op);
+                    }
+                    0x88 => {
+                        // js rel16, rel32
+                        op.command = Op::Js;
+                        op.params.dst = self.read_rel(mmu, op);
                     }
                     0x89 => {
-                        // jns rel16
+                        // jns rel16, rel32
                         op.command = Op::Jns;
-                        op.params.dst = Parameter::Imm16(self.read_rel16(mmu));
+                        op.params.dst = self.read_rel(mmu, op);
+                    }
+                    0x8A => {
+                        // jpe rel16, rel32
+                        op.command = Op::Jpe;
+                        op.params.dst = self.read_rel(mmu, op);
+                    }
+                    0x8B => {
+                        // jpo rel16, rel32
+                        op.command = Op::Jpo;
+                        op.params.dst = self.read_rel(mmu, op);
                     }
                     0x8C => {
-                        // jl rel16
+                        // jl rel16, rel32
                         op.command = Op::Jl;
-                        op.params.dst = Parameter::Imm16(self.read_rel16(mmu));
+                        op.params.dst = self.read_rel(mmu, op);
                     }
                     0x8D => {
-                        // jnl rel16
+                        // jnl rel16, rel32
                         op.command = Op::Jnl;
-                        op.params.dst = Parameter::Imm16(self.read_rel16(mmu));
+                        op.params.dst = self.read_rel(mmu, op);
                     }
                     0x8E => {
-                        // jng rel16
+                        // jng rel16, rel32
                         op.command = Op::Jng;
-                        op.params.dst = Parameter::Imm16(self.read_rel16(mmu));
+                        op.params.dst = self.read_rel(mmu, op);
                     }
                     0x8F => {
-                        // jg rel16
+                        // jg rel16, rel32
                         op.command = Op::Jg;
-                        op.params.dst = Parameter::Imm16(self.read_rel16(mmu));
+                        op.params.dst = self.read_rel(mmu, op);
                     }
-                    0x92 => {
-                        // setc r/m8
+                    0x90...0x9F => {
+                        // setcc r/m8
                         let x = self.read_mod_reg_rm(mmu);
-                        op.command = Op::Setc;
-                        op.params.dst = self.rm8(&mut mmu, op.segment_prefix, x.rm, x.md);
-                    }
-                    0x95 => {
-                        // setnz r/m8
-                        let x = self.read_mod_reg_rm(mmu);
-                        op.command = Op::Setnz;
-                        op.params.dst = self.rm8(&mut mmu, op.segment_prefix, x.rm, x.md);
-                    }
-                    0x9F => {
-                        // setg r/m8
-                        let x = self.read_mod_reg_rm(mmu);
-                        op.command = Op::Setg;
-                        op.params.dst = self.rm8(&mut mmu, op.segment_prefix, x.rm, x.md);
+                        op.command = match b2 {
+                            0x90 => Op::Seto,
+                            0x91 => Op::Setno,
+                            0x92 => Op::Setc,
+                            0x93 => Op::Setnc,
+                            0x94 => Op::Setz,
+                            0x95 => Op::Setnz,
+                            0x96 => Op::Setna,
+                            0x97 => Op::Seta,
+                            0x98 => Op::Sets,
+                            0x99 => Op::Setns,
+                            0x9A => Op::Setpe,
+                            0x9B => Op::Setpo,
+                            0x9C => Op::Setl,
+                            0x9D => Op::Setnl,
+                            0x9E => Op::Setng,
+                            _ => Op::Setg,
+                        };
+                        op.params.dst = self.rm8(&mut mmu, op, x.rm, x.md);
                     }
                     0xA0 => {
                         // push fs
-                        op.command = Op::Push16;
+                        op.command = self.sized_op(op, Op::Push16, Op::Push32);
                         op.params.dst = Parameter::SReg16(R::FS);
                     }
                     0xA1 => {
                         // pop fs
-                        op.command = Op::Pop16;
+                        op.command = self.sized_op(op, Op::Pop16, Op::Pop32);
                         op.params.dst = Parameter::SReg16(R::FS);
                     }
+                    0xA2 => op.command = Op::Cpuid,
                     0xA3 => {
                         // bt r/m16, r16
-                        op.command = Op::Bt;
-                        op.params = self.rm16_r16(&mut mmu, op);
+                        // bt r/m32, r32
+                        self.prefixed_16_32_rm_r(&mut mmu, &mut op, Op::Bt, Op::Bt)
                     }
                     0xA4 =>{
                         // shld r/m16, r16, imm8
@@ -278,14 +517,19 @@ impl Decoder {
                     }
                     0xA8 => {
                         // push gs
-                        op.command = Op::Push16;
+                        op.command = self.sized_op(op, Op::Push16, Op::Push32);
                         op.params.dst = Parameter::SReg16(R::GS);
                     }
                     0xA9 => {
                         // pop gs
-                        op.command = Op::Pop16;
+                        op.command = self.sized_op(op, Op::Pop16, Op::Pop32);
                         op.params.dst = Parameter::SReg16(R::GS);
                     }
+                    0xAB => {
+                        // bts r/m16, r16
+                        // bts r/m32, r32
+                        self.prefixed_16_32_rm_r(&mut mmu, &mut op, Op::Bts, Op::Bts)
+                    }
                     0xAC => {
                         // shrd r/m16, r16, imm8
                         op.command = Op::Shrd;
@@ -297,43 +541,110 @@ impl Decoder {
                         // imul r32, r/m32
                         self.prefixed_16_32_r_rm(&mut mmu, &mut op, Op::Imul16, Op::Imul32)
                     }
+                    0xB0 => {
+                        // cmpxchg r/m8, r8
+                        op.command = Op::Cmpxchg8;
+                        op.params = self.rm8_r8(&mut mmu, op);
+                    }
+                    0xB1 => {
+                        // cmpxchg r/m16, r16
+                        // cmpxchg r/m32, r32
+                        self.prefixed_16_32_rm_r(&mut mmu, &mut op, Op::Cmpxchg16, Op::Cmpxchg32)
+                    }
+                    0xB2 => {
+                        // lss r16, m16:16
+                        // lss r32, m16:32
+                        op.command = Op::Lss;
+                        op.params = self.r_far_pointer(&mut mmu, op);
+                    }
+                    0xB3 => {
+                        // btr r/m16, r16
+                        // btr r/m32, r32
+                        self.prefixed_16_32_rm_r(&mut mmu, &mut op, Op::Btr, Op::Btr)
+                    }
+                    0xB4 => {
+                        // lfs r16, m16:16
+                        // lfs r32, m16:32
+                        op.command = Op::Lfs;
+                        op.params = self.r_far_pointer(&mut mmu, op);
+                    }
+                    0xB5 => {
+                        // lgs r16, m16:16
+                        // lgs r32, m16:32
+                        op.command = Op::Lgs;
+                        op.params = self.r_far_pointer(&mut mmu, op);
+                    }
                     0xB6 => {
                         match op.op_size {
                             OperandSize::_16bit => {
                                 // movzx r16, r/m8
                                 op.command = Op::Movzx16;
-                                op.params = self.r16_rm8(&mut mmu, op.segment_prefix);
+                                op.params = self.r16_rm8(&mut mmu, op);
                             }
                             OperandSize::_32bit => {
                                 // movzx r32, r/m8
                                 op.command = Op::Movzx32;
-                                op.params = self.r32_rm8(&mut mmu, op.segment_prefix);
+                                op.params = self.r32_rm8(&mut mmu, op);
                             }
                         }
                     }
                     0xBA => {
-                        // bts r/m16, imm8
+                        // bt/bts/btr/btc r/m16, imm8
+                        // bt/bts/btr/btc r/m32, imm8
                         let x = self.read_mod_reg_rm(mmu);
-                        op.command = Op::Bts;
-                        op.params.dst = self.rm16(&mut mmu, op, x.rm, x.md);
+                        op.params.dst = match op.op_size {
+                            OperandSize::_16bit => self.rm16(&mut mmu, op, x.rm, x.md),
+                            OperandSize::_32bit => self.rm32(&mut mmu, op, x.rm, x.md),
+                        };
                         op.params.src = Parameter::Imm8(self.read_u8(mmu));
+                        op.command = match x.reg {
+                            4 => Op::Bt,
+                            5 => Op::Bts,
+                            6 => Op::Btr,
+                            7 => Op::Btc,
+                            _ => Op::Invalid(vec!(b, b2), Invalid::Reg(x.reg)),
+                        };
+                    }
+                    0xBB => {
+                        // btc r/m16, r16
+                        // btc r/m32, r32
+                        self.prefixed_16_32_rm_r(&mut mmu, &mut op, Op::Btc, Op::Btc)
                     }
                     0xBC => {
                         // bsf r16, r/m16
-                        op.command = Op::Bsf;
-                        op.params = self.r16_rm16(&mut mmu, op);
+                        // bsf r32, r/m32
+                        self.prefixed_16_32_r_rm(&mut mmu, &mut op, Op::Bsf, Op::Bsf)
+                    }
+                    0xBD => {
+                        // bsr r16, r/m16
+                        // bsr r32, r/m32
+                        self.prefixed_16_32_r_rm(&mut mmu, &mut op, Op::Bsr, Op::Bsr)
+                    }
+                    0xB7 => {
+                        match op.op_size {
+                            OperandSize::_16bit => {
+                                // movzx r16, r/m16
+                                op.command = Op::Movzx16;
+                                op.params = self.r16_rm16(&mut mmu, op);
+                            }
+                            OperandSize::_32bit => {
+                                // movzx r32, r/m16
+                                op.command = Op::Movzx32;
+                                op.params = self.r32_rm16(&mut mmu, op);
+                            }
+                        }
                     }
                     0xBE => {
                         match op.op_size {
                             OperandSize::_16bit => {
                                 // movsx r16, r/m8
                                 op.command = Op::Movsx16;
-                                op.params = self.r16_rm8(&mut mmu, op.segment_prefix);
+                                op.params = self.r16_rm8(&mut mmu, op);
                             }
                             OperandSize::_32bit => {
                                 // movsx r32, r/m8
                                 op.command = Op::Movsx32;
-                                op.params = self.r32_rm8(&mut mmu, op.segment_prefix);
+                                op.params = self.r32_rm8(&mut mmu, op);
                             }
                         }
                     }
@@ -347,28 +658,43 @@ impl Decoder {
                             }
                         }
                     }
+                    0xC0 => {
+                        // xadd r/m8, r8
+                        op.command = Op::Xadd8;
+                        op.params = self.rm8_r8(&mut mmu, op);
+                    }
+                    0xC1 => {
+                        // xadd r/m16, r16
+                        // xadd r/m32, r32
+                        self.prefixed_16_32_rm_r(&mut mmu, &mut op, Op::Xadd16, Op::Xadd32)
+                    }
+                    0xC8...0xCF => {
+                        // bswap r32
+                        op.command = Op::Bswap;
+                        op.params.dst = Parameter::Reg32(r32(b2 & 7));
+                    }
                     _ => op.command = Op::Invalid(vec!(b, b2), Invalid::Op),
                 }
             }
             0x10 => {
                 // adc r/m8, r8
                 op.command = Op::Adc8;
-                op.params = self.rm8_r8(&mut mmu, op.segment_prefix);
+                op.params = self.rm8_r8(&mut mmu, op);
             }
             0x11 => {
                 // adc r/m16, r16
-                op.command = Op::Adc16;
-                op.params = self.rm16_r16(&mut mmu, op);
+                // adc r/m32, r32
+                self.prefixed_16_32_rm_r(&mut mmu, &mut op, Op::Adc16, Op::Adc32)
             }
             0x12 => {
                 // adc r8, r/m8
                 op.command = Op::Adc8;
-                op.params = self.r8_rm8(&mut mmu, op.segment_prefix);
+                op.params = self.r8_rm8(&mut mmu, op);
             }
             0x13 => {
                 // adc r16, r/m16
-                op.command = Op::Adc16;
-                op.params = self.r16_rm16(&mut mmu, op);
+                // adc r32, r/m32
+                self.prefixed_16_32_r_rm(&mut mmu, &mut op, Op::Adc16, Op::Adc32)
             }
             0x14 => {
                 // adc al, imm8
@@ -377,40 +703,50 @@ impl Decoder {
                 op.params.src = Parameter::Imm8(self.read_u8(mmu));
             }
             0x15 => {
-                // adc ax, imm16
-                op.command = Op::Adc16;
-                op.params.dst = Parameter::Reg16(R::AX);
-                op.params.src = Parameter::Imm16(self.read_u16(mmu));
+                match op.op_size {
+                    OperandSize::_16bit => {
+                        // adc AX, imm16
+                        op.command = Op::Adc16;
+                        op.params.dst = Parameter::Reg16(R::AX);
+                        op.params.src = Parameter::Imm16(self.read_u16(mmu));
+                    }
+                    OperandSize::_32bit => {
+                        // adc EAX, imm32
+                        op.command = Op::Adc32;
+                        op.params.dst = Parameter::Reg32(R::EAX);
+                        op.params.src = Parameter::Imm32(self.read_u32(mmu));
+                    }
+                }
             }
             0x16 => {
                 // push ss
-                op.command = Op::Push16;
+                op.command = self.sized_op(op, Op::Push16, Op::Push32);
                 op.params.dst = Parameter::SReg16(R::SS);
             }
             0x17 => {
                 // pop ss
-                op.command = Op::Pop16;
+                op.command = self.sized_op(op, Op::Pop16, Op::Pop32);
                 op.params.dst = Parameter::SReg16(R::SS);
             }
             0x18 => {
                 // sbb r/m8, r8
                 op.command = Op::Sbb8;
-                op.params = self.rm8_r8(&mut mmu, op.segment_prefix);
+                op.params = self.rm8_r8(&mut mmu, op);
             }
             0x19 => {
                 // sbb r/m16, r16
-                op.command = Op::Sbb16;
-                op.params = self.rm16_r16(&mut mmu, op);
+                // sbb r/m32, r32
+                self.prefixed_16_32_rm_r(&mut mmu, &mut op, Op::Sbb16, Op::Sbb32)
             }
             0x1A => {
                 // sbb r8, r/m8
                 op.command = Op::Sbb8;
-                op.params = self.r8_rm8(&mut mmu, op.segment_prefix);
+                op.params = self.r8_rm8(&mut mmu, op);
             }
             0x1B => {
                 // sbb r16, r/m16
-                op.command = Op::Sbb16;
-                op.params = self.r16_rm16(&mut mmu, op);
+                // sbb r32, r/m32
+                self.prefixed_16_32_r_rm(&mut mmu, &mut op, Op::Sbb16, Op::Sbb32)
             }
             0x1C => {
                 // sbb al, imm8
@@ -419,40 +755,50 @@ impl Decoder {
                 op.params.src = Parameter::Imm8(self.read_u8(mmu));
             }
             0x1D => {
-                // sbb ax, imm16
-                op.command = Op::Sbb16;
-                op.params.dst = Parameter::Reg16(R::AX);
-                op.params.src = Parameter::Imm16(self.read_u16(mmu));
+                match op.op_size {
+                    OperandSize::_16bit => {
+                        // sbb AX, imm16
+                        op.command = Op::Sbb16;
+                        op.params.dst = Parameter::Reg16(R::AX);
+                        op.params.src = Parameter::Imm16(self.read_u16(mmu));
+                    }
+                    OperandSize::_32bit => {
+                        // sbb EAX, imm32
+                        op.command = Op::Sbb32;
+                        op.params.dst = Parameter::Reg32(R::EAX);
+                        op.params.src = Parameter::Imm32(self.read_u32(mmu));
+                    }
+                }
             }
             0x1E => {
                 // push ds
-                op.command = Op::Push16;
+                op.command = self.sized_op(op, Op::Push16, Op::Push32);
                 op.params.dst = Parameter::SReg16(R::DS);
             }
             0x1F => {
                 // pop ds
-                op.command = Op::Pop16;
+                op.command = self.sized_op(op, Op::Pop16, Op::Pop32);
                 op.params.dst = Parameter::SReg16(R::DS);
             }
             0x20 => {
                 // and r/m8, r8
                 op.command = Op::And8;
-                op.params = self.rm8_r8(&mut mmu, op.segment_prefix);
+                op.params = self.rm8_r8(&mut mmu, op);
             }
             0x21 => {
                 // and r/m16, r16
-                op.command = Op::And16;
-                op.params = self.rm16_r16(&mut mmu, op);
+                // and r/m32, r32
+                self.prefixed_16_32_rm_r(&mut mmu, &mut op, Op::And16, Op::And32)
             }
             0x22 => {
                 // and r8, r/m8
                 op.command = Op::And8;
-                op.params = self.r8_rm8(&mut mmu, op.segment_prefix);
+                op.params = self.r8_rm8(&mut mmu, op);
             }
             0x23 => {
                 // and r16, r/m16
-                op.command = Op::And16;
-                op.params = self.r16_rm16(&mut mmu, op);
+                // and r32, r/m32
+                self.prefixed_16_32_r_rm(&mut mmu, &mut op, Op::And16, Op::And32)
             }
             0x24 => {
                 // and AL, imm8
@@ -461,10 +807,20 @@ impl Decoder {
                 op.params.src = Parameter::Imm8(self.read_u8(mmu));
             }
             0x25 => {
-                // and AX, imm16
-                op.command = Op::And16;
-                op.params.dst = Parameter::Reg16(R::AX);
-                op.params.src = Parameter::Imm16(self.read_u16(mmu));
+                match op.op_size {
+                    OperandSize::_16bit => {
+                        // and AX, imm16
+                        op.command = Op::And16;
+                        op.params.dst = Parameter::Reg16(R::AX);
+                        op.params.src = Parameter::Imm16(self.read_u16(mmu));
+                    }
+                    OperandSize::_32bit => {
+                        // and EAX, imm32
+                        op.command = Op::And32;
+                        op.params.dst = Parameter::Reg32(R::EAX);
+                        op.params.src = Parameter::Imm32(self.read_u32(mmu));
+                    }
+                }
             }
             0x26 => {
                 // es segment prefix
@@ -477,17 +833,17 @@ impl Decoder {
             0x28 => {
                 // sub r/m8, r8
                 op.command = Op::Sub8;
-                op.params = self.rm8_r8(&mut mmu, op.segment_prefix);
+                op.params = self.rm8_r8(&mut mmu, op);
             }
             0x29 => {
                 // sub r/m16, r16
-                op.command = Op::Sub16;
-                op.params = self.rm16_r16(&mut mmu, op);
+                // sub r/m32, r32
+                self.prefixed_16_32_rm_r(&mut mmu, &mut op, Op::Sub16, Op::Sub32)
             }
             0x2A => {
                 // sub r8, r/m8
                 op.command = Op::Sub8;
-                op.params = self.r8_rm8(&mut mmu, op.segment_prefix);
+                op.params = self.r8_rm8(&mut mmu, op);
             }
             0x2B => {
                 // sub r16, r/m16
@@ -527,7 +883,7 @@ impl Decoder {
             0x30 => {
                 // xor r/m8, r8
                 op.command = Op::Xor8;
-                op.params = self.rm8_r8(&mut mmu, op.segment_prefix);
+                op.params = self.rm8_r8(&mut mmu, op);
             }
             0x31 => {
                 // xor r/m16, r16
@@ -537,7 +893,7 @@ impl Decoder {
             0x32 => {
                 // xor r8, r/m8
                 op.command = Op::Xor8;
-                op.params = self.r8_rm8(&mut mmu, op.segment_prefix);
+                op.params = self.r8_rm8(&mut mmu, op);
             }
             0x33 => {
                 // xor r16, r/m16
@@ -551,10 +907,20 @@ impl Decoder {
                 op.params.src = Parameter::Imm8(self.read_u8(mmu));
             }
             0x35 => {
-                // xor AX, imm16
-                op.command = Op::Xor16;
-                op.params.dst = Parameter::Reg16(R::AX);
-                op.params.src = Parameter::Imm16(self.read_u16(mmu));
+                match op.op_size {
+                    OperandSize::_16bit => {
+                        // xor AX, imm16
+                        op.command = Op::Xor16;
+                        op.params.dst = Parameter::Reg16(R::AX);
+                        op.params.src = Parameter::Imm16(self.read_u16(mmu));
+                    }
+                    OperandSize::_32bit => {
+                        // xor EAX, imm32
+                        op.command = Op::Xor32;
+                        op.params.dst = Parameter::Reg32(R::EAX);
+                        op.params.src = Parameter::Imm32(self.read_u32(mmu));
+                    }
+                }
             }
             0x36 => {
                 // ss segment prefix
@@ -567,17 +933,17 @@ impl Decoder {
             0x38 => {
                 // cmp r/m8, r8
                 op.command = Op::Cmp8;
-                op.params = self.rm8_r8(&mut mmu, op.segment_prefix);
+                op.params = self.rm8_r8(&mut mmu, op);
             }
             0x39 => {
                 // cmp r/m16, r16
-                op.command = Op::Cmp16;
-                op.params = self.rm16_r16(&mut mmu, op);
+                // cmp r/m32, r32
+                self.prefixed_16_32_rm_r(&mut mmu, &mut op, Op::Cmp16, Op::Cmp32)
             }
             0x3A => {
                 // cmp r8, r/m8
                 op.command = Op::Cmp8;
-                op.params = self.r8_rm8(&mut mmu, op.segment_prefix);
+                op.params = self.r8_rm8(&mut mmu, op);
             }
             0x3B => {
                 // cmp r16, r/m16
@@ -696,24 +1062,31 @@ impl Decoder {
                 return;
             }
             0x66 => {
-                // 80386+ Operand-size override prefix
-                op.op_size = OperandSize::_32bit;
+                // 80386+ Operand-size override prefix, selects the size that is not the default of the code segment
+                op.op_size = if self.code32 { OperandSize::_16bit } else { OperandSize::_32bit };
                 self.decode(&mut mmu, &mut op);
                 op.length += 1;
                 return;
             }
             0x67 => {
-                // 80386+ Address-size override prefix
-                op.address_size = AddressSize::_32bit;
+                // 80386+ Address-size override prefix, selects the size that is not the default of the code segment
+                op.address_size = if self.code32 { AddressSize::_16bit } else { AddressSize::_32bit };
                 self.decode(&mut mmu, &mut op);
                 op.length += 1;
                 return;
             }
-            0x68 => {
-                // push imm16
-                op.command = Op::Push16;
-                op.params.dst = Parameter::Imm16(self.read_u16(mmu));
-            }
+            0x68 => match op.op_size {
+                OperandSize::_16bit => {
+                    // push imm16
+                    op.command = Op::Push16;
+                    op.params.dst = Parameter::Imm16(self.read_u16(mmu));
+                }
+                OperandSize::_32bit => {
+                    // push imm32
+                    op.command = Op::Push32;
+                    op.params.dst = Parameter::Imm32(self.read_u32(mmu));
+                }
+            },
             0x69 => match op.op_size {
                 OperandSize::_16bit => {
                     // imul r16, r/m16, imm16
@@ -729,9 +1102,17 @@ impl Decoder {
                 }
             },
             0x6A => {
-                // push imm8
-                op.command = Op::Push16;
-                op.params.dst = Parameter::ImmS8(self.read_s8(mmu));
+                // push imm8, sign extended to the operand size
+                match op.op_size {
+                    OperandSize::_16bit => {
+                        op.command = Op::Push16;
+                        op.params.dst = Parameter::ImmS8(self.read_s8(mmu));
+                    }
+                    OperandSize::_32bit => {
+                        op.command = Op::Push32;
+                        op.params.dst = Parameter::Imm32(self.read_s8(mmu) as u32);
+                    }
+                }
             }
             0x6B => match op.op_size {
                 OperandSize::_16bit => {
@@ -754,88 +1135,88 @@ impl Decoder {
             0x70 => {
                 // jo rel8
                 op.command = Op::Jo;
-                op.params.dst = Parameter::Imm16(self.read_rel8(mmu));
+                op.params.dst = self.read_rel8(mmu, op);
             }
             0x71 => {
                 // jno rel8
                 op.command = Op::Jno;
-                op.params.dst = Parameter::Imm16(self.read_rel8(mmu));
+                op.params.dst = self.read_rel8(mmu, op);
             }
             0x72 => {
                 // jc rel8
                 op.command = Op::Jc;
-                op.params.dst = Parameter::Imm16(self.read_rel8(mmu));
+                op.params.dst = self.read_rel8(mmu, op);
             }
             0x73 => {
                 // jnc rel8
                 op.command = Op::Jnc;
-                op.params.dst = Parameter::Imm16(self.read_rel8(mmu));
+                op.params.dst = self.read_rel8(mmu, op);
             }
             0x74 => {
                 // jz rel8
                 op.command = Op::Jz;
-                op.params.dst = Parameter::Imm16(self.read_rel8(mmu));
+                op.params.dst = self.read_rel8(mmu, op);
             }
             0x75 => {
                 // jnz rel8
                 op.command = Op::Jnz;
-                op.params.dst = Parameter::Imm16(self.read_rel8(mmu));
+                op.params.dst = self.read_rel8(mmu, op);
             }
             0x76 => {
                 // jna rel8
                 op.command = Op::Jna;
-                op.params.dst = Parameter::Imm16(self.read_rel8(mmu));
+                op.params.dst = self.read_rel8(mmu, op);
             }
             0x77 => {
                 // ja rel8
                 op.command = Op::Ja;
-                op.params.dst = Parameter::Imm16(self.read_rel8(mmu));
+                op.params.dst = self.read_rel8(mmu, op);
             }
             0x78 => {
                 // js rel8
                 op.command = Op::Js;
-                op.params.dst = Parameter::Imm16(self.read_rel8(mmu));
+                op.params.dst = self.read_rel8(mmu, op);
             }
             0x79 => {
                 // jns rel8
                 op.command = Op::Jns;
-                op.params.dst = Parameter::Imm16(self.read_rel8(mmu));
+                op.params.dst = self.read_rel8(mmu, op);
             }
 	        0x7A => {
                 // jpe rel8
 		        op.command = Op::Jpe; // alias: jp
-		        op.params.dst = Parameter::Imm16(self.read_rel8(mmu));
+		        op.params.dst = self.read_rel8(mmu, op);
             }
             0x7B => {
                 // jpo rel8
                 op.command = Op::Jpo; // alias: jnp
-                op.params.dst = Parameter::Imm16(self.read_rel8(mmu));
+                op.params.dst = self.read_rel8(mmu, op);
             }
             0x7C => {
                 // jl rel8
                 op.command = Op::Jl;
-                op.params.dst = Parameter::Imm16(self.read_rel8(mmu));
+                op.params.dst = self.read_rel8(mmu, op);
             }
             0x7D => {
                 // jnl rel8
                 op.command = Op::Jnl;
-                op.params.dst = Parameter::Imm16(self.read_rel8(mmu));
+                op.params.dst = self.read_rel8(mmu, op);
             }
             0x7E => {
                 // jng rel8
                 op.command = Op::Jng;
-                op.params.dst = Parameter::Imm16(self.read_rel8(mmu));
+                op.params.dst = self.read_rel8(mmu, op);
             }
             0x7F => {
                 // jg rel8
                 op.command = Op::Jg;
-                op.params.dst = Parameter::Imm16(self.read_rel8(mmu));
+                op.params.dst = self.read_rel8(mmu, op);
             }
             0x80 | 0x82 => {
                 // <arithmetic> r/m8, imm8
                 // 0x82 is unrecognized by objdump & ndisasm, but alias to 0x80 on pre Pentium 4:s according to ref.x86asm.net
                 let x = self.read_mod_reg_rm(mmu);
-                op.params.dst = self.rm8(&mut mmu, op.segment_prefix, x.rm, x.md);
+                op.params.dst = self.rm8(&mut mmu, op, x.rm, x.md);
                 op.params.src = Parameter::Imm8(self.read_u8(mmu));
                 op.command = match x.reg {
                     0 => Op::Add8,
@@ -927,27 +1308,27 @@ impl Decoder {
             0x84 => {
                 // test r/m8, r8
                 op.command = Op::Test8;
-                op.params = self.rm8_r8(&mut mmu, op.segment_prefix);
+                op.params = self.rm8_r8(&mut mmu, op);
             }
             0x85 => {
                 // test r/m16, r16
-                op.command = Op::Test16;
-                op.params = self.rm16_r16(&mut mmu, op);
+                // test r/m32, r32
+                self.prefixed_16_32_rm_r(&mut mmu, &mut op, Op::Test16, Op::Test32)
             }
             0x86 => {
                 // xchg r/m8, r8
                 op.command = Op::Xchg8;
-                op.params = self.rm8_r8(&mut mmu, op.segment_prefix);
+                op.params = self.rm8_r8(&mut mmu, op);
             }
             0x87 => {
                 // xchg r/m16, r16
-                op.command = Op::Xchg16;
-                op.params = self.rm16_r16(&mut mmu, op);
+                // xchg r/m32, r32
+                self.prefixed_16_32_rm_r(&mut mmu, &mut op, Op::Xchg16, Op::Xchg32)
             }
             0x88 => {
                 // mov r/m8, r8
                 op.command = Op::Mov8;
-                op.params = self.rm8_r8(&mut mmu, op.segment_prefix);
+                op.params = self.rm8_r8(&mut mmu, op);
             }
             0x89 => {
                 // mov r/m16, r16
@@ -957,7 +1338,7 @@ impl Decoder {
             0x8A => {
                 // mov r8, r/m8
                 op.command = Op::Mov8;
-                op.params = self.r8_rm8(&mut mmu, op.segment_prefix);
+                op.params = self.r8_rm8(&mut mmu, op);
             }
             0x8B => {
                 // mov r16, r/m16
@@ -970,9 +1351,18 @@ impl Decoder {
                 op.params = self.rm16_sreg(&mut mmu, op);
             }
             0x8D => {
-                // lea r16, m
-                op.command = Op::Lea16;
-                op.params = self.r16_m16(&mut mmu, op);
+                match op.op_size {
+                    OperandSize::_16bit => {
+                        // lea r16, m
+                        op.command = Op::Lea16;
+                        op.params = self.r16_m16(&mut mmu, op);
+                    }
+                    OperandSize::_32bit => {
+                        // lea r32, m
+                        op.command = Op::Lea32;
+                        op.params = self.r32_rm32(&mut mmu, op);
+                    }
+                }
             }
             0x8E => {
                 // mov sreg, r/m16
@@ -981,9 +1371,12 @@ impl Decoder {
             }
             0x8F => {
                 let x = self.read_mod_reg_rm(mmu);
-                op.params.dst = self.rm16(&mut mmu, op, x.rm, x.md);
+                op.params.dst = match op.op_size {
+                    OperandSize::_16bit => self.rm16(&mut mmu, op, x.rm, x.md),
+                    OperandSize::_32bit => self.rm32(&mut mmu, op, x.rm, x.md),
+                };
                 op.command = match x.reg {
-                    0 => Op::Pop16, // pop r/m16
+                    0 => self.sized_op(op, Op::Pop16, Op::Pop32), // pop r/m16, r/m32
                     _ => Op::Invalid(vec!(b), Invalid::FPUOp),
                 };
             }
@@ -1008,18 +1401,18 @@ impl Decoder {
                     OperandSize::_32bit => Op::Cwde32,
                 };
             }
-            0x99 => op.command = Op::Cwd16,
+            0x99 => op.command = self.sized_op(op, Op::Cwd16, Op::Cdq32),
             0x9A => {
-                // call ptr16:16
+                // call ptr16:16, ptr16:32
                 op.command = Op::CallFar;
-                let imm = self.read_u16(mmu);
+                let imm = match op.op_size {
+                    OperandSize::_16bit => u32::from(self.read_u16(mmu)),
+                    OperandSize::_32bit => self.read_u32(mmu),
+                };
                 let seg = self.read_u16(mmu);
                 op.params.dst = Parameter::Ptr16Imm(seg, imm);
             }
-            0x9B => {
-                // TODO fpu instructions
-                op.command = Op::Invalid(vec!(b), Invalid::FPUOp);
-            }
+            0x9B => op.command = Op::Fwait,
             0x9C => op.command = Op::Pushf,
             0x9D => op.command = Op::Popf,
             0x9E => op.command = Op::Sahf,
@@ -1028,39 +1421,39 @@ impl Decoder {
                 // mov AL, [moffs8]
                 op.command = Op::Mov8;
                 op.params.dst = Parameter::Reg8(R::AL);
-                op.params.src = Parameter::Ptr8(op.segment_prefix, self.read_u16(mmu));
+                op.params.src = self.moffs(mmu, op).ptr8(op.segment_prefix);
             }
             0xA1 => match op.op_size {
                 OperandSize::_16bit => {
                     // mov AX, [moffs16]
                     op.command = Op::Mov16;
                     op.params.dst = Parameter::Reg16(R::AX);
-                    op.params.src = Parameter::Ptr16(op.segment_prefix, self.read_u16(mmu));
+                    op.params.src = self.moffs(mmu, op).ptr16(op.segment_prefix);
                 }
                 OperandSize::_32bit => {
                     // mov EAX, [moffs32]
                     op.command = Op::Mov32;
                     op.params.dst = Parameter::Reg32(R::EAX);
-                    op.params.src = Parameter::Ptr32(op.segment_prefix, self.read_u16(mmu));
+                    op.params.src = self.moffs(mmu, op).ptr32(op.segment_prefix);
                 }
             },
             0xA2 => {
                 // mov [moffs8], AL
                 op.command = Op::Mov8;
-                op.params.dst = Parameter::Ptr8(op.segment_prefix, self.read_u16(mmu));
+                op.params.dst = self.moffs(mmu, op).ptr8(op.segment_prefix);
                 op.params.src = Parameter::Reg8(R::AL);
             }
             0xA3 => match op.op_size {
                 OperandSize::_16bit => {
                     // mov [moffs16], AX
                     op.command = Op::Mov16;
-                    op.params.dst = Parameter::Ptr16(op.segment_prefix, self.read_u16(mmu));
+                    op.params.dst = self.moffs(mmu, op).ptr16(op.segment_prefix);
                     op.params.src = Parameter::Reg16(R::AX);
                 }
                 OperandSize::_32bit => {
                     // mov [moffs32], EAX
                     op.command = Op::Mov32;
-                    op.params.dst = Parameter::Ptr32(op.segment_prefix, self.read_u16(mmu));
+                    op.params.dst = self.moffs(mmu, op).ptr32(op.segment_prefix);
                     op.params.src = Parameter::Reg32(R::EAX);
                 }
             },
@@ -1070,7 +1463,7 @@ impl Decoder {
                 OperandSize::_32bit => Op::Movsd,
             },
             0xA6 => op.command = Op::Cmpsb,
-            0xA7 => op.command = Op::Cmpsw,
+            0xA7 => op.command = self.sized_op(op, Op::Cmpsw, Op::Cmpsd),
             0xA8 => {
                 // test AL, imm8
                 op.command = Op::Test8;
@@ -1078,10 +1471,20 @@ impl Decoder {
                 op.params.src = Parameter::Imm8(self.read_u8(mmu));
             }
             0xA9 => {
-                // test AX, imm16
-                op.command = Op::Test16;
-                op.params.dst = Parameter::Reg16(R::AX);
-                op.params.src = Parameter::Imm16(self.read_u16(mmu));
+                match op.op_size {
+                    OperandSize::_16bit => {
+                        // test AX, imm16
+                        op.command = Op::Test16;
+                        op.params.dst = Parameter::Reg16(R::AX);
+                        op.params.src = Parameter::Imm16(self.read_u16(mmu));
+                    }
+                    OperandSize::_32bit => {
+                        // test EAX, imm32
+                        op.command = Op::Test32;
+                        op.params.dst = Parameter::Reg32(R::EAX);
+                        op.params.src = Parameter::Imm32(self.read_u32(mmu));
+                    }
+                }
             }
             0xAA => op.command = Op::Stosb,
             0xAB => op.command = match op.op_size {
@@ -1094,7 +1497,7 @@ impl Decoder {
                 OperandSize::_32bit => Op::Lodsd,
             },
             0xAE => op.command = Op::Scasb,
-            0xAF => op.command = Op::Scasw,
+            0xAF => op.command = self.sized_op(op, Op::Scasw, Op::Scasd),
             0xB0...0xB7 => {
                 // mov r8, u8
                 op.command = Op::Mov8;
@@ -1128,7 +1531,7 @@ impl Decoder {
                     7 => Op::Sar8,
                     _ => Op::Invalid(vec!(b), Invalid::Reg(x.reg)),
                 };
-                op.params.dst = self.rm8(&mut mmu, op.segment_prefix, x.rm, x.md);
+                op.params.dst = self.rm8(&mut mmu, op, x.rm, x.md);
                 op.params.src = Parameter::Imm8(self.read_u8(mmu));
             }
             0xC1 => {
@@ -1173,18 +1576,20 @@ impl Decoder {
             }
             0xC3 => op.command = Op::Retn, // ret [near]
             0xC4 => {
-                // les r16, m16
+                // les r16, m16:16
+                // les r32, m16:32
                 op.command = Op::Les;
-                op.params = self.r16_m16(&mut mmu, op);
+                op.params = self.r_far_pointer(&mut mmu, op);
             }
             0xC5 => {
-                // lds r16, m16
+                // lds r16, m16:16
+                // lds r32, m16:32
                 op.command = Op::Lds;
-                op.params = self.r16_m16(&mut mmu, op);
+                op.params = self.r_far_pointer(&mut mmu, op);
             }
             0xC6 => {
                 let x = self.read_mod_reg_rm(mmu);
-                op.params.dst = self.rm8(&mut mmu, op.segment_prefix, x.rm, x.md);
+                op.params.dst = self.rm8(&mut mmu, op, x.rm, x.md);
                 op.params.src = Parameter::Imm8(self.read_u8(mmu));
                 op.command = match x.reg {
                     0 => Op::Mov8, // mov r/m8, imm8
@@ -1249,23 +1654,40 @@ impl Decoder {
                     7 => Op::Sar8,
                     _ => Op::Invalid(vec!(b), Invalid::Reg(x.reg)),
                 };
-                op.params.dst = self.rm8(&mut mmu, op.segment_prefix, x.rm, x.md);
+                op.params.dst = self.rm8(&mut mmu, op, x.rm, x.md);
                 op.params.src = Parameter::Imm8(1);
             }
             0xD1 => {
-                // bit shift word by 1
+                // bit shift word by 1 or dword
                 let x = self.read_mod_reg_rm(mmu);
-                op.command = match x.reg {
-                    0 => Op::Rol16,
-                    1 => Op::Ror16,
-                    2 => Op::Rcl16,
-                    3 => Op::Rcr16,
-                    4 => Op::Shl16,
-                    5 => Op::Shr16,
-                    7 => Op::Sar16,
-                    _ => Op::Invalid(vec!(b), Invalid::Reg(x.reg)),
-                };
-                op.params.dst = self.rm16(&mut mmu, op, x.rm, x.md);
+                match op.op_size {
+                    OperandSize::_16bit => {
+                        op.command = match x.reg {
+                            0 => Op::Rol16,
+                            1 => Op::Ror16,
+                            2 => Op::Rcl16,
+                            3 => Op::Rcr16,
+                            4 => Op::Shl16,
+                            5 => Op::Shr16,
+                            7 => Op::Sar16,
+                            _ => Op::Invalid(vec!(b), Invalid::Reg(x.reg)),
+                        };
+                        op.params.dst = self.rm16(&mut mmu, op, x.rm, x.md);
+                    }
+                    OperandSize::_32bit => {
+                        op.command = match x.reg {
+                            0 => Op::Rol32,
+                            1 => Op::Ror32,
+                            2 => Op::Rcl32,
+                            3 => Op::Rcr32,
+                            4 => Op::Shl32,
+                            5 => Op::Shr32,
+                            7 => Op::Sar32,
+                            _ => Op::Invalid(vec!(b), Invalid::Reg(x.reg)),
+                        };
+                        op.params.dst = self.rm32(&mut mmu, op, x.rm, x.md);
+                    }
+                }
                 op.params.src = Parameter::Imm16(1);
             }
             0xD2 => {
@@ -1281,23 +1703,40 @@ impl Decoder {
                     7 => Op::Sar8,
                     _ => Op::Invalid(vec!(b), Invalid::Reg(x.reg)),
                 };
-                op.params.dst = self.rm8(&mut mmu, op.segment_prefix, x.rm, x.md);
+                op.params.dst = self.rm8(&mut mmu, op, x.rm, x.md);
                 op.params.src = Parameter::Reg8(R::CL);
             }
             0xD3 => {
-                // bit shift word by CL
+                // bit shift word by CL or dword
                 let x = self.read_mod_reg_rm(mmu);
-                op.command = match x.reg {
-                    0 => Op::Rol16,
-                    1 => Op::Ror16,
-                    2 => Op::Rcl16,
-                    3 => Op::Rcr16,
-                    4 => Op::Shl16,
-                    5 => Op::Shr16,
-                    7 => Op::Sar16,
-                    _ => Op::Invalid(vec!(b), Invalid::Reg(x.reg)),
-                };
-                op.params.dst = self.rm16(&mut mmu, op, x.rm, x.md);
+                match op.op_size {
+                    OperandSize::_16bit => {
+                        op.command = match x.reg {
+                            0 => Op::Rol16,
+                            1 => Op::Ror16,
+                            2 => Op::Rcl16,
+                            3 => Op::Rcr16,
+                            4 => Op::Shl16,
+                            5 => Op::Shr16,
+                            7 => Op::Sar16,
+                            _ => Op::Invalid(vec!(b), Invalid::Reg(x.reg)),
+                        };
+                        op.params.dst = self.rm16(&mut mmu, op, x.rm, x.md);
+                    }
+                    OperandSize::_32bit => {
+                        op.command = match x.reg {
+                            0 => Op::Rol32,
+                            1 => Op::Ror32,
+                            2 => Op::Rcl32,
+                            3 => Op::Rcr32,
+                            4 => Op::Shl32,
+                            5 => Op::Shr32,
+                            7 => Op::Sar32,
+                            _ => Op::Invalid(vec!(b), Invalid::Reg(x.reg)),
+                        };
+                        op.params.dst = self.rm32(&mut mmu, op, x.rm, x.md);
+                    }
+                }
                 op.params.src = Parameter::Reg8(R::CL);
             }
             0xD4 => {
@@ -1311,26 +1750,23 @@ impl Decoder {
             }
             0xD6 => op.command = Op::Salc,
             0xD7 => op.command = Op::Xlatb,
-            0xD8...0xDF => {
-                // fpu
-                op.command = Op::Invalid(vec!(b), Invalid::FPUOp);
-            }
+            0xD8...0xDF => self.decode_fpu(&mut mmu, op, b),
             0xE0 => {
                 op.command = Op::Loopne;
-                op.params.dst = Parameter::Imm16(self.read_rel8(mmu));
+                op.params.dst = self.read_rel8(mmu, op);
             }
             0xE1 => {
                 op.command = Op::Loope;
-                op.params.dst = Parameter::Imm16(self.read_rel8(mmu));
+                op.params.dst = self.read_rel8(mmu, op);
             }
             0xE2 => {
                 op.command = Op::Loop;
-                op.params.dst = Parameter::Imm16(self.read_rel8(mmu));
+                op.params.dst = self.read_rel8(mmu, op);
             }
             0xE3 => {
                 // jcxz rel8
                 op.command = Op::Jcxz;
-                op.params.dst = Parameter::Imm16(self.read_rel8(mmu));
+                op.params.dst = self.read_rel8(mmu, op);
             }
             0xE4 => {
                 // in AL, imm8
@@ -1357,26 +1793,29 @@ impl Decoder {
                 op.params.src = Parameter::Reg16(R::AX);
             }
             0xE8 => {
-                // call near s16
+                // call near rel16, rel32
                 op.command = Op::CallNear;
-                op.params.dst = Parameter::Imm16(self.read_rel16(mmu));
+                op.params.dst = self.read_rel(mmu, op);
             }
             0xE9 => {
-                // jmp near rel16
+                // jmp near rel16, rel32
                 op.command = Op::JmpNear;
-                op.params.dst = Parameter::Imm16(self.read_rel16(mmu));
+                op.params.dst = self.read_rel(mmu, op);
             }
             0xEA => {
-                // jmp far ptr16:16
+                // jmp far ptr16:16, ptr16:32
                 op.command = Op::JmpFar;
-                let imm = self.read_u16(mmu);
+                let imm = match op.op_size {
+                    OperandSize::_16bit => u32::from(self.read_u16(mmu)),
+                    OperandSize::_32bit => self.read_u32(mmu),
+                };
                 let seg = self.read_u16(mmu);
                 op.params.dst = Parameter::Ptr16Imm(seg, imm);
             }
             0xEB => {
                 // jmp short rel8
                 op.command = Op::JmpShort;
-                op.params.dst = Parameter::Imm16(self.read_rel8(mmu));
+                op.params.dst = self.read_rel8(mmu, op);
             }
             0xEC => {
                 // in AL, DX
@@ -1445,7 +1884,7 @@ impl Decoder {
             0xF6 => {
                 // <math> r/m8
                 let x = self.read_mod_reg_rm(mmu);
-                op.params.dst = self.rm8(&mut mmu, op.segment_prefix, x.rm, x.md);
+                op.params.dst = self.rm8(&mut mmu, op, x.rm, x.md);
                 match x.reg {
                     0 | 1 => {
                         // test r/m8, imm8
@@ -1511,7 +1950,7 @@ impl Decoder {
             0xFE => {
                 // r/m8
                 let x = self.read_mod_reg_rm(mmu);
-                op.params.dst = self.rm8(&mut mmu, op.segment_prefix, x.rm, x.md);
+                op.params.dst = self.rm8(&mut mmu, op, x.rm, x.md);
                 op.command = match x.reg {
                     // NOTE: 2 is a deprecated but valid encoding, example:
                     // https://www.pouet.net/prod.php?which=65203
@@ -1543,6 +1982,11 @@ impl Decoder {
                         op.command = match x.reg {
                             0 => Op::Inc32,
                             1 => Op::Dec32,
+                            2 => Op::CallNear,
+                            3 => Op::CallFar,
+                            4 => Op::JmpNear,
+                            5 => Op::JmpFar,
+                            6 => Op::Push32,
                             _ => Op::Invalid(vec!(b), Invalid::Reg(x.reg)),
                         };
                     }
@@ -1551,12 +1995,20 @@ impl Decoder {
             _ => op.command = Op::Invalid(vec!(b), Invalid::Op),
         }
         // calculate instruction length
-        op.length = (Wrapping(u16::from(op.length)) + Wrapping(self.current_offset) - Wrapping(start_offset)).0 as u8;
+        op.length = (u32::from(op.length) + (self.current_offset.wrapping_sub(start_offset) & self.offset_mask())) as u8;
         if DEBUG_DECODER {
             println!("decode op end {:?}", op);
         }
     }
 
+    /// returns op16 or op32 by the operand size of op
+    fn sized_op(&self, op: &Instruction, op16: Op, op32: Op) -> Op {
+        match op.op_size {
+            OperandSize::_16bit => op16,
+            OperandSize::_32bit => op32,
+        }
+    }
+
     fn prefixed_16_32_rm_r(&mut self, mut mmu: &mut MMU, op: &mut Instruction, op16: Op, op32: Op) {
         match op.op_size {
             OperandSize::_16bit => {
@@ -1583,88 +2035,263 @@ impl Decoder {
         }
     }
 
-    /// decode rm8
-    fn rm8(&mut self, mmu: &mut MMU, seg: Segment, rm: u8, md: u8) -> Parameter {
-        let adr_size = AddressSize::_16bit;
-        match md {
-            0 => {
-                if rm == 6 {
-                    // [u16]
-                    Parameter::Ptr8(seg, self.read_u16(mmu))
+    /// decode the memory operand of a mod r/m byte (md 0-2), including a sib byte and disp32 under the 0x67 prefix
+    fn memory_operand(&mut self, mmu: &MMU, op: &Instruction, rm: u8, md: u8) -> MemoryOperand {
+        match op.address_size {
+            AddressSize::_16bit => match md {
+                // [u16]
+                0 if rm == 6 => MemoryOperand::Direct(self.read_u16(mmu)),
+                // [amode]
+                0 => MemoryOperand::Amode(op.address_size.amode_from(rm)),
+                // [amode+s8]
+                1 => MemoryOperand::AmodeS8(op.address_size.amode_from(rm), self.read_s8(mmu)),
+                // [amode+s16]
+                2 => MemoryOperand::AmodeS16(op.address_size.amode_from(rm), self.read_s16(mmu)),
+                _ => unreachable!(),
+            },
+            AddressSize::_32bit => {
+                let amode = if rm == 4 {
+                    self.read_sib(mmu, md)
+                } else if md == 0 && rm == 5 {
+                    // [u32]
+                    AMode::SIB(None, None, 1)
                 } else {
+                    op.address_size.amode_from(rm)
+                };
+                let without_base = match amode {
+                    AMode::SIB(None, _, _) => true,
+                    _ => false,
+                };
+                match md {
+                    // [index*scale+s32]
+                    0 if without_base => MemoryOperand::AmodeS32(amode, self.read_s32(mmu)),
                     // [amode]
-                    Parameter::Ptr8Amode(seg, adr_size.amode_from(rm))
+                    0 => MemoryOperand::Amode(amode),
+                    // [amode+s8]
+                    1 => MemoryOperand::AmodeS8(amode, self.read_s8(mmu)),
+                    // [amode+s32]
+                    2 => MemoryOperand::AmodeS32(amode, self.read_s32(mmu)),
+                    _ => unreachable!(),
                 }
             }
-            // [amode+s8]
-            1 => Parameter::Ptr8AmodeS8(seg, adr_size.amode_from(rm), self.read_s8(mmu)),
-            // [amode+s16]
-            2 => Parameter::Ptr8AmodeS16(seg, adr_size.amode_from(rm), self.read_s16(mmu)),
-            // reg
-            3 => Parameter::Reg8(r8(rm)),
-            _ => unreachable!(),
         }
     }
 
+    /// decode a moffs operand, which is a disp32 under the 0x67 prefix
+    fn moffs(&mut self, mmu: &MMU, op: &Instruction) -> MemoryOperand {
+        match op.address_size {
+            AddressSize::_16bit => MemoryOperand::Direct(self.read_u16(mmu)),
+            AddressSize::_32bit => MemoryOperand::AmodeS32(AMode::SIB(None, None, 1), self.read_s32(mmu)),
+        }
+    }
+
+    /// decode a scale-index-base byte
+    fn read_sib(&mut self, mmu: &MMU, md: u8) -> AMode {
+        let b = self.read_u8(mmu);
+        let scale = 1 << (b >> 6);
+        let index = (b >> 3) & 7;
+        let base = b & 7;
+        AMode::SIB(
+            // base 5 without displacement means disp32 only
+            if base == 5 && md == 0 { None } else { Some(r32(base)) },
+            // index 4 means no index
+            if index == 4 { None } else { Some(r32(index)) },
+            scale,
+        )
+    }
+
+    /// decode rm8
+    fn rm8(&mut self, mmu: &mut MMU, op: &Instruction, rm: u8, md: u8) -> Parameter {
+        if md == 3 {
+            return Parameter::Reg8(r8(rm));
+        }
+        self.memory_operand(mmu, op, rm, md).ptr8(op.segment_prefix)
+    }
+
     /// decode rm16
     fn rm16(&mut self, mmu: &mut MMU, op: &Instruction, rm: u8, md: u8) -> Parameter {
-        match md {
-            0 => {
-                if rm == 6 {
-                    // [u16]
-                    Parameter::Ptr16(op.segment_prefix, self.read_u16(mmu))
-                } else {
-                    // [amode]
-                    Parameter::Ptr16Amode(op.segment_prefix, op.address_size.amode_from(rm))
-                }
-            }
-            // [amode+s8]
-            1 => Parameter::Ptr16AmodeS8(op.segment_prefix, op.address_size.amode_from(rm), self.read_s8(mmu)),
-            // [amode+s16]
-            2 => Parameter::Ptr16AmodeS16(op.segment_prefix, op.address_size.amode_from(rm), self.read_s16(mmu)),
-            // [reg]
-            3 => Parameter::Reg16(r16(rm)),
-            _ => unreachable!(),
+        if md == 3 {
+            return Parameter::Reg16(r16(rm));
         }
+        self.memory_operand(mmu, op, rm, md).ptr16(op.segment_prefix)
     }
 
     /// decode rm32
     fn rm32(&mut self, mmu: &mut MMU, op: &Instruction, rm: u8, md: u8) -> Parameter {
-        match md {
-            0 => {
-                if rm == 6 {
-                    // [u16]
-                    Parameter::Ptr32(op.segment_prefix, self.read_u16(mmu))
-                } else {
-                    // [amode]
-                    Parameter::Ptr32Amode(op.segment_prefix, op.address_size.amode_from(rm))
+        if md == 3 {
+            return Parameter::Reg32(r32(rm));
+        }
+        self.memory_operand(mmu, op, rm, md).ptr32(op.segment_prefix)
+    }
+
+    /// decode m64
+    fn rm64(&mut self, mmu: &mut MMU, op: &Instruction, rm: u8, md: u8) -> Parameter {
+        self.memory_operand(mmu, op, rm, md).ptr64(op.segment_prefix)
+    }
+
+    /// decode m80
+    fn rm80(&mut self, mmu: &mut MMU, op: &Instruction, rm: u8, md: u8) -> Parameter {
+        self.memory_operand(mmu, op, rm, md).ptr80(op.segment_prefix)
+    }
+
+    /// decode the x87 escape opcodes D8-DF
+    fn decode_fpu(&mut self, mut mmu: &mut MMU, op: &mut Instruction, b: u8) {
+        let x = self.read_mod_reg_rm(mmu);
+        let b2 = (x.md << 6) | (x.reg << 3) | x.rm;
+        if x.md != 3 {
+            // memory operand
+            let mem = match (b, x.reg) {
+                (0xD8, _) | (0xD9, 0...3) | (0xDA, _) | (0xDB, 0...3) => self.rm32(&mut mmu, op, x.rm, x.md),
+                (0xDC, _) | (0xDD, 0...3) | (0xDF, 5) | (0xDF, 7) => self.rm64(&mut mmu, op, x.rm, x.md),
+                (0xDB, 5) | (0xDB, 7) | (0xDF, 4) | (0xDF, 6) => self.rm80(&mut mmu, op, x.rm, x.md),
+                // m16int, m2byte and the environment / state images
+                _ => self.rm16(&mut mmu, op, x.rm, x.md),
+            };
+            op.command = match b {
+                0xD8 | 0xDC => match x.reg {
+                    0 => Op::Fadd, 1 => Op::Fmul, 2 => Op::Fcom, 3 => Op::Fcomp,
+                    4 => Op::Fsub, 5 => Op::Fsubr, 6 => Op::Fdiv, _ => Op::Fdivr,
+                },
+                0xDA | 0xDE => match x.reg {
+                    0 => Op::Fiadd, 1 => Op::Fimul, 2 => Op::Ficom, 3 => Op::Ficomp,
+                    4 => Op::Fisub, 5 => Op::Fisubr, 6 => Op::Fidiv, _ => Op::Fidivr,
+                },
+                0xD9 => match x.reg {
+                    0 => Op::Fld, 2 => Op::Fst, 3 => Op::Fstp, 4 => Op::Fldenv,
+                    5 => Op::Fldcw, 6 => Op::Fnstenv, 7 => Op::Fnstcw,
+                    _ => Op::Invalid(vec!(b, b2), Invalid::FPUOp),
+                },
+                0xDB => match x.reg {
+                    0 => Op::Fild, 2 => Op::Fist, 3 => Op::Fistp, 5 => Op::Fld, 7 => Op::Fstp,
+                    _ => Op::Invalid(vec!(b, b2), Invalid::FPUOp),
+                },
+                0xDD => match x.reg {
+                    0 => Op::Fld, 2 => Op::Fst, 3 => Op::Fstp, 4 => Op::Frstor,
+                    6 => Op::Fnsave, 7 => Op::Fnstsw,
+                    _ => Op::Invalid(vec!(b, b2), Invalid::FPUOp),
+                },
+                _ => match x.reg {
+                    0 | 5 => Op::Fild, 2 => Op::Fist, 3 | 7 => Op::Fistp, 4 => Op::Fbld, 6 => Op::Fbstp,
+                    _ => Op::Invalid(vec!(b, b2), Invalid::FPUOp),
+                },
+            };
+            match op.command {
+                Op::Fadd | Op::Fmul | Op::Fsub | Op::Fsubr | Op::Fdiv | Op::Fdivr |
+                Op::Fiadd | Op::Fimul | Op::Fisub | Op::Fisubr | Op::Fidiv | Op::Fidivr => {
+                    // st0 = st0 <op> mem
+                    op.params.dst = Parameter::ST(0);
+                    op.params.src = mem;
                 }
+                _ => op.params.dst = mem,
             }
-            // [amode+s8]
-            1 => Parameter::Ptr32AmodeS8(op.segment_prefix, op.address_size.amode_from(rm), self.read_s8(mmu)),
-            // [amode+s16]
-            2 => Parameter::Ptr32AmodeS16(op.segment_prefix, op.address_size.amode_from(rm), self.read_s16(mmu)),
-            // [reg]
-            3 => Parameter::Reg32(r32(rm)),
-            _ => unreachable!(),
+            return;
         }
+
+        // register operand
+        let sti = Parameter::ST(x.rm);
+        let (command, dst, src) = match (b, x.reg) {
+            (0xD8, 2) | (0xDC, 2) => (Op::Fcom, sti, Parameter::None),
+            (0xD8, 3) | (0xDC, 3) | (0xDE, 2) => (Op::Fcomp, sti, Parameter::None),
+            (0xD8, _) => {
+                // st0 = st0 <op> sti
+                let command = match x.reg {
+                    0 => Op::Fadd, 1 => Op::Fmul, 4 => Op::Fsub, 5 => Op::Fsubr, 6 => Op::Fdiv, _ => Op::Fdivr,
+                };
+                (command, Parameter::ST(0), sti)
+            }
+            (0xDC, _) | (0xDE, _) => {
+                // sti = sti <op> st0, note that the sub and div forms are swapped
+                let command = match (b, x.reg) {
+                    (0xDC, 0) => Op::Fadd, (0xDC, 1) => Op::Fmul,
+                    (0xDC, 4) => Op::Fsubr, (0xDC, 5) => Op::Fsub,
+                    (0xDC, 6) => Op::Fdivr, (0xDC, _) => Op::Fdiv,
+                    (_, 0) => Op::Faddp, (_, 1) => Op::Fmulp,
+                    (_, 3) if x.rm == 1 => Op::Fcompp,
+                    (_, 4) => Op::Fsubrp, (_, 5) => Op::Fsubp,
+                    (_, 6) => Op::Fdivrp, (_, 7) => Op::Fdivp,
+                    _ => Op::Invalid(vec!(b, b2), Invalid::FPUOp),
+                };
+                if command == Op::Fcompp {
+                    (command, Parameter::None, Parameter::None)
+                } else {
+                    (command, sti, Parameter::ST(0))
+                }
+            }
+            (0xD9, 0) => (Op::Fld, sti, Parameter::None),
+            (0xD9, 1) | (0xDD, 1) => (Op::Fxch, sti, Parameter::None),
+            (0xD9, _) => {
+                let command = match b2 {
+                    0xD0 => Op::Fnop,
+                    0xE0 => Op::Fchs,
+                    0xE1 => Op::Fabs,
+                    0xE4 => Op::Ftst,
+                    0xE5 => Op::Fxam,
+                    0xE8 => Op::Fld1,
+                    0xE9 => Op::Fldl2t,
+                    0xEA => Op::Fldl2e,
+                    0xEB => Op::Fldpi,
+                    0xEC => Op::Fldlg2,
+                    0xED => Op::Fldln2,
+                    0xEE => Op::Fldz,
+                    0xF0 => Op::F2xm1,
+                    0xF1 => Op::Fyl2x,
+                    0xF2 => Op::Fptan,
+                    0xF3 => Op::Fpatan,
+                    0xF4 => Op::Fxtract,
+                    0xF5 => Op::Fprem1,
+                    0xF6 => Op::Fdecstp,
+                    0xF7 => Op::Fincstp,
+                    0xF8 => Op::Fprem,
+                    0xF9 => Op::Fyl2xp1,
+                    0xFA => Op::Fsqrt,
+                    0xFB => Op::Fsincos,
+                    0xFC => Op::Frndint,
+                    0xFD => Op::Fscale,
+                    0xFE => Op::Fsin,
+                    0xFF => Op::Fcos,
+                    _ => Op::Invalid(vec!(b, b2), Invalid::FPUOp),
+                };
+                (command, Parameter::None, Parameter::None)
+            }
+            (0xDA, _) if b2 == 0xE9 => (Op::Fucompp, Parameter::None, Parameter::None),
+            (0xDB, _) => {
+                let command = match b2 {
+                    // FENI, FDISI (8087) and FSETPM (80287) are ignored by later FPUs
+                    0xE0 | 0xE1 | 0xE4 => Op::Fnop,
+                    0xE2 => Op::Fnclex,
+                    0xE3 => Op::Fninit,
+                    _ => Op::Invalid(vec!(b, b2), Invalid::FPUOp),
+                };
+                (command, Parameter::None, Parameter::None)
+            }
+            (0xDD, 0) => (Op::Ffree, sti, Parameter::None),
+            (0xDD, 2) => (Op::Fst, sti, Parameter::None),
+            (0xDD, 3) => (Op::Fstp, sti, Parameter::None),
+            (0xDD, 4) => (Op::Fucom, sti, Parameter::None),
+            (0xDD, 5) => (Op::Fucomp, sti, Parameter::None),
+            (0xDF, _) if b2 == 0xE0 => (Op::Fnstsw, Parameter::Reg16(R::AX), Parameter::None),
+            _ => (Op::Invalid(vec!(b, b2), Invalid::FPUOp), Parameter::None, Parameter::None),
+        };
+        op.command = command;
+        op.params.dst = dst;
+        op.params.src = src;
     }
 
     /// decode r8, r/m8
-    fn r8_rm8(&mut self, mut mmu: &mut MMU, seg: Segment) -> ParameterSet {
+    fn r8_rm8(&mut self, mut mmu: &mut MMU, op: &Instruction) -> ParameterSet {
         let x = self.read_mod_reg_rm(mmu);
         ParameterSet {
             dst: Parameter::Reg8(r8(x.reg)),
-            src: self.rm8(&mut mmu, seg, x.rm, x.md),
+            src: self.rm8(&mut mmu, op, x.rm, x.md),
             src2: Parameter::None,
         }
     }
 
     /// decode r/m8, r8
-    fn rm8_r8(&mut self, mut mmu: &mut MMU, seg: Segment) -> ParameterSet {
+    fn rm8_r8(&mut self, mut mmu: &mut MMU, op: &Instruction) -> ParameterSet {
         let x = self.read_mod_reg_rm(mmu);
         ParameterSet {
-            dst: self.rm8(&mut mmu, seg, x.rm, x.md),
+            dst: self.rm8(&mut mmu, op, x.rm, x.md),
             src: Parameter::Reg8(r8(x.reg)),
             src2: Parameter::None,
         }
@@ -1691,21 +2318,21 @@ impl Decoder {
     }
 
     /// decode r16, r/m8 (movzx)
-    fn r16_rm8(&mut self, mut mmu: &mut MMU, seg: Segment) -> ParameterSet {
+    fn r16_rm8(&mut self, mut mmu: &mut MMU, op: &Instruction) -> ParameterSet {
         let x = self.read_mod_reg_rm(mmu);
         ParameterSet {
             dst: Parameter::Reg16(r16(x.reg)),
-            src: self.rm8(&mut mmu, seg, x.rm, x.md),
+            src: self.rm8(&mut mmu, op, x.rm, x.md),
             src2: Parameter::None,
         }
     }
 
     /// decode r32, r/m8 (movzx)
-    fn r32_rm8(&mut self, mut mmu: &mut MMU, seg: Segment) -> ParameterSet {
+    fn r32_rm8(&mut self, mut mmu: &mut MMU, op: &Instruction) -> ParameterSet {
         let x = self.read_mod_reg_rm(mmu);
         ParameterSet {
             dst: Parameter::Reg32(r32(x.reg)),
-            src: self.rm8(&mut mmu, seg, x.rm, x.md),
+            src: self.rm8(&mut mmu, op, x.rm, x.md),
             src2: Parameter::None,
         }
     }
@@ -1753,6 +2380,26 @@ impl Decoder {
         }
     }
 
+    /// decode r16, m16:16 or r32, m16:32 (lss, lfs, lgs)
+    fn r_far_pointer(&mut self, mut mmu: &mut MMU, op: &Instruction) -> ParameterSet {
+        let x = self.read_mod_reg_rm(mmu);
+        if x.md == 3 {
+            println!("r_far_pointer error: invalid encoding, ip={:04X}", self.current_offset);
+        }
+        match op.op_size {
+            OperandSize::_16bit => ParameterSet {
+                dst: Parameter::Reg16(r16(x.reg)),
+                src: self.rm16(&mut mmu, op, x.rm, x.md),
+                src2: Parameter::None,
+            },
+            OperandSize::_32bit => ParameterSet {
+                dst: Parameter::Reg32(r32(x.reg)),
+                src: self.rm32(&mut mmu, op, x.rm, x.md),
+                src2: Parameter::None,
+            },
+        }
+    }
+
     /// decode r32, r/m32
     fn r32_rm32(&mut self, mut mmu: &mut MMU, op: &Instruction) -> ParameterSet {
         let x = self.read_mod_reg_rm(mmu);
@@ -1774,8 +2421,7 @@ impl Decoder {
     }
 
     fn read_mod_reg_rm(&mut self, mmu: &MMU) -> ModRegRm {
-        let b = mmu.read_u8(self.current_seg, self.current_offset);
-        self.current_offset += 1;
+        let b = self.read_u8(mmu);
         let res = ModRegRm {
             md: b >> 6, // high 2 bits
             reg: (b >> 3) & 7, // mid 3 bits
@@ -1787,22 +2433,46 @@ impl Decoder {
         res
     }
 
-    fn read_rel8(&mut self, mmu: &MMU) -> u16 {
+    /// reads a rel8 branch displacement, returns the branch target
+    fn read_rel8(&mut self, mmu: &MMU, op: &Instruction) -> Parameter {
         let val = self.read_s8(mmu);
-        (self.current_offset as isize + val as isize) as u16
+        self.branch_target(op, i32::from(val))
     }
 
-    fn read_rel16(&mut self, mmu: &MMU) -> u16 {
-        let val = self.read_s16(mmu);
-        (self.current_offset as isize + val as isize) as u16
+    /// reads a rel16 or rel32 branch displacement by operand size, returns the branch target
+    fn read_rel(&mut self, mmu: &MMU, op: &Instruction) -> Parameter {
+        let val = match op.op_size {
+            OperandSize::_16bit => i32::from(self.read_s16(mmu)),
+            OperandSize::_32bit => self.read_s32(mmu),
+        };
+        self.branch_target(op, val)
+    }
+
+    /// returns the target of a displacement from the next instruction. targets of 16-bit
+    /// operands are truncated to 16 bits
+    fn branch_target(&self, op: &Instruction, displacement: i32) -> Parameter {
+        let target = self.current_offset.wrapping_add(displacement as u32);
+        match op.op_size {
+            OperandSize::_16bit => Parameter::Imm16(target as u16),
+            OperandSize::_32bit => Parameter::Imm32(target),
+        }
     }
 
     fn read_u8(&mut self, mmu: &MMU) -> u8 {
-        let b = mmu.read_u8(self.current_seg, self.current_offset);
-        self.current_offset = (Wrapping(self.current_offset) + Wrapping(1)).0;
+        let b = mmu.fetch_u8(self.current_seg, self.current_offset);
+        self.current_offset = self.current_offset.wrapping_add(1) & self.offset_mask();
         b
     }
 
+    /// offsets of 16-bit code segments wrap at 64k
+    fn offset_mask(&self) -> u32 {
+        if self.code32 {
+            0xFFFF_FFFF
+        } else {
+            0xFFFF
+        }
+    }
+
     fn read_s8(&mut self, mmu: &MMU) -> i8 {
         self.read_u8(mmu) as i8
     }
@@ -1823,9 +2493,13 @@ impl Decoder {
         self.read_u16(mmu) as i16
     }
 
+    fn read_s32(&mut self, mmu: &MMU) -> i32 {
+        self.read_u32(mmu) as i32
+    }
+
     /// returns the flat starting offset of the instruction being decoded
     fn current_flat(&self) -> u32 {
-        MemoryAddress::RealSegmentOffset(self.current_seg, self.current_offset).value()
+        MemoryAddress::RealSegmentOffset(self.current_seg, self.current_offset as u16).value()
     }
 }
 
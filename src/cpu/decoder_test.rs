@@ -91,3 +91,82 @@ fn can_disassemble_relative_short_jumps() {
 [085F:0106] 74FA             Jz       0x0102",
                res);
 }
+
+#[test]
+fn can_disassemble_fpu() {
+    let mut machine = Machine::default();
+    let code: Vec<u8> = vec![
+        0xD8, 0x47, 0x04,       // fadd dword [bx+0x4]
+        0xDC, 0xE9,             // fsub st1,st0
+        0xDE, 0xF9,             // fdivp st1,st0
+        0xDD, 0x5E, 0xF8,       // fstp qword [bp-0x8]
+        0xDB, 0x2E, 0x00, 0x02, // fld tword [0x200]
+        0xDF, 0xE0,             // fnstsw ax
+        0x9B,                   // wait
+    ];
+    machine.load_executable(&code);
+
+    let res = machine.cpu.decoder.disassemble_block_to_str(&mut machine.hw.mmu, 0x85F, 0x100, 7);
+    assert_eq!("[085F:0100] D84704           Fadd     st0, dword [ds:bx+0x04]
+[085F:0103] DCE9             Fsub     st1, st0
+[085F:0105] DEF9             Fdivp    st1, st0
+[085F:0107] DD5EF8           Fstp     qword [ds:bp-0x08]
+[085F:010A] DB2E0002         Fld      tword [ds:0x0200]
+[085F:010E] DFE0             Fnstsw   ax
+[085F:0110] 9B               Fwait",
+               res);
+}
+
+#[test]
+fn can_disassemble_32bit_addressing() {
+    let mut machine = Machine::default();
+    let code: Vec<u8> = vec![
+        0x67, 0x66, 0x8B, 0x44, 0x8B, 0x10,                   // mov eax,[ebx+ecx*4+0x10]
+        0x67, 0x8B, 0x1C, 0x24,                               // mov bx,[esp]
+        0x67, 0x88, 0x84, 0x36, 0x00, 0x00, 0x01, 0x00,       // mov [esi+esi+0x10000],al
+        0x67, 0x8B, 0x14, 0xBD, 0xF0, 0xFF, 0xFF, 0xFF,       // mov dx,[edi*4-0x10]
+        0x67, 0xA1, 0x00, 0x02, 0x00, 0x00,                   // mov ax,[dword 0x200]
+        0x67, 0x8B, 0x0D, 0x34, 0x12, 0x00, 0x00,             // mov cx,[dword 0x1234]
+        0x67, 0x8B, 0x45, 0xFC,                               // mov ax,[ebp-0x4]
+    ];
+    machine.load_executable(&code);
+
+    let res = machine.cpu.decoder.disassemble_block_to_str(&mut machine.hw.mmu, 0x85F, 0x100, 7);
+    assert_eq!("[085F:0100] 67668B448B10     Mov32    eax, dword [ds:ebx+ecx*4+0x10]
+[085F:0106] 678B1C24         Mov16    bx, word [ds:esp]
+[085F:010A] 6788843600000100 Mov8     byte [ds:esi+esi+0x00010000], al
+[085F:0112] 678B14BDF0FFFFFF Mov16    dx, word [ds:edi*4-0x00000010]
+[085F:011A] 67A100020000     Mov16    ax, word [ds:0x00000200]
+[085F:0120] 678B0D34120000   Mov16    cx, word [ds:0x00001234]
+[085F:0127] 678B45FC         Mov16    ax, word [ds:ebp-0x04]",
+               res);
+}
+
+#[test]
+fn can_disassemble_system_instructions() {
+    let mut machine = Machine::default();
+    let code: Vec<u8> = vec![
+        0x0F, 0x01, 0x16, 0x00, 0x0A, // lgdt [0xa00]
+        0x0F, 0x01, 0x0F,             // sidt [bx]
+        0x0F, 0x01, 0xF0,             // lmsw ax
+        0x0F, 0x20, 0xC0,             // mov eax,cr0
+        0x0F, 0x22, 0xDB,             // mov cr3,ebx
+        0x0F, 0x00, 0xD0,             // lldt ax
+        0x0F, 0x00, 0x5F, 0x02,       // ltr [bx+0x2]
+        0x0F, 0x00, 0xEA,             // verw dx
+        0x0F, 0x06,                   // clts
+    ];
+    machine.load_executable(&code);
+
+    let res = machine.cpu.decoder.disassemble_block_to_str(&mut machine.hw.mmu, 0x85F, 0x100, 9);
+    assert_eq!("[085F:0100] 0F0116000A       Lgdt     word [ds:0x0A00]
+[085F:0105] 0F010F           Sidt     word [ds:bx]
+[085F:0108] 0F01F0           Lmsw     ax
+[085F:010B] 0F20C0           Mov32    eax, cr0
+[085F:010E] 0F22DB           Mov32    cr3, ebx
+[085F:0111] 0F00D0           Lldt     ax
+[085F:0114] 0F005F02         Ltr      word [ds:bx+0x02]
+[085F:0118] 0F00EA           Verw     dx
+[085F:011B] 0F06             Clts",
+               res);
+}
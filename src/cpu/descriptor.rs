@@ -0,0 +1,319 @@
+// Protected mode segment descriptors, gates and descriptor tables
+//
+// Layouts are from the Intel 80286 and 80386 programmer's reference manuals.
+
+#[cfg(test)]
+#[path = "./descriptor_test.rs"]
+mod descriptor_test;
+
+/// CR0 protection enable
+pub const CR0_PE: u32 = 1 << 0;
+
+/// CR0 task switched
+pub const CR0_TS: u32 = 1 << 3;
+
+/// CR0 paging enable
+pub const CR0_PG: u32 = 1 << 31;
+
+// access byte
+const ACCESS_PRESENT: u8 = 0x80;
+const ACCESS_SEGMENT: u8 = 0x10;  // code or data segment, clear for system descriptors
+const ACCESS_CODE: u8 = 0x08;
+const ACCESS_CONFORMING: u8 = 0x04; // code segments
+const ACCESS_EXPAND_DOWN: u8 = 0x04; // data segments
+const ACCESS_READABLE: u8 = 0x02;   // code segments
+const ACCESS_WRITABLE: u8 = 0x02;   // data segments
+
+// flags nibble
+const FLAG_GRANULARITY: u8 = 0x8; // limit is in 4k pages
+pub const FLAG_DEFAULT_32: u8 = 0x4;  // 32-bit code or stack segment
+
+// system descriptor types
+pub const SYSTEM_TSS_286: u8 = 0x1;
+pub const SYSTEM_LDT: u8 = 0x2;
+pub const SYSTEM_TSS_286_BUSY: u8 = 0x3;
+pub const SYSTEM_CALL_GATE_286: u8 = 0x4;
+pub const SYSTEM_TASK_GATE: u8 = 0x5;
+pub const SYSTEM_INT_GATE_286: u8 = 0x6;
+pub const SYSTEM_TRAP_GATE_286: u8 = 0x7;
+pub const SYSTEM_TSS_386: u8 = 0x9;
+pub const SYSTEM_TSS_386_BUSY: u8 = 0xB;
+pub const SYSTEM_CALL_GATE_386: u8 = 0xC;
+pub const SYSTEM_INT_GATE_386: u8 = 0xE;
+pub const SYSTEM_TRAP_GATE_386: u8 = 0xF;
+
+/// a segment or system descriptor, as held in the hidden part of a segment register
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Descriptor {
+    pub base: u32,
+
+    /// segment limit in bytes, with the granularity applied
+    pub limit: u32,
+
+    /// present, dpl, descriptor type and segment type
+    pub access: u8,
+
+    /// granularity and default operand size
+    pub flags: u8,
+}
+
+impl Descriptor {
+    /// decodes a 8 byte descriptor table entry
+    pub fn from_u64(raw: u64) -> Self {
+        let flags = ((raw >> 52) & 0xF) as u8;
+        let mut limit = (raw & 0xFFFF) as u32 | ((raw >> 32) & 0xF_0000) as u32;
+        if flags & FLAG_GRANULARITY != 0 {
+            limit = (limit << 12) | 0xFFF;
+        }
+        Descriptor {
+            base: ((raw >> 16) & 0xFF_FFFF) as u32 | ((raw >> 32) & 0xFF00_0000) as u32,
+            limit,
+            access: (raw >> 40) as u8,
+            flags,
+        }
+    }
+
+    /// encodes the descriptor as a 8 byte descriptor table entry. limits above 1 MB are stored in 4k pages
+    pub fn to_u64(&self) -> u64 {
+        let (limit, flags) = if self.limit > 0xF_FFFF {
+            (self.limit >> 12, self.flags | FLAG_GRANULARITY)
+        } else {
+            (self.limit, self.flags & !FLAG_GRANULARITY)
+        };
+        u64::from(limit & 0xFFFF) |
+        u64::from(self.base & 0xFF_FFFF) << 16 |
+        u64::from(self.access) << 40 |
+        u64::from((limit >> 16) & 0xF) << 48 |
+        u64::from(flags & 0xF) << 52 |
+        u64::from(self.base >> 24) << 56
+    }
+
+    /// the descriptor cache of a segment register loaded in real mode
+    pub fn real_mode(segment: u16) -> Self {
+        Descriptor {
+            base: u32::from(segment) << 4,
+            limit: 0xFFFF,
+            access: ACCESS_PRESENT | ACCESS_SEGMENT | ACCESS_WRITABLE,
+            flags: 0,
+        }
+    }
+
+    pub fn present(&self) -> bool {
+        self.access & ACCESS_PRESENT != 0
+    }
+
+    /// descriptor privilege level
+    pub fn dpl(&self) -> u8 {
+        (self.access >> 5) & 3
+    }
+
+    /// true for code and data segments, false for system descriptors and gates
+    pub fn is_segment(&self) -> bool {
+        self.access & ACCESS_SEGMENT != 0
+    }
+
+    pub fn is_code(&self) -> bool {
+        self.is_segment() && self.access & ACCESS_CODE != 0
+    }
+
+    pub fn is_data(&self) -> bool {
+        self.is_segment() && self.access & ACCESS_CODE == 0
+    }
+
+    pub fn is_conforming(&self) -> bool {
+        self.is_code() && self.access & ACCESS_CONFORMING != 0
+    }
+
+    /// data segments are always readable, code segments only with the readable bit set
+    pub fn is_readable(&self) -> bool {
+        self.is_data() || (self.is_code() && self.access & ACCESS_READABLE != 0)
+    }
+
+    pub fn is_writable(&self) -> bool {
+        self.is_data() && self.access & ACCESS_WRITABLE != 0
+    }
+
+    /// 32-bit code or stack segment
+    pub fn is_32bit(&self) -> bool {
+        self.flags & FLAG_DEFAULT_32 != 0
+    }
+
+    /// the type of a system descriptor
+    pub fn system_type(&self) -> u8 {
+        self.access & 0xF
+    }
+
+    pub fn is_tss(&self) -> bool {
+        !self.is_segment() && match self.system_type() {
+            SYSTEM_TSS_286 | SYSTEM_TSS_286_BUSY | SYSTEM_TSS_386 | SYSTEM_TSS_386_BUSY => true,
+            _ => false,
+        }
+    }
+
+    /// expand-down data segments hold the offsets above the limit, as used for stacks
+    pub fn is_expand_down(&self) -> bool {
+        self.is_data() && self.access & ACCESS_EXPAND_DOWN != 0
+    }
+
+    /// returns true if offset..offset+size is inside the segment limit
+    pub fn contains(&self, offset: u32, size: u32) -> bool {
+        let end = match offset.checked_add(size - 1) {
+            Some(end) => end,
+            None => return false,
+        };
+        if self.is_expand_down() {
+            let upper = if self.is_32bit() { 0xFFFF_FFFF } else { 0xFFFF };
+            offset > self.limit && end <= upper
+        } else {
+            end <= self.limit
+        }
+    }
+}
+
+/// a call, interrupt, trap or task gate
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Gate {
+    /// code segment selector of the entry point, or the TSS selector of a task gate
+    pub selector: u16,
+    pub offset: u32,
+
+    /// present, dpl and gate type
+    pub access: u8,
+
+    /// number of stack words copied by a call gate
+    pub param_count: u8,
+}
+
+impl Gate {
+    /// decodes a 8 byte descriptor table entry
+    pub fn from_u64(raw: u64) -> Self {
+        Gate {
+            selector: (raw >> 16) as u16,
+            offset: (raw & 0xFFFF) as u32 | ((raw >> 32) & 0xFFFF_0000) as u32,
+            access: (raw >> 40) as u8,
+            param_count: ((raw >> 32) & 0x1F) as u8,
+        }
+    }
+
+    /// encodes the gate as a 8 byte descriptor table entry
+    pub fn to_u64(&self) -> u64 {
+        u64::from(self.offset & 0xFFFF) |
+        u64::from(self.selector) << 16 |
+        u64::from(self.param_count & 0x1F) << 32 |
+        u64::from(self.access) << 40 |
+        u64::from(self.offset >> 16) << 48
+    }
+
+    pub fn present(&self) -> bool {
+        self.access & ACCESS_PRESENT != 0
+    }
+
+    /// descriptor privilege level
+    pub fn dpl(&self) -> u8 {
+        (self.access >> 5) & 3
+    }
+
+    /// the system descriptor type, or None for code and data segments
+    pub fn gate_type(&self) -> Option<u8> {
+        if self.access & ACCESS_SEGMENT != 0 {
+            None
+        } else {
+            Some(self.access & 0xF)
+        }
+    }
+
+    pub fn is_call_gate(&self) -> bool {
+        match self.gate_type() {
+            Some(SYSTEM_CALL_GATE_286) | Some(SYSTEM_CALL_GATE_386) => true,
+            _ => false,
+        }
+    }
+
+    /// interrupt and trap gates, which are valid in the IDT together with task gates
+    pub fn is_interrupt_gate(&self) -> bool {
+        match self.gate_type() {
+            Some(SYSTEM_INT_GATE_286) | Some(SYSTEM_TRAP_GATE_286) |
+            Some(SYSTEM_INT_GATE_386) | Some(SYSTEM_TRAP_GATE_386) => true,
+            _ => false,
+        }
+    }
+
+    /// 386 gates have a 32-bit offset and push 32-bit stack frames
+    pub fn is_32bit(&self) -> bool {
+        match self.gate_type() {
+            Some(SYSTEM_CALL_GATE_386) | Some(SYSTEM_INT_GATE_386) | Some(SYSTEM_TRAP_GATE_386) => true,
+            _ => false,
+        }
+    }
+
+    /// returns the offset of the entry point, the upper word of 286 gates is reserved
+    pub fn entry_offset(&self) -> u32 {
+        if self.is_32bit() {
+            self.offset
+        } else {
+            self.offset & 0xFFFF
+        }
+    }
+
+    /// interrupt gates clear IF, trap gates leave it unchanged
+    pub fn is_trap_gate(&self) -> bool {
+        match self.gate_type() {
+            Some(SYSTEM_TRAP_GATE_286) | Some(SYSTEM_TRAP_GATE_386) => true,
+            _ => false,
+        }
+    }
+}
+
+/// location of the GDT, IDT or the current LDT in linear memory
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DescriptorTable {
+    pub base: u32,
+    pub limit: u32,
+}
+
+impl DescriptorTable {
+    pub fn default() -> Self {
+        DescriptorTable {
+            base: 0,
+            limit: 0,
+        }
+    }
+
+    /// the real mode interrupt vector table
+    pub fn ivt() -> Self {
+        DescriptorTable {
+            base: 0,
+            limit: 0x3FF,
+        }
+    }
+
+    /// linear address of the 8 byte entry at offset, or None if it is outside the table limit
+    pub fn entry(&self, offset: u16) -> Option<u32> {
+        let offset = u32::from(offset);
+        if offset + 7 > self.limit {
+            None
+        } else {
+            Some(self.base.wrapping_add(offset))
+        }
+    }
+}
+
+/// requested privilege level of a selector
+pub fn selector_rpl(selector: u16) -> u8 {
+    (selector & 3) as u8
+}
+
+/// returns true if the selector refers to the LDT
+pub fn selector_is_ldt(selector: u16) -> bool {
+    selector & 4 != 0
+}
+
+/// byte offset of the selector in its descriptor table
+pub fn selector_offset(selector: u16) -> u16 {
+    selector & !7
+}
+
+/// null selectors (index 0 in the GDT) can be loaded in data segment registers, but not be used
+pub fn selector_is_null(selector: u16) -> bool {
+    selector & !3 == 0
+}
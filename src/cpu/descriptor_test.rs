@@ -0,0 +1,83 @@
+use cpu::descriptor::{Descriptor, DescriptorTable, Gate, SYSTEM_INT_GATE_386, selector_rpl, selector_is_ldt, selector_offset, selector_is_null};
+
+#[test]
+fn can_decode_segment_descriptors() {
+    // flat 4 GB code segment, dpl 0, readable, 32-bit
+    let code = Descriptor::from_u64(0x00CF_9A00_0000_FFFF);
+    assert_eq!(0, code.base);
+    assert_eq!(0xFFFF_FFFF, code.limit);
+    assert_eq!(true, code.present());
+    assert_eq!(0, code.dpl());
+    assert_eq!(true, code.is_code());
+    assert_eq!(true, code.is_readable());
+    assert_eq!(false, code.is_writable());
+    assert_eq!(false, code.is_conforming());
+    assert_eq!(true, code.is_32bit());
+
+    // 64k data segment at 0x123450, dpl 3, writable, not present
+    let data = Descriptor::from_u64(0x0000_7212_3450_FFFF);
+    assert_eq!(0x0012_3450, data.base);
+    assert_eq!(0xFFFF, data.limit);
+    assert_eq!(false, data.present());
+    assert_eq!(3, data.dpl());
+    assert_eq!(true, data.is_data());
+    assert_eq!(true, data.is_writable());
+    assert_eq!(true, data.contains(0xFFFE, 2));
+    assert_eq!(false, data.contains(0xFFFF, 2));
+    assert_eq!(false, data.is_expand_down());
+
+    // expand-down 16-bit stack segment, offsets 0x1000-0xFFFF are valid
+    let stack = Descriptor::from_u64(0x0000_9600_0000_0FFF);
+    assert_eq!(true, stack.is_expand_down());
+    assert_eq!(false, stack.contains(0x0FFF, 1));
+    assert_eq!(true, stack.contains(0x1000, 2));
+    assert_eq!(true, stack.contains(0xFFFE, 2));
+    assert_eq!(false, stack.contains(0xFFFF, 2));
+
+    // 286 TSS
+    let tss = Descriptor::from_u64(0x0000_8100_1000_002B);
+    assert_eq!(false, tss.is_segment());
+    assert_eq!(true, tss.is_tss());
+    assert_eq!(false, tss.is_readable());
+
+    assert_eq!(0x00CF_9A00_0000_FFFF, code.to_u64());
+    assert_eq!(0x0000_7212_3450_FFFF, data.to_u64());
+}
+
+#[test]
+fn can_decode_gates() {
+    let gate = Gate::from_u64(0x1234_EE00_0008_5678);
+    assert_eq!(0x0008, gate.selector);
+    assert_eq!(0x1234_5678, gate.offset);
+    assert_eq!(Some(SYSTEM_INT_GATE_386), gate.gate_type());
+    assert_eq!(3, gate.dpl());
+    assert_eq!(true, gate.is_interrupt_gate());
+    assert_eq!(false, gate.is_trap_gate());
+    assert_eq!(false, gate.is_call_gate());
+    assert_eq!(true, gate.is_32bit());
+    assert_eq!(0x1234_5678, gate.entry_offset());
+
+    assert_eq!(0x1234_EE00_0008_5678, gate.to_u64());
+
+    // the upper offset word of 286 gates is reserved
+    let gate = Gate::from_u64(0x1234_8600_0008_5678);
+    assert_eq!(false, gate.is_32bit());
+    assert_eq!(0x5678, gate.entry_offset());
+
+    // a code segment descriptor is not a gate
+    assert_eq!(None, Gate::from_u64(0x00CF_9A00_0000_FFFF).gate_type());
+}
+
+#[test]
+fn can_index_descriptor_tables() {
+    let gdt = DescriptorTable { base: 0x1000, limit: 0x17 };
+    assert_eq!(Some(0x1010), gdt.entry(0x10));
+    assert_eq!(None, gdt.entry(0x18));
+
+    assert_eq!(3, selector_rpl(0x001B));
+    assert_eq!(0x0018, selector_offset(0x001B));
+    assert_eq!(false, selector_is_ldt(0x001B));
+    assert_eq!(true, selector_is_ldt(0x0004));
+    assert_eq!(true, selector_is_null(0x0003));
+    assert_eq!(false, selector_is_null(0x0004));
+}
@@ -3,8 +3,9 @@ use simple_error::SimpleError;
 use cpu::instruction::{Instruction, ModRegRm};
 use cpu::parameter::{Parameter, ParameterSet};
 use cpu::segment::Segment;
-use cpu::register::R;
+use cpu::register::{R, AMode};
 use cpu::op::{Op};
+use cpu::{OperandSize, AddressSize};
 
 #[cfg(test)]
 #[path = "./encoder_test.rs"]
@@ -66,6 +67,9 @@ impl Encoder {
             Segment::FS => out.push(0x64),
             Segment::GS => out.push(0x65),
         }
+        if op.address_size == AddressSize::_32bit {
+            out.push(0x67);
+        }
 
         match op.command {
             Op::Daa => out.push(0x27),
@@ -184,6 +188,107 @@ impl Encoder {
                 out.push(0xAC);
                 out.extend(self.encode_rm_r_imm(&op.params));
             }
+            Op::Seto | Op::Setno | Op::Setc | Op::Setnc | Op::Setz | Op::Setnz | Op::Setna | Op::Seta |
+            Op::Sets | Op::Setns | Op::Setpe | Op::Setpo | Op::Setl | Op::Setnl | Op::Setng | Op::Setg => {
+                // 0x0F 0x90...0x9F: setcc r/m8
+                out.push(0x0F);
+                out.push(0x90 | self.setcc_index(&op.command));
+                out.extend(self.encode_rm(&op.params.dst, 0));
+            }
+            Op::Bt | Op::Bts | Op::Btr | Op::Btc => {
+                let idx = self.bt_index(&op.command);
+                if op.op_size == OperandSize::_32bit {
+                    out.push(0x66);
+                }
+                out.push(0x0F);
+                if let Parameter::Imm8(imm) = op.params.src {
+                    // 0x0F 0xBA: bt/bts/btr/btc r/m, imm8
+                    out.push(0xBA);
+                    out.extend(self.encode_rm(&op.params.dst, idx));
+                    out.push(imm);
+                } else {
+                    // 0x0F 0xA3: bt r/m, r
+                    // 0x0F 0xAB: bts r/m, r
+                    // 0x0F 0xB3: btr r/m, r
+                    // 0x0F 0xBB: btc r/m, r
+                    out.push(0x83 | (idx << 3));
+                    out.extend(self.encode_rm_r(&op.params));
+                }
+            }
+            Op::Bsf | Op::Bsr => {
+                // 0x0F 0xBC: bsf r, r/m
+                // 0x0F 0xBD: bsr r, r/m
+                if op.op_size == OperandSize::_32bit {
+                    out.push(0x66);
+                }
+                out.push(0x0F);
+                out.push(if op.command == Op::Bsf { 0xBC } else { 0xBD });
+                out.extend(self.encode_r_rm(&op.params));
+            }
+            Op::Lss | Op::Lfs | Op::Lgs => {
+                // 0x0F 0xB2: lss r16, m16:16
+                // 0x0F 0xB4: lfs r16, m16:16
+                // 0x0F 0xB5: lgs r16, m16:16
+                if op.op_size == OperandSize::_32bit {
+                    out.push(0x66);
+                }
+                out.push(0x0F);
+                out.push(match op.command {
+                    Op::Lss => 0xB2,
+                    Op::Lfs => 0xB4,
+                    _ => 0xB5,
+                });
+                out.extend(self.encode_r_rm(&op.params));
+            }
+            Op::Cmpxchg8 | Op::Cmpxchg16 | Op::Cmpxchg32 => {
+                // 0x0F 0xB0: cmpxchg r/m8, r8
+                // 0x0F 0xB1: cmpxchg r/m16, r16
+                if op.op_size == OperandSize::_32bit {
+                    out.push(0x66);
+                }
+                out.push(0x0F);
+                out.push(if op.command == Op::Cmpxchg8 { 0xB0 } else { 0xB1 });
+                out.extend(self.encode_rm_r(&op.params));
+            }
+            Op::Xadd8 | Op::Xadd16 | Op::Xadd32 => {
+                // 0x0F 0xC0: xadd r/m8, r8
+                // 0x0F 0xC1: xadd r/m16, r16
+                if op.op_size == OperandSize::_32bit {
+                    out.push(0x66);
+                }
+                out.push(0x0F);
+                out.push(if op.command == Op::Xadd8 { 0xC0 } else { 0xC1 });
+                out.extend(self.encode_rm_r(&op.params));
+            }
+            Op::Bswap => {
+                if let Parameter::Reg32(ref r) = op.params.dst {
+                    // 0x0F 0xC8...0xCF: bswap r32
+                    out.push(0x0F);
+                    out.push(0xC8 | r.index() as u8);
+                } else {
+                    return Err(EncodeError::UnhandledParameter(op.params.dst.clone()));
+                }
+            }
+            Op::Cpuid => {
+                out.push(0x0F);
+                out.push(0xA2);
+            }
+            Op::Sldt | Op::Str | Op::Lldt | Op::Ltr | Op::Verr | Op::Verw => {
+                // 0x0F 0x00: sldt, str, lldt, ltr, verr, verw r/m16
+                out.push(0x0F);
+                out.push(0x00);
+                out.extend(self.encode_rm(&op.params.dst, self.system_index(&op.command)));
+            }
+            Op::Sgdt | Op::Sidt | Op::Lgdt | Op::Lidt | Op::Smsw | Op::Lmsw => {
+                // 0x0F 0x01: sgdt, sidt, lgdt, lidt m, smsw, lmsw r/m16
+                out.push(0x0F);
+                out.push(0x01);
+                out.extend(self.encode_rm(&op.params.dst, self.system_index(&op.command)));
+            }
+            Op::Clts => {
+                out.push(0x0F);
+                out.push(0x06);
+            }
             Op::Mov8 => {
                 match op.params.dst {
                     Parameter::Reg8(r) => {
@@ -255,21 +360,36 @@ impl Encoder {
                     } else {
                         return Err(EncodeError::UnhandledParameter(op.params.dst.clone()));
                     }
-                    /*
-                } else if op.params.src.is_ptr() {
-                    // 0x8A: mov r8, r/m8
-                    out.push(0x8A);
-                    out.extend(self.encode_r8_rm8(&op.params));
-                } else {
-                    // 0x88: mov r/m8, r8
-                    out.push(0x88);
-                    out.extend(self.encode_rm8_r8(&op.params));
-                }*/
+                } else if let Parameter::Reg16(_) = op.params.src {
+                    // 0x89: mov r/m16, r16
+                    out.push(0x89);
+                    out.extend(self.encode_rm_r(&op.params));
+                } else if let Parameter::Reg16(_) = op.params.dst {
+                    // 0x8B: mov r16, r/m16
+                    out.push(0x8B);
+                    out.extend(self.encode_r_rm(&op.params));
                 } else {
                     return Err(EncodeError::UnhandledParameter(op.params.dst.clone()));
                 }
             }
             Op::Mov32 => {
+                match (&op.params.dst, &op.params.src) {
+                    (&Parameter::Reg32(ref r), &Parameter::CR(n)) => {
+                        // 0x0F 0x20: mov r32, CRn
+                        out.push(0x0F);
+                        out.push(0x20);
+                        out.push(ModRegRm{md: 3, rm: r.index() as u8, reg: n}.u8());
+                        return Ok(out);
+                    }
+                    (&Parameter::CR(n), &Parameter::Reg32(ref r)) => {
+                        // 0x0F 0x22: mov CRn, r32
+                        out.push(0x0F);
+                        out.push(0x22);
+                        out.push(ModRegRm{md: 3, rm: r.index() as u8, reg: n}.u8());
+                        return Ok(out);
+                    }
+                    _ => {}
+                }
                 // XXX TODO handle more forms
                 out.push(0x66);
                 if op.params.src.is_imm() {
@@ -287,6 +407,14 @@ impl Encoder {
                     } else {
                         return Err(EncodeError::UnhandledParameter(op.params.dst.clone()));
                     }
+                } else if let Parameter::Reg32(_) = op.params.src {
+                    // 0x66 0x89: mov r/m32, r32
+                    out.push(0x89);
+                    out.extend(self.encode_rm_r(&op.params));
+                } else if let Parameter::Reg32(_) = op.params.dst {
+                    // 0x66 0x8B: mov r32, r/m32
+                    out.push(0x8B);
+                    out.extend(self.encode_r_rm(&op.params));
                 } else {
                     return Err(EncodeError::UnhandledParameter(op.params.dst.clone()));
                 }
@@ -494,6 +622,52 @@ impl Encoder {
         }
     }
 
+    fn setcc_index(&self, op: &Op) -> u8 {
+        match *op {
+            Op::Seto => 0,
+            Op::Setno => 1,
+            Op::Setc => 2,
+            Op::Setnc => 3,
+            Op::Setz => 4,
+            Op::Setnz => 5,
+            Op::Setna => 6,
+            Op::Seta => 7,
+            Op::Sets => 8,
+            Op::Setns => 9,
+            Op::Setpe => 10,
+            Op::Setpo => 11,
+            Op::Setl => 12,
+            Op::Setnl => 13,
+            Op::Setng => 14,
+            Op::Setg => 15,
+            _ => panic!("setcc_index {:?}", op),
+        }
+    }
+
+    fn bt_index(&self, op: &Op) -> u8 {
+        match *op {
+            Op::Bt => 4,
+            Op::Bts => 5,
+            Op::Btr => 6,
+            Op::Btc => 7,
+            _ => panic!("bt_index {:?}", op),
+        }
+    }
+
+    /// reg field of the 0x0F 0x00 and 0x0F 0x01 groups
+    fn system_index(&self, op: &Op) -> u8 {
+        match *op {
+            Op::Sldt | Op::Sgdt => 0,
+            Op::Str | Op::Sidt => 1,
+            Op::Lldt | Op::Lgdt => 2,
+            Op::Ltr | Op::Lidt => 3,
+            Op::Verr | Op::Smsw => 4,
+            Op::Verw => 5,
+            Op::Lmsw => 6,
+            _ => panic!("system_index {:?}", op),
+        }
+    }
+
     fn arith_index(&self, op: &Op) -> u8 {
         match *op {
             Op::Add8 => 0,
@@ -524,7 +698,8 @@ impl Encoder {
     fn encode_r_rm(&self, params: &ParameterSet) -> Vec<u8> {
         match params.dst {
             Parameter::Reg8(ref r) |
-            Parameter::Reg16(ref r) => self.encode_rm(&params.src, r.index() as u8),
+            Parameter::Reg16(ref r) |
+            Parameter::Reg32(ref r) => self.encode_rm(&params.src, r.index() as u8),
             _ => unreachable!(),
         }
     }
@@ -532,7 +707,8 @@ impl Encoder {
     fn encode_rm_r(&self, params: &ParameterSet) -> Vec<u8> {
         match params.src {
             Parameter::Reg8(ref r) |
-            Parameter::Reg16(ref r) => self.encode_rm(&params.dst, r.index() as u8),
+            Parameter::Reg16(ref r) |
+            Parameter::Reg32(ref r) => self.encode_rm(&params.dst, r.index() as u8),
             _ => panic!("unexpected parameter type: {:?}", params.src),
         }
     }
@@ -556,26 +732,54 @@ impl Encoder {
     fn encode_rm(&self, dst: &Parameter, reg: u8) -> Vec<u8> {
         let mut out = Vec::new();
         match *dst {
-            Parameter::Ptr8(_, imm16) => {
+            Parameter::Ptr8(_, imm16) |
+            Parameter::Ptr16(_, imm16) |
+            Parameter::Ptr32(_, imm16) => {
                 out.push(ModRegRm{md: 0, rm: 6, reg}.u8());
                 out.push(imm16 as u8);
                 out.push((imm16 >> 8) as u8);
             }
             Parameter::Ptr8Amode(_, ref amode) |
-            Parameter::Ptr16Amode(_, ref amode) => {
+            Parameter::Ptr16Amode(_, ref amode) |
+            Parameter::Ptr32Amode(_, ref amode) => {
                 // XXX how does md:0, rm: 0 not collide with above one...
                 out.push(ModRegRm{md: 0, rm: amode.index() as u8, reg}.u8());
+                out.extend(self.encode_sib(amode));
             }
             Parameter::Ptr8AmodeS8(_, ref amode, imm) |
-            Parameter::Ptr16AmodeS8(_, ref amode, imm) => {
+            Parameter::Ptr16AmodeS8(_, ref amode, imm) |
+            Parameter::Ptr32AmodeS8(_, ref amode, imm) => {
                 out.push(ModRegRm{md: 1, rm: amode.index() as u8, reg}.u8());
+                out.extend(self.encode_sib(amode));
                 out.push(imm as u8);
             },
-            Parameter::Ptr8AmodeS16(_, ref amode, imm16) => {
+            Parameter::Ptr8AmodeS16(_, ref amode, imm16) |
+            Parameter::Ptr16AmodeS16(_, ref amode, imm16) |
+            Parameter::Ptr32AmodeS16(_, ref amode, imm16) => {
                 out.push(ModRegRm{md: 2, rm: amode.index() as u8, reg}.u8());
                 out.push(imm16 as u8);
                 out.push((imm16 >> 8) as u8);
             }
+            Parameter::Ptr8AmodeS32(_, ref amode, imm32) |
+            Parameter::Ptr16AmodeS32(_, ref amode, imm32) |
+            Parameter::Ptr32AmodeS32(_, ref amode, imm32) => {
+                match *amode {
+                    AMode::SIB(None, None, _) => {
+                        // [u32]
+                        out.push(ModRegRm{md: 0, rm: 5, reg}.u8());
+                    }
+                    AMode::SIB(None, _, _) => {
+                        // [index*scale+s32]
+                        out.push(ModRegRm{md: 0, rm: 4, reg}.u8());
+                    }
+                    _ => out.push(ModRegRm{md: 2, rm: amode.index() as u8, reg}.u8()),
+                }
+                out.extend(self.encode_sib(amode));
+                out.push(imm32 as u8);
+                out.push((imm32 >> 8) as u8);
+                out.push((imm32 >> 16) as u8);
+                out.push((imm32 >> 24) as u8);
+            }
             Parameter::Reg8(ref r) |
             Parameter::Reg16(ref r) |
             Parameter::Reg32(ref r) => {
@@ -586,6 +790,34 @@ impl Encoder {
         out
     }
 
+    /// encodes the scale-index-base byte of a SIB addressing mode
+    fn encode_sib(&self, amode: &AMode) -> Vec<u8> {
+        let mut out = Vec::new();
+        match *amode {
+            AMode::SIB(None, None, _) => {}
+            AMode::SIB(base, index, scale) => {
+                let scale = match scale {
+                    1 => 0,
+                    2 => 1,
+                    4 => 2,
+                    8 => 3,
+                    _ => panic!("invalid sib scale {}", scale),
+                };
+                let index = match index {
+                    Some(r) => r.index() as u8,
+                    None => 4,
+                };
+                let base = match base {
+                    Some(r) => r.index() as u8,
+                    None => 5,
+                };
+                out.push(scale << 6 | index << 3 | base);
+            }
+            _ => {}
+        }
+        out
+    }
+
     fn encode_imm8(&self, param: &Parameter) -> Vec<u8> {
         let mut out = Vec::new();
         if let Parameter::Imm8(imm) = *param {
@@ -13,7 +13,9 @@ use rand::prelude::*;
 use rand::prng::{XorShiftRng};
 
 use cpu::CPU;
+use cpu::model::CpuModel;
 use cpu::encoder::{Encoder};
+use cpu::decoder::OperandSize;
 use cpu::segment::Segment;
 use cpu::parameter::Parameter;
 use cpu::instruction::{Instruction, InstructionInfo, RepeatMode};
@@ -30,6 +32,7 @@ fn can_encode_random_seq() {
     let mut code = vec![0u8; 10];
 
     let mut machine = Machine::default();
+    machine.set_cpu_model(CpuModel::I80486);
 
     for _ in 0..1000 {
         for mut b in &mut code {
@@ -488,6 +491,112 @@ fn can_encode_mov32() {
     assert_encdec(&op, "mov ebx,0x11228844", vec!(0x66, 0xBB, 0x44, 0x88, 0x22, 0x11));
 }
 
+#[test]
+fn can_encode_setcc() {
+    // r/m8
+    let op = Instruction::new1(Op::Setc, Parameter::Reg8(R::AL));
+    assert_encdec(&op, "setc al", vec!(0x0F, 0x92, 0xC0));
+
+    let op = Instruction::new1(Op::Setg, Parameter::Ptr8(Segment::Default, 0x0200));
+    assert_encdec(&op, "setg [0x200]", vec!(0x0F, 0x9F, 0x06, 0x00, 0x02));
+}
+
+#[test]
+fn can_encode_bit_instructions() {
+    // r/m16, r16
+    let op = Instruction::new2(Op::Bt, Parameter::Reg16(R::BX), Parameter::Reg16(R::AX));
+    assert_encdec(&op, "bt bx,ax", vec!(0x0F, 0xA3, 0xC3));
+
+    let op = Instruction::new2(Op::Btc, Parameter::Ptr16Amode(Segment::Default, AMode::SI), Parameter::Reg16(R::DX));
+    assert_encdec(&op, "btc [si],dx", vec!(0x0F, 0xBB, 0x14));
+
+    // r16, r/m16
+    let op = Instruction::new2(Op::Bsr, Parameter::Reg16(R::AX), Parameter::Reg16(R::BX));
+    assert_encdec(&op, "bsr ax,bx", vec!(0x0F, 0xBD, 0xC3));
+}
+
+#[test]
+fn can_encode_xadd_cmpxchg_bswap() {
+    let op = Instruction::new2(Op::Xadd16, Parameter::Reg16(R::BX), Parameter::Reg16(R::AX));
+    assert_encdec(&op, "xadd bx,ax", vec!(0x0F, 0xC1, 0xC3));
+
+    let op = Instruction::new2(Op::Xadd32, Parameter::Reg32(R::EBX), Parameter::Reg32(R::EAX));
+    assert_encdec(&op, "xadd ebx,eax", vec!(0x66, 0x0F, 0xC1, 0xC3));
+
+    let op = Instruction::new2(Op::Cmpxchg8, Parameter::Reg8(R::BL), Parameter::Reg8(R::CL));
+    assert_encdec(&op, "cmpxchg bl,cl", vec!(0x0F, 0xB0, 0xCB));
+
+    let op = Instruction::new1(Op::Bswap, Parameter::Reg32(R::ECX));
+    assert_encdec(&op, "bswap ecx", vec!(0x0F, 0xC9));
+
+    let op = Instruction::new(Op::Cpuid);
+    assert_encdec(&op, "cpuid", vec!(0x0F, 0xA2));
+}
+
+#[test]
+fn can_encode_far_pointer_loads() {
+    // r16, m16:16
+    let op = Instruction::new2(Op::Lss, Parameter::Reg16(R::SP), Parameter::Ptr16(Segment::Default, 0x0200));
+    assert_encdec(&op, "lss sp,[0x200]", vec!(0x0F, 0xB2, 0x26, 0x00, 0x02));
+
+    let op = Instruction::new2(Op::Lfs, Parameter::Reg16(R::AX), Parameter::Ptr16Amode(Segment::Default, AMode::BX));
+    assert_encdec(&op, "lfs ax,[bx]", vec!(0x0F, 0xB4, 0x07));
+
+    let op = Instruction::new2(Op::Lgs, Parameter::Reg16(R::DI), Parameter::Ptr16AmodeS8(Segment::Default, AMode::BP, 0x04));
+    assert_encdec(&op, "lgs di,[bp+0x4]", vec!(0x0F, 0xB5, 0x7E, 0x04));
+
+    // r32, m16:32
+    let mut op = Instruction::new2(Op::Lss, Parameter::Reg32(R::ESP), Parameter::Ptr32(Segment::Default, 0x0200));
+    op.op_size = OperandSize::_32bit;
+    assert_encdec(&op, "lss esp,[0x200]", vec!(0x66, 0x0F, 0xB2, 0x26, 0x00, 0x02));
+}
+
+#[test]
+fn can_encode_system_instructions() {
+    let op = Instruction::new1(Op::Lgdt, Parameter::Ptr16(Segment::Default, 0x0A00));
+    assert_encdec(&op, "lgdt [0xa00]", vec!(0x0F, 0x01, 0x16, 0x00, 0x0A));
+
+    let op = Instruction::new1(Op::Sidt, Parameter::Ptr16Amode(Segment::Default, AMode::BX));
+    assert_encdec(&op, "sidt [bx]", vec!(0x0F, 0x01, 0x0F));
+
+    let op = Instruction::new1(Op::Lmsw, Parameter::Reg16(R::AX));
+    assert_encdec(&op, "lmsw ax", vec!(0x0F, 0x01, 0xF0));
+
+    let op = Instruction::new1(Op::Str, Parameter::Reg16(R::AX));
+    assert_encdec(&op, "str ax", vec!(0x0F, 0x00, 0xC8));
+
+    let op = Instruction::new1(Op::Verr, Parameter::Ptr16Amode(Segment::Default, AMode::BX));
+    assert_encdec(&op, "verr [bx]", vec!(0x0F, 0x00, 0x27));
+
+    let op = Instruction::new2(Op::Mov32, Parameter::Reg32(R::EAX), Parameter::CR(0));
+    assert_encdec(&op, "mov eax,cr0", vec!(0x0F, 0x20, 0xC0));
+
+    let op = Instruction::new2(Op::Mov32, Parameter::CR(3), Parameter::Reg32(R::EBX));
+    assert_encdec(&op, "mov cr3,ebx", vec!(0x0F, 0x22, 0xDB));
+
+    let op = Instruction::new(Op::Clts);
+    assert_encdec(&op, "clts", vec!(0x0F, 0x06));
+}
+
+#[test]
+fn can_encode_32bit_addressing() {
+    // r32, r/m32 with sib byte and disp8
+    let op = Instruction::new2(Op::Mov32, Parameter::Reg32(R::EAX), Parameter::Ptr32AmodeS8(Segment::Default, AMode::SIB(Some(R::EBX), Some(R::ECX), 4), 0x10));
+    assert_encdec(&op, "mov eax,[ebx+ecx*4+0x10]", vec!(0x67, 0x66, 0x8B, 0x44, 0x8B, 0x10));
+
+    // r/m16, r16 with sib byte without base
+    let op = Instruction::new2(Op::Mov16, Parameter::Ptr16AmodeS32(Segment::Default, AMode::SIB(None, Some(R::EDI), 4), -0x10), Parameter::Reg16(R::DX));
+    assert_encdec(&op, "mov [edi*4-0x10],dx", vec!(0x67, 0x89, 0x14, 0xBD, 0xF0, 0xFF, 0xFF, 0xFF));
+
+    // r8, r/m8 with esp base
+    let op = Instruction::new2(Op::Mov8, Parameter::Reg8(R::AL), Parameter::Ptr8Amode(Segment::Default, AMode::SIB(Some(R::ESP), None, 1)));
+    assert_encdec(&op, "mov al,[esp]", vec!(0x67, 0x8A, 0x04, 0x24));
+
+    // r16, r/m16 with disp32
+    let op = Instruction::new2(Op::Mov16, Parameter::Reg16(R::CX), Parameter::Ptr16AmodeS32(Segment::Default, AMode::EBP, 0x1000));
+    assert_encdec(&op, "mov cx,[ebp+0x1000]", vec!(0x67, 0x8B, 0x8D, 0x00, 0x10, 0x00, 0x00));
+}
+
 // TODO make this into a macro to retain caller line numbers in the asserts
 fn assert_encdec(op :&Instruction, expected_ndisasm: &str, expected_bytes: Vec<u8>) {
     let encoder = Encoder::new();
@@ -496,6 +605,7 @@ fn assert_encdec(op :&Instruction, expected_ndisasm: &str, expected_bytes: Vec<u
 
     let mut want_op = op.clone();
     let mut machine = Machine::default();
+    machine.set_cpu_model(CpuModel::I80486);
     machine.load_executable(&code);
     let cs = machine.cpu.get_r16(R::CS);
     let ops = machine.cpu.decoder.decode_to_block(&mut machine.hw.mmu, cs, 0x100, 1);
@@ -170,7 +170,7 @@ impl Flags {
         self.zero        = val & 0x40 != 0;
         self.sign        = val & 0x80 != 0;
         self.trap        = val & 0x100 != 0;
-        //self.interrupt   = val & 0x200 != 0;
+        self.interrupt   = val & 0x200 != 0;
         self.direction   = val & 0x400 != 0;
         self.overflow    = val & 0x800 != 0;
         //self.iopl12      = val & 0x1000 != 0;
@@ -178,6 +178,13 @@ impl Flags {
         //self.nested_task = val & 0x4000 != 0;
     }
 
+    /// loads IOPL and NT (bits 12-14), which are left unchanged by set_u16
+    pub fn set_iopl_nt(&mut self, val: u16) {
+        self.iopl12      = val & 0x1000 != 0;
+        self.iopl13      = val & 0x2000 != 0;
+        self.nested_task = val & 0x4000 != 0;
+    }
+
     pub fn carry_val(&self) -> usize {
         if self.carry {
             1
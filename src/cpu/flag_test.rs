@@ -4,5 +4,5 @@ use cpu::flag::Flags;
 fn can_pack_unpack_flags() {
     let mut flags = Flags::new();
     flags.set_u16(0xFFFF);
-    assert_eq!(0x0DD5, flags.u16());
+    assert_eq!(0x0FD5, flags.u16());
 }
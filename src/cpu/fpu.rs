@@ -0,0 +1,410 @@
+// x87 floating point unit
+// https://www.felixcloutier.com/x86/ and the Intel 80387 Programmer's Reference Manual
+//
+// The register stack holds f64 values, so results are rounded to double
+// precision rather than to the 64-bit significand of the real hardware.
+// Exceptions are only flagged in the status word, unmasked exceptions
+// do not raise a interrupt.
+
+#[cfg(test)]
+#[path = "./fpu_test.rs"]
+mod fpu_test;
+
+// status word bits
+pub const FPU_IE: u16 = 0x0001; // invalid operation
+pub const FPU_DE: u16 = 0x0002; // denormalized operand
+pub const FPU_ZE: u16 = 0x0004; // zero divide
+pub const FPU_OE: u16 = 0x0008; // overflow
+pub const FPU_UE: u16 = 0x0010; // underflow
+pub const FPU_PE: u16 = 0x0020; // precision
+pub const FPU_SF: u16 = 0x0040; // stack fault
+pub const FPU_ES: u16 = 0x0080; // error summary
+pub const FPU_C0: u16 = 0x0100;
+pub const FPU_C1: u16 = 0x0200;
+pub const FPU_C2: u16 = 0x0400;
+pub const FPU_C3: u16 = 0x4000;
+pub const FPU_BUSY: u16 = 0x8000;
+
+const FPU_TOP_MASK: u16 = 0x3800;
+const FPU_EXCEPTIONS: u16 = 0x003F;
+
+/// the real indefinite value, a negative quiet NaN
+const INDEFINITE: f64 = -::std::f64::NAN;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Tag {
+    Valid = 0,
+    Zero = 1,
+    Special = 2,
+    Empty = 3,
+}
+
+impl Tag {
+    fn of(val: f64) -> Tag {
+        if val == 0.0 {
+            Tag::Zero
+        } else if val.is_finite() && val.is_normal() {
+            Tag::Valid
+        } else {
+            Tag::Special
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct FPU {
+    /// physical registers R0-R7
+    regs: [f64; 8],
+    tags: [Tag; 8],
+
+    /// physical register number of ST(0)
+    top: usize,
+
+    pub control: u16,
+
+    /// status word, without the TOP field
+    status: u16,
+}
+
+impl FPU {
+    pub fn default() -> Self {
+        FPU {
+            regs: [0.; 8],
+            tags: [Tag::Empty; 8],
+            top: 0,
+            control: 0x037F,
+            status: 0,
+        }
+    }
+
+    /// FNINIT
+    pub fn init(&mut self) {
+        *self = FPU::default();
+    }
+
+    pub fn status_word(&self) -> u16 {
+        (self.status & !FPU_TOP_MASK) | ((self.top as u16) << 11)
+    }
+
+    pub fn set_status_word(&mut self, val: u16) {
+        self.status = val & !FPU_TOP_MASK;
+        self.top = usize::from((val & FPU_TOP_MASK) >> 11);
+    }
+
+    pub fn tag_word(&self) -> u16 {
+        let mut res = 0;
+        for (i, tag) in self.tags.iter().enumerate() {
+            res |= (*tag as u16) << (i * 2);
+        }
+        res
+    }
+
+    /// sets the tag word. only empty or non-empty is used, the tag of a
+    /// non-empty register is recomputed from its value
+    pub fn set_tag_word(&mut self, val: u16) {
+        for i in 0..8 {
+            self.tags[i] = if (val >> (i * 2)) & 0b11 == Tag::Empty as u16 {
+                Tag::Empty
+            } else {
+                Tag::of(self.regs[i])
+            };
+        }
+    }
+
+    /// FNCLEX
+    pub fn clear_exceptions(&mut self) {
+        self.status &= !(FPU_EXCEPTIONS | FPU_SF | FPU_ES | FPU_BUSY);
+    }
+
+    /// flags a exception in the status word
+    pub fn set_exception(&mut self, flag: u16) {
+        self.status |= flag;
+        if self.control & flag & FPU_EXCEPTIONS == 0 {
+            self.status |= FPU_ES | FPU_BUSY;
+        }
+    }
+
+    /// sets the condition code bits C3, C2, C1 and C0
+    pub fn set_condition(&mut self, c3: bool, c2: bool, c1: bool, c0: bool) {
+        self.status &= !(FPU_C3 | FPU_C2 | FPU_C1 | FPU_C0);
+        if c3 {
+            self.status |= FPU_C3;
+        }
+        if c2 {
+            self.status |= FPU_C2;
+        }
+        if c1 {
+            self.status |= FPU_C1;
+        }
+        if c0 {
+            self.status |= FPU_C0;
+        }
+    }
+
+    /// sets C2 to flag a incomplete operation, like a operand out of range for FSIN
+    pub fn set_incomplete(&mut self, incomplete: bool) {
+        self.status &= !(FPU_C1 | FPU_C2);
+        if incomplete {
+            self.status |= FPU_C2;
+        }
+    }
+
+    fn physical(&self, i: usize) -> usize {
+        (self.top + i) & 7
+    }
+
+    pub fn is_empty(&self, i: usize) -> bool {
+        self.tags[self.physical(i)] == Tag::Empty
+    }
+
+    /// returns ST(i)
+    pub fn st(&mut self, i: usize) -> f64 {
+        let reg = self.physical(i);
+        if self.tags[reg] == Tag::Empty {
+            // stack underflow
+            self.status &= !FPU_C1;
+            self.set_exception(FPU_IE | FPU_SF);
+            return INDEFINITE;
+        }
+        self.regs[reg]
+    }
+
+    /// sets ST(i)
+    pub fn set_st(&mut self, i: usize, val: f64) {
+        let reg = self.physical(i);
+        self.regs[reg] = val;
+        self.tags[reg] = Tag::of(val);
+    }
+
+    /// returns ST(i) regardless of its tag, used by FNSAVE
+    pub fn st_raw(&self, i: usize) -> f64 {
+        self.regs[self.physical(i)]
+    }
+
+    /// sets ST(i) without marking it as used, used by FRSTOR
+    pub fn set_st_raw(&mut self, i: usize, val: f64) {
+        let reg = self.physical(i);
+        self.regs[reg] = val;
+        if self.tags[reg] != Tag::Empty {
+            self.tags[reg] = Tag::of(val);
+        }
+    }
+
+    pub fn push(&mut self, val: f64) {
+        self.top = (self.top + 7) & 7;
+        if self.tags[self.top] != Tag::Empty {
+            // stack overflow
+            self.status |= FPU_C1;
+            self.set_exception(FPU_IE | FPU_SF);
+            self.set_st(0, INDEFINITE);
+            return;
+        }
+        self.set_st(0, val);
+    }
+
+    pub fn pop(&mut self) -> f64 {
+        let val = self.st(0);
+        self.tags[self.top] = Tag::Empty;
+        self.top = (self.top + 1) & 7;
+        val
+    }
+
+    /// FFREE
+    pub fn free(&mut self, i: usize) {
+        let reg = self.physical(i);
+        self.tags[reg] = Tag::Empty;
+    }
+
+    /// FXCH
+    pub fn exchange(&mut self, i: usize) {
+        let a = self.st(0);
+        let b = self.st(i);
+        self.set_st(0, b);
+        self.set_st(i, a);
+    }
+
+    /// FINCSTP
+    pub fn increment_top(&mut self) {
+        self.top = (self.top + 1) & 7;
+    }
+
+    /// FDECSTP
+    pub fn decrement_top(&mut self) {
+        self.top = (self.top + 7) & 7;
+    }
+
+    /// FCOM, FUCOM, FTST: sets C3, C2, C0 from comparing a with b
+    pub fn compare(&mut self, a: f64, b: f64, unordered_ok: bool) {
+        if a.is_nan() || b.is_nan() {
+            if !unordered_ok {
+                self.set_exception(FPU_IE);
+            }
+            self.set_condition(true, true, false, true);
+        } else if a > b {
+            self.set_condition(false, false, false, false);
+        } else if a < b {
+            self.set_condition(false, false, false, true);
+        } else {
+            self.set_condition(true, false, false, false);
+        }
+    }
+
+    /// FXAM: classifies ST(0)
+    pub fn examine(&mut self) {
+        let sign = self.regs[self.top].is_sign_negative();
+        if self.is_empty(0) {
+            self.set_condition(true, false, sign, true);
+            return;
+        }
+        let val = self.regs[self.top];
+        match (val.is_nan(), val.is_infinite(), val == 0.0, val.is_normal()) {
+            (true, _, _, _) => self.set_condition(false, false, sign, true),
+            (_, true, _, _) => self.set_condition(false, true, sign, true),
+            (_, _, true, _) => self.set_condition(true, false, sign, false),
+            (_, _, _, true) => self.set_condition(false, true, sign, false),
+            _ => self.set_condition(true, true, sign, false), // denormal
+        }
+    }
+
+    /// rounds val to a integer as selected by the rounding control
+    pub fn round(&self, val: f64) -> f64 {
+        match (self.control >> 10) & 0b11 {
+            0 => {
+                // round to nearest, ties to even
+                let r = val.round();
+                if (val - val.trunc()).abs() == 0.5 && r % 2.0 != 0.0 {
+                    r - val.signum()
+                } else {
+                    r
+                }
+            }
+            1 => val.floor(),
+            2 => val.ceil(),
+            _ => val.trunc(),
+        }
+    }
+
+    /// converts val to a integer in the range of a signed integer of `bits` size.
+    /// returns the integer indefinite value on overflow
+    pub fn to_int(&mut self, val: f64, bits: u32) -> i64 {
+        let min = -(2f64.powi(bits as i32 - 1));
+        let rounded = self.round(val);
+        if rounded.is_nan() || rounded < min || rounded >= -min {
+            self.set_exception(FPU_IE);
+            return i64::min_value() >> (64 - bits);
+        }
+        if rounded != val {
+            self.set_exception(FPU_PE);
+        }
+        rounded as i64
+    }
+
+    /// returns a / b and flags zero divide and invalid operations
+    pub fn divide(&mut self, a: f64, b: f64) -> f64 {
+        if b == 0.0 {
+            if a == 0.0 || a.is_nan() {
+                self.set_exception(FPU_IE);
+            } else if a.is_finite() {
+                self.set_exception(FPU_ZE);
+            }
+        }
+        a / b
+    }
+
+    /// FPREM and FPREM1: partial remainder of a / b. round_nearest selects FPREM1
+    pub fn remainder(&mut self, a: f64, b: f64, round_nearest: bool) -> f64 {
+        if b == 0.0 || a.is_infinite() || a.is_nan() || b.is_nan() {
+            self.set_exception(FPU_IE);
+            self.set_condition(false, false, false, false);
+            return INDEFINITE;
+        }
+        let q = if round_nearest {
+            let q = (a / b).round();
+            if ((a / b) - (a / b).trunc()).abs() == 0.5 && q % 2.0 != 0.0 {
+                q - (a / b).signum()
+            } else {
+                q
+            }
+        } else {
+            (a / b).trunc()
+        };
+        let res = a - q * b;
+        // C0, C3, C1 = bits 2, 1, 0 of the quotient
+        let q = q.abs() as u64;
+        self.set_condition(q & 2 != 0, false, q & 1 != 0, q & 4 != 0);
+        res
+    }
+}
+
+/// converts val to the 80-bit extended precision format, returns (significand, sign and exponent)
+pub fn f64_to_f80(val: f64) -> (u64, u16) {
+    let bits = val.to_bits();
+    let sign = if bits >> 63 != 0 { 0x8000 } else { 0 };
+    let exp = ((bits >> 52) & 0x7FF) as i32;
+    let frac = bits & 0x000F_FFFF_FFFF_FFFF;
+    match exp {
+        0 if frac == 0 => (0, sign),
+        0 => {
+            // denormal, normalize it
+            let shift = frac.leading_zeros();
+            let exp = 16383 - 1074 + 63 - shift as i32;
+            (frac << shift, sign | exp as u16)
+        }
+        0x7FF if frac == 0 => (0x8000_0000_0000_0000, sign | 0x7FFF),
+        0x7FF => (0xC000_0000_0000_0000 | (frac << 11), sign | 0x7FFF),
+        _ => (0x8000_0000_0000_0000 | (frac << 11), sign | (exp - 1023 + 16383) as u16),
+    }
+}
+
+/// converts a 80-bit extended precision value to f64
+pub fn f80_to_f64(significand: u64, sign_exp: u16) -> f64 {
+    let negative = sign_exp & 0x8000 != 0;
+    let exp = i32::from(sign_exp & 0x7FFF);
+    let val = if exp == 0x7FFF {
+        if significand << 1 == 0 {
+            ::std::f64::INFINITY
+        } else {
+            ::std::f64::NAN
+        }
+    } else {
+        // value = significand * 2^(exp - 16383 - 63), scaled in two steps
+        // to avoid overflow in the power of two
+        let scale = exp - 16383 - 63;
+        significand as f64 * 2f64.powi(scale / 2) * 2f64.powi(scale - scale / 2)
+    };
+    if negative {
+        -val
+    } else {
+        val
+    }
+}
+
+/// converts a 80-bit packed BCD value to f64
+pub fn bcd_to_f64(bytes: &[u8]) -> f64 {
+    let mut val = 0.;
+    for b in bytes[0..9].iter().rev() {
+        val = val * 100. + f64::from(b >> 4) * 10. + f64::from(b & 0xF);
+    }
+    if bytes[9] & 0x80 != 0 {
+        -val
+    } else {
+        val
+    }
+}
+
+/// converts a integer to 80-bit packed BCD, returns None if it doesn't fit in 18 digits
+pub fn i64_to_bcd(val: i64) -> Option<[u8; 10]> {
+    let mut res = [0u8; 10];
+    let mut n = val.checked_abs()? as u64;
+    if n >= 1_000_000_000_000_000_000 {
+        return None;
+    }
+    for b in res.iter_mut().take(9) {
+        *b = (n % 10) as u8 | (((n / 10) % 10) as u8) << 4;
+        n /= 100;
+    }
+    if val < 0 {
+        res[9] = 0x80;
+    }
+    Some(res)
+}
@@ -0,0 +1,150 @@
+use machine::Machine;
+use cpu::register::R;
+use cpu::fpu::{FPU, FPU_C0, FPU_C1, FPU_C2, FPU_C3, FPU_IE, FPU_SF, f64_to_f80, f80_to_f64, bcd_to_f64, i64_to_bcd};
+
+#[test]
+fn can_execute_fpu_arithmetic() {
+    let mut machine = Machine::default();
+    let code: Vec<u8> = vec![
+        0xDB, 0xE3,             // fninit
+        0xDF, 0x06, 0x00, 0x02, // fild word [0x200]
+        0xD9, 0xE8,             // fld1
+        0xDE, 0xC1,             // faddp st1,st0
+        0xD9, 0xEB,             // fldpi
+        0xDE, 0xC9,             // fmulp st1,st0
+        0xDF, 0x1E, 0x02, 0x02, // fistp word [0x202]
+        0xDF, 0xE0,             // fnstsw ax
+    ];
+    machine.load_executable(&code);
+    let ds = machine.cpu.get_r16(R::DS);
+    machine.hw.mmu.write_u16(ds, 0x200, 3);
+
+    machine.execute_instructions(6);
+    assert_eq!(4. * ::std::f64::consts::PI, machine.cpu.fpu.st(0));
+
+    machine.execute_instructions(2);
+    assert_eq!(13, machine.hw.mmu.read_u16(ds, 0x202));
+    assert_eq!(0x0000, machine.cpu.get_r16(R::AX) & 0x3800); // TOP
+    assert_eq!(true, machine.cpu.fpu.is_empty(0));
+}
+
+#[test]
+fn can_execute_fpu_compare() {
+    let mut machine = Machine::default();
+    let code: Vec<u8> = vec![
+        0xD9, 0xE8, // fld1
+        0xD9, 0xEE, // fldz
+        0xDE, 0xD9, // fcompp
+        0xDF, 0xE0, // fnstsw ax
+        0x9E,       // sahf
+    ];
+    machine.load_executable(&code);
+    machine.execute_instructions(5);
+    assert_eq!(FPU_C0, machine.cpu.get_r16(R::AX) & (FPU_C3 | FPU_C2 | FPU_C0));
+    assert_eq!(true, machine.cpu.regs.flags.carry);
+    assert_eq!(false, machine.cpu.regs.flags.zero);
+}
+
+#[test]
+fn can_store_fpu_memory_formats() {
+    let mut machine = Machine::default();
+    let code: Vec<u8> = vec![
+        0xDD, 0x06, 0x00, 0x02, // fld qword [0x200]
+        0xDB, 0x16, 0x08, 0x02, // fist dword [0x208]
+        0xDB, 0x3E, 0x10, 0x02, // fstp tword [0x210]
+        0xDB, 0x2E, 0x10, 0x02, // fld tword [0x210]
+        0xDF, 0x36, 0x20, 0x02, // fbstp tword [0x220]
+        0xD9, 0x1E, 0x30, 0x02, // fstp dword [0x230]   ; stack underflow
+    ];
+    machine.load_executable(&code);
+    let ds = machine.cpu.get_r16(R::DS);
+    let bits = (-2.5f64).to_bits();
+    machine.hw.mmu.write_u32(ds, 0x200, bits as u32);
+    machine.hw.mmu.write_u32(ds, 0x204, (bits >> 32) as u32);
+
+    machine.execute_instructions(2);
+    // round to nearest even
+    assert_eq!(0xFFFF_FFFE, machine.hw.mmu.read_u32(ds, 0x208));
+
+    machine.execute_instructions(1);
+    assert_eq!(0xA000_0000, machine.hw.mmu.read_u32(ds, 0x214));
+    assert_eq!(0xC000, machine.hw.mmu.read_u16(ds, 0x218));
+
+    machine.execute_instructions(2);
+    assert_eq!(0x02, machine.hw.mmu.read_u8(ds, 0x220));
+    assert_eq!(0x80, machine.hw.mmu.read_u8(ds, 0x229));
+
+    machine.execute_instructions(1);
+    let status = machine.cpu.fpu.status_word();
+    assert_eq!(FPU_IE | FPU_SF, status & (FPU_IE | FPU_SF | FPU_C1));
+    assert_eq!(0xFFC0_0000, machine.hw.mmu.read_u32(ds, 0x230)); // negative quiet NaN
+}
+
+#[test]
+fn can_save_and_restore_fpu_state() {
+    let mut machine = Machine::default();
+    let code: Vec<u8> = vec![
+        0xD9, 0xE8,             // fld1
+        0xD9, 0xEB,             // fldpi
+        0xD9, 0x3E, 0x00, 0x02, // fnstcw [0x200]
+        0xDD, 0x36, 0x00, 0x03, // fnsave [0x300]
+        0xDD, 0x26, 0x00, 0x03, // frstor [0x300]
+    ];
+    machine.load_executable(&code);
+    let ds = machine.cpu.get_r16(R::DS);
+    machine.execute_instructions(3);
+    assert_eq!(0x037F, machine.hw.mmu.read_u16(ds, 0x200));
+
+    machine.execute_instructions(1);
+    assert_eq!(0x037F, machine.hw.mmu.read_u16(ds, 0x300)); // control word
+    assert_eq!(0x3000, machine.hw.mmu.read_u16(ds, 0x302)); // status word, TOP = 6
+    assert_eq!(0x0FFF, machine.hw.mmu.read_u16(ds, 0x304)); // tag word
+    assert_eq!(true, machine.cpu.fpu.is_empty(0));
+
+    machine.execute_instructions(1);
+    assert_eq!(::std::f64::consts::PI, machine.cpu.fpu.st(0));
+    assert_eq!(1., machine.cpu.fpu.st(1));
+    assert_eq!(true, machine.cpu.fpu.is_empty(2));
+}
+
+#[test]
+fn can_round_by_rounding_control() {
+    let mut fpu = FPU::default();
+    assert_eq!(2., fpu.round(2.5));
+    assert_eq!(-4., fpu.round(-3.5));
+    fpu.control = 0x077F; // round down
+    assert_eq!(2., fpu.round(2.5));
+    assert_eq!(-3., fpu.round(-2.5));
+    fpu.control = 0x0B7F; // round up
+    assert_eq!(3., fpu.round(2.5));
+    fpu.control = 0x0F7F; // truncate
+    assert_eq!(-2., fpu.round(-2.5));
+    assert_eq!(-0x8000, fpu.to_int(40000., 16));
+    assert_eq!(FPU_IE, fpu.status_word() & FPU_IE);
+}
+
+#[test]
+fn can_detect_stack_overflow() {
+    let mut fpu = FPU::default();
+    for i in 0..8 {
+        fpu.push(f64::from(i));
+    }
+    assert_eq!(0, fpu.status_word() & FPU_IE);
+    fpu.push(8.);
+    assert_eq!(FPU_IE | FPU_SF | FPU_C1, fpu.status_word() & (FPU_IE | FPU_SF | FPU_C1));
+    assert_eq!(true, fpu.st(0).is_nan());
+}
+
+#[test]
+fn can_convert_extended_and_bcd() {
+    for val in &[1., -2.5, 0.1, 1e300, -1e-310, ::std::f64::INFINITY] {
+        let (significand, exp) = f64_to_f80(*val);
+        assert_eq!(*val, f80_to_f64(significand, exp));
+    }
+    assert_eq!((0x8000_0000_0000_0000, 0x3FFF), f64_to_f80(1.));
+
+    let bcd = i64_to_bcd(-1234).unwrap();
+    assert_eq!([0x34, 0x12, 0, 0, 0, 0, 0, 0, 0, 0x80], bcd);
+    assert_eq!(-1234., bcd_to_f64(&bcd));
+    assert_eq!(None, i64_to_bcd(1_000_000_000_000_000_000));
+}
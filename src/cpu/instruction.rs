@@ -45,6 +45,7 @@ impl Instruction {
 
     pub fn new3(op: Op, dst: Parameter, src: Parameter, src2: Parameter) -> Self {
         let op_size = Instruction::op_size_from_op(&op);
+        let address_size = Instruction::address_size_from_params(&[&dst, &src, &src2]);
         Instruction {
             command: op,
             segment_prefix: Segment::Default,
@@ -52,18 +53,29 @@ impl Instruction {
             lock: false,
             repeat: RepeatMode::None,
             op_size,
-            address_size: AddressSize::_16bit,
+            address_size,
             length: 0,
         }
     }
 
     fn op_size_from_op(op: &Op) -> OperandSize {
         match *op {
-            Op::Mov32 | Op::Inc32 | Op::Dec32 => OperandSize::_32bit,
+            Op::Mov32 | Op::Inc32 | Op::Dec32 | Op::Xadd32 | Op::Cmpxchg32 => OperandSize::_32bit,
             _ => OperandSize::_16bit,
         }
     }
 
+    fn address_size_from_params(params: &[&Parameter]) -> AddressSize {
+        for p in params {
+            if let Some(amode) = p.amode() {
+                if amode.is_32bit() {
+                    return AddressSize::_32bit;
+                }
+            }
+        }
+        AddressSize::_16bit
+    }
+
     fn hide_segment_prefix(&self) -> bool {
         self.command == Op::Add8 || self.command == Op::Add16 || self.command == Op::Add32 ||
         self.command == Op::Adc8 || self.command == Op::Adc16 || self.command == Op::Adc32 ||
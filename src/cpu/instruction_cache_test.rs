@@ -0,0 +1,70 @@
+use std::rc::Rc;
+
+use machine::Machine;
+use cpu::instruction::Instruction;
+use cpu::instruction_cache::InstructionCache;
+use cpu::op::Op;
+use cpu::register::R;
+use memory::FlatMemory;
+
+fn instruction(length: u8) -> Rc<Instruction> {
+    let mut op = Instruction::new(Op::Nop);
+    op.length = length;
+    Rc::new(op)
+}
+
+#[test]
+fn invalidates_exactly_the_written_instructions() {
+    let mut memory = FlatMemory::new();
+    let mut cache = InstructionCache::default();
+    cache.insert(&mut memory, 0x1000, 0x0700, false, instruction(3));
+    cache.insert(&mut memory, 0x1003, 0x0703, false, instruction(2));
+    cache.insert(&mut memory, 0x1010, 0x0710, false, instruction(4));
+    assert_eq!(3, cache.get(0x1000, 0x0700, false).unwrap().length);
+    assert_eq!(true, cache.get(0x1001, 0x0701, false).is_none());
+
+    // the same address reached from another segment is decoded again
+    assert_eq!(true, cache.get(0x1000, 0x0000, false).is_none());
+
+    // and so is the same offset in a 32-bit code segment
+    assert_eq!(true, cache.get(0x1000, 0x0700, true).is_none());
+
+    // writes next to cached instructions are not recorded
+    memory.write_u8(0x0FFF, 0x90);
+    memory.write_u16(0x1005, 0x9090);
+    assert_eq!(false, memory.has_modified_code());
+
+    // a write to the last byte of an instruction only removes that instruction
+    memory.write_u8(0x1002, 0x90);
+    cache.invalidate_modified(&mut memory);
+    assert_eq!(true, cache.get(0x1000, 0x0700, false).is_none());
+    assert_eq!(true, cache.get(0x1003, 0x0703, false).is_some());
+    assert_eq!(true, cache.get(0x1010, 0x0710, false).is_some());
+
+    // block writes are seen too
+    memory.write(0x1012, &[0x90, 0x90]);
+    cache.invalidate_modified(&mut memory);
+    assert_eq!(true, cache.get(0x1003, 0x0703, false).is_some());
+    assert_eq!(true, cache.get(0x1010, 0x0710, false).is_none());
+    assert_eq!(false, memory.has_modified_code());
+}
+
+#[test]
+fn can_execute_self_modifying_code() {
+    let mut machine = Machine::default();
+    let code: Vec<u8> = vec![
+        0xB9, 0x02, 0x00,       // mov cx,0x2
+        0xB8, 0x01, 0x00,       // l_0x103: mov ax,0x1
+        0xC6, 0x06, 0x04, 0x01, 0x05, // mov byte [0x104],0x5
+        0xE2, 0xF6,             // loop l_0x103
+    ];
+    machine.load_executable(&code);
+
+    machine.execute_instructions(4);
+    assert_eq!(0x0001, machine.cpu.get_r16(R::AX));
+    assert_eq!(0x0103, machine.cpu.regs.ip);
+
+    // the cached mov ax was patched by the previous pass
+    machine.execute_instruction();
+    assert_eq!(0x0005, machine.cpu.get_r16(R::AX));
+}
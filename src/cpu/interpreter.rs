@@ -1,4 +1,5 @@
 use std::{mem, u8};
+use std::f64::consts;
 use std::num::Wrapping;
 use std::marker::PhantomData;
 
@@ -6,23 +7,35 @@ use cpu::flag::Flags;
 use cpu::instruction::{Instruction, InstructionInfo, ModRegRm, RepeatMode};
 use cpu::parameter::{Parameter, ParameterSet};
 use cpu::op::{Op, Invalid};
-use cpu::register::{R, AMode, RegisterSnapshot};
-use cpu::decoder::Decoder;
+use cpu::register::{R, AMode, RegisterSnapshot, r32};
+use cpu::decoder::{Decoder, OperandSize, AddressSize};
+use cpu::descriptor::{Descriptor, DescriptorTable, Gate, CR0_PE, CR0_PG, CR0_TS, SYSTEM_LDT, SYSTEM_TSS_286, SYSTEM_TSS_386,
+    SYSTEM_TSS_386_BUSY, selector_rpl, selector_is_ldt, selector_offset, selector_is_null};
+use cpu::fpu::{FPU, FPU_IE, FPU_ZE, f64_to_f80, f80_to_f64, bcd_to_f64, i64_to_bcd};
 use cpu::segment::Segment;
+use cpu::timing::{TimingProfile, instruction_cycles};
+use cpu::model::CpuModel;
 use memory::{MMU, MemoryAddress};
 use interrupt;
 use gpu::GPU;
 use machine::Machine;
 use hardware::Hardware;
+use hex::hex_bytes;
+use ndisasm::ndisasm_first_instr;
 
 #[cfg(test)]
 #[path = "./interpreter_test.rs"]
 mod interpreter_test;
 
-#[derive(Debug)]
-enum Exception {
+pub use self::block::*;
+#[path = "./block.rs"]
+mod block;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Exception {
     // http://wiki.osdev.org/Interrupt_Vector_Table
     DIV0 = 0,    // Divide by 0
+    DB = 1,      // Debug, single step trap
     UD = 6,      // Invalid opcode (UD2)
     DF = 8,      // Double fault
     TS = 10,     // Invalid TSS
@@ -32,6 +45,24 @@ enum Exception {
     PF = 14,     // Page fault
 }
 
+impl Exception {
+    /// exceptions that push a error code in protected mode
+    pub fn has_error_code(self) -> bool {
+        match self {
+            Exception::DF | Exception::TS | Exception::NP | Exception::SS | Exception::GP | Exception::PF => true,
+            _ => false,
+        }
+    }
+
+    /// a contributory exception raised while delivering another one causes a double fault
+    fn is_contributory(self) -> bool {
+        match self {
+            Exception::DIV0 | Exception::TS | Exception::NP | Exception::SS | Exception::GP => true,
+            _ => false,
+        }
+    }
+}
+
 pub struct CPU {
     pub instruction_count: usize,
     pub cycle_count: usize,
@@ -48,11 +79,65 @@ pub struct CPU {
     /// signals to debugger we hit an error (used by debugger)
     pub fatal_error: bool,
 
+    /// the cpu executed HLT and waits for a interrupt
+    pub halted: bool,
+
     /// toggles non-deterministic behaviour (used by tests)
     pub deterministic: bool,
 
     pub decoder: Decoder,
     pub clock_hz: usize,
+
+    /// decoded basic blocks of the threaded engine
+    blocks: BlockCache,
+
+    /// selects the instruction timing tables
+    pub timing: TimingProfile,
+
+    /// the emulated processor, see set_model
+    model: CpuModel,
+
+    /// the last instruction was a REP string instruction that will repeat
+    repeating: bool,
+
+    /// offset of the instruction being executed, faults return to it
+    instruction_start: u32,
+
+    /// the trap flag was set when the instruction started, a single step trap follows it
+    single_step: bool,
+
+    /// x87 floating point unit
+    pub fpu: FPU,
+
+    /// control registers. CR0 holds the protection enable bit
+    pub cr0: u32,
+    pub cr2: u32,
+    pub cr3: u32,
+
+    /// global and interrupt descriptor tables. in real mode the IDTR locates the interrupt vector table
+    pub gdtr: DescriptorTable,
+    pub idtr: DescriptorTable,
+
+    /// local descriptor table selector and location
+    pub ldtr: u16,
+    pub ldt: DescriptorTable,
+
+    /// task register selector and the descriptor of the task state segment
+    pub tr: u16,
+    pub tss: Descriptor,
+
+    /// hidden descriptor caches of ES, CS, SS, DS, FS, GS, only used in protected mode
+    pub segment_cache: [Descriptor; 6],
+
+    /// current privilege level
+    cpl: u8,
+
+    /// the exception delivered by the last protected mode interrupt, None for software and hardware interrupts.
+    /// lets HLE handlers tell exceptions apart from interrupts sharing the vector
+    pub last_exception: Option<Exception>,
+
+    /// exception raised by a instruction, delivered when it completes
+    fault: Option<(Exception, u16)>,
 }
 
 impl CPU {
@@ -64,12 +149,51 @@ impl CPU {
             rom_base: 0,
             rom_length: 0,
             fatal_error: false,
+            halted: false,
             deterministic: false,
-            decoder: Decoder::default(),
-            clock_hz: 5_000_000, // Intel 8086: 0.330 MIPS at 5.000 MHz
+            decoder: Decoder::with_cache(),
+            blocks: BlockCache::default(),
+            clock_hz: 25_000_000, // Intel 80386: 25 MHz
+            timing: TimingProfile::I80386,
+            model: CpuModel::I80386,
+            repeating: false,
+            instruction_start: 0,
+            single_step: false,
+            fpu: FPU::default(),
+            cr0: 0,
+            cr2: 0,
+            cr3: 0,
+            gdtr: DescriptorTable::default(),
+            idtr: DescriptorTable::ivt(),
+            ldtr: 0,
+            ldt: DescriptorTable::default(),
+            tr: 0,
+            tss: Descriptor::default(),
+            segment_cache: [Descriptor::default(); 6],
+            cpl: 0,
+            last_exception: None,
+            fault: None,
         }
     }
 
+    /// selects the instruction timing tables and the clock speed of a typical machine with the cpu
+    pub fn set_timing_profile(&mut self, profile: TimingProfile) {
+        self.timing = profile;
+        self.clock_hz = profile.clock_hz();
+    }
+
+    /// selects the emulated processor with its instruction set, timing and behaviour
+    pub fn set_model(&mut self, mmu: &mut MMU, model: CpuModel) {
+        self.model = model;
+        self.decoder.set_model(model);
+        self.set_timing_profile(model.timing_profile());
+        mmu.set_wrap(model < CpuModel::I80286);
+    }
+
+    pub fn model(&self) -> CpuModel {
+        self.model
+    }
+
     pub fn get_r8(&self, r: R) -> u8 {
         self.regs.get_r8(r)
     }
@@ -99,11 +223,60 @@ impl CPU {
         self.rom_base
     }
 
-    pub fn execute(&mut self, mut hw: &mut Hardware, op: &Instruction) {
+    pub fn execute(&mut self, hw: &mut Hardware, op: &Instruction) {
+        let start_ip = self.regs.ip;
+        let next_ip = start_ip.wrapping_add(u32::from(op.length)) & self.ip_mask();
+        let first_iteration = !self.repeating;
+        let start_gpr = self.regs.gpr;
+        let start_flags = self.regs.flags;
+        self.single_step = self.regs.flags.trap;
+
+        if let Some(pf) = hw.mmu.take_page_fault() {
+            // the instruction fetch faulted
+            self.instruction_start = start_ip;
+            self.cr2 = pf.address;
+            return self.exception(hw, Exception::PF, pf.error);
+        }
+
+        // faults of HLE or host code between instructions don't fault the instruction
+        self.fault = None;
+        self.execute_op(hw, op);
+
+        if let Some(pf) = hw.mmu.take_page_fault() {
+            self.cr2 = pf.address;
+            self.fault(Exception::PF, pf.error);
+        }
+
+        if let Some((which, error)) = self.fault.take() {
+            // faults leave the registers as they were before the instruction, so it can be restarted
+            self.regs.gpr[..8].copy_from_slice(&start_gpr[..8]);
+            self.regs.flags = start_flags;
+            self.exception(hw, which, error);
+        }
+
+        self.repeating = op.repeat != RepeatMode::None && self.regs.ip == start_ip;
+        let taken = self.regs.ip != next_ip;
+        let count = match op.params.src {
+            Parameter::Imm8(v) => v,
+            Parameter::Imm16(v) => v as u8,
+            Parameter::Reg8(ref r) => self.get_r8(*r),
+            _ => 0,
+        };
+        self.cycle_count += instruction_cycles(self.timing, op, taken, first_iteration, count);
+
+        // interrupts delivered by the instruction clear the trap flag, and loading SS
+        // inhibits the trap until the following instruction has loaded SP
+        if self.single_step && !loads_ss(op) {
+            self.single_step = false;
+            self.deliver_exception(hw, Exception::DB, 0);
+        }
+    }
+
+    fn execute_op(&mut self, mut hw: &mut Hardware, op: &Instruction) {
         let start_ip = self.regs.ip;
-        self.regs.ip = (Wrapping(self.regs.ip) + Wrapping(u16::from(op.length))).0;
+        self.instruction_start = start_ip;
+        self.regs.ip = start_ip.wrapping_add(u32::from(op.length)) & self.ip_mask();
         self.instruction_count += 1;
-        self.cycle_count += 1; // XXX temp hack; we pretend each instruction takes 8 cycles due to lack of timing
         match op.command {
             Op::Aaa => {
                 let v = if self.get_r8(R::AL) > 0xf9 {
@@ -116,15 +289,24 @@ impl CPU {
             Op::Aad => {
                 // one parameter
                 let op1 = self.read_parameter_value(&hw.mmu, &op.params.dst) as u16; // read_parameter_value XXX add param that specify mmu
-                let mut ax = u16::from(self.get_r8(R::AH)) * op1;
-                ax += u16::from(self.get_r8(R::AL));
+                let product = (u16::from(self.get_r8(R::AH)) * op1) as u8;
+                let old_al = self.get_r8(R::AL);
+                let ax = u16::from(product) + u16::from(old_al);
                 let al = ax as u8;
                 self.set_r8(R::AL, al);
                 self.set_r8(R::AH, 0);
                 // modification of flags A,C,O is undocumented
-                self.regs.flags.carry = false;
-                self.regs.flags.overflow = false;
-                self.regs.flags.adjust = false;
+                if self.model < CpuModel::I80286 {
+                    // the 8086 and 80186 set them from the addition
+                    let (res, src, dst) = (ax as usize, product as usize, old_al as usize);
+                    self.regs.flags.set_carry_u8(res);
+                    self.regs.flags.set_overflow_add_u8(res, src, dst);
+                    self.regs.flags.set_adjust(res, src, dst);
+                } else {
+                    self.regs.flags.carry = false;
+                    self.regs.flags.overflow = false;
+                    self.regs.flags.adjust = false;
+                }
                 // The SF, ZF, and PF flags are set according to the resulting binary value in the AL register
                 self.regs.flags.sign = al >= 0x80;
                 self.regs.flags.zero = al == 0;
@@ -136,7 +318,7 @@ impl CPU {
                 // AL ← tempAL MOD imm8;
                 let imm8 = self.read_parameter_value(&hw.mmu, &op.params.dst) as u8;
                 if imm8 == 0 {
-                    return self.exception(&Exception::DIV0, 0);
+                    return self.exception(&mut hw, Exception::DIV0, 0);
                 }
                 let al = self.get_r8(R::AL);
                 self.set_r8(R::AH, al / imm8);
@@ -274,25 +456,28 @@ impl CPU {
                 }
                 */
             }
-            Op::Bsf => {
-                let mut src = self.read_parameter_value(&hw.mmu, &op.params.src);
+            Op::Bsf | Op::Bsr => {
+                let src = self.read_parameter_value(&hw.mmu, &op.params.src) as u32;
                 if src == 0 {
+                    // the destination is undefined
                     self.regs.flags.zero = true;
                 } else {
-                    let mut count = 0;
-                    while src & 1 == 0 {
-                        count += 1;
-                        src >>= 1;
+                    let index = match op.command {
+                        Op::Bsf => src.trailing_zeros(),
+                        _ => 31 - src.leading_zeros(),
+                    };
+                    match op.op_size {
+                        OperandSize::_16bit => self.write_parameter_u16(&mut hw.mmu, op.segment_prefix, &op.params.dst, index as u16),
+                        OperandSize::_32bit => self.write_parameter_u32(&mut hw.mmu, op.segment_prefix, &op.params.dst, index),
                     }
-                    self.write_parameter_u16(&mut hw.mmu, op.segment_prefix, &op.params.dst, count);
                     self.regs.flags.zero = false;
                 }
             }
-            Op::Bt => {
-                let bit_base = self.read_parameter_value(&hw.mmu, &op.params.dst);
-                let bit_offset = self.read_parameter_value(&hw.mmu, &op.params.src);
-                self.regs.flags.carry = bit_base & (1 << (bit_offset & 15)) != 0;
+            Op::Bswap => {
+                let val = self.read_parameter_value(&hw.mmu, &op.params.dst) as u32;
+                self.write_parameter_u32(&mut hw.mmu, op.segment_prefix, &op.params.dst, val.swap_bytes());
             }
+            Op::Bt | Op::Btc | Op::Btr | Op::Bts => self.bit_test(&mut hw, op),
             Op::Bound => {
                 // XXX throw BR exception if out of bounds
                 println!("XXX impl {}", op);
@@ -300,32 +485,25 @@ impl CPU {
             Op::CallNear => {
                 let old_ip = self.regs.ip;
                 let temp_ip = self.read_parameter_value(&hw.mmu, &op.params.dst);
-                self.push16(&mut hw.mmu, old_ip);
-                self.regs.ip = temp_ip as u16;
-            }
-            Op::CallFar => {
-                let old_seg = self.regs.get_r16(R::CS);
-                let old_ip = self.regs.ip;
-                self.push16(&mut hw.mmu, old_seg);
-                self.push16(&mut hw.mmu, old_ip);
-                match op.params.dst {
-                    Parameter::Ptr16Imm(seg, offs) => {
-                        self.regs.ip = offs;
-                        self.regs.set_r16(R::CS, seg);
-                    }
-                    Parameter::Ptr16(seg, offs) => {
-                        let seg = self.segment(seg);
-                        self.set_r16(R::CS, seg);
-                        self.regs.ip = offs;
+                match op.op_size {
+                    OperandSize::_16bit => {
+                        self.push16(&mut hw.mmu, old_ip as u16);
+                        self.regs.ip = u32::from(temp_ip as u16);
                     }
-                    Parameter::Ptr16AmodeS8(seg, ref amode, imm) => {
-                        let seg = self.segment(seg);
-                        self.set_r16(R::CS, seg);
-                        self.regs.ip = (self.amode(amode) as isize + imm as isize) as u16;
+                    OperandSize::_32bit => {
+                        self.push32(&mut hw.mmu, old_ip);
+                        self.regs.ip = temp_ip as u32;
                     }
-                    _ => panic!("CallFar unhandled type {:?}", op.params.dst),
                 }
             }
+            Op::CallFar => {
+                let (seg, offs) = match op.params.dst {
+                    Parameter::Ptr16Imm(seg, offs) => (seg, offs),
+                    // call far m16:16, m16:32
+                    _ => self.read_far_pointer(&hw.mmu, &op.params.dst, &op.op_size),
+                };
+                self.far_transfer(&mut hw.mmu, seg, offs, true, op.op_size == OperandSize::_32bit);
+            }
             Op::Cbw => {
                 let ah = if self.get_r8(R::AL) & 0x80 != 0 {
                     0xFF
@@ -343,6 +521,12 @@ impl CPU {
             Op::Cli => {
                 self.regs.flags.interrupt = false;
             }
+            Op::Clts => {
+                if self.cpl() != 0 {
+                    return self.exception(&mut hw, Exception::GP, 0);
+                }
+                self.cr0 &= !CR0_TS;
+            }
             Op::Cmc => {
                 self.regs.flags.carry = !self.regs.flags.carry;
             }
@@ -371,22 +555,81 @@ impl CPU {
                 // no parameters
                 // Compare word at address DS:(E)SI with word at address ES:(E)DI
                 // The DS segment may be overridden with a segment override prefix, but the ES segment cannot be overridden.
-                let src = hw.mmu.read_u16(self.segment(op.segment_prefix), self.get_r16(R::SI)) as usize;
-                let dst = hw.mmu.read_u16(self.get_r16(R::ES), self.get_r16(R::DI)) as usize;
+                let src = self.read_mem_u16(&hw.mmu, op.segment_prefix.as_register(), self.get_address_register(&op.address_size, R::SI)) as usize;
+                let dst = self.read_mem_u16(&hw.mmu, R::ES, self.get_address_register(&op.address_size, R::DI)) as usize;
                 self.cmp16(dst, src);
 
-                let si = if !self.regs.flags.direction {
-                    (Wrapping(self.get_r16(R::SI)) + Wrapping(2)).0
+                self.advance_string_index(op, R::SI, 2);
+                self.advance_string_index(op, R::DI, 2);
+            }
+            Op::Cmpsd => {
+                // no parameters
+                // Compare dword at address DS:(E)SI with dword at address ES:(E)DI
+                // The DS segment may be overridden with a segment override prefix, but the ES segment cannot be overridden.
+                let src = self.read_mem_u32(&hw.mmu, op.segment_prefix.as_register(), self.get_address_register(&op.address_size, R::SI)) as usize;
+                let dst = self.read_mem_u32(&hw.mmu, R::ES, self.get_address_register(&op.address_size, R::DI)) as usize;
+                self.cmp32(dst, src);
+
+                self.advance_string_index(op, R::SI, 4);
+                self.advance_string_index(op, R::DI, 4);
+            }
+            Op::Cmpxchg8 => {
+                // compare AL with r/m8. if equal, ZF is set and r8 is loaded into r/m8.
+                // else, clear ZF and load r/m8 into AL.
+                let dst = self.read_parameter_value(&hw.mmu, &op.params.dst);
+                let al = self.get_r8(R::AL) as usize;
+                self.cmp8(al, dst);
+                if self.regs.flags.zero {
+                    let src = self.read_parameter_value(&hw.mmu, &op.params.src) as u8;
+                    self.write_parameter_u8(&mut hw.mmu, &op.params.dst, src);
                 } else {
-                    (Wrapping(self.get_r16(R::SI)) - Wrapping(2)).0
-                };
-                self.set_r16(R::SI, si);
-                let di = if !self.regs.flags.direction {
-                    (Wrapping(self.get_r16(R::DI)) + Wrapping(2)).0
+                    self.set_r8(R::AL, dst as u8);
+                }
+            }
+            Op::Cmpxchg16 => {
+                let dst = self.read_parameter_value(&hw.mmu, &op.params.dst);
+                let ax = self.get_r16(R::AX) as usize;
+                self.cmp16(ax, dst);
+                if self.regs.flags.zero {
+                    let src = self.read_parameter_value(&hw.mmu, &op.params.src) as u16;
+                    self.write_parameter_u16(&mut hw.mmu, op.segment_prefix, &op.params.dst, src);
                 } else {
-                    (Wrapping(self.get_r16(R::DI)) - Wrapping(2)).0
-                };
-                self.set_r16(R::DI, di);
+                    self.set_r16(R::AX, dst as u16);
+                }
+            }
+            Op::Cmpxchg32 => {
+                let dst = self.read_parameter_value(&hw.mmu, &op.params.dst);
+                let eax = self.get_r32(R::EAX) as usize;
+                self.cmp32(eax, dst);
+                if self.regs.flags.zero {
+                    let src = self.read_parameter_value(&hw.mmu, &op.params.src) as u32;
+                    self.write_parameter_u32(&mut hw.mmu, op.segment_prefix, &op.params.dst, src);
+                } else {
+                    self.set_r32(R::EAX, dst as u32);
+                }
+            }
+            Op::Cpuid => {
+                // only decoded for the 486, identifies as a Intel 486 DX2 with on-chip FPU
+                match self.get_r32(R::EAX) {
+                    0 => {
+                        self.set_r32(R::EAX, 1); // highest supported function
+                        self.set_r32(R::EBX, 0x756E_6547); // "Genu"
+                        self.set_r32(R::EDX, 0x4965_6E69); // "ineI"
+                        self.set_r32(R::ECX, 0x6C65_746E); // "ntel"
+                    }
+                    1 => {
+                        self.set_r32(R::EAX, 0x0000_0430); // family 4, model 3, stepping 0
+                        self.set_r32(R::EBX, 0);
+                        self.set_r32(R::ECX, 0);
+                        self.set_r32(R::EDX, 0x0000_0001); // FPU
+                    }
+                    _ => {
+                        self.set_r32(R::EAX, 0);
+                        self.set_r32(R::EBX, 0);
+                        self.set_r32(R::ECX, 0);
+                        self.set_r32(R::EDX, 0);
+                    }
+                }
             }
             Op::Cwd16 => {
                 // DX:AX ← sign-extend of AX.
@@ -397,6 +640,15 @@ impl CPU {
                 };
                 self.set_r16(R::DX, dx);
             }
+            Op::Cdq32 => {
+                // EDX:EAX ← sign-extend of EAX.
+                let edx = if self.get_r32(R::EAX) & 0x8000_0000 != 0 {
+                    0xFFFF_FFFF
+                } else {
+                    0
+                };
+                self.set_r32(R::EDX, edx);
+            }
             Op::Cwde32 => {
                 // EAX ← sign-extend of AX.
                 let ax = self.get_r16(R::AX) as i16;
@@ -427,18 +679,8 @@ impl CPU {
             Op::Dec16 => {
                 // single parameter (dst)
                 let dst = self.read_parameter_value(&hw.mmu, &op.params.dst);
-                let src = 1;
-                let res = (Wrapping(dst) - Wrapping(src)).0;
-
-                // The CF flag is not affected. The OF, SF, ZF, AF,
-                // and PF flags are set according to the result.
-                self.regs.flags.set_overflow_sub_u16(res, src, dst);
-                self.regs.flags.set_sign_u16(res);
-                self.regs.flags.set_zero_u16(res);
-                self.regs.flags.set_adjust(res, src, dst);
-                self.regs.flags.set_parity(res);
-
-                self.write_parameter_u16(&mut hw.mmu, op.segment_prefix, &op.params.dst, res as u16);
+                let res = self.dec16(dst);
+                self.write_parameter_u16(&mut hw.mmu, op.segment_prefix, &op.params.dst, res);
             }
             Op::Dec32 => {
                 // single parameter (dst)
@@ -461,13 +703,13 @@ impl CPU {
                 let ax = self.get_r16(R::AX) as u16;
                 let op1 = self.read_parameter_value(&hw.mmu, &op.params.dst) as u16;
                 if op1 == 0 {
-                    return self.exception(&Exception::DIV0, 0);
+                    return self.exception(&mut hw, Exception::DIV0, 0);
                 }
                 let quotient = ax / op1;
                 let remainder = (ax % op1) as u8;
                 let quo8 = (quotient & 0xFF) as u8;
                 if quotient > 0xFF {
-                    return self.exception(&Exception::DIV0, 0);
+                    return self.exception(&mut hw, Exception::DIV0, 0);
                 }
                 self.set_r8(R::AH, remainder);
                 self.set_r8(R::AL, quo8);
@@ -478,13 +720,13 @@ impl CPU {
                 let num = (u32::from(self.get_r16(R::DX)) << 16) + u32::from(self.get_r16(R::AX)); // DX:AX
                 let op1 = self.read_parameter_value(&hw.mmu, &op.params.dst) as u32;
                 if op1 == 0 {
-                    return self.exception(&Exception::DIV0, 0);
+                    return self.exception(&mut hw, Exception::DIV0, 0);
                 }
                 let remainder = (num % op1) as u16;
                 let quotient = num / op1;
                 let quo16 = (quotient & 0xFFFF) as u16;
                 if quotient != u32::from(quo16) {
-                    return self.exception(&Exception::DIV0, 0);
+                    return self.exception(&mut hw, Exception::DIV0, 0);
                 }
                 self.set_r16(R::DX, remainder);
                 self.set_r16(R::AX, quo16);
@@ -495,13 +737,13 @@ impl CPU {
                 let num = (u64::from(self.get_r32(R::EDX)) << 32) + u64::from(self.get_r32(R::EAX)); // EDX:EAX
                 let op1 = self.read_parameter_value(&hw.mmu, &op.params.dst) as u64;
                 if op1 == 0 {
-                    return self.exception(&Exception::DIV0, 0);
+                    return self.exception(&mut hw, Exception::DIV0, 0);
                 }
                 let remainder = (num % op1) as u32;
                 let quotient = num / op1;
-                let quo32 = (quotient & 0xFFFF) as u32;
+                let quo32 = (quotient & 0xFFFF_FFFF) as u32;
                 if quotient != u64::from(quo32) {
-                    return self.exception(&Exception::DIV0, 0);
+                    return self.exception(&mut hw, Exception::DIV0, 0);
                 }
                 self.set_r32(R::EDX, remainder);
                 self.set_r32(R::EAX, quo32);
@@ -510,45 +752,68 @@ impl CPU {
             Op::Enter => {
                 // Make Stack Frame for Procedure Parameters
                 // Create a stack frame with optional nested pointers for a procedure.
+                // a 32-bit stack segment uses ESP and EBP
                 // XXX test this
-                let alloc_size = self.read_parameter_value(&hw.mmu, &op.params.dst) as u16;
+                let alloc_size = self.read_parameter_value(&hw.mmu, &op.params.dst) as u32;
                 let mut nesting_level = self.read_parameter_value(&hw.mmu, &op.params.src);
 
                 nesting_level &= 0x1F; // XXX "mod 32" says docs
-                let bp = self.get_r16(R::BP);
-                self.push16(&mut hw.mmu, bp);
-                let frame_temp = self.get_r16(R::SP);
+                let op32 = op.op_size == OperandSize::_32bit;
+                let width = if op32 { 4 } else { 2 };
+                let mut bp = if self.stack32() { self.get_r32(R::EBP) } else { u32::from(self.get_r16(R::BP)) };
+                if op32 {
+                    self.push32(&mut hw.mmu, bp);
+                } else {
+                    self.push16(&mut hw.mmu, bp as u16);
+                }
+                let frame_temp = self.stack_pointer();
 
                 if nesting_level != 0 {
                     for i in 0..nesting_level {
-                        let bp = self.get_r16(R::BP) - 2;
-                        self.set_r16(R::BP, bp);
-                        let val = hw.mmu.read_u16(self.get_r16(R::SS), self.get_r16(R::BP));
-                        println!("XXX ENTER: pushing {} = {:04X}", i, val);
-                        self.push16(&mut hw.mmu, val);
+                        bp = bp.wrapping_sub(width);
+                        if op32 {
+                            let val = self.read_mem_u32(&hw.mmu, R::SS, bp);
+                            println!("XXX ENTER: pushing {} = {:08X}", i, val);
+                            self.push32(&mut hw.mmu, val);
+                        } else {
+                            let val = self.read_mem_u16(&hw.mmu, R::SS, bp & 0xFFFF);
+                            println!("XXX ENTER: pushing {} = {:04X}", i, val);
+                            self.push16(&mut hw.mmu, val);
+                        }
+                    }
+                    if op32 {
+                        self.push32(&mut hw.mmu, frame_temp);
+                    } else {
+                        self.push16(&mut hw.mmu, frame_temp as u16);
                     }
-                    self.push16(&mut hw.mmu, frame_temp);
                 }
 
-                self.set_r16(R::BP, frame_temp);
-                let sp = self.get_r16(R::SP) - alloc_size;
-                self.set_r16(R::SP, sp);
+                if self.stack32() {
+                    self.set_r32(R::EBP, frame_temp);
+                } else {
+                    self.set_r16(R::BP, frame_temp as u16);
+                }
+                let sp = self.stack_pointer().wrapping_sub(alloc_size);
+                self.set_stack_pointer(sp);
             }
             Op::Hlt => {
-                // println!("XXX impl {}", op);
-                // self.fatal_error = true;
+                if self.cpl() != 0 {
+                    return self.exception(&mut hw, Exception::GP, 0);
+                }
+                self.halted = true;
             }
             Op::Idiv8 => {
                 let ax = self.get_r16(R::AX) as i16; // dividend
                 let op1 = self.read_parameter_value(&hw.mmu, &op.params.dst) as i8;
                 if op1 == 0 {
-                    return self.exception(&Exception::DIV0, 0);
+                    return self.exception(&mut hw, Exception::DIV0, 0);
                 }
                 let rem = (ax % i16::from(op1)) as i8;
                 let quo = ax / i16::from(op1);
                 let quo8s = (quo & 0xFF) as i8;
-                if quo != i16::from(quo8s) {
-                    return self.exception(&Exception::DIV0, 0);
+                // the 8086 can't return the most negative quotient
+                if quo != i16::from(quo8s) || (self.model == CpuModel::I8086 && quo8s == i8::MIN) {
+                    return self.exception(&mut hw, Exception::DIV0, 0);
                 }
                 self.set_r8(R::AL, quo as u8);
                 self.set_r8(R::AH, rem as u8);
@@ -558,13 +823,13 @@ impl CPU {
                 let dividend = ((u32::from(self.get_r16(R::DX)) << 16) | u32::from(self.get_r16(R::AX))) as i32; // DX:AX
                 let op1 = self.read_parameter_value(&hw.mmu, &op.params.dst) as i16;
                 if op1 == 0 {
-                    return self.exception(&Exception::DIV0, 0);
+                    return self.exception(&mut hw, Exception::DIV0, 0);
                 }
                 let quo = dividend / i32::from(op1);
                 let rem = (dividend % i32::from(op1)) as i16;
                 let quo16s = quo as i16;
-	            if quo != i32::from(quo16s) {
-                    return self.exception(&Exception::DIV0, 0);
+                if quo != i32::from(quo16s) || (self.model == CpuModel::I8086 && quo16s == i16::MIN) {
+                    return self.exception(&mut hw, Exception::DIV0, 0);
                 }
                 self.set_r16(R::AX, quo16s as u16);
                 self.set_r16(R::DX, rem as u16);
@@ -574,13 +839,13 @@ impl CPU {
                 let dividend = ((u64::from(self.get_r32(R::EDX)) << 32) | u64::from(self.get_r32(R::EAX))) as i64; // EDX:EAX
                 let op1 = self.read_parameter_value(&hw.mmu, &op.params.dst) as i32;
                 if op1 == 0 {
-                    return self.exception(&Exception::DIV0, 0);
+                    return self.exception(&mut hw, Exception::DIV0, 0);
                 }
                 let quo = dividend / i64::from(op1);
                 let rem = (dividend % i64::from(op1)) as i32;
                 let quo32s = quo as i32;
-	            if quo != i64::from(quo32s) {
-                    return self.exception(&Exception::DIV0, 0);
+                if quo != i64::from(quo32s) {
+                    return self.exception(&mut hw, Exception::DIV0, 0);
                 }
                 self.set_r32(R::EAX, quo32s as u32);
                 self.set_r32(R::EDX, rem as u32);
@@ -604,6 +869,7 @@ impl CPU {
                     self.regs.flags.carry = true;
                     self.regs.flags.overflow = true;
                 }
+                self.set_mul_flags(ax >> 8, 0x80);
             }
             Op::Imul16 => {
                 match op.params.count() {
@@ -613,6 +879,7 @@ impl CPU {
                         let tmp = (self.get_r16(R::AX) as i16) as isize * a as isize;
                         self.set_r16(R::AX, tmp as u16);
                         self.set_r16(R::DX, (tmp >> 16) as u16);
+                        self.set_mul_flags((tmp >> 16) as u16, 0x8000);
                     }
                     2 => {
                         // IMUL r16, r/m16          : word register ← word register ∗ r/m16.
@@ -696,17 +963,8 @@ impl CPU {
             }
             Op::Inc16 => {
                 let dst = self.read_parameter_value(&hw.mmu, &op.params.dst);
-                let src = 1;
-                let res = (Wrapping(dst) + Wrapping(src)).0;
-
-                // The OF, SF, ZF, AF, and PF flags are set according to the result.
-                self.regs.flags.set_overflow_add_u16(res, src, dst);
-                self.regs.flags.set_sign_u16(res);
-                self.regs.flags.set_zero_u16(res);
-                self.regs.flags.set_adjust(res, src, dst);
-                self.regs.flags.set_parity(res);
-
-                self.write_parameter_u16(&mut hw.mmu, op.segment_prefix, &op.params.dst, res as u16);
+                let res = self.inc16(dst);
+                self.write_parameter_u16(&mut hw.mmu, op.segment_prefix, &op.params.dst, res);
             }
             Op::Inc32 => {
                 let dst = self.read_parameter_value(&hw.mmu, &op.params.dst);
@@ -727,124 +985,35 @@ impl CPU {
                 // The ES segment cannot be overridden with a segment override prefix.
                 let dx = self.get_r16(R::DX);
                 let data = hw.in_u8(dx);
-                hw.mmu.write_u8(self.get_r16(R::ES), self.get_r16(R::DI), data);
-                let di = if !self.regs.flags.direction {
-                    (Wrapping(self.get_r16(R::DI)) + Wrapping(1)).0
-                } else {
-                    (Wrapping(self.get_r16(R::DI)) - Wrapping(1)).0
-                };
-                self.set_r16(R::DI, di);
+                self.write_mem_u8(&mut hw.mmu, R::ES, self.get_address_register(&op.address_size, R::DI), data);
+                self.advance_string_index(op, R::DI, 1);
             }
             Op::Int => {
                 let int = self.read_parameter_imm(&op.params.dst);
-                self.int(&mut hw, int as u8);
-            }
-            Op::Ja => {
-                if !self.regs.flags.carry & !self.regs.flags.zero {
-                    self.regs.ip = self.read_parameter_value(&hw.mmu, &op.params.dst) as u16;
-                }
+                self.software_int(&mut hw, int as u8);
             }
-            Op::Jc => {
-                if self.regs.flags.carry {
-                    self.regs.ip = self.read_parameter_value(&hw.mmu, &op.params.dst) as u16;
+            Op::Ja | Op::Jc | Op::Jg | Op::Jl | Op::Jna | Op::Jnc | Op::Jng | Op::Jnl |
+            Op::Jno | Op::Jns | Op::Jnz | Op::Jo | Op::Jpe | Op::Jpo | Op::Js | Op::Jz => {
+                if self.jump_condition(&op.command) {
+                    self.regs.ip = self.read_parameter_value(&hw.mmu, &op.params.dst) as u32;
                 }
             }
             Op::Jcxz => {
-                if self.get_r16(R::CX) == 0 {
-                    self.regs.ip = self.read_parameter_value(&hw.mmu, &op.params.dst) as u16;
-                }
-            }
-            Op::Jg => {
-                if !self.regs.flags.zero & self.regs.flags.sign == self.regs.flags.overflow {
-                    self.regs.ip = self.read_parameter_value(&hw.mmu, &op.params.dst) as u16;
-                }
-            }
-            Op::Jl => {
-                if self.regs.flags.sign != self.regs.flags.overflow {
-                    self.regs.ip = self.read_parameter_value(&hw.mmu, &op.params.dst) as u16;
+                if self.get_address_register(&op.address_size, R::CX) == 0 {
+                    self.regs.ip = self.read_parameter_value(&hw.mmu, &op.params.dst) as u32;
                 }
             }
             Op::JmpFar => {
-                match op.params.dst {
-                    Parameter::Ptr16Imm(seg, imm) => {
-                        self.set_r16(R::CS, seg);
-                        self.regs.ip = imm;
-                    }
-                    Parameter::Ptr16Amode(seg, ref amode) => {
-                        let seg = self.segment(seg);
-                        self.set_r16(R::CS, seg);
-                        self.regs.ip = self.amode(amode) as u16;
-                    }
-                    Parameter::Ptr16AmodeS8(seg, ref amode, imm) => {
-                        let seg = self.segment(seg);
-                        self.set_r16(R::CS, seg);
-                        self.regs.ip = (self.amode(amode) as isize + imm as isize) as u16;
-                    }
-                    _ => panic!("jmp far with unexpected type {:?}", op.params.dst),
-                }
+                let (seg, offs) = match op.params.dst {
+                    Parameter::Ptr16Imm(seg, offs) => (seg, offs),
+                    // jmp far m16:16, m16:32
+                    _ => self.read_far_pointer(&hw.mmu, &op.params.dst, &op.op_size),
+                };
+                self.far_transfer(&mut hw.mmu, seg, offs, false, op.op_size == OperandSize::_32bit);
             }
             Op::JmpNear | Op::JmpShort => {
-                self.regs.ip = self.read_parameter_value(&hw.mmu, &op.params.dst) as u16;
-            }
-            Op::Jna => {
-                if self.regs.flags.carry | self.regs.flags.zero {
-                    self.regs.ip = self.read_parameter_value(&hw.mmu, &op.params.dst) as u16;
-                }
-            }
-            Op::Jnc => {
-                if !self.regs.flags.carry {
-                    self.regs.ip = self.read_parameter_value(&hw.mmu, &op.params.dst) as u16;
-                }
-            }
-            Op::Jng => {
-                if self.regs.flags.zero | self.regs.flags.sign != self.regs.flags.overflow {
-                    self.regs.ip = self.read_parameter_value(&hw.mmu, &op.params.dst) as u16;
-                }
-            }
-            Op::Jnl => {
-                if self.regs.flags.sign == self.regs.flags.overflow {
-                    self.regs.ip = self.read_parameter_value(&hw.mmu, &op.params.dst) as u16;
-                }
-            }
-            Op::Jno => {
-                if !self.regs.flags.overflow {
-                    self.regs.ip = self.read_parameter_value(&hw.mmu, &op.params.dst) as u16;
-                }
-            }
-            Op::Jns => {
-                if !self.regs.flags.sign {
-                    self.regs.ip = self.read_parameter_value(&hw.mmu, &op.params.dst) as u16;
-                }
-            }
-            Op::Jnz => {
-                if !self.regs.flags.zero {
-                    self.regs.ip = self.read_parameter_value(&hw.mmu, &op.params.dst) as u16;
-                }
-            }
-            Op::Jo => {
-                if self.regs.flags.overflow {
-                    self.regs.ip = self.read_parameter_value(&hw.mmu, &op.params.dst) as u16;
-                }
-            }
-            Op::Jpe => {
-                if self.regs.flags.parity {
-                    self.regs.ip = self.read_parameter_value(&hw.mmu, &op.params.dst) as u16;
-                }
-            }
-            Op::Jpo => {
-                 if !self.regs.flags.parity {
-                    self.regs.ip = self.read_parameter_value(&hw.mmu, &op.params.dst) as u16;
-                }
-            }
-            Op::Js => {
-                if self.regs.flags.sign {
-                    self.regs.ip = self.read_parameter_value(&hw.mmu, &op.params.dst) as u16;
-                }
-            }
-            Op::Jz => {
-                if self.regs.flags.zero {
-                    self.regs.ip = self.read_parameter_value(&hw.mmu, &op.params.dst) as u16;
-                }
+                // targets of 16-bit operands are truncated to 16 bits
+                self.regs.ip = self.read_parameter_value(&hw.mmu, &op.params.dst) as u32;
             }
             Op::Lahf => {
                 // Load: AH ← EFLAGS(SF:ZF:0:AF:0:PF:1:CF).
@@ -868,99 +1037,170 @@ impl CPU {
                 self.set_r8(R::AH, val);
             }
             Op::Lds => {
-                // Load DS:r16 with far pointer from memory.
-                let (segment, offset) = self.read_segment_selector(&hw.mmu, &op.params.src);
-                self.set_r16(R::DS, segment);
-                self.write_parameter_u16(&mut hw.mmu, op.segment_prefix, &op.params.dst, offset);
+                // Load DS:r16 with a m16:16 far pointer, or DS:r32 with a m16:32 far pointer.
+                let (segment, offset) = self.read_far_pointer(&hw.mmu, &op.params.src, &op.op_size);
+                if self.load_segment(&mut hw.mmu, R::DS, segment) {
+                    match op.op_size {
+                        OperandSize::_16bit => self.write_parameter_u16(&mut hw.mmu, op.segment_prefix, &op.params.dst, offset as u16),
+                        OperandSize::_32bit => self.write_parameter_u32(&mut hw.mmu, op.segment_prefix, &op.params.dst, offset),
+                    }
+                }
             }
             Op::Lea16 => {
                 let src = self.read_parameter_address(&op.params.src) as u16;
                 self.write_parameter_u16(&mut hw.mmu, op.segment_prefix, &op.params.dst, src);
             }
+            Op::Lea32 => {
+                let (_, src) = self.memory_address(&op.params.src);
+                self.write_parameter_u32(&mut hw.mmu, op.segment_prefix, &op.params.dst, src);
+            }
             Op::Leave => {
                 // High Level Procedure Exit
-                // Set SP to BP, then pop BP.
+                // Set SP to BP, then pop BP. a 32-bit stack segment uses ESP and EBP
                 // XXX test this
-                let bp = self.get_r16(R::BP);
-                self.set_r16(R::SP, bp);
-                let bp = self.pop16(&mut hw.mmu);
-                self.set_r16(R::BP, bp);
+                let bp = if self.stack32() { self.get_r32(R::EBP) } else { u32::from(self.get_r16(R::BP)) };
+                self.set_stack_pointer(bp);
+                match op.op_size {
+                    OperandSize::_16bit => {
+                        let bp = self.pop16(&mut hw.mmu);
+                        self.set_r16(R::BP, bp);
+                    }
+                    OperandSize::_32bit => {
+                        let ebp = self.pop32(&mut hw.mmu);
+                        self.set_r32(R::EBP, ebp);
+                    }
+                }
             }
             Op::Les => {
-                // Load ES:r16 with far pointer from memory.
-                let (segment, offset) = self.read_segment_selector(&hw.mmu, &op.params.src);
-                self.set_r16(R::ES, segment);
-                self.write_parameter_u16(&mut hw.mmu, op.segment_prefix, &op.params.dst, offset);
+                // Load ES:r16 with a m16:16 far pointer, or ES:r32 with a m16:32 far pointer.
+                let (segment, offset) = self.read_far_pointer(&hw.mmu, &op.params.src, &op.op_size);
+                if self.load_segment(&mut hw.mmu, R::ES, segment) {
+                    match op.op_size {
+                        OperandSize::_16bit => self.write_parameter_u16(&mut hw.mmu, op.segment_prefix, &op.params.dst, offset as u16),
+                        OperandSize::_32bit => self.write_parameter_u32(&mut hw.mmu, op.segment_prefix, &op.params.dst, offset),
+                    }
+                }
+            }
+            Op::Lfs | Op::Lgs | Op::Lss => {
+                // Load FS/GS/SS:r16 with a m16:16 far pointer, or FS/GS/SS:r32 with a m16:32 far pointer.
+                let r = match op.command {
+                    Op::Lfs => R::FS,
+                    Op::Lgs => R::GS,
+                    _ => R::SS,
+                };
+                let (segment, offset) = self.read_far_pointer(&hw.mmu, &op.params.src, &op.op_size);
+                if self.load_segment(&mut hw.mmu, r, segment) {
+                    match op.op_size {
+                        OperandSize::_16bit => self.write_parameter_u16(&mut hw.mmu, op.segment_prefix, &op.params.dst, offset as u16),
+                        OperandSize::_32bit => self.write_parameter_u32(&mut hw.mmu, op.segment_prefix, &op.params.dst, offset),
+                    }
+                }
+            }
+            Op::Lgdt | Op::Lidt => {
+                if !op.params.dst.is_ptr() {
+                    return self.exception(&mut hw, Exception::UD, 0);
+                }
+                if self.cpl() != 0 {
+                    return self.exception(&mut hw, Exception::GP, 0);
+                }
+                let (seg, off) = self.memory_address(&op.params.dst);
+                let limit = self.read_mem_u16(&hw.mmu, seg, off);
+                let mut base = self.read_mem_u32(&hw.mmu, seg, off.wrapping_add(2));
+                if op.op_size == OperandSize::_16bit {
+                    // the 80286 has a 24-bit base
+                    base &= 0x00FF_FFFF;
+                }
+                let table = DescriptorTable { base, limit: u32::from(limit) };
+                if op.command == Op::Lgdt {
+                    self.gdtr = table;
+                } else {
+                    self.idtr = table;
+                }
+            }
+            Op::Lldt => {
+                if !self.protected_mode() {
+                    return self.exception(&mut hw, Exception::UD, 0);
+                }
+                if self.cpl() != 0 {
+                    return self.exception(&mut hw, Exception::GP, 0);
+                }
+                let selector = self.read_parameter_value(&hw.mmu, &op.params.dst) as u16;
+                if let Err((which, error)) = self.load_ldt(&hw.mmu, selector) {
+                    return self.exception(&mut hw, which, error);
+                }
+            }
+            Op::Lmsw => {
+                if self.cpl() != 0 {
+                    return self.exception(&mut hw, Exception::GP, 0);
+                }
+                // loads PE, MP, EM and TS. PE can be set but not cleared
+                let msw = self.read_parameter_value(&hw.mmu, &op.params.dst) as u32;
+                let cr0 = (self.cr0 & !0xF) | (msw & 0xF) | (self.cr0 & CR0_PE);
+                self.set_cr0(&mut hw.mmu, cr0);
             }
             Op::Lodsb => {
                 // no arguments
                 // Load byte at address DS:(E)SI into AL.
                 // The DS segment may be over-ridden with a segment override prefix.
-                let val = hw.mmu.read_u8(self.segment(op.segment_prefix), self.get_r16(R::SI));
+                let val = self.read_mem_u8(&hw.mmu, op.segment_prefix.as_register(), self.get_address_register(&op.address_size, R::SI));
 
                 self.set_r8(R::AL, val);
-                let si = if !self.regs.flags.direction {
-                    (Wrapping(self.get_r16(R::SI)) + Wrapping(1)).0
-                } else {
-                    (Wrapping(self.get_r16(R::SI)) - Wrapping(1)).0
-                };
-                self.set_r16(R::SI, si);
+                self.advance_string_index(op, R::SI, 1);
             }
             Op::Lodsw => {
                 // no arguments
                 // Load word at address DS:(E)SI into AX.
                 // The DS segment may be over-ridden with a segment override prefix.
-                let val = hw.mmu.read_u16(self.segment(op.segment_prefix), self.get_r16(R::SI));
+                let val = self.read_mem_u16(&hw.mmu, op.segment_prefix.as_register(), self.get_address_register(&op.address_size, R::SI));
 
                 self.set_r16(R::AX, val);
-                let si = if !self.regs.flags.direction {
-                    (Wrapping(self.get_r16(R::SI)) + Wrapping(2)).0
-                } else {
-                    (Wrapping(self.get_r16(R::SI)) - Wrapping(2)).0
-                };
-                self.set_r16(R::SI, si);
+                self.advance_string_index(op, R::SI, 2);
             }
             Op::Lodsd => {
                 // no arguments
                 // Load dword at address DS:(E)SI into EAX.
                 // The DS segment may be over-ridden with a segment override prefix.
-                let val = hw.mmu.read_u32(self.segment(op.segment_prefix), self.get_r16(R::SI));
+                let val = self.read_mem_u32(&hw.mmu, op.segment_prefix.as_register(), self.get_address_register(&op.address_size, R::SI));
 
                 self.set_r32(R::EAX, val);
-                let si = if !self.regs.flags.direction {
-                    (Wrapping(self.get_r16(R::SI)) + Wrapping(4)).0
-                } else {
-                    (Wrapping(self.get_r16(R::SI)) - Wrapping(4)).0
-                };
-                self.set_r16(R::SI, si);
+                self.advance_string_index(op, R::SI, 4);
             }
             Op::Loop => {
                 // Decrement count; jump short if count ≠ 0.
-                let dst = self.read_parameter_value(&hw.mmu, &op.params.dst) as u16;
-                let cx = (Wrapping(self.get_r16(R::CX)) - Wrapping(1)).0;
-                self.set_r16(R::CX, cx);
+                let dst = self.read_parameter_value(&hw.mmu, &op.params.dst) as u32;
+                let cx = self.decrement_counter(op);
                 if cx != 0 {
                     self.regs.ip = dst;
                 }
             }
             Op::Loope => {
                 // Decrement count; jump short if count ≠ 0 and ZF = 1.
-                let dst = self.read_parameter_value(&hw.mmu, &op.params.dst) as u16;
-                let cx = (Wrapping(self.get_r16(R::CX)) - Wrapping(1)).0;
-                self.set_r16(R::CX, cx);
+                let dst = self.read_parameter_value(&hw.mmu, &op.params.dst) as u32;
+                let cx = self.decrement_counter(op);
                 if cx != 0 && self.regs.flags.zero {
                     self.regs.ip = dst;
                 }
             }
             Op::Loopne => {
                 // Decrement count; jump short if count ≠ 0 and ZF = 0.
-                let dst = self.read_parameter_value(&hw.mmu, &op.params.dst) as u16;
-                let cx = (Wrapping(self.get_r16(R::CX)) - Wrapping(1)).0;
-                self.set_r16(R::CX, cx);
+                let dst = self.read_parameter_value(&hw.mmu, &op.params.dst) as u32;
+                let cx = self.decrement_counter(op);
                 if cx != 0 && !self.regs.flags.zero {
                     self.regs.ip = dst;
                 }
             } 
+            Op::Ltr => {
+                if !self.protected_mode() {
+                    return self.exception(&mut hw, Exception::UD, 0);
+                }
+                if self.cpl() != 0 {
+                    return self.exception(&mut hw, Exception::GP, 0);
+                }
+                let selector = self.read_parameter_value(&hw.mmu, &op.params.dst) as u16;
+                if let Err((which, error)) = self.load_task_register(&mut hw.mmu, selector) {
+                    return self.exception(&mut hw, which, error);
+                }
+            }
             Op::Mov8 => {
                 // two arguments (dst=reg)
                 let data = self.read_parameter_value(&hw.mmu, &op.params.src) as u8;
@@ -979,62 +1219,29 @@ impl CPU {
             Op::Movsb => {
                 // move byte from address DS:(E)SI to ES:(E)DI.
                 // The DS segment may be overridden with a segment override prefix, but the ES segment cannot be overridden.
-                let val = hw.mmu.read_u8(self.segment(op.segment_prefix), self.get_r16(R::SI));
-                let si = if !self.regs.flags.direction {
-                    (Wrapping(self.get_r16(R::SI)) + Wrapping(1)).0
-                } else {
-                    (Wrapping(self.get_r16(R::SI)) - Wrapping(1)).0
-                };
-                self.set_r16(R::SI, si);
-                let es = self.get_r16(R::ES);
-                let di = self.get_r16(R::DI);
-                hw.mmu.write_u8(es, di, val);
-                let di = if !self.regs.flags.direction {
-                    (Wrapping(self.get_r16(R::DI)) + Wrapping(1)).0
-                } else {
-                    (Wrapping(self.get_r16(R::DI)) - Wrapping(1)).0
-                };
-                self.set_r16(R::DI, di);
+                let val = self.read_mem_u8(&hw.mmu, op.segment_prefix.as_register(), self.get_address_register(&op.address_size, R::SI));
+                self.advance_string_index(op, R::SI, 1);
+                let di = self.get_address_register(&op.address_size, R::DI);
+                self.write_mem_u8(&mut hw.mmu, R::ES, di, val);
+                self.advance_string_index(op, R::DI, 1);
             }
             Op::Movsw => {
                 // move word from address DS:(E)SI to ES:(E)DI.
                 // The DS segment may be overridden with a segment override prefix, but the ES segment cannot be overridden.
-                let val = hw.mmu.read_u16(self.segment(op.segment_prefix), self.get_r16(R::SI));
-                let si = if !self.regs.flags.direction {
-                    (Wrapping(self.get_r16(R::SI)) + Wrapping(2)).0
-                } else {
-                    (Wrapping(self.get_r16(R::SI)) - Wrapping(2)).0
-                };
-                self.set_r16(R::SI, si);
-                let es = self.get_r16(R::ES);
-                let di = self.get_r16(R::DI);
-                hw.mmu.write_u16(es, di, val);
-                let di = if !self.regs.flags.direction {
-                    (Wrapping(self.get_r16(R::DI)) + Wrapping(2)).0
-                } else {
-                    (Wrapping(self.get_r16(R::DI)) - Wrapping(2)).0
-                };
-                self.set_r16(R::DI, di);
+                let val = self.read_mem_u16(&hw.mmu, op.segment_prefix.as_register(), self.get_address_register(&op.address_size, R::SI));
+                self.advance_string_index(op, R::SI, 2);
+                let di = self.get_address_register(&op.address_size, R::DI);
+                self.write_mem_u16(&mut hw.mmu, R::ES, di, val);
+                self.advance_string_index(op, R::DI, 2);
             }
             Op::Movsd => {
                 // move dword from address DS:(E)SI to ES:(E)DI
                 // The DS segment may be overridden with a segment override prefix, but the ES segment cannot be overridden.
-                let val = hw.mmu.read_u32(self.segment(op.segment_prefix), self.get_r16(R::SI));
-                let si = if !self.regs.flags.direction {
-                    (Wrapping(self.get_r16(R::SI)) + Wrapping(4)).0
-                } else {
-                    (Wrapping(self.get_r16(R::SI)) - Wrapping(4)).0
-                };
-                self.set_r16(R::SI, si);
-                let es = self.get_r16(R::ES);
-                let di = self.get_r16(R::DI);
-                hw.mmu.write_u32(es, di, val);
-                let di = if !self.regs.flags.direction {
-                    (Wrapping(self.get_r16(R::DI)) + Wrapping(4)).0
-                } else {
-                    (Wrapping(self.get_r16(R::DI)) - Wrapping(4)).0
-                };
-                self.set_r16(R::DI, di);
+                let val = self.read_mem_u32(&hw.mmu, op.segment_prefix.as_register(), self.get_address_register(&op.address_size, R::SI));
+                self.advance_string_index(op, R::SI, 4);
+                let di = self.get_address_register(&op.address_size, R::DI);
+                self.write_mem_u32(&mut hw.mmu, R::ES, di, val);
+                self.advance_string_index(op, R::DI, 4);
             }
             Op::Movsx16 => {
                 // 80386+
@@ -1053,6 +1260,12 @@ impl CPU {
                 // 80386+
                 // moves a signed value into a register and sign-extends it with 1.
                 // two arguments (dst=reg)
+                if is_word_operand(&op.params.src) {
+                    // movsx r32, r/m16
+                    let src = self.read_parameter_value(&hw.mmu, &op.params.src) as u16;
+                    self.write_parameter_u32(&mut hw.mmu, op.segment_prefix, &op.params.dst, src as i16 as u32);
+                    return;
+                }
                 let src = self.read_parameter_value(&hw.mmu, &op.params.src) as u8;
 
                 let mut data = u32::from(src);
@@ -1066,6 +1279,12 @@ impl CPU {
                 // 80386+
                 // moves an unsigned value into a register and zero-extends it with zero.
                 // two arguments (dst=reg)
+                if is_word_operand(&op.params.src) {
+                    // movzx r16, r/m16
+                    let src = self.read_parameter_value(&hw.mmu, &op.params.src) as u16;
+                    self.write_parameter_u16(&mut hw.mmu, op.segment_prefix, &op.params.dst, src);
+                    return;
+                }
                 let src = self.read_parameter_value(&hw.mmu, &op.params.src) as u8;
                 let mut data = u16::from(src);
                 if src & 0x80 != 0 {
@@ -1077,6 +1296,12 @@ impl CPU {
                 // 80386+
                 // moves an unsigned value into a register and zero-extends it with zero.
                 // two arguments (dst=reg)
+                if is_word_operand(&op.params.src) {
+                    // movzx r32, r/m16
+                    let src = self.read_parameter_value(&hw.mmu, &op.params.src) as u16;
+                    self.write_parameter_u32(&mut hw.mmu, op.segment_prefix, &op.params.dst, u32::from(src));
+                    return;
+                }
                 let src = self.read_parameter_value(&hw.mmu, &op.params.src) as u8;
                 let mut data = u32::from(src);
                 if src & 0x80 != 0 {
@@ -1100,6 +1325,7 @@ impl CPU {
                     self.regs.flags.carry = false;
                     self.regs.flags.overflow = false;
                 }
+                self.set_mul_flags(ax >> 8, 0x80);
             }
             Op::Mul16 => {
                 // Unsigned multiply (DX:AX ← AX ∗ r/m16).
@@ -1113,6 +1339,7 @@ impl CPU {
 
                 self.regs.flags.carry = dx != 0;
                 self.regs.flags.overflow = dx != 0;
+                self.set_mul_flags(dx, 0x8000);
             }
             Op::Mul32 => {
                 // Unsigned multiply (EDX:EAX ← EAX ∗ r/m32)
@@ -1231,28 +1458,18 @@ impl CPU {
             Op::Outsb => {
                 // Output byte from memory location specified in DS:(E)SI or RSI to I/O port specified in DX.
                 // no arguments
-                let val = hw.mmu.read_u8(self.segment(op.segment_prefix), self.get_r16(R::SI));
+                let val = self.read_mem_u8(&hw.mmu, op.segment_prefix.as_register(), self.get_address_register(&op.address_size, R::SI));
                 let port = self.get_r16(R::DX);
                 hw.out_u8(port, val);
-                let si = if !self.regs.flags.direction {
-                    (Wrapping(self.get_r16(R::SI)) + Wrapping(1)).0
-                } else {
-                    (Wrapping(self.get_r16(R::SI)) - Wrapping(1)).0
-                };
-                self.set_r16(R::SI, si);
+                self.advance_string_index(op, R::SI, 1);
             }
             Op::Outsw => {
                 // Output word from memory location specified in DS:(E)SI or RSI to I/O port specified in DX**.
                 // no arguments
-                let val = hw.mmu.read_u16(self.segment(op.segment_prefix), self.get_r16(R::SI));
+                let val = self.read_mem_u16(&hw.mmu, op.segment_prefix.as_register(), self.get_address_register(&op.address_size, R::SI));
                 let port = self.get_r16(R::DX);
                 hw.out_u16(port, val);
-                let si = if !self.regs.flags.direction {
-                    (Wrapping(self.get_r16(R::SI)) + Wrapping(2)).0
-                } else {
-                    (Wrapping(self.get_r16(R::SI)) - Wrapping(2)).0
-                };
-                self.set_r16(R::SI, si);
+                self.advance_string_index(op, R::SI, 2);
             }
             Op::Pop16 => {
                 // one arguments (dst)
@@ -1271,8 +1488,8 @@ impl CPU {
                 self.set_r16(R::SI, si);
                 let bp = self.pop16(&mut hw.mmu);
                 self.set_r16(R::BP, bp);
-                let sp = self.get_r16(R::SP) + 2; // skip next word of stack
-                self.set_r16(R::SP, sp);
+                let sp = self.stack_pointer().wrapping_add(2); // skip next word of stack
+                self.set_stack_pointer(sp);
                 let bx = self.pop16(&mut hw.mmu);
                 self.set_r16(R::BX, bx);
                 let dx = self.pop16(&mut hw.mmu);
@@ -1289,8 +1506,8 @@ impl CPU {
                 self.set_r32(R::ESI, esi);
                 let ebp = self.pop32(&mut hw.mmu);
                 self.set_r32(R::EBP, ebp);
-                let esp = self.get_r32(R::ESP) + 4; // skip next dword of stack
-                self.set_r32(R::ESP, esp);
+                let esp = self.stack_pointer().wrapping_add(4); // skip next dword of stack
+                self.set_stack_pointer(esp);
                 let ebx = self.pop32(&mut hw.mmu);
                 self.set_r32(R::EBX, ebx);
                 let edx = self.pop32(&mut hw.mmu);
@@ -1301,12 +1518,20 @@ impl CPU {
                 self.set_r32(R::EAX, eax);
             }
             Op::Popf => {
-                let data = self.pop16(&mut hw.mmu);
-                self.regs.flags.set_u16(data);
+                // XXX the upper half of EFLAGS is not kept
+                let data = match op.op_size {
+                    OperandSize::_16bit => self.pop16(&mut hw.mmu),
+                    OperandSize::_32bit => self.pop32(&mut hw.mmu) as u16,
+                };
+                self.set_flags_u16(data);
             }
             Op::Push16 => {
                 // single parameter (dst)
-                let data = self.read_parameter_value(&hw.mmu, &op.params.dst) as u16;
+                let data = match op.params.dst {
+                    // the 8086 and 80186 push the decremented stack pointer
+                    Parameter::Reg16(R::SP) if self.model < CpuModel::I80286 => self.get_r16(R::SP).wrapping_sub(2),
+                    _ => self.read_parameter_value(&hw.mmu, &op.params.dst) as u16,
+                };
                 self.push16(&mut hw.mmu, data);
             }
             Op::Push32 => {
@@ -1351,13 +1576,16 @@ impl CPU {
                 self.push32(&mut hw.mmu, edi);
             }
             Op::Pushf => {
-                let data = self.regs.flags.u16();
-                self.push16(&mut hw.mmu, data);
+                let data = self.flags_u16();
+                match op.op_size {
+                    OperandSize::_16bit => self.push16(&mut hw.mmu, data),
+                    OperandSize::_32bit => self.push32(&mut hw.mmu, u32::from(data)),
+                }
             }
             Op::Rcl8 => {
                 // Rotate 9 bits (CF, r/m8) left imm8 times.
                 // two arguments
-                let mut count = (self.read_parameter_value(&hw.mmu, &op.params.src) & 0x1F) % 9;
+                let mut count = self.shift_count(&hw.mmu, &op.params.src) % 9;
                 if count > 0 {
                     let cf = self.regs.flags.carry_val() as u16;
                     let op1 = self.read_parameter_value(&hw.mmu, &op.params.dst) as u16;
@@ -1377,7 +1605,7 @@ impl CPU {
                 // Rotate 9 bits (CF, r/m8) left imm8 times.
                 // two arguments
                 let op1 = self.read_parameter_value(&hw.mmu, &op.params.dst) as u16;
-                let count = (self.read_parameter_value(&hw.mmu, &op.params.src) & 0x1F) % 17;
+                let count = self.shift_count(&hw.mmu, &op.params.src) % 17;
                 if count > 0 {
                     let cf = self.regs.flags.carry_val() as u16;
                     let res = if count == 1 {
@@ -1395,7 +1623,7 @@ impl CPU {
             Op::Rcr8 => {
                 // two arguments
                 // rotate 9 bits right `op1` times
-                let mut count = self.read_parameter_value(&hw.mmu, &op.params.src) as u16/* & 0x1F*/;
+                let mut count = self.shift_count(&hw.mmu, &op.params.src) as u16;
                 if count % 9 != 0 {
                     count %= 9;
                     let cf = self.regs.flags.carry_val() as u16;
@@ -1412,7 +1640,7 @@ impl CPU {
                 // two arguments
                 // rotate 9 bits right `op1` times
                 let op1 = self.read_parameter_value(&hw.mmu, &op.params.dst);
-                let count = (self.read_parameter_value(&hw.mmu, &op.params.src) as u32 & 0x1F) % 17;
+                let count = self.shift_count(&hw.mmu, &op.params.src) as u32 % 17;
                 if count > 0 {
                     let cf = self.regs.flags.carry_val();
                     let res = (op1 >> count) | (cf << (16 - count)) | (op1 << (17 - count));
@@ -1439,40 +1667,49 @@ impl CPU {
                 }
             }
             Op::Iret => {
-                self.regs.ip = self.pop16(&mut hw.mmu);
-                let cs = self.pop16(&mut hw.mmu);
-                self.set_r16(R::CS, cs);
-                let flags = self.pop16(&mut hw.mmu);
-                self.regs.flags.set_u16(flags);
+                let op32 = op.op_size == OperandSize::_32bit;
+                let (ip, cs) = self.pop_return_address(&mut hw.mmu, op32);
+                // XXX the upper half of EFLAGS is not kept
+                let flags = if op32 {
+                    self.pop32(&mut hw.mmu) as u16
+                } else {
+                    self.pop16(&mut hw.mmu)
+                };
+                if self.far_return(&mut hw.mmu, cs, ip, 0, op32) {
+                    self.set_flags_u16(flags);
+                }
                 hw.bios.flags_address = MemoryAddress::Unset;
             }
             Op::Retf => {
-                if op.params.count() == 1 {
-                    // 1 argument: pop imm16 bytes from stack
-                    let imm16 = self.read_parameter_value(&hw.mmu, &op.params.dst) as u16;
-                    let sp = self.get_r16(R::SP) + imm16;
-                    self.set_r16(R::SP, sp);
-                }
-                self.regs.ip = self.pop16(&mut hw.mmu);
-                let cs = self.pop16(&mut hw.mmu);
-                self.set_r16(R::CS, cs);
+                let op32 = op.op_size == OperandSize::_32bit;
+                let (ip, cs) = self.pop_return_address(&mut hw.mmu, op32);
+                // 1 argument: pop imm16 bytes from stack
+                let release = if op.params.count() == 1 {
+                    self.read_parameter_value(&hw.mmu, &op.params.dst) as u16
+                } else {
+                    0
+                };
+                self.far_return(&mut hw.mmu, cs, ip, release, op32);
             }
             Op::Retn => {
-                self.regs.ip = self.pop16(&mut hw.mmu);
+                self.regs.ip = match op.op_size {
+                    OperandSize::_16bit => u32::from(self.pop16(&mut hw.mmu)),
+                    OperandSize::_32bit => self.pop32(&mut hw.mmu),
+                };
                 if op.params.count() == 1 {
                     // 1 argument: pop imm16 bytes from stack
-                    let imm16 = self.read_parameter_value(&hw.mmu, &op.params.dst) as u16;
-                    let sp = self.get_r16(R::SP) + imm16;
-                    self.set_r16(R::SP, sp);
+                    let imm16 = self.read_parameter_value(&hw.mmu, &op.params.dst) as u32;
+                    let sp = self.stack_pointer().wrapping_add(imm16);
+                    self.set_stack_pointer(sp);
                 }
             }
             Op::Rol8 => {
                 // Rotate 8 bits of 'dst' left for 'src' times.
                 // two arguments: op1, count
                 let mut op1 = self.read_parameter_value(&hw.mmu, &op.params.dst) as u8;
-                let mut count = self.read_parameter_value(&hw.mmu, &op.params.src);
+                let mut count = self.shift_count(&hw.mmu, &op.params.src);
                 if count & 0b0_0111 == 0 {
-                    if count & 0b1_1000 != 0 {
+                    if count != 0 {
                         let bit0 = op1 & 1;
                         let bit7 = op1 >> 7;
                         self.regs.flags.overflow = bit0 ^ bit7 != 0;
@@ -1493,7 +1730,7 @@ impl CPU {
                 // Rotate 16 bits of 'dst' left for 'src' times.
                 // two arguments
                 let mut res = self.read_parameter_value(&hw.mmu, &op.params.dst) as u16;
-                let count = self.read_parameter_value(&hw.mmu, &op.params.src) & 0x1F;
+                let count = self.shift_count(&hw.mmu, &op.params.src);
                 res = res.rotate_left(count as u32);
                 self.write_parameter_u16(&mut hw.mmu, op.segment_prefix, &op.params.dst, res);
                 let bit0 = res & 1;
@@ -1507,10 +1744,10 @@ impl CPU {
                 // Rotate 8 bits of 'dst' right for 'src' times.
                 // two arguments
                 let op1 = self.read_parameter_value(&hw.mmu, &op.params.dst) as u8;
-                let count = self.read_parameter_value(&hw.mmu, &op.params.src) & 0x1F;
+                let count = self.shift_count(&hw.mmu, &op.params.src);
 
                 if count & 0b0_0111 == 0 {
-                    if count & 0b1_1000 != 0 {
+                    if count != 0 {
                         let bit6 = (op1 >> 6) & 1;
                         let bit7 = op1 >> 7;
                         self.regs.flags.overflow = bit6 ^ bit7 != 0;
@@ -1530,7 +1767,7 @@ impl CPU {
                 // Rotate 16 bits of 'dst' right for 'src' times.
                 // two arguments
                 let mut res = self.read_parameter_value(&hw.mmu, &op.params.dst) as u16;
-                let mut count = self.read_parameter_value(&hw.mmu, &op.params.src) & 0x1F;
+                let mut count = self.shift_count(&hw.mmu, &op.params.src);
                 res = res.rotate_right(count as u32);
                 self.write_parameter_u16(&mut hw.mmu, op.segment_prefix, &op.params.dst, res);
                 let bit14 = (res >> 14) & 1;
@@ -1562,7 +1799,7 @@ impl CPU {
                 // Signed divide r/m8 by 2, imm8 times.
                 // two arguments
                 let op1 = self.read_parameter_value(&hw.mmu, &op.params.dst) as u8;
-                let mut count = self.read_parameter_value(&hw.mmu, &op.params.src) & 0x1F;
+                let mut count = self.shift_count(&hw.mmu, &op.params.src);
                 if count > 0 {
                     if count > 8 {
                         count = 8;
@@ -1586,8 +1823,11 @@ impl CPU {
                 // Signed divide r/m8 by 2, imm8 times.
                 // two arguments
                 let dst = self.read_parameter_value(&hw.mmu, &op.params.dst);
-                let count = self.read_parameter_value(&hw.mmu, &op.params.src) & 0xF;
+                let mut count = self.shift_count(&hw.mmu, &op.params.src);
                 if count > 0 {
+                    if count > 16 {
+                        count = 16;
+                    }
                     let res = if dst & 0x8000 != 0 {
                         let x = 0xFFFF as usize;
                         dst.rotate_right(count as u32) | x.rotate_left(16 - count as u32)
@@ -1662,48 +1902,53 @@ impl CPU {
                 // Compare AL with byte at ES:(E)DI then set status flags.
                 // ES cannot be overridden with a segment override prefix.
                 let src = self.get_r8(R::AL);
-                let dst = hw.mmu.read_u8(self.get_r16(R::ES), self.get_r16(R::DI));
+                let dst = self.read_mem_u8(&hw.mmu, R::ES, self.get_address_register(&op.address_size, R::DI));
                 self.cmp8(dst as usize, src as usize);
-                let di = if !self.regs.flags.direction {
-                    (Wrapping(self.get_r16(R::DI)) + Wrapping(1)).0
-                } else {
-                    (Wrapping(self.get_r16(R::DI)) - Wrapping(1)).0
-                };
-                self.set_r16(R::DI, di);
+                self.advance_string_index(op, R::DI, 1);
             }
             Op::Scasw => {
                 // Compare AX with word at ES:(E)DI or RDI then set status flags.
                 // ES cannot be overridden with a segment override prefix.
                 let src = self.get_r16(R::AX);
-                let dst = hw.mmu.read_u16(self.get_r16(R::ES), self.get_r16(R::DI));
+                let dst = self.read_mem_u16(&hw.mmu, R::ES, self.get_address_register(&op.address_size, R::DI));
                 self.cmp16(dst as usize, src as usize);
-                let di = if !self.regs.flags.direction {
-                    (Wrapping(self.get_r16(R::DI)) + Wrapping(2)).0
-                } else {
-                    (Wrapping(self.get_r16(R::DI)) - Wrapping(2)).0
-                };
-                self.set_r16(R::DI, di);
+                self.advance_string_index(op, R::DI, 2);
             }
-            Op::Setc => {
-                let val = if self.regs.flags.carry {
-                    1
-                } else {
-                    0
-                };
-                self.write_parameter_u8(&mut hw.mmu, &op.params.dst, val);
-            }
-            Op::Setnz => {
-                let val = if !self.regs.flags.zero {
-                    1
-                } else {
-                    0
+            Op::Scasd => {
+                // Compare EAX with dword at ES:(E)DI or RDI then set status flags.
+                // ES cannot be overridden with a segment override prefix.
+                let src = self.get_r32(R::EAX);
+                let dst = self.read_mem_u32(&hw.mmu, R::ES, self.get_address_register(&op.address_size, R::DI));
+                self.cmp32(dst as usize, src as usize);
+                self.advance_string_index(op, R::DI, 4);
+            }
+            Op::Seto | Op::Setno | Op::Setc | Op::Setnc | Op::Setz | Op::Setnz | Op::Setna | Op::Seta |
+            Op::Sets | Op::Setns | Op::Setpe | Op::Setpo | Op::Setl | Op::Setnl | Op::Setng | Op::Setg => {
+                let flags = &self.regs.flags;
+                let condition = match op.command {
+                    Op::Seto => flags.overflow,
+                    Op::Setno => !flags.overflow,
+                    Op::Setc => flags.carry,
+                    Op::Setnc => !flags.carry,
+                    Op::Setz => flags.zero,
+                    Op::Setnz => !flags.zero,
+                    Op::Setna => flags.carry || flags.zero,
+                    Op::Seta => !flags.carry && !flags.zero,
+                    Op::Sets => flags.sign,
+                    Op::Setns => !flags.sign,
+                    Op::Setpe => flags.parity,
+                    Op::Setpo => !flags.parity,
+                    Op::Setl => flags.sign != flags.overflow,
+                    Op::Setnl => flags.sign == flags.overflow,
+                    Op::Setng => flags.zero || flags.sign != flags.overflow,
+                    _ => !flags.zero && flags.sign == flags.overflow,
                 };
-                self.write_parameter_u8(&mut hw.mmu, &op.params.dst, val);
+                self.write_parameter_u8(&mut hw.mmu, &op.params.dst, condition as u8);
             }
             Op::Shl8 => {
                 // Multiply `dst` by 2, `src` times.
                 // two arguments    (alias: sal)
-                let count = self.read_parameter_value(&hw.mmu, &op.params.src) & 0b1_1111;
+                let count = self.shift_count(&hw.mmu, &op.params.src);
                 // XXX differs from dosbox & winxp
                 //if count > 0 {
                     let op1 = self.read_parameter_value(&hw.mmu, &op.params.dst) as u16;
@@ -1733,9 +1978,9 @@ impl CPU {
                 // Multiply `dst` by 2, `src` times.
                 // two arguments    (alias: sal)
                 let dst = self.read_parameter_value(&hw.mmu, &op.params.dst);
-                let count = self.read_parameter_value(&hw.mmu, &op.params.src) & 0x1F;
+                let count = self.shift_count(&hw.mmu, &op.params.src);
                 if count > 0 {
-                    let res = dst.wrapping_shl(count as u32);
+                    let res = dst.checked_shl(count as u32).unwrap_or(0);
                     self.write_parameter_u16(&mut hw.mmu, op.segment_prefix, &op.params.dst, res as u16);
                     self.regs.flags.carry = (res & 0x8000) != 0;
                     if count == 1 {
@@ -1799,11 +2044,11 @@ impl CPU {
                 // Unsigned divide r/m8 by 2, `src` times.
                 // two arguments
                 let dst = self.read_parameter_value(&hw.mmu, &op.params.dst);
-                let count = self.read_parameter_value(&hw.mmu, &op.params.src) & 0x1F;
+                let count = self.shift_count(&hw.mmu, &op.params.src);
                 if count > 0 {
-                    let res = dst.wrapping_shr(count as u32);
+                    let res = dst.checked_shr(count as u32).unwrap_or(0);
                     self.write_parameter_u8(&mut hw.mmu, &op.params.dst, res as u8);
-                    self.regs.flags.carry = (dst.wrapping_shr((count - 1) as u32) & 0x1) != 0;
+                    self.regs.flags.carry = (dst.checked_shr((count - 1) as u32).unwrap_or(0) & 0x1) != 0;
                     self.regs.flags.overflow = dst & 0x80 != 0;
                     self.regs.flags.set_sign_u8(res);
                     self.regs.flags.set_zero_u8(res);
@@ -1821,11 +2066,11 @@ impl CPU {
             Op::Shr16 => {
                 // two arguments
                 let dst = self.read_parameter_value(&hw.mmu, &op.params.dst);
-                let count = self.read_parameter_value(&hw.mmu, &op.params.src) & 0x1F;
+                let count = self.shift_count(&hw.mmu, &op.params.src);
                 if count > 0 {
-                    let res = dst.wrapping_shr(count as u32);
+                    let res = dst.checked_shr(count as u32).unwrap_or(0);
                     self.write_parameter_u16(&mut hw.mmu, op.segment_prefix, &op.params.dst, res as u16);
-                    self.regs.flags.carry = (dst.wrapping_shr((count - 1) as u32) & 0x1) != 0;
+                    self.regs.flags.carry = (dst.checked_shr((count - 1) as u32).unwrap_or(0) & 0x1) != 0;
                     self.regs.flags.overflow = dst & 0x8000 != 0;
                     self.regs.flags.set_sign_u16(res);
                     self.regs.flags.set_zero_u16(res);
@@ -1884,8 +2129,25 @@ impl CPU {
                 // If a shift occurs, the AF flag is undefined. If the count is greater than the operand size,
                 // the flags are undefined.
             }
+            Op::Sgdt | Op::Sidt => {
+                if !op.params.dst.is_ptr() {
+                    return self.exception(&mut hw, Exception::UD, 0);
+                }
+                let table = if op.command == Op::Sgdt { self.gdtr } else { self.idtr };
+                let (seg, off) = self.memory_address(&op.params.dst);
+                self.write_mem_u16(&mut hw.mmu, seg, off, table.limit as u16);
+                self.write_mem_u32(&mut hw.mmu, seg, off.wrapping_add(2), table.base);
+            }
             Op::Sldt => {
-                println!("XXX impl {:?}", op);
+                if !self.protected_mode() {
+                    return self.exception(&mut hw, Exception::UD, 0);
+                }
+                let ldtr = self.ldtr;
+                self.write_parameter_u16(&mut hw.mmu, op.segment_prefix, &op.params.dst, ldtr);
+            }
+            Op::Smsw => {
+                let msw = self.cr0 as u16;
+                self.write_parameter_u16(&mut hw.mmu, op.segment_prefix, &op.params.dst, msw);
             }
             Op::Stc => {
                 self.regs.flags.carry = true;
@@ -1896,51 +2158,39 @@ impl CPU {
             Op::Sti => {
                 self.regs.flags.interrupt = true;
             }
+            Op::Str => {
+                if !self.protected_mode() {
+                    return self.exception(&mut hw, Exception::UD, 0);
+                }
+                let tr = self.tr;
+                self.write_parameter_u16(&mut hw.mmu, op.segment_prefix, &op.params.dst, tr);
+            }
             Op::Stosb => {
                 // no parameters
                 // store AL at ES:(E)DI
                 // The ES segment cannot be overridden with a segment override prefix.
                 let al = self.get_r8(R::AL);
-                let es = self.get_r16(R::ES);
-                let di = self.get_r16(R::DI);
-                hw.mmu.write_u8(es, di, al);
-                let di = if !self.regs.flags.direction {
-                    (Wrapping(self.get_r16(R::DI)) + Wrapping(1)).0
-                } else {
-                    (Wrapping(self.get_r16(R::DI)) - Wrapping(1)).0
-                };
-                self.set_r16(R::DI, di);
+                let di = self.get_address_register(&op.address_size, R::DI);
+                self.write_mem_u8(&mut hw.mmu, R::ES, di, al);
+                self.advance_string_index(op, R::DI, 1);
             }
             Op::Stosw => {
                 // no parameters
                 // store AX at address ES:(E)DI
                 // The ES segment cannot be overridden with a segment override prefix.
                 let ax = self.get_r16(R::AX);
-                let es = self.get_r16(R::ES);
-                let di = self.get_r16(R::DI);
-                hw.mmu.write_u16(es, di, ax);
-                let di = if !self.regs.flags.direction {
-                    (Wrapping(self.get_r16(R::DI)) + Wrapping(2)).0
-                } else {
-                    (Wrapping(self.get_r16(R::DI)) - Wrapping(2)).0
-                };
-                self.set_r16(R::DI, di);
+                let di = self.get_address_register(&op.address_size, R::DI);
+                self.write_mem_u16(&mut hw.mmu, R::ES, di, ax);
+                self.advance_string_index(op, R::DI, 2);
             }
             Op::Stosd => {
                 // no parameters
                 // store EAX at address ES:(E)DI
                 // The ES segment cannot be overridden with a segment override prefix.
                 let eax = self.get_r32(R::EAX);
-                let es = self.get_r16(R::ES);
-                let di = self.get_r16(R::DI);
-                hw.mmu.write_u32(es, di, eax);
-                // XXX adjust DI or EDI ?
-                let di = if !self.regs.flags.direction {
-                    (Wrapping(self.get_r16(R::DI)) + Wrapping(4)).0
-                } else {
-                    (Wrapping(self.get_r16(R::DI)) - Wrapping(4)).0
-                };
-                self.set_r16(R::DI, di);
+                let di = self.get_address_register(&op.address_size, R::DI);
+                self.write_mem_u32(&mut hw.mmu, R::ES, di, eax);
+                self.advance_string_index(op, R::DI, 4);
             }
             Op::Sub8 => {
                 // two parameters (dst=reg)
@@ -2010,6 +2260,53 @@ impl CPU {
                 self.regs.flags.set_zero_u16(res);
                 self.regs.flags.set_parity(res);
             }
+            Op::Verr | Op::Verw => {
+                if !self.protected_mode() {
+                    return self.exception(&mut hw, Exception::UD, 0);
+                }
+                let selector = self.read_parameter_value(&hw.mmu, &op.params.dst) as u16;
+                self.regs.flags.zero = self.verify_segment(&hw.mmu, selector, op.command == Op::Verw);
+            }
+            Op::Xadd8 => {
+                // exchange r8 and r/m8, load the sum into r/m8
+                let src = self.read_parameter_value(&hw.mmu, &op.params.src) as u8;
+                let dst = self.read_parameter_value(&hw.mmu, &op.params.dst) as u8;
+                let res = src as usize + dst as usize;
+                self.regs.flags.set_carry_u8(res);
+                self.regs.flags.set_parity(res);
+                self.regs.flags.set_adjust(res, src as usize, dst as usize);
+                self.regs.flags.set_zero_u8(res);
+                self.regs.flags.set_sign_u8(res);
+                self.regs.flags.set_overflow_add_u8(res, src as usize, dst as usize);
+                self.write_parameter_u8(&mut hw.mmu, &op.params.src, dst);
+                self.write_parameter_u8(&mut hw.mmu, &op.params.dst, res as u8);
+            }
+            Op::Xadd16 => {
+                let src = self.read_parameter_value(&hw.mmu, &op.params.src) as u16;
+                let dst = self.read_parameter_value(&hw.mmu, &op.params.dst) as u16;
+                let res = src as usize + dst as usize;
+                self.regs.flags.set_carry_u16(res);
+                self.regs.flags.set_parity(res);
+                self.regs.flags.set_adjust(res, src as usize, dst as usize);
+                self.regs.flags.set_zero_u16(res);
+                self.regs.flags.set_sign_u16(res);
+                self.regs.flags.set_overflow_add_u16(res, src as usize, dst as usize);
+                self.write_parameter_u16(&mut hw.mmu, op.segment_prefix, &op.params.src, dst);
+                self.write_parameter_u16(&mut hw.mmu, op.segment_prefix, &op.params.dst, res as u16);
+            }
+            Op::Xadd32 => {
+                let src = self.read_parameter_value(&hw.mmu, &op.params.src) as u32;
+                let dst = self.read_parameter_value(&hw.mmu, &op.params.dst) as u32;
+                let res = src as usize + dst as usize;
+                self.regs.flags.set_carry_u32(res);
+                self.regs.flags.set_parity(res);
+                self.regs.flags.set_adjust(res, src as usize, dst as usize);
+                self.regs.flags.set_zero_u32(res);
+                self.regs.flags.set_sign_u32(res);
+                self.regs.flags.set_overflow_add_u32(res, src as usize, dst as usize);
+                self.write_parameter_u32(&mut hw.mmu, op.segment_prefix, &op.params.src, dst);
+                self.write_parameter_u32(&mut hw.mmu, op.segment_prefix, &op.params.dst, res as u32);
+            }
             Op::Xchg8 => {
                 // two parameters (registers)
                 let mut src = self.read_parameter_value(&hw.mmu, &op.params.src);
@@ -2038,7 +2335,8 @@ impl CPU {
                 // no parameters
                 // Set AL to memory byte DS:[(E)BX + unsigned AL].
                 // The DS segment may be overridden with a segment override prefix.
-                let al = hw.mmu.read_u8(self.segment(op.segment_prefix), self.get_r16(R::BX) + u16::from(self.get_r8(R::AL)));
+                let offset = self.get_r16(R::BX).wrapping_add(u16::from(self.get_r8(R::AL)));
+                let al = self.read_mem_u8(&hw.mmu, op.segment_prefix.as_register(), u32::from(offset));
                 self.set_r8(R::AL, al);
             }
             Op::Xor8 => {
@@ -2089,6 +2387,8 @@ impl CPU {
 
                 self.write_parameter_u32(&mut hw.mmu, op.segment_prefix, &op.params.dst, res as u32);
             }
+            _ if op.command.is_fpu() => self.execute_fpu(&mut hw, op),
+            Op::Invalid(_, _) => return self.exception(&mut hw, Exception::UD, 0),
             _ => {
                 let (seg, off) = self.get_address_pair();
                 println!("execute error: unhandled '{}' at {:04X}:{:04X} (flat {:06X})",
@@ -2101,22 +2401,19 @@ impl CPU {
 
         match op.repeat {
             RepeatMode::Rep => {
-                let cx = (Wrapping(self.get_r16(R::CX)) - Wrapping(1)).0;
-                self.set_r16(R::CX, cx);
+                let cx = self.decrement_counter(op);
                 if cx != 0 {
                     self.regs.ip = start_ip;
                 }
             }
             RepeatMode::Repe => {
-                let cx = (Wrapping(self.get_r16(R::CX)) - Wrapping(1)).0;
-                self.set_r16(R::CX, cx);
+                let cx = self.decrement_counter(op);
                 if cx != 0 && self.regs.flags.zero {
                     self.regs.ip = start_ip;
                 }
             }
             RepeatMode::Repne => {
-                let cx = (Wrapping(self.get_r16(R::CX)) - Wrapping(1)).0;
-                self.set_r16(R::CX, cx);
+                let cx = self.decrement_counter(op);
                 if cx != 0 && !self.regs.flags.zero {
                     self.regs.ip = start_ip;
                 }
@@ -2130,16 +2427,668 @@ impl CPU {
         }
     }
 
-    fn exception(&mut self, which: &Exception, error: usize) {
-        /*
-        #define CPU_INT_SOFTWARE    0x1
-        #define CPU_INT_EXCEPTION   0x2
-        #define CPU_INT_HAS_ERROR   0x4
-        #define CPU_INT_NOIOPLCHECK 0x8
-        */
-        println!("Exception {:?}, error {}", which, error);
+    /// raises a CPU exception through the interrupt vector table or the IDT
+    fn exception(&mut self, hw: &mut Hardware, which: Exception, error: u16) {
+        match which {
+            // the 8086/8088 returns to the instruction following the division
+            Exception::DIV0 if self.model == CpuModel::I8086 => {}
+            // faults return to the faulting instruction
+            _ => self.regs.ip = self.instruction_start,
+        }
+        self.deliver_exception(hw, which, error);
+    }
+
+    /// delivers a exception. in protected mode the error code is pushed, and faults
+    /// while delivering it escalate to a double fault
+    fn deliver_exception(&mut self, hw: &mut Hardware, which: Exception, error: u16) {
+        if !self.protected_mode() {
+            return self.int(hw, which as u8);
+        }
+        let error_code = if which.has_error_code() { Some(error) } else { None };
+        self.last_exception = Some(which);
+        if let Err((fault, fault_error)) = self.protected_mode_interrupt(&mut hw.mmu, which as u8, error_code, false) {
+            if which == Exception::DF {
+                // triple fault, the processor shuts down
+                println!("[{:04X}:{:04X}] ERROR: triple fault", self.get_r16(R::CS), self.regs.ip);
+                self.fatal_error = true;
+            } else if (which.is_contributory() && fault.is_contributory()) ||
+                    (which == Exception::PF && (fault.is_contributory() || fault == Exception::PF)) {
+                self.deliver_exception(hw, Exception::DF, 0);
+            } else {
+                self.deliver_exception(hw, fault, fault_error);
+            }
+        }
+    }
+
+    /// returns true if CR0.PE is set
+    pub fn protected_mode(&self) -> bool {
+        self.cr0 & CR0_PE != 0
+    }
+
+    /// current privilege level
+    pub fn cpl(&self) -> u8 {
+        if self.protected_mode() {
+            self.cpl
+        } else {
+            0
+        }
+    }
+
+    /// raises a exception that is delivered when the current instruction completes
+    fn fault(&mut self, which: Exception, error: u16) {
+        if self.fault.is_none() {
+            self.fault = Some((which, error));
+        }
+    }
+
+    fn read_control_register(&mut self, n: u8) -> u32 {
+        if self.cpl() != 0 {
+            self.fault(Exception::GP, 0);
+        }
+        match n {
+            0 => self.cr0,
+            2 => self.cr2,
+            3 => self.cr3,
+            _ => {
+                self.fault(Exception::UD, 0);
+                0
+            }
+        }
+    }
+
+    fn write_control_register(&mut self, mmu: &mut MMU, n: u8, data: u32) {
+        if self.cpl() != 0 {
+            return self.fault(Exception::GP, 0);
+        }
+        match n {
+            0 => {
+                if data & CR0_PG != 0 && data & CR0_PE == 0 {
+                    // paging requires protected mode
+                    return self.fault(Exception::GP, 0);
+                }
+                self.set_cr0(mmu, data);
+            }
+            2 => self.cr2 = data,
+            3 => {
+                self.cr3 = data;
+                mmu.set_page_directory(data);
+            }
+            _ => self.fault(Exception::UD, 0),
+        }
+    }
+
+    /// switches between real and protected mode when CR0.PE changes, and enables paging with CR0.PG
+    pub fn set_cr0(&mut self, mmu: &mut MMU, cr0: u32) {
+        let was_protected = self.protected_mode();
+        self.cr0 = cr0;
+        if was_protected && !self.protected_mode() {
+            // segment registers are translated as real mode segments again
+            mmu.clear_segments();
+            self.decoder.set_code32(false);
+        }
+        if !was_protected && self.protected_mode() {
+            // the segment registers keep their real mode base until they are reloaded
+            for r in &[R::ES, R::CS, R::SS, R::DS, R::FS, R::GS] {
+                self.segment_cache[r.index()] = Descriptor::real_mode(self.get_r16(*r));
+            }
+        }
+        self.cpl = 0;
+        mmu.set_user_mode(false);
+        mmu.set_paging(cr0 & CR0_PG != 0);
+    }
+
+    /// reads the descriptor of selector from the GDT or LDT, or None if it is outside of the table limit
+    fn read_descriptor(&self, mmu: &MMU, selector: u16) -> Option<u64> {
+        let table = if selector_is_ldt(selector) { self.ldt } else { self.gdtr };
+        table.entry(selector_offset(selector)).map(|addr| mmu.read_linear_u64(addr))
+    }
+
+    /// sets a segment register and its descriptor cache
+    fn set_segment(&mut self, mmu: &mut MMU, r: R, selector: u16, desc: Descriptor) {
+        if r == R::CS && self.protected_mode() {
+            self.cpl = selector_rpl(selector);
+            mmu.set_user_mode(self.cpl == 3);
+        }
+        self.set_r16(r, selector);
+        self.segment_cache[r.index()] = desc;
+        if self.protected_mode() {
+            mmu.set_segment(r, selector, desc.base);
+        }
+        if r == R::CS {
+            self.decoder.set_code32(self.code32());
+        }
+    }
+
+    /// loads a segment register. in protected mode the descriptor is checked, and a fault
+    /// is raised if it can't be loaded. returns false on faults
+    fn load_segment(&mut self, mmu: &mut MMU, r: R, selector: u16) -> bool {
+        if !self.protected_mode() {
+            self.set_segment(mmu, r, selector, Descriptor::real_mode(selector));
+            return true;
+        }
+        if r == R::CS {
+            // CS is only loaded by far transfers
+            self.fault(Exception::UD, 0);
+            return false;
+        }
+        let cpl = self.cpl;
+        match self.check_data_segment(mmu, r, selector, cpl) {
+            Ok(desc) => {
+                self.set_segment(mmu, r, selector, desc);
+                true
+            }
+            Err((which, error)) => {
+                self.fault(which, error);
+                false
+            }
+        }
+    }
+
+    /// loads a segment register outside of a instruction, as done by the DPMI host. in protected mode
+    /// CS is loaded at the privilege level of the selector
+    pub fn load_segment_register(&mut self, mmu: &mut MMU, r: R, selector: u16) -> Result<(), (Exception, u16)> {
+        if !self.protected_mode() {
+            self.set_segment(mmu, r, selector, Descriptor::real_mode(selector));
+            return Ok(());
+        }
+        let desc = if r == R::CS {
+            let error = selector & !3;
+            let desc = Descriptor::from_u64(self.read_descriptor(mmu, selector).ok_or((Exception::GP, error))?);
+            if selector_is_null(selector) || !desc.is_code() {
+                return Err((Exception::GP, error));
+            }
+            if !desc.present() {
+                return Err((Exception::NP, error));
+            }
+            desc
+        } else {
+            let cpl = self.cpl;
+            self.check_data_segment(mmu, r, selector, cpl)?
+        };
+        self.set_segment(mmu, r, selector, desc);
+        Ok(())
+    }
+
+    /// returns the linear base address of segment register r
+    pub fn segment_base(&self, r: R) -> u32 {
+        self.segment_descriptor(r).base
+    }
+
+    /// returns the descriptor cache of segment register r. in real mode it follows the register value
+    fn segment_descriptor(&self, r: R) -> Descriptor {
+        if self.protected_mode() {
+            self.segment_cache[r.index()]
+        } else {
+            Descriptor::real_mode(self.get_r16(r))
+        }
+    }
+
+    /// checks the descriptor of a selector to be loaded in SS, DS, ES, FS or GS at privilege level cpl
+    fn check_data_segment(&self, mmu: &MMU, r: R, selector: u16, cpl: u8) -> Result<Descriptor, (Exception, u16)> {
+        if selector_is_null(selector) {
+            // a null selector can be loaded in a data segment register, but not be used
+            return if r == R::SS { Err((Exception::GP, 0)) } else { Ok(Descriptor::default()) };
+        }
+        let error = selector & !3;
+        let desc = match self.read_descriptor(mmu, selector) {
+            Some(raw) => Descriptor::from_u64(raw),
+            None => return Err((Exception::GP, error)),
+        };
+        let rpl = selector_rpl(selector);
+        if r == R::SS {
+            if rpl != cpl || desc.dpl() != cpl || !desc.is_writable() {
+                return Err((Exception::GP, error));
+            }
+            if !desc.present() {
+                return Err((Exception::SS, error));
+            }
+        } else {
+            if !desc.is_readable() || (!desc.is_conforming() && (rpl > desc.dpl() || cpl > desc.dpl())) {
+                return Err((Exception::GP, error));
+            }
+            if !desc.present() {
+                return Err((Exception::NP, error));
+            }
+        }
+        Ok(desc)
+    }
+
+    /// checks the code segment descriptor of a far jump or call at the current privilege level
+    fn check_code_segment(&self, selector: u16, desc: &Descriptor) -> Result<(), (Exception, u16)> {
+        let error = selector & !3;
+        let cpl = self.cpl();
+        if !desc.is_code() {
+            return Err((Exception::GP, error));
+        }
+        if desc.is_conforming() {
+            if desc.dpl() > cpl {
+                return Err((Exception::GP, error));
+            }
+        } else if selector_rpl(selector) > cpl || desc.dpl() != cpl {
+            return Err((Exception::GP, error));
+        }
+        if !desc.present() {
+            return Err((Exception::NP, error));
+        }
+        Ok(())
+    }
+
+    /// far jump or call to selector:offset. in protected mode the selector refers to a code segment or call gate.
+    /// calls with op32 push a 32-bit return address
+    fn far_transfer(&mut self, mmu: &mut MMU, selector: u16, offset: u32, call: bool, op32: bool) {
+        if !self.protected_mode() {
+            if offset > 0xFFFF {
+                // outside of the real mode code segment
+                return self.fault(Exception::GP, 0);
+            }
+            if call {
+                self.push_return_address(mmu, op32);
+                if self.fault.is_some() {
+                    // the return address did not fit on the stack
+                    return;
+                }
+            }
+            self.set_segment(mmu, R::CS, selector, Descriptor::real_mode(selector));
+            self.regs.ip = offset;
+            return;
+        }
+        if let Err((which, error)) = self.protected_mode_far_transfer(mmu, selector, offset, call, op32) {
+            self.fault(which, error);
+        }
+    }
+
+    /// pushes CS and EIP as the return address of a far call, 32-bit frames hold CS in a dword
+    fn push_return_address(&mut self, mmu: &mut MMU, op32: bool) {
+        let (cs, ip) = self.get_address_pair();
+        if op32 {
+            self.push32(mmu, u32::from(cs));
+            self.push32(mmu, ip);
+        } else {
+            self.push16(mmu, cs);
+            self.push16(mmu, ip as u16);
+        }
+    }
+
+    /// pops EIP and CS of a far return
+    fn pop_return_address(&mut self, mmu: &mut MMU, op32: bool) -> (u32, u16) {
+        if op32 {
+            let ip = self.pop32(mmu);
+            let cs = self.pop32(mmu) as u16;
+            (ip, cs)
+        } else {
+            let ip = self.pop16(mmu);
+            let cs = self.pop16(mmu);
+            (u32::from(ip), cs)
+        }
+    }
+
+    fn protected_mode_far_transfer(&mut self, mmu: &mut MMU, selector: u16, offset: u32, call: bool, op32: bool) -> Result<(), (Exception, u16)> {
+        if selector_is_null(selector) {
+            return Err((Exception::GP, 0));
+        }
+        let raw = self.read_descriptor(mmu, selector).ok_or((Exception::GP, selector & !3))?;
+        let desc = Descriptor::from_u64(raw);
+        let (selector, offset, desc, op32) = if desc.is_segment() {
+            self.check_code_segment(selector, &desc)?;
+            (selector, offset, desc, op32)
+        } else {
+            let gate = Gate::from_u64(raw);
+            if !gate.is_call_gate() {
+                // XXX task switches through task gates and TSS descriptors are not supported
+                return Err((Exception::GP, selector & !3));
+            }
+            if gate.dpl() < self.cpl() || gate.dpl() < selector_rpl(selector) {
+                return Err((Exception::GP, selector & !3));
+            }
+            if !gate.present() {
+                return Err((Exception::NP, selector & !3));
+            }
+            if selector_is_null(gate.selector) {
+                return Err((Exception::GP, 0));
+            }
+            let target = gate.selector & !3;
+            let raw = self.read_descriptor(mmu, target).ok_or((Exception::GP, target))?;
+            let desc = Descriptor::from_u64(raw);
+            // XXX calls to a more privileged level through a call gate are not supported
+            self.check_code_segment(target, &desc)?;
+            // the gate type selects the size of the return address
+            (target, gate.entry_offset(), desc, gate.is_32bit())
+        };
+        if !desc.contains(offset, 1) {
+            return Err((Exception::GP, 0));
+        }
+        if call {
+            self.check_stack_room(if op32 { 8 } else { 4 })?;
+            self.push_return_address(mmu, op32);
+        }
+        let cpl = self.cpl();
+        self.set_segment(mmu, R::CS, (selector & !3) | u16::from(cpl), desc);
+        self.regs.ip = offset;
+        Ok(())
+    }
+
+    /// far return to selector:offset, as popped by RETF and IRET. release is the number of
+    /// parameter bytes to remove from the stack, op32 returns pop a 32-bit SS:ESP when returning
+    /// to a outer privilege level. returns false on faults
+    fn far_return(&mut self, mmu: &mut MMU, selector: u16, offset: u32, release: u16, op32: bool) -> bool {
+        if self.fault.is_some() {
+            // popping the return address faulted
+            return false;
+        }
+        if !self.protected_mode() {
+            if offset > 0xFFFF {
+                self.fault(Exception::GP, 0);
+                return false;
+            }
+            self.set_segment(mmu, R::CS, selector, Descriptor::real_mode(selector));
+            self.regs.ip = offset;
+            let sp = self.get_r16(R::SP).wrapping_add(release);
+            self.set_r16(R::SP, sp);
+            return true;
+        }
+        match self.protected_mode_far_return(mmu, selector, offset, release, op32) {
+            Ok(()) => true,
+            Err((which, error)) => {
+                self.fault(which, error);
+                false
+            }
+        }
+    }
+
+    fn protected_mode_far_return(&mut self, mmu: &mut MMU, selector: u16, offset: u32, release: u16, op32: bool) -> Result<(), (Exception, u16)> {
+        if selector_is_null(selector) {
+            return Err((Exception::GP, 0));
+        }
+        let error = selector & !3;
+        let cpl = self.cpl();
+        let rpl = selector_rpl(selector);
+        let desc = Descriptor::from_u64(self.read_descriptor(mmu, selector).ok_or((Exception::GP, error))?);
+        if rpl < cpl || !desc.is_code() {
+            return Err((Exception::GP, error));
+        }
+        if (desc.is_conforming() && desc.dpl() > rpl) || (!desc.is_conforming() && desc.dpl() != rpl) {
+            return Err((Exception::GP, error));
+        }
+        if !desc.present() {
+            return Err((Exception::NP, error));
+        }
+        if !desc.contains(offset, 1) {
+            return Err((Exception::GP, 0));
+        }
+
+        let sp = self.stack_pointer().wrapping_add(u32::from(release));
+        let sp = if self.stack32() { sp } else { sp & 0xFFFF };
+        if rpl == cpl {
+            self.set_segment(mmu, R::CS, selector, desc);
+            self.set_stack_pointer(sp);
+        } else {
+            // returns to a outer privilege level also pop SS:SP of the caller
+            let width = if op32 { 4 } else { 2 };
+            if !self.segment_descriptor(R::SS).contains(sp, width * 2) {
+                return Err((Exception::SS, 0));
+            }
+            let outer_sp = if op32 {
+                self.read_mem_u32(mmu, R::SS, sp)
+            } else {
+                u32::from(self.read_mem_u16(mmu, R::SS, sp))
+            };
+            let outer_ss = self.read_mem_u16(mmu, R::SS, sp + width);
+            let ss_desc = self.check_data_segment(mmu, R::SS, outer_ss, rpl)?;
+            self.set_segment(mmu, R::CS, selector, desc);
+            self.set_segment(mmu, R::SS, outer_ss, ss_desc);
+            self.set_stack_pointer(outer_sp.wrapping_add(u32::from(release)));
+
+            // data segments that are not accessible at the outer privilege level are cleared
+            for r in &[R::ES, R::DS, R::FS, R::GS] {
+                let cache = self.segment_cache[r.index()];
+                if cache.is_segment() && !cache.is_conforming() && cache.dpl() < rpl {
+                    self.set_segment(mmu, *r, 0, Descriptor::default());
+                }
+            }
+        }
+        self.regs.ip = offset;
+        Ok(())
+    }
+
+    /// delivers interrupt vector through the IDT. error is pushed by exceptions with a error code,
+    /// software interrupts check the privilege level of the gate. returns the size of the pushed
+    /// frame entries, 4 for 386 gates
+    fn protected_mode_interrupt(&mut self, mmu: &mut MMU, vector: u8, error: Option<u16>, software: bool) -> Result<u32, (Exception, u16)> {
+        let idt_error = u16::from(vector) * 8 + 2;
+        let addr = self.idtr.entry(u16::from(vector) * 8).ok_or((Exception::GP, idt_error))?;
+        let gate = Gate::from_u64(mmu.read_linear_u64(addr));
+        self.check_page_fault(mmu)?;
+        if !gate.is_interrupt_gate() {
+            // XXX task gates are not supported
+            return Err((Exception::GP, idt_error));
+        }
+        let cpl = self.cpl();
+        if software && gate.dpl() < cpl {
+            return Err((Exception::GP, idt_error));
+        }
+        if !gate.present() {
+            return Err((Exception::NP, idt_error));
+        }
+        if selector_is_null(gate.selector) {
+            return Err((Exception::GP, 0));
+        }
+        let target = gate.selector & !3;
+        let desc = Descriptor::from_u64(self.read_descriptor(mmu, target).ok_or((Exception::GP, target))?);
+        self.check_page_fault(mmu)?;
+        if !desc.is_code() || desc.dpl() > cpl {
+            return Err((Exception::GP, target));
+        }
+        if !desc.present() {
+            return Err((Exception::NP, target));
+        }
+        let offset = gate.entry_offset();
+        if !desc.contains(offset, 1) {
+            return Err((Exception::GP, 0));
+        }
 
-        // CPU_Interrupt(which,CPU_INT_EXCEPTION | ((which>=8) ? CPU_INT_HAS_ERROR : 0),reg_eip);
+        let new_cpl = if desc.is_conforming() { cpl } else { desc.dpl() };
+        let (saved_ss, saved_ss_desc, saved_sp) = (self.get_r16(R::SS), self.segment_cache[R::SS.index()], self.get_r32(R::ESP));
+        // 386 gates push a frame of dwords
+        let op32 = gate.is_32bit();
+        let width = if op32 { 4 } else { 2 };
+        let frame = width * if error.is_some() { 4 } else { 3 };
+        let room = if new_cpl < cpl {
+            // interrupts to a inner privilege level switch to the stack of that level from the TSS
+            let (ss, sp) = self.tss_stack(mmu, new_cpl)?;
+            let ss_desc = self.check_data_segment(mmu, R::SS, ss, new_cpl).map_err(|_| (Exception::TS, ss & !3))?;
+            self.set_segment(mmu, R::SS, ss, ss_desc);
+            self.set_stack_pointer(u32::from(sp));
+            self.check_stack_room(frame + width * 2).map_err(|_| (Exception::SS, ss & !3))
+        } else {
+            self.check_stack_room(frame)
+        };
+        if let Err(fault) = room {
+            self.set_segment(mmu, R::SS, saved_ss, saved_ss_desc);
+            self.set_r32(R::ESP, saved_sp);
+            return Err(fault);
+        }
+        let flags = self.flags_u16();
+        if op32 {
+            if new_cpl < cpl {
+                self.push32(mmu, u32::from(saved_ss));
+                self.push32(mmu, saved_sp);
+            }
+            self.push32(mmu, u32::from(flags));
+        } else {
+            if new_cpl < cpl {
+                self.push16(mmu, saved_ss);
+                self.push16(mmu, saved_sp as u16);
+            }
+            self.push16(mmu, flags);
+        }
+        self.push_return_address(mmu, op32);
+        if let Some(error) = error {
+            if op32 {
+                self.push32(mmu, u32::from(error));
+            } else {
+                self.push16(mmu, error);
+            }
+        }
+        if let Err(fault) = self.check_page_fault(mmu) {
+            // the stack is left unchanged when pushing the frame faults
+            self.set_segment(mmu, R::SS, saved_ss, saved_ss_desc);
+            self.set_r32(R::ESP, saved_sp);
+            return Err(fault);
+        }
+        if !gate.is_trap_gate() {
+            self.regs.flags.interrupt = false;
+        }
+        self.regs.flags.trap = false;
+        self.single_step = false;
+        self.set_segment(mmu, R::CS, target | u16::from(new_cpl), desc);
+        self.regs.ip = offset;
+        Ok(width)
+    }
+
+    /// returns the page fault raised by the last memory accesses as a error, and loads CR2
+    fn check_page_fault(&mut self, mmu: &MMU) -> Result<(), (Exception, u16)> {
+        match mmu.take_page_fault() {
+            Some(pf) => {
+                self.cr2 = pf.address;
+                Err((Exception::PF, pf.error))
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// reads SS:SP of privilege level dpl from the current TSS
+    fn tss_stack(&self, mmu: &MMU, dpl: u8) -> Result<(u16, u16), (Exception, u16)> {
+        let error = self.tr & !3;
+        if !self.tss.is_tss() {
+            return Err((Exception::TS, error));
+        }
+        let dpl = u32::from(dpl);
+        let (sp_offset, ss_offset) = match self.tss.system_type() {
+            SYSTEM_TSS_386 | SYSTEM_TSS_386_BUSY => (4 + dpl * 8, 8 + dpl * 8),
+            _ => (2 + dpl * 4, 4 + dpl * 4),
+        };
+        if !self.tss.contains(ss_offset, 2) {
+            return Err((Exception::TS, error));
+        }
+        let sp = mmu.read_linear_u16(self.tss.base.wrapping_add(sp_offset));
+        let ss = mmu.read_linear_u16(self.tss.base.wrapping_add(ss_offset));
+        Ok((ss, sp))
+    }
+
+    /// LLDT
+    fn load_ldt(&mut self, mmu: &MMU, selector: u16) -> Result<(), (Exception, u16)> {
+        if selector_is_null(selector) {
+            // a null LDT can't be used
+            self.ldtr = selector;
+            self.ldt = DescriptorTable::default();
+            return Ok(());
+        }
+        let error = selector & !3;
+        if selector_is_ldt(selector) {
+            return Err((Exception::GP, error));
+        }
+        let desc = Descriptor::from_u64(self.read_descriptor(mmu, selector).ok_or((Exception::GP, error))?);
+        if desc.is_segment() || desc.system_type() != SYSTEM_LDT {
+            return Err((Exception::GP, error));
+        }
+        if !desc.present() {
+            return Err((Exception::NP, error));
+        }
+        self.ldtr = selector;
+        self.ldt = DescriptorTable { base: desc.base, limit: desc.limit };
+        Ok(())
+    }
+
+    /// LTR, the TSS descriptor is marked busy
+    fn load_task_register(&mut self, mmu: &mut MMU, selector: u16) -> Result<(), (Exception, u16)> {
+        let error = selector & !3;
+        if selector_is_null(selector) {
+            return Err((Exception::GP, 0));
+        }
+        if selector_is_ldt(selector) {
+            return Err((Exception::GP, error));
+        }
+        let addr = self.gdtr.entry(selector_offset(selector)).ok_or((Exception::GP, error))?;
+        let mut desc = Descriptor::from_u64(mmu.read_linear_u64(addr));
+        if desc.is_segment() || (desc.system_type() != SYSTEM_TSS_286 && desc.system_type() != SYSTEM_TSS_386) {
+            return Err((Exception::GP, error));
+        }
+        if !desc.present() {
+            return Err((Exception::NP, error));
+        }
+        // the busy bit is bit 1 of the type
+        desc.access |= 2;
+        mmu.write_linear_u8(addr + 5, desc.access);
+        self.tr = selector;
+        self.tss = desc;
+        Ok(())
+    }
+
+    /// VERR and VERW, returns true if the segment is readable or writable at the current privilege level
+    fn verify_segment(&self, mmu: &MMU, selector: u16, write: bool) -> bool {
+        if selector_is_null(selector) {
+            return false;
+        }
+        let desc = match self.read_descriptor(mmu, selector) {
+            Some(raw) => Descriptor::from_u64(raw),
+            None => return false,
+        };
+        let accessible = if write { desc.is_writable() } else { desc.is_readable() };
+        let privileged = desc.is_conforming() || (desc.dpl() >= self.cpl() && desc.dpl() >= selector_rpl(selector));
+        accessible && privileged
+    }
+
+    /// returns true if the conditional jump op is taken
+    fn jump_condition(&self, op: &Op) -> bool {
+        let flags = &self.regs.flags;
+        match *op {
+            Op::Ja => !flags.carry & !flags.zero,
+            Op::Jc => flags.carry,
+            Op::Jg => !flags.zero & flags.sign == flags.overflow,
+            Op::Jl => flags.sign != flags.overflow,
+            Op::Jna => flags.carry | flags.zero,
+            Op::Jnc => !flags.carry,
+            Op::Jng => flags.zero | flags.sign != flags.overflow,
+            Op::Jnl => flags.sign == flags.overflow,
+            Op::Jno => !flags.overflow,
+            Op::Jns => !flags.sign,
+            Op::Jnz => !flags.zero,
+            Op::Jo => flags.overflow,
+            Op::Jpe => flags.parity,
+            Op::Jpo => !flags.parity,
+            Op::Js => flags.sign,
+            Op::Jz => flags.zero,
+            _ => panic!("jump_condition {:?}", op),
+        }
+    }
+
+    /// returns dst + 1, setting the flags like INC
+    fn inc16(&mut self, dst: usize) -> u16 {
+        let src = 1;
+        let res = (Wrapping(dst) + Wrapping(src)).0;
+
+        // The OF, SF, ZF, AF, and PF flags are set according to the result.
+        self.regs.flags.set_overflow_add_u16(res, src, dst);
+        self.regs.flags.set_sign_u16(res);
+        self.regs.flags.set_zero_u16(res);
+        self.regs.flags.set_adjust(res, src, dst);
+        self.regs.flags.set_parity(res);
+        res as u16
+    }
+
+    /// returns dst - 1, setting the flags like DEC
+    fn dec16(&mut self, dst: usize) -> u16 {
+        let src = 1;
+        let res = (Wrapping(dst) - Wrapping(src)).0;
+
+        // The CF flag is not affected. The OF, SF, ZF, AF,
+        // and PF flags are set according to the result.
+        self.regs.flags.set_overflow_sub_u16(res, src, dst);
+        self.regs.flags.set_sign_u16(res);
+        self.regs.flags.set_zero_u16(res);
+        self.regs.flags.set_adjust(res, src, dst);
+        self.regs.flags.set_parity(res);
+        res as u16
     }
 
     fn cmp8(&mut self, dst: usize, src: usize) {
@@ -2178,53 +3127,266 @@ impl CPU {
         self.regs.flags.set_parity(res);
     }
 
-    fn push16(&mut self, mmu: &mut MMU, data: u16) {
-        let sp = (Wrapping(self.get_r16(R::SP)) - Wrapping(2)).0;
-        self.set_r16(R::SP, sp);
-        let ss = self.get_r16(R::SS);
-        mmu.write_u16(ss, sp, data);
+    pub fn push16(&mut self, mmu: &mut MMU, data: u16) {
+        let sp = self.stack_pointer().wrapping_sub(2);
+        self.set_stack_pointer(sp);
+        self.write_mem_u16(mmu, R::SS, sp, data);
     }
 
-    fn push32(&mut self, mmu: &mut MMU, data: u32) {
-        let sp = (Wrapping(self.get_r16(R::SP)) - Wrapping(4)).0;
-        self.set_r16(R::SP, sp);
-        let ss = self.get_r16(R::SS);
-        mmu.write_u32(ss, sp, data);
+    pub fn push32(&mut self, mmu: &mut MMU, data: u32) {
+        let sp = self.stack_pointer().wrapping_sub(4);
+        self.set_stack_pointer(sp);
+        self.write_mem_u32(mmu, R::SS, sp, data);
     }
 
-    fn pop16(&mut self, mmu: &mut MMU) -> u16 {
-        let data = mmu.read_u16(self.get_r16(R::SS), self.get_r16(R::SP));
-        let sp = (Wrapping(self.get_r16(R::SP)) + Wrapping(2)).0;
-        self.set_r16(R::SP, sp);
+    pub fn pop16(&mut self, mmu: &mut MMU) -> u16 {
+        let sp = self.stack_pointer();
+        let data = self.read_mem_u16(mmu, R::SS, sp);
+        self.set_stack_pointer(sp.wrapping_add(2));
         data
     }
 
-    fn pop32(&mut self, mmu: &mut MMU) -> u32 {
-        let data = mmu.read_u32(self.get_r16(R::SS), self.get_r16(R::SP));
-        let sp = (Wrapping(self.get_r16(R::SP)) + Wrapping(4)).0;
-        self.set_r16(R::SP, sp);
+    pub fn pop32(&mut self, mmu: &mut MMU) -> u32 {
+        let sp = self.stack_pointer();
+        let data = self.read_mem_u32(mmu, R::SS, sp);
+        self.set_stack_pointer(sp.wrapping_add(4));
         data
     }
 
+    /// returns true if SS is a 32-bit stack segment, which is addressed by ESP
+    pub fn stack32(&self) -> bool {
+        self.protected_mode() && self.segment_cache[R::SS.index()].is_32bit()
+    }
+
+    /// returns SP, or ESP in a 32-bit stack segment
+    pub fn stack_pointer(&self) -> u32 {
+        if self.stack32() {
+            self.get_r32(R::ESP)
+        } else {
+            u32::from(self.get_r16(R::SP))
+        }
+    }
+
+    /// sets SP, or ESP in a 32-bit stack segment
+    pub fn set_stack_pointer(&mut self, sp: u32) {
+        if self.stack32() {
+            self.set_r32(R::ESP, sp);
+        } else {
+            self.set_r16(R::SP, sp as u16);
+        }
+    }
+
+    /// checks that a frame of size bytes below the stack pointer fits in the stack segment, before a far call or
+    /// interrupt pushes it
+    fn check_stack_room(&self, size: u32) -> Result<(), (Exception, u16)> {
+        let sp = self.stack_pointer().wrapping_sub(size);
+        let sp = if self.stack32() { sp } else { sp & 0xFFFF };
+        if self.segment_descriptor(R::SS).contains(sp, size) {
+            Ok(())
+        } else {
+            Err((Exception::SS, 0))
+        }
+    }
+
+    /// reads CX, SI or DI as used by loops and string instructions, or ECX, ESI or EDI with a
+    /// 32-bit address size
+    fn get_address_register(&self, address_size: &AddressSize, r: R) -> u32 {
+        match *address_size {
+            AddressSize::_16bit => u32::from(self.get_r16(r)),
+            AddressSize::_32bit => self.get_r32(r32(r.index() as u8)),
+        }
+    }
+
+    /// writes CX, SI or DI, or ECX, ESI or EDI with a 32-bit address size
+    fn set_address_register(&mut self, address_size: &AddressSize, r: R, val: u32) {
+        match *address_size {
+            AddressSize::_16bit => self.set_r16(r, val as u16),
+            AddressSize::_32bit => self.set_r32(r32(r.index() as u8), val),
+        }
+    }
+
+    /// steps the string index SI or DI of op by size bytes, backwards if the direction flag is set
+    fn advance_string_index(&mut self, op: &Instruction, r: R, size: u32) {
+        let val = self.get_address_register(&op.address_size, r);
+        let val = if self.regs.flags.direction {
+            val.wrapping_sub(size)
+        } else {
+            val.wrapping_add(size)
+        };
+        self.set_address_register(&op.address_size, r, val);
+    }
+
+    /// decrements the loop counter CX, or ECX with a 32-bit address size, and returns the new count
+    fn decrement_counter(&mut self, op: &Instruction) -> u32 {
+        let count = self.get_address_register(&op.address_size, R::CX).wrapping_sub(1);
+        self.set_address_register(&op.address_size, R::CX, count);
+        count
+    }
+
+    /// returns the linear address of a access of size bytes at offset in segment register r. in
+    /// protected mode and in real mode on the 286 and later, a access outside of the segment limit,
+    /// through a null selector or not permitted by the segment type raises #GP, or #SS for the stack
+    /// segment, and returns None
+    fn data_address(&mut self, r: R, offset: u32, size: u32, write: bool) -> Option<u32> {
+        let desc = self.segment_descriptor(r);
+        if self.model < CpuModel::I80286 {
+            // 20 address lines
+            return Some(desc.base.wrapping_add(offset) & 0xF_FFFF);
+        }
+        let permitted = if write { desc.is_writable() } else { desc.is_readable() };
+        if permitted && desc.contains(offset, size) {
+            return Some(desc.base.wrapping_add(offset));
+        }
+        self.fault(if r == R::SS { Exception::SS } else { Exception::GP }, 0);
+        None
+    }
+
+    /// returns true if a access of size bytes at offset wraps at the end of the segment, as on the
+    /// 8086 and 80186
+    fn wraps(&self, offset: u32, size: u32) -> bool {
+        self.model < CpuModel::I80286 && offset + size > 0x1_0000
+    }
+
+    /// accesses by a program at CPL 3 are user accesses to paged memory
+    fn user_access(&self) -> bool {
+        self.cpl() == 3
+    }
+
+    fn read_mem_u8(&mut self, mmu: &MMU, r: R, offset: u32) -> u8 {
+        match self.data_address(r, offset, 1, false) {
+            Some(addr) => mmu.load_u8(addr, self.user_access()),
+            None => 0,
+        }
+    }
+
+    fn read_mem_u16(&mut self, mmu: &MMU, r: R, offset: u32) -> u16 {
+        if self.wraps(offset, 2) {
+            let lo = self.read_mem_u8(mmu, r, offset);
+            let hi = self.read_mem_u8(mmu, r, 0);
+            return u16::from(hi) << 8 | u16::from(lo);
+        }
+        match self.data_address(r, offset, 2, false) {
+            Some(addr) => mmu.load_u16(addr, self.user_access()),
+            None => 0,
+        }
+    }
+
+    fn read_mem_u32(&mut self, mmu: &MMU, r: R, offset: u32) -> u32 {
+        if self.wraps(offset, 4) {
+            let lo = self.read_mem_u16(mmu, r, offset);
+            let hi = self.read_mem_u16(mmu, r, (offset + 2) & 0xFFFF);
+            return u32::from(hi) << 16 | u32::from(lo);
+        }
+        match self.data_address(r, offset, 4, false) {
+            Some(addr) => mmu.load_u32(addr, self.user_access()),
+            None => 0,
+        }
+    }
+
+    fn write_mem_u8(&mut self, mmu: &mut MMU, r: R, offset: u32, data: u8) {
+        if let Some(addr) = self.data_address(r, offset, 1, true) {
+            mmu.store_u8(addr, self.user_access(), data);
+        }
+    }
+
+    fn write_mem_u16(&mut self, mmu: &mut MMU, r: R, offset: u32, data: u16) {
+        if self.wraps(offset, 2) {
+            self.write_mem_u8(mmu, r, offset, data as u8);
+            self.write_mem_u8(mmu, r, 0, (data >> 8) as u8);
+            return;
+        }
+        if let Some(addr) = self.data_address(r, offset, 2, true) {
+            mmu.store_u16(addr, self.user_access(), data);
+        }
+    }
+
+    fn write_mem_u32(&mut self, mmu: &mut MMU, r: R, offset: u32, data: u32) {
+        if self.wraps(offset, 4) {
+            self.write_mem_u16(mmu, r, offset, data as u16);
+            self.write_mem_u16(mmu, r, (offset + 2) & 0xFFFF, (data >> 16) as u16);
+            return;
+        }
+        if let Some(addr) = self.data_address(r, offset, 4, true) {
+            mmu.store_u32(addr, self.user_access(), data);
+        }
+    }
+
+    /// returns FLAGS as stored by PUSHF. bits 12-15 are always set on the 8086 and 80186, and
+    /// always clear in real mode on the 286
+    fn flags_u16(&self) -> u16 {
+        let flags = self.regs.flags.u16();
+        match self.model {
+            CpuModel::I8086 | CpuModel::I80186 => flags | 0xF000,
+            CpuModel::I80286 if !self.protected_mode() => flags & 0x0FFF,
+            _ => flags,
+        }
+    }
+
+    /// loads FLAGS as done by POPF. IOPL and NT (bits 12-14) are only loaded by the 286 in protected mode
+    /// and by the 386 and later
+    fn set_flags_u16(&mut self, data: u16) {
+        self.regs.flags.set_u16(data);
+        match self.model {
+            CpuModel::I80386 | CpuModel::I80486 => self.regs.flags.set_iopl_nt(data),
+            CpuModel::I80286 if self.protected_mode() => self.regs.flags.set_iopl_nt(data),
+            _ => {}
+        }
+    }
+
+    /// reads the count of a shift or rotate. the 80186 and later use the low 5 bits
+    fn shift_count(&mut self, mmu: &MMU, p: &Parameter) -> usize {
+        let count = self.read_parameter_value(mmu, p) & 0xFF;
+        if self.model == CpuModel::I8086 {
+            count
+        } else {
+            count & 0x1F
+        }
+    }
+
+    /// sets the flags left undefined by MUL and IMUL. the 8086 and 80186 set SF, ZF and PF from
+    /// the upper half of the product, later models leave them unchanged
+    fn set_mul_flags(&mut self, high: u16, sign_bit: u16) {
+        if self.model < CpuModel::I80286 {
+            self.regs.flags.sign = high & sign_bit != 0;
+            self.regs.flags.zero = high == 0;
+            self.regs.flags.set_parity(high as usize);
+            self.regs.flags.adjust = false;
+        }
+    }
+
     /// returns the absoute address of CS:IP
     pub fn get_address(&self) -> u32 {
         self.get_memory_address().value()
     }
 
-    /// returns cs, ip
-    pub fn get_address_pair(&self) -> (u16, u16) {
+    /// returns cs, eip
+    pub fn get_address_pair(&self) -> (u16, u32) {
         (self.get_r16(R::CS), self.regs.ip)
     }
 
     /// returns the address of CS:IP as a MemoryAddress::RealSegmentOffset
     pub fn get_memory_address(&self) -> MemoryAddress {
-        MemoryAddress::RealSegmentOffset(self.get_r16(R::CS), self.regs.ip)
+        MemoryAddress::RealSegmentOffset(self.get_r16(R::CS), self.regs.ip as u16)
+    }
+
+    /// returns true if CS is a 32-bit code segment
+    pub fn code32(&self) -> bool {
+        self.protected_mode() && self.segment_cache[R::CS.index()].is_32bit()
+    }
+
+    /// EIP wraps at 64k in 16-bit code segments
+    fn ip_mask(&self) -> u32 {
+        if self.code32() {
+            0xFFFF_FFFF
+        } else {
+            0xFFFF
+        }
     }
 
     fn read_u8(&mut self, mmu: &MMU) -> u8 {
         let (seg, off) = self.get_address_pair();
-        let b = mmu.read_u8(seg, off);
-        self.regs.ip += 1;
+        let b = mmu.fetch_u8(seg, off);
+        self.regs.ip = off.wrapping_add(1) & self.ip_mask();
         b
     }
 
@@ -2252,41 +3414,27 @@ impl CPU {
         (self.regs.ip as i16 + val) as u16
     }
 
-    /// returns "segment, offset" pair
-    fn get_amode_addr(&self, amode: &AMode) -> (u16, u16) {
-        match *amode {
-            AMode::BX => (self.get_r16(R::DS), self.get_r16(R::BX)),
-            AMode::BP => (self.get_r16(R::SS), self.get_r16(R::BP)),
-            AMode::SI => (self.get_r16(R::DS), self.get_r16(R::SI)),
-            AMode::DI => (self.get_r16(R::DS), self.get_r16(R::DI)),
-            AMode::BXSI => (self.get_r16(R::DS), self.get_r16(R::BX) + self.get_r16(R::SI)),
-            AMode::BXDI => (self.get_r16(R::DS), self.get_r16(R::BX) + self.get_r16(R::DI)),
-            AMode::BPSI => (self.get_r16(R::SS), self.get_r16(R::BP) + self.get_r16(R::SI)),
-            AMode::BPDI => (self.get_r16(R::SS), self.get_r16(R::BP) + self.get_r16(R::DI)),
-            _ => panic!("xxx"),
-        }
-    }
-
-    /// used by lds, les
-    fn read_segment_selector(&self, mmu: &MMU, p: &Parameter) -> (u16, u16) {
-        let (segment, offset) = match *p {
-            Parameter::Ptr16(seg, imm) => (self.segment(seg), imm),
-            Parameter::Ptr16Amode(_, ref amode) => self.get_amode_addr(amode),
-            Parameter::Ptr16AmodeS8(_, ref amode, imms) => {
-                let (seg, off) = self.get_amode_addr(amode);
-                (seg, (i32::from(off) + i32::from(imms)) as u16)
-            }
-            /*
-            Parameter::Ptr16AmodeS16(_, ref amode, imms) => {
-                let (seg, off) = self.get_amode_addr(amode);
-                (seg, (i32::from(off) + i32::from(imms)) as u16)
-            }
-            */
-            _ => panic!("unhandled parameter {:?}", p),
-        };
+    /// used by lds, les, lss, lfs, lgs. reads a m16:16 or m16:32 far pointer, returns segment, offset
+    fn read_far_pointer(&mut self, mmu: &MMU, p: &Parameter, op_size: &OperandSize) -> (u16, u32) {
+        match *op_size {
+            OperandSize::_16bit => {
+                let (segment, offset) = self.read_segment_selector(mmu, p);
+                (segment, u32::from(offset))
+            }
+            OperandSize::_32bit => {
+                let (r, offset) = self.memory_address(p);
+                let o_val = self.read_mem_u32(mmu, r, offset);
+                let s_val = self.read_mem_u16(mmu, r, offset.wrapping_add(4));
+                (s_val, o_val)
+            }
+        }
+    }
 
-        let o_val = mmu.read_u16(segment, offset);
-        let s_val = mmu.read_u16(segment, offset + 2);
+    /// reads a m16:16 far pointer, returns segment, offset
+    fn read_segment_selector(&mut self, mmu: &MMU, p: &Parameter) -> (u16, u16) {
+        let (r, offset) = self.memory_address(p);
+        let o_val = self.read_mem_u16(mmu, r, offset);
+        let s_val = self.read_mem_u16(mmu, r, offset.wrapping_add(2));
         (s_val, o_val)
     }
 
@@ -2296,6 +3444,7 @@ impl CPU {
             Parameter::Ptr16Amode(_, ref amode) => self.amode(amode),
             Parameter::Ptr16AmodeS8(_, ref amode, imm) => (Wrapping(self.amode(amode)) + Wrapping(imm as usize)).0,
             Parameter::Ptr16AmodeS16(_, ref amode, imm) => (Wrapping(self.amode(amode)) + Wrapping(imm as usize)).0,
+            Parameter::Ptr16AmodeS32(_, ref amode, imm) => (Wrapping(self.amode(amode) as u32) + Wrapping(imm as u32)).0 as usize,
             Parameter::Ptr16(_, imm) => imm as usize,
             _ => panic!("unhandled parameter: {:?} at {:06X}", p, self.get_address()),
         }
@@ -2320,48 +3469,21 @@ impl CPU {
             Parameter::Reg16(r) => self.get_r16(r) as usize,
             Parameter::Reg32(r) => self.get_r32(r) as usize,
             Parameter::SReg16(sr) => self.get_r16(sr) as usize,
-            Parameter::Ptr8(seg, imm) => mmu.read_u8(self.segment(seg), imm) as usize,
-            Parameter::Ptr8Amode(seg, ref amode) => {
-                let seg = self.segment(seg);
-                let offset = self.amode(amode) as u16;
-                mmu.read_u8(seg, offset) as usize
-            }
-            Parameter::Ptr8AmodeS8(seg, ref amode, imm) => {
-                let seg = self.segment(seg);
-                let offset = (Wrapping(self.amode(amode) as u16) + Wrapping(imm as u16)).0;
-                mmu.read_u8(seg, offset) as usize
-            }
-            Parameter::Ptr8AmodeS16(seg, ref amode, imm) => {
-                let seg = self.segment(seg);
-                let offset = (Wrapping(self.amode(amode) as u16) + Wrapping(imm as u16)).0;
-                mmu.read_u8(seg, offset) as usize
-            }
-            Parameter::Ptr16(seg, imm) => mmu.read_u16(self.segment(seg), imm) as usize,
-            Parameter::Ptr16Amode(seg, ref amode) => {
-                let seg = self.segment(seg);
-                let offset = self.amode(amode) as u16;
-                mmu.read_u16(seg, offset) as usize
-            }
-            Parameter::Ptr16AmodeS8(seg, ref amode, imm) => {
-                let seg = self.segment(seg);
-                let offset = (Wrapping(self.amode(amode) as u16) + Wrapping(imm as u16)).0;
-                mmu.read_u16(seg, offset) as usize
-            }
-            Parameter::Ptr16AmodeS16(seg, ref amode, imm) => {
-                let seg = self.segment(seg);
-                let offset = (Wrapping(self.amode(amode) as u16) + Wrapping(imm as u16)).0;
-                mmu.read_u16(seg, offset) as usize
-            }
-            Parameter::Ptr32(seg, imm) => mmu.read_u32(self.segment(seg), imm) as usize,
-            Parameter::Ptr32Amode(seg, ref amode) => {
-                let seg = self.segment(seg);
-                let offset = self.amode(amode) as u16;
-                mmu.read_u32(seg, offset) as usize
-            }
-            Parameter::Ptr32AmodeS8(seg, ref amode, imm) => {
-                let seg = self.segment(seg);
-                let offset = (Wrapping(self.amode(amode) as u16) + Wrapping(imm as u16)).0;
-                mmu.read_u32(seg, offset) as usize
+            Parameter::CR(n) => self.read_control_register(n) as usize,
+            Parameter::Ptr8(..) | Parameter::Ptr8Amode(..) | Parameter::Ptr8AmodeS8(..) |
+            Parameter::Ptr8AmodeS16(..) | Parameter::Ptr8AmodeS32(..) => {
+                let (r, offset) = self.memory_address(p);
+                self.read_mem_u8(mmu, r, offset) as usize
+            }
+            Parameter::Ptr16(..) | Parameter::Ptr16Amode(..) | Parameter::Ptr16AmodeS8(..) |
+            Parameter::Ptr16AmodeS16(..) | Parameter::Ptr16AmodeS32(..) => {
+                let (r, offset) = self.memory_address(p);
+                self.read_mem_u16(mmu, r, offset) as usize
+            }
+            Parameter::Ptr32(..) | Parameter::Ptr32Amode(..) | Parameter::Ptr32AmodeS8(..) |
+            Parameter::Ptr32AmodeS16(..) | Parameter::Ptr32AmodeS32(..) => {
+                let (r, offset) = self.memory_address(p);
+                self.read_mem_u32(mmu, r, offset) as usize
             }
             _ => {
                 let (seg, off) = self.get_address_pair();
@@ -2373,24 +3495,10 @@ impl CPU {
     fn write_parameter_u8(&mut self, mmu: &mut MMU, p: &Parameter, data: u8) {
         match *p {
             Parameter::Reg8(r) => self.set_r8(r, data),
-            Parameter::Ptr8(seg, imm) => {
-                let seg = self.segment(seg);
-                mmu.write_u8(seg, imm, data);
-            }
-            Parameter::Ptr8Amode(seg, ref amode) => {
-                let seg = self.segment(seg);
-                let offset = self.amode(amode) as u16;
-                mmu.write_u8(seg, offset, data);
-            }
-            Parameter::Ptr8AmodeS8(seg, ref amode, imm) => {
-                let seg = self.segment(seg);
-                let offset = Wrapping(self.amode(amode) as u16) + Wrapping(imm as u16);
-                mmu.write_u8(seg, offset.0, data);
-            }
-            Parameter::Ptr8AmodeS16(seg, ref amode, imm) => {
-                let seg = self.segment(seg);
-                let offset = Wrapping(self.amode(amode) as u16) + Wrapping(imm as u16);
-                mmu.write_u8(seg, offset.0, data);
+            Parameter::Ptr8(..) | Parameter::Ptr8Amode(..) | Parameter::Ptr8AmodeS8(..) |
+            Parameter::Ptr8AmodeS16(..) | Parameter::Ptr8AmodeS32(..) => {
+                let (r, offset) = self.memory_address(p);
+                self.write_mem_u8(mmu, r, offset, data);
             }
             _ => panic!("write_parameter_u8 unhandled type {:?} at {:06X}", p, self.get_address()),
         }
@@ -2398,30 +3506,17 @@ impl CPU {
 
     fn write_parameter_u16(&mut self, mmu: &mut MMU, segment: Segment, p: &Parameter, data: u16) {
         match *p {
-            Parameter::Reg16(r) |
-            Parameter::SReg16(r) => self.set_r16(r, data),
-            Parameter::Imm16(imm) => {
-                let seg = self.segment(segment);
-                mmu.write_u16(seg, imm, data);
+            Parameter::Reg16(r) => self.set_r16(r, data),
+            Parameter::SReg16(r) => {
+                self.load_segment(mmu, r, data);
             }
-            Parameter::Ptr16(seg, imm) => {
-                let seg = self.segment(seg);
-                mmu.write_u16(seg, imm, data);
-            }
-            Parameter::Ptr16Amode(seg, ref amode) => {
-                let seg = self.segment(seg);
-                let offset = self.amode(amode) as u16;
-                mmu.write_u16(seg, offset, data);
-            }
-            Parameter::Ptr16AmodeS8(seg, ref amode, imm) => {
-                let seg = self.segment(seg);
-                let offset = Wrapping(self.amode(amode) as u16) + Wrapping(imm as u16);
-                mmu.write_u16(seg, offset.0, data);
+            Parameter::Imm16(imm) => {
+                self.write_mem_u16(mmu, segment.as_register(), u32::from(imm), data);
             }
-            Parameter::Ptr16AmodeS16(seg, ref amode, imm) => {
-                let seg = self.segment(seg);
-                let offset = Wrapping(self.amode(amode) as u16) + Wrapping(imm as u16);
-                mmu.write_u16(seg, offset.0, data);
+            Parameter::Ptr16(..) | Parameter::Ptr16Amode(..) | Parameter::Ptr16AmodeS8(..) |
+            Parameter::Ptr16AmodeS16(..) | Parameter::Ptr16AmodeS32(..) => {
+                let (r, offset) = self.memory_address(p);
+                self.write_mem_u16(mmu, r, offset, data);
             }
             _ => panic!("unhandled type {:?} at {:06X}", p, self.get_address()),
         }
@@ -2430,32 +3525,429 @@ impl CPU {
     fn write_parameter_u32(&mut self, mmu: &mut MMU, _segment: Segment, p: &Parameter, data: u32) {
         match *p {
             Parameter::Reg32(r) => self.set_r32(r, data),
-            Parameter::Ptr32(seg, imm) => {
-                let seg = self.segment(seg);
-                mmu.write_u32(seg, imm, data);
+            Parameter::SReg16(r) => {
+                // pop sreg with a 32-bit operand size
+                self.load_segment(mmu, r, data as u16);
             }
-            Parameter::Ptr32Amode(seg, ref amode) => {
-                let seg = self.segment(seg);
-                let offset = self.amode(amode);
-                mmu.write_u32(seg, offset as u16, data);
+            Parameter::CR(n) => self.write_control_register(mmu, n, data),
+            Parameter::Ptr32(..) | Parameter::Ptr32Amode(..) | Parameter::Ptr32AmodeS8(..) |
+            Parameter::Ptr32AmodeS16(..) | Parameter::Ptr32AmodeS32(..) => {
+                let (r, offset) = self.memory_address(p);
+                self.write_mem_u32(mmu, r, offset, data);
             }
-            Parameter::Ptr32AmodeS8(seg, ref amode, imm) => {
-                let seg = self.segment(seg);
-                let offset = Wrapping(self.amode(amode) as u16) + Wrapping(imm as u16);
-                mmu.write_u32(seg, offset.0, data);
+            _ => panic!("unhandled type {:?} at {:06X}", p, self.get_address()),
+        }
+    }
+
+    /// executes a x87 instruction
+    fn execute_fpu(&mut self, hw: &mut Hardware, op: &Instruction) {
+        match op.command {
+            Op::Fld => {
+                let val = self.read_fpu_real(&hw.mmu, &op.params.dst);
+                self.fpu.push(val);
+            }
+            Op::Fild => {
+                let val = self.read_fpu_integer(&hw.mmu, &op.params.dst);
+                self.fpu.push(val);
+            }
+            Op::Fbld => {
+                let bytes = self.read_fpu_bytes(&hw.mmu, &op.params.dst, 10);
+                self.fpu.push(bcd_to_f64(&bytes));
+            }
+            Op::Fst | Op::Fstp => {
+                let val = self.fpu.st(0);
+                self.write_fpu_real(&mut hw.mmu, &op.params.dst, val);
+                if op.command == Op::Fstp {
+                    self.fpu.pop();
+                }
             }
-            Parameter::Ptr32AmodeS16(seg, ref amode, imm) => {
-                let seg = self.segment(seg);
-                let offset = Wrapping(self.amode(amode) as u16) + Wrapping(imm as u16);
-                mmu.write_u32(seg, offset.0, data);
+            Op::Fist | Op::Fistp => {
+                let val = self.fpu.st(0);
+                let size = fpu_operand_size(&op.params.dst);
+                let int = self.fpu.to_int(val, size as u32 * 8);
+                let bytes: Vec<u8> = (0..size).map(|i| (int >> (i * 8)) as u8).collect();
+                self.write_fpu_bytes(&mut hw.mmu, &op.params.dst, &bytes);
+                if op.command == Op::Fistp {
+                    self.fpu.pop();
+                }
+            }
+            Op::Fbstp => {
+                let val = self.fpu.st(0);
+                let rounded = self.fpu.round(val);
+                let bytes = if rounded.is_finite() && rounded.abs() < 1e18 {
+                    i64_to_bcd(rounded as i64).unwrap()
+                } else {
+                    // packed BCD indefinite
+                    self.fpu.set_exception(FPU_IE);
+                    [0, 0, 0, 0, 0, 0, 0, 0xC0, 0xFF, 0xFF]
+                };
+                self.write_fpu_bytes(&mut hw.mmu, &op.params.dst, &bytes);
+                self.fpu.pop();
+            }
+            Op::Fxch => {
+                let i = fpu_register(&op.params.dst);
+                self.fpu.exchange(i);
+            }
+            Op::Fld1 => self.fpu.push(1.),
+            Op::Fldl2t => self.fpu.push(consts::LOG2_10),
+            Op::Fldl2e => self.fpu.push(consts::LOG2_E),
+            Op::Fldpi => self.fpu.push(consts::PI),
+            Op::Fldlg2 => self.fpu.push(consts::LOG10_2),
+            Op::Fldln2 => self.fpu.push(consts::LN_2),
+            Op::Fldz => self.fpu.push(0.),
+            Op::Fadd | Op::Faddp | Op::Fiadd |
+            Op::Fsub | Op::Fsubp | Op::Fisub |
+            Op::Fsubr | Op::Fsubrp | Op::Fisubr |
+            Op::Fmul | Op::Fmulp | Op::Fimul |
+            Op::Fdiv | Op::Fdivp | Op::Fidiv |
+            Op::Fdivr | Op::Fdivrp | Op::Fidivr => {
+                // dst = dst <op> src, dst is a stack register
+                let dst = fpu_register(&op.params.dst);
+                let a = self.fpu.st(dst);
+                let b = match op.command {
+                    Op::Fiadd | Op::Fisub | Op::Fisubr | Op::Fimul | Op::Fidiv | Op::Fidivr => {
+                        self.read_fpu_integer(&hw.mmu, &op.params.src)
+                    }
+                    _ => self.read_fpu_real(&hw.mmu, &op.params.src),
+                };
+                let res = match op.command {
+                    Op::Fadd | Op::Faddp | Op::Fiadd => a + b,
+                    Op::Fsub | Op::Fsubp | Op::Fisub => a - b,
+                    Op::Fsubr | Op::Fsubrp | Op::Fisubr => b - a,
+                    Op::Fmul | Op::Fmulp | Op::Fimul => a * b,
+                    Op::Fdiv | Op::Fdivp | Op::Fidiv => self.fpu.divide(a, b),
+                    _ => self.fpu.divide(b, a),
+                };
+                if res.is_nan() && !a.is_nan() && !b.is_nan() {
+                    // like inf - inf or 0 * inf
+                    self.fpu.set_exception(FPU_IE);
+                }
+                self.fpu.set_st(dst, res);
+                match op.command {
+                    Op::Faddp | Op::Fsubp | Op::Fsubrp | Op::Fmulp | Op::Fdivp | Op::Fdivrp => {
+                        self.fpu.pop();
+                    }
+                    _ => {}
+                }
+            }
+            Op::Fcom | Op::Fcomp | Op::Fucom | Op::Fucomp => {
+                let a = self.fpu.st(0);
+                let b = self.read_fpu_real(&hw.mmu, &op.params.dst);
+                self.fpu.compare(a, b, op.command == Op::Fucom || op.command == Op::Fucomp);
+                if op.command == Op::Fcomp || op.command == Op::Fucomp {
+                    self.fpu.pop();
+                }
+            }
+            Op::Ficom | Op::Ficomp => {
+                let a = self.fpu.st(0);
+                let b = self.read_fpu_integer(&hw.mmu, &op.params.dst);
+                self.fpu.compare(a, b, false);
+                if op.command == Op::Ficomp {
+                    self.fpu.pop();
+                }
+            }
+            Op::Fcompp | Op::Fucompp => {
+                let a = self.fpu.st(0);
+                let b = self.fpu.st(1);
+                self.fpu.compare(a, b, op.command == Op::Fucompp);
+                self.fpu.pop();
+                self.fpu.pop();
+            }
+            Op::Ftst => {
+                let a = self.fpu.st(0);
+                self.fpu.compare(a, 0., false);
+            }
+            Op::Fxam => self.fpu.examine(),
+            Op::Fchs => {
+                let val = self.fpu.st(0);
+                self.fpu.set_st(0, -val);
+            }
+            Op::Fabs => {
+                let val = self.fpu.st(0);
+                self.fpu.set_st(0, val.abs());
+            }
+            Op::Fsqrt => {
+                let val = self.fpu.st(0);
+                if val < 0. {
+                    self.fpu.set_exception(FPU_IE);
+                }
+                self.fpu.set_st(0, val.sqrt());
+            }
+            Op::Fsin | Op::Fcos | Op::Fptan | Op::Fsincos => {
+                let val = self.fpu.st(0);
+                // |x| >= 2^63: operand out of range, ST(0) is left unchanged
+                let out_of_range = val.abs() >= 2f64.powi(63);
+                self.fpu.set_incomplete(out_of_range);
+                if out_of_range {
+                    return;
+                }
+                match op.command {
+                    Op::Fsin => self.fpu.set_st(0, val.sin()),
+                    Op::Fcos => self.fpu.set_st(0, val.cos()),
+                    Op::Fptan => {
+                        self.fpu.set_st(0, val.tan());
+                        self.fpu.push(1.);
+                    }
+                    _ => {
+                        self.fpu.set_st(0, val.sin());
+                        self.fpu.push(val.cos());
+                    }
+                }
+            }
+            Op::Fpatan => {
+                let x = self.fpu.st(0);
+                let y = self.fpu.st(1);
+                self.fpu.set_st(1, y.atan2(x));
+                self.fpu.pop();
+            }
+            Op::F2xm1 => {
+                let val = self.fpu.st(0);
+                self.fpu.set_st(0, (val * consts::LN_2).exp_m1());
+            }
+            Op::Fyl2x | Op::Fyl2xp1 => {
+                let x = self.fpu.st(0);
+                let y = self.fpu.st(1);
+                let res = if op.command == Op::Fyl2x {
+                    if x < 0. {
+                        self.fpu.set_exception(FPU_IE);
+                    }
+                    y * x.log2()
+                } else {
+                    y * x.ln_1p() / consts::LN_2
+                };
+                self.fpu.set_st(1, res);
+                self.fpu.pop();
+            }
+            Op::Fscale => {
+                let val = self.fpu.st(0);
+                let scale = self.fpu.st(1).trunc();
+                self.fpu.set_st(0, val * scale.exp2());
+            }
+            Op::Frndint => {
+                let val = self.fpu.st(0);
+                let res = self.fpu.round(val);
+                self.fpu.set_st(0, res);
+            }
+            Op::Fprem | Op::Fprem1 => {
+                let a = self.fpu.st(0);
+                let b = self.fpu.st(1);
+                let res = self.fpu.remainder(a, b, op.command == Op::Fprem1);
+                self.fpu.set_st(0, res);
+            }
+            Op::Fxtract => {
+                let val = self.fpu.st(0);
+                if val == 0. {
+                    self.fpu.set_exception(FPU_ZE);
+                    self.fpu.set_st(0, ::std::f64::NEG_INFINITY);
+                    self.fpu.push(val);
+                } else {
+                    let exp = val.abs().log2().floor();
+                    self.fpu.set_st(0, exp);
+                    self.fpu.push(val / exp.exp2());
+                }
+            }
+            Op::Fninit => self.fpu.init(),
+            Op::Fnclex => self.fpu.clear_exceptions(),
+            Op::Fnstsw => {
+                let val = self.fpu.status_word();
+                self.write_parameter_u16(&mut hw.mmu, op.segment_prefix, &op.params.dst, val);
+            }
+            Op::Fnstcw => {
+                let val = self.fpu.control;
+                self.write_parameter_u16(&mut hw.mmu, op.segment_prefix, &op.params.dst, val);
+            }
+            Op::Fldcw => {
+                self.fpu.control = self.read_parameter_value(&hw.mmu, &op.params.dst) as u16;
+            }
+            Op::Fnstenv => {
+                self.store_fpu_environment(&mut hw.mmu, op);
+            }
+            Op::Fldenv => {
+                self.load_fpu_environment(&hw.mmu, op);
+            }
+            Op::Fnsave => {
+                let len = self.store_fpu_environment(&mut hw.mmu, op);
+                let (seg, offset) = self.memory_address(&op.params.dst);
+                for i in 0..8 {
+                    let (significand, exp) = f64_to_f80(self.fpu.st_raw(i));
+                    let offset = offset.wrapping_add(u32::from(len) + i as u32 * 10);
+                    for j in 0..8 {
+                        self.write_mem_u8(&mut hw.mmu, seg, offset.wrapping_add(j), (significand >> (j * 8)) as u8);
+                    }
+                    self.write_mem_u16(&mut hw.mmu, seg, offset.wrapping_add(8), exp);
+                }
+                self.fpu.init();
+            }
+            Op::Frstor => {
+                let len = self.load_fpu_environment(&hw.mmu, op);
+                let (seg, offset) = self.memory_address(&op.params.dst);
+                for i in 0..8 {
+                    let offset = offset.wrapping_add(u32::from(len) + i as u32 * 10);
+                    let mut significand = 0;
+                    for j in 0..8 {
+                        significand |= u64::from(self.read_mem_u8(&hw.mmu, seg, offset.wrapping_add(j))) << (j * 8);
+                    }
+                    let exp = self.read_mem_u16(&hw.mmu, seg, offset.wrapping_add(8));
+                    self.fpu.set_st_raw(i, f80_to_f64(significand, exp));
+                }
+            }
+            Op::Ffree => {
+                let i = fpu_register(&op.params.dst);
+                self.fpu.free(i);
+            }
+            Op::Fincstp => self.fpu.increment_top(),
+            Op::Fdecstp => self.fpu.decrement_top(),
+            Op::Fnop | Op::Fwait => {}
+            _ => unreachable!(),
+        }
+    }
+
+    /// writes the FPU environment as used by FNSTENV and FNSAVE, returns its size in bytes
+    fn store_fpu_environment(&mut self, mmu: &mut MMU, op: &Instruction) -> u16 {
+        let (seg, offset) = self.memory_address(&op.params.dst);
+        // XXX the instruction and operand pointers are not tracked and stored as 0
+        let fields = [self.fpu.control, self.fpu.status_word(), self.fpu.tag_word(), 0, 0, 0, 0];
+        let width = match op.op_size {
+            OperandSize::_16bit => 2,
+            OperandSize::_32bit => 4,
+        };
+        for (i, val) in fields.iter().enumerate() {
+            let offset = offset.wrapping_add(u32::from(i as u16 * width));
+            self.write_mem_u16(mmu, seg, offset, *val);
+            if width == 4 {
+                self.write_mem_u16(mmu, seg, offset.wrapping_add(2), 0);
             }
-            _ => panic!("unhandled type {:?} at {:06X}", p, self.get_address()),
         }
+        width * 7
+    }
+
+    /// reads the FPU environment as used by FLDENV and FRSTOR, returns its size in bytes
+    fn load_fpu_environment(&mut self, mmu: &MMU, op: &Instruction) -> u16 {
+        let (seg, offset) = self.memory_address(&op.params.dst);
+        let width = match op.op_size {
+            OperandSize::_16bit => 2,
+            OperandSize::_32bit => 4,
+        };
+        self.fpu.control = self.read_mem_u16(mmu, seg, offset);
+        let status = self.read_mem_u16(mmu, seg, offset.wrapping_add(u32::from(width)));
+        self.fpu.set_status_word(status);
+        let tag = self.read_mem_u16(mmu, seg, offset.wrapping_add(u32::from(width * 2)));
+        self.fpu.set_tag_word(tag);
+        width * 7
     }
 
-    /// returns the value of the given segment register
-    fn segment(&self, seg: Segment) -> u16 {
-        self.get_r16(seg.as_register())
+    /// reads a floating point operand: a stack register, or a 32, 64 or 80-bit real in memory
+    fn read_fpu_real(&mut self, mmu: &MMU, p: &Parameter) -> f64 {
+        if let Parameter::ST(i) = *p {
+            return self.fpu.st(usize::from(i));
+        }
+        let bytes = self.read_fpu_bytes(mmu, p, fpu_operand_size(p));
+        let mut val = 0u64;
+        for (i, b) in bytes.iter().take(8).enumerate() {
+            val |= u64::from(*b) << (i * 8);
+        }
+        match bytes.len() {
+            4 => f64::from(f32::from_bits(val as u32)),
+            8 => f64::from_bits(val),
+            _ => f80_to_f64(val, u16::from(bytes[8]) | u16::from(bytes[9]) << 8),
+        }
+    }
+
+    /// writes a floating point operand: a stack register, or a 32, 64 or 80-bit real in memory
+    fn write_fpu_real(&mut self, mmu: &mut MMU, p: &Parameter, val: f64) {
+        if let Parameter::ST(i) = *p {
+            self.fpu.set_st(usize::from(i), val);
+            return;
+        }
+        let bytes: Vec<u8> = match fpu_operand_size(p) {
+            4 => (0..4).map(|i| ((val as f32).to_bits() >> (i * 8)) as u8).collect(),
+            8 => (0..8).map(|i| (val.to_bits() >> (i * 8)) as u8).collect(),
+            _ => {
+                let (significand, exp) = f64_to_f80(val);
+                (0..10).map(|i| if i < 8 {
+                    (significand >> (i * 8)) as u8
+                } else {
+                    (exp >> ((i - 8) * 8)) as u8
+                }).collect()
+            }
+        };
+        self.write_fpu_bytes(mmu, p, &bytes);
+    }
+
+    /// reads a signed 16, 32 or 64-bit integer operand
+    fn read_fpu_integer(&mut self, mmu: &MMU, p: &Parameter) -> f64 {
+        let bytes = self.read_fpu_bytes(mmu, p, fpu_operand_size(p));
+        let mut val = 0u64;
+        for (i, b) in bytes.iter().enumerate() {
+            val |= u64::from(*b) << (i * 8);
+        }
+        match bytes.len() {
+            2 => f64::from(val as i16),
+            4 => f64::from(val as i32),
+            _ => val as i64 as f64,
+        }
+    }
+
+    fn read_fpu_bytes(&mut self, mmu: &MMU, p: &Parameter, len: usize) -> Vec<u8> {
+        let (seg, offset) = self.memory_address(p);
+        (0..len).map(|i| self.read_mem_u8(mmu, seg, offset.wrapping_add(i as u32))).collect()
+    }
+
+    fn write_fpu_bytes(&mut self, mmu: &mut MMU, p: &Parameter, bytes: &[u8]) {
+        let (seg, offset) = self.memory_address(p);
+        for (i, b) in bytes.iter().enumerate() {
+            self.write_mem_u8(mmu, seg, offset.wrapping_add(i as u32), *b);
+        }
+    }
+
+    /// returns the segment register and offset of a memory operand. operands based on BP, EBP or
+    /// ESP address the stack segment unless a segment prefix is used. offsets of the 16-bit
+    /// addressing modes wrap at 64k
+    fn memory_address(&self, p: &Parameter) -> (R, u32) {
+        match *p {
+            Parameter::Ptr8(seg, imm) |
+            Parameter::Ptr16(seg, imm) |
+            Parameter::Ptr32(seg, imm) |
+            Parameter::Ptr64(seg, imm) |
+            Parameter::Ptr80(seg, imm) => (seg.as_register(), u32::from(imm)),
+            Parameter::Ptr8Amode(seg, ref amode) |
+            Parameter::Ptr16Amode(seg, ref amode) |
+            Parameter::Ptr32Amode(seg, ref amode) |
+            Parameter::Ptr64Amode(seg, ref amode) |
+            Parameter::Ptr80Amode(seg, ref amode) => (amode_segment(seg, amode), self.amode_offset(amode, 0)),
+            Parameter::Ptr8AmodeS8(seg, ref amode, imm) |
+            Parameter::Ptr16AmodeS8(seg, ref amode, imm) |
+            Parameter::Ptr32AmodeS8(seg, ref amode, imm) |
+            Parameter::Ptr64AmodeS8(seg, ref amode, imm) |
+            Parameter::Ptr80AmodeS8(seg, ref amode, imm) => {
+                (amode_segment(seg, amode), self.amode_offset(amode, i32::from(imm)))
+            }
+            Parameter::Ptr8AmodeS16(seg, ref amode, imm) |
+            Parameter::Ptr16AmodeS16(seg, ref amode, imm) |
+            Parameter::Ptr32AmodeS16(seg, ref amode, imm) |
+            Parameter::Ptr64AmodeS16(seg, ref amode, imm) |
+            Parameter::Ptr80AmodeS16(seg, ref amode, imm) => {
+                (amode_segment(seg, amode), self.amode_offset(amode, i32::from(imm)))
+            }
+            Parameter::Ptr8AmodeS32(seg, ref amode, imm) |
+            Parameter::Ptr16AmodeS32(seg, ref amode, imm) |
+            Parameter::Ptr32AmodeS32(seg, ref amode, imm) |
+            Parameter::Ptr64AmodeS32(seg, ref amode, imm) |
+            Parameter::Ptr80AmodeS32(seg, ref amode, imm) => {
+                (amode_segment(seg, amode), self.amode_offset(amode, imm))
+            }
+            _ => panic!("unhandled parameter: {:?} at {:06X}", p, self.get_address()),
+        }
+    }
+
+    /// returns the offset addressed by amode plus a displacement
+    fn amode_offset(&self, amode: &AMode, disp: i32) -> u32 {
+        let offset = (self.amode(amode) as u32).wrapping_add(disp as u32);
+        if amode.is_32bit() {
+            offset
+        } else {
+            offset & 0xFFFF
+        }
     }
 
     fn amode(&self, amode: &AMode) -> usize {
@@ -2477,6 +3969,57 @@ impl CPU {
             AMode::EBP => self.get_r32(R::EBP) as usize,
             AMode::ESI => self.get_r32(R::ESI) as usize,
             AMode::EDI => self.get_r32(R::EDI) as usize,
+
+            AMode::SIB(base, index, scale) => {
+                let base = match base {
+                    Some(r) => self.get_r32(r),
+                    None => 0,
+                };
+                let index = match index {
+                    Some(r) => self.get_r32(r),
+                    None => 0,
+                };
+                base.wrapping_add(index.wrapping_mul(u32::from(scale))) as usize
+            }
+        }
+    }
+
+    /// used by bt, btc, btr, bts
+    fn bit_test(&mut self, hw: &mut Hardware, op: &Instruction) {
+        let bits = match op.op_size {
+            OperandSize::_16bit => 16,
+            OperandSize::_32bit => 32,
+        };
+        let offset = self.read_parameter_value(&hw.mmu, &op.params.src);
+        let bit = offset & (bits - 1);
+        if op.params.dst.is_ptr() && !op.params.src.is_imm() {
+            // a register bit offset is signed and selects a bit anywhere in the bit string at dst
+            let (seg, off) = self.memory_address(&op.params.dst);
+            let disp = match op.op_size {
+                OperandSize::_16bit => i32::from(offset as u16 as i16 >> 4) * 2,
+                OperandSize::_32bit => (offset as u32 as i32 >> 5) * 4,
+            };
+            let off = off.wrapping_add(disp as u32);
+            let val = match op.op_size {
+                OperandSize::_16bit => self.read_mem_u16(&hw.mmu, seg, off) as usize,
+                OperandSize::_32bit => self.read_mem_u32(&hw.mmu, seg, off) as usize,
+            };
+            self.regs.flags.carry = val & (1 << bit) != 0;
+            if let Some(res) = bit_test_result(&op.command, val, bit) {
+                match op.op_size {
+                    OperandSize::_16bit => self.write_mem_u16(&mut hw.mmu, seg, off, res as u16),
+                    OperandSize::_32bit => self.write_mem_u32(&mut hw.mmu, seg, off, res as u32),
+                }
+            }
+        } else {
+            let val = self.read_parameter_value(&hw.mmu, &op.params.dst);
+            self.regs.flags.carry = val & (1 << bit) != 0;
+            if let Some(res) = bit_test_result(&op.command, val, bit) {
+                match op.op_size {
+                    OperandSize::_16bit => self.write_parameter_u16(&mut hw.mmu, op.segment_prefix, &op.params.dst, res as u16),
+                    OperandSize::_32bit => self.write_parameter_u32(&mut hw.mmu, op.segment_prefix, &op.params.dst, res as u32),
+                }
+            }
         }
     }
 
@@ -2524,36 +4067,94 @@ impl CPU {
         self.regs.flags.set_parity(al as usize);
     }
 
-    fn int(&mut self, hw: &mut Hardware, int: u8) {
-        let flags = self.regs.flags.u16();
+    /// INT n. in protected mode the privilege level of the gate is checked
+    fn software_int(&mut self, hw: &mut Hardware, int: u8) {
+        if self.protected_mode() {
+            self.protected_mode_int(hw, int, true);
+        } else {
+            self.int(hw, int);
+        }
+    }
+
+    fn protected_mode_int(&mut self, hw: &mut Hardware, int: u8, software: bool) {
+        self.last_exception = None;
+        match self.protected_mode_interrupt(&mut hw.mmu, int, None, software) {
+            Ok(width) => {
+                // the flags are above the pushed IP and CS
+                let addr = self.segment_base(R::SS).wrapping_add(self.stack_pointer().wrapping_add(width * 2));
+                hw.bios.flags_address = MemoryAddress::LongSegmentOffset((addr >> 16) as u16, addr as u16);
+            }
+            Err((which, error)) => self.deliver_exception(hw, which, error),
+        }
+    }
+
+    /// delivers a interrupt through the interrupt vector table, or the IDT in protected mode
+    pub fn int(&mut self, hw: &mut Hardware, int: u8) {
+        if self.protected_mode() {
+            return self.protected_mode_int(hw, int, false);
+        }
+        let flags = self.flags_u16();
         self.push16(&mut hw.mmu, flags);
         hw.bios.flags_address = MemoryAddress::RealSegmentOffset(self.get_r16(R::SS), self.get_r16(R::SP));
 
         self.regs.flags.interrupt = false;
         self.regs.flags.trap = false;
-        let (cs, ip) = self.get_address_pair();
-        self.push16(&mut hw.mmu, cs);
-        self.push16(&mut hw.mmu, ip);
-        let base = 0;
-        let idx = u16::from(int) << 2;
-        let ip = hw.mmu.read_u16(base, idx);
-        let cs = hw.mmu.read_u16(base, idx + 2);
-        // println!("int: jumping to interrupt handler for interrupt {:02X} pos at {:04X}:{:04X} = {:04X}:{:04X}", int, base, idx, cs, ip);
-        self.regs.ip = ip;
-        self.set_r16(R::CS, cs);
+        self.single_step = false;
+        self.push_return_address(&mut hw.mmu, false);
+        let base = self.idtr.base;
+        let idx = u32::from(int) << 2;
+        let ip = hw.mmu.read_linear_u16(base + idx);
+        let cs = hw.mmu.read_linear_u16(base + idx + 2);
+        // println!("int: jumping to interrupt handler for interrupt {:02X} pos at {:06X} = {:04X}:{:04X}", int, base + idx, cs, ip);
+        self.regs.ip = u32::from(ip);
+        self.set_segment(&mut hw.mmu, R::CS, cs, Descriptor::real_mode(cs));
+    }
+
+    /// returns the return address of the interrupt being handled, read from the stack
+    fn interrupted_address(&self, mmu: &MMU) -> (u16, u16) {
+        let ss = self.get_r16(R::SS);
+        let sp = self.get_r16(R::SP);
+        let ip = mmu.read_u16(ss, sp);
+        let cs = mmu.read_u16(ss, sp.wrapping_add(2));
+        (cs, ip)
     }
 
     pub fn handle_interrupt(&mut self, mut hw: &mut Hardware, int: u8) {
         match int {
-            0x03 => {
-                // debugger interrupt
+            0x00 => {
+                // divide error, no handler was installed by the program
+                let (cs, ip) = self.interrupted_address(&hw.mmu);
+                println!("[{:04X}:{:04X}] Divide overflow", cs, ip);
+                self.fatal_error = true; // stops execution
+            }
+            0x01 | 0x03 => {
+                // single step and breakpoint, no debugger was installed by the program
                 // http://www.ctyme.com/intr/int-03.htm
-                println!("INT 3 - debugger interrupt. AX={:04X}", self.get_r16(R::AX));
+            }
+            0x06 => {
+                // invalid opcode, no handler was installed by the program
+                let (cs, ip) = self.interrupted_address(&hw.mmu);
+                let op = self.decoder.get_instruction(&mut hw.mmu, cs, u32::from(ip));
+                if let Op::Invalid(bytes, reason) = op.command {
+                    let hex = hex_bytes(&bytes);
+                    match reason {
+                        Invalid::Op => println!("[{:04X}:{:04X}] {} ERROR: unhandled opcode", cs, ip, hex),
+                        Invalid::FPUOp => println!("[{:04X}:{:04X}] {} ERROR: unhandled FPU opcode", cs, ip, hex),
+                        Invalid::Reg(reg) => println!("[{:04X}:{:04X}] {} ERROR: unhandled reg value {:02X}", cs, ip, hex, reg),
+                    }
+                    println!("ndisasm: {}", ndisasm_first_instr(&hw.mmu.read(cs, ip, 16)).unwrap());
+                }
+                println!("{} Instructions executed", self.instruction_count);
                 self.fatal_error = true; // stops execution
             }
+            0x08 => interrupt::int08::handle(self, &mut hw),
+            0x09 => interrupt::int09::handle(self, &mut hw),
             0x10 => interrupt::int10::handle(self, &mut hw),
             0x16 => interrupt::int16::handle(self, &mut hw),
             0x1A => interrupt::int1a::handle(self, &mut hw),
+            0x1C => {
+                // user timer tick, called by the system timer. programs hook it
+            }
             0x20 => {
                 // DOS 1+ - TERMINATE PROGRAM
                 // NOTE: Windows overloads INT 20
@@ -2561,6 +4162,8 @@ impl CPU {
                 self.fatal_error = true; // stops execution
             }
             0x21 => interrupt::int21::handle(self, &mut hw),
+            0x2F => interrupt::int2f::handle(self, &mut hw),
+            0x31 => interrupt::int31::handle(self, &mut hw),
             0x33 => interrupt::int33::handle(self, &mut hw),
             _ => {
                 println!("int error: unknown interrupt {:02X}, AX={:04X}, BX={:04X}",
@@ -2572,6 +4175,76 @@ impl CPU {
     }
 }
 
+/// returns true for the 16-bit source operands of movzx r/m16 and movsx r32, r/m16
+fn is_word_operand(p: &Parameter) -> bool {
+    match *p {
+        Parameter::Reg16(_) | Parameter::Ptr16(..) | Parameter::Ptr16Amode(..) | Parameter::Ptr16AmodeS8(..) |
+        Parameter::Ptr16AmodeS16(..) | Parameter::Ptr16AmodeS32(..) => true,
+        _ => false,
+    }
+}
+
+/// returns true for mov ss and pop ss
+fn loads_ss(op: &Instruction) -> bool {
+    match (&op.command, &op.params.dst) {
+        (&Op::Mov16, &Parameter::SReg16(R::SS)) | (&Op::Pop16, &Parameter::SReg16(R::SS)) => true,
+        _ => false,
+    }
+}
+
+/// returns the segment register of a memory operand using amode
+fn amode_segment(seg: Segment, amode: &AMode) -> R {
+    match seg {
+        Segment::Default if amode.is_stack_based() => R::SS,
+        _ => seg.as_register(),
+    }
+}
+
+/// returns the new value of the bit string operand of bt, btc, btr, bts
+fn bit_test_result(op: &Op, val: usize, bit: usize) -> Option<usize> {
+    match *op {
+        Op::Btc => Some(val ^ (1 << bit)),
+        Op::Btr => Some(val & !(1 << bit)),
+        Op::Bts => Some(val | (1 << bit)),
+        _ => None,
+    }
+}
+
+/// returns the stack register number of a x87 operand
+fn fpu_register(p: &Parameter) -> usize {
+    match *p {
+        Parameter::ST(i) => usize::from(i),
+        _ => panic!("expected a fpu register: {:?}", p),
+    }
+}
+
+/// returns the size in bytes of a x87 memory operand
+fn fpu_operand_size(p: &Parameter) -> usize {
+    match *p {
+        Parameter::Ptr16(_, _) |
+        Parameter::Ptr16Amode(_, _) |
+        Parameter::Ptr16AmodeS8(_, _, _) |
+        Parameter::Ptr16AmodeS16(_, _, _) |
+        Parameter::Ptr16AmodeS32(_, _, _) => 2,
+        Parameter::Ptr32(_, _) |
+        Parameter::Ptr32Amode(_, _) |
+        Parameter::Ptr32AmodeS8(_, _, _) |
+        Parameter::Ptr32AmodeS16(_, _, _) |
+        Parameter::Ptr32AmodeS32(_, _, _) => 4,
+        Parameter::Ptr64(_, _) |
+        Parameter::Ptr64Amode(_, _) |
+        Parameter::Ptr64AmodeS8(_, _, _) |
+        Parameter::Ptr64AmodeS16(_, _, _) |
+        Parameter::Ptr64AmodeS32(_, _, _) => 8,
+        Parameter::Ptr80(_, _) |
+        Parameter::Ptr80Amode(_, _) |
+        Parameter::Ptr80AmodeS8(_, _, _) |
+        Parameter::Ptr80AmodeS16(_, _, _) |
+        Parameter::Ptr80AmodeS32(_, _, _) => 10,
+        _ => panic!("expected a fpu memory operand: {:?}", p),
+    }
+}
+
 fn count_to_bitmask(v: usize) -> usize {
     match v {
         0  => 0,
@@ -4,6 +4,8 @@ use machine::Machine;
 use cpu::flag::Flags;
 use cpu::register::R;
 use cpu::segment::Segment;
+use cpu::model::CpuModel;
+use cpu::Engine;
 use memory::MMU;
 
 #[test]
@@ -616,6 +618,8 @@ fn can_execute_32bit_addressing() {
     ];
 
     machine.load_executable(&code);
+    // a dword at the initial DI of 0xFFFE would run past the end of the segment
+    machine.cpu.set_r16(R::DI, 0x1000);
 
     let res = machine.cpu.decoder.disassemble_block_to_str(&mut machine.hw.mmu, 0x85F, 0x100, 5);
     assert_eq!("[085F:0100] 66BB00020000     Mov32    ebx, 0x00000200
@@ -641,6 +645,34 @@ fn can_execute_32bit_addressing() {
     assert_eq!(0x0000_0200, machine.hw.mmu.read_u32(ds, di - 0x140));
 }
 
+#[test]
+fn can_execute_sib_addressing() {
+    let mut machine = Machine::default();
+    let code: Vec<u8> = vec![
+        0x66, 0xBB, 0x00, 0x02, 0x00, 0x00,                   // mov ebx,0x200
+        0x66, 0xB9, 0x02, 0x00, 0x00, 0x00,                   // mov ecx,0x2
+        0x67, 0x66, 0x8B, 0x44, 0x8B, 0x10,                   // mov eax,[ebx+ecx*4+0x10]
+        0x67, 0x8D, 0x14, 0xCB,                               // lea dx,[ebx+ecx*8]
+        0x67, 0x89, 0x14, 0x8D, 0x00, 0x03, 0x00, 0x00,       // mov [ecx*4+0x300],dx
+        0x67, 0xA0, 0x19, 0x02, 0x00, 0x00,                   // mov al,[dword 0x219]
+    ];
+    machine.load_executable(&code);
+    let ds = machine.cpu.get_r16(R::DS);
+    machine.hw.mmu.write_u32(ds, 0x218, 0x1122_3344);
+
+    machine.execute_instructions(3);
+    assert_eq!(0x1122_3344, machine.cpu.get_r32(R::EAX));
+
+    machine.execute_instruction();
+    assert_eq!(0x0210, machine.cpu.get_r16(R::DX));
+
+    machine.execute_instruction();
+    assert_eq!(0x0210, machine.hw.mmu.read_u16(ds, 0x308));
+
+    machine.execute_instruction();
+    assert_eq!(0x33, machine.cpu.get_r8(R::AL));
+}
+
 #[test]
 fn can_execute_math() {
     let mut machine = Machine::default();
@@ -777,6 +809,24 @@ fn can_execute_div16() {
     assert_eq!(0x0000, machine.cpu.get_r16(R::DX)); // remainder
 }
 
+#[test]
+fn can_execute_div32() {
+    let mut machine = Machine::default();
+    let code: Vec<u8> = vec![
+        0x66, 0xBA, 0x01, 0x00, 0x00, 0x00, // mov edx,0x1
+        0x66, 0xB8, 0x05, 0x00, 0x00, 0x00, // mov eax,0x5
+        0x66, 0xBB, 0x02, 0x00, 0x00, 0x00, // mov ebx,0x2
+        0x66, 0xF7, 0xF3,                   // div ebx             ; 0x1_0000_0005 / 2 = 0x8000_0002
+    ];
+    machine.load_executable(&code);
+
+    machine.execute_instructions(4);
+    assert_eq!(false, machine.cpu.fatal_error);
+    assert_eq!(0x0115, machine.cpu.regs.ip);
+    assert_eq!(0x8000_0002, machine.cpu.get_r32(R::EAX)); // quotient
+    assert_eq!(0x0000_0001, machine.cpu.get_r32(R::EDX)); // remainder
+}
+
 #[test]
 fn can_execute_idiv8() {
     let mut machine = Machine::default();
@@ -989,8 +1039,11 @@ fn can_execute_bts() {
     let res = machine.cpu.decoder.disassemble_block_to_str(&mut machine.hw.mmu, 0x85F, 0x100, 1);
     assert_eq!("[085F:0100] 0FBA2EAE010F     Bts      word [ds:0x01AE], 0x0F", res);
 
-    // XXX also test emulation
+    let ds = machine.cpu.get_r16(R::DS);
+    machine.hw.mmu.write_u16(ds, 0x1AE, 0x0001);
     machine.execute_instructions(1);
+    assert_eq!(0x8001, machine.hw.mmu.read_u16(ds, 0x1AE));
+    assert_eq!(false, machine.cpu.regs.flags.carry);
 }
 
 #[test]
@@ -1038,6 +1091,166 @@ fn can_execute_bt() {
     assert_eq!(true, machine.cpu.regs.flags.carry);
 }
 
+#[test]
+fn can_execute_bit_string_instructions() {
+    let mut machine = Machine::default();
+    let code: Vec<u8> = vec![
+        0xBB, 0x00, 0x02,       // mov bx,0x200
+        0xB8, 0x13, 0x00,       // mov ax,0x13
+        0x0F, 0xAB, 0x07,       // bts [bx],ax      ; bit 3 of word [bx+2]
+        0xB8, 0xFF, 0xFF,       // mov ax,0xffff
+        0x0F, 0xB3, 0x07,       // btr [bx],ax      ; bit 15 of word [bx-2]
+        0x0F, 0xBB, 0x07,       // btc [bx],ax
+        0x0F, 0xBA, 0xF9, 0x01, // btc cx,0x1
+    ];
+    machine.load_executable(&code);
+    let ds = machine.cpu.get_r16(R::DS);
+    machine.hw.mmu.write_u16(ds, 0x1FE, 0x8000);
+
+    machine.execute_instructions(3);
+    assert_eq!(0x0008, machine.hw.mmu.read_u16(ds, 0x202));
+    assert_eq!(false, machine.cpu.regs.flags.carry);
+
+    machine.execute_instructions(2);
+    assert_eq!(0x0000, machine.hw.mmu.read_u16(ds, 0x1FE));
+    assert_eq!(true, machine.cpu.regs.flags.carry);
+
+    machine.execute_instruction();
+    assert_eq!(0x8000, machine.hw.mmu.read_u16(ds, 0x1FE));
+    assert_eq!(false, machine.cpu.regs.flags.carry);
+
+    machine.cpu.set_r16(R::CX, 0x0003);
+    machine.execute_instruction();
+    assert_eq!(0x0001, machine.cpu.get_r16(R::CX));
+    assert_eq!(true, machine.cpu.regs.flags.carry);
+}
+
+#[test]
+fn can_execute_bsr() {
+    let mut machine = Machine::default();
+    let code: Vec<u8> = vec![
+        0xB8, 0x14, 0x00,       // mov ax,0x14
+        0x0F, 0xBD, 0xD0,       // bsr dx,ax
+        0x66, 0x0F, 0xBD, 0xD1, // bsr edx,ecx
+    ];
+    machine.load_executable(&code);
+
+    machine.execute_instructions(2);
+    assert_eq!(4, machine.cpu.get_r16(R::DX));
+    assert_eq!(false, machine.cpu.regs.flags.zero);
+
+    machine.cpu.set_r32(R::ECX, 0x8000_0001);
+    machine.execute_instruction();
+    assert_eq!(31, machine.cpu.get_r32(R::EDX));
+    assert_eq!(false, machine.cpu.regs.flags.zero);
+}
+
+#[test]
+fn can_execute_xadd_cmpxchg_bswap() {
+    let mut machine = Machine::default();
+    machine.set_cpu_model(CpuModel::I80486);
+    let code: Vec<u8> = vec![
+        0xB8, 0x01, 0x00,       // mov ax,0x1
+        0xBB, 0xFF, 0xFF,       // mov bx,0xffff
+        0x0F, 0xC1, 0xC3,       // xadd bx,ax
+        0xB8, 0x00, 0x00,       // mov ax,0x0
+        0xB9, 0x34, 0x12,       // mov cx,0x1234
+        0x0F, 0xB1, 0xCB,       // cmpxchg bx,cx
+        0x0F, 0xB1, 0xCB,       // cmpxchg bx,cx
+        0x66, 0xBA, 0x44, 0x33, 0x22, 0x11, // mov edx,0x11223344
+        0x0F, 0xCA,             // bswap edx
+    ];
+    machine.load_executable(&code);
+
+    machine.execute_instructions(3);
+    assert_eq!(0x0000, machine.cpu.get_r16(R::BX));
+    assert_eq!(0xFFFF, machine.cpu.get_r16(R::AX));
+    assert_eq!(true, machine.cpu.regs.flags.carry);
+    assert_eq!(true, machine.cpu.regs.flags.zero);
+
+    // equal: bx = cx
+    machine.execute_instructions(3);
+    assert_eq!(0x1234, machine.cpu.get_r16(R::BX));
+    assert_eq!(true, machine.cpu.regs.flags.zero);
+
+    // not equal: ax = bx
+    machine.execute_instruction();
+    assert_eq!(0x1234, machine.cpu.get_r16(R::AX));
+    assert_eq!(false, machine.cpu.regs.flags.zero);
+
+    machine.execute_instructions(2);
+    assert_eq!(0x4433_2211, machine.cpu.get_r32(R::EDX));
+}
+
+#[test]
+fn can_execute_far_pointer_loads() {
+    let mut machine = Machine::default();
+    let code: Vec<u8> = vec![
+        0x0F, 0xB2, 0x26, 0x00, 0x02, // lss sp,[0x200]
+        0x0F, 0xB4, 0x06, 0x04, 0x02, // lfs ax,[0x204]
+        0x0F, 0xB5, 0x1E, 0x08, 0x02, // lgs bx,[0x208]
+    ];
+    machine.load_executable(&code);
+    let ds = machine.cpu.get_r16(R::DS);
+    machine.hw.mmu.write_u16(ds, 0x200, 0xFFFE);
+    machine.hw.mmu.write_u16(ds, 0x202, 0x2000);
+    machine.hw.mmu.write_u16(ds, 0x204, 0x1122);
+    machine.hw.mmu.write_u16(ds, 0x206, 0x3344);
+    machine.hw.mmu.write_u16(ds, 0x208, 0x5566);
+    machine.hw.mmu.write_u16(ds, 0x20A, 0x7788);
+
+    machine.execute_instructions(3);
+    assert_eq!(0xFFFE, machine.cpu.get_r16(R::SP));
+    assert_eq!(0x2000, machine.cpu.get_r16(R::SS));
+    assert_eq!(0x1122, machine.cpu.get_r16(R::AX));
+    assert_eq!(0x3344, machine.cpu.get_r16(R::FS));
+    assert_eq!(0x5566, machine.cpu.get_r16(R::BX));
+    assert_eq!(0x7788, machine.cpu.get_r16(R::GS));
+}
+
+#[test]
+fn can_execute_far_pointer_loads_m16_32() {
+    let mut machine = Machine::default();
+    let code: Vec<u8> = vec![
+        0x66, 0x0F, 0xB2, 0x26, 0x00, 0x02, // lss esp,[0x200]
+        0x66, 0x0F, 0xB4, 0x1E, 0x06, 0x02, // lfs ebx,[0x206]
+    ];
+    machine.load_executable(&code);
+    let ds = machine.cpu.get_r16(R::DS);
+    machine.hw.mmu.write_u32(ds, 0x200, 0x0001_FFFC);
+    machine.hw.mmu.write_u16(ds, 0x204, 0x2000);
+    machine.hw.mmu.write_u32(ds, 0x206, 0x1122_3344);
+    machine.hw.mmu.write_u16(ds, 0x20A, 0x5566);
+
+    machine.execute_instructions(2);
+    assert_eq!(0x0001_FFFC, machine.cpu.get_r32(R::ESP));
+    assert_eq!(0x2000, machine.cpu.get_r16(R::SS));
+    assert_eq!(0x1122_3344, machine.cpu.get_r32(R::EBX));
+    assert_eq!(0x5566, machine.cpu.get_r16(R::FS));
+}
+
+#[test]
+fn can_execute_cpuid() {
+    let mut machine = Machine::default();
+    machine.set_cpu_model(CpuModel::I80486);
+    let code: Vec<u8> = vec![
+        0x66, 0x31, 0xC0, // xor eax,eax
+        0x0F, 0xA2,       // cpuid
+        0x0F, 0xA2,       // cpuid              ; eax = 1 from the first call
+    ];
+    machine.load_executable(&code);
+
+    machine.execute_instructions(2);
+    assert_eq!(1, machine.cpu.get_r32(R::EAX));
+    assert_eq!(0x756E_6547, machine.cpu.get_r32(R::EBX)); // "Genu"
+    assert_eq!(0x4965_6E69, machine.cpu.get_r32(R::EDX)); // "ineI"
+    assert_eq!(0x6C65_746E, machine.cpu.get_r32(R::ECX)); // "ntel"
+
+    machine.execute_instruction();
+    assert_eq!(0x0430, machine.cpu.get_r32(R::EAX));
+    assert_eq!(1, machine.cpu.get_r32(R::EDX) & 1); // FPU on chip
+}
+
 #[test]
 fn can_execute_daa() {
     let mut machine = Machine::default();
@@ -1204,9 +1417,15 @@ fn can_execute_jmp_far_mem() {
         0xFF, 0x28,       // jmp far [bx+si]
     ];
     machine.load_executable(&code);
+    // far pointer 085F:CC40 at [bx+si], the low byte is written by the program
+    let cs = machine.cpu.get_r16(R::CS);
+    machine.hw.mmu.write_u8(cs, 0xCCAB, 0xCC);
+    machine.hw.mmu.write_u16(cs, 0xCCAC, cs);
+    machine.hw.mmu.write_u8(cs, 0xCC40, 0x40); // inc ax
 
     machine.execute_instructions(6);
-    assert_eq!(0xCCAB, machine.cpu.regs.ip);
+    assert_eq!(0x085F, machine.cpu.get_r16(R::CS));
+    assert_eq!(0xCC41, machine.cpu.regs.ip);
     assert_eq!(0x0001, machine.cpu.get_r16(R::AX));
 }
 
@@ -1228,6 +1447,30 @@ fn can_execute_setc() {
     assert_eq!(0x00, machine.cpu.get_r8(R::AL));
 }
 
+#[test]
+fn can_execute_setcc() {
+    let mut machine = Machine::default();
+    let code: Vec<u8> = vec![
+        0xB0, 0x80,       // mov al,0x80
+        0x3C, 0x01,       // cmp al,0x1
+        0x0F, 0x9C, 0xC3, // setl bl
+        0x0F, 0x9F, 0xC7, // setg bh
+        0x0F, 0x97, 0xC1, // seta cl
+        0x0F, 0x96, 0xC5, // setna ch
+        0x0F, 0x90, 0xC2, // seto dl
+        0x0F, 0x9B, 0xC6, // setpo dh
+    ];
+    machine.load_executable(&code);
+
+    machine.execute_instructions(8);
+    assert_eq!(0x01, machine.cpu.get_r8(R::BL)); // -128 < 1
+    assert_eq!(0x00, machine.cpu.get_r8(R::BH));
+    assert_eq!(0x01, machine.cpu.get_r8(R::CL)); // 128 > 1
+    assert_eq!(0x00, machine.cpu.get_r8(R::CH));
+    assert_eq!(0x01, machine.cpu.get_r8(R::DL)); // 0x80 - 1 overflows
+    assert_eq!(0x01, machine.cpu.get_r8(R::DH)); // 0x7F has odd parity
+}
+
 #[test]
 fn can_execute_movzx() {
     let mut machine = Machine::default();
@@ -2231,3 +2474,638 @@ fn estimate_mips() {
     let mips = (machine.cpu.instruction_count as f64) / 1_000_000.;
     println!("MIPS: {}", mips);
 }
+
+#[test]
+fn can_raise_div0_exception() {
+    let code: Vec<u8> = vec![
+        0xB3, 0x00, // mov bl,0x0
+        0xF6, 0xF3, // div bl
+    ];
+    // 286+: the return address is the faulting instruction
+    let mut machine = Machine::default();
+    machine.load_executable(&code);
+    let cs = machine.cpu.get_r16(R::CS);
+    machine.hw.mmu.write_u16(0, 0x0000, 0x0200); // int 0 handler
+    machine.hw.mmu.write_u16(0, 0x0002, cs);
+    machine.execute_instructions(2);
+    assert_eq!(0x0200, machine.cpu.regs.ip);
+    let ss = machine.cpu.get_r16(R::SS);
+    let sp = machine.cpu.get_r16(R::SP);
+    assert_eq!(0x0102, machine.hw.mmu.read_u16(ss, sp));
+    assert_eq!(cs, machine.hw.mmu.read_u16(ss, sp + 2));
+    assert_eq!(false, machine.cpu.regs.flags.interrupt);
+
+    // 8086: the return address is the following instruction
+    let mut machine = Machine::default();
+    machine.set_cpu_model(CpuModel::I8086);
+    machine.load_executable(&code);
+    machine.hw.mmu.write_u16(0, 0x0000, 0x0200);
+    machine.hw.mmu.write_u16(0, 0x0002, cs);
+    machine.execute_instructions(2);
+    assert_eq!(0x0200, machine.cpu.regs.ip);
+    let sp = machine.cpu.get_r16(R::SP);
+    assert_eq!(0x0104, machine.hw.mmu.read_u16(ss, sp));
+
+    // without a handler the program is stopped
+    let mut machine = Machine::default();
+    machine.load_executable(&code);
+    machine.execute_instructions(3);
+    assert_eq!(true, machine.cpu.fatal_error);
+}
+
+#[test]
+fn can_raise_invalid_opcode_exception() {
+    let mut machine = Machine::default();
+    let code: Vec<u8> = vec![
+        0x0F, 0xFF, // invalid opcode
+    ];
+    machine.load_executable(&code);
+    let cs = machine.cpu.get_r16(R::CS);
+    machine.hw.mmu.write_u16(0, 0x0018, 0x0200); // int 6 handler
+    machine.hw.mmu.write_u16(0, 0x001A, cs);
+    machine.execute_instruction();
+    assert_eq!(false, machine.cpu.fatal_error);
+    assert_eq!(0x0200, machine.cpu.regs.ip);
+    let ss = machine.cpu.get_r16(R::SS);
+    let sp = machine.cpu.get_r16(R::SP);
+    assert_eq!(0x0100, machine.hw.mmu.read_u16(ss, sp));
+}
+
+#[test]
+fn can_single_step_with_the_trap_flag() {
+    let code: Vec<u8> = vec![
+        0x9C,               // pushf
+        0x58,               // pop ax
+        0x80, 0xCC, 0x01,   // or ah,0x1
+        0x50,               // push ax
+        0x9D,               // popf
+        0x43,               // inc bx
+        0x16,               // push ss
+        0x17,               // pop ss
+        0x43,               // inc bx
+    ];
+    for engine in &[Engine::Interpreter, Engine::Threaded] {
+        let mut machine = Machine::default();
+        machine.engine = *engine;
+        machine.load_executable(&code);
+        let cs = machine.cpu.get_r16(R::CS);
+        machine.hw.mmu.write_u16(0, 0x0004, 0x0200); // int 1 handler
+        machine.hw.mmu.write_u16(0, 0x0006, cs);
+        machine.hw.mmu.write(cs, 0x0200, &[
+            0x42,           // inc dx
+            0xCF,           // iret
+        ]);
+        let ss = machine.cpu.get_r16(R::SS);
+        let dx = machine.cpu.get_r16(R::DX);
+
+        // the trap follows the first instruction run with TF set
+        machine.execute_instructions(6);
+        assert_eq!(0x0200, machine.cpu.regs.ip);
+        assert_eq!(false, machine.cpu.regs.flags.trap);
+        let sp = machine.cpu.get_r16(R::SP);
+        assert_eq!(0x0108, machine.hw.mmu.read_u16(ss, sp));
+        assert_eq!(0x0100, machine.hw.mmu.read_u16(ss, sp + 4) & 0x0100);
+
+        // the handler returns with TF set
+        machine.execute_instructions(2);
+        assert_eq!(0x0108, machine.cpu.regs.ip);
+        assert_eq!(true, machine.cpu.regs.flags.trap);
+
+        // push ss traps, pop ss inhibits the trap for the following instruction
+        machine.execute_instructions(4);
+        assert_eq!(0x010A, machine.cpu.regs.ip);
+        machine.execute_instructions(1);
+        assert_eq!(0x0200, machine.cpu.regs.ip);
+        let sp = machine.cpu.get_r16(R::SP);
+        assert_eq!(0x010B, machine.hw.mmu.read_u16(ss, sp));
+        assert_eq!(0x0002, machine.cpu.get_r16(R::BX));
+        assert_eq!(dx + 2, machine.cpu.get_r16(R::DX));
+    }
+}
+
+#[test]
+fn can_execute_int3_through_the_ivt() {
+    let code: Vec<u8> = vec![
+        0xCC,   // int3
+        0x43,   // inc bx
+    ];
+    let mut machine = Machine::default();
+    machine.load_executable(&code);
+    let cs = machine.cpu.get_r16(R::CS);
+    machine.hw.mmu.write_u16(0, 0x000C, 0x0200); // int 3 handler
+    machine.hw.mmu.write_u16(0, 0x000E, cs);
+    machine.execute_instruction();
+    assert_eq!(0x0200, machine.cpu.regs.ip);
+    let ss = machine.cpu.get_r16(R::SS);
+    let sp = machine.cpu.get_r16(R::SP);
+    assert_eq!(0x0101, machine.hw.mmu.read_u16(ss, sp));
+
+    // without a handler the program continues
+    let mut machine = Machine::default();
+    machine.load_executable(&code);
+    machine.execute_instructions(3);
+    assert_eq!(false, machine.cpu.fatal_error);
+    assert_eq!(0x0102, machine.cpu.regs.ip);
+    assert_eq!(0x0001, machine.cpu.get_r16(R::BX));
+}
+
+/// runs a cpu detection routine, which returns 0 (8086), 1 (80186), 2 (80286) or 3 (80386) in AX
+fn detect_cpu_model(model: CpuModel) -> u16 {
+    let mut machine = Machine::default();
+    machine.set_cpu_model(model);
+    let code: Vec<u8> = vec![
+        0x9C,               // pushf
+        0x58,               // pop ax
+        0x25, 0xFF, 0x0F,   // and ax,0xfff
+        0x50,               // push ax
+        0x9D,               // popf
+        0x9C,               // pushf
+        0x58,               // pop ax
+        0x25, 0x00, 0xF0,   // and ax,0xf000
+        0x3D, 0x00, 0xF0,   // cmp ax,0xf000
+        0x75, 0x0F,         // jnz 0x120
+        0xB8, 0x01, 0x00,   // mov ax,0x1
+        0xB1, 0x21,         // mov cl,0x21
+        0xD3, 0xE0,         // shl ax,cl
+        0xD1, 0xE8,         // shr ax,1
+        0xEB, 0x14,         // jmp short 0x130
+        0x90, 0x90, 0x90, 0x90,
+        0xB8, 0x00, 0x70,   // l_0x120: mov ax,0x7000
+        0x50,               // push ax
+        0x9D,               // popf
+        0x9C,               // pushf
+        0x58,               // pop ax
+        0x25, 0x00, 0x70,   // and ax,0x7000
+        0xB8, 0x02, 0x00,   // mov ax,0x2
+        0x74, 0x01,         // jz 0x130
+        0x40,               // inc ax
+    ];
+    machine.load_executable(&code);
+    for _ in 0..30 {
+        if machine.cpu.regs.ip == 0x0130 {
+            break;
+        }
+        machine.execute_instruction();
+    }
+    assert_eq!(0x0130, machine.cpu.regs.ip);
+    machine.cpu.get_r16(R::AX)
+}
+
+#[test]
+fn detects_the_cpu_model() {
+    assert_eq!(0, detect_cpu_model(CpuModel::I8086));
+    assert_eq!(1, detect_cpu_model(CpuModel::I80186));
+    assert_eq!(2, detect_cpu_model(CpuModel::I80286));
+    assert_eq!(3, detect_cpu_model(CpuModel::I80386));
+}
+
+#[test]
+fn can_execute_push_sp_and_mul_per_cpu_model() {
+    let code: Vec<u8> = vec![
+        0x54,       // push sp
+        0x30, 0xC0, // xor al,al
+        0xB0, 0x40, // mov al,0x40
+        0xF6, 0xE0, // mul al
+    ];
+    for &(model, pushed, zero) in &[(CpuModel::I8086, 0xFFFC, false), (CpuModel::I80286, 0xFFFE, true)] {
+        let mut machine = Machine::default();
+        machine.set_cpu_model(model);
+        machine.load_executable(&code);
+        machine.execute_instructions(4);
+        let ss = machine.cpu.get_r16(R::SS);
+        assert_eq!(pushed, machine.hw.mmu.read_u16(ss, 0xFFFC), "{:?}", model);
+        assert_eq!(0x1000, machine.cpu.get_r16(R::AX));
+        // the 8086 sets ZF from AH
+        assert_eq!(zero, machine.cpu.regs.flags.zero, "{:?}", model);
+    }
+}
+
+#[test]
+fn decodes_the_instruction_set_of_the_cpu_model() {
+    let code: Vec<u8> = vec![
+        0xB8, 0x00, 0x10, // mov ax,0x1000
+        0x50,             // push ax
+        0x0F,             // pop cs (8086 only)
+    ];
+    let mut machine = Machine::default();
+    machine.set_cpu_model(CpuModel::I8086);
+    machine.load_executable(&code);
+    machine.execute_instructions(3);
+    assert_eq!(0x1000, machine.cpu.get_r16(R::CS));
+    assert_eq!(0x0105, machine.cpu.regs.ip);
+
+    let mut machine = Machine::default();
+    machine.set_cpu_model(CpuModel::I80186);
+    machine.load_executable(&code);
+    let cs = machine.cpu.get_r16(R::CS);
+    machine.hw.mmu.write_u16(0, 0x0018, 0x0200); // int 6 handler
+    machine.hw.mmu.write_u16(0, 0x001A, cs);
+    machine.execute_instructions(3);
+    assert_eq!(cs, machine.cpu.get_r16(R::CS));
+    assert_eq!(0x0200, machine.cpu.regs.ip);
+
+    // the operand size prefix came with the 386
+    let code: Vec<u8> = vec![
+        0x66, 0x40, // inc eax
+    ];
+    let mut machine = Machine::default();
+    machine.set_cpu_model(CpuModel::I80286);
+    machine.load_executable(&code);
+    machine.hw.mmu.write_u16(0, 0x0018, 0x0200);
+    machine.hw.mmu.write_u16(0, 0x001A, cs);
+    machine.execute_instruction();
+    assert_eq!(0x0200, machine.cpu.regs.ip);
+
+    machine.set_cpu_model(CpuModel::I80386);
+    machine.cpu.regs.ip = 0x0100;
+    machine.execute_instruction();
+    assert_eq!(0x0102, machine.cpu.regs.ip);
+}
+
+#[test]
+fn can_access_words_at_the_segment_end_per_cpu_model() {
+    let code: Vec<u8> = vec![
+        0xA1, 0xFF, 0xFF, // mov ax,[0xffff]
+    ];
+    let mut machine = Machine::default();
+    machine.set_cpu_model(CpuModel::I8086);
+    machine.load_executable(&code);
+    let ds = machine.cpu.get_r16(R::DS);
+    machine.hw.mmu.write_u8(ds, 0xFFFF, 0x34);
+    machine.hw.mmu.write_u8(ds, 0x0000, 0x12);
+    machine.execute_instruction();
+    assert_eq!(0x1234, machine.cpu.get_r16(R::AX));
+    // the 8086 has 20 address lines
+    machine.hw.mmu.write_u8(0, 0x0000, 0x56);
+    assert_eq!(0x56, machine.hw.mmu.read_u8(0xFFFF, 0x0010));
+
+    // 286+: the access raises a general protection fault
+    let mut machine = Machine::default();
+    machine.load_executable(&code);
+    let cs = machine.cpu.get_r16(R::CS);
+    machine.hw.mmu.write_u16(0, 0x0034, 0x0200); // int 13 handler
+    machine.hw.mmu.write_u16(0, 0x0036, cs);
+    machine.execute_instruction();
+    assert_eq!(0x0200, machine.cpu.regs.ip);
+    let ss = machine.cpu.get_r16(R::SS);
+    let sp = machine.cpu.get_r16(R::SP);
+    assert_eq!(0x0100, machine.hw.mmu.read_u16(ss, sp));
+}
+
+#[test]
+fn ignores_segment_overruns_of_host_code() {
+    let mut machine = Machine::default();
+    let code: Vec<u8> = vec![
+        0xB8, 0x34, 0x12, // mov ax,0x1234
+    ];
+    machine.load_executable(&code);
+    let ds = machine.cpu.get_r16(R::DS);
+    // a word read at the segment end by a HLE handler
+    machine.hw.mmu.read_u16(ds, 0xFFFF);
+    machine.execute_instruction();
+    assert_eq!(0x0103, machine.cpu.regs.ip);
+    assert_eq!(0x1234, machine.cpu.get_r16(R::AX));
+}
+
+/// writes a GDT with a code segment at CS (0x08), a data segment at 0x20000 (0x10)
+/// and a data segment that is not present (0x18), and a IDT with gates to offset 0x0200
+/// for int 11 and int 21h. the pseudo descriptors are at 0x0A00 (GDT) and 0x0A06 (IDT)
+fn write_descriptor_tables(machine: &mut Machine) {
+    // the PIC delivers IRQ 0 to vector 8, which has no gate
+    machine.cpu.regs.flags.interrupt = false;
+
+    let cs = machine.cpu.get_r16(R::CS);
+    let base = u32::from(cs) << 4;
+    let mmu = &mut machine.hw.mmu;
+    let write_u64 = |mmu: &mut MMU, offset: u16, val: u64| {
+        mmu.write_u32(cs, offset, val as u32);
+        mmu.write_u32(cs, offset + 4, (val >> 32) as u32);
+    };
+    write_u64(mmu, 0x0808, 0x0000_9A00_0000_FFFF | u64::from(base) << 16);
+    write_u64(mmu, 0x0810, 0x0000_9202_0000_FFFF);
+    write_u64(mmu, 0x0818, 0x0000_1202_0000_FFFF);
+    write_u64(mmu, 0x0400 + 11 * 8, 0x0000_8600_0008_0200); // 286 interrupt gate
+    write_u64(mmu, 0x0400 + 0x21 * 8, 0x0000_8700_0008_0200); // 286 trap gate
+    mmu.write_u16(cs, 0x0A00, 0x001F);
+    mmu.write_u32(cs, 0x0A02, base + 0x0800);
+    mmu.write_u16(cs, 0x0A06, 0x01FF);
+    mmu.write_u32(cs, 0x0A08, base + 0x0400);
+}
+
+#[test]
+fn can_switch_to_protected_mode() {
+    let mut machine = Machine::default();
+    let code: Vec<u8> = vec![
+        0x0F, 0x01, 0x16, 0x00, 0x0A,   // lgdt [0xa00]
+        0x0F, 0x01, 0x1E, 0x06, 0x0A,   // lidt [0xa06]
+        0xB8, 0x01, 0x00,               // mov ax,0x1
+        0x0F, 0x01, 0xF0,               // lmsw ax
+        0xEA, 0x15, 0x01, 0x08, 0x00,   // jmp 0x8:0x115
+        0xB8, 0x10, 0x00,               // mov ax,0x10
+        0x8E, 0xD8,                     // mov ds,ax
+        0xA1, 0x04, 0x00,               // mov ax,[0x4]
+        0xBB, 0x18, 0x00,               // mov bx,0x18
+        0x8E, 0xC3,                     // mov es,bx
+    ];
+    machine.load_executable(&code);
+    write_descriptor_tables(&mut machine);
+    machine.hw.mmu.write_u16(0x2000, 0x0004, 0x1234);
+    let es = machine.cpu.get_r16(R::ES);
+
+    machine.execute_instructions(4);
+    assert_eq!(true, machine.cpu.protected_mode());
+    assert_eq!(0x001F, machine.cpu.gdtr.limit);
+
+    machine.execute_instructions(1);
+    assert_eq!(0x0008, machine.cpu.get_r16(R::CS));
+    assert_eq!(0x0115, machine.cpu.regs.ip);
+    assert_eq!(0, machine.cpu.cpl());
+
+    machine.execute_instructions(3);
+    assert_eq!(0x1234, machine.cpu.get_r16(R::AX));
+    assert_eq!(0x0002_0000, machine.cpu.segment_cache[R::DS.index()].base);
+
+    // loading a segment that is not present raises #NP with the selector as error code
+    machine.execute_instructions(2);
+    assert_eq!(es, machine.cpu.get_r16(R::ES));
+    assert_eq!(0x0008, machine.cpu.get_r16(R::CS));
+    assert_eq!(0x0200, machine.cpu.regs.ip);
+    let ss = machine.cpu.get_r16(R::SS);
+    let sp = machine.cpu.get_r16(R::SP);
+    assert_eq!(0x0018, machine.hw.mmu.read_u16(ss, sp));
+    assert_eq!(0x0120, machine.hw.mmu.read_u16(ss, sp + 2));
+    assert_eq!(0x0008, machine.hw.mmu.read_u16(ss, sp + 4));
+}
+
+#[test]
+fn can_execute_protected_mode_interrupts() {
+    let mut machine = Machine::default();
+    let code: Vec<u8> = vec![
+        0x0F, 0x01, 0x16, 0x00, 0x0A,   // lgdt [0xa00]
+        0x0F, 0x01, 0x1E, 0x06, 0x0A,   // lidt [0xa06]
+        0x66, 0x0F, 0x20, 0xC0,         // mov eax,cr0
+        0xB0, 0x01,                     // mov al,0x1
+        0x66, 0x0F, 0x22, 0xC0,         // mov cr0,eax
+        0xEA, 0x19, 0x01, 0x08, 0x00,   // jmp 0x8:0x119
+        0xF9,                           // stc
+        0xCD, 0x21,                     // int 0x21
+        0xB8, 0x10, 0x00,               // mov ax,0x10
+        0x0F, 0x00, 0xE0,               // verr ax
+        0x0F, 0x00, 0xE9,               // verw cx
+        0x0F, 0x01, 0x06, 0x10, 0x0A,   // sgdt [0xa10]
+    ];
+    machine.load_executable(&code);
+    write_descriptor_tables(&mut machine);
+    let cs = machine.cpu.get_r16(R::CS);
+    machine.hw.mmu.write(cs, 0x0200, &[
+        0xB9, 0x08, 0x00,               // mov cx,0x8
+        0xCF,                           // iret
+    ]);
+
+    machine.execute_instructions(6);
+    assert_eq!(true, machine.cpu.protected_mode());
+    assert_eq!(0x0008, machine.cpu.get_r16(R::CS));
+
+    machine.execute_instructions(2);
+    assert_eq!(0x0200, machine.cpu.regs.ip);
+    let ss = machine.cpu.get_r16(R::SS);
+    let sp = machine.cpu.get_r16(R::SP);
+    assert_eq!(0x011C, machine.hw.mmu.read_u16(ss, sp));
+
+    // iret restores the flags
+    machine.cpu.regs.flags.carry = false;
+    machine.execute_instructions(2);
+    assert_eq!(0x0008, machine.cpu.get_r16(R::CS));
+    assert_eq!(0x011C, machine.cpu.regs.ip);
+    assert_eq!(true, machine.cpu.regs.flags.carry);
+
+    // data segments are readable, code segments are not writable
+    machine.execute_instructions(2);
+    assert_eq!(true, machine.cpu.regs.flags.zero);
+    machine.execute_instructions(1);
+    assert_eq!(false, machine.cpu.regs.flags.zero);
+
+    machine.execute_instructions(1);
+    assert_eq!(0x001F, machine.hw.mmu.read_u16(cs, 0x0A10));
+    assert_eq!((u32::from(cs) << 4) + 0x0800, machine.hw.mmu.read_u32(cs, 0x0A12));
+}
+
+#[test]
+fn can_raise_general_protection_fault() {
+    let mut machine = Machine::default();
+    let code: Vec<u8> = vec![
+        0x0F, 0x01, 0x16, 0x00, 0x0A,   // lgdt [0xa00]
+        0x0F, 0x01, 0x1E, 0x06, 0x0A,   // lidt [0xa06]
+        0xB8, 0x01, 0x00,               // mov ax,0x1
+        0x0F, 0x01, 0xF0,               // lmsw ax
+        0xEA, 0x00, 0x01, 0x20, 0x00,   // jmp 0x20:0x100
+    ];
+    machine.load_executable(&code);
+    write_descriptor_tables(&mut machine);
+
+    // selector 0x20 is outside of the GDT. there is no gate for the #GP, which
+    // escalates to #DF, and without a gate for #DF to a triple fault
+    machine.execute_instructions(5);
+    assert_eq!(true, machine.cpu.fatal_error);
+}
+
+/// adds a small data segment 0x20 at 0x20000 with limit 0x000F, a stack segment 0x28 at 0x30000
+/// with limit 0x0FFF, and interrupt gates for #SS and #GP
+fn write_limit_descriptors(machine: &mut Machine) {
+    let cs = machine.cpu.get_r16(R::CS);
+    let mmu = &mut machine.hw.mmu;
+    let write_u64 = |mmu: &mut MMU, offset: u16, val: u64| {
+        mmu.write_u32(cs, offset, val as u32);
+        mmu.write_u32(cs, offset + 4, (val >> 32) as u32);
+    };
+    write_u64(mmu, 0x0820, 0x0000_9202_0000_000F);
+    write_u64(mmu, 0x0828, 0x0000_9203_0000_0FFF);
+    write_u64(mmu, 0x0400 + 12 * 8, 0x0000_8600_0008_0200); // 286 interrupt gate
+    write_u64(mmu, 0x0400 + 13 * 8, 0x0000_8600_0008_0200); // 286 interrupt gate
+    mmu.write_u16(cs, 0x0A00, 0x002F);
+}
+
+#[test]
+fn can_raise_general_protection_fault_on_segment_limits() {
+    let mut machine = Machine::default();
+    let code: Vec<u8> = vec![
+        0x0F, 0x01, 0x16, 0x00, 0x0A,   // lgdt [0xa00]
+        0x0F, 0x01, 0x1E, 0x06, 0x0A,   // lidt [0xa06]
+        0xB8, 0x01, 0x00,               // mov ax,0x1
+        0x0F, 0x01, 0xF0,               // lmsw ax
+        0xEA, 0x15, 0x01, 0x08, 0x00,   // jmp 0x8:0x115
+        0xB8, 0x20, 0x00,               // mov ax,0x20
+        0x8E, 0xD8,                     // mov ds,ax
+        0xA1, 0x0E, 0x00,               // mov ax,[0xe]
+        0xA1, 0x0F, 0x00,               // mov ax,[0xf]
+    ];
+    machine.load_executable(&code);
+    write_descriptor_tables(&mut machine);
+    write_limit_descriptors(&mut machine);
+    machine.hw.mmu.write_u16(0x2000, 0x000E, 0x1234);
+
+    machine.execute_instructions(8);
+    assert_eq!(0x1234, machine.cpu.get_r16(R::AX));
+    assert_eq!(0x000F, machine.cpu.segment_cache[R::DS.index()].limit);
+
+    // the word at 0xF ends past the limit and raises #GP(0)
+    machine.execute_instructions(1);
+    assert_eq!(0x1234, machine.cpu.get_r16(R::AX));
+    assert_eq!(0x0008, machine.cpu.get_r16(R::CS));
+    assert_eq!(0x0200, machine.cpu.regs.ip);
+    let ss = machine.cpu.get_r16(R::SS);
+    let sp = machine.cpu.get_r16(R::SP);
+    assert_eq!(0x0000, machine.hw.mmu.read_u16(ss, sp));
+    assert_eq!(0x011D, machine.hw.mmu.read_u16(ss, sp + 2));
+    assert_eq!(0x0008, machine.hw.mmu.read_u16(ss, sp + 4));
+}
+
+#[test]
+fn can_raise_stack_faults() {
+    let mut machine = Machine::default();
+    let code: Vec<u8> = vec![
+        0x0F, 0x01, 0x16, 0x00, 0x0A,   // lgdt [0xa00]
+        0x0F, 0x01, 0x1E, 0x06, 0x0A,   // lidt [0xa06]
+        0xB8, 0x01, 0x00,               // mov ax,0x1
+        0x0F, 0x01, 0xF0,               // lmsw ax
+        0xEA, 0x15, 0x01, 0x08, 0x00,   // jmp 0x8:0x115
+        0xB8, 0x28, 0x00,               // mov ax,0x28
+        0x8E, 0xD0,                     // mov ss,ax
+        0xBC, 0x00, 0x08,               // mov sp,0x800
+        0xBD, 0xFE, 0x0F,               // mov bp,0xffe
+        0x8B, 0x46, 0x00,               // mov ax,[bp+0x0]
+        0x8B, 0x46, 0x01,               // mov ax,[bp+0x1]
+    ];
+    machine.load_executable(&code);
+    write_descriptor_tables(&mut machine);
+    write_limit_descriptors(&mut machine);
+    machine.hw.mmu.write_linear_u16(0x0003_0FFE, 0x5678);
+
+    // bp based operands address the stack segment
+    machine.execute_instructions(10);
+    assert_eq!(0x5678, machine.cpu.get_r16(R::AX));
+    assert_eq!(0x0003_0000, machine.cpu.segment_base(R::SS));
+
+    // the word at bp+1 ends past the limit of the stack segment and raises #SS(0)
+    machine.execute_instructions(1);
+    assert_eq!(0x5678, machine.cpu.get_r16(R::AX));
+    assert_eq!(0x0008, machine.cpu.get_r16(R::CS));
+    assert_eq!(0x0200, machine.cpu.regs.ip);
+    assert_eq!(0x07F8, machine.cpu.get_r16(R::SP));
+    assert_eq!(0x0000, machine.hw.mmu.read_linear_u16(0x0003_07F8));
+    assert_eq!(0x0123, machine.hw.mmu.read_linear_u16(0x0003_07FA));
+    assert_eq!(0x0008, machine.hw.mmu.read_linear_u16(0x0003_07FC));
+}
+
+#[test]
+fn can_execute_32bit_far_transfers_and_gates() {
+    let mut machine = Machine::default();
+    let code: Vec<u8> = vec![
+        0x0F, 0x01, 0x16, 0x00, 0x0A,   // lgdt [0xa00]
+        0x0F, 0x01, 0x1E, 0x06, 0x0A,   // lidt [0xa06]
+        0xB8, 0x01, 0x00,               // mov ax,0x1
+        0x0F, 0x01, 0xF0,               // lmsw ax
+        0xEA, 0x15, 0x01, 0x08, 0x00,   // jmp 0x8:0x115
+        0x66, 0x9A, 0x00, 0x03, 0x00, 0x00, 0x08, 0x00, // call dword 0x8:0x300
+        0xCD, 0x31,                     // int 0x31
+        0xCD, 0x30,                     // int 0x30
+    ];
+    machine.load_executable(&code);
+    write_descriptor_tables(&mut machine);
+    let cs = machine.cpu.get_r16(R::CS);
+    {
+        let mmu = &mut machine.hw.mmu;
+        let write_u64 = |mmu: &mut MMU, offset: u16, val: u64| {
+            mmu.write_u32(cs, offset, val as u32);
+            mmu.write_u32(cs, offset + 4, (val >> 32) as u32);
+        };
+        // 32-bit code segment 0x30 at 0x40000 with limit 0x1FFFF
+        write_u64(mmu, 0x0830, 0x0041_9A04_0000_FFFF);
+        mmu.write_u16(cs, 0x0A00, 0x0037);
+        write_u64(mmu, 0x0400 + 0x30 * 8, 0x0001_8E00_0030_0000); // 386 interrupt gate to 0x30:0x10000
+        write_u64(mmu, 0x0400 + 0x31 * 8, 0x0000_8E00_0008_0200); // 386 interrupt gate to 0x8:0x200
+        mmu.write(cs, 0x0200, &[0x66, 0xCF]); // iretd
+        mmu.write(cs, 0x0300, &[0x66, 0xCB]); // retfd
+    }
+    machine.execute_instructions(5);
+    assert_eq!(0x0115, machine.cpu.regs.ip);
+    let ss = machine.cpu.get_r16(R::SS);
+    let sp = machine.cpu.get_r16(R::SP);
+
+    // o32 far calls push CS and EIP as dwords, o32 far returns pop them
+    machine.execute_instructions(1);
+    assert_eq!(0x0300, machine.cpu.regs.ip);
+    assert_eq!(sp - 8, machine.cpu.get_r16(R::SP));
+    assert_eq!(0x0000_011D, machine.hw.mmu.read_u32(ss, sp - 8));
+    assert_eq!(0x0000_0008, machine.hw.mmu.read_u32(ss, sp - 4));
+    machine.execute_instructions(1);
+    assert_eq!(0x011D, machine.cpu.regs.ip);
+    assert_eq!(sp, machine.cpu.get_r16(R::SP));
+
+    // 386 gates push a frame of dwords, o32 iret pops it
+    machine.execute_instructions(1);
+    assert_eq!(0x0200, machine.cpu.regs.ip);
+    assert_eq!(sp - 12, machine.cpu.get_r16(R::SP));
+    assert_eq!(0x0000_011F, machine.hw.mmu.read_u32(ss, sp - 12));
+    assert_eq!(0x0000_0008, machine.hw.mmu.read_u32(ss, sp - 8));
+    machine.execute_instructions(1);
+    assert_eq!(0x011F, machine.cpu.regs.ip);
+    assert_eq!(sp, machine.cpu.get_r16(R::SP));
+
+    // the full offset of 386 gates is used
+    machine.execute_instructions(1);
+    assert_eq!(0x0030, machine.cpu.get_r16(R::CS));
+    assert_eq!(0x0001_0000, machine.cpu.regs.ip);
+    assert_eq!(true, machine.cpu.code32());
+    assert_eq!(0x0000_0121, machine.hw.mmu.read_u32(ss, sp - 12));
+    assert_eq!(0x0000_0008, machine.hw.mmu.read_u32(ss, sp - 8));
+}
+
+#[test]
+fn can_raise_page_faults() {
+    let mut machine = Machine::default();
+    let code: Vec<u8> = vec![
+        0x0F, 0x01, 0x16, 0x00, 0x0A,       // lgdt [0xa00]
+        0x0F, 0x01, 0x1E, 0x06, 0x0A,       // lidt [0xa06]
+        0x66, 0xB8, 0x00, 0x00, 0x03, 0x00, // mov eax,0x30000
+        0x66, 0x0F, 0x22, 0xD8,             // mov cr3,eax
+        0x66, 0x0F, 0x20, 0xC0,             // mov eax,cr0
+        0x66, 0xB8, 0x01, 0x00, 0x00, 0x80, // mov eax,0x80000001
+        0x66, 0x0F, 0x22, 0xC0,             // mov cr0,eax
+        0xEA, 0x27, 0x01, 0x08, 0x00,       // jmp 0x8:0x127
+        0xB8, 0x10, 0x00,                   // mov ax,0x10
+        0x8E, 0xD8,                         // mov ds,ax
+        0xA1, 0x04, 0x00,                   // mov ax,[0x4]
+    ];
+    machine.load_executable(&code);
+    write_descriptor_tables(&mut machine);
+    let cs = machine.cpu.get_r16(R::CS);
+    machine.hw.mmu.write_u32(cs, 0x0400 + 14 * 8, 0x0008_0200);
+    machine.hw.mmu.write_u32(cs, 0x0400 + 14 * 8 + 4, 0x0000_8600); // 286 interrupt gate
+
+    // identity map the first megabyte, except for the page of the data segment at 0x20000
+    let mmu = &mut machine.hw.mmu;
+    mmu.write_linear_u32(0x0003_0000, 0x0003_1000 | 0x3);
+    for page in 0..0x100 {
+        if page != 0x20 {
+            mmu.write_linear_u32(0x0003_1000 + page * 4, (page << 12) | 0x3);
+        }
+    }
+
+    machine.execute_instructions(8);
+    assert_eq!(0x8000_0001, machine.cpu.cr0);
+    assert_eq!(0x0003_0000, machine.cpu.cr3);
+    assert_eq!(0x0008, machine.cpu.get_r16(R::CS));
+
+    // the not present page raises #PF with the linear address in CR2
+    machine.execute_instructions(3);
+    assert_eq!(0x0010, machine.cpu.get_r16(R::AX));
+    assert_eq!(0x0008, machine.cpu.get_r16(R::CS));
+    assert_eq!(0x0200, machine.cpu.regs.ip);
+    assert_eq!(0x0002_0004, machine.cpu.cr2);
+    let ss = machine.cpu.get_r16(R::SS);
+    let sp = machine.cpu.get_r16(R::SP);
+    assert_eq!(0x0000, machine.hw.mmu.read_u16(ss, sp));
+    assert_eq!(0x012C, machine.hw.mmu.read_u16(ss, sp + 2));
+    assert_eq!(0x0008, machine.hw.mmu.read_u16(ss, sp + 4));
+}
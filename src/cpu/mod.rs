@@ -26,3 +26,18 @@ mod op;
 
 pub use self::encoder::*;
 mod encoder;
+
+pub use self::timing::*;
+mod timing;
+
+pub use self::fpu::*;
+mod fpu;
+
+pub use self::descriptor::*;
+mod descriptor;
+
+pub use self::instruction_cache::*;
+mod instruction_cache;
+
+pub use self::model::*;
+mod model;
@@ -0,0 +1,80 @@
+// Processor models
+//
+// The later processors run the instructions of the earlier ones, but differ in a few details
+// that software can observe. CPU detection routines rely on them.
+
+use cpu::timing::TimingProfile;
+
+#[cfg(test)]
+#[path = "./model_test.rs"]
+mod model_test;
+
+/// selects the emulated processor
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum CpuModel {
+    I8086,
+    I80186,
+    I80286,
+    I80386,
+    I80486,
+}
+
+impl Default for CpuModel {
+    fn default() -> Self {
+        CpuModel::I80386
+    }
+}
+
+impl CpuModel {
+    /// the instruction timing tables of the processor
+    pub fn timing_profile(self) -> TimingProfile {
+        match self {
+            // XXX the 80186 is timed as a 8088
+            CpuModel::I8086 | CpuModel::I80186 => TimingProfile::I8088,
+            CpuModel::I80286 => TimingProfile::I80286,
+            // XXX the 486 is timed as a 386
+            CpuModel::I80386 | CpuModel::I80486 => TimingProfile::I80386,
+        }
+    }
+
+    /// returns the opcode the 8086 runs for opcode b. it has no invalid opcode exception, the
+    /// encodings later used by the 80186 repeat other instructions
+    pub fn alias_8086(b: u8) -> u8 {
+        match b {
+            0x60...0x6F => b + 0x10, // jcc
+            0xC0 | 0xC1 => b + 2,    // ret imm16, ret
+            0xC8 | 0xC9 => b + 2,    // retf imm16, retf
+            _ => b,
+        }
+    }
+
+    /// returns true if the processor has the instruction starting with opcode b. b2 is the
+    /// following byte, used for two byte opcodes
+    pub fn has_opcode(self, b: u8, b2: u8) -> bool {
+        match self {
+            CpuModel::I8086 | CpuModel::I80486 => true,
+            // 0F is POP CS on the 8086, ARPL and FS, GS and the size prefixes came with the 286 and 386
+            CpuModel::I80186 => match b {
+                0x0F | 0x63...0x67 => false,
+                _ => true,
+            },
+            CpuModel::I80286 => match b {
+                // sldt group, sgdt group, lar, lsl, clts
+                0x0F => match b2 {
+                    0x00...0x03 | 0x06 => true,
+                    _ => false,
+                },
+                0x64...0x67 => false,
+                _ => true,
+            },
+            CpuModel::I80386 => match b {
+                // cpuid, cmpxchg, xadd and bswap came with the 486
+                0x0F => match b2 {
+                    0xA2 | 0xB0 | 0xB1 | 0xC0 | 0xC1 | 0xC8...0xCF => false,
+                    _ => true,
+                },
+                _ => true,
+            },
+        }
+    }
+}
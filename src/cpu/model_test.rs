@@ -0,0 +1,24 @@
+use cpu::model::CpuModel;
+
+#[test]
+fn aliases_80186_opcodes_on_the_8086() {
+    assert_eq!(0x74, CpuModel::alias_8086(0x64)); // fs prefix is jz
+    assert_eq!(0xC2, CpuModel::alias_8086(0xC0));
+    assert_eq!(0xCB, CpuModel::alias_8086(0xC9));
+    assert_eq!(0x0F, CpuModel::alias_8086(0x0F));
+}
+
+#[test]
+fn knows_the_opcodes_of_each_model() {
+    assert_eq!(true, CpuModel::I8086 < CpuModel::I80186);
+    assert_eq!(false, CpuModel::I80186.has_opcode(0x0F, 0x01));
+    assert_eq!(true, CpuModel::I80186.has_opcode(0x62, 0x00)); // bound
+    assert_eq!(true, CpuModel::I80286.has_opcode(0x0F, 0x01)); // lgdt
+    assert_eq!(false, CpuModel::I80286.has_opcode(0x0F, 0xB6)); // movzx
+    assert_eq!(false, CpuModel::I80286.has_opcode(0x66, 0x90));
+    assert_eq!(true, CpuModel::I80386.has_opcode(0x66, 0x90));
+    assert_eq!(false, CpuModel::I80386.has_opcode(0x0F, 0xA2)); // cpuid
+    assert_eq!(false, CpuModel::I80386.has_opcode(0x0F, 0xC9)); // bswap
+    assert_eq!(true, CpuModel::I80386.has_opcode(0x0F, 0xB6));
+    assert_eq!(true, CpuModel::I80486.has_opcode(0x0F, 0xA2));
+}
@@ -27,10 +27,24 @@ pub enum Op {
     /// Bit Scan Forward
     Bsf,
 
+    /// Bit Scan Reverse
+    Bsr,
+
+    /// Byte Swap
+    Bswap,
+
     /// Bit Test
     Bt,
 
+    /// Bit Test and Complement
+    Btc,
+
+    /// Bit Test and Reset
+    Btr,
+
+    /// Bit Test and Set
     Bts,
+
     CallNear, CallFar,
 
     /// Convert Byte to Word
@@ -42,6 +56,9 @@ pub enum Op {
     /// Clear Direction Flag
     Cld,
 
+    /// Clear Task-Switched Flag in CR0
+    Clts,
+
     /// Clear Interrupt Flag
     Cli,
 
@@ -49,10 +66,16 @@ pub enum Op {
     Cmc,
 
     Cmp8, Cmp16, Cmp32,
-    Cmpsb, Cmpsw,
+    Cmpsb, Cmpsw, Cmpsd,
+
+    /// Compare and Exchange
+    Cmpxchg8, Cmpxchg16, Cmpxchg32,
+
+    /// CPU Identification
+    Cpuid,
 
     /// Convert Word to Doubleword
-    Cwd16, Cwde32,
+    Cwd16, Cwde32, Cdq32,
 
     /// Decimal Adjust AL after Addition
     Daa,
@@ -64,6 +87,148 @@ pub enum Op {
     Div8, Div16, Div32,
 
     Enter,
+
+    /// x87 Compute 2^x - 1
+    F2xm1,
+
+    /// x87 Absolute Value
+    Fabs,
+
+    /// x87 Add, Add and Pop, Add Integer
+    Fadd, Faddp, Fiadd,
+
+    /// x87 Load Binary Coded Decimal
+    Fbld,
+
+    /// x87 Store BCD Integer and Pop
+    Fbstp,
+
+    /// x87 Change Sign
+    Fchs,
+
+    /// x87 Compare Floating Point Values (and Pop, and Pop Twice)
+    Fcom, Fcomp, Fcompp,
+
+    /// x87 Cosine
+    Fcos,
+
+    /// x87 Decrement Stack-Top Pointer
+    Fdecstp,
+
+    /// x87 Divide, Divide and Pop, Divide by Integer
+    Fdiv, Fdivp, Fidiv,
+
+    /// x87 Reverse Divide
+    Fdivr, Fdivrp, Fidivr,
+
+    /// x87 Free Floating-Point Register
+    Ffree,
+
+    /// x87 Compare Integer (and Pop)
+    Ficom, Ficomp,
+
+    /// x87 Load Integer
+    Fild,
+
+    /// x87 Increment Stack-Top Pointer
+    Fincstp,
+
+    /// x87 Store Integer (and Pop)
+    Fist, Fistp,
+
+    /// x87 Load Floating Point Value
+    Fld,
+
+    /// x87 Load Constant: +1.0, log2(10), log2(e), pi, log10(2), ln(2), +0.0
+    Fld1, Fldl2t, Fldl2e, Fldpi, Fldlg2, Fldln2, Fldz,
+
+    /// x87 Load Control Word
+    Fldcw,
+
+    /// x87 Load Environment
+    Fldenv,
+
+    /// x87 Multiply, Multiply and Pop, Multiply by Integer
+    Fmul, Fmulp, Fimul,
+
+    /// x87 Clear Exceptions, without checking for pending exceptions
+    Fnclex,
+
+    /// x87 Initialize Floating-Point Unit, without checking for pending exceptions
+    Fninit,
+
+    /// x87 No Operation
+    Fnop,
+
+    /// x87 Store State and reinitialize, without checking for pending exceptions
+    Fnsave,
+
+    /// x87 Store Control Word, without checking for pending exceptions
+    Fnstcw,
+
+    /// x87 Store Environment, without checking for pending exceptions
+    Fnstenv,
+
+    /// x87 Store Status Word, without checking for pending exceptions
+    Fnstsw,
+
+    /// x87 Partial Arctangent
+    Fpatan,
+
+    /// x87 Partial Remainder (truncating, IEEE round to nearest)
+    Fprem, Fprem1,
+
+    /// x87 Partial Tangent
+    Fptan,
+
+    /// x87 Round to Integer
+    Frndint,
+
+    /// x87 Restore State
+    Frstor,
+
+    /// x87 Scale by power of two
+    Fscale,
+
+    /// x87 Sine
+    Fsin,
+
+    /// x87 Sine and Cosine
+    Fsincos,
+
+    /// x87 Square Root
+    Fsqrt,
+
+    /// x87 Store Floating Point Value (and Pop)
+    Fst, Fstp,
+
+    /// x87 Subtract, Subtract and Pop, Subtract Integer
+    Fsub, Fsubp, Fisub,
+
+    /// x87 Reverse Subtract
+    Fsubr, Fsubrp, Fisubr,
+
+    /// x87 Test against +0.0
+    Ftst,
+
+    /// x87 Unordered Compare Floating Point Values (and Pop, and Pop Twice)
+    Fucom, Fucomp, Fucompp,
+
+    /// Wait for the FPU
+    Fwait,
+
+    /// x87 Examine ModR/M
+    Fxam,
+
+    /// x87 Exchange Register Contents
+    Fxch,
+
+    /// x87 Extract Exponent and Significand
+    Fxtract,
+
+    /// x87 Compute y * log2(x) (+1)
+    Fyl2x, Fyl2xp1,
+
     Hlt,
 
     Idiv8, Idiv16, Idiv32,
@@ -140,16 +305,31 @@ pub enum Op {
     Lds,
 
     /// Load Effective Address
-    Lea16,
+    Lea16, Lea32,
 
     Leave,
 
     Les,
 
+    /// Load Far Pointer into FS, GS or SS
+    Lfs, Lgs, Lss,
+
+    /// Load Global/Interrupt Descriptor Table Register
+    Lgdt, Lidt,
+
+    /// Load Local Descriptor Table Register
+    Lldt,
+
+    /// Load Machine Status Word
+    Lmsw,
+
     Lodsb, Lodsw, Lodsd,
 
     Loop, Loope, Loopne,
 
+    /// Load Task Register
+    Ltr,
+
     Mov8, Mov16, Mov32,
     Movsb, Movsw, Movsd,
 
@@ -210,20 +390,71 @@ pub enum Op {
     /// Integer Subtraction with Borrow
     Sbb8, Sbb16, Sbb32,
 
-    Scasb, Scasw,
+    Scasb, Scasw, Scasd,
+
+    /// Set byte if overflow (OF=1).
+    Seto,
+
+    /// Set byte if not overflow (OF=0).
+    Setno,
 
     /// setc: Set byte if carry (CF=1).
     /// alias setb: Set byte if below (CF=1).
     Setc,
 
-    /// setg: Set byte if greater (ZF=0 and SF=OF).
-    /// alias setnle: Set byte if not less or equal (ZF=0 and SF=OF).
-    Setg,
+    /// setnc: Set byte if not carry (CF=0).
+    /// alias setae: Set byte if above or equal (CF=0).
+    Setnc,
+
+    /// setz: Set byte if zero (ZF=1).
+    /// alias sete: Set byte if equal (ZF=1).
+    Setz,
 
     /// setnz: Set byte if not zero (ZF=0).
     /// alias setne: Set byte if not equal (ZF=0).
     Setnz,
 
+    /// setna: Set byte if not above (CF=1 or ZF=1).
+    /// alias setbe: Set byte if below or equal (CF=1 or ZF=1).
+    Setna,
+
+    /// seta: Set byte if above (CF=0 and ZF=0).
+    /// alias setnbe: Set byte if not below or equal (CF=0 and ZF=0).
+    Seta,
+
+    /// Set byte if sign (SF=1).
+    Sets,
+
+    /// Set byte if not sign (SF=0).
+    Setns,
+
+    /// setpe: Set byte if parity even (PF=1).
+    /// alias setp: Set byte if parity (PF=1).
+    Setpe,
+
+    /// setpo: Set byte if parity odd (PF=0).
+    /// alias setnp: Set byte if not parity (PF=0).
+    Setpo,
+
+    /// setl: Set byte if less (SF ≠ OF).
+    /// alias setnge: Set byte if not greater or equal (SF ≠ OF).
+    Setl,
+
+    /// setnl: Set byte if not less (SF=OF).
+    /// alias setge: Set byte if greater or equal (SF=OF).
+    Setnl,
+
+    /// setng: Set byte if not greater (ZF=1 or SF ≠ OF).
+    /// alias setle: Set byte if less or equal (ZF=1 or SF ≠ OF).
+    Setng,
+
+    /// setg: Set byte if greater (ZF=0 and SF=OF).
+    /// alias setnle: Set byte if not less or equal (ZF=0 and SF=OF).
+    Setg,
+
+    /// Store Global/Interrupt Descriptor Table Register
+    Sgdt, Sidt,
+
     Shl8, Shl16, Shl32,
 
     /// Double Precision Shift Left
@@ -234,8 +465,12 @@ pub enum Op {
     /// Double Precision Shift Right
     Shrd,
 
+    /// Store Local Descriptor Table Register
     Sldt,
 
+    /// Store Machine Status Word
+    Smsw,
+
     // Set Carry Flag
     Stc,
 
@@ -245,10 +480,19 @@ pub enum Op {
     /// Set Interrupt Flag
     Sti,
 
+    /// Store Task Register
+    Str,
+
     Stosb, Stosw, Stosd,
     Sub8, Sub16, Sub32,
     Test8, Test16, Test32,
 
+    /// Verify a Segment for Reading or Writing
+    Verr, Verw,
+
+    /// Exchange and Add
+    Xadd8, Xadd16, Xadd32,
+
     /// Exchange Register/Memory with Register
     Xchg8, Xchg16, Xchg32,
 
@@ -283,6 +527,34 @@ impl Op {
         }
     }
 
+    /// returns true for the jumps taken on a flag condition
+    pub fn is_conditional_jump(&self) -> bool {
+        match *self {
+            Op::Ja | Op::Jc | Op::Jg | Op::Jl | Op::Jna | Op::Jnc | Op::Jng | Op::Jnl |
+            Op::Jno | Op::Jns | Op::Jnz | Op::Jo | Op::Jpe | Op::Jpo | Op::Js | Op::Jz => true,
+            _ => false,
+        }
+    }
+
+    /// returns true for x87 instructions
+    pub fn is_fpu(&self) -> bool {
+        match *self {
+            Op::F2xm1 | Op::Fabs | Op::Fadd | Op::Faddp | Op::Fiadd | Op::Fbld | Op::Fbstp |
+            Op::Fchs | Op::Fcom | Op::Fcomp | Op::Fcompp | Op::Fcos | Op::Fdecstp |
+            Op::Fdiv | Op::Fdivp | Op::Fidiv | Op::Fdivr | Op::Fdivrp | Op::Fidivr |
+            Op::Ffree | Op::Ficom | Op::Ficomp | Op::Fild | Op::Fincstp | Op::Fist | Op::Fistp |
+            Op::Fld | Op::Fld1 | Op::Fldl2t | Op::Fldl2e | Op::Fldpi | Op::Fldlg2 | Op::Fldln2 |
+            Op::Fldz | Op::Fldcw | Op::Fldenv | Op::Fmul | Op::Fmulp | Op::Fimul |
+            Op::Fnclex | Op::Fninit | Op::Fnop | Op::Fnsave | Op::Fnstcw | Op::Fnstenv |
+            Op::Fnstsw | Op::Fpatan | Op::Fprem | Op::Fprem1 | Op::Fptan | Op::Frndint |
+            Op::Frstor | Op::Fscale | Op::Fsin | Op::Fsincos | Op::Fsqrt | Op::Fst | Op::Fstp |
+            Op::Fsub | Op::Fsubp | Op::Fisub | Op::Fsubr | Op::Fsubrp | Op::Fisubr | Op::Ftst |
+            Op::Fucom | Op::Fucomp | Op::Fucompp | Op::Fwait | Op::Fxam | Op::Fxch |
+            Op::Fxtract | Op::Fyl2x | Op::Fyl2xp1 => true,
+            _ => false,
+        }
+    }
+
     /// used by encoder
     pub fn f6_index(&self) -> u8 {
         match *self {
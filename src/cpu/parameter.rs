@@ -39,22 +39,40 @@ pub enum Parameter {
     ImmS8(i8),                          // byte +0x3f
     Imm16(u16),                         // word 0x8000
     Imm32(u32),                         // dword 0x8000_0000
-    Ptr16Imm(u16, u16),                 // jmp far u16:u16
+    Ptr16Imm(u16, u32),                 // jmp far u16:u16, or u16:u32 with the 0x66 prefix
 
     Ptr8(Segment, u16),                 // byte [u16], like "byte [0x4040]"
     Ptr8Amode(Segment, AMode),          // byte [amode], like "byte [bx]"
     Ptr8AmodeS8(Segment, AMode, i8),    // byte [amode+s8], like "byte [bp-0x20]"
     Ptr8AmodeS16(Segment, AMode, i16),  // byte [amode+s16], like "byte [bp-0x2020]"
+    Ptr8AmodeS32(Segment, AMode, i32),  // byte [amode+s32], like "byte [ebx+ecx*4-0x20202020]"
 
     Ptr16(Segment, u16),                // word [u16], like "word [0x4040]"
     Ptr16Amode(Segment, AMode),         // word [amode], like "word [bx]"
     Ptr16AmodeS8(Segment, AMode, i8),   // word [amode+s8], like "word [bp-0x20]"
     Ptr16AmodeS16(Segment, AMode, i16), // word [amode+s16], like "word [bp-0x2020]"
+    Ptr16AmodeS32(Segment, AMode, i32), // word [amode+s32], like "word [ebx+ecx*4-0x20202020]"
 
     Ptr32(Segment, u16),                // dword [u16], like "dword [0x4040]"
     Ptr32Amode(Segment, AMode),         // dword [amode], like "dword [bx]"
     Ptr32AmodeS8(Segment, AMode, i8),   // dword [amode+s8], like "dword [bp-0x20]"
     Ptr32AmodeS16(Segment, AMode, i16), // dword [amode+s16], like "dword [bp-0x2020]"
+    Ptr32AmodeS32(Segment, AMode, i32), // dword [amode+s32], like "dword [ebx+ecx*4-0x20202020]"
+
+    Ptr64(Segment, u16),                // qword [u16], like "qword [0x4040]"
+    Ptr64Amode(Segment, AMode),         // qword [amode], like "qword [bx]"
+    Ptr64AmodeS8(Segment, AMode, i8),   // qword [amode+s8], like "qword [bp-0x20]"
+    Ptr64AmodeS16(Segment, AMode, i16), // qword [amode+s16], like "qword [bp-0x2020]"
+    Ptr64AmodeS32(Segment, AMode, i32), // qword [amode+s32], like "qword [ebx+ecx*4-0x20202020]"
+
+    Ptr80(Segment, u16),                // tword [u16], like "tword [0x4040]"
+    Ptr80Amode(Segment, AMode),         // tword [amode], like "tword [bx]"
+    Ptr80AmodeS8(Segment, AMode, i8),   // tword [amode+s8], like "tword [bp-0x20]"
+    Ptr80AmodeS16(Segment, AMode, i16), // tword [amode+s16], like "tword [bp-0x2020]"
+    Ptr80AmodeS32(Segment, AMode, i32), // tword [amode+s32], like "tword [ebx+ecx*4-0x20202020]"
+
+    ST(u8),                             // x87 stack register, like "st1"
+    CR(u8),                             // control register, like "cr0"
     None,
 }
 
@@ -81,12 +99,12 @@ impl fmt::Display for Parameter {
             ),
             Parameter::Ptr16Imm(seg, v) => write!(f, "{:04X}:{:04X}", seg, v),
             Parameter::Ptr8(seg, v) => write!(f, "byte [{}:0x{:04X}]", seg, v),
-            Parameter::Ptr8Amode(seg, ref amode) => write!(f, "byte [{}:{}]", seg, amode.as_str()),
+            Parameter::Ptr8Amode(seg, ref amode) => write!(f, "byte [{}:{}]", seg, amode),
             Parameter::Ptr8AmodeS8(seg, ref amode, imm) => write!(
                 f,
                 "byte [{}:{}{}0x{:02X}]",
                 seg,
-                amode.as_str(),
+                amode,
                 if imm < 0 { "-" } else { "+" },
                 if imm < 0 {
                     (Wrapping(0) - Wrapping(imm)).0
@@ -98,7 +116,7 @@ impl fmt::Display for Parameter {
                 f,
                 "byte [{}:{}{}0x{:04X}]",
                 seg,
-                amode.as_str(),
+                amode,
                 if imm < 0 { "-" } else { "+" },
                 if imm < 0 {
                     (Wrapping(0) - Wrapping(imm)).0
@@ -106,13 +124,14 @@ impl fmt::Display for Parameter {
                     imm
                 }
             ),
+            Parameter::Ptr8AmodeS32(seg, ref amode, imm) => fmt_ptr_s32(f, "byte", seg, amode, imm),
             Parameter::Ptr16(seg, v) => write!(f, "word [{}:0x{:04X}]", seg, v),
-            Parameter::Ptr16Amode(seg, ref amode) => write!(f, "word [{}:{}]", seg, amode.as_str()),
+            Parameter::Ptr16Amode(seg, ref amode) => write!(f, "word [{}:{}]", seg, amode),
             Parameter::Ptr16AmodeS8(seg, ref amode, imm) => write!(
                 f,
                 "word [{}:{}{}0x{:02X}]",
                 seg,
-                amode.as_str(),
+                amode,
                 if imm < 0 { "-" } else { "+" },
                 if imm < 0 {
                     (Wrapping(0) - Wrapping(imm)).0
@@ -124,7 +143,7 @@ impl fmt::Display for Parameter {
                 f,
                 "word [{}:{}{}0x{:04X}]",
                 seg,
-                amode.as_str(),
+                amode,
                 if imm < 0 { "-" } else { "+" },
                 if imm < 0 {
                     (Wrapping(0) - Wrapping(imm)).0
@@ -132,13 +151,14 @@ impl fmt::Display for Parameter {
                     imm
                 }
             ),
+            Parameter::Ptr16AmodeS32(seg, ref amode, imm) => fmt_ptr_s32(f, "word", seg, amode, imm),
             Parameter::Ptr32(seg, v) => write!(f, "dword [{}:0x{:04X}]", seg, v),
-            Parameter::Ptr32Amode(seg, ref amode) => write!(f, "dword [{}:{}]", seg, amode.as_str()),
+            Parameter::Ptr32Amode(seg, ref amode) => write!(f, "dword [{}:{}]", seg, amode),
             Parameter::Ptr32AmodeS8(seg, ref amode, imm) => write!(
                 f,
                 "dword [{}:{}{}0x{:02X}]",
                 seg,
-                amode.as_str(),
+                amode,
                 if imm < 0 { "-" } else { "+" },
                 if imm < 0 {
                     (Wrapping(0) - Wrapping(imm)).0
@@ -150,7 +170,22 @@ impl fmt::Display for Parameter {
                 f,
                 "dword [{}:{}{}0x{:04X}]",
                 seg,
-                amode.as_str(),
+                amode,
+                if imm < 0 { "-" } else { "+" },
+                if imm < 0 {
+                    (Wrapping(0) - Wrapping(imm)).0
+                } else {
+                    imm
+                }
+            ),
+            Parameter::Ptr32AmodeS32(seg, ref amode, imm) => fmt_ptr_s32(f, "dword", seg, amode, imm),
+            Parameter::Ptr64(seg, v) => write!(f, "qword [{}:0x{:04X}]", seg, v),
+            Parameter::Ptr64Amode(seg, ref amode) => write!(f, "qword [{}:{}]", seg, amode),
+            Parameter::Ptr64AmodeS8(seg, ref amode, imm) => write!(
+                f,
+                "qword [{}:{}{}0x{:02X}]",
+                seg,
+                amode,
                 if imm < 0 { "-" } else { "+" },
                 if imm < 0 {
                     (Wrapping(0) - Wrapping(imm)).0
@@ -158,11 +193,75 @@ impl fmt::Display for Parameter {
                     imm
                 }
             ),
+            Parameter::Ptr64AmodeS16(seg, ref amode, imm) => write!(
+                f,
+                "qword [{}:{}{}0x{:04X}]",
+                seg,
+                amode,
+                if imm < 0 { "-" } else { "+" },
+                if imm < 0 {
+                    (Wrapping(0) - Wrapping(imm)).0
+                } else {
+                    imm
+                }
+            ),
+            Parameter::Ptr64AmodeS32(seg, ref amode, imm) => fmt_ptr_s32(f, "qword", seg, amode, imm),
+            Parameter::Ptr80(seg, v) => write!(f, "tword [{}:0x{:04X}]", seg, v),
+            Parameter::Ptr80Amode(seg, ref amode) => write!(f, "tword [{}:{}]", seg, amode),
+            Parameter::Ptr80AmodeS8(seg, ref amode, imm) => write!(
+                f,
+                "tword [{}:{}{}0x{:02X}]",
+                seg,
+                amode,
+                if imm < 0 { "-" } else { "+" },
+                if imm < 0 {
+                    (Wrapping(0) - Wrapping(imm)).0
+                } else {
+                    imm
+                }
+            ),
+            Parameter::Ptr80AmodeS16(seg, ref amode, imm) => write!(
+                f,
+                "tword [{}:{}{}0x{:04X}]",
+                seg,
+                amode,
+                if imm < 0 { "-" } else { "+" },
+                if imm < 0 {
+                    (Wrapping(0) - Wrapping(imm)).0
+                } else {
+                    imm
+                }
+            ),
+            Parameter::Ptr80AmodeS32(seg, ref amode, imm) => fmt_ptr_s32(f, "tword", seg, amode, imm),
+            Parameter::ST(n) => write!(f, "st{}", n),
+            Parameter::CR(n) => write!(f, "cr{}", n),
             Parameter::None => write!(f, ""),
         }
     }
 }
 
+/// formats a memory operand with 32-bit displacement, like "dword [ds:ebx+ecx*4+0x00000010]"
+fn fmt_ptr_s32(f: &mut fmt::Formatter, size: &str, seg: Segment, amode: &AMode, imm: i32) -> fmt::Result {
+    let amode = amode.to_string();
+    if amode.is_empty() {
+        // [disp32]
+        return write!(f, "{} [{}:0x{:08X}]", size, seg, imm as u32);
+    }
+    write!(
+        f,
+        "{} [{}:{}{}0x{:08X}]",
+        size,
+        seg,
+        amode,
+        if imm < 0 { "-" } else { "+" },
+        if imm < 0 {
+            (Wrapping(0) - Wrapping(imm)).0
+        } else {
+            imm
+        }
+    )
+}
+
 impl Parameter {
     pub fn is_imm(&self) -> bool {
         match *self {
@@ -182,13 +281,57 @@ impl Parameter {
             Parameter::Ptr8Amode(_, _) |
             Parameter::Ptr8AmodeS8(_, _, _) |
             Parameter::Ptr8AmodeS16(_, _, _) |
+            Parameter::Ptr8AmodeS32(_, _, _) |
             Parameter::Ptr16Amode(_, _) |
             Parameter::Ptr16AmodeS8(_, _, _) |
-            Parameter::Ptr16AmodeS16(_, _, _) => true,
+            Parameter::Ptr16AmodeS16(_, _, _) |
+            Parameter::Ptr16AmodeS32(_, _, _) |
+            Parameter::Ptr32(_, _) |
+            Parameter::Ptr32Amode(_, _) |
+            Parameter::Ptr32AmodeS8(_, _, _) |
+            Parameter::Ptr32AmodeS16(_, _, _) |
+            Parameter::Ptr32AmodeS32(_, _, _) |
+            Parameter::Ptr64(_, _) |
+            Parameter::Ptr64Amode(_, _) |
+            Parameter::Ptr64AmodeS8(_, _, _) |
+            Parameter::Ptr64AmodeS16(_, _, _) |
+            Parameter::Ptr64AmodeS32(_, _, _) |
+            Parameter::Ptr80(_, _) |
+            Parameter::Ptr80Amode(_, _) |
+            Parameter::Ptr80AmodeS8(_, _, _) |
+            Parameter::Ptr80AmodeS16(_, _, _) |
+            Parameter::Ptr80AmodeS32(_, _, _) => true,
             _ => false,
         }
     }
 
+    /// returns the addressing mode of a memory operand
+    pub fn amode(&self) -> Option<&AMode> {
+        match *self {
+            Parameter::Ptr8Amode(_, ref amode) |
+            Parameter::Ptr8AmodeS8(_, ref amode, _) |
+            Parameter::Ptr8AmodeS16(_, ref amode, _) |
+            Parameter::Ptr8AmodeS32(_, ref amode, _) |
+            Parameter::Ptr16Amode(_, ref amode) |
+            Parameter::Ptr16AmodeS8(_, ref amode, _) |
+            Parameter::Ptr16AmodeS16(_, ref amode, _) |
+            Parameter::Ptr16AmodeS32(_, ref amode, _) |
+            Parameter::Ptr32Amode(_, ref amode) |
+            Parameter::Ptr32AmodeS8(_, ref amode, _) |
+            Parameter::Ptr32AmodeS16(_, ref amode, _) |
+            Parameter::Ptr32AmodeS32(_, ref amode, _) |
+            Parameter::Ptr64Amode(_, ref amode) |
+            Parameter::Ptr64AmodeS8(_, ref amode, _) |
+            Parameter::Ptr64AmodeS16(_, ref amode, _) |
+            Parameter::Ptr64AmodeS32(_, ref amode, _) |
+            Parameter::Ptr80Amode(_, ref amode) |
+            Parameter::Ptr80AmodeS8(_, ref amode, _) |
+            Parameter::Ptr80AmodeS16(_, ref amode, _) |
+            Parameter::Ptr80AmodeS32(_, ref amode, _) => Some(amode),
+            _ => None,
+        }
+    }
+
     pub fn is_reg(&self) -> bool {
         match *self {
             Parameter::Reg8(_) |
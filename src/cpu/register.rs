@@ -1,4 +1,5 @@
 use std::convert::From;
+use std::fmt;
 
 use cpu::flag::Flags;
 use cpu::decoder::AddressSize;
@@ -168,6 +169,50 @@ pub enum AMode {
 
     // 32-bit addressing modes
     EAX, ECX, EDX, EBX, ESP, EBP, ESI, EDI,
+
+    // 32-bit scale-index-base addressing: [base + index * scale]
+    // both registers are optional, a missing base means [index * scale + disp32]
+    SIB(Option<R>, Option<R>, u8),
+}
+
+impl fmt::Display for AMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AMode::BXSI => write!(f, "bx+si"),
+            AMode::BXDI => write!(f, "bx+di"),
+            AMode::BPSI => write!(f, "bp+si"),
+            AMode::BPDI => write!(f, "bp+di"),
+            AMode::SI => write!(f, "si"),
+            AMode::DI => write!(f, "di"),
+            AMode::BP => write!(f, "bp"),
+            AMode::BX => write!(f, "bx"),
+
+            AMode::EAX => write!(f, "eax"),
+            AMode::ECX => write!(f, "ecx"),
+            AMode::EDX => write!(f, "edx"),
+            AMode::EBX => write!(f, "ebx"),
+            AMode::ESP => write!(f, "esp"),
+            AMode::EBP => write!(f, "ebp"),
+            AMode::ESI => write!(f, "esi"),
+            AMode::EDI => write!(f, "edi"),
+
+            AMode::SIB(base, index, scale) => {
+                if let Some(base) = base {
+                    write!(f, "{}", base.as_str())?;
+                }
+                if let Some(index) = index {
+                    if base.is_some() {
+                        write!(f, "+")?;
+                    }
+                    write!(f, "{}", index.as_str())?;
+                    if scale > 1 {
+                        write!(f, "*{}", scale)?;
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
 }
 
 impl AMode {
@@ -177,32 +222,28 @@ impl AMode {
             AMode::BXDI | AMode::ECX => 1,
             AMode::BPSI | AMode::EDX => 2,
             AMode::BPDI | AMode::EBX => 3,
-            AMode::SI | AMode::ESP => 4,
+            AMode::SI | AMode::ESP | AMode::SIB(_, _, _) => 4,
             AMode::DI | AMode::EBP => 5,
             AMode::BP | AMode::ESI => 6,
             AMode::BX | AMode::EDI => 7,
         }
     }
 
-    pub fn as_str(&self) -> &'static str {
+    /// returns true for addressing modes that require the 0x67 prefix in real mode
+    pub fn is_32bit(&self) -> bool {
+        match *self {
+            AMode::BXSI | AMode::BXDI | AMode::BPSI | AMode::BPDI |
+            AMode::SI | AMode::DI | AMode::BP | AMode::BX => false,
+            _ => true,
+        }
+    }
+
+    /// returns true for addressing modes based on BP, EBP or ESP, which default to the stack segment
+    pub fn is_stack_based(&self) -> bool {
         match *self {
-            AMode::BXSI => "bx+si",
-            AMode::BXDI => "bx+di",
-            AMode::BPSI => "bp+si",
-            AMode::BPDI => "bp+di",
-            AMode::SI => "si",
-            AMode::DI => "di",
-            AMode::BP => "bp",
-            AMode::BX => "bx",
-
-            AMode::EAX => "eax",
-            AMode::ECX => "ecx",
-            AMode::EDX => "edx",
-            AMode::EBX => "ebx",
-            AMode::ESP => "esp",
-            AMode::EBP => "ebp",
-            AMode::ESI => "esi",
-            AMode::EDI => "edi",
+            AMode::BPSI | AMode::BPDI | AMode::BP | AMode::ESP | AMode::EBP |
+            AMode::SIB(Some(R::ESP), _, _) | AMode::SIB(Some(R::EBP), _, _) => true,
+            _ => false,
         }
     }
 }
@@ -242,7 +283,8 @@ impl AddressSize {
 
 #[derive(Clone, Default)]
 pub struct RegisterSnapshot {
-    pub ip: u16,
+    /// EIP, above 0xFFFF only in 32-bit code segments
+    pub ip: u32,
     pub gpr: [GPR; 8 + 6 + 1],   // 8 general purpose registers, 6 segment registers, 1 ip
     pub sreg16: [u16; 6],        // segment registers
     pub flags: Flags,
@@ -293,7 +335,7 @@ impl RegisterSnapshot {
             R::DS => self.sreg16[3],
             R::FS => self.sreg16[4],
             R::GS => self.sreg16[5],
-            R::IP => self.ip,
+            R::IP => self.ip as u16,
             _ => unreachable!(),
         }
     }
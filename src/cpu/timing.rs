@@ -0,0 +1,546 @@
+// Instruction timing tables
+//
+// Clock counts are from the Intel 8086/8088, 80286 and 80386 programmer's
+// reference manuals. Instructions with data dependent timing (MUL, DIV)
+// use the average of the listed range.
+
+use cpu::instruction::{Instruction, RepeatMode};
+use cpu::op::Op;
+use cpu::parameter::Parameter;
+use cpu::register::AMode;
+use cpu::segment::Segment;
+
+#[cfg(test)]
+#[path = "./timing_test.rs"]
+mod timing_test;
+
+/// selects the instruction timing tables
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TimingProfile {
+    I8088,
+    I80286,
+    I80386,
+}
+
+impl TimingProfile {
+    /// cpu clock of a typical machine with this cpu
+    pub fn clock_hz(self) -> usize {
+        match self {
+            TimingProfile::I8088 => 4_772_727,   // IBM PC
+            TimingProfile::I80286 => 12_000_000, // IBM PC/AT model 339
+            TimingProfile::I80386 => 25_000_000,
+        }
+    }
+}
+
+/// how the operands of a instruction are accessed
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Form {
+    /// register operands only
+    Reg,
+    /// register and immediate operands
+    Imm,
+    /// a memory operand is read
+    MemSrc,
+    /// a memory operand is the destination
+    MemDst,
+}
+
+/// clock cycles of a instruction in each operand form
+struct Cycles {
+    reg: usize,
+    imm: usize,
+    mem_src: usize,
+    mem_dst: usize,
+}
+
+impl Cycles {
+    fn get(&self, form: Form) -> usize {
+        match form {
+            Form::Reg => self.reg,
+            Form::Imm => self.imm,
+            Form::MemSrc => self.mem_src,
+            Form::MemDst => self.mem_dst,
+        }
+    }
+}
+
+fn c(reg: usize, imm: usize, mem_src: usize, mem_dst: usize) -> Cycles {
+    Cycles { reg, imm, mem_src, mem_dst }
+}
+
+/// same clock count in all forms
+fn all(n: usize) -> Cycles {
+    c(n, n, n, n)
+}
+
+/// returns the number of clock cycles used to execute op.
+/// taken is true if the instruction transferred control, first_iteration is false
+/// for repeated iterations of a REP string instruction. count is the shift count
+pub fn instruction_cycles(profile: TimingProfile, op: &Instruction, taken: bool, first_iteration: bool, count: u8) -> usize {
+    if let Some((taken_cycles, not_taken)) = branch_cycles(profile, &op.command) {
+        return if taken { taken_cycles } else { not_taken };
+    }
+
+    if let Some((single, repeated, overhead)) = string_cycles(profile, &op.command) {
+        return match op.repeat {
+            RepeatMode::None => single,
+            _ if first_iteration => overhead + repeated,
+            _ => repeated,
+        };
+    }
+
+    let form = operand_form(op);
+    let cycles = match shift_cycles(profile, &op.command, form, count) {
+        Some(cycles) => cycles,
+        None => match profile {
+            TimingProfile::I8088 => table_8088(&op.command),
+            TimingProfile::I80286 => table_80286(&op.command),
+            TimingProfile::I80386 => table_80386(&op.command),
+        }.get(form),
+    };
+    cycles + memory_cycles(profile, op, form)
+}
+
+fn operand_form(op: &Instruction) -> Form {
+    if is_memory(&op.params.dst) {
+        Form::MemDst
+    } else if is_memory(&op.params.src) || is_memory(&op.params.src2) {
+        Form::MemSrc
+    } else if op.params.dst.is_imm() || op.params.src.is_imm() || op.params.src2.is_imm() {
+        Form::Imm
+    } else if let Parameter::Ptr16Imm(_, _) = op.params.dst {
+        Form::Imm
+    } else {
+        Form::Reg
+    }
+}
+
+fn is_memory(p: &Parameter) -> bool {
+    match *p {
+        Parameter::Ptr16Imm(_, _) => false,
+        _ => p.is_ptr(),
+    }
+}
+
+fn is_word_ptr(p: &Parameter) -> bool {
+    p.is_ptr() && !is_byte_ptr(p)
+}
+
+fn is_byte_ptr(p: &Parameter) -> bool {
+    match *p {
+        Parameter::Ptr8(_, _) |
+        Parameter::Ptr8Amode(_, _) |
+        Parameter::Ptr8AmodeS8(_, _, _) |
+        Parameter::Ptr8AmodeS16(_, _, _) |
+        Parameter::Ptr8AmodeS32(_, _, _) => true,
+        _ => false,
+    }
+}
+
+/// returns the memory operand of op
+fn memory_operand(op: &Instruction) -> Option<&Parameter> {
+    [&op.params.dst, &op.params.src, &op.params.src2].iter()
+        .find(|p| is_memory(p))
+        .cloned()
+}
+
+/// extra clocks for the effective address calculation and, on the
+/// 8088, for each word transferred over the 8-bit data bus
+fn memory_cycles(profile: TimingProfile, op: &Instruction, form: Form) -> usize {
+    let p = match memory_operand(op) {
+        Some(p) => p,
+        None => return 0,
+    };
+    match profile {
+        TimingProfile::I8088 => {
+            let mut cycles = ea_cycles_8086(p);
+            if op.segment_prefix != Segment::Default {
+                cycles += 2;
+            }
+            if is_word_ptr(p) || is_far_pointer_op(&op.command) {
+                cycles += 4 * word_transfers(&op.command, form);
+            }
+            cycles
+        }
+        TimingProfile::I80286 => match *p {
+            // base + index + displacement
+            Parameter::Ptr8AmodeS8(_, ref amode, _) |
+            Parameter::Ptr8AmodeS16(_, ref amode, _) |
+            Parameter::Ptr8AmodeS32(_, ref amode, _) |
+            Parameter::Ptr16AmodeS8(_, ref amode, _) |
+            Parameter::Ptr16AmodeS16(_, ref amode, _) |
+            Parameter::Ptr16AmodeS32(_, ref amode, _) |
+            Parameter::Ptr32AmodeS8(_, ref amode, _) |
+            Parameter::Ptr32AmodeS16(_, ref amode, _) |
+            Parameter::Ptr32AmodeS32(_, ref amode, _) => match *amode {
+                AMode::BXSI | AMode::BXDI | AMode::BPSI | AMode::BPDI => 1,
+                _ => 0,
+            },
+            _ => 0,
+        },
+        TimingProfile::I80386 => 0,
+    }
+}
+
+/// effective address calculation time of the 8086
+fn ea_cycles_8086(p: &Parameter) -> usize {
+    match *p {
+        Parameter::Ptr8(_, _) | Parameter::Ptr16(_, _) | Parameter::Ptr32(_, _) => 6,
+
+        Parameter::Ptr8Amode(_, ref amode) |
+        Parameter::Ptr16Amode(_, ref amode) |
+        Parameter::Ptr32Amode(_, ref amode) => amode_cycles_8086(amode),
+
+        Parameter::Ptr8AmodeS8(_, ref amode, _) |
+        Parameter::Ptr8AmodeS16(_, ref amode, _) |
+        Parameter::Ptr8AmodeS32(_, ref amode, _) |
+        Parameter::Ptr16AmodeS8(_, ref amode, _) |
+        Parameter::Ptr16AmodeS16(_, ref amode, _) |
+        Parameter::Ptr16AmodeS32(_, ref amode, _) |
+        Parameter::Ptr32AmodeS8(_, ref amode, _) |
+        Parameter::Ptr32AmodeS16(_, ref amode, _) |
+        Parameter::Ptr32AmodeS32(_, ref amode, _) => amode_cycles_8086(amode) + 4,
+
+        _ => 0,
+    }
+}
+
+fn amode_cycles_8086(amode: &AMode) -> usize {
+    match *amode {
+        AMode::BPDI | AMode::BXSI => 7,
+        AMode::BPSI | AMode::BXDI => 8,
+        _ => 5,
+    }
+}
+
+fn is_far_pointer_op(op: &Op) -> bool {
+    match *op {
+        Op::CallFar | Op::JmpFar | Op::Lds | Op::Les | Op::Lfs | Op::Lgs | Op::Lss => true,
+        _ => false,
+    }
+}
+
+/// number of words transferred to or from memory by the 8088
+fn word_transfers(op: &Op, form: Form) -> usize {
+    match *op {
+        Op::CallFar => 4,
+        Op::JmpFar | Op::Lds | Op::Les | Op::Lfs | Op::Lgs | Op::Lss => 2,
+        Op::Mov16 | Op::Mov32 | Op::Cmp16 | Op::Cmp32 | Op::Test16 | Op::Test32 | Op::JmpNear => 1,
+        _ if form == Form::MemDst => 2,
+        _ => 1,
+    }
+}
+
+/// clocks for (taken, not taken)
+fn branch_cycles(profile: TimingProfile, op: &Op) -> Option<(usize, usize)> {
+    let jcc = match *op {
+        Op::Ja | Op::Jc | Op::Jg | Op::Jl | Op::Jna | Op::Jnc | Op::Jng | Op::Jnl |
+        Op::Jno | Op::Jns | Op::Jnz | Op::Jo | Op::Jpe | Op::Jpo | Op::Js | Op::Jz => true,
+        _ => false,
+    };
+    Some(match profile {
+        TimingProfile::I8088 => match *op {
+            _ if jcc => (16, 4),
+            Op::Jcxz => (18, 6),
+            Op::Loop => (17, 5),
+            Op::Loope => (18, 6),
+            Op::Loopne => (19, 5),
+            Op::Into => (73, 4),
+            _ => return None,
+        },
+        TimingProfile::I80286 => match *op {
+            _ if jcc => (7, 3),
+            Op::Jcxz => (8, 4),
+            Op::Loop | Op::Loope | Op::Loopne => (8, 4),
+            Op::Into => (24, 3),
+            _ => return None,
+        },
+        TimingProfile::I80386 => match *op {
+            _ if jcc => (7, 3),
+            Op::Jcxz => (9, 5),
+            Op::Loop | Op::Loope | Op::Loopne => (11, 11),
+            Op::Into => (35, 3),
+            _ => return None,
+        },
+    })
+}
+
+/// clocks for (single, each repetition, REP overhead)
+fn string_cycles(profile: TimingProfile, op: &Op) -> Option<(usize, usize, usize)> {
+    Some(match profile {
+        TimingProfile::I8088 => match *op {
+            Op::Movsb => (18, 17, 9),
+            Op::Movsw | Op::Movsd => (26, 25, 9),
+            Op::Cmpsb => (22, 22, 9),
+            Op::Cmpsw | Op::Cmpsd => (30, 30, 9),
+            Op::Scasb => (15, 15, 9),
+            Op::Scasw | Op::Scasd => (19, 19, 9),
+            Op::Lodsb => (12, 13, 9),
+            Op::Lodsw | Op::Lodsd => (16, 17, 9),
+            Op::Stosb => (11, 10, 9),
+            Op::Stosw | Op::Stosd => (15, 14, 9),
+            Op::Insb | Op::Outsb => (14, 8, 8),
+            Op::Insw | Op::Outsw => (18, 12, 8),
+            _ => return None,
+        },
+        TimingProfile::I80286 => match *op {
+            Op::Movsb | Op::Movsw | Op::Movsd => (5, 4, 5),
+            Op::Cmpsb | Op::Cmpsw | Op::Cmpsd => (8, 9, 5),
+            Op::Scasb | Op::Scasw | Op::Scasd => (7, 8, 5),
+            Op::Lodsb | Op::Lodsw | Op::Lodsd => (5, 4, 5),
+            Op::Stosb | Op::Stosw | Op::Stosd => (3, 3, 4),
+            Op::Insb | Op::Insw | Op::Outsb | Op::Outsw => (5, 4, 5),
+            _ => return None,
+        },
+        TimingProfile::I80386 => match *op {
+            Op::Movsb | Op::Movsw | Op::Movsd => (7, 4, 5),
+            Op::Cmpsb | Op::Cmpsw | Op::Cmpsd => (10, 9, 5),
+            Op::Scasb | Op::Scasw | Op::Scasd => (7, 8, 5),
+            Op::Lodsb | Op::Lodsw | Op::Lodsd => (5, 6, 5),
+            Op::Stosb | Op::Stosw | Op::Stosd => (4, 5, 5),
+            Op::Insb | Op::Insw => (15, 8, 14),
+            Op::Outsb | Op::Outsw => (14, 5, 12),
+            _ => return None,
+        },
+    })
+}
+
+/// shifts and rotates, where the timing depends on the shift count
+fn shift_cycles(profile: TimingProfile, op: &Op, form: Form, count: u8) -> Option<usize> {
+    match *op {
+        Op::Rcl8 | Op::Rcl16 | Op::Rcl32 | Op::Rcr8 | Op::Rcr16 | Op::Rcr32 |
+        Op::Rol8 | Op::Rol16 | Op::Rol32 | Op::Ror8 | Op::Ror16 | Op::Ror32 |
+        Op::Sar8 | Op::Sar16 | Op::Sar32 | Op::Shl8 | Op::Shl16 | Op::Shl32 |
+        Op::Shr8 | Op::Shr16 | Op::Shr32 => {}
+        _ => return None,
+    }
+    let mem = form == Form::MemDst;
+    let n = usize::from(count);
+    Some(match profile {
+        TimingProfile::I8088 => match (count, mem) {
+            (1, false) => 2,
+            (1, true) => 15,
+            (_, false) => 8 + 4 * n,
+            (_, true) => 20 + 4 * n,
+        },
+        TimingProfile::I80286 => match (count, mem) {
+            (1, false) => 2,
+            (1, true) => 7,
+            (_, false) => 5 + (n & 0x1F),
+            (_, true) => 8 + (n & 0x1F),
+        },
+        TimingProfile::I80386 => if mem { 7 } else { 3 },
+    })
+}
+
+fn table_8088(op: &Op) -> Cycles {
+    match *op {
+        Op::Adc8 | Op::Adc16 | Op::Adc32 |
+        Op::Add8 | Op::Add16 | Op::Add32 |
+        Op::And8 | Op::And16 | Op::And32 |
+        Op::Or8 | Op::Or16 | Op::Or32 |
+        Op::Sbb8 | Op::Sbb16 | Op::Sbb32 |
+        Op::Sub8 | Op::Sub16 | Op::Sub32 |
+        Op::Xor8 | Op::Xor16 | Op::Xor32 => c(3, 4, 9, 16),
+        Op::Cmp8 | Op::Cmp16 | Op::Cmp32 => c(3, 4, 9, 9),
+        Op::Test8 | Op::Test16 | Op::Test32 => c(3, 5, 9, 9),
+        Op::Mov8 | Op::Mov16 | Op::Mov32 => c(2, 4, 8, 9),
+        Op::Movsx16 | Op::Movsx32 | Op::Movzx16 | Op::Movzx32 => c(3, 3, 6, 6),
+        Op::Inc8 | Op::Dec8 => c(3, 3, 15, 15),
+        Op::Inc16 | Op::Inc32 | Op::Dec16 | Op::Dec32 => c(2, 2, 15, 15),
+        Op::Neg8 | Op::Neg16 | Op::Neg32 |
+        Op::Not8 | Op::Not16 | Op::Not32 => c(3, 3, 16, 16),
+        Op::Mul8 => c(74, 74, 80, 80),
+        Op::Mul16 | Op::Mul32 => c(126, 126, 132, 132),
+        Op::Imul8 => c(89, 89, 95, 95),
+        Op::Imul16 | Op::Imul32 => c(141, 141, 147, 147),
+        Op::Div8 => c(85, 85, 91, 91),
+        Op::Div16 | Op::Div32 => c(153, 153, 159, 159),
+        Op::Idiv8 => c(107, 107, 113, 113),
+        Op::Idiv16 | Op::Idiv32 => c(175, 175, 181, 181),
+        Op::Push16 | Op::Push32 => c(15, 15, 16, 16),
+        Op::Pop16 | Op::Pop32 => c(12, 12, 17, 17),
+        Op::Pushf => all(14),
+        Op::Popf => all(12),
+        Op::Pusha16 | Op::Pushad32 => all(36),
+        Op::Popa16 | Op::Popad32 => all(51),
+        Op::Xchg8 | Op::Xchg16 | Op::Xchg32 => c(4, 4, 17, 17),
+        Op::Lea16 | Op::Lea32 => all(2),
+        Op::Lds | Op::Les => all(16),
+        Op::CallNear => c(20, 23, 21, 21),
+        Op::CallFar => c(36, 36, 37, 37),
+        Op::Retn => all(20),
+        Op::RetImm16 => all(24),
+        Op::Retf => c(34, 33, 34, 34),
+        Op::JmpShort | Op::JmpNear => c(11, 15, 18, 18),
+        Op::JmpFar => c(15, 15, 24, 24),
+        Op::Int => all(71),
+        Op::Iret => all(44),
+        Op::In8 | Op::Out8 => c(8, 10, 10, 10),
+        Op::In16 | Op::Out16 => c(12, 14, 14, 14),
+        Op::Clc | Op::Stc | Op::Cmc | Op::Cld | Op::Std | Op::Cli | Op::Sti => all(2),
+        Op::Cbw => all(2),
+        Op::Cwd16 | Op::Cwde32 | Op::Cdq32 => all(5),
+        Op::Lahf | Op::Sahf => all(4),
+        Op::Aaa | Op::Aas | Op::Daa | Op::Das => all(4),
+        Op::Aam => all(83),
+        Op::Aad => all(60),
+        Op::Xlatb => all(11),
+        Op::Hlt => all(2),
+        Op::Nop | Op::Salc => all(3),
+        Op::Enter => all(15),
+        Op::Leave => all(8),
+        Op::Bound => all(35),
+        _ => table_80386(op),
+    }
+}
+
+fn table_80286(op: &Op) -> Cycles {
+    match *op {
+        Op::Adc8 | Op::Adc16 | Op::Adc32 |
+        Op::Add8 | Op::Add16 | Op::Add32 |
+        Op::And8 | Op::And16 | Op::And32 |
+        Op::Or8 | Op::Or16 | Op::Or32 |
+        Op::Sbb8 | Op::Sbb16 | Op::Sbb32 |
+        Op::Sub8 | Op::Sub16 | Op::Sub32 |
+        Op::Xor8 | Op::Xor16 | Op::Xor32 => c(2, 3, 7, 7),
+        Op::Cmp8 | Op::Cmp16 | Op::Cmp32 => c(2, 3, 6, 6),
+        Op::Test8 | Op::Test16 | Op::Test32 => c(2, 3, 6, 6),
+        Op::Mov8 | Op::Mov16 | Op::Mov32 => c(2, 2, 5, 3),
+        Op::Movsx16 | Op::Movsx32 | Op::Movzx16 | Op::Movzx32 => c(3, 3, 6, 6),
+        Op::Inc8 | Op::Inc16 | Op::Inc32 |
+        Op::Dec8 | Op::Dec16 | Op::Dec32 |
+        Op::Neg8 | Op::Neg16 | Op::Neg32 |
+        Op::Not8 | Op::Not16 | Op::Not32 => c(2, 2, 7, 7),
+        Op::Mul8 | Op::Imul8 => c(13, 13, 16, 16),
+        Op::Mul16 | Op::Imul16 => c(21, 21, 24, 24),
+        Op::Div8 => c(14, 14, 17, 17),
+        Op::Div16 => c(22, 22, 25, 25),
+        Op::Idiv8 => c(17, 17, 20, 20),
+        Op::Idiv16 => c(25, 25, 28, 28),
+        Op::Push16 | Op::Push32 => c(3, 3, 5, 5),
+        Op::Pop16 | Op::Pop32 => all(5),
+        Op::Pushf => all(3),
+        Op::Popf => all(5),
+        Op::Pusha16 | Op::Pushad32 => all(17),
+        Op::Popa16 | Op::Popad32 => all(19),
+        Op::Xchg8 | Op::Xchg16 | Op::Xchg32 => c(3, 3, 5, 5),
+        Op::Lea16 | Op::Lea32 => all(3),
+        Op::Lds | Op::Les => all(7),
+        Op::CallNear => c(7, 7, 11, 11),
+        Op::CallFar => c(13, 13, 16, 16),
+        Op::Retn | Op::RetImm16 => all(11),
+        Op::Retf => all(15),
+        Op::JmpShort | Op::JmpNear => c(7, 7, 11, 11),
+        Op::JmpFar => c(11, 11, 15, 15),
+        Op::Int => all(23),
+        Op::Iret => all(17),
+        Op::In8 | Op::In16 => all(5),
+        Op::Out8 | Op::Out16 => all(3),
+        Op::Clc | Op::Stc | Op::Cmc | Op::Cld | Op::Std | Op::Cli | Op::Sti => all(2),
+        Op::Cbw | Op::Cwd16 | Op::Cwde32 | Op::Cdq32 | Op::Lahf | Op::Sahf => all(2),
+        Op::Aaa | Op::Aas | Op::Daa | Op::Das => all(3),
+        Op::Aam => all(16),
+        Op::Aad => all(14),
+        Op::Xlatb => all(5),
+        Op::Hlt => all(2),
+        Op::Nop | Op::Salc => all(3),
+        Op::Enter => all(11),
+        Op::Leave => all(5),
+        Op::Bound => all(13),
+        Op::Arpl => c(10, 10, 11, 11),
+        Op::Sldt | Op::Str | Op::Smsw => c(2, 2, 3, 3),
+        Op::Lldt | Op::Ltr => c(17, 17, 19, 19),
+        Op::Lgdt | Op::Sgdt => all(11),
+        Op::Lidt | Op::Sidt => all(12),
+        Op::Lmsw => c(3, 3, 6, 6),
+        Op::Verr | Op::Verw => c(14, 14, 16, 16),
+        Op::Clts => all(2),
+        _ => table_80386(op),
+    }
+}
+
+fn table_80386(op: &Op) -> Cycles {
+    match *op {
+        Op::Adc8 | Op::Adc16 | Op::Adc32 |
+        Op::Add8 | Op::Add16 | Op::Add32 |
+        Op::And8 | Op::And16 | Op::And32 |
+        Op::Or8 | Op::Or16 | Op::Or32 |
+        Op::Sbb8 | Op::Sbb16 | Op::Sbb32 |
+        Op::Sub8 | Op::Sub16 | Op::Sub32 |
+        Op::Xor8 | Op::Xor16 | Op::Xor32 => c(2, 2, 6, 7),
+        Op::Cmp8 | Op::Cmp16 | Op::Cmp32 => c(2, 2, 6, 5),
+        Op::Test8 | Op::Test16 | Op::Test32 => c(2, 2, 5, 5),
+        Op::Mov8 | Op::Mov16 | Op::Mov32 => c(2, 2, 4, 2),
+        Op::Movsx16 | Op::Movsx32 | Op::Movzx16 | Op::Movzx32 => c(3, 3, 6, 6),
+        Op::Inc8 | Op::Inc16 | Op::Inc32 |
+        Op::Dec8 | Op::Dec16 | Op::Dec32 |
+        Op::Neg8 | Op::Neg16 | Op::Neg32 |
+        Op::Not8 | Op::Not16 | Op::Not32 => c(2, 2, 6, 6),
+        Op::Mul8 | Op::Imul8 => c(12, 12, 15, 15),
+        Op::Mul16 | Op::Imul16 => c(17, 17, 20, 20),
+        Op::Mul32 | Op::Imul32 => c(25, 25, 28, 28),
+        Op::Div8 => c(14, 14, 17, 17),
+        Op::Div16 => c(22, 22, 25, 25),
+        Op::Div32 => c(38, 38, 41, 41),
+        Op::Idiv8 => c(19, 19, 22, 22),
+        Op::Idiv16 => c(27, 27, 30, 30),
+        Op::Idiv32 => c(43, 43, 46, 46),
+        Op::Shld | Op::Shrd => c(3, 3, 7, 7),
+        Op::Push16 | Op::Push32 => c(2, 2, 5, 5),
+        Op::Pop16 | Op::Pop32 => c(4, 4, 5, 5),
+        Op::Pushf => all(4),
+        Op::Popf => all(5),
+        Op::Pusha16 | Op::Pushad32 => all(18),
+        Op::Popa16 | Op::Popad32 => all(24),
+        Op::Xchg8 | Op::Xchg16 | Op::Xchg32 => c(3, 3, 5, 5),
+        Op::Lea16 | Op::Lea32 => all(2),
+        Op::Lds | Op::Les | Op::Lfs | Op::Lgs | Op::Lss => all(7),
+        Op::CallNear => c(7, 7, 10, 10),
+        Op::CallFar => c(17, 17, 22, 22),
+        Op::Retn | Op::RetImm16 => all(10),
+        Op::Retf => all(18),
+        Op::JmpShort | Op::JmpNear => c(7, 7, 10, 10),
+        Op::JmpFar => c(12, 12, 17, 17),
+        Op::Int => all(37),
+        Op::Iret => all(22),
+        Op::In8 | Op::In16 => c(13, 12, 12, 12),
+        Op::Out8 | Op::Out16 => c(11, 10, 10, 10),
+        Op::Clc | Op::Stc | Op::Cmc | Op::Cld | Op::Std => all(2),
+        Op::Cli | Op::Sti => all(3),
+        Op::Cbw | Op::Cwde32 | Op::Sahf => all(3),
+        Op::Cwd16 | Op::Cdq32 | Op::Lahf => all(2),
+        Op::Aaa | Op::Aas | Op::Daa | Op::Das => all(4),
+        Op::Aam => all(17),
+        Op::Aad => all(19),
+        Op::Xlatb => all(5),
+        Op::Hlt => all(5),
+        Op::Nop | Op::Salc => all(3),
+        Op::Enter => all(10),
+        Op::Leave => all(4),
+        Op::Bound => all(10),
+        Op::Arpl => c(20, 20, 21, 21),
+        Op::Sldt | Op::Str => all(2),
+        Op::Smsw => c(2, 2, 3, 3),
+        Op::Lldt => all(20),
+        Op::Ltr => all(23),
+        Op::Lgdt | Op::Lidt => all(11),
+        Op::Sgdt | Op::Sidt => all(9),
+        Op::Lmsw => c(10, 10, 13, 13),
+        Op::Verr => c(10, 10, 11, 11),
+        Op::Verw => c(15, 15, 16, 16),
+        Op::Clts => all(5),
+        Op::Bt => c(3, 3, 12, 12),
+        Op::Bts | Op::Btr | Op::Btc => c(6, 6, 13, 13),
+        Op::Bsf | Op::Bsr => all(11),
+        Op::Seto | Op::Setno | Op::Setc | Op::Setnc | Op::Setz | Op::Setnz | Op::Setna | Op::Seta |
+        Op::Sets | Op::Setns | Op::Setpe | Op::Setpo | Op::Setl | Op::Setnl | Op::Setng | Op::Setg => c(4, 4, 5, 5),
+        // 486 instructions, timed as on the 80486
+        Op::Xadd8 | Op::Xadd16 | Op::Xadd32 => c(3, 3, 4, 4),
+        Op::Cmpxchg8 | Op::Cmpxchg16 | Op::Cmpxchg32 => c(6, 6, 7, 10),
+        Op::Bswap => all(1),
+        Op::Cpuid => all(14),
+        _ => all(2),
+    }
+}
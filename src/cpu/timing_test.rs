@@ -0,0 +1,54 @@
+use machine::Machine;
+use cpu::TimingProfile;
+
+/// executes the instructions of code, returns the cycles used by each
+fn cycles_of(profile: TimingProfile, code: &[u8], count: usize) -> Vec<usize> {
+    let mut machine = Machine::default();
+    machine.cpu.set_timing_profile(profile);
+    machine.load_executable(code);
+    let mut res = Vec::new();
+    for _ in 0..count {
+        let before = machine.cpu.cycle_count;
+        machine.execute_instruction();
+        res.push(machine.cpu.cycle_count - before);
+    }
+    res
+}
+
+#[test]
+fn can_time_operand_forms() {
+    let code: Vec<u8> = vec![
+        0x89, 0xD8,             // mov ax,bx
+        0x01, 0x40, 0x04,       // add [bx+si+0x4],ax
+        0x26, 0x02, 0x07,       // add al,[es:bx]
+        0x83, 0xC0, 0x05,       // add ax,byte +0x5
+    ];
+    assert_eq!(vec![2, 16 + 11 + 8, 9 + 5 + 2, 4], cycles_of(TimingProfile::I8088, &code, 4));
+    assert_eq!(vec![2, 7 + 1, 7, 3], cycles_of(TimingProfile::I80286, &code, 4));
+    assert_eq!(vec![2, 7, 6, 2], cycles_of(TimingProfile::I80386, &code, 4));
+}
+
+#[test]
+fn can_time_branches_and_shifts() {
+    let code: Vec<u8> = vec![
+        0x31, 0xC9,             // xor cx,cx
+        0x75, 0x00,             // jnz 0x104        ; not taken
+        0x74, 0x01,             // jz 0x107         ; taken
+        0x90,                   // nop
+        0xB1, 0x04,             // mov cl,0x4
+        0xD3, 0xE0,             // shl ax,cl
+    ];
+    assert_eq!(vec![3, 4, 16, 4, 8 + 4 * 4], cycles_of(TimingProfile::I8088, &code, 5));
+    assert_eq!(vec![2, 3, 7, 2, 5 + 4], cycles_of(TimingProfile::I80286, &code, 5));
+}
+
+#[test]
+fn can_time_rep_string_instructions() {
+    let code: Vec<u8> = vec![
+        0xB9, 0x03, 0x00,       // mov cx,0x3
+        0xF3, 0xAA,             // rep stosb
+        0xAA,                   // stosb
+    ];
+    assert_eq!(vec![4, 9 + 10, 10, 10, 11], cycles_of(TimingProfile::I8088, &code, 5));
+    assert_eq!(vec![2, 5 + 5, 5, 5, 4], cycles_of(TimingProfile::I80386, &code, 5));
+}
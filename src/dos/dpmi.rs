@@ -0,0 +1,1069 @@
+// DPMI 0.9 host for 16-bit and 32-bit protected mode clients
+//
+// Programs detect the host with INT 2Fh AX=1687h and switch to protected mode with a far
+// call to the returned entry point. Clients run at privilege level 3 with selectors in the
+// host LDT. 32-bit clients get 386 interrupt gates and pass 32-bit offsets in the extended
+// registers. All IDT gates lead to the host code segment, which has the base of the BIOS ROM,
+// so protected mode interrupts end up in HLE handlers like in real mode. Interrupts the
+// client did not hook are handled directly, or reflected to their real mode handler.
+// http://www.delorie.com/djgpp/doc/dpmi/
+
+use std::collections::HashMap;
+
+use cpu::{CPU, CpuModel, R, Exception, RegisterSnapshot, Descriptor, DescriptorTable, Gate, CR0_PE, r32,
+    selector_is_ldt, selector_offset, SYSTEM_LDT, SYSTEM_INT_GATE_286, SYSTEM_INT_GATE_386, FLAG_CF, FLAG_DEFAULT_32};
+use hardware::Hardware;
+use memory::{MMU, MemoryAddress};
+
+#[cfg(test)]
+#[path = "./dpmi_test.rs"]
+mod dpmi_test;
+
+const DEBUG_DPMI: bool = false;
+
+/// segment of the host entry points, shared with the BIOS interrupt handlers
+pub const DPMI_ROM_SEG: u16 = 0xF000;
+
+// entry points in the ROM segment, above the BIOS interrupt handlers
+pub const DPMI_ENTRY_MODE_SWITCH: u16 = 0x0100;      // real mode far call to enter protected mode
+pub const DPMI_ENTRY_RM_RETURN: u16 = 0x0101;        // real mode calls of the host return here
+pub const DPMI_ENTRY_CALLBACK_RETURN: u16 = 0x0102;  // protected mode callback procedures IRET here
+pub const DPMI_ENTRY_EXCEPTION_RETURN: u16 = 0x0103; // client exception handlers RETF here
+pub const DPMI_ENTRY_IRET: u16 = 0x0104;             // IRET, returns from a interrupt after a real mode call
+pub const DPMI_ENTRY_EXCEPTION: u16 = 0x0105;        // the default exception handler
+pub const DPMI_ENTRY_SAVE_STATE: u16 = 0x0106;       // RETF, the state save procedure of INT 31h AX=0305h
+pub const DPMI_ENTRY_CALLBACKS: u16 = 0x0110;        // real mode callback addresses
+
+/// number of real mode callbacks
+pub const DPMI_CALLBACKS: u16 = 16;
+
+/// paragraphs of host data the client allocates before entering protected mode, used as real mode stack
+pub const DPMI_HOST_DATA_PARAGRAPHS: u16 = 0x0100;
+
+/// stack space for each level of nested real mode calls and callbacks
+const NESTED_STACK_SIZE: u16 = 0x0200;
+
+// host tables, above the high memory area
+const HOST_GDT: u32 = 0x0011_0000;
+const HOST_IDT: u32 = 0x0011_0100;
+const HOST_LDT: u32 = 0x0011_1000;
+const HOST_STACK: u32 = 0x0011_3000;
+const HOST_STACK_SIZE: u32 = 0x1000;
+
+/// number of LDT entries available to the client
+const LDT_ENTRIES: u16 = 0x0400;
+
+/// linear memory handed out by INT 31h AX=0501h, up to the end of memory
+const LINEAR_MEMORY_START: u32 = 0x0012_0000;
+
+const PAGE_SIZE: u32 = 0x1000;
+
+// GDT selectors
+const SELECTOR_HOST_CODE: u16 = 0x0008;
+const SELECTOR_LDT: u16 = 0x0010;
+const SELECTOR_HOST_STACK: u16 = 0x001B;
+
+/// the host code segment as seen by the client, which runs at privilege level 3
+pub const DPMI_HOST_CODE: u16 = SELECTOR_HOST_CODE | 3;
+
+// access bytes
+const ACCESS_CODE: u8 = 0xFA;      // present, dpl 3, readable code
+const ACCESS_DATA: u8 = 0xF2;      // present, dpl 3, writable data
+const ACCESS_HOST_CODE: u8 = 0x9E; // present, dpl 0, conforming readable code
+const ACCESS_INT_GATE: u8 = 0xE0; // present, dpl 3
+
+// real mode call structure, used by INT 31h AX=0300h-0302h and real mode callbacks
+const RMCS_EDI: u32 = 0x00;
+const RMCS_ESI: u32 = 0x04;
+const RMCS_EBP: u32 = 0x08;
+const RMCS_EBX: u32 = 0x10;
+const RMCS_EDX: u32 = 0x14;
+const RMCS_ECX: u32 = 0x18;
+const RMCS_EAX: u32 = 0x1C;
+const RMCS_FLAGS: u32 = 0x20;
+const RMCS_ES: u32 = 0x22;
+const RMCS_DS: u32 = 0x24;
+const RMCS_FS: u32 = 0x26;
+const RMCS_GS: u32 = 0x28;
+const RMCS_IP: u32 = 0x2A;
+const RMCS_CS: u32 = 0x2C;
+const RMCS_SP: u32 = 0x2E;
+const RMCS_SS: u32 = 0x30;
+
+/// flags passed back from a reflected interrupt
+const RESULT_FLAGS: u16 = 0x08D5; // OF, SF, ZF, AF, PF, CF
+
+/// DPMI 1.0 error codes, returned in AX with CF set
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DpmiError {
+    UnsupportedFunction = 0x8001,
+    DescriptorUnavailable = 0x8011,
+    LinearMemoryUnavailable = 0x8012,
+    CallbackUnavailable = 0x8015,
+    InvalidValue = 0x8021,
+    InvalidSelector = 0x8022,
+    InvalidHandle = 0x8023,
+    InvalidCallback = 0x8024,
+}
+
+impl DpmiError {
+    pub fn code(self) -> u16 {
+        self as u16
+    }
+}
+
+/// a block of linear memory allocated with INT 31h AX=0501h
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MemoryBlock {
+    pub handle: u32,
+    pub base: u32,
+    pub size: u32,
+}
+
+/// a real mode callback allocated with INT 31h AX=0303h
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Callback {
+    /// protected mode procedure, selector:offset
+    procedure: (u16, u32),
+
+    /// real mode call structure, selector:offset
+    call_struct: (u16, u32),
+
+    /// selects the real mode stack while the procedure runs
+    stack_selector: u16,
+}
+
+/// how a real mode procedure is entered by call_real_mode
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RealModeCall {
+    /// INT n through the interrupt vector table
+    Interrupt(u8),
+    /// far call to CS:IP of the call structure, returning with RETF
+    Far,
+    /// far call to CS:IP of the call structure, returning with IRET
+    Iret,
+}
+
+/// what the host does when a real mode call returns
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RealModeReturn {
+    /// a reflected interrupt passes the registers back to the client
+    Reflect,
+    /// the registers are stored in the real mode call structure at the linear address
+    CallStruct(u32),
+}
+
+/// the protected mode state of the client, saved during real mode calls
+struct ClientState {
+    regs: RegisterSnapshot,
+    flags_address: MemoryAddress,
+    ret: RealModeReturn,
+}
+
+pub struct DPMI {
+    /// a client has switched to protected mode
+    pub active: bool,
+
+    /// the client is a 32-bit program, with 32-bit offsets and interrupt frames
+    pub client32: bool,
+
+    /// LDT entries in use
+    ldt_used: Vec<bool>,
+
+    /// selectors created for real mode segments with AX=0002h, which are never freed
+    segment_selectors: HashMap<u16, u16>,
+
+    /// DOS memory blocks allocated with AX=0100h, by selector: segment and number of descriptors
+    dos_blocks: HashMap<u16, (u16, u16)>,
+
+    /// protected mode interrupt handlers of the client. None is the host default, which reflects to real mode
+    pm_vectors: Vec<Option<(u16, u32)>>,
+
+    /// exception handlers of the client
+    exception_handlers: Vec<Option<(u16, u32)>>,
+
+    callbacks: Vec<Option<Callback>>,
+
+    /// linear memory blocks, ordered by base address
+    memory_blocks: Vec<MemoryBlock>,
+    next_handle: u32,
+
+    /// real mode segment of the host data area, used as real mode stack
+    host_data_segment: u16,
+
+    /// client states of the real mode calls in progress
+    saved: Vec<ClientState>,
+
+    /// number of callback procedures in progress
+    callback_depth: u16,
+}
+
+impl DPMI {
+    pub fn default() -> Self {
+        DPMI {
+            active: false,
+            client32: false,
+            ldt_used: vec![false; LDT_ENTRIES as usize],
+            segment_selectors: HashMap::new(),
+            dos_blocks: HashMap::new(),
+            pm_vectors: vec![None; 0x100],
+            exception_handlers: vec![None; 0x20],
+            callbacks: vec![None; DPMI_CALLBACKS as usize],
+            memory_blocks: Vec::new(),
+            next_handle: 1,
+            host_data_segment: 0,
+            saved: Vec::new(),
+            callback_depth: 0,
+        }
+    }
+
+    /// writes the host GDT, IDT, an empty LDT and the host code in the ROM segment. for 32-bit
+    /// clients the host code and stack are 32-bit, so the IRET and RETF of the host pop 32-bit frames
+    pub fn init(&mut self, mmu: &mut MMU, client32: bool) {
+        *self = DPMI::default();
+        self.client32 = client32;
+        let (host_flags, gate_type) = if client32 {
+            (FLAG_DEFAULT_32, SYSTEM_INT_GATE_386)
+        } else {
+            (0, SYSTEM_INT_GATE_286)
+        };
+
+        write_descriptor(mmu, HOST_GDT, Descriptor::default());
+        write_descriptor(mmu, HOST_GDT + u32::from(SELECTOR_HOST_CODE), Descriptor {
+            base: u32::from(DPMI_ROM_SEG) << 4,
+            limit: 0xFFFF,
+            access: ACCESS_HOST_CODE,
+            flags: host_flags,
+        });
+        write_descriptor(mmu, HOST_GDT + u32::from(SELECTOR_LDT), Descriptor {
+            base: HOST_LDT,
+            limit: u32::from(LDT_ENTRIES) * 8 - 1,
+            access: 0x80 | SYSTEM_LDT,
+            flags: 0,
+        });
+        write_descriptor(mmu, HOST_GDT + u32::from(SELECTOR_HOST_STACK & !3), Descriptor {
+            base: HOST_STACK,
+            limit: HOST_STACK_SIZE - 1,
+            access: ACCESS_DATA,
+            flags: host_flags,
+        });
+
+        for vector in 0..0x100 {
+            let gate = Gate {
+                selector: SELECTOR_HOST_CODE,
+                offset: vector,
+                access: ACCESS_INT_GATE | gate_type,
+                param_count: 0,
+            };
+            let addr = HOST_IDT + vector * 8;
+            mmu.write_linear_u32(addr, gate.to_u64() as u32);
+            mmu.write_linear_u32(addr + 4, (gate.to_u64() >> 32) as u32);
+        }
+        for index in 0..LDT_ENTRIES {
+            write_descriptor(mmu, HOST_LDT + u32::from(index) * 8, Descriptor::default());
+        }
+
+        const IRET: u8 = 0xCF;
+        const RETF: u8 = 0xCB;
+        mmu.write_u8(DPMI_ROM_SEG, DPMI_ENTRY_IRET, IRET);
+        mmu.write_u8(DPMI_ROM_SEG, DPMI_ENTRY_SAVE_STATE, RETF);
+    }
+
+    /// the GDT of the host
+    pub fn gdt(&self) -> DescriptorTable {
+        DescriptorTable { base: HOST_GDT, limit: 0x1F }
+    }
+
+    /// the IDT of the host
+    pub fn idt(&self) -> DescriptorTable {
+        DescriptorTable { base: HOST_IDT, limit: 0x7FF }
+    }
+
+    /// the LDT of the host
+    pub fn ldt(&self) -> DescriptorTable {
+        DescriptorTable { base: HOST_LDT, limit: u32::from(LDT_ENTRIES) * 8 - 1 }
+    }
+
+    /// allocates count consecutive LDT descriptors as empty data segments, returns the first selector
+    pub fn allocate_descriptors(&mut self, mmu: &mut MMU, count: u16) -> Result<u16, DpmiError> {
+        if count == 0 {
+            return Err(DpmiError::InvalidValue);
+        }
+        let count = count as usize;
+        // index 0 is left unused, so no client selector looks like a null selector
+        let first = (1..LDT_ENTRIES as usize)
+            .find(|&i| i + count <= LDT_ENTRIES as usize && self.ldt_used[i..i + count].iter().all(|used| !used))
+            .ok_or(DpmiError::DescriptorUnavailable)?;
+        for index in first..first + count {
+            self.ldt_used[index] = true;
+            write_descriptor(mmu, HOST_LDT + index as u32 * 8, Descriptor {
+                base: 0,
+                limit: 0,
+                access: ACCESS_DATA,
+                flags: 0,
+            });
+        }
+        Ok(ldt_selector(first as u16))
+    }
+
+    /// allocates a descriptor for a segment at base
+    pub fn allocate_segment(&mut self, mmu: &mut MMU, base: u32, limit: u32, code: bool) -> Result<u16, DpmiError> {
+        let selector = self.allocate_descriptors(mmu, 1)?;
+        let access = if code { ACCESS_CODE } else { ACCESS_DATA };
+        self.set_descriptor(mmu, selector, Descriptor { base, limit, access, flags: 0 })?;
+        Ok(selector)
+    }
+
+    /// allocates a 4 GB segment at base 0 with 32-bit default size, for flat model programs
+    pub fn allocate_flat_segment(&mut self, mmu: &mut MMU, code: bool) -> Result<u16, DpmiError> {
+        let selector = self.allocate_descriptors(mmu, 1)?;
+        let access = if code { ACCESS_CODE } else { ACCESS_DATA };
+        self.set_descriptor(mmu, selector, Descriptor { base: 0, limit: 0xFFFF_FFFF, access, flags: FLAG_DEFAULT_32 })?;
+        Ok(selector)
+    }
+
+    pub fn free_descriptor(&mut self, mmu: &mut MMU, selector: u16) -> Result<(), DpmiError> {
+        let index = self.ldt_index(selector)?;
+        if self.segment_selectors.values().any(|&s| s == selector) {
+            // selectors of real mode segments can't be freed
+            return Err(DpmiError::InvalidSelector);
+        }
+        self.ldt_used[index as usize] = false;
+        write_descriptor(mmu, HOST_LDT + u32::from(index) * 8, Descriptor::default());
+        Ok(())
+    }
+
+    /// returns a selector for the real mode segment, the same selector is returned for the same segment
+    pub fn segment_to_descriptor(&mut self, mmu: &mut MMU, segment: u16) -> Result<u16, DpmiError> {
+        if let Some(&selector) = self.segment_selectors.get(&segment) {
+            return Ok(selector);
+        }
+        let selector = self.allocate_segment(mmu, u32::from(segment) << 4, 0xFFFF, false)?;
+        self.segment_selectors.insert(segment, selector);
+        Ok(selector)
+    }
+
+    /// reads the descriptor of a allocated selector
+    pub fn descriptor(&self, mmu: &MMU, selector: u16) -> Result<Descriptor, DpmiError> {
+        let index = self.ldt_index(selector)?;
+        Ok(Descriptor::from_u64(mmu.read_linear_u64(HOST_LDT + u32::from(index) * 8)))
+    }
+
+    /// replaces the descriptor of a allocated selector. clients can only create code and data segments at privilege level 3
+    pub fn set_descriptor(&mut self, mmu: &mut MMU, selector: u16, desc: Descriptor) -> Result<(), DpmiError> {
+        let index = self.ldt_index(selector)?;
+        if !desc.is_segment() || desc.dpl() != 3 {
+            return Err(DpmiError::InvalidValue);
+        }
+        if desc.limit > 0xF_FFFF && desc.limit & 0xFFF != 0xFFF {
+            // large limits are in 4k pages
+            return Err(DpmiError::InvalidValue);
+        }
+        write_descriptor(mmu, HOST_LDT + u32::from(index) * 8, desc);
+        Ok(())
+    }
+
+    /// returns the LDT index of a allocated selector
+    fn ldt_index(&self, selector: u16) -> Result<u16, DpmiError> {
+        let index = selector_offset(selector) >> 3;
+        if !selector_is_ldt(selector) || index >= LDT_ENTRIES || !self.ldt_used[index as usize] {
+            return Err(DpmiError::InvalidSelector);
+        }
+        Ok(index)
+    }
+
+    /// the protected mode handler of interrupt vector, as selector:offset
+    pub fn pm_vector(&self, vector: u8) -> (u16, u32) {
+        self.pm_vectors[vector as usize].unwrap_or((DPMI_HOST_CODE, u32::from(vector)))
+    }
+
+    /// sets the protected mode handler of interrupt vector. the host default restores the reflection to real mode
+    pub fn set_pm_vector(&mut self, vector: u8, selector: u16, offset: u32) {
+        self.pm_vectors[vector as usize] = if selector == DPMI_HOST_CODE && offset == u32::from(vector) {
+            None
+        } else {
+            Some((selector, offset))
+        };
+    }
+
+    /// the exception handler of the client, as selector:offset
+    pub fn exception_handler(&self, exception: u8) -> Result<(u16, u32), DpmiError> {
+        match self.exception_handlers.get(exception as usize) {
+            Some(handler) => Ok(handler.unwrap_or((DPMI_HOST_CODE, u32::from(DPMI_ENTRY_EXCEPTION)))),
+            None => Err(DpmiError::InvalidValue),
+        }
+    }
+
+    pub fn set_exception_handler(&mut self, exception: u8, selector: u16, offset: u32) -> Result<(), DpmiError> {
+        if exception as usize >= self.exception_handlers.len() {
+            return Err(DpmiError::InvalidValue);
+        }
+        self.exception_handlers[exception as usize] = if selector == DPMI_HOST_CODE && offset == u32::from(DPMI_ENTRY_EXCEPTION) {
+            None
+        } else {
+            Some((selector, offset))
+        };
+        Ok(())
+    }
+
+    /// allocates a real mode callback to the protected mode procedure, returns the real mode address as segment:offset
+    pub fn allocate_callback(&mut self, mmu: &mut MMU, procedure: (u16, u32), call_struct: (u16, u32)) -> Result<(u16, u16), DpmiError> {
+        let index = self.callbacks.iter().position(|cb| cb.is_none()).ok_or(DpmiError::CallbackUnavailable)?;
+        let stack_selector = self.allocate_descriptors(mmu, 1)?;
+        self.set_descriptor(mmu, stack_selector, Descriptor { base: 0, limit: 0xFFFF, access: ACCESS_DATA, flags: 0 })?;
+        self.callbacks[index] = Some(Callback { procedure, call_struct, stack_selector });
+        Ok((DPMI_ROM_SEG, DPMI_ENTRY_CALLBACKS + index as u16))
+    }
+
+    pub fn free_callback(&mut self, mmu: &mut MMU, segment: u16, offset: u16) -> Result<(), DpmiError> {
+        let index = offset.wrapping_sub(DPMI_ENTRY_CALLBACKS);
+        if segment != DPMI_ROM_SEG || index >= DPMI_CALLBACKS {
+            return Err(DpmiError::InvalidCallback);
+        }
+        match self.callbacks[index as usize].take() {
+            Some(cb) => self.free_descriptor(mmu, cb.stack_selector),
+            None => Err(DpmiError::InvalidCallback),
+        }
+    }
+
+    /// allocates a block of linear memory, rounded up to whole pages
+    pub fn allocate_memory(&mut self, mmu: &MMU, size: u32) -> Result<MemoryBlock, DpmiError> {
+        if size == 0 {
+            return Err(DpmiError::InvalidValue);
+        }
+        let size = size.checked_add(PAGE_SIZE - 1).ok_or(DpmiError::LinearMemoryUnavailable)? & !(PAGE_SIZE - 1);
+        let base = self.find_free_memory(mmu, size, None)?;
+        let block = MemoryBlock { handle: self.next_handle, base, size };
+        self.next_handle += 1;
+        let pos = self.memory_blocks.iter().position(|b| b.base > base).unwrap_or(self.memory_blocks.len());
+        self.memory_blocks.insert(pos, block);
+        Ok(block)
+    }
+
+    pub fn free_memory(&mut self, handle: u32) -> Result<(), DpmiError> {
+        let pos = self.memory_blocks.iter().position(|b| b.handle == handle).ok_or(DpmiError::InvalidHandle)?;
+        self.memory_blocks.remove(pos);
+        Ok(())
+    }
+
+    /// resizes a block of linear memory. the block is moved with its contents if it can't grow in place
+    pub fn resize_memory(&mut self, mmu: &mut MMU, handle: u32, size: u32) -> Result<MemoryBlock, DpmiError> {
+        if size == 0 {
+            return Err(DpmiError::InvalidValue);
+        }
+        let size = size.checked_add(PAGE_SIZE - 1).ok_or(DpmiError::LinearMemoryUnavailable)? & !(PAGE_SIZE - 1);
+        let pos = self.memory_blocks.iter().position(|b| b.handle == handle).ok_or(DpmiError::InvalidHandle)?;
+        let old = self.memory_blocks[pos];
+        let base = self.find_free_memory(mmu, size, Some(handle))?;
+        if base != old.base {
+            let data = mmu.memory.borrow().read(old.base, old.size.min(size) as usize);
+            mmu.memory.borrow_mut().write(base, &data);
+        }
+        self.memory_blocks.remove(pos);
+        let block = MemoryBlock { handle, base, size };
+        let pos = self.memory_blocks.iter().position(|b| b.base > base).unwrap_or(self.memory_blocks.len());
+        self.memory_blocks.insert(pos, block);
+        Ok(block)
+    }
+
+    /// returns the base of the first free range of size bytes. the block of handle counts as free,
+    /// and is kept in place if it fits
+    fn find_free_memory(&self, mmu: &MMU, size: u32, handle: Option<u32>) -> Result<u32, DpmiError> {
+        let end = memory_end(mmu);
+        let blocks: Vec<&MemoryBlock> = self.memory_blocks.iter().filter(|b| Some(b.handle) != handle).collect();
+        if let Some(old) = handle.and_then(|h| self.memory_blocks.iter().find(|b| b.handle == h)) {
+            let limit = blocks.iter().map(|b| b.base).find(|&b| b > old.base).unwrap_or(end);
+            if u64::from(old.base) + u64::from(size) <= u64::from(limit) {
+                return Ok(old.base);
+            }
+        }
+        let mut base = LINEAR_MEMORY_START;
+        for block in blocks {
+            if u64::from(base) + u64::from(size) <= u64::from(block.base) {
+                return Ok(base);
+            }
+            base = block.base + block.size;
+        }
+        if u64::from(base) + u64::from(size) <= u64::from(end) {
+            Ok(base)
+        } else {
+            Err(DpmiError::LinearMemoryUnavailable)
+        }
+    }
+
+    /// size of the largest free block of linear memory
+    pub fn largest_free_memory(&self, mmu: &MMU) -> u32 {
+        let mut largest = 0;
+        let mut base = LINEAR_MEMORY_START;
+        for block in &self.memory_blocks {
+            largest = largest.max(block.base - base);
+            base = block.base + block.size;
+        }
+        largest.max(memory_end(mmu).saturating_sub(base))
+    }
+
+    /// total size of free linear memory
+    pub fn free_memory_size(&self, mmu: &MMU) -> u32 {
+        let used: u32 = self.memory_blocks.iter().map(|b| b.size).sum();
+        memory_end(mmu) - LINEAR_MEMORY_START - used
+    }
+
+    /// records a DOS memory block allocated with AX=0100h
+    pub fn add_dos_block(&mut self, selector: u16, segment: u16, descriptors: u16) {
+        self.dos_blocks.insert(selector, (segment, descriptors));
+    }
+
+    /// returns the segment and number of descriptors of a DOS memory block
+    pub fn dos_block(&self, selector: u16) -> Result<(u16, u16), DpmiError> {
+        self.dos_blocks.get(&selector).cloned().ok_or(DpmiError::InvalidSelector)
+    }
+
+    pub fn remove_dos_block(&mut self, selector: u16) {
+        self.dos_blocks.remove(&selector);
+    }
+}
+
+/// selector with RPL 3 of the LDT entry
+fn ldt_selector(index: u16) -> u16 {
+    index << 3 | 4 | 3
+}
+
+fn write_descriptor(mmu: &mut MMU, addr: u32, desc: Descriptor) {
+    let raw = desc.to_u64();
+    mmu.write_linear_u32(addr, raw as u32);
+    mmu.write_linear_u32(addr + 4, (raw >> 32) as u32);
+}
+
+/// first address after the emulated memory
+fn memory_end(mmu: &MMU) -> u32 {
+    mmu.memory.borrow().memory.len() as u32
+}
+
+/// sets the descriptors of a DOS memory block of paragraphs size. the first descriptor covers
+/// the whole block, the following ones each the next 64k
+pub fn set_dos_block_descriptors(dpmi: &mut DPMI, mmu: &mut MMU, selector: u16, segment: u16, paragraphs: u16, descriptors: u16) -> Result<(), DpmiError> {
+    let base = u32::from(segment) << 4;
+    let size = u32::from(paragraphs) << 4;
+    for i in 0..u32::from(descriptors) {
+        let offset = i * 0x1_0000;
+        let limit = if i == 0 { size } else { size.saturating_sub(offset).min(0x1_0000) };
+        dpmi.set_descriptor(mmu, selector + i as u16 * 8, Descriptor {
+            base: base + offset,
+            limit: limit.saturating_sub(1),
+            access: ACCESS_DATA,
+            flags: 0,
+        })?;
+    }
+    Ok(())
+}
+
+/// number of descriptors for a DOS memory block
+pub fn dos_block_descriptors(paragraphs: u16) -> u16 {
+    ((u32::from(paragraphs) + 0xFFF) >> 12).max(1) as u16
+}
+
+/// switches the program to protected mode. the client continues at cs:ip with selectors for
+/// CS, DS and SS, and ES selecting the PSP. host_data is the real mode segment of the host data area.
+/// the segments are 16-bit for 32-bit clients too, they only differ in the size of offsets and frames
+pub fn enter_protected_mode(cpu: &mut CPU, hw: &mut Hardware, cs: u16, ip: u16, host_data: u16, client32: bool) -> Result<(), DpmiError> {
+    if hw.dos.dpmi.active || cpu.protected_mode() {
+        // XXX only one client is supported
+        return Err(DpmiError::UnsupportedFunction);
+    }
+    if client32 && cpu.model() < CpuModel::I80386 {
+        return Err(DpmiError::UnsupportedFunction);
+    }
+    hw.dos.dpmi.init(&mut hw.mmu, client32);
+    hw.dos.dpmi.host_data_segment = host_data;
+
+    let psp = hw.dos.psp_segment;
+    let ds = cpu.get_r16(R::DS);
+    let ss = cpu.get_r16(R::SS);
+    let dpmi = &mut hw.dos.dpmi;
+    let mmu = &mut hw.mmu;
+    let cs_sel = dpmi.allocate_segment(mmu, u32::from(cs) << 4, 0xFFFF, true)?;
+    let ds_sel = dpmi.allocate_segment(mmu, u32::from(ds) << 4, 0xFFFF, false)?;
+    let ss_sel = if ss == ds { ds_sel } else { dpmi.allocate_segment(mmu, u32::from(ss) << 4, 0xFFFF, false)? };
+    let psp_sel = dpmi.allocate_segment(mmu, u32::from(psp) << 4, 0xFF, false)?;
+
+    // the environment segment in the PSP is replaced by a selector
+    let env = mmu.read_u16(psp, ::dos::PSP_ENV_SEGMENT);
+    if env != 0 {
+        let env_sel = dpmi.allocate_segment(mmu, u32::from(env) << 4, 0xFFFF, false)?;
+        mmu.write_u16(psp, ::dos::PSP_ENV_SEGMENT, env_sel);
+    }
+
+    cpu.gdtr = dpmi.gdt();
+    cpu.idtr = dpmi.idt();
+    cpu.ldtr = SELECTOR_LDT;
+    cpu.ldt = dpmi.ldt();
+    let cr0 = cpu.cr0 | CR0_PE;
+    cpu.set_cr0(mmu, cr0);
+    load_segments(cpu, mmu, &[(R::CS, cs_sel), (R::SS, ss_sel), (R::DS, ds_sel), (R::ES, psp_sel), (R::FS, 0), (R::GS, 0)])?;
+    cpu.regs.ip = u32::from(ip);
+    // the upper half of ESP is cleared for 32-bit clients
+    let sp = cpu.get_r16(R::SP);
+    cpu.set_r32(R::ESP, u32::from(sp));
+    dpmi.active = true;
+    if DEBUG_DPMI {
+        println!("dpmi: entered protected mode at {:04X}:{:04X}", cs_sel, ip);
+    }
+    Ok(())
+}
+
+/// loads segment registers in order. CS must be first, as it sets the privilege level
+fn load_segments(cpu: &mut CPU, mmu: &mut MMU, segments: &[(R, u16)]) -> Result<(), DpmiError> {
+    for &(r, selector) in segments {
+        if cpu.load_segment_register(mmu, r, selector).is_err() {
+            println!("dpmi: can't load {} with selector {:04X}", r.as_str(), selector);
+            return Err(DpmiError::InvalidSelector);
+        }
+    }
+    Ok(())
+}
+
+/// reloads the segment registers holding selector, after its descriptor was changed
+pub fn reload_segments(cpu: &mut CPU, hw: &mut Hardware, selector: u16) {
+    for r in &[R::CS, R::SS, R::DS, R::ES, R::FS, R::GS] {
+        if cpu.get_r16(*r) == selector && cpu.load_segment_register(&mut hw.mmu, *r, selector).is_err() && *r != R::CS && *r != R::SS {
+            // a data segment register with a selector that can't be loaded anymore is cleared
+            let _ = cpu.load_segment_register(&mut hw.mmu, *r, 0);
+        }
+    }
+}
+
+/// linear address of selector:offset, from the LDT of the host
+pub fn linear_address(hw: &Hardware, selector: u16, offset: u32) -> Result<u32, DpmiError> {
+    let desc = hw.dos.dpmi.descriptor(&hw.mmu, selector)?;
+    Ok(desc.base.wrapping_add(offset))
+}
+
+/// returns the processor type reported to clients (02h 80286, 03h 80386, 04h 80486)
+pub fn processor_type(cpu: &CPU) -> u8 {
+    match cpu.model() {
+        CpuModel::I80486 => 0x04,
+        CpuModel::I80386 => 0x03,
+        _ => 0x02,
+    }
+}
+
+/// reads a offset or count passed in the 16-bit register r, which is the 32-bit register for 32-bit
+/// clients in protected mode
+pub fn client_offset(cpu: &CPU, hw: &Hardware, r: R) -> u32 {
+    if hw.dos.dpmi.client32 && cpu.protected_mode() {
+        cpu.get_r32(r32(r.index() as u8))
+    } else {
+        u32::from(cpu.get_r16(r))
+    }
+}
+
+/// returns a offset or count in the 16-bit register r, or the 32-bit register for 32-bit clients in protected mode
+pub fn set_client_offset(cpu: &mut CPU, hw: &Hardware, r: R, offset: u32) {
+    if hw.dos.dpmi.client32 && cpu.protected_mode() {
+        cpu.set_r32(r32(r.index() as u8), offset);
+    } else {
+        cpu.set_r16(r, offset as u16);
+    }
+}
+
+/// handles the entry points of the host in the ROM segment. unknown offsets are left to the code at CS:IP
+pub fn handle_dpmi_entry(cpu: &mut CPU, hw: &mut Hardware, ip: u16) {
+    match ip {
+        DPMI_ENTRY_MODE_SWITCH if !cpu.protected_mode() => {
+            // far call with AX = flags (bit 0: 32-bit client), ES = host data segment
+            let ret_ip = cpu.pop16(&mut hw.mmu);
+            let ret_cs = cpu.pop16(&mut hw.mmu);
+            let host_data = cpu.get_r16(R::ES);
+            let client32 = cpu.get_r16(R::AX) & 1 != 0;
+            let result = enter_protected_mode(cpu, hw, ret_cs, ret_ip, host_data, client32);
+            if result.is_err() {
+                cpu.load_segment_register(&mut hw.mmu, R::CS, ret_cs).ok();
+                cpu.regs.ip = u32::from(ret_ip);
+            }
+            cpu.regs.flags.carry = result.is_err();
+        }
+        DPMI_ENTRY_RM_RETURN if !cpu.protected_mode() => return_from_real_mode(cpu, hw),
+        DPMI_ENTRY_CALLBACK_RETURN if cpu.protected_mode() => return_from_callback(cpu, hw),
+        DPMI_ENTRY_EXCEPTION_RETURN if cpu.protected_mode() => return_from_exception(cpu, hw),
+        DPMI_ENTRY_EXCEPTION if cpu.protected_mode() => {
+            // the default handler, reached by clients chaining to the previous exception handler
+            let exception = cpu.last_exception.map_or(0xFF, |e| e as u8);
+            let (cs, ip) = (cpu.get_r16(R::CS), cpu.regs.ip);
+            println!("[{:04X}:{:04X}] ERROR: unhandled exception {:02X} in DPMI client", cs, ip, exception);
+            cpu.fatal_error = true;
+        }
+        _ => {
+            let index = ip.wrapping_sub(DPMI_ENTRY_CALLBACKS);
+            if !cpu.protected_mode() && index < DPMI_CALLBACKS {
+                enter_callback(cpu, hw, index);
+            }
+        }
+    }
+}
+
+/// handles interrupt vector in protected mode, after the IDT gate entered the host code segment
+pub fn handle_dpmi_interrupt(cpu: &mut CPU, hw: &mut Hardware, vector: u8) {
+    if !hw.dos.dpmi.active {
+        return cpu.handle_interrupt(hw, vector);
+    }
+    if let Some(exception) = cpu.last_exception {
+        if exception as u8 == vector {
+            cpu.last_exception = None;
+            return dispatch_exception(cpu, hw, exception);
+        }
+    }
+    if let Some((selector, offset)) = hw.dos.dpmi.pm_vectors[vector as usize] {
+        // the interrupt frame is left for the IRET of the client handler
+        if cpu.load_segment_register(&mut hw.mmu, R::CS, selector).is_err() {
+            println!("dpmi: invalid handler {:04X}:{:04X} for interrupt {:02X}", selector, offset, vector);
+            cpu.fatal_error = true;
+        }
+        cpu.regs.ip = offset;
+        return;
+    }
+    if vector == 0x21 && translate_dos_call(cpu, hw) {
+        return;
+    }
+    let rm_offset = hw.mmu.read_linear_u16(u32::from(vector) * 4);
+    let rm_segment = hw.mmu.read_linear_u16(u32::from(vector) * 4 + 2);
+    if vector == 0x31 || (rm_segment == DPMI_ROM_SEG && rm_offset == u16::from(vector)) {
+        // the real mode handler is emulated, which can take the protected mode registers
+        cpu.handle_interrupt(hw, vector);
+    } else if let Err(e) = call_real_mode(cpu, hw, RealModeCall::Interrupt(vector), None, 0) {
+        println!("dpmi: can't reflect interrupt {:02X}: {:?}", vector, e);
+    }
+}
+
+/// DOS functions that work on interrupt vectors use the protected mode vectors when called from
+/// protected mode, as done by DOS extenders. returns true if the call was handled
+fn translate_dos_call(cpu: &mut CPU, hw: &mut Hardware) -> bool {
+    match cpu.get_r8(R::AH) {
+        0x25 => {
+            // DOS 1+ - SET INTERRUPT VECTOR
+            let (selector, offset) = (cpu.get_r16(R::DS), client_offset(cpu, hw, R::DX));
+            hw.dos.dpmi.set_pm_vector(cpu.get_r8(R::AL), selector, offset);
+            true
+        }
+        0x35 => {
+            // DOS 2+ - GET INTERRUPT VECTOR
+            let (selector, offset) = hw.dos.dpmi.pm_vector(cpu.get_r8(R::AL));
+            if cpu.load_segment_register(&mut hw.mmu, R::ES, selector).is_err() {
+                return false;
+            }
+            set_client_offset(cpu, hw, R::BX, offset);
+            true
+        }
+        _ => false,
+    }
+}
+
+/// calls a real mode procedure or interrupt handler. with a call structure at the linear address,
+/// the real mode registers are loaded from it and stored back on return, and stack_words words are
+/// copied from the client stack. without one, the interrupt is reflected with the current registers
+pub fn call_real_mode(cpu: &mut CPU, hw: &mut Hardware, call: RealModeCall, call_struct: Option<u32>, stack_words: u16) -> Result<(), DpmiError> {
+    let depth = hw.dos.dpmi.saved.len() as u16 + hw.dos.dpmi.callback_depth;
+    let stack_top = (DPMI_HOST_DATA_PARAGRAPHS << 4).wrapping_sub(depth * NESTED_STACK_SIZE);
+    if (depth + 1) * NESTED_STACK_SIZE > DPMI_HOST_DATA_PARAGRAPHS << 4 {
+        return Err(DpmiError::UnsupportedFunction);
+    }
+
+    // the parameters are above the interrupt frame of INT 31h
+    let frame = if hw.dos.dpmi.client32 { 12 } else { 6 };
+    let params_addr = cpu.segment_base(R::SS).wrapping_add(cpu.stack_pointer().wrapping_add(frame));
+    let params: Vec<u16> = (0..u32::from(stack_words)).map(|i| hw.mmu.read_linear_u16(params_addr + i * 2)).collect();
+
+    hw.dos.dpmi.saved.push(ClientState {
+        regs: cpu.regs.clone(),
+        flags_address: hw.bios.flags_address,
+        ret: match call_struct {
+            Some(addr) => RealModeReturn::CallStruct(addr),
+            None => RealModeReturn::Reflect,
+        },
+    });
+
+    let host_data = hw.dos.dpmi.host_data_segment;
+    let mut segments = [(R::SS, host_data), (R::DS, host_data), (R::ES, host_data), (R::FS, 0), (R::GS, 0)];
+    let mut sp = stack_top;
+    let mut target = (0, 0);
+    if let Some(addr) = call_struct {
+        let mmu = &hw.mmu;
+        cpu.set_r32(R::EDI, mmu.read_linear_u32(addr + RMCS_EDI));
+        cpu.set_r32(R::ESI, mmu.read_linear_u32(addr + RMCS_ESI));
+        cpu.set_r32(R::EBP, mmu.read_linear_u32(addr + RMCS_EBP));
+        cpu.set_r32(R::EBX, mmu.read_linear_u32(addr + RMCS_EBX));
+        cpu.set_r32(R::EDX, mmu.read_linear_u32(addr + RMCS_EDX));
+        cpu.set_r32(R::ECX, mmu.read_linear_u32(addr + RMCS_ECX));
+        cpu.set_r32(R::EAX, mmu.read_linear_u32(addr + RMCS_EAX));
+        cpu.regs.flags.set_u16(mmu.read_linear_u16(addr + RMCS_FLAGS));
+        segments[2].1 = mmu.read_linear_u16(addr + RMCS_ES);
+        segments[1].1 = mmu.read_linear_u16(addr + RMCS_DS);
+        segments[3].1 = mmu.read_linear_u16(addr + RMCS_FS);
+        segments[4].1 = mmu.read_linear_u16(addr + RMCS_GS);
+        let (ss, struct_sp) = (mmu.read_linear_u16(addr + RMCS_SS), mmu.read_linear_u16(addr + RMCS_SP));
+        if ss != 0 || struct_sp != 0 {
+            segments[0].1 = ss;
+            sp = struct_sp;
+        }
+        target = (mmu.read_linear_u16(addr + RMCS_CS), mmu.read_linear_u16(addr + RMCS_IP));
+    }
+
+    let cr0 = cpu.cr0 & !CR0_PE;
+    cpu.set_cr0(&mut hw.mmu, cr0);
+    load_segments(cpu, &mut hw.mmu, &segments)?;
+    cpu.set_r16(R::SP, sp);
+    for word in params.iter().rev() {
+        cpu.push16(&mut hw.mmu, *word);
+    }
+    if call != RealModeCall::Far {
+        let flags = cpu.regs.flags.u16();
+        cpu.push16(&mut hw.mmu, flags);
+        hw.bios.flags_address = MemoryAddress::RealSegmentOffset(cpu.get_r16(R::SS), cpu.get_r16(R::SP));
+    }
+    cpu.push16(&mut hw.mmu, DPMI_ROM_SEG);
+    cpu.push16(&mut hw.mmu, DPMI_ENTRY_RM_RETURN);
+    if let RealModeCall::Interrupt(vector) = call {
+        cpu.regs.flags.interrupt = false;
+        cpu.regs.flags.trap = false;
+        target = (hw.mmu.read_linear_u16(u32::from(vector) * 4 + 2), hw.mmu.read_linear_u16(u32::from(vector) * 4));
+    }
+    if DEBUG_DPMI {
+        println!("dpmi: real mode call {:?} to {:04X}:{:04X}", call, target.0, target.1);
+    }
+    load_segments(cpu, &mut hw.mmu, &[(R::CS, target.0)])?;
+    cpu.regs.ip = u32::from(target.1);
+    Ok(())
+}
+
+/// the real mode procedure of call_real_mode returned to the host
+fn return_from_real_mode(cpu: &mut CPU, hw: &mut Hardware) {
+    let state = match hw.dos.dpmi.saved.pop() {
+        Some(state) => state,
+        None => {
+            println!("dpmi: real mode return without a call");
+            cpu.fatal_error = true;
+            return;
+        }
+    };
+    let result = cpu.regs.clone();
+    if let RealModeReturn::CallStruct(addr) = state.ret {
+        let mmu = &mut hw.mmu;
+        mmu.write_linear_u32(addr + RMCS_EDI, result.get_r32(R::EDI));
+        mmu.write_linear_u32(addr + RMCS_ESI, result.get_r32(R::ESI));
+        mmu.write_linear_u32(addr + RMCS_EBP, result.get_r32(R::EBP));
+        mmu.write_linear_u32(addr + RMCS_EBX, result.get_r32(R::EBX));
+        mmu.write_linear_u32(addr + RMCS_EDX, result.get_r32(R::EDX));
+        mmu.write_linear_u32(addr + RMCS_ECX, result.get_r32(R::ECX));
+        mmu.write_linear_u32(addr + RMCS_EAX, result.get_r32(R::EAX));
+        mmu.write_linear_u16(addr + RMCS_FLAGS, result.flags.u16());
+        mmu.write_linear_u16(addr + RMCS_ES, result.get_r16(R::ES));
+        mmu.write_linear_u16(addr + RMCS_DS, result.get_r16(R::DS));
+        mmu.write_linear_u16(addr + RMCS_FS, result.get_r16(R::FS));
+        mmu.write_linear_u16(addr + RMCS_GS, result.get_r16(R::GS));
+    }
+
+    restore_client(cpu, hw, &state.regs);
+    hw.bios.flags_address = state.flags_address;
+    match state.ret {
+        RealModeReturn::Reflect => {
+            for r in &[R::EAX, R::ECX, R::EDX, R::EBX, R::EBP, R::ESI, R::EDI] {
+                cpu.set_r32(*r, result.get_r32(*r));
+            }
+            set_frame_flags(hw, RESULT_FLAGS, result.flags.u16());
+        }
+        RealModeReturn::CallStruct(_) => set_frame_flags(hw, FLAG_CF, 0),
+    }
+    // continues with the IRET of the interrupt that made the call
+    cpu.regs.ip = u32::from(DPMI_ENTRY_IRET);
+}
+
+/// switches back to protected mode with the saved client registers
+fn restore_client(cpu: &mut CPU, hw: &mut Hardware, regs: &RegisterSnapshot) {
+    let cr0 = cpu.cr0 | CR0_PE;
+    cpu.set_cr0(&mut hw.mmu, cr0);
+    let segments = [R::CS, R::SS, R::DS, R::ES, R::FS, R::GS];
+    let segments: Vec<(R, u16)> = segments.iter().map(|r| (*r, regs.get_r16(*r))).collect();
+    if load_segments(cpu, &mut hw.mmu, &segments).is_err() {
+        cpu.fatal_error = true;
+    }
+    cpu.regs = regs.clone();
+}
+
+/// updates the flags in the interrupt frame of the current HLE interrupt
+fn set_frame_flags(hw: &mut Hardware, mask: u16, flags: u16) {
+    if hw.bios.flags_address == MemoryAddress::Unset {
+        return;
+    }
+    let addr = hw.bios.flags_address.value();
+    let old = hw.mmu.memory.borrow().read_u16(addr);
+    hw.mmu.memory.borrow_mut().write_u16(addr, (old & !mask) | (flags & mask));
+}
+
+/// a real mode program called a callback address. the registers are stored in the call structure, and the
+/// protected mode procedure is called with DS:(E)SI selecting the real mode stack and ES:(E)DI the call structure
+fn enter_callback(cpu: &mut CPU, hw: &mut Hardware, index: u16) {
+    let cb = match hw.dos.dpmi.callbacks[index as usize] {
+        Some(cb) => cb,
+        None => {
+            println!("dpmi: call to free callback {}", index);
+            cpu.fatal_error = true;
+            return;
+        }
+    };
+    let addr = match linear_address(hw, cb.call_struct.0, cb.call_struct.1) {
+        Ok(addr) => addr,
+        Err(_) => {
+            cpu.fatal_error = true;
+            return;
+        }
+    };
+    {
+        let mmu = &mut hw.mmu;
+        mmu.write_linear_u32(addr + RMCS_EDI, cpu.get_r32(R::EDI));
+        mmu.write_linear_u32(addr + RMCS_ESI, cpu.get_r32(R::ESI));
+        mmu.write_linear_u32(addr + RMCS_EBP, cpu.get_r32(R::EBP));
+        mmu.write_linear_u32(addr + RMCS_EBX, cpu.get_r32(R::EBX));
+        mmu.write_linear_u32(addr + RMCS_EDX, cpu.get_r32(R::EDX));
+        mmu.write_linear_u32(addr + RMCS_ECX, cpu.get_r32(R::ECX));
+        mmu.write_linear_u32(addr + RMCS_EAX, cpu.get_r32(R::EAX));
+        mmu.write_linear_u16(addr + RMCS_FLAGS, cpu.regs.flags.u16());
+        mmu.write_linear_u16(addr + RMCS_ES, cpu.get_r16(R::ES));
+        mmu.write_linear_u16(addr + RMCS_DS, cpu.get_r16(R::DS));
+        mmu.write_linear_u16(addr + RMCS_FS, cpu.get_r16(R::FS));
+        mmu.write_linear_u16(addr + RMCS_GS, cpu.get_r16(R::GS));
+        mmu.write_linear_u16(addr + RMCS_IP, cpu.regs.ip as u16);
+        mmu.write_linear_u16(addr + RMCS_CS, cpu.get_r16(R::CS));
+        mmu.write_linear_u16(addr + RMCS_SP, cpu.get_r16(R::SP));
+        mmu.write_linear_u16(addr + RMCS_SS, cpu.get_r16(R::SS));
+    }
+    let rm_ss = cpu.get_r16(R::SS);
+    let rm_sp = cpu.get_r16(R::SP);
+    let flags = cpu.regs.flags.u16();
+    let stack = Descriptor { base: u32::from(rm_ss) << 4, limit: 0xFFFF, access: ACCESS_DATA, flags: 0 };
+    if hw.dos.dpmi.set_descriptor(&mut hw.mmu, cb.stack_selector, stack).is_err() {
+        cpu.fatal_error = true;
+        return;
+    }
+
+    let depth = hw.dos.dpmi.callback_depth;
+    hw.dos.dpmi.callback_depth += 1;
+    let cr0 = cpu.cr0 | CR0_PE;
+    cpu.set_cr0(&mut hw.mmu, cr0);
+    let segments = [(R::CS, cb.procedure.0), (R::SS, SELECTOR_HOST_STACK), (R::DS, cb.stack_selector),
+        (R::ES, cb.call_struct.0), (R::FS, 0), (R::GS, 0)];
+    if load_segments(cpu, &mut hw.mmu, &segments).is_err() {
+        cpu.fatal_error = true;
+        return;
+    }
+    cpu.set_stack_pointer(HOST_STACK_SIZE - u32::from(depth * NESTED_STACK_SIZE));
+    set_client_offset(cpu, hw, R::SI, u32::from(rm_sp));
+    set_client_offset(cpu, hw, R::DI, cb.call_struct.1);
+    if hw.dos.dpmi.client32 {
+        for dword in &[u32::from(flags), u32::from(DPMI_HOST_CODE), u32::from(DPMI_ENTRY_CALLBACK_RETURN)] {
+            cpu.push32(&mut hw.mmu, *dword);
+        }
+    } else {
+        for word in &[flags, DPMI_HOST_CODE, DPMI_ENTRY_CALLBACK_RETURN] {
+            cpu.push16(&mut hw.mmu, *word);
+        }
+    }
+    cpu.regs.flags.interrupt = false;
+    cpu.regs.flags.trap = false;
+    cpu.regs.ip = cb.procedure.1;
+}
+
+/// the callback procedure returned, the real mode program continues with the registers in the call structure at ES:(E)DI
+fn return_from_callback(cpu: &mut CPU, hw: &mut Hardware) {
+    let addr = cpu.segment_base(R::ES).wrapping_add(client_offset(cpu, hw, R::DI));
+    hw.dos.dpmi.callback_depth = hw.dos.dpmi.callback_depth.saturating_sub(1);
+
+    let mmu = &hw.mmu;
+    let regs = [
+        (R::EDI, mmu.read_linear_u32(addr + RMCS_EDI)),
+        (R::ESI, mmu.read_linear_u32(addr + RMCS_ESI)),
+        (R::EBP, mmu.read_linear_u32(addr + RMCS_EBP)),
+        (R::EBX, mmu.read_linear_u32(addr + RMCS_EBX)),
+        (R::EDX, mmu.read_linear_u32(addr + RMCS_EDX)),
+        (R::ECX, mmu.read_linear_u32(addr + RMCS_ECX)),
+        (R::EAX, mmu.read_linear_u32(addr + RMCS_EAX)),
+    ];
+    let flags = mmu.read_linear_u16(addr + RMCS_FLAGS);
+    let segments = [
+        (R::CS, mmu.read_linear_u16(addr + RMCS_CS)),
+        (R::SS, mmu.read_linear_u16(addr + RMCS_SS)),
+        (R::DS, mmu.read_linear_u16(addr + RMCS_DS)),
+        (R::ES, mmu.read_linear_u16(addr + RMCS_ES)),
+        (R::FS, mmu.read_linear_u16(addr + RMCS_FS)),
+        (R::GS, mmu.read_linear_u16(addr + RMCS_GS)),
+    ];
+    let ip = mmu.read_linear_u16(addr + RMCS_IP);
+    let sp = mmu.read_linear_u16(addr + RMCS_SP);
+
+    let cr0 = cpu.cr0 & !CR0_PE;
+    cpu.set_cr0(&mut hw.mmu, cr0);
+    if load_segments(cpu, &mut hw.mmu, &segments).is_err() {
+        cpu.fatal_error = true;
+    }
+    for &(r, val) in &regs {
+        cpu.set_r32(r, val);
+    }
+    cpu.regs.flags.set_u16(flags);
+    cpu.set_r16(R::SP, sp);
+    cpu.regs.ip = u32::from(ip);
+}
+
+/// calls the exception handler of the client with a DPMI exception frame on the stack:
+/// return CS:IP to the host, error code, IP, CS, flags, SP, SS. the entries are dwords for 32-bit clients
+fn dispatch_exception(cpu: &mut CPU, hw: &mut Hardware, exception: Exception) {
+    let client32 = hw.dos.dpmi.client32;
+    let error = if exception.has_error_code() { pop_frame_entry(cpu, hw, client32) } else { 0 };
+    let ip = pop_frame_entry(cpu, hw, client32);
+    let cs = pop_frame_entry(cpu, hw, client32);
+    let flags = pop_frame_entry(cpu, hw, client32);
+    let (handler_cs, handler_ip) = match hw.dos.dpmi.exception_handlers[exception as usize] {
+        Some(handler) => handler,
+        None => {
+            println!("[{:04X}:{:08X}] ERROR: unhandled exception {:?} in DPMI client, error code {:04X}", cs, ip, exception, error);
+            cpu.fatal_error = true;
+            return;
+        }
+    };
+    let (ss, sp) = (u32::from(cpu.get_r16(R::SS)), cpu.stack_pointer());
+    let frame = [ss, sp, flags, cs, ip, error, u32::from(DPMI_HOST_CODE), u32::from(DPMI_ENTRY_EXCEPTION_RETURN)];
+    for entry in &frame {
+        if client32 {
+            cpu.push32(&mut hw.mmu, *entry);
+        } else {
+            cpu.push16(&mut hw.mmu, *entry as u16);
+        }
+    }
+    if load_segments(cpu, &mut hw.mmu, &[(R::CS, handler_cs)]).is_err() {
+        cpu.fatal_error = true;
+    }
+    cpu.regs.ip = handler_ip;
+}
+
+/// the exception handler returned, execution continues as given by the exception frame
+fn return_from_exception(cpu: &mut CPU, hw: &mut Hardware) {
+    let client32 = hw.dos.dpmi.client32;
+    let _error = pop_frame_entry(cpu, hw, client32);
+    let ip = pop_frame_entry(cpu, hw, client32);
+    let cs = pop_frame_entry(cpu, hw, client32) as u16;
+    let flags = pop_frame_entry(cpu, hw, client32) as u16;
+    let sp = pop_frame_entry(cpu, hw, client32);
+    let ss = pop_frame_entry(cpu, hw, client32) as u16;
+    if load_segments(cpu, &mut hw.mmu, &[(R::CS, cs), (R::SS, ss)]).is_err() {
+        cpu.fatal_error = true;
+    }
+    cpu.set_stack_pointer(sp);
+    cpu.regs.flags.set_u16(flags);
+    cpu.regs.ip = ip;
+}
+
+/// pops a entry of a interrupt or exception frame, a dword for 32-bit clients
+fn pop_frame_entry(cpu: &mut CPU, hw: &mut Hardware, client32: bool) -> u32 {
+    if client32 {
+        cpu.pop32(&mut hw.mmu)
+    } else {
+        u32::from(cpu.pop16(&mut hw.mmu))
+    }
+}
@@ -0,0 +1,285 @@
+use machine::Machine;
+use memory::MMU;
+use cpu::{CpuModel, R};
+use dos::{DPMI, DpmiError, MemoryBlock, DPMI_ROM_SEG, DPMI_ENTRY_MODE_SWITCH};
+
+/// code entering protected mode through the DPMI host, ends at offset 0x118 in protected mode
+fn enter_protected_mode_code() -> Vec<u8> {
+    vec![
+        0xB8, 0x87, 0x16,       // mov ax,0x1687
+        0xCD, 0x2F,             // int 0x2f
+        0x89, 0x3E, 0x00, 0x02, // mov [0x200],di
+        0x8C, 0x06, 0x02, 0x02, // mov [0x202],es
+        0xB8, 0x00, 0x20,       // mov ax,0x2000
+        0x8E, 0xC0,             // mov es,ax
+        0x31, 0xC0,             // xor ax,ax
+        0xFF, 0x1E, 0x00, 0x02, // call far [0x200]
+    ]
+}
+
+/// number of calls to execute_instruction to run enter_protected_mode_code
+const ENTER_STEPS: usize = 10;
+
+#[test]
+fn can_enter_protected_mode() {
+    let mut machine = Machine::default();
+    let mut code = enter_protected_mode_code();
+    code.extend_from_slice(&[
+        0xB8, 0x00, 0x04,       // mov ax,0x400
+        0xCD, 0x31,             // int 0x31
+    ]);
+    machine.load_executable(&code);
+    let psp = machine.cpu.get_r16(R::CS);
+
+    machine.execute_instructions(ENTER_STEPS);
+    assert_eq!(DPMI_ROM_SEG, machine.hw.mmu.read_u16(psp, 0x202));
+    assert_eq!(DPMI_ENTRY_MODE_SWITCH, machine.hw.mmu.read_u16(psp, 0x200));
+    assert_eq!(true, machine.cpu.protected_mode());
+    assert_eq!(true, machine.hw.dos.dpmi.active);
+    assert_eq!(false, machine.cpu.regs.flags.carry);
+    assert_eq!(3, machine.cpu.cpl());
+    assert_eq!(0x0118, machine.cpu.regs.ip);
+    assert_eq!(u32::from(psp) << 4, machine.cpu.segment_base(R::CS));
+    assert_eq!(u32::from(psp) << 4, machine.cpu.segment_base(R::DS));
+    assert_eq!(u32::from(psp) << 4, machine.cpu.segment_base(R::ES));
+    assert_ne!(psp, machine.cpu.get_r16(R::CS));
+
+    // the environment segment in the PSP is replaced by a selector
+    let env = machine.hw.mmu.read_u16(machine.cpu.get_r16(R::ES), 0x2C);
+    assert_eq!(Ok(0x0820 << 4), machine.hw.dos.dpmi.descriptor(&machine.hw.mmu, env).map(|desc| desc.base));
+
+    machine.execute_instructions(3);
+    assert_eq!(0x011D, machine.cpu.regs.ip);
+    assert_eq!(0x005A, machine.cpu.get_r16(R::AX));
+    assert_eq!(0x03, machine.cpu.get_r8(R::CL));
+}
+
+#[test]
+fn reports_the_host_by_cpu_model() {
+    for &(model, ax, bx, cl) in &[
+        (CpuModel::I8086, 0x1687, 0x0000, 0x00),
+        (CpuModel::I80186, 0x1687, 0x0000, 0x00),
+        (CpuModel::I80286, 0x0000, 0x0000, 0x02),
+        (CpuModel::I80386, 0x0000, 0x0001, 0x03),
+        (CpuModel::I80486, 0x0000, 0x0001, 0x04),
+    ] {
+        let mut machine = Machine::default();
+        machine.set_cpu_model(model);
+        machine.load_executable(&[
+            0x31, 0xDB,             // xor bx,bx
+            0xB1, 0x00,             // mov cl,0x0
+            0xB8, 0x87, 0x16,       // mov ax,0x1687
+            0xCD, 0x2F,             // int 0x2f
+        ]);
+
+        machine.execute_instructions(5);
+        assert_eq!(0x0109, machine.cpu.regs.ip, "{:?}", model);
+        assert_eq!(ax, machine.cpu.get_r16(R::AX), "{:?}", model);
+        assert_eq!(bx, machine.cpu.get_r16(R::BX), "{:?}", model);
+        assert_eq!(cl, machine.cpu.get_r8(R::CL), "{:?}", model);
+    }
+}
+
+#[test]
+fn can_manage_descriptors() {
+    let mut machine = Machine::default();
+    let mut code = enter_protected_mode_code();
+    code.extend_from_slice(&[
+        0xB8, 0x00, 0x00,       // mov ax,0x0
+        0xB9, 0x01, 0x00,       // mov cx,0x1
+        0xCD, 0x31,             // int 0x31
+        0x89, 0xC3,             // mov bx,ax
+        0xB8, 0x07, 0x00,       // mov ax,0x7
+        0xB9, 0x03, 0x00,       // mov cx,0x3
+        0xBA, 0x00, 0x00,       // mov dx,0x0
+        0xCD, 0x31,             // int 0x31
+        0xB8, 0x08, 0x00,       // mov ax,0x8
+        0x31, 0xC9,             // xor cx,cx
+        0xBA, 0xFF, 0x0F,       // mov dx,0xfff
+        0xCD, 0x31,             // int 0x31
+        0x8E, 0xC3,             // mov es,bx
+        0x26, 0xC7, 0x06, 0x10, 0x00, 0x34, 0x12, // mov word [es:0x10],0x1234
+    ]);
+    machine.load_executable(&code);
+    machine.execute_instructions(ENTER_STEPS);
+
+    machine.execute_instructions(4);
+    assert_eq!(false, machine.cpu.regs.flags.carry);
+    let selector = machine.cpu.get_r16(R::AX);
+    assert_eq!(7, selector & 7);
+
+    machine.execute_instructions(13);
+    assert_eq!(false, machine.cpu.regs.flags.carry);
+    assert_eq!(selector, machine.cpu.get_r16(R::ES));
+    assert_eq!(0x0003_0000, machine.cpu.segment_base(R::ES));
+    assert_eq!(0x1234, machine.hw.mmu.memory.borrow().read_u16(0x0003_0010));
+
+    let desc = machine.hw.dos.dpmi.descriptor(&machine.hw.mmu, selector).unwrap();
+    assert_eq!(0x0FFF, desc.limit);
+    assert_eq!(3, desc.dpl());
+}
+
+#[test]
+fn can_reflect_interrupts_to_real_mode() {
+    let mut machine = Machine::default();
+    let mut code = enter_protected_mode_code();
+    code.extend_from_slice(&[
+        0xB8, 0x11, 0x11,       // mov ax,0x1111
+        0xCD, 0x60,             // int 0x60
+    ]);
+    machine.load_executable(&code);
+    let psp = machine.cpu.get_r16(R::CS);
+
+    // real mode handler of int 0x60
+    machine.hw.mmu.write(psp, 0x0300, &[
+        0xB8, 0x78, 0x56,       // mov ax,0x5678
+        0xCF,                   // iret
+    ]);
+    machine.hw.mmu.write_u16(0, 0x60 * 4, 0x0300);
+    machine.hw.mmu.write_u16(0, 0x60 * 4 + 2, psp);
+
+    machine.execute_instructions(ENTER_STEPS);
+    let cs = machine.cpu.get_r16(R::CS);
+
+    // the interrupt is reflected to the real mode handler
+    machine.execute_instructions(3);
+    assert_eq!(false, machine.cpu.protected_mode());
+    assert_eq!(psp, machine.cpu.get_r16(R::CS));
+    assert_eq!(0x0300, machine.cpu.regs.ip);
+    assert_eq!(0x1111, machine.cpu.get_r16(R::AX));
+
+    // and returns to protected mode with the registers of the real mode handler
+    machine.execute_instructions(4);
+    assert_eq!(true, machine.cpu.protected_mode());
+    assert_eq!(cs, machine.cpu.get_r16(R::CS));
+    assert_eq!(0x011D, machine.cpu.regs.ip);
+    assert_eq!(0x5678, machine.cpu.get_r16(R::AX));
+}
+
+#[test]
+fn can_handle_exceptions() {
+    let mut machine = Machine::default();
+    let mut code = enter_protected_mode_code();
+    code.extend_from_slice(&[
+        0xB8, 0x03, 0x02,       // mov ax,0x203
+        0xB3, 0x0D,             // mov bl,0xd
+        0x8C, 0xC9,             // mov cx,cs
+        0xBA, 0x40, 0x01,       // mov dx,0x140
+        0xCD, 0x31,             // int 0x31
+        0xB8, 0x34, 0x12,       // mov ax,0x1234
+        0x8E, 0xD8,             // mov ds,ax
+        0x90,                   // nop
+    ]);
+    machine.load_executable(&code);
+    let psp = machine.cpu.get_r16(R::CS);
+
+    // exception handler, skips the faulting instruction
+    machine.hw.mmu.write(psp, 0x0140, &[
+        0x89, 0xE5,             // mov bp,sp
+        0x83, 0x46, 0x06, 0x02, // add word [bp+0x6],byte +0x2
+        0xCB,                   // retf
+    ]);
+
+    machine.execute_instructions(ENTER_STEPS);
+    let cs = machine.cpu.get_r16(R::CS);
+    let ds = machine.cpu.get_r16(R::DS);
+
+    // the general protection fault calls the handler with a DPMI exception frame
+    machine.execute_instructions(9);
+    assert_eq!(cs, machine.cpu.get_r16(R::CS));
+    assert_eq!(0x0140, machine.cpu.regs.ip);
+    let ss = machine.cpu.get_r16(R::SS);
+    let sp = machine.cpu.get_r16(R::SP);
+    assert_eq!(0x0103, machine.hw.mmu.read_u16(ss, sp));
+    assert_eq!(0x000B, machine.hw.mmu.read_u16(ss, sp + 2));
+    assert_eq!(0x1234, machine.hw.mmu.read_u16(ss, sp + 4));
+    assert_eq!(0x0127, machine.hw.mmu.read_u16(ss, sp + 6));
+    assert_eq!(cs, machine.cpu.get_r16(R::CS));
+
+    // returning from the handler continues as given by the frame
+    machine.execute_instructions(4);
+    assert_eq!(cs, machine.cpu.get_r16(R::CS));
+    assert_eq!(0x0129, machine.cpu.regs.ip);
+    assert_eq!(ds, machine.cpu.get_r16(R::DS));
+}
+
+#[test]
+fn can_allocate_linear_memory() {
+    let mut mmu = MMU::default();
+    let mut dpmi = DPMI::default();
+    dpmi.init(&mut mmu, false);
+
+    let a = dpmi.allocate_memory(&mmu, 0x1800).unwrap();
+    assert_eq!(MemoryBlock { handle: 1, base: 0x0012_0000, size: 0x2000 }, a);
+    let b = dpmi.allocate_memory(&mmu, 0x1000).unwrap();
+    assert_eq!(0x0012_2000, b.base);
+    assert_eq!(Err(DpmiError::InvalidValue), dpmi.allocate_memory(&mmu, 0).map(|_| ()));
+
+    // growing a block that can't grow in place moves its contents
+    mmu.memory.borrow_mut().write_u16(a.base + 0x10, 0x1234);
+    let a = dpmi.resize_memory(&mut mmu, a.handle, 0x3000).unwrap();
+    assert_eq!(0x0012_3000, a.base);
+    assert_eq!(0x1234, mmu.memory.borrow().read_u16(a.base + 0x10));
+
+    // freed space is reused
+    assert_eq!(Ok(()), dpmi.free_memory(b.handle));
+    assert_eq!(Err(DpmiError::InvalidHandle), dpmi.free_memory(b.handle));
+    let c = dpmi.allocate_memory(&mmu, 0x2000).unwrap();
+    assert_eq!(0x0012_0000, c.base);
+    let total = mmu.memory.borrow().memory.len() as u32 - 0x0012_0000;
+    assert_eq!(total - 0x5000, dpmi.free_memory_size(&mmu));
+}
+
+#[test]
+fn can_handle_exceptions_of_32bit_clients() {
+    let mut machine = Machine::default();
+    let mut code = enter_protected_mode_code();
+    code[18..20].copy_from_slice(&[0xB0, 0x01]); // mov al,0x1 selects a 32-bit client
+    code.extend_from_slice(&[
+        0xB8, 0x03, 0x02,       // mov ax,0x203
+        0xB3, 0x0D,             // mov bl,0xd
+        0x8C, 0xC9,             // mov cx,cs
+        0x66, 0xBA, 0x40, 0x01, 0x00, 0x00, // mov edx,0x140
+        0xCD, 0x31,             // int 0x31
+        0xB8, 0x34, 0x12,       // mov ax,0x1234
+        0x8E, 0xD8,             // mov ds,ax
+        0x90,                   // nop
+    ]);
+    machine.load_executable(&code);
+    let psp = machine.cpu.get_r16(R::CS);
+
+    // exception handler, skips the faulting instruction
+    machine.hw.mmu.write(psp, 0x0140, &[
+        0x89, 0xE5,             // mov bp,sp
+        0x66, 0x83, 0x46, 0x0C, 0x02, // add dword [bp+0xc],byte +0x2
+        0x66, 0xCB,             // o32 retf
+    ]);
+
+    machine.execute_instructions(ENTER_STEPS);
+    assert_eq!(true, machine.hw.dos.dpmi.client32);
+    assert_eq!(3, machine.cpu.cpl());
+    let cs = machine.cpu.get_r16(R::CS);
+    let ds = machine.cpu.get_r16(R::DS);
+    let sp = machine.cpu.get_r16(R::SP);
+
+    // the handler was set with a 32-bit offset, the exception frame has dwords
+    machine.execute_instructions(9);
+    assert_eq!(cs, machine.cpu.get_r16(R::CS));
+    assert_eq!(0x0140, machine.cpu.regs.ip);
+    let ss = machine.cpu.get_r16(R::SS);
+    let frame_sp = machine.cpu.get_r16(R::SP);
+    assert_eq!(sp - 32, frame_sp);
+    assert_eq!(0x0000_0103, machine.hw.mmu.read_u32(ss, frame_sp));
+    assert_eq!(0x0000_000B, machine.hw.mmu.read_u32(ss, frame_sp + 4));
+    assert_eq!(0x0000_1234, machine.hw.mmu.read_u32(ss, frame_sp + 8));
+    assert_eq!(0x0000_012A, machine.hw.mmu.read_u32(ss, frame_sp + 12));
+    assert_eq!(u32::from(cs), machine.hw.mmu.read_u32(ss, frame_sp + 16));
+    assert_eq!(u32::from(sp), machine.hw.mmu.read_u32(ss, frame_sp + 24));
+
+    // returning from the handler continues as given by the frame
+    machine.execute_instructions(4);
+    assert_eq!(cs, machine.cpu.get_r16(R::CS));
+    assert_eq!(0x012C, machine.cpu.regs.ip);
+    assert_eq!(sp, machine.cpu.get_r16(R::SP));
+    assert_eq!(ds, machine.cpu.get_r16(R::DS));
+}
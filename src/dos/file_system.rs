@@ -0,0 +1,280 @@
+// A DOS drive backed by a host directory, used by the INT 21h handle functions
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use dos::DosError;
+
+#[cfg(test)]
+#[path = "./file_system_test.rs"]
+mod file_system_test;
+
+/// size of the system file table, like FILES=20 in CONFIG.SYS
+const MAX_HANDLES: usize = 20;
+
+// the predefined handles, open at program start
+pub const STDIN: u16 = 0;
+pub const STDOUT: u16 = 1;
+pub const STDERR: u16 = 2;
+pub const STDAUX: u16 = 3;
+pub const STDPRN: u16 = 4;
+
+/// first handle returned for a opened file
+pub const FIRST_FILE_HANDLE: u16 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AccessMode {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl AccessMode {
+    /// decodes the access mode from AL of INT 21h AH=3Dh
+    pub fn from_u8(al: u8) -> Result<Self, DosError> {
+        match al & 7 {
+            0 => Ok(AccessMode::Read),
+            1 => Ok(AccessMode::Write),
+            2 => Ok(AccessMode::ReadWrite),
+            _ => Err(DosError::InvalidAccessCode),
+        }
+    }
+
+    fn can_read(self) -> bool {
+        self != AccessMode::Write
+    }
+
+    fn can_write(self) -> bool {
+        self != AccessMode::Read
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SeekOrigin {
+    Start,
+    Current,
+    End,
+}
+
+impl SeekOrigin {
+    /// decodes the seek origin from AL of INT 21h AH=42h
+    pub fn from_u8(al: u8) -> Result<Self, DosError> {
+        match al {
+            0 => Ok(SeekOrigin::Start),
+            1 => Ok(SeekOrigin::Current),
+            2 => Ok(SeekOrigin::End),
+            _ => Err(DosError::InvalidFunction),
+        }
+    }
+}
+
+struct OpenFile {
+    file: File,
+    mode: AccessMode,
+}
+
+pub struct FileSystem {
+    /// host directory mounted as drive C:
+    root: Option<PathBuf>,
+
+    /// index is the DOS file handle
+    handles: Vec<Option<OpenFile>>,
+}
+
+impl FileSystem {
+    pub fn default() -> Self {
+        FileSystem {
+            root: None,
+            handles: (0..MAX_HANDLES).map(|_| None).collect(),
+        }
+    }
+
+    /// mounts a host directory as drive C:
+    pub fn mount(&mut self, path: &Path) {
+        self.root = Some(path.to_path_buf());
+    }
+
+    /// returns the mounted host directory
+    pub fn root(&self) -> Option<&Path> {
+        match self.root {
+            Some(ref p) => Some(p.as_path()),
+            None => None,
+        }
+    }
+
+    /// creates or truncates a file, returns the handle
+    pub fn create(&mut self, name: &str) -> Result<u16, DosError> {
+        let path = self.host_path(name)?;
+        if path.is_dir() {
+            return Err(DosError::AccessDenied);
+        }
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&path)?;
+        self.allocate_handle(OpenFile{file, mode: AccessMode::ReadWrite})
+    }
+
+    /// opens a existing file, returns the handle
+    pub fn open(&mut self, name: &str, mode: AccessMode) -> Result<u16, DosError> {
+        let path = self.host_path(name)?;
+        if path.is_dir() {
+            return Err(DosError::AccessDenied);
+        }
+        let file = OpenOptions::new().read(mode.can_read()).write(mode.can_write()).open(&path)?;
+        self.allocate_handle(OpenFile{file, mode})
+    }
+
+    pub fn close(&mut self, handle: u16) -> Result<(), DosError> {
+        self.get_open_file(handle)?;
+        self.handles[handle as usize] = None;
+        Ok(())
+    }
+
+    /// reads up to `len` bytes from the current file position
+    pub fn read(&mut self, handle: u16, len: u32) -> Result<Vec<u8>, DosError> {
+        let f = self.get_open_file(handle)?;
+        if !f.mode.can_read() {
+            return Err(DosError::AccessDenied);
+        }
+        let mut data = Vec::with_capacity(len as usize);
+        (&mut f.file).take(u64::from(len)).read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    /// writes data at the current file position, returns number of bytes written
+    pub fn write(&mut self, handle: u16, data: &[u8]) -> Result<u32, DosError> {
+        let f = self.get_open_file(handle)?;
+        if !f.mode.can_write() {
+            return Err(DosError::AccessDenied);
+        }
+        f.file.write_all(data)?;
+        Ok(data.len() as u32)
+    }
+
+    /// truncates or extends the file to the current file position
+    pub fn truncate(&mut self, handle: u16) -> Result<(), DosError> {
+        let f = self.get_open_file(handle)?;
+        if !f.mode.can_write() {
+            return Err(DosError::AccessDenied);
+        }
+        let pos = f.file.seek(SeekFrom::Current(0))?;
+        f.file.set_len(pos)?;
+        Ok(())
+    }
+
+    /// moves the file pointer, returns the new position from start of file
+    pub fn seek(&mut self, handle: u16, origin: SeekOrigin, offset: i32) -> Result<u32, DosError> {
+        let f = self.get_open_file(handle)?;
+        let pos = match origin {
+            SeekOrigin::Start => i64::from(offset as u32),
+            SeekOrigin::Current => f.file.seek(SeekFrom::Current(0))? as i64 + i64::from(offset),
+            SeekOrigin::End => f.file.metadata()?.len() as i64 + i64::from(offset),
+        };
+        if pos < 0 {
+            // MS-DOS reports a position before start of file like a bad origin
+            return Err(DosError::InvalidFunction);
+        }
+        Ok(f.file.seek(SeekFrom::Start(pos as u64))? as u32)
+    }
+
+    pub fn delete(&mut self, name: &str) -> Result<(), DosError> {
+        let path = self.host_path(name)?;
+        if path.is_dir() {
+            return Err(DosError::AccessDenied);
+        }
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    pub fn rename(&mut self, old_name: &str, new_name: &str) -> Result<(), DosError> {
+        let old_path = self.host_path(old_name)?;
+        let new_path = self.host_path(new_name)?;
+        if !old_path.exists() {
+            return Err(DosError::FileNotFound);
+        }
+        if new_path.exists() {
+            return Err(DosError::AccessDenied);
+        }
+        fs::rename(&old_path, &new_path)?;
+        Ok(())
+    }
+
+    fn allocate_handle(&mut self, f: OpenFile) -> Result<u16, DosError> {
+        for handle in FIRST_FILE_HANDLE as usize..self.handles.len() {
+            if self.handles[handle].is_none() {
+                self.handles[handle] = Some(f);
+                return Ok(handle as u16);
+            }
+        }
+        Err(DosError::TooManyOpenFiles)
+    }
+
+    fn get_open_file(&mut self, handle: u16) -> Result<&mut OpenFile, DosError> {
+        match self.handles.get_mut(handle as usize) {
+            Some(&mut Some(ref mut f)) => Ok(f),
+            _ => Err(DosError::InvalidHandle),
+        }
+    }
+
+    /// translates a DOS path to a path in the mounted host directory.
+    /// directory and file names are matched case-insensitive, new
+    /// file names are upper cased
+    pub fn host_path(&self, name: &str) -> Result<PathBuf, DosError> {
+        let root = match self.root {
+            Some(ref p) => p,
+            None => return Err(DosError::PathNotFound),
+        };
+        let mut name = name.trim();
+        if name.len() >= 2 && name.as_bytes()[1] == b':' {
+            if !name.as_bytes()[0].eq_ignore_ascii_case(&b'C') {
+                return Err(DosError::PathNotFound);
+            }
+            name = &name[2..];
+        }
+
+        let parts: Vec<&str> = name
+            .split(|c| c == '\\' || c == '/')
+            .filter(|p| !p.is_empty() && *p != ".")
+            .collect();
+        if parts.is_empty() {
+            return Err(DosError::PathNotFound);
+        }
+
+        let mut path = root.clone();
+        for (i, part) in parts.iter().enumerate() {
+            if *part == ".." {
+                if path != *root {
+                    path.pop();
+                }
+                continue;
+            }
+            match find_dir_entry(&path, part) {
+                Some(entry) => path.push(entry),
+                None => {
+                    if i != parts.len() - 1 {
+                        return Err(DosError::PathNotFound);
+                    }
+                    path.push(part.to_uppercase());
+                }
+            }
+        }
+        Ok(path)
+    }
+}
+
+/// returns the name of a entry in dir, matching name case-insensitive
+fn find_dir_entry(dir: &Path, name: &str) -> Option<String> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return None,
+    };
+    for entry in entries {
+        if let Ok(entry) = entry {
+            if let Ok(entry_name) = entry.file_name().into_string() {
+                if entry_name.eq_ignore_ascii_case(name) {
+                    return Some(entry_name);
+                }
+            }
+        }
+    }
+    None
+}
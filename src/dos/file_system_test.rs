@@ -0,0 +1,124 @@
+use std::fs::{self, File};
+use std::io::Write;
+
+use tempdir::TempDir;
+
+use machine::Machine;
+use cpu::R;
+use dos::{FileSystem, DosError, AccessMode, SeekOrigin};
+
+fn write_host_file(dir: &TempDir, name: &str, data: &[u8]) {
+    let mut f = File::create(dir.path().join(name)).unwrap();
+    f.write_all(data).unwrap();
+}
+
+#[test]
+fn can_create_write_seek_read() {
+    let tmp_dir = TempDir::new("dos").unwrap();
+    let mut fs = FileSystem::default();
+    fs.mount(tmp_dir.path());
+
+    let handle = fs.create("C:\\OUT.DAT").unwrap();
+    assert_eq!(5, handle);
+    assert_eq!(Ok(5), fs.write(handle, b"hello"));
+    assert_eq!(Ok(1), fs.seek(handle, SeekOrigin::Start, 1));
+    assert_eq!(Ok(b"ell".to_vec()), fs.read(handle, 3));
+    assert_eq!(Ok(3), fs.seek(handle, SeekOrigin::End, -2));
+    assert_eq!(Ok(b"lo".to_vec()), fs.read(handle, 10));
+    assert_eq!(Ok(Vec::new()), fs.read(handle, 10));
+    assert_eq!(Err(DosError::InvalidFunction), fs.seek(handle, SeekOrigin::Current, -6));
+    assert_eq!(Err(DosError::InvalidFunction), fs.seek(handle, SeekOrigin::End, -6));
+    assert_eq!(Ok(5), fs.seek(handle, SeekOrigin::Current, 0));
+    assert_eq!(Ok(()), fs.close(handle));
+    assert_eq!(Err(DosError::InvalidHandle), fs.close(handle));
+
+    assert_eq!(b"hello".to_vec(), fs::read(tmp_dir.path().join("OUT.DAT")).unwrap());
+}
+
+#[test]
+fn can_resolve_paths_case_insensitive() {
+    let tmp_dir = TempDir::new("dos").unwrap();
+    fs::create_dir(tmp_dir.path().join("Data")).unwrap();
+    let mut f = File::create(tmp_dir.path().join("Data").join("level1.map")).unwrap();
+    f.write_all(b"map").unwrap();
+
+    let mut fs = FileSystem::default();
+    fs.mount(tmp_dir.path());
+
+    assert_eq!(tmp_dir.path().join("Data").join("level1.map"), fs.host_path("DATA\\LEVEL1.MAP").unwrap());
+    assert_eq!(tmp_dir.path().join("Data").join("NEW.MAP"), fs.host_path("c:\\data\\..\\data\\new.map").unwrap());
+    assert_eq!(Err(DosError::PathNotFound), fs.host_path("NODIR\\LEVEL1.MAP"));
+    assert_eq!(Err(DosError::PathNotFound), fs.host_path("A:\\LEVEL1.MAP"));
+
+    let handle = fs.open("data\\LEVEL1.MAP", AccessMode::Read).unwrap();
+    assert_eq!(Ok(b"map".to_vec()), fs.read(handle, 10));
+    assert_eq!(Err(DosError::AccessDenied), fs.write(handle, b"x"));
+}
+
+#[test]
+fn can_rename_and_delete() {
+    let tmp_dir = TempDir::new("dos").unwrap();
+    write_host_file(&tmp_dir, "A.TXT", b"a");
+    write_host_file(&tmp_dir, "B.TXT", b"b");
+
+    let mut fs = FileSystem::default();
+    fs.mount(tmp_dir.path());
+
+    assert_eq!(Err(DosError::AccessDenied), fs.rename("A.TXT", "B.TXT"));
+    assert_eq!(Ok(()), fs.rename("A.TXT", "C.TXT"));
+    assert_eq!(Err(DosError::FileNotFound), fs.rename("A.TXT", "D.TXT"));
+    assert_eq!(Ok(()), fs.delete("c.txt"));
+    assert_eq!(Err(DosError::FileNotFound), fs.delete("C.TXT"));
+    assert_eq!(Err(DosError::FileNotFound), fs.open("C.TXT", AccessMode::Read));
+}
+
+#[test]
+fn can_open_and_read_file_with_int21() {
+    let tmp_dir = TempDir::new("dos").unwrap();
+    write_host_file(&tmp_dir, "DATA.BIN", &[0x11, 0x22, 0x33]);
+
+    let mut machine = Machine::default();
+    machine.mount(tmp_dir.path());
+    let code: Vec<u8> = vec![
+        0xBA, 0x20, 0x01, // mov dx,0x120
+        0xB8, 0x00, 0x3D, // mov ax,0x3d00      ; open, read only
+        0xCD, 0x21,       // int 0x21
+        0x89, 0xC3,       // mov bx,ax
+        0xB9, 0x10, 0x00, // mov cx,0x10
+        0xBA, 0x00, 0x02, // mov dx,0x200
+        0xB4, 0x3F,       // mov ah,0x3f        ; read
+        0xCD, 0x21,       // int 0x21
+    ];
+    machine.load_executable(&code);
+    let ds = machine.cpu.get_r16(R::DS);
+    machine.hw.mmu.write(ds, 0x0120, b"data.bin\0");
+
+    machine.execute_instructions(4);
+    assert_eq!(false, machine.cpu.regs.flags.carry);
+    assert_eq!(0x0005, machine.cpu.get_r16(R::AX));
+
+    machine.execute_instructions(6);
+    assert_eq!(false, machine.cpu.regs.flags.carry);
+    assert_eq!(0x0003, machine.cpu.get_r16(R::AX));
+    assert_eq!(vec![0x11, 0x22, 0x33], machine.hw.mmu.read(ds, 0x0200, 3));
+}
+
+#[test]
+fn can_fail_open_missing_file_with_int21() {
+    let tmp_dir = TempDir::new("dos").unwrap();
+
+    let mut machine = Machine::default();
+    machine.mount(tmp_dir.path());
+    let code: Vec<u8> = vec![
+        0xBA, 0x20, 0x01, // mov dx,0x120
+        0xB8, 0x00, 0x3D, // mov ax,0x3d00      ; open, read only
+        0xCD, 0x21,       // int 0x21
+    ];
+    machine.load_executable(&code);
+    let ds = machine.cpu.get_r16(R::DS);
+    machine.hw.mmu.write(ds, 0x0120, b"MISSING.DAT\0");
+
+    machine.execute_instructions(4);
+    assert_eq!(true, machine.cpu.regs.flags.carry);
+    assert_eq!(0x0002, machine.cpu.get_r16(R::AX)); // file not found
+}
@@ -0,0 +1,270 @@
+// DOS memory manager, using a chain of Memory Control Blocks (MCB) in conventional memory.
+// Each block is preceded by a 16 byte MCB, and the last block in the chain is marked with 'Z'
+// http://www.ctyme.com/intr/rb-2938.htm#Table1451
+
+use memory::MMU;
+use dos::DosError;
+
+#[cfg(test)]
+#[path = "./memory_manager_test.rs"]
+mod memory_manager_test;
+
+const MCB_TYPE: u16 = 0x00;     // 'M' = more blocks follows, 'Z' = last block in chain
+const MCB_OWNER: u16 = 0x01;    // PSP segment of owner, 0000h = free
+const MCB_SIZE: u16 = 0x03;     // size of block in paragraphs, excluding the MCB
+const MCB_NAME: u16 = 0x08;     // DOS 4+: ASCII program name, if owner is a PSP
+
+const MCB_MORE: u8 = b'M';
+const MCB_LAST: u8 = b'Z';
+
+/// owner of a free block
+const OWNER_FREE: u16 = 0x0000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AllocationStrategy {
+    FirstFit = 0,
+    BestFit = 1,
+    LastFit = 2,
+}
+
+impl AllocationStrategy {
+    /// decodes the strategy from BX of INT 21h AX=5801h
+    pub fn from_u16(bx: u16) -> Result<Self, DosError> {
+        // bits 6-7 selects upper memory blocks, which we don't have
+        match bx & 0x3F {
+            0 => Ok(AllocationStrategy::FirstFit),
+            1 => Ok(AllocationStrategy::BestFit),
+            2 => Ok(AllocationStrategy::LastFit),
+            _ => Err(DosError::InvalidFunction),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MemoryControlBlock {
+    /// segment of the MCB, the block starts on the following paragraph
+    pub segment: u16,
+    pub owner: u16,
+    /// size in paragraphs
+    pub size: u16,
+    pub last: bool,
+}
+
+impl MemoryControlBlock {
+    fn read(mmu: &MMU, segment: u16) -> Result<Self, DosError> {
+        let last = match mmu.read_u8(segment, MCB_TYPE) {
+            MCB_MORE => false,
+            MCB_LAST => true,
+            _ => return Err(DosError::MemoryControlBlockDestroyed),
+        };
+        Ok(MemoryControlBlock {
+            segment,
+            owner: mmu.read_u16(segment, MCB_OWNER),
+            size: mmu.read_u16(segment, MCB_SIZE),
+            last,
+        })
+    }
+
+    fn write(&self, mmu: &mut MMU) {
+        mmu.write_u8(self.segment, MCB_TYPE, if self.last { MCB_LAST } else { MCB_MORE });
+        mmu.write_u16(self.segment, MCB_OWNER, self.owner);
+        mmu.write_u16(self.segment, MCB_SIZE, self.size);
+        if self.owner == OWNER_FREE {
+            mmu.write(self.segment, MCB_NAME, &[0; 8]);
+        }
+    }
+
+    /// segment of the memory block
+    pub fn block_segment(&self) -> u16 {
+        self.segment + 1
+    }
+
+    /// segment of the following MCB
+    fn next_segment(&self) -> u16 {
+        self.segment.wrapping_add(1).wrapping_add(self.size)
+    }
+
+    pub fn is_free(&self) -> bool {
+        self.owner == OWNER_FREE
+    }
+}
+
+pub struct MemoryManager {
+    /// segment of the first MCB in the chain
+    first_mcb: u16,
+    pub strategy: AllocationStrategy,
+}
+
+impl MemoryManager {
+    pub fn default() -> Self {
+        MemoryManager {
+            first_mcb: 0,
+            strategy: AllocationStrategy::FirstFit,
+        }
+    }
+
+    /// creates a chain with a single free block, from first_mcb up to end_segment
+    pub fn init(&mut self, mmu: &mut MMU, first_mcb: u16, end_segment: u16) {
+        self.first_mcb = first_mcb;
+        self.strategy = AllocationStrategy::FirstFit;
+        MemoryControlBlock {
+            segment: first_mcb,
+            owner: OWNER_FREE,
+            size: end_segment - first_mcb - 1,
+            last: true,
+        }.write(mmu);
+    }
+
+    /// returns all blocks in the chain
+    pub fn blocks(&self, mmu: &MMU) -> Result<Vec<MemoryControlBlock>, DosError> {
+        let mut res = Vec::new();
+        if self.first_mcb == 0 {
+            return Ok(res);
+        }
+        let mut segment = self.first_mcb;
+        loop {
+            let mcb = MemoryControlBlock::read(mmu, segment)?;
+            res.push(mcb);
+            if mcb.last {
+                return Ok(res);
+            }
+            if mcb.next_segment() <= segment {
+                return Err(DosError::MemoryControlBlockDestroyed);
+            }
+            segment = mcb.next_segment();
+        }
+    }
+
+    /// allocates a block of `paragraphs` size, returns the segment of the block
+    pub fn allocate(&mut self, mmu: &mut MMU, paragraphs: u16, owner: u16) -> Result<u16, DosError> {
+        self.merge_free_blocks(mmu)?;
+        let mut candidates = self.blocks(mmu)?.into_iter().filter(|mcb| mcb.is_free() && mcb.size >= paragraphs);
+        let found = match self.strategy {
+            AllocationStrategy::FirstFit => candidates.next(),
+            AllocationStrategy::BestFit => candidates.min_by_key(|mcb| mcb.size),
+            AllocationStrategy::LastFit => candidates.last(),
+        };
+        let mut mcb = match found {
+            Some(mcb) => mcb,
+            None => return Err(DosError::InsufficientMemory),
+        };
+
+        if mcb.size == paragraphs {
+            mcb.owner = owner;
+            mcb.write(mmu);
+            return Ok(mcb.block_segment());
+        }
+
+        if self.strategy == AllocationStrategy::LastFit {
+            // use the top of the free block
+            let remaining = mcb.size - paragraphs - 1;
+            let allocated = MemoryControlBlock {
+                segment: mcb.segment + 1 + remaining,
+                owner,
+                size: paragraphs,
+                last: mcb.last,
+            };
+            mcb.size = remaining;
+            mcb.last = false;
+            mcb.write(mmu);
+            allocated.write(mmu);
+            Ok(allocated.block_segment())
+        } else {
+            self.split(mmu, mcb, paragraphs, owner);
+            Ok(mcb.block_segment())
+        }
+    }
+
+    /// frees the block at segment
+    pub fn free(&mut self, mmu: &mut MMU, segment: u16) -> Result<(), DosError> {
+        let mut mcb = self.find(mmu, segment)?;
+        mcb.owner = OWNER_FREE;
+        mcb.write(mmu);
+        Ok(())
+    }
+
+    /// resizes the block at segment
+    pub fn resize(&mut self, mmu: &mut MMU, segment: u16, paragraphs: u16) -> Result<(), DosError> {
+        self.merge_free_blocks(mmu)?;
+        let mut mcb = self.find(mmu, segment)?;
+        if paragraphs > mcb.size {
+            if paragraphs > self.max_size(mmu, segment)? {
+                return Err(DosError::InsufficientMemory);
+            }
+            // absorb the following free block
+            let next = MemoryControlBlock::read(mmu, mcb.next_segment())?;
+            mcb.size += 1 + next.size;
+            mcb.last = next.last;
+        }
+        let owner = mcb.owner;
+        self.split(mmu, mcb, paragraphs, owner);
+        Ok(())
+    }
+
+    /// returns the max size the block at segment can be resized to
+    pub fn max_size(&mut self, mmu: &mut MMU, segment: u16) -> Result<u16, DosError> {
+        self.merge_free_blocks(mmu)?;
+        let mcb = self.find(mmu, segment)?;
+        if !mcb.last {
+            let next = MemoryControlBlock::read(mmu, mcb.next_segment())?;
+            if next.is_free() {
+                return Ok(mcb.size + 1 + next.size);
+            }
+        }
+        Ok(mcb.size)
+    }
+
+    /// returns the size of the largest free block
+    pub fn largest_free_block(&mut self, mmu: &mut MMU) -> Result<u16, DosError> {
+        self.merge_free_blocks(mmu)?;
+        Ok(self.blocks(mmu)?.iter().filter(|mcb| mcb.is_free()).map(|mcb| mcb.size).max().unwrap_or(0))
+    }
+
+    /// returns the MCB of the block at segment
+    fn find(&self, mmu: &MMU, segment: u16) -> Result<MemoryControlBlock, DosError> {
+        match self.blocks(mmu)?.into_iter().find(|mcb| mcb.block_segment() == segment) {
+            Some(mcb) => Ok(mcb),
+            None => Err(DosError::InvalidMemoryBlock),
+        }
+    }
+
+    /// sets the size of mcb to `paragraphs` and marks any remainder as a new free block
+    fn split(&self, mmu: &mut MMU, mut mcb: MemoryControlBlock, paragraphs: u16, owner: u16) {
+        if mcb.size > paragraphs {
+            MemoryControlBlock {
+                segment: mcb.segment + 1 + paragraphs,
+                owner: OWNER_FREE,
+                size: mcb.size - paragraphs - 1,
+                last: mcb.last,
+            }.write(mmu);
+            mcb.last = false;
+        }
+        mcb.size = paragraphs;
+        mcb.owner = owner;
+        mcb.write(mmu);
+    }
+
+    /// joins adjacent free blocks
+    fn merge_free_blocks(&self, mmu: &mut MMU) -> Result<(), DosError> {
+        let blocks = self.blocks(mmu)?;
+        let mut i = 0;
+        while i < blocks.len() {
+            let mut mcb = blocks[i];
+            i += 1;
+            if !mcb.is_free() {
+                continue;
+            }
+            let mut merged = false;
+            while i < blocks.len() && blocks[i].is_free() {
+                mcb.size += 1 + blocks[i].size;
+                mcb.last = blocks[i].last;
+                merged = true;
+                i += 1;
+            }
+            if merged {
+                mcb.write(mmu);
+            }
+        }
+        Ok(())
+    }
+}
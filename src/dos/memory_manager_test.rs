@@ -0,0 +1,115 @@
+use machine::Machine;
+use memory::MMU;
+use cpu::R;
+use dos::{MemoryManager, MemoryControlBlock, AllocationStrategy, DosError};
+
+fn new_memory_manager(mmu: &mut MMU) -> MemoryManager {
+    let mut mem = MemoryManager::default();
+    mem.init(mmu, 0x1000, 0x2000);
+    mem
+}
+
+#[test]
+fn can_allocate_and_free() {
+    let mut mmu = MMU::default();
+    let mut mem = new_memory_manager(&mut mmu);
+    assert_eq!(Ok(0x0FFF), mem.largest_free_block(&mut mmu));
+
+    assert_eq!(Ok(0x1001), mem.allocate(&mut mmu, 0x0100, 0x0800));
+    assert_eq!(Ok(0x1102), mem.allocate(&mut mmu, 0x0100, 0x0800));
+    assert_eq!(Ok(0x0DFD), mem.largest_free_block(&mut mmu));
+    assert_eq!(Err(DosError::InsufficientMemory), mem.allocate(&mut mmu, 0x0DFE, 0x0800));
+
+    assert_eq!(Ok(()), mem.free(&mut mmu, 0x1001));
+    assert_eq!(Err(DosError::InvalidMemoryBlock), mem.free(&mut mmu, 0x1002));
+    assert_eq!(vec![
+        MemoryControlBlock{segment: 0x1000, owner: 0x0000, size: 0x0100, last: false},
+        MemoryControlBlock{segment: 0x1101, owner: 0x0800, size: 0x0100, last: false},
+        MemoryControlBlock{segment: 0x1202, owner: 0x0000, size: 0x0DFD, last: true},
+    ], mem.blocks(&mmu).unwrap());
+
+    // freeing the middle block joins all free blocks
+    assert_eq!(Ok(()), mem.free(&mut mmu, 0x1102));
+    assert_eq!(Ok(0x0FFF), mem.largest_free_block(&mut mmu));
+    assert_eq!(1, mem.blocks(&mmu).unwrap().len());
+}
+
+#[test]
+fn can_allocate_with_strategies() {
+    let mut mmu = MMU::default();
+    let mut mem = new_memory_manager(&mut mmu);
+    let a = mem.allocate(&mut mmu, 0x0200, 0x0800).unwrap();
+    let _ = mem.allocate(&mut mmu, 0x0010, 0x0800).unwrap();
+    let c = mem.allocate(&mut mmu, 0x0080, 0x0800).unwrap();
+    let _ = mem.allocate(&mut mmu, 0x0010, 0x0800).unwrap();
+    mem.free(&mut mmu, a).unwrap();
+    mem.free(&mut mmu, c).unwrap();
+
+    mem.strategy = AllocationStrategy::BestFit;
+    assert_eq!(Ok(c), mem.allocate(&mut mmu, 0x0040, 0x0800));
+
+    mem.strategy = AllocationStrategy::FirstFit;
+    assert_eq!(Ok(a), mem.allocate(&mut mmu, 0x0040, 0x0800));
+
+    mem.strategy = AllocationStrategy::LastFit;
+    assert_eq!(Ok(0x2000 - 0x0040), mem.allocate(&mut mmu, 0x0040, 0x0800));
+    assert_eq!(true, mem.blocks(&mmu).unwrap().last().unwrap().last);
+}
+
+#[test]
+fn can_resize() {
+    let mut mmu = MMU::default();
+    let mut mem = new_memory_manager(&mut mmu);
+    let a = mem.allocate(&mut mmu, 0x0100, 0x0800).unwrap();
+    let b = mem.allocate(&mut mmu, 0x0100, 0x0800).unwrap();
+    mem.allocate(&mut mmu, 0x0100, 0x0800).unwrap();
+
+    assert_eq!(Err(DosError::InsufficientMemory), mem.resize(&mut mmu, a, 0x0101));
+    assert_eq!(Ok(0x0100), mem.max_size(&mut mmu, a));
+
+    mem.free(&mut mmu, b).unwrap();
+    assert_eq!(Ok(0x0201), mem.max_size(&mut mmu, a));
+    assert_eq!(Ok(()), mem.resize(&mut mmu, a, 0x0180));
+    assert_eq!(Ok(()), mem.resize(&mut mmu, a, 0x0010));
+    assert_eq!(Ok(0x0201), mem.max_size(&mut mmu, a));
+    assert_eq!(Err(DosError::InvalidMemoryBlock), mem.resize(&mut mmu, 0x1234, 0x0010));
+}
+
+#[test]
+fn can_detect_destroyed_chain() {
+    let mut mmu = MMU::default();
+    let mut mem = new_memory_manager(&mut mmu);
+    mem.allocate(&mut mmu, 0x0100, 0x0800).unwrap();
+    mmu.write_u8(0x1101, 0, 0x00);
+    assert_eq!(Err(DosError::MemoryControlBlockDestroyed), mem.allocate(&mut mmu, 0x0100, 0x0800));
+}
+
+#[test]
+fn can_shrink_program_and_allocate_with_int21() {
+    let mut machine = Machine::default();
+    let code: Vec<u8> = vec![
+        0xBB, 0x00, 0x10, // mov bx,0x1000
+        0xB4, 0x4A,       // mov ah,0x4a        ; resize ES block
+        0xCD, 0x21,       // int 0x21
+        0xBB, 0xFF, 0xFF, // mov bx,0xffff
+        0xB4, 0x48,       // mov ah,0x48        ; allocate
+        0xCD, 0x21,       // int 0x21
+        0xB4, 0x48,       // mov ah,0x48        ; allocate largest block
+        0xCD, 0x21,       // int 0x21
+    ];
+    machine.load_executable(&code);
+    let psp = machine.cpu.get_r16(R::ES);
+
+    machine.execute_instructions(4);
+    assert_eq!(false, machine.cpu.regs.flags.carry);
+
+    machine.execute_instructions(4);
+    assert_eq!(true, machine.cpu.regs.flags.carry);
+    assert_eq!(0x0008, machine.cpu.get_r16(R::AX));
+    let largest = 0xA000 - (psp + 0x1000) - 1;
+    assert_eq!(largest, machine.cpu.get_r16(R::BX));
+
+    machine.execute_instructions(3);
+    assert_eq!(false, machine.cpu.regs.flags.carry);
+    assert_eq!(psp + 0x1001, machine.cpu.get_r16(R::AX));
+}
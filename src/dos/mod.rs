@@ -0,0 +1,68 @@
+use std::io;
+
+// these modules are re-exported as a single module
+
+pub use self::file_system::*;
+mod file_system;
+
+pub use self::psp::*;
+mod psp;
+
+pub use self::memory_manager::*;
+mod memory_manager;
+
+pub use self::dpmi::*;
+mod dpmi;
+
+/// DOS error codes, as returned in AX with CF set (see #01680 at INT 21h AH=59h)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DosError {
+    InvalidFunction = 0x01,
+    FileNotFound = 0x02,
+    PathNotFound = 0x03,
+    TooManyOpenFiles = 0x04,
+    AccessDenied = 0x05,
+    InvalidHandle = 0x06,
+    MemoryControlBlockDestroyed = 0x07,
+    InsufficientMemory = 0x08,
+    InvalidMemoryBlock = 0x09,
+    InvalidAccessCode = 0x0C,
+}
+
+impl DosError {
+    pub fn code(self) -> u16 {
+        self as u16
+    }
+}
+
+impl From<io::Error> for DosError {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::NotFound => DosError::FileNotFound,
+            _ => DosError::AccessDenied,
+        }
+    }
+}
+
+/// the DOS kernel state
+pub struct DOS {
+    pub fs: FileSystem,
+    pub memory: MemoryManager,
+
+    /// DPMI host, active while a client runs in protected mode
+    pub dpmi: DPMI,
+
+    /// segment of the PSP of the running program
+    pub psp_segment: u16,
+}
+
+impl DOS {
+    pub fn default() -> Self {
+        DOS {
+            fs: FileSystem::default(),
+            memory: MemoryManager::default(),
+            dpmi: DPMI::default(),
+            psp_segment: 0,
+        }
+    }
+}
@@ -0,0 +1,143 @@
+// Program Segment Prefix, the 256 byte header DOS creates in front of a loaded program
+// http://www.ctyme.com/intr/rb-2682.htm
+
+use std::collections::BTreeMap;
+
+use memory::MMU;
+
+#[cfg(test)]
+#[path = "./psp_test.rs"]
+mod psp_test;
+
+pub const PSP_TERMINATE: u16       = 0x0000; // INT 20h instruction
+pub const PSP_END_SEGMENT: u16     = 0x0002; // segment of first byte beyond memory allocated to program
+pub const PSP_CPM_CALL: u16        = 0x0005; // far call to DOS function dispatcher (CP/M compatibility)
+pub const PSP_INT22_VECTOR: u16    = 0x000A; // terminate address
+pub const PSP_INT23_VECTOR: u16    = 0x000E; // control-break handler address
+pub const PSP_INT24_VECTOR: u16    = 0x0012; // critical error handler address
+pub const PSP_PARENT: u16          = 0x0016; // segment of parent PSP
+pub const PSP_JFT: u16             = 0x0018; // job file table, one byte per handle
+pub const PSP_ENV_SEGMENT: u16     = 0x002C; // segment of environment block
+pub const PSP_JFT_SIZE: u16        = 0x0032; // number of entries in job file table
+pub const PSP_JFT_POINTER: u16     = 0x0034; // far pointer to job file table
+pub const PSP_PREVIOUS: u16        = 0x0038; // far pointer to previous PSP
+pub const PSP_DOS_VERSION: u16     = 0x0040; // DOS version to return on INT 21h AH=30h
+pub const PSP_DISPATCHER: u16      = 0x0050; // INT 21h, RETF
+pub const PSP_FCB1: u16            = 0x005C; // first default FCB, filled from first argument
+pub const PSP_FCB2: u16            = 0x006C; // second default FCB, filled from second argument
+pub const PSP_COMMAND_TAIL: u16    = 0x0080; // length byte, followed by command tail and 0Dh
+
+const JFT_ENTRIES: u16 = 20;
+
+/// max length of the command tail, excluding the terminating 0Dh
+const MAX_COMMAND_TAIL: usize = 126;
+
+/// writes a PSP for a program with the given command tail (the command line without program name)
+pub fn write_psp(mmu: &mut MMU, seg: u16, end_segment: u16, env_segment: u16, command_tail: &str) {
+    mmu.write(seg, 0, &[0; 0x100]);
+
+    mmu.write(seg, PSP_TERMINATE, &[0xCD, 0x20]);               // int 0x20
+    mmu.write_u16(seg, PSP_END_SEGMENT, end_segment);
+    mmu.write(seg, PSP_CPM_CALL, &[0x9A, 0xF0, 0xFE, 0x1D, 0xF0]); // call 0xf01d:0xfef0
+
+    // the parent handlers
+    for &(offset, int) in &[(PSP_INT22_VECTOR, 0x22), (PSP_INT23_VECTOR, 0x23), (PSP_INT24_VECTOR, 0x24)] {
+        let (vec_seg, vec_off) = mmu.read_vec(int);
+        mmu.write_u16(seg, offset, vec_off);
+        mmu.write_u16(seg, offset + 2, vec_seg);
+    }
+    mmu.write_u16(seg, PSP_PARENT, seg);
+
+    // stdin, stdout, stderr are the console, stdaux is AUX, stdprn is PRN
+    let mut jft = [0xFF; JFT_ENTRIES as usize];
+    jft[..5].copy_from_slice(&[0x01, 0x01, 0x01, 0x00, 0x02]);
+    mmu.write(seg, PSP_JFT, &jft);
+
+    mmu.write_u16(seg, PSP_ENV_SEGMENT, env_segment);
+    mmu.write_u16(seg, PSP_JFT_SIZE, JFT_ENTRIES);
+    mmu.write_u16(seg, PSP_JFT_POINTER, PSP_JFT);
+    mmu.write_u16(seg, PSP_JFT_POINTER + 2, seg);
+    mmu.write_u32(seg, PSP_PREVIOUS, 0xFFFF_FFFF);
+    mmu.write(seg, PSP_DOS_VERSION, &[3, 10]);
+    mmu.write(seg, PSP_DISPATCHER, &[0xCD, 0x21, 0xCB]);       // int 0x21, retf
+
+    let mut args = command_tail.split_whitespace();
+    mmu.write(seg, PSP_FCB1, &parse_fcb(args.next().unwrap_or("")));
+    mmu.write(seg, PSP_FCB2, &parse_fcb(args.next().unwrap_or("")));
+
+    let mut tail: Vec<u8> = command_tail.bytes().collect();
+    if !tail.is_empty() && tail[0] != b' ' && tail[0] != b'\t' {
+        // like COMMAND.COM, keep the separator after the program name
+        tail.insert(0, b' ');
+    }
+    tail.truncate(MAX_COMMAND_TAIL);
+    mmu.write_u8(seg, PSP_COMMAND_TAIL, tail.len() as u8);
+    tail.push(0x0D);
+    mmu.write(seg, PSP_COMMAND_TAIL + 1, &tail);
+}
+
+/// writes a environment block of "KEY=VALUE" ASCIZ strings, followed by the program path.
+/// returns false if the block don't fit in size bytes
+pub fn write_environment(mmu: &mut MMU, seg: u16, size: usize, env: &BTreeMap<String, String>, program_path: &str) -> bool {
+    let mut data = Vec::new();
+    for (key, value) in env {
+        data.extend_from_slice(key.as_bytes());
+        data.push(b'=');
+        data.extend_from_slice(value.as_bytes());
+        data.push(0);
+    }
+    if data.is_empty() {
+        data.push(0);
+    }
+    data.push(0);
+
+    // DOS 3+: word count of strings following the environment, and the full program path
+    data.extend_from_slice(&[0x01, 0x00]);
+    data.extend_from_slice(program_path.as_bytes());
+    data.push(0);
+
+    if data.len() > size {
+        return false;
+    }
+    mmu.write(seg, 0, &data);
+    true
+}
+
+/// parses a filename into a unopened File Control Block: drive number
+/// (0 = default, 1 = A:), 8 byte name and 3 byte extension, space padded
+pub fn parse_fcb(arg: &str) -> [u8; 16] {
+    let mut fcb = [0u8; 16];
+    for b in fcb[1..12].iter_mut() {
+        *b = b' ';
+    }
+    let mut s = arg.as_bytes();
+    if s.len() >= 2 && s[1] == b':' && s[0].is_ascii_alphabetic() {
+        fcb[0] = s[0].to_ascii_uppercase() - b'A' + 1;
+        s = &s[2..];
+    }
+
+    let mut pos = 0;
+    let mut field = (1, 8); // name
+    for &c in s {
+        match c {
+            b'.' if field.1 == 8 => {
+                field = (9, 3); // extension
+                pos = 0;
+            }
+            b'.' | b'\\' | b'/' | b':' | b';' | b',' | b'=' | b'+' | b'<' | b'>' | b'|' | b'"' | b'[' | b']' => break,
+            b'*' => {
+                for b in fcb[field.0 + pos..field.0 + field.1].iter_mut() {
+                    *b = b'?';
+                }
+                pos = field.1;
+            }
+            _ => {
+                if pos < field.1 {
+                    fcb[field.0 + pos] = c.to_ascii_uppercase();
+                    pos += 1;
+                }
+            }
+        }
+    }
+    fcb
+}
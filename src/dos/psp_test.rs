@@ -0,0 +1,46 @@
+use std::collections::BTreeMap;
+
+use machine::Machine;
+use cpu::R;
+use dos::{parse_fcb, PSP_END_SEGMENT, PSP_ENV_SEGMENT, PSP_FCB1, PSP_FCB2, PSP_COMMAND_TAIL};
+
+#[test]
+fn can_parse_fcb() {
+    assert_eq!(b"\x00FILE    TXT\0\0\0\0", &parse_fcb("file.txt"));
+    assert_eq!(b"\x03README     \0\0\0\0", &parse_fcb("c:readme"));
+    assert_eq!(b"\x00LONGFILEEXT\0\0\0\0", &parse_fcb("longfilename.extension"));
+    assert_eq!(b"\x00A???????B??\0\0\0\0", &parse_fcb("a*.b*"));
+    assert_eq!(b"\x00           \0\0\0\0", &parse_fcb(""));
+    assert_eq!(b"\x00           \0\0\0\0", &parse_fcb("/x"));
+}
+
+#[test]
+fn can_build_psp() {
+    let mut machine = Machine::default();
+    let code: Vec<u8> = vec![
+        0xB4, 0x62,       // mov ah,0x62
+        0xCD, 0x21,       // int 0x21
+    ];
+    machine.load_executable(&code);
+    let mut env = BTreeMap::new();
+    env.insert("PATH".to_string(), "C:\\".to_string());
+    env.insert("BLASTER".to_string(), "A220 I7 D1".to_string());
+    machine.build_psp("game.exe level1.map /nosound", &env);
+
+    let psp = machine.cpu.get_r16(R::DS);
+    assert_eq!(vec![0xCD, 0x20], machine.hw.mmu.read(psp, 0x0000, 2));
+    assert_eq!(0xA000, machine.hw.mmu.read_u16(psp, PSP_END_SEGMENT));
+    assert_eq!(b"\x00LEVEL1  MAP".to_vec(), machine.hw.mmu.read(psp, PSP_FCB1, 12));
+    assert_eq!(b"\x00           ".to_vec(), machine.hw.mmu.read(psp, PSP_FCB2, 12));
+
+    let tail = b" level1.map /nosound\r";
+    assert_eq!(tail.len() as u8 - 1, machine.hw.mmu.read_u8(psp, PSP_COMMAND_TAIL));
+    assert_eq!(tail.to_vec(), machine.hw.mmu.read(psp, PSP_COMMAND_TAIL + 1, tail.len()));
+
+    let env_seg = machine.hw.mmu.read_u16(psp, PSP_ENV_SEGMENT);
+    let env_block = b"BLASTER=A220 I7 D1\0PATH=C:\\\0\0\x01\0C:\\GAME.EXE\0";
+    assert_eq!(env_block.to_vec(), machine.hw.mmu.read(env_seg, 0, env_block.len()));
+
+    machine.execute_instructions(3);
+    assert_eq!(psp, machine.cpu.get_r16(R::BX));
+}
@@ -0,0 +1,113 @@
+#[derive(Clone, Default)]
+pub struct AttributeController {
+    palette: [u8; 16],
+    mode_control: u8,
+    overscan_color: u8,
+    color_plane_enable: u8,
+    horizontal_pixel_panning: u8,
+    color_select: u8,
+
+    index: u8,
+
+    /// the next write to 03C0 goes to the data register, else to the index register
+    data_next: bool,
+}
+
+impl AttributeController {
+    // 03C0  -W  attribute controller index/data register
+    // writes alternate between the index and the data register, a read of 03DA selects
+    // the index register
+    // bit 5 of the index is the palette address source, the display is blanked while it is
+    // clear. XXX the display is not blanked
+    pub fn write(&mut self, data: u8) {
+        if self.data_next {
+            let index = self.index;
+            self.set_register(index, data);
+        } else {
+            self.index = data & 0x3F;
+        }
+        self.data_next = !self.data_next;
+    }
+
+    // 03C0  R-  attribute controller index register
+    pub fn get_index(&self) -> u8 {
+        self.index
+    }
+
+    /// selects the index register for the next write to 03C0
+    pub fn reset_flip_flop(&mut self) {
+        self.data_next = false;
+    }
+
+    // (Table P0703)
+    // Values for EGA/VGA attribute controller register index:
+    //  00h-0Fh	palette registers
+    //  10h	mode control register
+    //  11h	overscan color register
+    //  12h	color plane enable register
+    //  13h	horizontal pixel panning register
+    //  14h	(VGA) color select register
+    pub fn set_register(&mut self, index: u8, data: u8) {
+        match index & 0x1F {
+            0x00...0x0F => self.palette[usize::from(index & 0x0F)] = data & 0x3F,
+            0x10 => self.mode_control = data,
+            0x11 => self.overscan_color = data,
+            0x12 => self.color_plane_enable = data & 0x0F,
+            0x13 => self.horizontal_pixel_panning = data & 0x0F,
+            0x14 => self.color_select = data & 0x0F,
+            _ => println!("XXX attribute controller: unhandled register {:02X} = {:02X}", index, data),
+        }
+    }
+
+    pub fn register(&self, index: u8) -> u8 {
+        match index & 0x1F {
+            0x00...0x0F => self.palette[usize::from(index & 0x0F)],
+            0x10 => self.mode_control,
+            0x11 => self.overscan_color,
+            0x12 => self.color_plane_enable,
+            0x13 => self.horizontal_pixel_panning,
+            0x14 => self.color_select,
+            _ => 0,
+        }
+    }
+
+    // 03C1  R-  attribute controller data register
+    pub fn read_current(&self) -> u8 {
+        self.register(self.index)
+    }
+
+    /// returns the DAC entry shown for a 4 bit color
+    pub fn color(&self, color: u8) -> u8 {
+        let entry = self.palette[usize::from(color & self.color_plane_enable)];
+        // the color select register provides bit 7-6, and bit 5-4 if selected by the mode control
+        let entry = if self.mode_control & 0x80 != 0 {
+            (entry & 0x0F) | (self.color_select & 0x03) << 4
+        } else {
+            entry
+        };
+        entry | (self.color_select & 0x0C) << 4
+    }
+
+    /// attribute bit 7 selects blinking text, instead of a bright background color
+    pub fn blink(&self) -> bool {
+        self.mode_control & 0x08 != 0
+    }
+
+    pub fn set_blink(&mut self, blink: bool) {
+        self.mode_control = (self.mode_control & !0x08) | if blink { 0x08 } else { 0 };
+    }
+
+    /// the lines below a split screen are not panned
+    pub fn pixel_panning_compatibility(&self) -> bool {
+        self.mode_control & 0x20 != 0
+    }
+
+    /// number of pixels the display is shifted left. XXX the 9 dot text modes are not handled
+    pub fn pixel_panning(&self) -> u8 {
+        self.horizontal_pixel_panning & 0x07
+    }
+
+    pub fn overscan_color(&self) -> u8 {
+        self.overscan_color
+    }
+}
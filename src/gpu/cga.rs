@@ -0,0 +1,83 @@
+/// the mode control register value set by the BIOS for mode 00h-07h
+const MODE_CONTROL: [u8; 8] = [0x2C, 0x28, 0x2D, 0x29, 0x2A, 0x2E, 0x1E, 0x29];
+
+#[derive(Clone, Default)]
+pub struct CGA {
+    mode_control: u8,
+    color_select: u8,
+}
+
+impl CGA {
+    /// returns the mode control and color select register values set by the BIOS for mode
+    pub fn bios_registers(mode: u16) -> Option<(u8, u8)> {
+        match mode {
+            0x00...0x07 => {
+                let color_select = if mode == 0x06 { 0x3F } else { 0x30 };
+                Some((MODE_CONTROL[usize::from(mode)], color_select))
+            }
+            _ => None,
+        }
+    }
+
+    // 03D8  RW  CGA mode control register  (except PCjr) (see #P0817)
+    // Bitfields for CGA mode control register:
+    // Bit(s)	Description	(Table P0817)
+    //  7-6	not used
+    //  5	attribute bit 7: 0 = high intensity background, 1 = blink
+    //  4	640x200 graphics
+    //  3	video enabled
+    //  2	black and white
+    //  1	graphics
+    //  0	80x25 text
+    pub fn set_mode_control(&mut self, data: u8) {
+        self.mode_control = data & 0x3F;
+    }
+
+    pub fn mode_control(&self) -> u8 {
+        self.mode_control
+    }
+
+    // 03D9  -W  CGA palette register (see #P0818)
+    // Bitfields for CGA palette register:
+    // Bit(s)	Description
+    //  7-6	not used
+    //  5	palette: 0 = green, red, brown. 1 = cyan, magenta, white
+    //  4	bright foreground colors in 320x200 graphics
+    //  3-0	border color in text modes, background color in 320x200 graphics,
+    //      foreground color in 640x200 graphics
+    pub fn set_color_select(&mut self, data: u8) {
+        self.color_select = data & 0x3F;
+    }
+
+    pub fn color_select(&self) -> u8 {
+        self.color_select
+    }
+
+    pub fn set_blink(&mut self, blink: bool) {
+        self.mode_control = (self.mode_control & !0x20) | if blink { 0x20 } else { 0 };
+    }
+
+    /// returns the CGA color (IRGB) of a 2 bit pixel in the 320x200 modes
+    pub fn color_4(&self, pixel: u8) -> u8 {
+        let pixel = pixel & 3;
+        if pixel == 0 {
+            return self.color_select & 0x0F;
+        }
+        if self.mode_control & 0x04 != 0 {
+            // black and white disables the color burst, mode 05h shows as dark gray, light gray
+            // and white on a composite monitor
+            return [0x08, 0x07, 0x0F][usize::from(pixel - 1)];
+        }
+        let intensity = (self.color_select & 0x10) >> 1;
+        (pixel << 1) | ((self.color_select >> 5) & 1) | intensity
+    }
+
+    /// returns the CGA color (IRGB) of a 1 bit pixel in the 640x200 mode
+    pub fn color_2(&self, pixel: u8) -> u8 {
+        if pixel & 1 == 0 {
+            0
+        } else {
+            self.color_select & 0x0F
+        }
+    }
+}
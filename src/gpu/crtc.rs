@@ -37,7 +37,7 @@ impl CRTC {
     // bit 5   =0: (VGA) reserved for testage
     // bit 4-0   : selects which register is to be accessed through 03D5
     pub fn set_index(&mut self, data: u8) {
-        self.index = data & 0xF;
+        self.index = data & 0x1F;
     }
 
     // 03D5  -W  CRT (6845) data register   (CGA/MCGA/color EGA/color VGA) (see #P0708)
@@ -74,7 +74,118 @@ impl CRTC {
             0x16 => self.end_vertical_blanking = data,
             0x17 => self.mode_control = data,
             0x18 => self.line_compare = data,
-            _ => panic!(),
+            _ => println!("XXX crtc: unhandled register {:02X} = {:02X}", self.index, data),
         }
     }
+
+    // 03D5  R-  CRT (6845) data register, the register selected by PORT 03D4h
+    pub fn read_current(&self) -> u8 {
+        match self.index {
+            0x00 => self.horizontal_total,
+            0x01 => self.horizontal_display_end,
+            0x02 => self.start_horizontal_blanking,
+            0x03 => self.end_horizontal_blanking,
+            0x04 => self.start_horizontal_retrace,
+            0x05 => self.end_horizontal_retrace,
+            0x06 => self.vertical_total,
+            0x07 => self.overflow,
+            0x08 => self.preset_row_scan,
+            0x09 => self.maximum_scan_line,
+            0x0A => self.cursor_start,
+            0x0B => self.cursor_end,
+            0x0C => self.start_address_high,
+            0x0D => self.start_address_low,
+            0x0E => self.cursor_location_high,
+            0x0F => self.cursor_location_low,
+            0x10 => self.vertical_retrace_start,
+            0x11 => self.vertical_retrace_end,
+            0x12 => self.vertical_display_end,
+            0x13 => self.offset,
+            0x14 => self.underline_location,
+            0x15 => self.start_vertical_blanking,
+            0x16 => self.end_vertical_blanking,
+            0x17 => self.mode_control,
+            0x18 => self.line_compare,
+            _ => 0,
+        }
+    }
+
+    /// writes registers 00h-18h, from a video parameter table
+    pub fn load(&mut self, regs: &[u8]) {
+        for (index, data) in regs.iter().enumerate() {
+            self.set_index(index as u8);
+            self.write_current(*data);
+        }
+    }
+
+    /// offset in video memory of the first character shown, in words
+    pub fn start_address(&self) -> u16 {
+        u16::from(self.start_address_high) << 8 | u16::from(self.start_address_low)
+    }
+
+    /// offset in video memory of the cursor, in characters
+    pub fn cursor_location(&self) -> u16 {
+        u16::from(self.cursor_location_high) << 8 | u16::from(self.cursor_location_low)
+    }
+
+    /// first and last scan line of the text cursor, None if the cursor is off
+    pub fn cursor_lines(&self) -> Option<(u8, u8)> {
+        // bit 5 of the cursor start register disables the cursor
+        if self.cursor_start & 0x20 != 0 {
+            return None;
+        }
+        let start = self.cursor_start & 0x1F;
+        let end = self.cursor_end & 0x1F;
+        if end < start {
+            None
+        } else {
+            Some((start, end))
+        }
+    }
+
+    /// scan lines of a character row
+    pub fn char_height(&self) -> u8 {
+        (self.maximum_scan_line & 0x1F) + 1
+    }
+
+    /// scan line of the character cell where underlined characters are drawn
+    pub fn underline_line(&self) -> u8 {
+        self.underline_location & 0x1F
+    }
+
+    /// distance between the start of 2 character rows, in words
+    pub fn row_offset(&self) -> u16 {
+        u16::from(self.offset) * 2
+    }
+
+    /// characters shown in a row, from the horizontal display end register
+    pub fn display_chars(&self) -> u16 {
+        u16::from(self.horizontal_display_end) + 1
+    }
+
+    /// scan lines shown, from the vertical display end register and its overflow bits
+    pub fn display_lines(&self) -> u16 {
+        let overflow = u16::from(self.overflow);
+        (u16::from(self.vertical_display_end) | (overflow & 0x02) << 7 | (overflow & 0x40) << 3) + 1
+    }
+
+    /// scan lines of a row of pixels or characters, including the scan doubling
+    pub fn scan_lines_per_row(&self) -> u16 {
+        let lines = u16::from(self.maximum_scan_line & 0x1F) + 1;
+        if self.maximum_scan_line & 0x80 != 0 {
+            lines * 2
+        } else {
+            lines
+        }
+    }
+
+    /// scan line after which the display restarts at offset 0, for split screens
+    pub fn line_compare(&self) -> u16 {
+        u16::from(self.line_compare) | u16::from(self.overflow & 0x10) << 4 | u16::from(self.maximum_scan_line & 0x40) << 3
+    }
+
+    /// scan line of the first row where the display starts, for smooth vertical scrolling
+    pub fn preset_row_scan(&self) -> u16 {
+        u16::from(self.preset_row_scan & 0x1F)
+    }
 }
@@ -0,0 +1,119 @@
+#[derive(Clone, Default)]
+pub struct GraphicsController {
+    set_reset: u8,
+    enable_set_reset: u8,
+    color_compare: u8,
+    data_rotate: u8,
+    read_map_select: u8,
+    mode: u8,
+    miscellaneous: u8,
+    color_dont_care: u8,
+    bit_mask: u8,
+
+    index: u8,
+}
+
+impl GraphicsController {
+    // 03CE  RW  graphics controller register index (see #P0682)
+    // bit 7-4 : reserved (VGA)
+    // bit 3-0 : current graphics controller index
+    pub fn set_index(&mut self, data: u8) {
+        self.index = data & 0x0F;
+    }
+
+    pub fn get_index(&self) -> u8 {
+        self.index
+    }
+
+    // 03CF  RW  graphics controller register data
+    // (Table P0682)
+    // Values for EGA/VGA graphics controller register index:
+    //  00h	set/reset register (see #P0683)
+    //  01h	enable set/reset register (see #P0684)
+    //  02h	color compare register (see #P0685)
+    //  03h	data rotate register (see #P0686)
+    //  04h	read map select register (see #P0687)
+    //  05h	mode register (see #P0688)
+    //  06h	miscellaneous register (see #P0689)
+    //  07h	color don't care register (see #P0690)
+    //  08h	bit mask register (see #P0691)
+    pub fn write_current(&mut self, data: u8) {
+        match self.index {
+            0x00 => self.set_reset = data & 0x0F,
+            0x01 => self.enable_set_reset = data & 0x0F,
+            0x02 => self.color_compare = data & 0x0F,
+            0x03 => self.data_rotate = data,
+            0x04 => self.read_map_select = data & 0x03,
+            0x05 => self.mode = data,
+            0x06 => self.miscellaneous = data,
+            0x07 => self.color_dont_care = data & 0x0F,
+            0x08 => self.bit_mask = data,
+            _ => println!("XXX graphics controller: unhandled register {:02X} = {:02X}", self.index, data),
+        }
+    }
+
+    pub fn read_current(&self) -> u8 {
+        match self.index {
+            0x00 => self.set_reset,
+            0x01 => self.enable_set_reset,
+            0x02 => self.color_compare,
+            0x03 => self.data_rotate,
+            0x04 => self.read_map_select,
+            0x05 => self.mode,
+            0x06 => self.miscellaneous,
+            0x07 => self.color_dont_care,
+            0x08 => self.bit_mask,
+            _ => 0,
+        }
+    }
+
+    /// write mode 0-3
+    pub fn write_mode(&self) -> u8 {
+        self.mode & 0x03
+    }
+
+    /// reads compare the planes with the color compare register, instead of returning a plane
+    pub fn read_mode_compare(&self) -> bool {
+        self.mode & 0x08 != 0
+    }
+
+    /// plane returned by reads in read mode 0
+    pub fn read_map_select(&self) -> u8 {
+        self.read_map_select
+    }
+
+    /// number of bits the cpu data is rotated right in write mode 0 and 3
+    pub fn rotate_count(&self) -> u8 {
+        self.data_rotate & 0x07
+    }
+
+    /// operation combining the data with the latches: 0 = replace, 1 = and, 2 = or, 3 = xor
+    pub fn logical_operation(&self) -> u8 {
+        (self.data_rotate >> 3) & 0x03
+    }
+
+    pub fn set_reset(&self) -> u8 {
+        self.set_reset
+    }
+
+    pub fn enable_set_reset(&self) -> u8 {
+        self.enable_set_reset
+    }
+
+    pub fn color_compare(&self) -> u8 {
+        self.color_compare
+    }
+
+    pub fn color_dont_care(&self) -> u8 {
+        self.color_dont_care
+    }
+
+    pub fn bit_mask(&self) -> u8 {
+        self.bit_mask
+    }
+
+    /// cpu address range of the video memory: 0 = A000-BFFF, 1 = A000-AFFF, 2 = B000-B7FF, 3 = B800-BFFF
+    pub fn memory_map(&self) -> u8 {
+        (self.miscellaneous >> 2) & 0x03
+    }
+}
@@ -23,3 +23,18 @@ mod crtc;
 
 pub use self::dac::*;
 mod dac;
+
+pub use self::sequencer::*;
+mod sequencer;
+
+pub use self::graphics_controller::*;
+mod graphics_controller;
+
+pub use self::planar::*;
+mod planar;
+
+pub use self::attribute_controller::*;
+mod attribute_controller;
+
+pub use self::cga::*;
+mod cga;
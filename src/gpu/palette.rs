@@ -65,13 +65,6 @@ pub fn mtext_s3_palette() -> [ColorSpace; 64] {[
     rgb6(0x3f,0x3f,0x3f),rgb6(0x3f,0x3f,0x3f),rgb6(0x3f,0x3f,0x3f),rgb6(0x3f,0x3f,0x3f),
 ]}
 
-pub fn cga_palette() -> [ColorSpace; 16] {[
-    rgb6(0x00,0x00,0x00),rgb6(0x00,0x00,0x2a),rgb6(0x00,0x2a,0x00),rgb6(0x00,0x2a,0x2a),
-    rgb6(0x2a,0x00,0x00),rgb6(0x2a,0x00,0x2a),rgb6(0x2a,0x15,0x00),rgb6(0x2a,0x2a,0x2a),
-    rgb6(0x15,0x15,0x15),rgb6(0x15,0x15,0x3f),rgb6(0x15,0x3f,0x15),rgb6(0x15,0x3f,0x3f),
-    rgb6(0x3f,0x15,0x15),rgb6(0x3f,0x15,0x3f),rgb6(0x3f,0x3f,0x15),rgb6(0x3f,0x3f,0x3f),
-]}
-
 pub fn cga_palette_2() -> [ColorSpace; 64] {[
     rgb6(0x00,0x00,0x00),rgb6(0x00,0x00,0x2a),rgb6(0x00,0x2a,0x00),rgb6(0x00,0x2a,0x2a),
     rgb6(0x2a,0x00,0x00),rgb6(0x2a,0x00,0x2a),rgb6(0x2a,0x15,0x00),rgb6(0x2a,0x2a,0x2a),
@@ -0,0 +1,201 @@
+// EGA/VGA planar video memory
+//
+// The video memory is made of 4 planes of 64K. In the planar modes (0Dh-12h) a cpu access to
+// the A000 window addresses the same offset in all planes at once. Reads load the 4 latches,
+// writes combine the cpu data with the latches as programmed in the sequencer and graphics
+// controller.
+
+use std::cell::Cell;
+
+use gpu::sequencer::Sequencer;
+use gpu::graphics_controller::GraphicsController;
+
+#[cfg(test)]
+#[path = "./planar_test.rs"]
+mod planar_test;
+
+/// size of one plane
+pub const PLANE_SIZE: usize = 0x1_0000;
+
+/// start of the cpu window to the planes
+const WINDOW_START: u32 = 0xA_0000;
+
+#[derive(Clone, Default)]
+pub struct PlanarMemory {
+    pub sequencer: Sequencer,
+    pub graphics: GraphicsController,
+
+    /// plane 0-3, allocated on first use
+    planes: Vec<u8>,
+
+    /// loaded by each read, one byte per plane
+    latches: Cell<[u8; 4]>,
+
+    /// cpu accesses to the A000 window go to the planes
+    enabled: bool,
+}
+
+impl PlanarMemory {
+    /// returns true if addr is handled by the planes
+    pub fn maps(&self, addr: u32) -> bool {
+        // XXX the B000 half of the 128K memory map is not handled
+        self.enabled && addr >= WINDOW_START && addr < WINDOW_START + PLANE_SIZE as u32
+    }
+
+    /// returns true if any byte of addr..addr+length is handled by the planes
+    pub fn overlaps(&self, addr: u32, length: usize) -> bool {
+        self.enabled && length > 0 && addr < WINDOW_START + PLANE_SIZE as u32 &&
+            u64::from(addr) + length as u64 > u64::from(WINDOW_START)
+    }
+
+    // 03C4  RW  sequencer register index
+    pub fn set_sequencer_index(&mut self, data: u8) {
+        self.sequencer.set_index(data);
+    }
+
+    // 03C5  RW  sequencer register data
+    pub fn write_sequencer(&mut self, data: u8) {
+        self.sequencer.write_current(data);
+        self.update_enabled();
+    }
+
+    // 03CE  RW  graphics controller register index
+    pub fn set_graphics_index(&mut self, data: u8) {
+        self.graphics.set_index(data);
+    }
+
+    // 03CF  RW  graphics controller register data
+    pub fn write_graphics(&mut self, data: u8) {
+        self.graphics.write_current(data);
+        self.update_enabled();
+    }
+
+    /// writes a sequencer register
+    pub fn set_sequencer_register(&mut self, index: u8, data: u8) {
+        self.set_sequencer_index(index);
+        self.write_sequencer(data);
+    }
+
+    /// writes a graphics controller register
+    pub fn set_graphics_register(&mut self, index: u8, data: u8) {
+        self.set_graphics_index(index);
+        self.write_graphics(data);
+    }
+
+    /// the planes are used when they are neither chained nor in odd/even mode, and mapped at A000
+    fn update_enabled(&mut self) {
+        self.enabled = !self.sequencer.chain4() && !self.sequencer.odd_even() && self.graphics.memory_map() <= 1;
+        if self.enabled {
+            self.allocate();
+        }
+    }
+
+    fn allocate(&mut self) {
+        if self.planes.is_empty() {
+            self.planes = vec![0; 4 * PLANE_SIZE];
+        }
+    }
+
+    /// returns the byte at offset of plane
+    pub fn plane_u8(&self, plane: u8, offset: u16) -> u8 {
+        if self.planes.is_empty() {
+            return 0;
+        }
+        self.planes[usize::from(plane & 3) * PLANE_SIZE + usize::from(offset)]
+    }
+
+    /// writes data to plane at offset, bypassing the sequencer and graphics controller
+    pub fn write_plane(&mut self, plane: u8, offset: u16, data: &[u8]) {
+        self.allocate();
+        let start = usize::from(plane & 3) * PLANE_SIZE + usize::from(offset);
+        let len = data.len().min(PLANE_SIZE - usize::from(offset));
+        self.planes[start..start + len].copy_from_slice(&data[..len]);
+    }
+
+    /// returns the color index of the pixel at bit 7-0 of offset
+    pub fn pixel(&self, offset: u16, bit: u8) -> u8 {
+        let mut color = 0;
+        for plane in 0..4 {
+            color |= ((self.plane_u8(plane, offset) >> bit) & 1) << plane;
+        }
+        color
+    }
+
+    /// clears all planes
+    pub fn clear(&mut self) {
+        for b in &mut self.planes {
+            *b = 0;
+        }
+    }
+
+    /// cpu read from the window, loads the latches
+    pub fn read_u8(&self, addr: u32) -> u8 {
+        let offset = (addr - WINDOW_START) as u16;
+        let mut latches = [0; 4];
+        for (plane, latch) in latches.iter_mut().enumerate() {
+            *latch = self.plane_u8(plane as u8, offset);
+        }
+        self.latches.set(latches);
+
+        if !self.graphics.read_mode_compare() {
+            return latches[usize::from(self.graphics.read_map_select())];
+        }
+        // read mode 1: a bit is set if the pixel has the compared color, in the planes not ignored
+        let mut differs = 0;
+        for (plane, latch) in latches.iter().enumerate() {
+            if self.graphics.color_dont_care() & (1 << plane) != 0 {
+                differs |= latch ^ expand(self.graphics.color_compare(), plane);
+            }
+        }
+        !differs
+    }
+
+    /// cpu write to the window, combines data with the latches
+    pub fn write_u8(&mut self, addr: u32, data: u8) {
+        let offset = usize::from((addr - WINDOW_START) as u16);
+        let latches = self.latches.get();
+        let gc = &self.graphics;
+        let rotated = data.rotate_right(u32::from(gc.rotate_count()));
+        let mut bit_mask = gc.bit_mask();
+        if gc.write_mode() == 3 {
+            bit_mask &= rotated;
+        }
+        for (plane, latch) in latches.iter().enumerate() {
+            if self.sequencer.map_mask() & (1 << plane) == 0 {
+                continue;
+            }
+            let value = match gc.write_mode() {
+                0 => {
+                    if gc.enable_set_reset() & (1 << plane) != 0 {
+                        expand(gc.set_reset(), plane)
+                    } else {
+                        rotated
+                    }
+                }
+                // write mode 1 copies the latches
+                1 => {
+                    self.planes[plane * PLANE_SIZE + offset] = *latch;
+                    continue;
+                }
+                2 => expand(data, plane),
+                _ => expand(gc.set_reset(), plane),
+            };
+            let value = match gc.logical_operation() {
+                0 => value,
+                1 => value & latch,
+                2 => value | latch,
+                _ => value ^ latch,
+            };
+            self.planes[plane * PLANE_SIZE + offset] = (value & bit_mask) | (latch & !bit_mask);
+        }
+    }
+}
+
+/// returns 0xFF if bit `plane` of color is set, else 0
+fn expand(color: u8, plane: usize) -> u8 {
+    if color & (1 << plane) != 0 {
+        0xFF
+    } else {
+        0
+    }
+}
@@ -0,0 +1,125 @@
+use gpu::PlanarMemory;
+
+/// returns planar memory set up as in mode 12h
+fn planar() -> PlanarMemory {
+    let mut planar = PlanarMemory::default();
+    planar.set_sequencer_register(0x02, 0x0F);
+    planar.set_sequencer_register(0x04, 0x06);
+    planar.set_graphics_register(0x06, 0x05);
+    planar.set_graphics_register(0x07, 0x0F);
+    planar.set_graphics_register(0x08, 0xFF);
+    planar
+}
+
+/// returns the byte at offset of each plane
+fn planes(planar: &PlanarMemory, offset: u16) -> [u8; 4] {
+    [planar.plane_u8(0, offset), planar.plane_u8(1, offset), planar.plane_u8(2, offset), planar.plane_u8(3, offset)]
+}
+
+#[test]
+fn maps_the_window_only_in_planar_modes() {
+    let mut planar = planar();
+    assert_eq!(true, planar.maps(0xA_0000));
+    assert_eq!(true, planar.maps(0xA_FFFF));
+    assert_eq!(false, planar.maps(0xB_8000));
+
+    // chain 4, as in mode 13h
+    planar.set_sequencer_register(0x04, 0x0E);
+    assert_eq!(false, planar.maps(0xA_0000));
+}
+
+#[test]
+fn can_write_planes_with_map_mask() {
+    let mut planar = planar();
+    planar.write_u8(0xA_0010, 0xAA);
+    planar.set_sequencer_register(0x02, 0x06);
+    planar.write_u8(0xA_0010, 0x55);
+    assert_eq!([0xAA, 0x55, 0x55, 0xAA], planes(&planar, 0x10));
+
+    planar.set_graphics_register(0x04, 0x01);
+    assert_eq!(0x55, planar.read_u8(0xA_0010));
+    planar.set_graphics_register(0x04, 0x03);
+    assert_eq!(0xAA, planar.read_u8(0xA_0010));
+}
+
+#[test]
+fn can_write_set_reset_through_bit_mask() {
+    let mut planar = planar();
+    planar.write_u8(0xA_0000, 0x33);
+
+    // write mode 0 with set/reset enabled for plane 0 and 1
+    planar.set_graphics_register(0x00, 0x05);
+    planar.set_graphics_register(0x01, 0x03);
+    planar.set_graphics_register(0x08, 0x0F);
+    planar.read_u8(0xA_0000);
+    planar.write_u8(0xA_0000, 0xC0);
+    assert_eq!([0x3F, 0x30, 0x30, 0x30], planes(&planar, 0));
+}
+
+#[test]
+fn can_rotate_and_combine_with_latches() {
+    let mut planar = planar();
+    planar.write_u8(0xA_0000, 0x0F);
+
+    // rotate right by 4, xor
+    planar.set_graphics_register(0x03, 0x18 | 4);
+    planar.read_u8(0xA_0000);
+    planar.write_u8(0xA_0000, 0x3C);
+    assert_eq!([0xCC, 0xCC, 0xCC, 0xCC], planes(&planar, 0));
+}
+
+#[test]
+fn can_copy_latches_in_write_mode_1() {
+    let mut planar = planar();
+    planar.set_sequencer_register(0x02, 0x05);
+    planar.write_u8(0xA_0000, 0x81);
+
+    planar.set_sequencer_register(0x02, 0x0F);
+    planar.set_graphics_register(0x05, 0x01);
+    planar.read_u8(0xA_0000);
+    planar.write_u8(0xA_0050, 0x00);
+    assert_eq!([0x81, 0x00, 0x81, 0x00], planes(&planar, 0x50));
+}
+
+#[test]
+fn can_write_colors_in_write_mode_2_and_3() {
+    let mut planar = planar();
+
+    // write mode 2: the data is a color, the bit mask selects the pixels
+    planar.set_graphics_register(0x05, 0x02);
+    planar.set_graphics_register(0x08, 0x80);
+    planar.read_u8(0xA_0000);
+    planar.write_u8(0xA_0000, 0x0E);
+    assert_eq!(0x0E, planar.pixel(0, 7));
+    assert_eq!(0x00, planar.pixel(0, 6));
+
+    // write mode 3: the set/reset register is the color, the rotated data masks the pixels
+    planar.set_graphics_register(0x05, 0x03);
+    planar.set_graphics_register(0x00, 0x09);
+    planar.set_graphics_register(0x08, 0x7F);
+    planar.read_u8(0xA_0000);
+    planar.write_u8(0xA_0000, 0x41);
+    assert_eq!(0x0E, planar.pixel(0, 7));
+    assert_eq!(0x09, planar.pixel(0, 6));
+    assert_eq!(0x00, planar.pixel(0, 5));
+    assert_eq!(0x09, planar.pixel(0, 0));
+}
+
+#[test]
+fn can_compare_colors_in_read_mode_1() {
+    let mut planar = planar();
+    planar.set_graphics_register(0x05, 0x02);
+    for (bit, color) in [0x0C, 0x04, 0x0C, 0x0D].iter().enumerate() {
+        planar.set_graphics_register(0x08, 0x80 >> bit);
+        planar.read_u8(0xA_0000);
+        planar.write_u8(0xA_0000, *color);
+    }
+
+    planar.set_graphics_register(0x05, 0x08);
+    planar.set_graphics_register(0x02, 0x0C);
+    assert_eq!(0xA0, planar.read_u8(0xA_0000));
+
+    // plane 3 is ignored
+    planar.set_graphics_register(0x07, 0x07);
+    assert_eq!(0xE0, planar.read_u8(0xA_0000));
+}
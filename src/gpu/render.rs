@@ -15,8 +15,11 @@ use gpu::graphic_card::GraphicCard;
 use bios::BIOS;
 use bios;
 use gpu::crtc::CRTC;
+use gpu::attribute_controller::AttributeController;
+use gpu::cga::CGA;
 use gpu::dac::DAC;
 use gpu::dac;
+use gpu::planar::PlanarMemory;
 
 #[cfg(test)]
 #[path = "./render_test.rs"]
@@ -32,6 +35,19 @@ const CGA_MASKS2: [u8; 8] = [0x7f, 0xbf, 0xdf, 0xef, 0xf7, 0xfb, 0xfd, 0xfe];
 
 const ACTL_MAX_REG: u8 = 0x14;
 
+/// bytes of a character in the character generator, plane 2 of the video memory
+const CHAR_GEN_CHAR_SIZE: usize = 32;
+
+/// bytes of a character set in the character generator memory
+const CHAR_GEN_SET_SIZE: usize = 256 * CHAR_GEN_CHAR_SIZE;
+
+
+/// bytes of a entry in video_parameters::TABLE_VGA
+const VIDEO_PARAMETER_SIZE: usize = 0x40;
+
+/// offset of the CRTC registers in a video parameter table entry
+const VIDEO_PARAMETER_CRTC: usize = 10;
+
 pub static STATIC_FUNCTIONALITY: [u8; 0x10] = [
  /* 0 */ 0xff,  // All modes supported #1
  /* 1 */ 0xff,  // All modes supported #2
@@ -52,6 +68,8 @@ pub static STATIC_FUNCTIONALITY: [u8; 0x10] = [
 pub struct GPU {
     pub scanline: u32,
     pub crtc: CRTC,
+    pub attribute: AttributeController,
+    pub cga: CGA,
     pub dac: DAC,
     font_8_first: MemoryAddress,
     font_8_second: MemoryAddress,
@@ -65,6 +83,9 @@ pub struct GPU {
     pub card: GraphicCard,
     pub mode: VideoModeBlock,
     modes: Vec<VideoModeBlock>,
+
+    /// number of frames drawn, times the blinking of the cursor and text
+    pub frames: usize,
 }
 
 impl GPU {
@@ -75,6 +96,8 @@ impl GPU {
         GPU {
             scanline: 0,
             crtc: CRTC::default(),
+            attribute: AttributeController::default(),
+            cga: CGA::default(),
             dac: DAC::default(),
             font_8_first: MemoryAddress::Unset,
             font_8_second: MemoryAddress::Unset,
@@ -88,58 +111,163 @@ impl GPU {
             card: generation,
             mode,
             modes,
+            frames: 0,
         }
     }
 
     pub fn render_frame(&self, mmu: &MMU) -> Vec<u8> {
         let memory = mmu.dump_mem();
         match self.mode.mode {
-            // 00: 40x25 Black and White text (CGA,EGA,MCGA,VGA)
-            // 01: 40x25 16 color text (CGA,EGA,MCGA,VGA)
-            // 02: 80x25 16 shades of gray text (CGA,EGA,MCGA,VGA)
-            //0x03 => self.render_mode03_frame(memory), // 80x25 16 color text (CGA,EGA,MCGA,VGA)
-            0x04 => self.render_mode04_frame(&memory), // 320x200 4 color graphics (CGA,EGA,MCGA,VGA)
-            // 05: 320x200 4 color graphics (CGA,EGA,MCGA,VGA)
-            //0x06 => self.render_mode06_frame(memory), // 640x200 B/W graphics (CGA,EGA,MCGA,VGA)
-            // 07: 80x25 Monochrome text (MDA,HERC,EGA,VGA)
+            0x00 | // 40x25 Black and White text (CGA,EGA,MCGA,VGA)
+            0x01 | // 40x25 16 color text (CGA,EGA,MCGA,VGA)
+            0x02 | // 80x25 16 shades of gray text (CGA,EGA,MCGA,VGA)
+            0x03 | // 80x25 16 color text (CGA,EGA,MCGA,VGA)
+            0x07 => self.render_text_frame(&memory, &mmu.memory.borrow().planar), // 80x25 Monochrome text (MDA,HERC,EGA,VGA)
+            0x04 | // 320x200 4 color graphics (CGA,EGA,MCGA,VGA)
+            0x05 => self.render_mode04_frame(&memory), // 320x200 4 color graphics (CGA,EGA,MCGA,VGA)
+            0x06 => self.render_mode06_frame(&memory), // 640x200 B/W graphics (CGA,EGA,MCGA,VGA)
             // 08: 160x200 16 color graphics (PCjr)
             // 09: 320x200 16 color graphics (PCjr)
             // 0A: 640x200 4 color graphics (PCjr)
-            // 0D: 320x200 16 color graphics (EGA,VGA)
-            // 0E: 640x200 16 color graphics (EGA,VGA)
+            0x0D | // 320x200 16 color graphics (EGA,VGA)
+            0x0E | // 640x200 16 color graphics (EGA,VGA)
+            0x10 | // 640x350 16 color graphics (EGA or VGA with 128K)
+            0x12 => self.render_planar_frame(&mmu.memory.borrow().planar), // 640x480 16 color graphics (VGA)
             // 0F: 640x350 Monochrome graphics (EGA,VGA)
-            // 10: 640x350 16 color graphics (EGA or VGA with 128K)
-            //     640x350 4 color graphics (64K EGA)
             //0x11 => self.render_mode11_frame(memory), // 640x480 B/W graphics (MCGA,VGA)
-            //0x12 => self.render_mode12_frame(memory), // 640x480 16 color graphics (VGA)
-            0x13 => self.render_mode13_frame(&memory), // 320x200 256 color graphics (MCGA,VGA)
+            0x13 => self.render_mode13_frame(&memory, &mmu.memory.borrow().planar), // 320x200 256 color graphics (MCGA,VGA)
             _ => {
                 println!("XXX fixme render_frame for mode {:02x}", self.mode.mode);
                 Vec::new()
             }
         }
     }
-/*
-    fn render_mode03_frame(&self, memory: &[u8]) -> Vec<u8> {
+
+    /// returns the width and height of the frame in pixels. the size of the graphics modes
+    /// follows the CRTC, which is reprogrammed for the tweaked modes
+    pub fn frame_size(&self) -> (u32, u32) {
+        match self.mode.kind {
+            GFXMode::EGA | GFXMode::VGA => {
+                // a character clock is 4 pixels in the 256 color modes
+                let pixels_per_char = if self.mode.kind == GFXMode::VGA { 4 } else { 8 };
+                let width = u32::from(self.crtc.display_chars()) * pixels_per_char;
+                let height = u32::from(self.crtc.display_lines() / self.crtc.scan_lines_per_row());
+                (width, height)
+            }
+            _ => (self.mode.swidth, self.mode.sheight),
+        }
+    }
+
+    /// returns the offset in the planes of the first pixel of row y of a graphics frame, and
+    /// the pixel panning of the row. the rows after the line compare scan line show the start
+    /// of the video memory
+    fn row_address(&self, y: u32) -> (u16, u8) {
+        let scan_lines = u32::from(self.crtc.scan_lines_per_row());
+        let scan_line = y * scan_lines;
+        let line_compare = u32::from(self.crtc.line_compare());
+        let row_offset = u32::from(self.crtc.row_offset());
+        if scan_line > line_compare {
+            let address = (((scan_line - line_compare - 1) / scan_lines) * row_offset) as u16;
+            if self.attribute.pixel_panning_compatibility() {
+                (address, 0)
+            } else {
+                (address, self.attribute.pixel_panning())
+            }
+        } else {
+            let row = (scan_line + u32::from(self.crtc.preset_row_scan())) / scan_lines;
+            let address = (u32::from(self.crtc.start_address()) + row * row_offset) as u16;
+            (address, self.attribute.pixel_panning())
+        }
+    }
+
+    fn render_text_frame(&self, memory: &[u8], planar: &PlanarMemory) -> Vec<u8> {
         // 03h = T  80x25  8x8   640x200   16       4   B800 CGA,PCjr,Tandy
         //     = T  80x25  8x14  640x350   16/64    8   B800 EGA
         //     = T  80x25  8x16  640x400   16       8   B800 MCGA
         //     = T  80x25  9x16  720x400   16       8   B800 VGA
         //     = T  80x43  8x8   640x350   16       4   B800 EGA,VGA [17]
         //     = T  80x50  8x8   640x400   16       4   B800 VGA [17]
-        // XXX impl
-        Vec::new()
+        // the screen is divided in character rows of the CRTC character height, so fonts
+        // loaded with int 10h, ax = 1111h-1114h change the number of rows
+        let width = self.mode.swidth as usize;
+        let cwidth = self.mode.cwidth;
+        let cheight = usize::from(self.crtc.char_height());
+        let cols = self.mode.twidth;
+        let rows = self.mode.sheight as usize / cheight;
+        let row_offset = match self.crtc.row_offset() {
+            0 => cols,
+            n => usize::from(n),
+        };
+        let start = usize::from(self.crtc.start_address());
+        let cursor = usize::from(self.crtc.cursor_location());
+        let underline = usize::from(self.crtc.underline_line());
+        // the cursor blinks every 16 frames, blinking text every 32 frames
+        let cursor_on = self.frames & 0x08 == 0;
+        let blink_on = self.frames & 0x10 == 0;
+
+        // the character map select register gives the character sets of attribute bit 3 set and clear
+        let (map_a, map_b) = planar.sequencer.character_maps();
+
+        let mut buf = vec![0u8; width * self.mode.sheight as usize * 3];
+        for row in 0..rows {
+            for col in 0..cols {
+                let cell = start + row * row_offset + col;
+                let offset = self.mode.pstart as usize + ((cell * 2) & 0x7FFF);
+                let chr = usize::from(memory[offset]);
+                let attr = memory[offset + 1];
+                let mut fg = attr & 0xF;
+                let mut bg = attr >> 4;
+                if self.attribute.blink() {
+                    if bg & 8 != 0 && !blink_on {
+                        fg = bg & 7;
+                    }
+                    bg &= 7;
+                }
+                let fg = self.dac.pal[usize::from(self.attribute.color(fg))].clone();
+                let bg = self.dac.pal[usize::from(self.attribute.color(bg))].clone();
+                // line graphics characters extend into the 9th column
+                let line_graphics = cwidth == 9 && match chr {
+                    0xC0...0xDF => true,
+                    _ => false,
+                };
+
+                let glyph = char_set_offset(if attr & 0x08 != 0 { map_a } else { map_b }) + chr * CHAR_GEN_CHAR_SIZE;
+                for y in 0..cheight {
+                    let mut bits = u16::from(planar.plane_u8(2, (glyph + y) as u16)) << 1;
+                    if line_graphics {
+                        bits |= (bits >> 1) & 1;
+                    }
+                    if y == underline && attr & 0x77 == 0x01 {
+                        bits = 0x1FF;
+                    }
+                    if cell == cursor && cursor_on {
+                        if let Some((first, last)) = self.crtc.cursor_lines() {
+                            if y >= usize::from(first) && y <= usize::from(last) {
+                                bits = 0x1FF;
+                            }
+                        }
+                    }
+                    let line = (row * cheight + y) * width + col * cwidth;
+                    for x in 0..cwidth {
+                        let color = if bits & (0x100 >> x) != 0 { &fg } else { &bg };
+                        if let RGB(r, g, b) = *color {
+                            let i = (line + x) * 3;
+                            buf[i] = r;
+                            buf[i+1] = g;
+                            buf[i+2] = b;
+                        }
+                    }
+                }
+            }
+        }
+        buf
     }
-*/
-    fn render_mode04_frame(&self, memory: &[u8]) -> Vec<u8> {
-        // XXX palette selection is done by writes to cga registers
-        // mappings to the cga palette
-        let pal1_map: [usize; 4] = [0, 3, 5, 7];
-        // let pal1_map: [u8; 3] = [11, 13, 15];
-        // let pal0_map: [u8; 4] = [0, 2, 4, 6];
-        // let pal0_map: [u8; 4] = [0, 10, 12, 14];
 
+    /// renders the 320x200 4 color modes 04h and 05h, with the colors selected by the CGA mode
+    /// control and color select registers
+    fn render_mode04_frame(&self, memory: &[u8]) -> Vec<u8> {
         // 04h = G  40x25  8x8   320x200    4       .   B800 CGA,PCjr,EGA,MCGA,VGA
+        // 05h = G  40x25  8x8   320x200   4gray    .   B800 CGA,PCjr,EGA
         let mut buf = vec![0u8; (self.mode.swidth * self.mode.sheight * 3) as usize];
         // println!("cga draw {}x{}", self.mode.swidth, self.mode.sheight);
         for y in 0..self.mode.sheight {
@@ -149,7 +277,8 @@ impl GPU {
                 // 80 bytes per line (80 * 4 = 320), 4 pixels per byte
                 let offset = (0xB_8000 + ((y%2) * 0x2000) + (80 * (y >> 1)) + (x >> 2)) as usize;
                 let bits = (memory[offset] >> ((3 - (x & 3)) * 2)) & 3; // 2 bits: cga palette to use
-                let pal = &self.dac.pal[pal1_map[bits as usize]];
+                let color = self.cga.color_4(bits);
+                let pal = &self.dac.pal[usize::from(self.attribute.color(color))];
 
                 let dst = (((y * self.mode.swidth) + x) * 3) as usize;
                 if let RGB(r, g, b) = *pal {
@@ -161,36 +290,83 @@ impl GPU {
         }
         buf
     }
-/*
+    /// renders the 640x200 2 color mode 06h, the foreground color is selected by the CGA color
+    /// select register
     fn render_mode06_frame(&self, memory: &[u8]) -> Vec<u8> {
         // 06h = G  80x25  8x8   640x200    2       .   B800 CGA,PCjr,EGA,MCGA,VGA
         //     = G  80x25   .       .     mono      .   B000 HERCULES.COM on HGC [14]
-        // XXX impl
-        Vec::new()
+        let mut buf = vec![0u8; (self.mode.swidth * self.mode.sheight * 3) as usize];
+        for y in 0..self.mode.sheight {
+            for x in 0..self.mode.swidth {
+                // 80 bytes per line, 8 pixels per byte. odd lines start at 2000h
+                let offset = (0xB_8000 + ((y%2) * 0x2000) + (80 * (y >> 1)) + (x >> 3)) as usize;
+                let bit = (memory[offset] >> (7 - (x & 7))) & 1;
+                let dst = (((y * self.mode.swidth) + x) * 3) as usize;
+                let color = self.cga.color_2(bit);
+                if let RGB(r, g, b) = self.dac.pal[usize::from(self.attribute.color(color))] {
+                    buf[dst] = r;
+                    buf[dst+1] = g;
+                    buf[dst+2] = b;
+                }
+            }
+        }
+        buf
     }
-
+/*
     fn render_mode11_frame(&self, memory: &[u8]) -> Vec<u8> {
         // 11h = G  80x30  8x16  640x480  mono      .   A000 VGA,MCGA,ATI EGA,ATI VIP
         // XXX impl
         Vec::new()
     }
 
-    fn render_mode12_frame(&self, memory: &[u8]) -> Vec<u8> {
+*/
+    /// renders the 16 color planar modes 0Dh, 0Eh, 10h and 12h. each plane holds one bit of
+    /// the color of 8 pixels in a byte
+    fn render_planar_frame(&self, planar: &PlanarMemory) -> Vec<u8> {
+        // 0Dh = G  40x25  8x8   320x200   16       8   A000 EGA,VGA
+        // 0Eh = G  80x25  8x8   640x200   16       4   A000 EGA,VGA
+        // 10h = G  80x25  8x14  640x350   16       2   A000 EGA,VGA
         // 12h = G  80x30  8x16  640x480   16/256K  .   A000 VGA,ATI VIP
-        //     = G  80x30  8x16  640x480   16/64    .   A000 ATI EGA Wonder
-        //     = G    .     .    640x480   16       .     .  UltraVision+256K EGA
-        // XXX impl, planar mode
-        Vec::new()
+        let (width, height) = self.frame_size();
+        let mut buf = vec![0u8; (width * height * 3) as usize];
+        for y in 0..height {
+            let (row, panning) = self.row_address(y);
+            for x in 0..width {
+                let pixel = x + u32::from(panning);
+                let color = planar.pixel(row.wrapping_add((pixel >> 3) as u16), 7 - (pixel & 7) as u8);
+                let pal = &self.dac.pal[usize::from(self.attribute.color(color))];
+                let i = ((y * width + x) * 3) as usize;
+                if let RGB(r, g, b) = *pal {
+                    buf[i] = r;
+                    buf[i+1] = g;
+                    buf[i+2] = b;
+                }
+            }
+        }
+        buf
     }
-*/
-    fn render_mode13_frame(&self, memory: &[u8]) -> Vec<u8> {
-        let mut buf = vec![0u8; (self.mode.swidth * self.mode.sheight * 3) as usize];
-        for y in 0..self.mode.sheight {
-            for x in 0..self.mode.swidth {
-                let offset = 0xA_0000 + ((y * self.mode.swidth) + x) as usize;
-                let byte = memory[offset];
+
+    /// renders mode 13h. with chain 4 disabled ("mode X") pixel x of a row is in plane x & 3,
+    /// else in the memory at A000
+    fn render_mode13_frame(&self, memory: &[u8], planar: &PlanarMemory) -> Vec<u8> {
+        // XXX the palette registers are bypassed, as they hold the identity in mode 13h
+        let unchained = planar.maps(0xA_0000);
+        let (width, height) = self.frame_size();
+        let mut buf = vec![0u8; (width * height * 3) as usize];
+        for y in 0..height {
+            let (row, panning) = self.row_address(y);
+            let row = u32::from(row);
+            for x in 0..width {
+                // the panning register counts half pixels
+                let pixel = x + u32::from(panning >> 1);
+                let byte = if unchained {
+                    planar.plane_u8((pixel & 3) as u8, (row + (pixel >> 2)) as u16)
+                } else {
+                    // each offset in the planes holds 4 chained pixels
+                    memory[0xA_0000 + ((row * 4 + pixel) & 0xFFFF) as usize]
+                };
                 let pal = &self.dac.pal[byte as usize];
-                let i = ((y * self.mode.swidth + x) * 3) as usize;
+                let i = ((y * width + x) * 3) as usize;
                 if let RGB(r, g, b) = *pal {
                     buf[i] = r;
                     buf[i+1] = g;
@@ -220,9 +396,11 @@ impl GPU {
         }
 
         match self.mode.kind {
+            GFXMode::TEXT if self.mode.mono_mode() => self.dac.pal = palette::mtext_palette().to_vec(),
             GFXMode::TEXT => self.dac.pal = palette::text_palette().to_vec(),
-            GFXMode::CGA2 => self.dac.pal = palette::cga_palette_2().to_vec(),
-            GFXMode::CGA4 => self.dac.pal = palette::cga_palette().to_vec(), // XXX is this the right cga pal for this mode?
+            GFXMode::CGA2 | GFXMode::CGA4 => self.dac.pal = palette::cga_palette_2().to_vec(),
+            // the 350 and 480 line modes use the 64 colors of the EGA monitor
+            GFXMode::EGA if self.mode.mode > 0x0F => self.dac.pal = palette::text_palette().to_vec(),
             GFXMode::EGA => self.dac.pal = palette::ega_palette().to_vec(),
             GFXMode::VGA => self.dac.pal = palette::vga_palette().to_vec(),
             _ => panic!("set_mode: unhandled palette for video mode {:?}", self.mode.kind),
@@ -230,13 +408,19 @@ impl GPU {
 
         let clear_mem = true;
         bios.set_video_mode(mmu, &self.mode, clear_mem);
+        self.init_memory_layout(mmu);
+        self.init_attribute_controller();
+        self.init_cga_registers(mmu);
+        if self.card.is_vga() {
+            if let Some(index) = vga_parameter_index(self.mode.mode) {
+                let crtc = index * VIDEO_PARAMETER_SIZE + VIDEO_PARAMETER_CRTC;
+                self.crtc.load(&video_parameters::TABLE_VGA[crtc..crtc + 0x19]);
+            }
+        }
 
-        /*
-        // Set cursor shape
-        if self.current_mode.kind == M_TEXT {
-            INT10_SetCursorShape(0x06, 07);
+        if self.mode.is_text() {
+            self.init_text_mode(mmu);
         }
-        */
         // Set cursor pos for page 0..7
         for ct in 0..8 {
             self.set_cursor_pos(mmu, 0, 0, ct);
@@ -252,6 +436,211 @@ impl GPU {
         }
     }
 
+    /// programs the sequencer and graphics controller for the video memory layout of the mode
+    fn init_memory_layout(&mut self, mmu: &mut MMU) {
+        // map mask, memory mode, graphics mode and miscellaneous registers
+        let (map_mask, memory_mode, graphics_mode, misc) = match self.mode.kind {
+            GFXMode::TEXT if self.mode.mono_mode() => (0x03, 0x02, 0x10, 0x0A),
+            GFXMode::TEXT => (0x03, 0x02, 0x10, 0x0E),
+            GFXMode::CGA2 => (0x0F, 0x06, 0x00, 0x0D),
+            GFXMode::CGA4 => (0x03, 0x02, 0x30, 0x0F),
+            GFXMode::EGA => (0x0F, 0x06, 0x00, 0x05),
+            _ => (0x0F, 0x0E, 0x40, 0x05),
+        };
+        let mut memory = mmu.memory.borrow_mut();
+        let planar = &mut memory.planar;
+        planar.set_sequencer_register(0x02, map_mask);
+        planar.set_sequencer_register(0x03, 0x00);
+        planar.set_sequencer_register(0x04, memory_mode);
+        for (index, data) in [0x00, 0x00, 0x00, 0x00, 0x00, graphics_mode, misc, 0x0F, 0xFF].iter().enumerate() {
+            planar.set_graphics_register(index as u8, *data);
+        }
+        if self.mode.kind == GFXMode::EGA {
+            planar.clear();
+        }
+    }
+
+    /// programs the palette and mode control registers of the attribute controller for the mode
+    fn init_attribute_controller(&mut self) {
+        let mut palette = [0; 16];
+        for (i, entry) in palette.iter_mut().enumerate() {
+            *entry = i as u8;
+        }
+        let mode_control = match self.mode.kind {
+            GFXMode::TEXT if self.mode.mono_mode() => {
+                palette = [0x00, 0x08, 0x08, 0x08, 0x08, 0x08, 0x08, 0x08, 0x10, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18];
+                0x0E
+            }
+            // the CGA modes are rendered with the CGA color of the pixel, bit 4 of the DAC entry
+            // is the intensity
+            GFXMode::CGA2 | GFXMode::CGA4 => {
+                for (i, entry) in palette.iter_mut().enumerate().skip(8) {
+                    *entry = i as u8 + 0x08;
+                }
+                0x01
+            }
+            // the 200 line EGA modes use bit 4 as the intensity
+            GFXMode::EGA if self.mode.mode <= 0x0F => {
+                for (i, entry) in palette.iter_mut().enumerate().skip(8) {
+                    *entry = i as u8 + 0x08;
+                }
+                0x01
+            }
+            GFXMode::VGA => 0x41,
+            _ => {
+                // the 64 colors of the EGA monitor
+                palette[6] = 0x14;
+                for (i, entry) in palette.iter_mut().enumerate().skip(8) {
+                    *entry = i as u8 + 0x30;
+                }
+                if self.mode.is_text() { 0x0C } else { 0x01 }
+            }
+        };
+        for (i, entry) in palette.iter().enumerate() {
+            self.attribute.set_register(i as u8, *entry);
+        }
+        self.attribute.set_register(0x10, mode_control);
+        self.attribute.set_register(0x11, 0x00);
+        self.attribute.set_register(0x12, 0x0F);
+        self.attribute.set_register(0x13, 0x00);
+        self.attribute.set_register(0x14, 0x00);
+        self.attribute.reset_flip_flop();
+    }
+
+    /// sets the CGA mode control and color select registers of the CGA compatible modes
+    fn init_cga_registers(&mut self, mmu: &mut MMU) {
+        if let Some((mode_control, color_select)) = CGA::bios_registers(self.mode.mode) {
+            self.cga.set_mode_control(mode_control);
+            self.cga.set_color_select(color_select);
+            mmu.write_u8(BIOS::DATA_SEG, BIOS::DATA_CURRENT_MSR, mode_control);
+            mmu.write_u8(BIOS::DATA_SEG, BIOS::DATA_CURRENT_PAL, color_select);
+        }
+    }
+
+    /// programs the CRTC for the character cells of the text mode, and loads the ROM font
+    fn init_text_mode(&mut self, mmu: &mut MMU) {
+        let cheight = self.mode.cheight as u8;
+        load_rom_font(mmu, cheight, 0);
+        self.crtc.set_index(0x09);
+        self.crtc.write_current(cheight - 1);
+        self.crtc.set_index(0x13);
+        self.crtc.write_current((self.mode.twidth / 2) as u8);
+        // underlining is only visible in the monochrome mode
+        self.crtc.set_index(0x14);
+        self.crtc.write_current(if self.mode.mono_mode() { cheight - 1 } else { 0x1F });
+        self.set_cursor_shape(mmu, 0x06, 0x07);
+    }
+
+    /// int 10h, ah = 01h
+    /// SET TEXT-MODE CURSOR SHAPE
+    /// first and last are scan lines of a 8 line character, the VGA BIOS scales them to the
+    /// character height
+    pub fn set_cursor_shape(&mut self, mmu: &mut MMU, mut first: u8, mut last: u8) {
+        if DEBUG_INTERRUPTS {
+            println!("int 10h, ah = 01h: set_cursor_shape {:02X}, {:02X}", first, last);
+        }
+        mmu.write_u16(BIOS::DATA_SEG, BIOS::DATA_CURSOR_TYPE, u16::from(first) << 8 | u16::from(last));
+        let options = first & 0x60;
+
+        // cursor emulation, based on the original IBM VGA BIOS as in dosbox
+        let cheight = self.crtc.char_height() - 1;
+        let emulate = mmu.read_u8(BIOS::DATA_SEG, BIOS::DATA_VIDEO_CTL) & 1 == 0 && (first | last) & 0xE0 == 0;
+        if self.card.is_ega_vga() && emulate {
+            if last < first {
+                if last != 0 {
+                    first = last;
+                    last = cheight;
+                }
+            } else if (first | last) >= cheight || last != cheight.wrapping_sub(1) || first != cheight {
+                // this might be a cga style cursor
+                if last > 3 {
+                    if first + 2 < last {
+                        if first > 2 {
+                            first = (cheight + 1) / 2;
+                        }
+                        last = cheight;
+                    } else {
+                        first = (first + cheight).wrapping_sub(last);
+                        last = cheight;
+                        if cheight > 0xC {
+                            first -= 1;
+                            last -= 1;
+                        }
+                    }
+                }
+            }
+        }
+        self.crtc.set_index(0x0A);
+        self.crtc.write_current((first & 0x1F) | options);
+        self.crtc.set_index(0x0B);
+        self.crtc.write_current(last);
+    }
+
+    /// int 10h, ax = 1003h
+    /// TOGGLE INTENSITY/BLINKING BIT
+    pub fn set_blink(&mut self, mmu: &mut MMU, blink: bool) {
+        if DEBUG_INTERRUPTS {
+            println!("int 10h, ax = 1003h: set_blink {}", blink);
+        }
+        self.attribute.set_blink(blink);
+        self.cga.set_blink(blink);
+        let msr = mmu.read_u8(BIOS::DATA_SEG, BIOS::DATA_CURRENT_MSR) & !0x20;
+        mmu.write_u8(BIOS::DATA_SEG, BIOS::DATA_CURRENT_MSR, msr | if blink { 0x20 } else { 0 });
+    }
+
+    /// writes the CGA mode control register. the blink bit also applies to the text modes of
+    /// the EGA/VGA, which makes the 16 background colors of the 160x100 tweaked text mode
+    pub fn set_cga_mode_control(&mut self, data: u8) {
+        self.cga.set_mode_control(data);
+        self.attribute.set_blink(data & 0x20 != 0);
+    }
+
+    /// int 10h, ax = 1100h, 1110h
+    /// LOAD USER-SPECIFIED CHARACTERS
+    /// loads count characters from first, height bytes each, into character set block. with
+    /// recalc the character height of the screen is changed to the font height
+    pub fn load_user_font(&mut self, mmu: &mut MMU, seg: u16, off: u16, count: u16, first: u16, block: u8, height: u8, recalc: bool) {
+        if DEBUG_INTERRUPTS {
+            println!("int 10h, ax = 1100h: load_user_font {} chars from {:02X}, height {} at {:04X}:{:04X}", count, first, height, seg, off);
+        }
+        let data = mmu.read(seg, off, usize::from(count) * usize::from(height));
+        write_char_gen(mmu, block, first, height, &data);
+        if recalc {
+            self.set_char_height(mmu, height);
+        }
+    }
+
+    /// int 10h, ax = 1101h, 1102h, 1104h, 1111h, 1112h, 1114h
+    /// LOAD ROM 8x14, 8x8, 8x16 CHARACTER SET
+    pub fn load_rom_text_font(&mut self, mmu: &mut MMU, height: u8, block: u8, recalc: bool) {
+        if DEBUG_INTERRUPTS {
+            println!("int 10h, ax = 11xxh: load_rom_text_font, height {}", height);
+        }
+        load_rom_font(mmu, height, block);
+        if recalc {
+            self.set_char_height(mmu, height);
+        }
+    }
+
+    /// changes the character height of the text mode, which changes the number of rows
+    fn set_char_height(&mut self, mmu: &mut MMU, height: u8) {
+        if height == 0 || height > 32 {
+            return;
+        }
+        self.crtc.set_index(0x09);
+        self.crtc.write_current(height - 1);
+        let rows = self.mode.sheight as u16 / u16::from(height);
+        let cols = mmu.read_u16(BIOS::DATA_SEG, BIOS::DATA_NB_COLS);
+        mmu.write_u8(BIOS::DATA_SEG, BIOS::DATA_NB_ROWS, (rows - 1) as u8);
+        mmu.write_u16(BIOS::DATA_SEG, BIOS::DATA_CHAR_HEIGHT, u16::from(height));
+        mmu.write_u16(BIOS::DATA_SEG, BIOS::DATA_PAGE_SIZE, ((rows * cols * 2) | 0xFF) + 1);
+        if self.mode.mono_mode() {
+            self.crtc.set_index(0x14);
+            self.crtc.write_current(height - 1);
+        }
+        self.set_cursor_shape(mmu, 0x06, 0x07);
+    }
+
     /// int 10h, ah = 05h
     /// SELECT ACTIVE DISPLAY PAGE
     pub fn set_active_page(&mut self, mmu: &mut MMU, page: u8) {
@@ -490,15 +879,12 @@ impl GPU {
         } else {
             0x80
         };
-        /*
         if self.mode.kind == GFXMode::EGA {
             // enable all planes for EGA modes (Ultima 1 colour bug)
             // might be put into INT10_PutPixel but different vga bios
             // implementations have different opinions about this
-            hw.out_u8(0x3C4, 0x2);
-            hw.out_u8(0x3C5, 0xF);
+            mmu.memory.borrow_mut().planar.set_sequencer_register(0x02, 0x0F);
         }
-        */
         if DEBUG_FONT {
             println!("reading fontdata from {:04X}:{:04X}", fontdata_seg, fontdata_off);
         }
@@ -523,10 +909,35 @@ impl GPU {
         }
     }
 
+    /// int 10h, ah = 0Bh, bh = 00h
+    /// SET BACKGROUND/BORDER COLOR
+    /// bit 4 of color selects the bright foreground colors of the 320x200 modes
+    /// XXX the attribute controller of the EGA/VGA modes is not programmed
+    pub fn set_background_color(&mut self, mmu: &mut MMU, color: u8) {
+        if DEBUG_INTERRUPTS {
+            println!("int 10h, ah = 0Bh: set_background_color {:02X}", color);
+        }
+        let color_select = (self.cga.color_select() & 0xE0) | (color & 0x1F);
+        self.cga.set_color_select(color_select);
+        mmu.write_u8(BIOS::DATA_SEG, BIOS::DATA_CURRENT_PAL, color_select);
+    }
+
+    /// int 10h, ah = 0Bh, bh = 01h
+    /// SET PALETTE
+    /// 0 = green, red, brown. 1 = cyan, magenta, white
+    pub fn set_palette_id(&mut self, mmu: &mut MMU, id: u8) {
+        if DEBUG_INTERRUPTS {
+            println!("int 10h, ah = 0Bh: set_palette_id {:02X}", id);
+        }
+        let color_select = (self.cga.color_select() & !0x20) | ((id & 1) << 5);
+        self.cga.set_color_select(color_select);
+        mmu.write_u8(BIOS::DATA_SEG, BIOS::DATA_CURRENT_PAL, color_select);
+    }
+
     /// int 10h, ah = 0Ch
     /// WRITE GRAPHICS PIXEL
     /// color: if bit 7 is set, value is XOR'ed onto screen except in 256-color modes
-    pub fn write_pixel(&mut self, mmu: &mut MMU, x: u16, y: u16, _page: u8, mut color: u8) {
+    pub fn write_pixel(&mut self, mmu: &mut MMU, x: u16, y: u16, page: u8, mut color: u8) {
         if DEBUG_INTERRUPTS {
             println!("int 10h, ah = 0Ch: write_pixel");
         }
@@ -570,6 +981,34 @@ impl GPU {
                     mmu.write_u16(seg, off, old);
                 }
             }
+            GFXMode::EGA => {
+                // the bit mask selects the pixel, the set/reset register provides the color
+                let xor = color & 0x80 != 0;
+                {
+                    let mut memory = mmu.memory.borrow_mut();
+                    let planar = &mut memory.planar;
+                    planar.set_graphics_register(0x08, 0x80 >> (x & 7));
+                    planar.set_graphics_register(0x00, color);
+                    planar.set_graphics_register(0x01, 0x0F);
+                    if xor {
+                        planar.set_graphics_register(0x03, 0x18);
+                    }
+                }
+                let page_size = u32::from(mmu.read_u16(BIOS::DATA_SEG, BIOS::DATA_PAGE_SIZE));
+                let cols = u32::from(mmu.read_u16(BIOS::DATA_SEG, BIOS::DATA_NB_COLS));
+                let off = page_size * u32::from(page) + ((u32::from(y) * cols * 8 + u32::from(x)) >> 3);
+                // the read loads the latches with the other pixels of the byte
+                mmu.read_u8(0xA000, off as u16);
+                mmu.write_u8(0xA000, off as u16, 0xFF);
+
+                let mut memory = mmu.memory.borrow_mut();
+                let planar = &mut memory.planar;
+                planar.set_graphics_register(0x08, 0xFF);
+                planar.set_graphics_register(0x01, 0x00);
+                if xor {
+                    planar.set_graphics_register(0x03, 0x00);
+                }
+            }
             GFXMode::VGA => mmu.write_u8(0xA000, y * 320 + x, color),
             _ => panic!("put_pixel TODO unimplemented mode {:?}", self.mode.kind),
         }
@@ -646,27 +1085,74 @@ impl GPU {
         }
     }
 
+    /// int 10h, ax = 1000h
+    /// SET SINGLE PALETTE REGISTER (PCjr,Tandy,EGA,MCGA,VGA)
+    /// reg 10h-14h are the other attribute controller registers (undocumented)
+    pub fn set_single_palette_register(&mut self, reg: u8, val: u8) {
+        if DEBUG_INTERRUPTS {
+            println!("int 10h, ax = 1000h: set_single_palette_register {:02X} = {:02X}", reg, val);
+        }
+        if reg <= ACTL_MAX_REG {
+            self.attribute.set_register(reg, val);
+        }
+    }
+
+    /// int 10h, ax = 1001h
+    /// SET BORDER (OVERSCAN) COLOR (PCjr,Tandy,EGA,VGA)
+    pub fn set_overscan_border_color(&mut self, val: u8) {
+        if DEBUG_INTERRUPTS {
+            println!("int 10h, ax = 1001h: set_overscan_border_color {:02X}", val);
+        }
+        self.attribute.set_register(0x11, val);
+    }
+
+    /// int 10h, ax = 1002h
+    /// SET ALL PALETTE REGISTERS (PCjr,Tandy,EGA,VGA)
+    /// reads the 16 palette registers and the border color from seg:off
+    pub fn set_all_palette_registers(&mut self, mmu: &MMU, seg: u16, off: u16) {
+        if DEBUG_INTERRUPTS {
+            println!("int 10h, ax = 1002h: set_all_palette_registers");
+        }
+        let regs = mmu.read(seg, off, 17);
+        for (i, val) in regs.iter().take(16).enumerate() {
+            self.attribute.set_register(i as u8, *val);
+        }
+        // the 17th byte is the overscan color
+        self.attribute.set_register(0x11, regs[16]);
+    }
+
     /// int 10h, ax = 1007h
     /// GET INDIVIDUAL PALETTE REGISTER (VGA,UltraVision v2+)
-    pub fn get_individual_palette_register(&self, _reg: u8) -> u8 {
+    pub fn get_individual_palette_register(&self, reg: u8) -> u8 {
         if DEBUG_INTERRUPTS {
             println!("int 10h, ax = 1007h: get_individual_palette_register");
         }
-        panic!("todo");
-        /*
-        const VGAREG_ACTL_ADDRESS: u16    = 0x3C0;
-        const VGAREG_ACTL_WRITE_DATA: u16 = 0x3C0;
-        const VGAREG_ACTL_READ_DATA: u16  = 0x3C1;
-
         if reg <= ACTL_MAX_REG {
-            self.reset_actl();
-            IO_Write(VGAREG_ACTL_ADDRESS, reg + 32);
-            let ret = IO_Read(VGAREG_ACTL_READ_DATA);
-            IO_Write(VGAREG_ACTL_WRITE_DATA, ret);
-            ret
+            self.attribute.register(reg)
+        } else {
+            0
         }
-        0
-        */
+    }
+
+    /// int 10h, ax = 1008h
+    /// READ OVERSCAN (BORDER COLOR) REGISTER (VGA)
+    pub fn read_overscan_border_color(&self) -> u8 {
+        if DEBUG_INTERRUPTS {
+            println!("int 10h, ax = 1008h: read_overscan_border_color");
+        }
+        self.attribute.overscan_color()
+    }
+
+    /// int 10h, ax = 1009h
+    /// READ ALL PALETTE REGISTERS AND OVERSCAN REGISTER (VGA)
+    /// writes the 16 palette registers and the border color to seg:off
+    pub fn read_all_palette_registers(&self, mmu: &mut MMU, seg: u16, off: u16) {
+        if DEBUG_INTERRUPTS {
+            println!("int 10h, ax = 1009h: read_all_palette_registers");
+        }
+        let mut regs: Vec<u8> = (0..16).map(|i| self.attribute.register(i)).collect();
+        regs.push(self.attribute.overscan_color());
+        mmu.write(seg, off, &regs);
     }
 
     /// int 10h, ax = 1010h
@@ -753,22 +1239,12 @@ impl GPU {
         (r, g, b)
     }
 
-    fn reset_actl(&self) {
-        // 03BA  R-  CRT status register (see #P0656)
-        // 03DA  R-  CGA status register (see #P0818)
-        /*
-        match self.mode.crtc_address() + 6 {
-            0x3BA => panic!("xxx"),
-            0x3DA => self.read_cga_status_register(),
-        }
-        */
-    }
-
     /// HACK to have a source of info to toggle CGA status register
     pub fn progress_scanline(&mut self) {
         self.scanline += 1;
         if self.scanline > self.mode.swidth {
             self.scanline = 0;
+            self.frames += 1;
         }
     }
 
@@ -968,5 +1444,54 @@ impl GPU {
         if self.card.is_tandy() {
             mmu.write_vec(0x44, &self.font_8_first);
         }
+
+        self.init_attribute_controller();
+        if self.mode.is_text() {
+            self.init_text_mode(&mut mmu);
+        }
+    }
+}
+
+/// loads the ROM font with characters of height lines into character set block
+fn load_rom_font(mmu: &mut MMU, height: u8, block: u8) {
+    let font: &[u8] = match height {
+        8 => &font::FONT_08,
+        14 => &font::FONT_14,
+        _ => &font::FONT_16,
+    };
+    let height = (font.len() / 256) as u8;
+    write_char_gen(mmu, block, 0, height, font);
+}
+
+/// writes the glyphs in data, of height bytes each, to character set block in plane 2
+fn write_char_gen(mmu: &mut MMU, block: u8, first: u16, height: u8, data: &[u8]) {
+    let height = usize::from(height);
+    if height == 0 {
+        return;
+    }
+    let base = char_set_offset(block & 7);
+    let planar = &mut mmu.memory.borrow_mut().planar;
+    for (i, glyph) in data.chunks(height).enumerate() {
+        let chr = (usize::from(first) + i) & 0xFF;
+        let len = glyph.len().min(CHAR_GEN_CHAR_SIZE);
+        planar.write_plane(2, (base + chr * CHAR_GEN_CHAR_SIZE) as u16, &glyph[..len]);
+    }
+}
+
+/// returns the offset of character set n in plane 2. the sets are interleaved as 0, 4, 1, 5, 2, 6, 3, 7
+fn char_set_offset(n: u8) -> usize {
+    usize::from((n & 3) << 1 | n >> 2) * CHAR_GEN_SET_SIZE
+}
+
+/// returns the entry of a graphics mode in video_parameters::TABLE_VGA
+fn vga_parameter_index(mode: u16) -> Option<usize> {
+    match mode {
+        0x04...0x06 | 0x0D | 0x0E => Some(mode as usize),
+        0x0F => Some(0x11),
+        0x10 => Some(0x12),
+        0x11 => Some(0x1A),
+        0x12 => Some(0x1B),
+        0x13 => Some(0x1C),
+        _ => None,
     }
 }
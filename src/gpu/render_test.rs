@@ -12,10 +12,11 @@ use image;
 use image::{ImageBuffer, Rgb, Pixel, GenericImage};
 
 use tools;
-use cpu::{CPU, R};
+use cpu::{CPU, Engine, R};
 use machine::Machine;
 use memory::MMU;
-use gpu::VideoModeBlock;
+use gpu::GPU;
+use bios::BIOS;
 
 #[test]
 fn can_get_palette_entry() {
@@ -60,6 +61,72 @@ fn can_set_palette_entry() {
     assert_eq!(0x3F, machine.cpu.get_r8(R::CL)); // blue
 }
 
+#[test]
+fn can_set_palette_registers() {
+    let mut machine = Machine::default();
+    let code: Vec<u8> = vec![
+        0xBB, 0x01, 0x3F,   // mov bx,0x3f01    ; register 1 = 3Fh
+        0xB8, 0x00, 0x10,   // mov ax,0x1000
+        0xCD, 0x10,         // int 0x10
+
+        0xB7, 0x15,         // mov bh,0x15
+        0xB8, 0x01, 0x10,   // mov ax,0x1001    ; border color = 15h
+        0xCD, 0x10,         // int 0x10
+
+        0xBA, 0x00, 0x02,   // mov dx,0x200
+        0xB8, 0x09, 0x10,   // mov ax,0x1009    ; read all palette registers to es:dx
+        0xCD, 0x10,         // int 0x10
+
+        0xB3, 0x01,         // mov bl,0x1
+        0xB8, 0x07, 0x10,   // mov ax,0x1007
+        0xCD, 0x10,         // int 0x10
+    ];
+    machine.load_executable(&code);
+
+    for n in &[3, 3, 3, 3] {
+        machine.execute_instructions(*n);
+        machine.execute_instruction(); // trigger the interrupt
+    }
+    assert_eq!(0x3F, machine.cpu.get_r8(R::BH));
+    let es = machine.cpu.get_r16(R::ES);
+    assert_eq!(vec![0x00, 0x3F, 0x02, 0x03], machine.hw.mmu.read(es, 0x200, 4));
+    assert_eq!(0x15, machine.hw.mmu.read_u8(es, 0x210));
+}
+
+#[test]
+fn can_remap_colors_with_the_attribute_controller() {
+    let mut machine = Machine::default();
+    let code: Vec<u8> = vec![
+        0xB8, 0x12, 0x00,   // mov ax,0x12
+        0xCD, 0x10,         // int 0x10
+        0xB8, 0x01, 0x0C,   // mov ax,0xc01     ; write pixel, color 1
+        0xB7, 0x00,         // mov bh,0x0
+        0xB9, 0x01, 0x00,   // mov cx,0x1       x
+        0xBA, 0x00, 0x00,   // mov dx,0x0       y
+        0xCD, 0x10,         // int 0x10
+        0xBA, 0xDA, 0x03,   // mov dx,0x3da
+        0xEC,               // in al,dx         ; reset the attribute controller flip-flop
+        0xBA, 0xC0, 0x03,   // mov dx,0x3c0
+        0xB0, 0x21,         // mov al,0x21      ; palette register 1
+        0xEE,               // out dx,al
+        0xB0, 0x3F,         // mov al,0x3f      ; white
+        0xEE,               // out dx,al
+    ];
+    machine.load_executable(&code);
+
+    machine.execute_instructions(2);
+    machine.execute_instruction(); // trigger the interrupt
+    machine.execute_instructions(5);
+    machine.execute_instruction(); // trigger the interrupt
+    machine.execute_instructions(7);
+    assert_eq!(0x011F, machine.cpu.regs.ip);
+
+    let frame = machine.hw.gpu.render_frame(&machine.hw.mmu);
+    let mut img = draw_image(&frame, &machine.hw.gpu);
+    let img = img.sub_image(0, 0, 4, 1).to_image();
+    assert_eq!(".0..\n", draw_ascii(&img));
+}
+
 #[test]
 fn can_get_font_info() {
     let mut machine = Machine::default();
@@ -98,7 +165,7 @@ fn can_int10_put_pixel() {
     assert_eq!(0x0113, machine.cpu.regs.ip);
 
     let frame = machine.hw.gpu.render_frame(&machine.hw.mmu);
-    let mut img = draw_image(&frame, &machine.hw.gpu.mode);
+    let mut img = draw_image(&frame, &machine.hw.gpu);
     let img = img.sub_image(0, 0, 6, 6).to_image();
     assert_eq!("\
 ......
@@ -110,6 +177,194 @@ fn can_int10_put_pixel() {
 ", draw_ascii(&img));
 }
 
+#[test]
+fn can_render_cga_palettes() {
+    let mut machine = Machine::default();
+    let code: Vec<u8> = vec![
+        0xB8, 0x04, 0x00,   // mov ax,0x4
+        0xCD, 0x10,         // int 0x10
+        0xBA, 0xD9, 0x03,   // mov dx,0x3d9
+        0xB0, 0x01,         // mov al,0x1       ; palette 0, blue background
+        0xEE,               // out dx,al
+    ];
+    machine.load_executable(&code);
+
+    machine.execute_instructions(2);
+    machine.execute_instruction(); // trigger the interrupt
+    machine.execute_instructions(3);
+    assert_eq!(0x010B, machine.cpu.regs.ip);
+    machine.hw.mmu.write_u8(0xB800, 0x0000, 0x1B); // pixel 0, 1, 2, 3
+
+    // blue, green, red, brown
+    let frame = machine.hw.gpu.render_frame(&machine.hw.mmu);
+    assert_eq!(vec![0x00, 0x00, 0xA8, 0x00, 0xA8, 0x00, 0xA8, 0x00, 0x00, 0xA8, 0x54, 0x00], frame[0..12].to_vec());
+
+    // bright cyan, magenta, white as set by the BIOS
+    machine.hw.out_u8(0x03D9, 0x30);
+    let frame = machine.hw.gpu.render_frame(&machine.hw.mmu);
+    assert_eq!(vec![0x00, 0x00, 0x00, 0x54, 0xFC, 0xFC, 0xFC, 0x54, 0xFC, 0xFC, 0xFC, 0xFC], frame[0..12].to_vec());
+
+    // black and white as in mode 5: dark gray, light gray, white
+    machine.hw.out_u8(0x03D8, 0x2E);
+    let frame = machine.hw.gpu.render_frame(&machine.hw.mmu);
+    assert_eq!(vec![0x00, 0x00, 0x00, 0x54, 0x54, 0x54, 0xA8, 0xA8, 0xA8, 0xFC, 0xFC, 0xFC], frame[0..12].to_vec());
+
+    // the CGA colors are mapped by the attribute controller and the DAC
+    machine.hw.out_u8(0x03D8, 0x2A);
+    machine.hw.in_u8(0x03DA);           // reset the attribute controller flip-flop
+    machine.hw.out_u8(0x03C0, 0x2B);    // palette register 0Bh, bright cyan
+    machine.hw.out_u8(0x03C0, 0x01);    // blue
+    machine.hw.out_u8(0x03C8, 0x17);    // DAC entry of bright white
+    for component in &[0x3F, 0x00, 0x00] {
+        machine.hw.out_u8(0x03C9, *component);
+    }
+    let frame = machine.hw.gpu.render_frame(&machine.hw.mmu);
+    assert_eq!(vec![0x00, 0x00, 0x00, 0x00, 0x00, 0xA8, 0xFC, 0x54, 0xFC, 0xFC, 0x00, 0x00], frame[0..12].to_vec());
+}
+
+#[test]
+fn can_render_cga_mode06() {
+    let mut machine = Machine::default();
+    let code: Vec<u8> = vec![
+        0xB8, 0x06, 0x00,   // mov ax,0x6
+        0xCD, 0x10,         // int 0x10
+        0xB8, 0x00, 0x0B,   // mov ax,0xb00     ; int 10h, ah = 0Bh
+        0xBB, 0x0E, 0x00,   // mov bx,0xe       ; bh = 00h: foreground color yellow
+        0xCD, 0x10,         // int 0x10
+    ];
+    machine.load_executable(&code);
+
+    machine.execute_instructions(2);
+    machine.execute_instruction(); // trigger the interrupt
+    machine.execute_instructions(3);
+    machine.execute_instruction(); // trigger the interrupt
+    assert_eq!(0x010D, machine.cpu.regs.ip);
+    assert_eq!(0x2E, machine.hw.mmu.read_u8(BIOS::DATA_SEG, BIOS::DATA_CURRENT_PAL));
+    machine.hw.mmu.write_u8(0xB800, 0x0000, 0xA0);
+    machine.hw.mmu.write_u8(0xB800, 0x2000, 0x40);
+
+    let frame = machine.hw.gpu.render_frame(&machine.hw.mmu);
+    assert_eq!(640 * 200 * 3, frame.len());
+    let mut img = draw_image(&frame, &machine.hw.gpu);
+    let img = img.sub_image(0, 0, 4, 2).to_image();
+    assert_eq!("\
+O.O.
+.O..
+", draw_ascii(&img));
+}
+
+#[test]
+fn can_render_planar_mode() {
+    let mut machine = Machine::default();
+    let code: Vec<u8> = vec![
+        0xB8, 0x12, 0x00,                   // mov ax,0x12
+        0xCD, 0x10,                         // int 0x10
+        0xB4, 0x0C,                         // mov ah,0xc       ; int 10h, ah = 0Ch
+        0xB7, 0x00,                         // mov bh,0x0
+        0xB0, 0x0F,                         // mov al,0xf       color
+        0xB9, 0x01, 0x00,                   // mov cx,0x1       x
+        0xBA, 0x04, 0x00,                   // mov dx,0x4       y
+        0xCD, 0x10,                         // int 0x10
+        0xB8, 0x00, 0xA0,                   // mov ax,0xa000
+        0x8E, 0xC0,                         // mov es,ax
+        0xBA, 0xC4, 0x03,                   // mov dx,0x3c4
+        0xB8, 0x02, 0x01,                   // mov ax,0x102     ; map mask = plane 0
+        0xEF,                               // out dx,ax
+        0x26, 0xC6, 0x06, 0x90, 0x01, 0xF0, // mov byte [es:0x190],0xf0
+    ];
+    machine.load_executable(&code);
+
+    machine.execute_instructions(2);
+    machine.execute_instruction(); // trigger the interrupt
+    machine.execute_instructions(6);
+    machine.execute_instruction(); // trigger the interrupt
+    machine.execute_instructions(6);
+    assert_eq!(0x0125, machine.cpu.regs.ip);
+
+    let frame = machine.hw.gpu.render_frame(&machine.hw.mmu);
+    let mut img = draw_image(&frame, &machine.hw.gpu);
+    let img = img.sub_image(0, 3, 6, 3).to_image();
+    assert_eq!("\
+......
+.0....
+,,,,..
+", draw_ascii(&img));
+}
+
+#[test]
+fn can_render_unchained_mode_with_page_flipping_and_split_screen() {
+    let mut machine = Machine::default();
+    let code: Vec<u8> = vec![
+        0xB8, 0x13, 0x00,                   // mov ax,0x13
+        0xCD, 0x10,                         // int 0x10
+        0xBA, 0xC4, 0x03,                   // mov dx,0x3c4
+        0xB8, 0x04, 0x06,                   // mov ax,0x604     ; memory mode: chain 4 off
+        0xEF,                               // out dx,ax
+        0xB8, 0x02, 0x02,                   // mov ax,0x202     ; map mask = plane 1
+        0xEF,                               // out dx,ax
+        0xB8, 0x00, 0xA0,                   // mov ax,0xa000
+        0x8E, 0xC0,                         // mov es,ax
+        0x26, 0xC6, 0x06, 0x50, 0x00, 0x0F, // mov byte [es:0x50],0xf
+        0xBA, 0xD4, 0x03,                   // mov dx,0x3d4
+        0xB8, 0x0D, 0x50,                   // mov ax,0x500d    ; start address = second row
+        0xEF,                               // out dx,ax
+        0xB8, 0x07, 0x0F,                   // mov ax,0xf07     ; overflow: clear line compare bit 8
+        0xEF,                               // out dx,ax
+        0xB8, 0x09, 0x01,                   // mov ax,0x109     ; max scan line: clear line compare bit 9
+        0xEF,                               // out dx,ax
+        0xB8, 0x18, 0x03,                   // mov ax,0x318     ; line compare = scan line 3
+        0xEF,                               // out dx,ax
+    ];
+    machine.load_executable(&code);
+
+    machine.execute_instructions(2);
+    machine.execute_instruction(); // trigger the interrupt
+    machine.execute_instructions(17);
+    assert_eq!(0x012E, machine.cpu.regs.ip);
+    assert_eq!((320, 200), machine.hw.gpu.frame_size());
+
+    let frame = machine.hw.gpu.render_frame(&machine.hw.mmu);
+    let mut img = draw_image(&frame, &machine.hw.gpu);
+    let img = img.sub_image(0, 0, 4, 5).to_image();
+    assert_eq!("\
+.0..
+....
+....
+.0..
+....
+", draw_ascii(&img));
+}
+
+#[test]
+fn can_pan_mode13_horizontally() {
+    let mut machine = Machine::default();
+    let code: Vec<u8> = vec![
+        0xB8, 0x13, 0x00,                   // mov ax,0x13
+        0xCD, 0x10,                         // int 0x10
+        0xB8, 0x00, 0xA0,                   // mov ax,0xa000
+        0x8E, 0xC0,                         // mov es,ax
+        0x26, 0xC6, 0x06, 0x02, 0x00, 0x0F, // mov byte [es:0x2],0xf
+        0xBA, 0xDA, 0x03,                   // mov dx,0x3da
+        0xEC,                               // in al,dx         ; reset the attribute controller flip-flop
+        0xBA, 0xC0, 0x03,                   // mov dx,0x3c0
+        0xB0, 0x33,                         // mov al,0x33      ; horizontal pixel panning
+        0xEE,                               // out dx,al
+        0xB0, 0x02,                         // mov al,0x2       ; one pixel
+        0xEE,                               // out dx,al
+    ];
+    machine.load_executable(&code);
+
+    machine.execute_instructions(2);
+    machine.execute_instruction(); // trigger the interrupt
+    machine.execute_instructions(10);
+    assert_eq!(0x011D, machine.cpu.regs.ip);
+
+    let frame = machine.hw.gpu.render_frame(&machine.hw.mmu);
+    let mut img = draw_image(&frame, &machine.hw.gpu);
+    let img = img.sub_image(0, 0, 4, 1).to_image();
+    assert_eq!(".0..\n", draw_ascii(&img));
+}
+
 #[test]
 fn can_write_vga_text() {
 let mut machine = Machine::default();
@@ -132,7 +387,7 @@ let mut machine = Machine::default();
     assert_eq!(0x0112, machine.cpu.regs.ip);
 
     let frame = machine.hw.gpu.render_frame(&machine.hw.mmu);
-    let mut img = draw_image(&frame, &machine.hw.gpu.mode);
+    let mut img = draw_image(&frame, &machine.hw.gpu);
     let img = img.sub_image(0, 0, 8, 8).to_image();
     assert_eq!("\
 .,,,,...
@@ -146,6 +401,204 @@ let mut machine = Machine::default();
 ", draw_ascii(&img));
 }
 
+#[test]
+fn can_render_text_mode() {
+    let mut machine = Machine::default();
+    let code: Vec<u8> = vec![
+        0xB8, 0x03, 0x00,   // mov ax,0x3
+        0xCD, 0x10,         // int 0x10
+        0xB4, 0x02,         // mov ah,0x2       ; int 10h, ah = 02h
+        0xB7, 0x00,         // mov bh,0x0       ; page
+        0xBA, 0x01, 0x00,   // mov dx,0x1       ; row 0, column 1
+        0xCD, 0x10,         // int 0x10
+    ];
+    machine.load_executable(&code);
+    machine.execute_instructions(2);
+    machine.execute_instruction(); // trigger the interrupt
+    machine.execute_instructions(4);
+    machine.execute_instruction(); // trigger the interrupt
+    machine.hw.mmu.write(0xB800, 0x0000, &[
+        b'A', 0x1F,         // white on blue
+        b' ', 0x07,         // cursor
+        0xC4, 0x8F,         // line graphics, blinking
+    ]);
+
+    let frame = machine.hw.gpu.render_frame(&machine.hw.mmu);
+    assert_eq!(720 * 400 * 3, frame.len());
+    let mut img = draw_image(&frame, &machine.hw.gpu);
+    let img = img.sub_image(0, 0, 27, 16).to_image();
+    assert_eq!("\
+,,,,,,,,,..................
+,,,,,,,,,..................
+,,,0,,,,,..................
+,,000,,,,..................
+,00,00,,,..................
+00,,,00,,..................
+00,,,00,,..................
+0000000,,.........000000000
+00,,,00,,..................
+00,,,00,,..................
+00,,,00,,..................
+00,,,00,,..................
+,,,,,,,,,..................
+,,,,,,,,,666666666.........
+,,,,,,,,,666666666.........
+,,,,,,,,,..................
+", draw_ascii(&img));
+
+    // the cursor and blinking text are hidden in the second half of their period
+    machine.hw.gpu.frames = 24;
+    let frame = machine.hw.gpu.render_frame(&machine.hw.mmu);
+    let mut img = draw_image(&frame, &machine.hw.gpu);
+    let img = img.sub_image(9, 4, 18, 12).to_image();
+    assert_eq!("\
+..................
+..................
+..................
+..................
+..................
+..................
+..................
+..................
+..................
+..................
+..................
+..................
+", draw_ascii(&img));
+
+    // attribute bit 7 selects a bright background
+    machine.hw.gpu.set_blink(&mut machine.hw.mmu, false);
+    let frame = machine.hw.gpu.render_frame(&machine.hw.mmu);
+    let mut img = draw_image(&frame, &machine.hw.gpu);
+    let img = img.sub_image(18, 6, 9, 3).to_image();
+    assert_eq!("\
++++++++++
+000000000
++++++++++
+", draw_ascii(&img));
+}
+
+#[test]
+fn can_load_text_mode_font() {
+    let mut machine = Machine::default();
+    let code: Vec<u8> = vec![
+        0xB8, 0x12, 0x11,   // mov ax,0x1112    ; load 8x8 font, 50 rows
+        0xB3, 0x00,         // mov bl,0x0
+        0xCD, 0x10,         // int 0x10
+    ];
+    machine.load_executable(&code);
+    machine.execute_instructions(3);
+    machine.execute_instruction(); // trigger the interrupt
+    assert_eq!(49, machine.hw.mmu.read_u8(BIOS::DATA_SEG, BIOS::DATA_NB_ROWS));
+    assert_eq!(8, machine.hw.mmu.read_u8(BIOS::DATA_SEG, BIOS::DATA_CHAR_HEIGHT));
+    machine.hw.mmu.write(0xB800, 49 * 160, &[b'x', 0x07]);
+
+    let frame = machine.hw.gpu.render_frame(&machine.hw.mmu);
+    let mut img = draw_image(&frame, &machine.hw.gpu);
+    let img = img.sub_image(0, 392, 9, 8).to_image();
+    assert_eq!("\
+.........
+.........
+66...66..
+.66.66...
+..666....
+.66.66...
+66...66..
+.........
+", draw_ascii(&img));
+}
+
+#[test]
+fn can_render_fonts_written_to_plane_2() {
+    let mut machine = Machine::default();
+    let code: Vec<u8> = vec![
+        0xB8, 0x03, 0x00,   // mov ax,0x3
+        0xCD, 0x10,         // int 0x10
+        0xBA, 0xC4, 0x03,   // mov dx,0x3c4
+        0xB8, 0x02, 0x04,   // mov ax,0x402     ; map mask = plane 2
+        0xEF,               // out dx,ax
+        0xB8, 0x04, 0x07,   // mov ax,0x704     ; sequential addressing
+        0xEF,               // out dx,ax
+        0xB2, 0xCE,         // mov dl,0xce
+        0xB8, 0x06, 0x04,   // mov ax,0x406     ; planes at A000
+        0xEF,               // out dx,ax
+        0xB8, 0x06, 0x0E,   // mov ax,0xe06     ; text mode memory at B800
+        0xEF,               // out dx,ax
+        0xB2, 0xC4,         // mov dl,0xc4
+        0xB8, 0x02, 0x03,   // mov ax,0x302
+        0xEF,               // out dx,ax
+        0xB8, 0x04, 0x03,   // mov ax,0x304
+        0xEF,               // out dx,ax
+        0xB8, 0x03, 0x04,   // mov ax,0x403     ; character map A = set 1, B = set 0
+        0xEF,               // out dx,ax
+    ];
+    machine.load_executable(&code);
+    machine.execute_instructions(2);
+    machine.execute_instruction(); // trigger the interrupt
+    machine.execute_instructions(8);
+    assert_eq!(0x0116, machine.cpu.regs.ip);
+
+    // character set 1 is at 16K in plane 2
+    machine.hw.mmu.write(0xA000, 0x4000 + u16::from(b'A') * 32, &[0xFF, 0x81, 0x81, 0x81, 0x81, 0x81, 0x81, 0xFF]);
+    machine.execute_instructions(9);
+    assert_eq!(0x0128, machine.cpu.regs.ip);
+    machine.hw.mmu.write(0xB800, 0x0000, &[
+        b'A', 0x0F,         // bright white, character map A
+        b'A', 0x07,         // white, character map B
+    ]);
+
+    let frame = machine.hw.gpu.render_frame(&machine.hw.mmu);
+    let mut img = draw_image(&frame, &machine.hw.gpu);
+    let img = img.sub_image(0, 0, 18, 10).to_image();
+    assert_eq!("\
+00000000..........
+0......0..........
+0......0....6.....
+0......0...666....
+0......0..66.66...
+0......0.66...66..
+0......0.66...66..
+00000000.6666666..
+.........66...66..
+.........66...66..
+", draw_ascii(&img));
+}
+
+#[test]
+fn can_render_tweaked_text_mode() {
+    let mut machine = Machine::default();
+    let code: Vec<u8> = vec![
+        0xB8, 0x03, 0x00,   // mov ax,0x3
+        0xCD, 0x10,         // int 0x10
+        0xBA, 0xD8, 0x03,   // mov dx,0x3d8
+        0xB0, 0x09,         // mov al,0x9       ; 80x25 text, blink off
+        0xEE,               // out dx,al
+        0xB2, 0xD4,         // mov dl,0xd4
+        0xB8, 0x09, 0x03,   // mov ax,0x309     ; maximum scan line = 3
+        0xEF,               // out dx,ax
+    ];
+    machine.load_executable(&code);
+
+    machine.execute_instructions(2);
+    machine.execute_instruction(); // trigger the interrupt
+    machine.execute_instructions(6);
+    assert_eq!(0x0111, machine.cpu.regs.ip);
+    machine.hw.mmu.write(0xB800, 0x0000, &[
+        0xDE, 0xC1,         // blue on bright red
+        0xDE, 0x2E,         // yellow on green
+    ]);
+
+    // 2 pixels of 16 colors in each character cell of 4 lines
+    let frame = machine.hw.gpu.render_frame(&machine.hw.mmu);
+    let mut img = draw_image(&frame, &machine.hw.gpu);
+    let img = img.sub_image(0, 2, 18, 3).to_image();
+    assert_eq!("\
+5555,,,,,,,,,OOOOO
+5555,,,,,,,,,OOOOO
+..................
+", draw_ascii(&img));
+}
+
 fn draw_ascii(img: &ImageBuffer<Rgb<u8>, Vec<u8>>) -> String {
     let mut res = String::new();
     for y in 0..img.height() {
@@ -174,7 +627,11 @@ fn scale(value_in:f64, base_min:f64, base_max:f64, limit_min:f64, limit_max:f64)
 
 #[test] #[ignore] // expensive test
 fn demo_256() {
-    let test_bins = vec![
+    run_and_save_video_frames(demo_256_bins(), "demo_256", "256");
+}
+
+fn demo_256_bins() -> Vec<&'static str> {
+    vec![
         "../dos-software-decoding/demo-256/4sum/4sum.com",
         "../dos-software-decoding/demo-256/165plasm/165plasm.com",
         "../dos-software-decoding/demo-256/244b/244b.com",
@@ -222,13 +679,16 @@ fn demo_256() {
         "../dos-software-decoding/demo-256/wetwet/wetwet.com",
         "../dos-software-decoding/demo-256/x/x.com",
         "../dos-software-decoding/demo-256/zork/zork.com",
-    ];
-    run_and_save_video_frames(test_bins, "demo_256", "256");
+    ]
 }
 
 #[test] #[ignore] // expensive test
 fn demo_512() {
-    let test_bins = vec![
+    run_and_save_video_frames(demo_512_bins(), "demo_512", "512");
+}
+
+fn demo_512_bins() -> Vec<&'static str> {
+    vec![
         "../dos-software-decoding/demo-512/1/1.com",
         "../dos-software-decoding/demo-512/bars512/bars512.com",
         "../dos-software-decoding/demo-512/basicboy/basicboy.com",
@@ -249,13 +709,16 @@ fn demo_512() {
         "../dos-software-decoding/demo-512/unknown/unknown.com",
         "../dos-software-decoding/demo-512/wamma/wamma.com",
         "../dos-software-decoding/demo-512/waves/waves.com",
-    ];
-    run_and_save_video_frames(test_bins, "demo_512", "512");
+    ]
 }
 
 #[test] #[ignore] // expensive test
 fn demo_256_32bit() {
-    let test_bins = vec![
+    run_and_save_video_frames(demo_256_32bit_bins(), "demo_256_32bit", "256_32bit");
+}
+
+fn demo_256_32bit_bins() -> Vec<&'static str> {
+    vec![
         "../dos-software-decoding/demo-256-32bit/anding/anding.com",
         "../dos-software-decoding/demo-256-32bit/enchante/enchante.com",
         "../dos-software-decoding/demo-256-32bit/fire!/fire!.com",
@@ -273,13 +736,16 @@ fn demo_256_32bit() {
         "../dos-software-decoding/demo-256-32bit/textaroo/textaroo.com",
         "../dos-software-decoding/demo-256-32bit/wtrfall/wtrfall.com",
         "../dos-software-decoding/demo-256-32bit/xwater/xwater.com",
-    ];
-    run_and_save_video_frames(test_bins, "demo_256_32bit", "256_32bit");
+    ]
 }
 
 #[test] #[ignore] // expensive test
 fn demo_512_32bit() {
-    let test_bins = vec![
+    run_and_save_video_frames(demo_512_32bit_bins(), "demo_512_32bit", "512_32bit");
+}
+
+fn demo_512_32bit_bins() -> Vec<&'static str> {
+    vec![
         "../dos-software-decoding/demo-512-32bit/200h/200h.com",
         "../dos-software-decoding/demo-512-32bit/blobsf/blobsf.com",
         "../dos-software-decoding/demo-512-32bit/bt7/bt7.com",
@@ -295,22 +761,28 @@ fn demo_512_32bit() {
         "../dos-software-decoding/demo-512-32bit/grindkng/grindkng.com",
         "../dos-software-decoding/demo-512-32bit/rwater/rwater.com",
         "../dos-software-decoding/demo-512-32bit/voronoy/voronoy.com",
-    ];
-    run_and_save_video_frames(test_bins, "demo_512_32bit", "512_32bit");
+    ]
 }
 
 #[test] #[ignore] // expensive test
 fn demo_16k() {
-    let test_bins = vec![
+    run_and_save_video_frames(demo_16k_bins(), "demo_16k", "16k");
+}
+
+fn demo_16k_bins() -> Vec<&'static str> {
+    vec![
         "../dos-software-decoding/demo-16k/dreamers_bbs/dreamer.com",
         "../dos-software-decoding/demo-16k/microsoft_golf_cracktro/mgc.com",
-    ];
-    run_and_save_video_frames(test_bins, "demo_16k", "16k");
+    ]
 }
 
 #[test] #[ignore] // expensive test
 fn games_com() {
-    let test_bins = vec![
+    run_and_save_video_frames(games_com_bins(), "games_com", "game");
+}
+
+fn games_com_bins() -> Vec<&'static str> {
+    vec![
         "../dos-software-decoding/games-com/8088 Othello (1985)(Bayley)/8088_othello.com",
         "../dos-software-decoding/games-com/Apple Panic (1982)(Broderbund Software Inc)/panic.com",
         "../dos-software-decoding/games-com/Astro Dodge (1982)(Digital Marketing Corporation)/astroids.com",
@@ -350,8 +822,7 @@ fn games_com() {
         "../dos-software-decoding/games-com/Yatzy (1984)(Jan Ivar Gundersen)/yatzy.com",
         "../dos-software-decoding/games-com/Zaxxon (1984)(Sega)/zaxxon.com",
         "../dos-software-decoding/games-com/Zyll (1984)(Marshal Linder)/zyll.com",
-    ];
-    run_and_save_video_frames(test_bins, "games_com", "game");
+    ]
 }
 
 fn run_and_save_video_frames(mut test_bins: Vec<&str>, group: &str, name_prefix: &str) {
@@ -361,19 +832,7 @@ fn run_and_save_video_frames(mut test_bins: Vec<&str>, group: &str, name_prefix:
     while let Some(bin) = test_bins.pop() {
         println!("{}: {}", group, bin);
 
-        let mut machine = Machine::default();
-        machine.cpu.deterministic = true;
-        match tools::read_binary(bin) {
-            Ok(data) => machine.load_executable(&data),
-            Err(err) => panic!("failed to read {}: {}", bin, err),
-        }
-
-        for _ in 0..7_000_000 {
-            machine.execute_instruction();
-            if machine.cpu.fatal_error {
-                break;
-            }
-        }
+        let machine = run_binary(bin, Engine::Interpreter);
         let path = Path::new(bin);
 
         let _ = fs::create_dir(&format!("docs/render/{}", group));
@@ -391,7 +850,7 @@ fn run_and_save_video_frames(mut test_bins: Vec<&str>, group: &str, name_prefix:
             out_images.push(pub_filename);
             /*
             let frame = machine.hw.gpu.render_frame(&machine.hw.mmu);
-            let img = draw_image(&frame, &machine.hw.gpu.mode);
+            let img = draw_image(&frame, &machine.hw.gpu);
             print!("{}", draw_ascii(&img));
             */
         } else {
@@ -419,10 +878,55 @@ fn run_and_save_video_frames(mut test_bins: Vec<&str>, group: &str, name_prefix:
     }
 }
 
+#[test] #[ignore] // expensive test
+fn threaded_engine_renders_like_the_interpreter() {
+    let groups = vec![
+        demo_256_bins(),
+        demo_512_bins(),
+        demo_256_32bit_bins(),
+        demo_512_32bit_bins(),
+        demo_16k_bins(),
+        games_com_bins(),
+    ];
+    let mut mismatches = vec![];
+    for bin in groups.into_iter().flatten() {
+        println!("engines: {}", bin);
+        let interpreter = run_binary(bin, Engine::Interpreter);
+        let threaded = run_binary(bin, Engine::Threaded);
+        let expected = interpreter.hw.gpu.render_frame(&interpreter.hw.mmu);
+        let frame = threaded.hw.gpu.render_frame(&threaded.hw.mmu);
+        if frame != expected {
+            mismatches.push(bin);
+        }
+    }
+    assert_eq!(Vec::<&str>::new(), mismatches, "the threaded engine rendered different frames");
+}
+
+/// runs a binary for 7 million instructions with the selected engine
+fn run_binary(bin: &str, engine: Engine) -> Machine {
+    let mut machine = Machine::default();
+    machine.cpu.deterministic = true;
+    machine.engine = engine;
+    match tools::read_binary(bin) {
+        Ok(data) => machine.load_executable(&data),
+        Err(err) => panic!("failed to read {}: {}", bin, err),
+    }
+
+    let mut executed = 0;
+    while executed < 7_000_000 {
+        executed += machine.execute_step(7_000_000 - executed);
+        if machine.cpu.fatal_error {
+            break;
+        }
+    }
+    machine
+}
+
 // converts a video frame to a ImageBuffer, used for saving video frame to disk in gpu_test
-fn draw_image(frame: &[u8], mode: &VideoModeBlock) -> ImageBuffer<Rgb<u8>, Vec<u8>> {
-    let img = ImageBuffer::from_fn(mode.swidth, mode.sheight, |x, y| {
-        let offset = 3 * ((y * mode.swidth) + x) as usize;
+fn draw_image(frame: &[u8], gpu: &GPU) -> ImageBuffer<Rgb<u8>, Vec<u8>> {
+    let (width, height) = gpu.frame_size();
+    let img = ImageBuffer::from_fn(width, height, |x, y| {
+        let offset = 3 * ((y * width) + x) as usize;
         let r = frame[offset];
         let g = frame[offset + 1];
         let b = frame[offset + 2];
@@ -438,7 +942,7 @@ fn write_video_frame_to_disk(machine: &Machine, pngfile: &str) -> bool {
         println!("ERROR: no frame rendered");
         return false;
     }
-    let img = draw_image(&frame, &machine.hw.gpu.mode);
+    let img = draw_image(&frame, &machine.hw.gpu);
     if let Err(why) = img.save(pngfile) {
         println!("save err: {:?}", why);
         return false;
@@ -0,0 +1,76 @@
+#[derive(Clone, Default)]
+pub struct Sequencer {
+    reset: u8,
+    clocking_mode: u8,
+    map_mask: u8,
+    character_map_select: u8,
+    memory_mode: u8,
+
+    index: u8,
+}
+
+impl Sequencer {
+    // 03C4  RW  sequencer register index (see #P0670)
+    // bit 7-3 : reserved (VGA)
+    // bit 2-0 : current sequencer index
+    pub fn set_index(&mut self, data: u8) {
+        self.index = data & 0x07;
+    }
+
+    pub fn get_index(&self) -> u8 {
+        self.index
+    }
+
+    // 03C5  RW  sequencer register data
+    // (Table P0670)
+    // Values for EGA/VGA sequencer register index:
+    //  00h	reset register (see #P0671)
+    //  01h	clocking mode register (see #P0672)
+    //  02h	map mask register (see #P0673)
+    //  03h	character map select register (see #P0674)
+    //  04h	memory mode register (see #P0675)
+    pub fn write_current(&mut self, data: u8) {
+        match self.index {
+            0x00 => self.reset = data,
+            0x01 => self.clocking_mode = data,
+            0x02 => self.map_mask = data & 0x0F,
+            0x03 => self.character_map_select = data,
+            0x04 => self.memory_mode = data,
+            _ => println!("XXX sequencer: unhandled register {:02X} = {:02X}", self.index, data),
+        }
+    }
+
+    pub fn read_current(&self) -> u8 {
+        match self.index {
+            0x00 => self.reset,
+            0x01 => self.clocking_mode,
+            0x02 => self.map_mask,
+            0x03 => self.character_map_select,
+            0x04 => self.memory_mode,
+            _ => 0,
+        }
+    }
+
+    /// bit 0-3 enable writes to plane 0-3
+    pub fn map_mask(&self) -> u8 {
+        self.map_mask
+    }
+
+    /// returns the character sets of the text modes, selected by attribute bit 3 set (map A) and clear (map B)
+    pub fn character_maps(&self) -> (u8, u8) {
+        let data = self.character_map_select;
+        let map_a = (data >> 3) & 0x04 | (data >> 2) & 0x03;
+        let map_b = (data >> 2) & 0x04 | data & 0x03;
+        (map_a, map_b)
+    }
+
+    /// the planes are chained, the low 2 bits of a address select the plane (mode 13h)
+    pub fn chain4(&self) -> bool {
+        self.memory_mode & 0x08 != 0
+    }
+
+    /// even addresses select plane 0 and 2, odd addresses plane 1 and 3 (text modes)
+    pub fn odd_even(&self) -> bool {
+        self.memory_mode & 0x04 == 0
+    }
+}
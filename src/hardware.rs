@@ -3,6 +3,8 @@ use memory::MMU;
 use pit::PIT;
 use pic::PIC;
 use bios::BIOS;
+use dos::DOS;
+use keyboard::Keyboard;
 
 const DEBUG_IO: bool = false;
 
@@ -10,9 +12,11 @@ pub struct Hardware {
     pub gpu: GPU,
     pub mmu: MMU,
     pub bios: BIOS,
+    pub dos: DOS,
     pub pit: PIT,
     pub pic: PIC,
     pub pic2: PIC, // secondary pic
+    pub keyboard: Keyboard,
 }
 
 impl Hardware {
@@ -26,9 +30,67 @@ impl Hardware {
             mmu,
             gpu,
             bios,
+            dos: DOS::default(),
             pit: PIT::default(),
-            pic: PIC::default(),
-            pic2: PIC::default(),
+            pic: PIC::new(0x08, 0xB8, 0x04),
+            pic2: PIC::new(0x70, 0x9D, 0x02),
+            keyboard: Keyboard::default(),
+        }
+    }
+
+    /// presses a key, scancode is a scancode set 1 make code
+    pub fn key_down(&mut self, scancode: u8) {
+        self.keyboard.key_down(scancode);
+        self.update_keyboard_irq();
+    }
+
+    /// releases a key, scancode is a scancode set 1 make code
+    pub fn key_up(&mut self, scancode: u8) {
+        self.keyboard.key_up(scancode);
+        self.update_keyboard_irq();
+    }
+
+    /// raises a hardware interrupt request. IRQ 0-7 goes to the master PIC,
+    /// IRQ 8-15 to the slave PIC. IRQ 2 is redirected to IRQ 9 as on the AT
+    pub fn raise_irq(&mut self, irq: u8) {
+        match irq {
+            2 => self.pic2.request_irq(1),
+            0...7 => self.pic.request_irq(irq),
+            8...15 => self.pic2.request_irq(irq - 8),
+            _ => panic!("invalid irq {}", irq),
+        }
+    }
+
+    /// performs the interrupt acknowledge cycle, returns the interrupt
+    /// vector of the highest priority pending IRQ
+    pub fn acknowledge_interrupt(&mut self) -> Option<u8> {
+        // the slave PIC output is connected to IRQ 2 of the master
+        if self.pic2.pending_irq().is_some() {
+            self.pic.request_irq(2);
+        } else {
+            self.pic.clear_irq(2);
+        }
+        let irq = self.pic.pending_irq()?;
+        if self.pic.is_cascade(irq) {
+            let slave_irq = self.pic2.pending_irq()?;
+            self.pic.acknowledge(irq);
+            Some(self.pic2.acknowledge(slave_irq))
+        } else {
+            Some(self.pic.acknowledge(irq))
+        }
+    }
+
+    /// advances the timers by the emulated time of cycles at clock_hz
+    pub fn advance_timers(&mut self, cycles: usize, clock_hz: usize) {
+        if self.pit.advance(cycles, clock_hz) {
+            self.raise_irq(0);
+        }
+    }
+
+    /// raises IRQ 1 when the keyboard controller filled its output buffer
+    fn update_keyboard_irq(&mut self) {
+        if self.keyboard.take_irq() {
+            self.raise_irq(1);
         }
     }
 
@@ -54,16 +116,16 @@ impl Hardware {
             // Note: XT uses ports 60h-63h, AT uses ports 60h-64h
             0x0060 => {
                 // keyboard controller data output buffer
-                0 // XXX
+                let val = self.keyboard.read_data();
+                self.update_keyboard_irq();
+                val
             },
             0x0061 => {
                 // keyboard controller port b control register
-                0 // XXX
-            }
-            0x0064 => {
-                // keyboard controller read status
-                0 // XXX
+                // bit 5 = timer 2 output, bit 0 = timer 2 gate
+                (self.pit.counter2.output as u8) << 5 | self.pit.counter2.gate() as u8
             }
+            0x0064 => self.keyboard.read_status(),
             0x00A0 => self.pic2.get_register(),
             0x00A1 => self.pic2.get_ocw1(),
             0x0201 => {
@@ -79,15 +141,22 @@ impl Hardware {
                 //  0	A joystick X coordinate	   / A paddle coordinate
                 0 // XXX
             }
+            0x03B5 | 0x03D5 => self.gpu.crtc.read_current(),
+            0x03C0 => self.gpu.attribute.get_index(),
+            0x03C1 => self.gpu.attribute.read_current(),
+            0x03C4 => self.mmu.memory.borrow().planar.sequencer.get_index(),
+            0x03C5 => self.mmu.memory.borrow().planar.sequencer.read_current(),
             0x03C7 => self.gpu.dac.get_state(),
             0x03C8 => self.gpu.dac.get_pel_write_index(),
             0x03C9 => self.gpu.dac.get_pel_data(),
-            0x03D5 => {
-                // RW  CRT control register value
-                // XXX
-                0
-            },
-            0x03DA => self.gpu.read_cga_status_register(),
+            0x03CE => self.mmu.memory.borrow().planar.graphics.get_index(),
+            0x03CF => self.mmu.memory.borrow().planar.graphics.read_current(),
+            0x03D8 => self.gpu.cga.mode_control(),
+            0x03DA => {
+                // the read also selects the attribute controller index register
+                self.gpu.attribute.reset_flip_flop();
+                self.gpu.read_cga_status_register()
+            }
             _ => {
                 println!("in_u8: unhandled port {:04X}", port);
                 0
@@ -120,9 +189,21 @@ impl Hardware {
             0x0041 => self.pit.counter1.write_reload_part(data),
             0x0042 => self.pit.counter2.write_reload_part(data),
             0x0043 => self.pit.set_mode_command(data),
+            0x0060 => {
+                // keyboard controller data input buffer
+                self.keyboard.write_data(data);
+                self.update_keyboard_irq();
+            }
             0x0061 => {
                 // keyboard controller port b OR ppi programmable perihpial interface (XT only) - which mode are we in?
+                // bit 0 = timer 2 gate
+                self.pit.counter2.set_gate(data & 1 != 0);
             },
+            0x0064 => {
+                // keyboard controller input buffer
+                self.keyboard.write_command(data);
+                self.update_keyboard_irq();
+            }
             0x00A0 => self.pic2.set_command(data),
             0x00A1 => self.pic2.set_data(data),
             0x0201 => {
@@ -140,23 +221,32 @@ impl Hardware {
                 // XXX impl
             },
 
+            // PORT 03C0 - EGA/VGA - ATTRIBUTE CONTROLLER
+            0x03C0 => self.gpu.attribute.write(data),
+
+            // PORT 03C4-03C5 - EGA/VGA - SEQUENCER REGISTERS
+            0x03C4 => self.mmu.memory.borrow_mut().planar.set_sequencer_index(data),
+            0x03C5 => self.mmu.memory.borrow_mut().planar.write_sequencer(data),
+
             // PORT 03C6-03C9 - EGA/VGA/MCGA - DAC REGISTERS
             0x03C6 => self.gpu.dac.set_pel_mask(data),
             0x03C7 => self.gpu.dac.set_pel_read_index(data),
             0x03C8 => self.gpu.dac.set_pel_write_index(data),
             0x03C9 => self.gpu.dac.set_pel_data(data),
 
+            // PORT 03CE-03CF - EGA/VGA - GRAPHICS CONTROLLER REGISTERS
+            0x03CE => self.mmu.memory.borrow_mut().planar.set_graphics_index(data),
+            0x03CF => self.mmu.memory.borrow_mut().planar.write_graphics(data),
+
             // PORT 03D4-03D5 - COLOR VIDEO - CRT CONTROL REGISTERS
             0x03D4 => self.gpu.crtc.set_index(data),
             0x03D5 => self.gpu.crtc.write_current(data),
 
-            0x03D8 => {
-                // RW  CGA mode control register  (except PCjr) (see #P0817)
-	            // cannot be found on native color EGA, color VGA, but on most clones
-            }
-            0x03D9 => {
-                // XXX CGA palette register!!!
-            }
+            // RW  CGA mode control register  (except PCjr) (see #P0817)
+            // cannot be found on native color EGA, color VGA, but on most clones
+            0x03D8 => self.gpu.set_cga_mode_control(data),
+            // -W  CGA palette register
+            0x03D9 => self.gpu.cga.set_color_select(data),
             0x03DA => {
                 // 03DA  -W  color EGA/color VGA feature control register (see #P0820)
 	            //  (at PORT 03BAh w in mono mode, VGA: 3CAh r)
@@ -191,19 +281,16 @@ impl Hardware {
             println!("out_u16: write to {:04X} = {:04X}", port, data);
         }
         match port {
-            // PORT 03C4-03C5 - EGA/VGA - SEQUENCER REGISTERS
-            0x03C4 => {
-                // XXX if 16bit, its first INDEX byte, then DATA byte
-                let _idx = data >> 8 as u8; // TS index register
-                let _val = data as u8; // sequencer register index
-                // println!("XXX out_u16 03C4 idx {:02X} = {:02X}", idx, val);
-            },
+            // PORT 03C4-03C5, 03CE-03CF, 03D4-03D5 - SEQUENCER, GRAPHICS CONTROLLER AND CRT CONTROL REGISTERS
+            // the low byte goes to the index register, the high byte to the data register
+            0x03C4 | 0x03CE | 0x03D4 => {
+                self.out_u8(port, data as u8);
+                self.out_u8(port + 1, (data >> 8) as u8);
+            }
 
             // PORT 03C6-03C9 - EGA/VGA/MCGA - DAC REGISTERS
             0x03C9 => self.gpu.dac.set_pel_data(data as u8),
 
-            // PORT 03D4-03D5 - COLOR VIDEO - CRT CONTROL REGISTERS
-            0x03D4 => self.gpu.crtc.set_index(data as u8),
             0x03D5 => self.gpu.crtc.write_current(data as u8),
 
             _ => println!("out_u16: unhandled port {:04X} = {:04X}", port, data),
@@ -0,0 +1,20 @@
+use hardware::Hardware;
+use cpu::CPU;
+use bios::BIOS;
+
+/// number of timer ticks in 24 hours
+pub const TICKS_PER_DAY: u32 = 0x0018_00B0;
+
+// system timer, called by IRQ 0
+pub fn handle(cpu: &mut CPU, hw: &mut Hardware) {
+    // increment the tick counter, and roll over at midnight
+    let mut ticks = hw.mmu.read_u32(BIOS::DATA_SEG, BIOS::DATA_TIMER_TICKS) + 1;
+    if ticks >= TICKS_PER_DAY {
+        ticks = 0;
+        hw.mmu.write_u8(BIOS::DATA_SEG, BIOS::DATA_TIMER_OVERFLOW, 1);
+    }
+    hw.mmu.write_u32(BIOS::DATA_SEG, BIOS::DATA_TIMER_TICKS, ticks);
+
+    // continues in the ROM, which calls the user timer tick (INT 1Ch) and sends the end of interrupt
+    cpu.regs.ip = u32::from(BIOS::ROM_TIMER_CHAIN);
+}
@@ -0,0 +1,270 @@
+use hardware::Hardware;
+use cpu::CPU;
+use bios::{BIOS, add_key_to_buffer};
+
+// keyboard flags at 0040:0017
+const FLAGS1_RIGHT_SHIFT: u8 = 0x01;
+const FLAGS1_LEFT_SHIFT: u8  = 0x02;
+const FLAGS1_CTRL: u8        = 0x04;
+const FLAGS1_ALT: u8         = 0x08;
+const FLAGS1_SCROLL_LOCK: u8 = 0x10;
+const FLAGS1_NUM_LOCK: u8    = 0x20;
+const FLAGS1_CAPS_LOCK: u8   = 0x40;
+const FLAGS1_INSERT: u8      = 0x80;
+
+// keyboard flags at 0040:0018
+const FLAGS2_LEFT_CTRL: u8   = 0x01;
+const FLAGS2_LEFT_ALT: u8    = 0x02;
+const FLAGS2_SCROLL_DOWN: u8 = 0x10;
+const FLAGS2_NUM_DOWN: u8    = 0x20;
+const FLAGS2_CAPS_DOWN: u8   = 0x40;
+const FLAGS2_INSERT_DOWN: u8 = 0x80;
+
+// keyboard flags at 0040:0096
+const FLAGS3_E1_PREFIX: u8   = 0x01;
+const FLAGS3_E0_PREFIX: u8   = 0x02;
+const FLAGS3_RIGHT_CTRL: u8  = 0x04;
+const FLAGS3_RIGHT_ALT: u8   = 0x08;
+
+/// translation from scancode to key code: normal, shift, ctrl, alt
+const SCANCODE_KEYS: [[u16; 4]; 0x59] = [
+    [0x0000, 0x0000, 0x0000, 0x0000], // 00
+    [0x011B, 0x011B, 0x011B, 0x01F0], // 01 escape
+    [0x0231, 0x0221, 0x0000, 0x7800], // 02 1!
+    [0x0332, 0x0340, 0x0300, 0x7900], // 03 2@
+    [0x0433, 0x0423, 0x0000, 0x7A00], // 04 3#
+    [0x0534, 0x0524, 0x0000, 0x7B00], // 05 4$
+    [0x0635, 0x0625, 0x0000, 0x7C00], // 06 5%
+    [0x0736, 0x075E, 0x071E, 0x7D00], // 07 6^
+    [0x0837, 0x0826, 0x0000, 0x7E00], // 08 7&
+    [0x0938, 0x092A, 0x0000, 0x7F00], // 09 8*
+    [0x0A39, 0x0A28, 0x0000, 0x8000], // 0A 9(
+    [0x0B30, 0x0B29, 0x0000, 0x8100], // 0B 0)
+    [0x0C2D, 0x0C5F, 0x0C1F, 0x8200], // 0C -_
+    [0x0D3D, 0x0D2B, 0x0000, 0x8300], // 0D =+
+    [0x0E08, 0x0E08, 0x0E7F, 0x0EF0], // 0E backspace
+    [0x0F09, 0x0F00, 0x9400, 0x0000], // 0F tab
+    [0x1071, 0x1051, 0x1011, 0x1000], // 10 Q
+    [0x1177, 0x1157, 0x1117, 0x1100], // 11 W
+    [0x1265, 0x1245, 0x1205, 0x1200], // 12 E
+    [0x1372, 0x1352, 0x1312, 0x1300], // 13 R
+    [0x1474, 0x1454, 0x1414, 0x1400], // 14 T
+    [0x1579, 0x1559, 0x1519, 0x1500], // 15 Y
+    [0x1675, 0x1655, 0x1615, 0x1600], // 16 U
+    [0x1769, 0x1749, 0x1709, 0x1700], // 17 I
+    [0x186F, 0x184F, 0x180F, 0x1800], // 18 O
+    [0x1970, 0x1950, 0x1910, 0x1900], // 19 P
+    [0x1A5B, 0x1A7B, 0x1A1B, 0x1AF0], // 1A [{
+    [0x1B5D, 0x1B7D, 0x1B1D, 0x1BF0], // 1B ]}
+    [0x1C0D, 0x1C0D, 0x1C0A, 0x0000], // 1C enter
+    [0x0000, 0x0000, 0x0000, 0x0000], // 1D left ctrl
+    [0x1E61, 0x1E41, 0x1E01, 0x1E00], // 1E A
+    [0x1F73, 0x1F53, 0x1F13, 0x1F00], // 1F S
+    [0x2064, 0x2044, 0x2004, 0x2000], // 20 D
+    [0x2166, 0x2146, 0x2106, 0x2100], // 21 F
+    [0x2267, 0x2247, 0x2207, 0x2200], // 22 G
+    [0x2368, 0x2348, 0x2308, 0x2300], // 23 H
+    [0x246A, 0x244A, 0x240A, 0x2400], // 24 J
+    [0x256B, 0x254B, 0x250B, 0x2500], // 25 K
+    [0x266C, 0x264C, 0x260C, 0x2600], // 26 L
+    [0x273B, 0x273A, 0x0000, 0x27F0], // 27 ;:
+    [0x2827, 0x2822, 0x0000, 0x28F0], // 28 '"
+    [0x2960, 0x297E, 0x0000, 0x29F0], // 29 `~
+    [0x0000, 0x0000, 0x0000, 0x0000], // 2A left shift
+    [0x2B5C, 0x2B7C, 0x2B1C, 0x2BF0], // 2B \|
+    [0x2C7A, 0x2C5A, 0x2C1A, 0x2C00], // 2C Z
+    [0x2D78, 0x2D58, 0x2D18, 0x2D00], // 2D X
+    [0x2E63, 0x2E43, 0x2E03, 0x2E00], // 2E C
+    [0x2F76, 0x2F56, 0x2F16, 0x2F00], // 2F V
+    [0x3062, 0x3042, 0x3002, 0x3000], // 30 B
+    [0x316E, 0x314E, 0x310E, 0x3100], // 31 N
+    [0x326D, 0x324D, 0x320D, 0x3200], // 32 M
+    [0x332C, 0x333C, 0x0000, 0x33F0], // 33 ,<
+    [0x342E, 0x343E, 0x0000, 0x34F0], // 34 .>
+    [0x352F, 0x353F, 0x0000, 0x35F0], // 35 /?
+    [0x0000, 0x0000, 0x0000, 0x0000], // 36 right shift
+    [0x372A, 0x372A, 0x9600, 0x37F0], // 37 keypad *
+    [0x0000, 0x0000, 0x0000, 0x0000], // 38 left alt
+    [0x3920, 0x3920, 0x3920, 0x3920], // 39 space
+    [0x0000, 0x0000, 0x0000, 0x0000], // 3A caps lock
+    [0x3B00, 0x5400, 0x5E00, 0x6800], // 3B F1
+    [0x3C00, 0x5500, 0x5F00, 0x6900], // 3C F2
+    [0x3D00, 0x5600, 0x6000, 0x6A00], // 3D F3
+    [0x3E00, 0x5700, 0x6100, 0x6B00], // 3E F4
+    [0x3F00, 0x5800, 0x6200, 0x6C00], // 3F F5
+    [0x4000, 0x5900, 0x6300, 0x6D00], // 40 F6
+    [0x4100, 0x5A00, 0x6400, 0x6E00], // 41 F7
+    [0x4200, 0x5B00, 0x6500, 0x6F00], // 42 F8
+    [0x4300, 0x5C00, 0x6600, 0x7000], // 43 F9
+    [0x4400, 0x5D00, 0x6700, 0x7100], // 44 F10
+    [0x0000, 0x0000, 0x0000, 0x0000], // 45 num lock
+    [0x0000, 0x0000, 0x0000, 0x0000], // 46 scroll lock
+    [0x4700, 0x4737, 0x7700, 0x0000], // 47 keypad 7 home
+    [0x4800, 0x4838, 0x8D00, 0x0000], // 48 keypad 8 up
+    [0x4900, 0x4939, 0x8400, 0x0000], // 49 keypad 9 page up
+    [0x4A2D, 0x4A2D, 0x8E00, 0x0000], // 4A keypad -
+    [0x4B00, 0x4B34, 0x7300, 0x0000], // 4B keypad 4 left
+    [0x4C00, 0x4C35, 0x8F00, 0x0000], // 4C keypad 5
+    [0x4D00, 0x4D36, 0x7400, 0x0000], // 4D keypad 6 right
+    [0x4E2B, 0x4E2B, 0x9000, 0x0000], // 4E keypad +
+    [0x4F00, 0x4F31, 0x7500, 0x0000], // 4F keypad 1 end
+    [0x5000, 0x5032, 0x9100, 0x0000], // 50 keypad 2 down
+    [0x5100, 0x5133, 0x7600, 0x0000], // 51 keypad 3 page down
+    [0x5200, 0x5230, 0x9200, 0x0000], // 52 keypad 0 insert
+    [0x5300, 0x532E, 0x9300, 0x0000], // 53 keypad . delete
+    [0x0000, 0x0000, 0x0000, 0x0000], // 54
+    [0x0000, 0x0000, 0x0000, 0x0000], // 55
+    [0x565C, 0x567C, 0x0000, 0x0000], // 56 102-key \|
+    [0x8500, 0x8700, 0x8900, 0x8B00], // 57 F11
+    [0x8600, 0x8800, 0x8A00, 0x8C00], // 58 F12
+];
+
+// BIOS keyboard IRQ handler, called on IRQ 1
+pub fn handle(_cpu: &mut CPU, hw: &mut Hardware) {
+    let scancode = hw.in_u8(0x60);
+    let mut flags1 = hw.mmu.read_u8(BIOS::DATA_SEG, BIOS::DATA_KBD_FLAGS1);
+    let mut flags2 = hw.mmu.read_u8(BIOS::DATA_SEG, BIOS::DATA_KBD_FLAGS2);
+    let mut flags3 = hw.mmu.read_u8(BIOS::DATA_SEG, BIOS::DATA_KBD_FLAGS3);
+    let extended = flags3 & FLAGS3_E0_PREFIX != 0;
+
+    match scancode {
+        0xE0 => flags3 |= FLAGS3_E0_PREFIX,
+        0xE1 => flags3 |= FLAGS3_E1_PREFIX,
+        0x1D => {
+            // ctrl pressed
+            if extended {
+                flags3 |= FLAGS3_RIGHT_CTRL;
+            } else {
+                flags2 |= FLAGS2_LEFT_CTRL;
+            }
+            flags1 |= FLAGS1_CTRL;
+        }
+        0x9D => {
+            if extended {
+                flags3 &= !FLAGS3_RIGHT_CTRL;
+            } else {
+                flags2 &= !FLAGS2_LEFT_CTRL;
+            }
+            if flags2 & FLAGS2_LEFT_CTRL == 0 && flags3 & FLAGS3_RIGHT_CTRL == 0 {
+                flags1 &= !FLAGS1_CTRL;
+            }
+        }
+        0x38 => {
+            // alt pressed
+            if extended {
+                flags3 |= FLAGS3_RIGHT_ALT;
+            } else {
+                flags2 |= FLAGS2_LEFT_ALT;
+            }
+            flags1 |= FLAGS1_ALT;
+        }
+        0xB8 => {
+            if extended {
+                flags3 &= !FLAGS3_RIGHT_ALT;
+            } else {
+                flags2 &= !FLAGS2_LEFT_ALT;
+            }
+            if flags2 & FLAGS2_LEFT_ALT == 0 && flags3 & FLAGS3_RIGHT_ALT == 0 {
+                flags1 &= !FLAGS1_ALT;
+            }
+        }
+        // the fake shifts sent with E0 prefix by gray keys are ignored
+        0x2A => if !extended { flags1 |= FLAGS1_LEFT_SHIFT },
+        0xAA => if !extended { flags1 &= !FLAGS1_LEFT_SHIFT },
+        0x36 => if !extended { flags1 |= FLAGS1_RIGHT_SHIFT },
+        0xB6 => if !extended { flags1 &= !FLAGS1_RIGHT_SHIFT },
+        0x3A => {
+            if flags2 & FLAGS2_CAPS_DOWN == 0 {
+                flags1 ^= FLAGS1_CAPS_LOCK;
+            }
+            flags2 |= FLAGS2_CAPS_DOWN;
+        }
+        0xBA => flags2 &= !FLAGS2_CAPS_DOWN,
+        0x45 if flags3 & FLAGS3_E1_PREFIX == 0 => {
+            if flags2 & FLAGS2_NUM_DOWN == 0 {
+                flags1 ^= FLAGS1_NUM_LOCK;
+            }
+            flags2 |= FLAGS2_NUM_DOWN;
+        }
+        0xC5 if flags3 & FLAGS3_E1_PREFIX == 0 => flags2 &= !FLAGS2_NUM_DOWN,
+        0x46 if !extended => {
+            if flags2 & FLAGS2_SCROLL_DOWN == 0 {
+                flags1 ^= FLAGS1_SCROLL_LOCK;
+            }
+            flags2 |= FLAGS2_SCROLL_DOWN;
+        }
+        0xC6 if !extended => flags2 &= !FLAGS2_SCROLL_DOWN,
+        _ => {
+            if scancode == 0x52 && flags1 & (FLAGS1_CTRL | FLAGS1_ALT) == 0 {
+                if flags2 & FLAGS2_INSERT_DOWN == 0 {
+                    flags1 ^= FLAGS1_INSERT;
+                }
+                flags2 |= FLAGS2_INSERT_DOWN;
+            } else if scancode == 0xD2 {
+                flags2 &= !FLAGS2_INSERT_DOWN;
+            }
+            if scancode & 0x80 == 0 && flags3 & FLAGS3_E1_PREFIX == 0 {
+                if let Some(key) = translate_scancode(scancode, flags1, extended) {
+                    if !add_key_to_buffer(&mut hw.mmu, key) {
+                        println!("int09: keyboard buffer full, dropping key {:04X}", key);
+                    }
+                }
+            }
+        }
+    }
+
+    if scancode != 0xE0 && scancode != 0xE1 {
+        flags3 &= !(FLAGS3_E0_PREFIX | FLAGS3_E1_PREFIX);
+    }
+
+    hw.mmu.write_u8(BIOS::DATA_SEG, BIOS::DATA_KBD_FLAGS1, flags1);
+    hw.mmu.write_u8(BIOS::DATA_SEG, BIOS::DATA_KBD_FLAGS2, flags2);
+    hw.mmu.write_u8(BIOS::DATA_SEG, BIOS::DATA_KBD_FLAGS3, flags3);
+
+    // end of interrupt
+    hw.out_u8(0x20, 0x20);
+}
+
+/// returns the key code (scancode in high byte, ASCII in low byte) for a key press
+fn translate_scancode(scancode: u8, flags1: u8, extended: bool) -> Option<u16> {
+    let keys = SCANCODE_KEYS.get(scancode as usize)?;
+    let shift = flags1 & (FLAGS1_LEFT_SHIFT | FLAGS1_RIGHT_SHIFT) != 0;
+    let ctrl = flags1 & FLAGS1_CTRL != 0;
+    let alt = flags1 & FLAGS1_ALT != 0;
+
+    let key = if extended {
+        match scancode {
+            // keypad enter and keypad /
+            0x1C => if alt { 0xA600 } else if ctrl { 0xE00A } else { 0xE00D },
+            0x35 => if alt { 0xA400 } else if ctrl { 0x9500 } else { 0xE02F },
+            // gray insert, delete, home, end, page up, page down and arrow keys
+            0x47...0x53 => if alt {
+                u16::from(scancode + 0x50) << 8
+            } else if ctrl {
+                (keys[2] & 0xFF00) | 0xE0
+            } else {
+                (u16::from(scancode) << 8) | 0xE0
+            },
+            _ => keys[0],
+        }
+    } else if alt {
+        keys[3]
+    } else if ctrl {
+        keys[2]
+    } else {
+        let ascii = keys[0] as u8;
+        let shifted = if ascii.is_ascii_alphabetic() {
+            shift != (flags1 & FLAGS1_CAPS_LOCK != 0)
+        } else if scancode >= 0x47 && scancode <= 0x53 && scancode != 0x4A && scancode != 0x4E {
+            shift != (flags1 & FLAGS1_NUM_LOCK != 0)
+        } else {
+            shift
+        };
+        if shifted { keys[1] } else { keys[0] }
+    };
+    if key == 0 {
+        None
+    } else {
+        Some(key)
+    }
+}
@@ -4,6 +4,7 @@ use memory::{MMU, MemoryAddress};
 use gpu::{VideoModeBlock, GFXMode, SpecialMode, ega_mode_block, vga_mode_block};
 use gpu::GFXMode::*;
 use bios::BIOS;
+use bios;
 
 // video related interrupts
 pub fn handle(cpu: &mut CPU, hw: &mut Hardware) {
@@ -21,10 +22,9 @@ pub fn handle(cpu: &mut CPU, hw: &mut Hardware) {
 
             // Return:
             // Nothing
-            println!("XXX set text-mode cursor shape, start_options={:02X}, bottom_line={:02X}",
-                     cpu.get_r8(R::CH),
-                     cpu.get_r8(R::CL));
-
+            let first = cpu.get_r8(R::CH);
+            let last = cpu.get_r8(R::CL);
+            hw.gpu.set_cursor_shape(&mut hw.mmu, first, last);
         }
         0x02 => {
             // VIDEO - SET CURSOR POSITION
@@ -42,7 +42,10 @@ pub fn handle(cpu: &mut CPU, hw: &mut Hardware) {
             // CL = end scan line
             // DH = row (00h is top)
             // DL = column (00h is left)
-            println!("XXX GET CURSOR POSITION AND SIZE, page {}", page);
+            cpu.set_r16(R::AX, 0);
+            cpu.set_r16(R::CX, hw.mmu.read_u16(BIOS::DATA_SEG, BIOS::DATA_CURSOR_TYPE));
+            cpu.set_r8(R::DH, bios::cursor_pos_row(&hw.mmu, page & 7));
+            cpu.set_r8(R::DL, bios::cursor_pos_col(&hw.mmu, page & 7));
         }
         0x05 => {
             // VIDEO - SELECT ACTIVE DISPLAY PAGE
@@ -126,7 +129,8 @@ pub fn handle(cpu: &mut CPU, hw: &mut Hardware) {
                     // VIDEO - SET BACKGROUND/BORDER COLOR
                     // BL = background/border color (border only in text modes)
                     // Return: Nothing
-                    println!("XXX set bg/border color, bl={:02X}", cpu.get_r8(R::BL));
+                    let color = cpu.get_r8(R::BL);
+                    hw.gpu.set_background_color(&mut hw.mmu, color);
                 }
                 0x01 => {
                     // VIDEO - SET PALETTE
@@ -138,7 +142,8 @@ pub fn handle(cpu: &mut CPU, hw: &mut Hardware) {
                     // Note: This call was only valid in 320x200 graphics on
                     // the CGA, but newer cards support it in many or all
                     // graphics modes
-                    println!("XXX TODO set palette id, bl={:02X}", cpu.get_r8(R::BL));
+                    let id = cpu.get_r8(R::BL);
+                    hw.gpu.set_palette_id(&mut hw.mmu, id);
                 }
                 _ => {
                     println!("video error: unknown int 10, ah=0B, bh={:02X}", cpu.get_r8(R::BH));
@@ -173,15 +178,47 @@ pub fn handle(cpu: &mut CPU, hw: &mut Hardware) {
                     // BL = palette register number (00h-0Fh)
                     //    = attribute register number (undocumented) (see #00017)
                     // BH = color or attribute register value
-                    panic!("XXX VIDEO - SET SINGLE PALETTE REGISTER, bl={:02X}, bh={:02X}",
-                             cpu.get_r8(R::BL),
-                             cpu.get_r8(R::BH));
+                    let reg = cpu.get_r8(R::BL);
+                    let val = cpu.get_r8(R::BH);
+                    hw.gpu.set_single_palette_register(reg, val);
+                }
+                0x01 => {
+                    // VIDEO - SET BORDER (OVERSCAN) COLOR (PCjr,Tandy,EGA,VGA)
+                    // BH = border color (00h-3Fh)
+                    let val = cpu.get_r8(R::BH);
+                    hw.gpu.set_overscan_border_color(val);
+                }
+                0x02 => {
+                    // VIDEO - SET ALL PALETTE REGISTERS (PCjr,Tandy,EGA,VGA)
+                    // ES:DX -> palette register list (see #00018)
+                    let seg = cpu.get_r16(R::ES);
+                    let off = cpu.get_r16(R::DX);
+                    hw.gpu.set_all_palette_registers(&hw.mmu, seg, off);
+                }
+                0x03 => {
+                    // VIDEO - TOGGLE INTENSITY/BLINKING BIT (Jr, PS, TANDY 1000, EGA, VGA)
+                    // BL = new state
+                    //      00h background intensity enabled
+                    //      01h blink enabled
+                    let bl = cpu.get_r8(R::BL);
+                    hw.gpu.set_blink(&mut hw.mmu, bl != 0);
                 }
                 0x07 => {
                     // VIDEO - GET INDIVIDUAL PALETTE REGISTER (VGA,UltraVision v2+)
                     let reg = cpu.get_r8(R::BL);
                     cpu.set_r8(R::BH, hw.gpu.get_individual_palette_register(reg));
                 }
+                0x08 => {
+                    // VIDEO - READ OVERSCAN (BORDER COLOR) REGISTER (VGA)
+                    cpu.set_r8(R::BH, hw.gpu.read_overscan_border_color());
+                }
+                0x09 => {
+                    // VIDEO - READ ALL PALETTE REGISTERS AND OVERSCAN REGISTER (VGA)
+                    // ES:DX -> 17-byte buffer for palette registers and border color
+                    let seg = cpu.get_r16(R::ES);
+                    let off = cpu.get_r16(R::DX);
+                    hw.gpu.read_all_palette_registers(&mut hw.mmu, seg, off);
+                }
                 0x10 => {
                     // VIDEO - SET INDIVIDUAL DAC REGISTER (VGA/MCGA)
                     let index = cpu.get_r8(R::BL);
@@ -221,6 +258,41 @@ pub fn handle(cpu: &mut CPU, hw: &mut Hardware) {
         }
         0x11 => {
             match cpu.get_r8(R::AL) {
+                0x00 | 0x10 => {
+                    // VIDEO - TEXT-MODE CHARGEN - LOAD USER-SPECIFIED CHARACTERS (PS,EGA,VGA)
+                    // ES:BP -> user table
+                    // CX    = count of patterns to store
+                    // DX    = character offset into map 2 block
+                    // BL    = block to load in map 2
+                    // BH    = number of bytes per character pattern
+                    // AL = 10h also recalculates the character rows
+                    let recalc = cpu.get_r8(R::AL) == 0x10;
+                    let seg = cpu.get_r16(R::ES);
+                    let off = cpu.get_r16(R::BP);
+                    let count = cpu.get_r16(R::CX);
+                    let first = cpu.get_r16(R::DX);
+                    let block = cpu.get_r8(R::BL);
+                    let height = cpu.get_r8(R::BH);
+                    hw.gpu.load_user_font(&mut hw.mmu, seg, off, count, first, block, height, recalc);
+                }
+                0x01 | 0x11 => {
+                    // VIDEO - TEXT-MODE CHARGEN - LOAD ROM MONOCHROME PATTERNS (PS,EGA,VGA)
+                    // BL = block to load
+                    let block = cpu.get_r8(R::BL);
+                    hw.gpu.load_rom_text_font(&mut hw.mmu, 14, block, cpu.get_r8(R::AL) == 0x11);
+                }
+                0x02 | 0x12 => {
+                    // VIDEO - TEXT-MODE CHARGEN - LOAD ROM 8x8 DBL-DOT PATTERNS (PS,EGA,VGA)
+                    // BL = block to load
+                    let block = cpu.get_r8(R::BL);
+                    hw.gpu.load_rom_text_font(&mut hw.mmu, 8, block, cpu.get_r8(R::AL) == 0x12);
+                }
+                0x04 | 0x14 => {
+                    // VIDEO - TEXT-MODE CHARGEN - LOAD ROM 8x16 CHARACTER SET (VGA)
+                    // BL = block to load
+                    let block = cpu.get_r8(R::BL);
+                    hw.gpu.load_rom_text_font(&mut hw.mmu, 16, block, cpu.get_r8(R::AL) == 0x14);
+                }
                 0x24 => {
                     // VIDEO - GRAPH-MODE CHARGEN - LOAD 8x16 GRAPHICS CHARS (VGA,MCGA)
                     let bl = cpu.get_r8(R::BL);
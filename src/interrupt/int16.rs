@@ -1,6 +1,7 @@
 use hardware::Hardware;
 use cpu::{CPU, R};
 use cpu::*;
+use bios::{BIOS, add_key_to_buffer, peek_key_from_buffer, get_key_from_buffer};
 
 // keyboard related interrupts
 pub fn handle(cpu: &mut CPU, hw: &mut Hardware) {
@@ -10,8 +11,18 @@ pub fn handle(cpu: &mut CPU, hw: &mut Hardware) {
             // Return:
             // AH = BIOS scan code
             // AL = ASCII character
-            cpu.set_r16(R::AX, 0); // XXX
-            // println!("XXX impl KEYBOARD - GET KEYSTROKE");
+            loop {
+                match get_key_from_buffer(&mut hw.mmu) {
+                    Some(key) => if let Some(key) = standard_key(key) {
+                        cpu.set_r16(R::AX, key);
+                        break;
+                    },
+                    None => {
+                        wait_for_keystroke(cpu);
+                        break;
+                    }
+                }
+            }
         }
         0x01 => {
             // KEYBOARD - CHECK FOR KEYSTROKE
@@ -20,9 +31,55 @@ pub fn handle(cpu: &mut CPU, hw: &mut Hardware) {
             // ZF clear if keystroke available
             // AH = BIOS scan code
             // AL = ASCII character
-
-            // println!("XXX impl KEYBOARD - CHECK FOR KEYSTROKE");
-            hw.bios.set_flag(&mut hw.mmu, FLAG_ZF, true);
+            loop {
+                match peek_key_from_buffer(&hw.mmu) {
+                    Some(key) => match standard_key(key) {
+                        Some(key) => {
+                            cpu.set_r16(R::AX, key);
+                            hw.bios.set_flag(&mut hw.mmu, FLAG_ZF, false);
+                            break;
+                        }
+                        None => {
+                            // drop keys without a translation
+                            get_key_from_buffer(&mut hw.mmu);
+                        }
+                    },
+                    None => {
+                        hw.bios.set_flag(&mut hw.mmu, FLAG_ZF, true);
+                        break;
+                    }
+                }
+            }
+        }
+        0x02 => {
+            // KEYBOARD - GET SHIFT FLAGS
+            // Return:
+            // AL = shift flags (see #00582)
+            // AH destroyed by many BIOSes
+            let flags1 = hw.mmu.read_u8(BIOS::DATA_SEG, BIOS::DATA_KBD_FLAGS1);
+            cpu.set_r8(R::AL, flags1);
+        }
+        0x05 => {
+            // KEYBOARD - STORE KEYSTROKE IN KEYBOARD BUFFER (AT/PS w enh keybd only)
+            // CH = scan code
+            // CL = ASCII character
+            // Return:
+            // AL = status
+            //      00h if successful
+            //      01h if keyboard buffer full
+            let key = cpu.get_r16(R::CX);
+            let full = !add_key_to_buffer(&mut hw.mmu, key);
+            cpu.set_r8(R::AL, full as u8);
+        }
+        0x10 => {
+            // KEYBOARD - GET ENHANCED KEYSTROKE (enhanced kbd support only)
+            // Return:
+            // AH = BIOS scan code
+            // AL = ASCII character
+            match get_key_from_buffer(&mut hw.mmu) {
+                Some(key) => cpu.set_r16(R::AX, key),
+                None => wait_for_keystroke(cpu),
+            }
         }
         0x11 => {
             // KEYBOARD - CHECK FOR ENHANCED KEYSTROKE (enh kbd support only)
@@ -31,8 +88,34 @@ pub fn handle(cpu: &mut CPU, hw: &mut Hardware) {
             // ZF clear if keystroke available
             // AH = BIOS scan code
             // AL = ASCII character
-            println!("XXX impl KEYBOARD - CHECK FOR ENHANCED KEYSTROKE");
-            hw.bios.set_flag(&mut hw.mmu, FLAG_ZF, true);
+            match peek_key_from_buffer(&hw.mmu) {
+                Some(key) => {
+                    cpu.set_r16(R::AX, key);
+                    hw.bios.set_flag(&mut hw.mmu, FLAG_ZF, false);
+                }
+                None => hw.bios.set_flag(&mut hw.mmu, FLAG_ZF, true),
+            }
+        }
+        0x12 => {
+            // KEYBOARD - GET EXTENDED SHIFT STATES (enh kbd support only)
+            // Return:
+            // AL = shift flags 1 (see #00582)
+            // AH = shift flags 2 (see #00583)
+            let flags1 = hw.mmu.read_u8(BIOS::DATA_SEG, BIOS::DATA_KBD_FLAGS1);
+            let flags2 = hw.mmu.read_u8(BIOS::DATA_SEG, BIOS::DATA_KBD_FLAGS2);
+            let flags3 = hw.mmu.read_u8(BIOS::DATA_SEG, BIOS::DATA_KBD_FLAGS3);
+            // (Bitfields for keyboard shift flags 2)
+            // 7 SysReq key pressed
+            // 6 Caps Lock pressed
+            // 5 Num Lock pressed
+            // 4 Scroll Lock pressed
+            // 3 right Alt pressed
+            // 2 right Ctrl pressed
+            // 1 left Alt pressed
+            // 0 left Ctrl pressed
+            let ah = (flags2 & 0b0111_0011) | (flags3 & 0b0000_1100) | ((flags2 & 0b0000_0100) << 5);
+            cpu.set_r8(R::AL, flags1);
+            cpu.set_r8(R::AH, ah);
         }
         0x92 => {
             // KEYB.COM KEYBOARD CAPABILITIES CHECK (not an actual function!)
@@ -48,3 +131,29 @@ pub fn handle(cpu: &mut CPU, hw: &mut Hardware) {
         }
     }
 }
+
+/// waits in the handler like the BIOS does with STI; HLT. the handler runs
+/// again when the interrupt that woke the cpu returns here
+fn wait_for_keystroke(cpu: &mut CPU) {
+    cpu.regs.flags.interrupt = true;
+    cpu.halted = true;
+}
+
+/// translates a enhanced keyboard key code for the standard functions
+/// AH=00h and AH=01h, returns None for keys that don't exist on a 84-key keyboard
+fn standard_key(key: u16) -> Option<u16> {
+    let scancode = (key >> 8) as u8;
+    let ascii = key as u8;
+    if scancode == 0xE0 {
+        // keypad enter and keypad /
+        return Some(if ascii == 0x0D || ascii == 0x0A { 0x1C00 } else { 0x3500 } | u16::from(ascii));
+    }
+    if scancode > 0x84 || (ascii == 0xF0 && scancode != 0) {
+        return None;
+    }
+    if ascii == 0xE0 && scancode != 0 {
+        // gray keys
+        return Some(u16::from(scancode) << 8);
+    }
+    Some(key)
+}
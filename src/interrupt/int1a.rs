@@ -1,33 +1,28 @@
-use time;
-
 use hardware::Hardware;
 use cpu::{CPU, R};
+use bios::BIOS;
 
 // time related interrupts
-pub fn handle(cpu: &mut CPU, _hw: &mut Hardware) {
+pub fn handle(cpu: &mut CPU, hw: &mut Hardware) {
     match cpu.get_r8(R::AH) {
         0x00 => {
             // TIME - GET SYSTEM TIME
             // Return:
             // CX:DX = number of clock ticks since midnight
             // AL = midnight flag, nonzero if midnight passed since time last read
-            if cpu.deterministic {
-                cpu.set_r16(R::CX, 0);
-                cpu.set_r16(R::DX, 0);
-                cpu.set_r8(R::AL, 0);
-            } else {
-                println!("XXX FIXME - INT 1A GET TIME: return number of clock ticks since midnight");
-                cpu.set_r16(R::CX, 1);
-                cpu.set_r16(R::DX, 1);
-                cpu.set_r8(R::AL, 0);
-            }
+            let ticks = hw.mmu.read_u32(BIOS::DATA_SEG, BIOS::DATA_TIMER_TICKS);
+            let midnight = hw.mmu.read_u8(BIOS::DATA_SEG, BIOS::DATA_TIMER_OVERFLOW);
+            hw.mmu.write_u8(BIOS::DATA_SEG, BIOS::DATA_TIMER_OVERFLOW, 0);
+            cpu.set_r16(R::CX, (ticks >> 16) as u16);
+            cpu.set_r16(R::DX, ticks as u16);
+            cpu.set_r8(R::AL, midnight);
         }
         0x01 => {
             // TIME - SET SYSTEM TIME
             // CX:DX = number of clock ticks since midnight
-            let cx = cpu.get_r16(R::CX);
-            let dx = cpu.get_r16(R::DX);
-            println!("XXX SET SYSTEM TIME: CX:DX = {:04X}:{:04X}", cx, dx);
+            let ticks = u32::from(cpu.get_r16(R::CX)) << 16 | u32::from(cpu.get_r16(R::DX));
+            hw.mmu.write_u32(BIOS::DATA_SEG, BIOS::DATA_TIMER_TICKS, ticks);
+            hw.mmu.write_u8(BIOS::DATA_SEG, BIOS::DATA_TIMER_OVERFLOW, 0);
         }
         _ => {
             println!("int1a error: unknown ah={:02X}, ax={:04X}",
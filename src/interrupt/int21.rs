@@ -1,10 +1,11 @@
 use time;
-use std::str;
 
 use hardware::Hardware;
-use cpu::{CPU, R};
+use cpu::{CPU, R, FLAG_CF};
 use codepage::cp437;
 use memory::MemoryAddress;
+use dos::{DosError, AccessMode, SeekOrigin, AllocationStrategy, STDIN, STDOUT, STDERR, STDAUX, STDPRN, FIRST_FILE_HANDLE,
+    client_offset, set_client_offset};
 
 // dos related interrupts
 pub fn handle(cpu: &mut CPU, hw: &mut Hardware) {
@@ -58,10 +59,9 @@ pub fn handle(cpu: &mut CPU, hw: &mut Hardware) {
             // Standard output is always the screen under DOS 1.x, but may be
             // redirected under DOS 2+. Under the FlashTek X-32 DOS extender,
             // the pointer is in DS:EDX
-            let mut count = 0;
-            loop {
-                let b = hw.mmu.read_u8(cpu.get_r16(R::DS), cpu.get_r16(R::DX) + count);
-                count += 1;
+            let addr = buffer_address(cpu, hw, R::DS, R::DX);
+            for count in 0.. {
+                let b = hw.mmu.load_u8(addr.wrapping_add(count), cpu.cpl() == 3);
                 if b as char == '$' {
                     break;
                 }
@@ -147,44 +147,71 @@ pub fn handle(cpu: &mut CPU, hw: &mut Hardware) {
             cpu.set_r16(R::ES, seg);
             cpu.set_r16(R::BX, off);
         }
+        0x3C => {
+            // DOS 2+ - CREAT - CREATE OR TRUNCATE FILE
+            // CX = file attributes (see #01401)
+            // DS:DX -> ASCIZ filename
+            // Return:
+            // CF clear if successful and AX = file handle
+            // CF set on error and AX = error code (03h,04h,05h) (see #01680 at AH=59h/BX=0000h)
+            let filename = read_asciiz(hw, buffer_address(cpu, hw, R::DS, R::DX));
+            let res = hw.dos.fs.create(&filename);
+            set_result(cpu, hw, res);
+        }
         0x3D => {
             // DOS 2+ - OPEN - OPEN EXISTING FILE
-            let mode = cpu.get_r8(R::AL); // access and sharing modes (see #01402)
-            let attr = cpu.get_r8(R::CL); // attribute mask of files to look for (server call only)
+            // AL = access and sharing modes (see #01402)
             // DS:DX -> ASCIZ filename
-            let ds = cpu.get_r16(R::DS);
-            let dx = cpu.get_r16(R::DX);
-            let data = hw.mmu.readz(ds, dx);
-            let filename = str::from_utf8(&data).unwrap();
+            // CL = attribute mask of files to look for (server call only)
             // Return:
             // CF clear if successful and AX = file handle
             // CF set on error and AX = error code (01h,02h,03h,04h,05h,0Ch,56h) (see #01680 at AH=59h)
-            println!("int21 XXX DOS 2+ - OPEN - OPEN EXISTING FILE, name {}, mode {:02X}, attr {:02X}", filename, mode, attr);
-
-            cpu.regs.flags.carry = true; // XXX fake failure
-            cpu.set_r16(R::AX, 0x0002); // XXX 2 = "file not found"
+            let filename = read_asciiz(hw, buffer_address(cpu, hw, R::DS, R::DX));
+            let res = match AccessMode::from_u8(cpu.get_r8(R::AL)) {
+                Ok(mode) => hw.dos.fs.open(&filename, mode),
+                Err(e) => Err(e),
+            };
+            set_result(cpu, hw, res);
         }
         0x3E => {
             // DOS 2+ - CLOSE - CLOSE FILE
-            let handle = cpu.get_r16(R::BX); // file handle
+            // BX = file handle
             // Return:
             // CF clear if successful and AX destroyed
             // CF set on error and AX = error code (06h) (see #01680 at AH=59h/BX=0000h)
-            println!("int21 XXX DOS 2+ - CLOSE - CLOSE FILE, handle {:04X}", handle);
-            cpu.regs.flags.carry = false; // XXX fake success
+            let handle = cpu.get_r16(R::BX);
+            let res = if handle < FIRST_FILE_HANDLE {
+                Ok(())
+            } else {
+                hw.dos.fs.close(handle)
+            };
+            set_result(cpu, hw, res.map(|_| 0));
         }
         0x3F => {
             // DOS 2+ - READ - READ FROM FILE OR DEVICE
-            let handle = cpu.get_r16(R::BX); // file handle
-            let len = cpu.get_r16(R::CX); // number of bytes to read
+            // BX = file handle
+            // CX = number of bytes to read
             // DS:DX -> buffer for data
-            let ds = cpu.get_r16(R::DS);
-            let dx = cpu.get_r16(R::DX);
-            println!("int21 XXX DOS 2+ - READ - READ FROM FILE OR DEVICE, handle {:04X}, len {}, buffer at {:04X}:{:04X}", handle, len, ds, dx);
+            // Return:
+            // CF clear if successful and AX = number of bytes actually read (0 if at EOF before call)
+            // CF set on error and AX = error code (05h,06h) (see #01680 at AH=59h/BX=0000h)
+            let handle = cpu.get_r16(R::BX);
+            let len = client_offset(cpu, hw, R::CX);
+            let res = if handle == STDIN {
+                // XXX no console input yet, behave as end of file
+                Ok(Vec::new())
+            } else {
+                hw.dos.fs.read(handle, len)
+            };
+            let res = res.map(|data| {
+                let addr = buffer_address(cpu, hw, R::DS, R::DX);
+                hw.mmu.write_linear(addr, &data);
+                data.len() as u32
+            });
+            set_count_result(cpu, hw, res);
         }
         0x40 => {
             // DOS 2+ - WRITE - WRITE TO FILE OR DEVICE
-
             // BX = file handle
             // CX = number of bytes to write
             // DS:DX -> data to write
@@ -201,11 +228,58 @@ pub fn handle(cpu: &mut CPU, hw: &mut Hardware) {
             // file must have been opened with AX=6C00h with the "extended size" flag in order
             // to expand the file beyond 2GB; otherwise the write will fail with error code
             // 0005h (access denied). The usual cause for AX < CX on return is a full disk
-            println!("XXX DOS - WRITE TO FILE OR DEVICE, handle={:04X}, count={:04X}, data from {:04X}:{:04X}",
-                     cpu.get_r16(R::BX),
-                     cpu.get_r16(R::CX),
-                     cpu.get_r16(R::DS),
-                     cpu.get_r16(R::DX));
+            let handle = cpu.get_r16(R::BX);
+            let len = client_offset(cpu, hw, R::CX);
+            let data = hw.mmu.read_linear(buffer_address(cpu, hw, R::DS, R::DX), len as usize);
+            let res = match handle {
+                STDOUT | STDERR => {
+                    for b in &data {
+                        print!("{}", cp437::u8_as_char(*b));
+                    }
+                    Ok(len)
+                }
+                STDIN | STDAUX | STDPRN => Ok(len),
+                _ => if len == 0 {
+                    hw.dos.fs.truncate(handle).map(|_| 0)
+                } else {
+                    hw.dos.fs.write(handle, &data)
+                },
+            };
+            set_count_result(cpu, hw, res);
+        }
+        0x41 => {
+            // DOS 2+ - UNLINK - DELETE FILE
+            // DS:DX -> ASCIZ filename (no wildcards, but see notes)
+            // CL = attribute mask for deletion (server call only, see notes)
+            // Return:
+            // CF clear if successful, AX destroyed (DOS 3.3) AL seems to be drive of deleted file
+            // CF set on error AX = error code (02h,03h,05h) (see #01680 at AH=59h/BX=0000h)
+            let filename = read_asciiz(hw, buffer_address(cpu, hw, R::DS, R::DX));
+            let res = hw.dos.fs.delete(&filename);
+            set_result(cpu, hw, res.map(|_| 0));
+        }
+        0x42 => {
+            // DOS 2+ - LSEEK - SET CURRENT FILE POSITION
+            // AL = origin of move
+            //      00h start of file
+            //      01h current file position
+            //      02h end of file
+            // BX = file handle
+            // CX:DX = (signed) offset from origin of new file position
+            // Return:
+            // CF clear if successful, DX:AX = new file position in bytes from start of file
+            // CF set on error, AX = error code (01h,06h) (see #01680 at AH=59h/BX=0000h)
+            let handle = cpu.get_r16(R::BX);
+            let offset = (u32::from(cpu.get_r16(R::CX)) << 16 | u32::from(cpu.get_r16(R::DX))) as i32;
+            let res = match SeekOrigin::from_u8(cpu.get_r8(R::AL)) {
+                Ok(origin) => hw.dos.fs.seek(handle, origin, offset),
+                Err(e) => Err(e),
+            };
+            let res = res.map(|pos| {
+                cpu.set_r16(R::DX, (pos >> 16) as u16);
+                pos as u16
+            });
+            set_result(cpu, hw, res);
         }
         0x48 => {
             // DOS 2+ - ALLOCATE MEMORY
@@ -216,8 +290,26 @@ pub fn handle(cpu: &mut CPU, hw: &mut Hardware) {
             // CF set on error
             // AX = error code (07h,08h) (see #01680 at AH=59h/BX=0000h)
             // BX = size of largest available block
-            println!("XXX impl DOS 2+ - ALLOCATE MEMORY. bx={:04X}",
-                     cpu.get_r16(R::BX));
+            let paragraphs = cpu.get_r16(R::BX);
+            let owner = hw.dos.psp_segment;
+            let res = hw.dos.memory.allocate(&mut hw.mmu, paragraphs, owner);
+            if res == Err(DosError::InsufficientMemory) {
+                let largest = hw.dos.memory.largest_free_block(&mut hw.mmu).unwrap_or(0);
+                cpu.set_r16(R::BX, largest);
+            }
+            set_result(cpu, hw, res);
+        }
+        0x49 => {
+            // DOS 2+ - FREE MEMORY
+            // ES = segment of block to free
+            // Return:
+            // CF clear if successful
+            // CF set on error
+            // AX = error code (07h,09h) (see #01680 at AH=59h/BX=0000h)
+            let seg = cpu.get_r16(R::ES);
+            let ax = cpu.get_r16(R::AX);
+            let res = hw.dos.memory.free(&mut hw.mmu, seg);
+            set_result(cpu, hw, res.map(|_| ax));
         }
         0x4A => {
             // DOS 2+ - RESIZE MEMORY BLOCK
@@ -228,9 +320,15 @@ pub fn handle(cpu: &mut CPU, hw: &mut Hardware) {
             // CF set on error
             // AX = error code (07h,08h,09h) (see #01680 at AH=59h/BX=0000h)
             // BX = maximum paragraphs available for specified memory block
-            println!("XXX impl DOS 2+ - RESIZE MEMORY BLOCK. bx={:04X}, es={:04X}",
-                     cpu.get_r16(R::BX),
-                     cpu.get_r16(R::ES));
+            let paragraphs = cpu.get_r16(R::BX);
+            let seg = cpu.get_r16(R::ES);
+            let ax = cpu.get_r16(R::AX);
+            let res = hw.dos.memory.resize(&mut hw.mmu, seg, paragraphs);
+            if res == Err(DosError::InsufficientMemory) {
+                let max = hw.dos.memory.max_size(&mut hw.mmu, seg).unwrap_or(0);
+                cpu.set_r16(R::BX, max);
+            }
+            set_result(cpu, hw, res.map(|_| ax));
         }
         0x4C => {
             // DOS 2+ - EXIT - TERMINATE WITH RETURN CODE
@@ -243,6 +341,59 @@ pub fn handle(cpu: &mut CPU, hw: &mut Hardware) {
             println!("DOS - TERMINATE WITH RETURN CODE {:02X}", al);
             cpu.fatal_error = true; // XXX just to stop debugger.run() function
         }
+        0x51 | 0x62 => {
+            // DOS 2+ internal - GET CURRENT PROCESS ID (GET PSP ADDRESS)
+            // DOS 3.0+ - GET CURRENT PSP ADDRESS
+            // Return:
+            // BX = segment of PSP for current process
+            cpu.set_r16(R::BX, hw.dos.psp_segment);
+        }
+        0x56 => {
+            // DOS 2+ - RENAME - RENAME FILE
+            // DS:DX -> ASCIZ filename of existing file (no wildcards, but see below)
+            // ES:DI -> ASCIZ new filename (no wildcards)
+            // CL = attribute mask (server call only, see below)
+            // Return:
+            // CF clear if successful
+            // CF set on error, AX = error code (02h,03h,05h,11h) (see #01680 at AH=59h)
+            let old_name = read_asciiz(hw, buffer_address(cpu, hw, R::DS, R::DX));
+            let new_name = read_asciiz(hw, buffer_address(cpu, hw, R::ES, R::DI));
+            let res = hw.dos.fs.rename(&old_name, &new_name);
+            set_result(cpu, hw, res.map(|_| 0));
+        }
+        0x58 => {
+            match cpu.get_r8(R::AL) {
+                0x00 => {
+                    // DOS 2.11+ - GET OR SET MEMORY ALLOCATION STRATEGY - GET
+                    // Return:
+                    // CF clear if successful
+                    // AX = current strategy (see #01679)
+                    let strategy = hw.dos.memory.strategy as u16;
+                    set_result(cpu, hw, Ok(strategy));
+                }
+                0x01 => {
+                    // DOS 2.11+ - GET OR SET MEMORY ALLOCATION STRATEGY - SET
+                    // BX = new allocation strategy (see #01679)
+                    //      00h low memory first fit
+                    //      01h low memory best fit
+                    //      02h low memory last fit
+                    // Return:
+                    // CF clear if successful
+                    // CF set on error
+                    // AX = error code (01h) (see #01680 at AH=59h/BX=0000h)
+                    let ax = cpu.get_r16(R::AX);
+                    let res = AllocationStrategy::from_u16(cpu.get_r16(R::BX)).map(|strategy| {
+                        hw.dos.memory.strategy = strategy;
+                        ax
+                    });
+                    set_result(cpu, hw, res);
+                }
+                _ => {
+                    println!("int21 error: unknown ah=58, al={:02X}", cpu.get_r8(R::AL));
+                    set_result(cpu, hw, Err(DosError::InvalidFunction));
+                }
+            }
+        }
         _ => {
             println!("int21 error: unknown ah={:02X}, ax={:04X}",
                      cpu.get_r8(R::AH),
@@ -250,3 +401,39 @@ pub fn handle(cpu: &mut CPU, hw: &mut Hardware) {
         }
     }
 }
+
+/// returns the result of a DOS call in CF and AX
+fn set_result(cpu: &mut CPU, hw: &mut Hardware, res: Result<u16, DosError>) {
+    match res {
+        Ok(ax) => {
+            cpu.set_r16(R::AX, ax);
+            hw.bios.set_flag(&mut hw.mmu, FLAG_CF, false);
+        }
+        Err(e) => {
+            cpu.set_r16(R::AX, e.code());
+            hw.bios.set_flag(&mut hw.mmu, FLAG_CF, true);
+        }
+    }
+}
+
+/// returns a byte count in CF and AX, or EAX for 32-bit DPMI clients
+fn set_count_result(cpu: &mut CPU, hw: &mut Hardware, res: Result<u32, DosError>) {
+    match res {
+        Ok(count) => {
+            set_client_offset(cpu, hw, R::AX, count);
+            hw.bios.set_flag(&mut hw.mmu, FLAG_CF, false);
+        }
+        Err(e) => set_result(cpu, hw, Err(e)),
+    }
+}
+
+/// linear address of the buffer at seg:off. 32-bit DPMI clients pass 32-bit offsets, as with DOS extenders
+fn buffer_address(cpu: &CPU, hw: &Hardware, seg: R, off: R) -> u32 {
+    cpu.segment_base(seg).wrapping_add(client_offset(cpu, hw, off))
+}
+
+/// reads a ASCIZ string, such as a filename
+fn read_asciiz(hw: &Hardware, addr: u32) -> String {
+    let data = hw.mmu.readz_linear(addr);
+    String::from_utf8_lossy(&data).into_owned()
+}
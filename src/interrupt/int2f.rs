@@ -0,0 +1,48 @@
+use hardware::Hardware;
+use cpu::{CPU, CpuModel, R};
+use dos::{DPMI_ROM_SEG, DPMI_ENTRY_MODE_SWITCH, DPMI_HOST_DATA_PARAGRAPHS, processor_type};
+
+// multiplex interrupt
+pub fn handle(cpu: &mut CPU, hw: &mut Hardware) {
+    match cpu.get_r16(R::AX) {
+        0x1680 => {
+            // MS Windows, DPMI, various - RELEASE CURRENT VIRTUAL MACHINE TIME-SLICE
+            // Return:
+            // AL = 00h if the call is supported
+            cpu.set_r8(R::AL, 0);
+        }
+        0x1686 => {
+            // DPMI 0.9+ - GET CPU MODE
+            // Return:
+            // AX = 0000h if in protected mode, nonzero if in real/V86 mode
+            if cpu.protected_mode() && hw.dos.dpmi.active {
+                cpu.set_r16(R::AX, 0);
+            }
+        }
+        0x1687 if cpu.model() >= CpuModel::I80286 => {
+            // DPMI 0.9+ - GET PROTECTED MODE SWITCH ENTRY POINT
+            // Return:
+            // AX = 0000h if installed
+            // BX = flags, bit 0: 32-bit programs supported
+            // CL = processor type (02h 80286, 03h 80386, 04h 80486)
+            // DH = DPMI major version, DL = DPMI minor version
+            // SI = number of paragraphs of DOS extender private data
+            // ES:DI -> DPMI mode-switch entry point
+            cpu.set_r16(R::AX, 0);
+            cpu.set_r16(R::BX, if cpu.model() >= CpuModel::I80386 { 0x0001 } else { 0x0000 }); // 32-bit clients need a 386
+            cpu.set_r8(R::CL, processor_type(cpu));
+            cpu.set_r8(R::DH, 0x00);
+            cpu.set_r8(R::DL, 0x5A);
+            cpu.set_r16(R::SI, DPMI_HOST_DATA_PARAGRAPHS);
+            if cpu.load_segment_register(&mut hw.mmu, R::ES, DPMI_ROM_SEG).is_ok() {
+                cpu.set_r16(R::DI, DPMI_ENTRY_MODE_SWITCH);
+            }
+        }
+        0x1687 => {
+            // the host needs protected mode, AX is returned unchanged on the 8086 and 80186
+        }
+        _ => {
+            println!("int2f error: unknown ax={:04X}", cpu.get_r16(R::AX));
+        }
+    }
+}
@@ -0,0 +1,506 @@
+use hardware::Hardware;
+use cpu::{CPU, R, Descriptor, FLAG_CF, FLAG_IF};
+use dos::{DpmiError, RealModeCall, DPMI_ROM_SEG, DPMI_HOST_CODE, DPMI_ENTRY_SAVE_STATE, DosError,
+    call_real_mode, reload_segments, set_dos_block_descriptors, dos_block_descriptors, client_offset, set_client_offset, processor_type};
+
+// DPMI services, only available in protected mode
+pub fn handle(cpu: &mut CPU, hw: &mut Hardware) {
+    if !hw.dos.dpmi.active {
+        println!("int31 error: no DPMI client, ax={:04X}", cpu.get_r16(R::AX));
+        return;
+    }
+    match cpu.get_r16(R::AX) {
+        0x0000 => {
+            // DPMI 0.9+ - ALLOCATE LDT DESCRIPTORS
+            // CX = number of descriptors to allocate
+            // Return:
+            // CF clear if successful
+            // AX = base selector
+            let count = cpu.get_r16(R::CX);
+            let res = hw.dos.dpmi.allocate_descriptors(&mut hw.mmu, count);
+            let res = res.map(|selector| cpu.set_r16(R::AX, selector));
+            set_result(cpu, hw, res);
+        }
+        0x0001 => {
+            // DPMI 0.9+ - FREE LDT DESCRIPTOR
+            // BX = selector to free
+            let selector = cpu.get_r16(R::BX);
+            let res = hw.dos.dpmi.free_descriptor(&mut hw.mmu, selector);
+            if res.is_ok() {
+                reload_segments(cpu, hw, selector);
+            }
+            set_result(cpu, hw, res);
+        }
+        0x0002 => {
+            // DPMI 0.9+ - SEGMENT TO DESCRIPTOR
+            // BX = real mode segment
+            // Return:
+            // AX = selector
+            let segment = cpu.get_r16(R::BX);
+            let res = hw.dos.dpmi.segment_to_descriptor(&mut hw.mmu, segment);
+            let res = res.map(|selector| cpu.set_r16(R::AX, selector));
+            set_result(cpu, hw, res);
+        }
+        0x0003 => {
+            // DPMI 0.9+ - GET SELECTOR INCREMENT VALUE
+            // Return:
+            // AX = value to add to get next sequential selector
+            cpu.set_r16(R::AX, 8);
+            set_result(cpu, hw, Ok(()));
+        }
+        0x0006 => {
+            // DPMI 0.9+ - GET SEGMENT BASE ADDRESS
+            // BX = selector
+            // Return:
+            // CX:DX = linear base address of segment
+            let selector = cpu.get_r16(R::BX);
+            let res = hw.dos.dpmi.descriptor(&hw.mmu, selector).map(|desc| {
+                cpu.set_r16(R::CX, (desc.base >> 16) as u16);
+                cpu.set_r16(R::DX, desc.base as u16);
+            });
+            set_result(cpu, hw, res);
+        }
+        0x0007 => {
+            // DPMI 0.9+ - SET SEGMENT BASE ADDRESS
+            // BX = selector
+            // CX:DX = linear base address
+            let base = u32::from(cpu.get_r16(R::CX)) << 16 | u32::from(cpu.get_r16(R::DX));
+            update_descriptor(cpu, hw, |desc| {
+                desc.base = base;
+                Ok(())
+            });
+        }
+        0x0008 => {
+            // DPMI 0.9+ - SET SEGMENT LIMIT
+            // BX = selector
+            // CX:DX = segment limit
+            let limit = u32::from(cpu.get_r16(R::CX)) << 16 | u32::from(cpu.get_r16(R::DX));
+            update_descriptor(cpu, hw, |desc| {
+                desc.limit = limit;
+                Ok(())
+            });
+        }
+        0x0009 => {
+            // DPMI 0.9+ - SET DESCRIPTOR ACCESS RIGHTS
+            // BX = selector
+            // CL = access rights/type byte
+            // CH = 80386 extended access rights (bit 7 granularity, bit 6 default size)
+            let access = cpu.get_r8(R::CL);
+            let flags = cpu.get_r8(R::CH) >> 4;
+            update_descriptor(cpu, hw, |desc| {
+                desc.access = access;
+                desc.flags = flags;
+                Ok(())
+            });
+        }
+        0x000A => {
+            // DPMI 0.9+ - CREATE ALIAS DESCRIPTOR
+            // BX = selector
+            // Return:
+            // AX = new data selector
+            let selector = cpu.get_r16(R::BX);
+            let res = hw.dos.dpmi.descriptor(&hw.mmu, selector).and_then(|desc| {
+                if !desc.is_segment() {
+                    return Err(DpmiError::InvalidSelector);
+                }
+                hw.dos.dpmi.allocate_segment(&mut hw.mmu, desc.base, desc.limit, false)
+            });
+            let res = res.map(|alias| cpu.set_r16(R::AX, alias));
+            set_result(cpu, hw, res);
+        }
+        0x000B => {
+            // DPMI 0.9+ - GET DESCRIPTOR
+            // BX = selector
+            // ES:(E)DI -> 8-byte buffer for copy of descriptor
+            let selector = cpu.get_r16(R::BX);
+            let addr = buffer_address(cpu, hw);
+            let res = hw.dos.dpmi.descriptor(&hw.mmu, selector).map(|desc| {
+                let raw = desc.to_u64();
+                hw.mmu.write_linear_u32(addr, raw as u32);
+                hw.mmu.write_linear_u32(addr + 4, (raw >> 32) as u32);
+            });
+            set_result(cpu, hw, res);
+        }
+        0x000C => {
+            // DPMI 0.9+ - SET DESCRIPTOR
+            // BX = selector
+            // ES:(E)DI -> 8-byte buffer containing descriptor
+            let new = Descriptor::from_u64(hw.mmu.read_linear_u64(buffer_address(cpu, hw)));
+            update_descriptor(cpu, hw, |desc| {
+                *desc = new;
+                Ok(())
+            });
+        }
+        0x0100 => {
+            // DPMI 0.9+ - ALLOCATE DOS MEMORY BLOCK
+            // BX = number of paragraphs to allocate
+            // Return:
+            // CF clear if successful
+            // AX = real mode segment of allocated block
+            // DX = first selector for allocated block
+            // CF set on error
+            // AX = DOS error code
+            // BX = size (in paragraphs) of largest available block
+            let paragraphs = cpu.get_r16(R::BX);
+            let owner = hw.dos.psp_segment;
+            match hw.dos.memory.allocate(&mut hw.mmu, paragraphs, owner) {
+                Ok(segment) => {
+                    let descriptors = dos_block_descriptors(paragraphs);
+                    let res = hw.dos.dpmi.allocate_descriptors(&mut hw.mmu, descriptors).and_then(|selector| {
+                        set_dos_block_descriptors(&mut hw.dos.dpmi, &mut hw.mmu, selector, segment, paragraphs, descriptors)?;
+                        Ok(selector)
+                    });
+                    match res {
+                        Ok(selector) => {
+                            hw.dos.dpmi.add_dos_block(selector, segment, descriptors);
+                            cpu.set_r16(R::AX, segment);
+                            cpu.set_r16(R::DX, selector);
+                            set_result(cpu, hw, Ok(()));
+                        }
+                        Err(e) => {
+                            let _ = hw.dos.memory.free(&mut hw.mmu, segment);
+                            set_result(cpu, hw, Err(e));
+                        }
+                    }
+                }
+                Err(e) => {
+                    if e == DosError::InsufficientMemory {
+                        let largest = hw.dos.memory.largest_free_block(&mut hw.mmu).unwrap_or(0);
+                        cpu.set_r16(R::BX, largest);
+                    }
+                    set_dos_error(cpu, hw, e);
+                }
+            }
+        }
+        0x0101 => {
+            // DPMI 0.9+ - FREE DOS MEMORY BLOCK
+            // DX = selector of block
+            let selector = cpu.get_r16(R::DX);
+            match hw.dos.dpmi.dos_block(selector) {
+                Ok((segment, descriptors)) => match hw.dos.memory.free(&mut hw.mmu, segment) {
+                    Ok(()) => {
+                        hw.dos.dpmi.remove_dos_block(selector);
+                        for i in 0..descriptors {
+                            let _ = hw.dos.dpmi.free_descriptor(&mut hw.mmu, selector + i * 8);
+                            reload_segments(cpu, hw, selector + i * 8);
+                        }
+                        set_result(cpu, hw, Ok(()));
+                    }
+                    Err(e) => set_dos_error(cpu, hw, e),
+                },
+                Err(e) => set_result(cpu, hw, Err(e)),
+            }
+        }
+        0x0102 => {
+            // DPMI 0.9+ - RESIZE DOS MEMORY BLOCK
+            // BX = new block size in paragraphs
+            // DX = selector of block
+            // Return:
+            // CF set on error
+            // AX = DOS error code
+            // BX = maximum block size possible in paragraphs
+            let paragraphs = cpu.get_r16(R::BX);
+            let selector = cpu.get_r16(R::DX);
+            let (segment, descriptors) = match hw.dos.dpmi.dos_block(selector) {
+                Ok(block) => block,
+                Err(e) => return set_result(cpu, hw, Err(e)),
+            };
+            if dos_block_descriptors(paragraphs) > descriptors {
+                // XXX the block can't grow past the descriptors allocated for it
+                cpu.set_r16(R::BX, descriptors.saturating_mul(0x1000));
+                return set_dos_error(cpu, hw, DosError::InsufficientMemory);
+            }
+            match hw.dos.memory.resize(&mut hw.mmu, segment, paragraphs) {
+                Ok(()) => {
+                    let res = set_dos_block_descriptors(&mut hw.dos.dpmi, &mut hw.mmu, selector, segment, paragraphs, descriptors);
+                    for i in 0..descriptors {
+                        reload_segments(cpu, hw, selector + i * 8);
+                    }
+                    set_result(cpu, hw, res);
+                }
+                Err(e) => {
+                    if e == DosError::InsufficientMemory {
+                        let max = hw.dos.memory.max_size(&mut hw.mmu, segment).unwrap_or(0);
+                        cpu.set_r16(R::BX, max);
+                    }
+                    set_dos_error(cpu, hw, e);
+                }
+            }
+        }
+        0x0200 => {
+            // DPMI 0.9+ - GET REAL MODE INTERRUPT VECTOR
+            // BL = interrupt number
+            // Return:
+            // CX:DX = segment:offset of real mode interrupt handler
+            let addr = u32::from(cpu.get_r8(R::BL)) * 4;
+            let offset = hw.mmu.read_linear_u16(addr);
+            let segment = hw.mmu.read_linear_u16(addr + 2);
+            cpu.set_r16(R::CX, segment);
+            cpu.set_r16(R::DX, offset);
+            set_result(cpu, hw, Ok(()));
+        }
+        0x0201 => {
+            // DPMI 0.9+ - SET REAL MODE INTERRUPT VECTOR
+            // BL = interrupt number
+            // CX:DX = segment:offset of real mode handler
+            let addr = u32::from(cpu.get_r8(R::BL)) * 4;
+            let (segment, offset) = (cpu.get_r16(R::CX), cpu.get_r16(R::DX));
+            hw.mmu.write_linear_u16(addr, offset);
+            hw.mmu.write_linear_u16(addr + 2, segment);
+            set_result(cpu, hw, Ok(()));
+        }
+        0x0202 => {
+            // DPMI 0.9+ - GET PROCESSOR EXCEPTION HANDLER VECTOR
+            // BL = exception number (00h-1Fh)
+            // Return:
+            // CX:(E)DX = selector:offset of handler
+            let exception = cpu.get_r8(R::BL);
+            let res = hw.dos.dpmi.exception_handler(exception).map(|(selector, offset)| {
+                cpu.set_r16(R::CX, selector);
+                set_client_offset(cpu, hw, R::DX, offset);
+            });
+            set_result(cpu, hw, res);
+        }
+        0x0203 => {
+            // DPMI 0.9+ - SET PROCESSOR EXCEPTION HANDLER VECTOR
+            // BL = exception number (00h-1Fh)
+            // CX:(E)DX = selector:offset of handler
+            let exception = cpu.get_r8(R::BL);
+            let (selector, offset) = (cpu.get_r16(R::CX), client_offset(cpu, hw, R::DX));
+            let res = hw.dos.dpmi.set_exception_handler(exception, selector, offset);
+            set_result(cpu, hw, res);
+        }
+        0x0204 => {
+            // DPMI 0.9+ - GET PROTECTED MODE INTERRUPT VECTOR
+            // BL = interrupt number
+            // Return:
+            // CX:(E)DX = selector:offset of handler
+            let (selector, offset) = hw.dos.dpmi.pm_vector(cpu.get_r8(R::BL));
+            cpu.set_r16(R::CX, selector);
+            set_client_offset(cpu, hw, R::DX, offset);
+            set_result(cpu, hw, Ok(()));
+        }
+        0x0205 => {
+            // DPMI 0.9+ - SET PROTECTED MODE INTERRUPT VECTOR
+            // BL = interrupt number
+            // CX:(E)DX = selector:offset of handler
+            let vector = cpu.get_r8(R::BL);
+            let (selector, offset) = (cpu.get_r16(R::CX), client_offset(cpu, hw, R::DX));
+            hw.dos.dpmi.set_pm_vector(vector, selector, offset);
+            set_result(cpu, hw, Ok(()));
+        }
+        0x0300...0x0302 => {
+            // DPMI 0.9+ - SIMULATE REAL MODE INTERRUPT (0300h)
+            // DPMI 0.9+ - CALL REAL MODE PROCEDURE WITH FAR RETURN FRAME (0301h)
+            // DPMI 0.9+ - CALL REAL MODE PROCEDURE WITH IRET FRAME (0302h)
+            // BL = interrupt number (0300h)
+            // BH = flags, bit 0 resets the interrupt controller and A20 line (ignored)
+            // CX = number of words to copy from protected mode to real mode stack
+            // ES:(E)DI -> real mode call structure
+            // Return:
+            // CF clear if successful, ES:(E)DI -> updated real mode call structure
+            let call = match cpu.get_r16(R::AX) {
+                0x0300 => RealModeCall::Interrupt(cpu.get_r8(R::BL)),
+                0x0301 => RealModeCall::Far,
+                _ => RealModeCall::Iret,
+            };
+            let words = cpu.get_r16(R::CX);
+            let addr = buffer_address(cpu, hw);
+            if let Err(e) = call_real_mode(cpu, hw, call, Some(addr), words) {
+                set_result(cpu, hw, Err(e));
+            }
+        }
+        0x0303 => {
+            // DPMI 0.9+ - ALLOCATE REAL MODE CALLBACK ADDRESS
+            // DS:(E)SI -> procedure to call
+            // ES:(E)DI -> real mode call structure
+            // Return:
+            // CX:DX = segment:offset of real mode callback address
+            let procedure = (cpu.get_r16(R::DS), client_offset(cpu, hw, R::SI));
+            let call_struct = (cpu.get_r16(R::ES), client_offset(cpu, hw, R::DI));
+            let res = hw.dos.dpmi.allocate_callback(&mut hw.mmu, procedure, call_struct).map(|(segment, offset)| {
+                cpu.set_r16(R::CX, segment);
+                cpu.set_r16(R::DX, offset);
+            });
+            set_result(cpu, hw, res);
+        }
+        0x0304 => {
+            // DPMI 0.9+ - FREE REAL MODE CALLBACK ADDRESS
+            // CX:DX = real mode callback address
+            let (segment, offset) = (cpu.get_r16(R::CX), cpu.get_r16(R::DX));
+            let res = hw.dos.dpmi.free_callback(&mut hw.mmu, segment, offset);
+            set_result(cpu, hw, res);
+        }
+        0x0305 => {
+            // DPMI 0.9+ - GET STATE SAVE/RESTORE ADDRESSES
+            // Return:
+            // AX = size of buffer in bytes required to save state
+            // BX:CX = address of real mode save/restore routine
+            // SI:(E)DI = address of protected mode save/restore routine
+            // the host keeps no state that needs saving, so the procedures just return
+            cpu.set_r16(R::AX, 0);
+            cpu.set_r16(R::BX, DPMI_ROM_SEG);
+            cpu.set_r16(R::CX, DPMI_ENTRY_SAVE_STATE);
+            cpu.set_r16(R::SI, DPMI_HOST_CODE);
+            set_client_offset(cpu, hw, R::DI, u32::from(DPMI_ENTRY_SAVE_STATE));
+            set_result(cpu, hw, Ok(()));
+        }
+        0x0400 => {
+            // DPMI 0.9+ - GET VERSION
+            // Return:
+            // AH = major version, AL = minor version
+            // BX = flags: bit 0 32-bit host, bit 1 real mode is virtual 86 mode
+            //             bit 2 virtual memory supported
+            // CL = processor type (02h 80286, 03h 80386, 04h 80486)
+            // DH = current value of virtual master PIC base interrupt
+            // DL = current value of virtual slave PIC base interrupt
+            cpu.set_r16(R::AX, 0x005A);
+            cpu.set_r16(R::BX, 0x0002);
+            cpu.set_r8(R::CL, processor_type(cpu));
+            cpu.set_r8(R::DH, 0x08);
+            cpu.set_r8(R::DL, 0x70);
+            set_result(cpu, hw, Ok(()));
+        }
+        0x0500 => {
+            // DPMI 0.9+ - GET FREE MEMORY INFORMATION
+            // ES:(E)DI -> buffer for 30h bytes of memory information
+            // values not supported by the host are FFFFFFFFh
+            let addr = buffer_address(cpu, hw);
+            let largest = hw.dos.dpmi.largest_free_memory(&hw.mmu);
+            let free_pages = hw.dos.dpmi.free_memory_size(&hw.mmu) / 0x1000;
+            let info = [
+                largest,        // largest available free block in bytes
+                largest / 0x1000, // maximum unlocked page allocation
+                largest / 0x1000, // maximum locked page allocation
+                0xFFFF_FFFF,    // linear address space size in pages
+                0xFFFF_FFFF,    // total number of unlocked pages
+                free_pages,     // total number of free pages
+                0xFFFF_FFFF,    // total number of physical pages
+                0xFFFF_FFFF,    // free linear address space in pages
+                0xFFFF_FFFF,    // size of paging file/partition in pages
+                0xFFFF_FFFF, 0xFFFF_FFFF, 0xFFFF_FFFF, // reserved
+            ];
+            for (i, val) in info.iter().enumerate() {
+                hw.mmu.write_linear_u32(addr + i as u32 * 4, *val);
+            }
+            set_result(cpu, hw, Ok(()));
+        }
+        0x0501 => {
+            // DPMI 0.9+ - ALLOCATE MEMORY BLOCK
+            // BX:CX = size of block in bytes
+            // Return:
+            // BX:CX = linear address of allocated memory block
+            // SI:DI = memory block handle
+            let size = u32::from(cpu.get_r16(R::BX)) << 16 | u32::from(cpu.get_r16(R::CX));
+            let res = hw.dos.dpmi.allocate_memory(&hw.mmu, size).map(|block| set_memory_block(cpu, block.base, block.handle));
+            set_result(cpu, hw, res);
+        }
+        0x0502 => {
+            // DPMI 0.9+ - FREE MEMORY BLOCK
+            // SI:DI = memory block handle
+            let handle = u32::from(cpu.get_r16(R::SI)) << 16 | u32::from(cpu.get_r16(R::DI));
+            let res = hw.dos.dpmi.free_memory(handle);
+            set_result(cpu, hw, res);
+        }
+        0x0503 => {
+            // DPMI 0.9+ - RESIZE MEMORY BLOCK
+            // BX:CX = new size in bytes
+            // SI:DI = memory block handle
+            // Return:
+            // BX:CX = new linear address
+            // SI:DI = new handle
+            let size = u32::from(cpu.get_r16(R::BX)) << 16 | u32::from(cpu.get_r16(R::CX));
+            let handle = u32::from(cpu.get_r16(R::SI)) << 16 | u32::from(cpu.get_r16(R::DI));
+            let res = hw.dos.dpmi.resize_memory(&mut hw.mmu, handle, size).map(|block| set_memory_block(cpu, block.base, block.handle));
+            set_result(cpu, hw, res);
+        }
+        0x0600...0x0603 => {
+            // DPMI 0.9+ - LOCK LINEAR REGION (0600h), UNLOCK LINEAR REGION (0601h)
+            // MARK REAL MODE REGION AS PAGEABLE (0602h), RELOCK REAL MODE REGION (0603h)
+            // there is no virtual memory, all memory is locked
+            set_result(cpu, hw, Ok(()));
+        }
+        0x0604 => {
+            // DPMI 0.9+ - GET PAGE SIZE
+            // Return:
+            // BX:CX = page size in bytes
+            cpu.set_r16(R::BX, 0);
+            cpu.set_r16(R::CX, 0x1000);
+            set_result(cpu, hw, Ok(()));
+        }
+        0x0800 => {
+            // DPMI 0.9+ - PHYSICAL ADDRESS MAPPING
+            // BX:CX = physical address
+            // SI:DI = size in bytes
+            // Return:
+            // BX:CX = linear address that can be used to access the physical memory
+            // the host does not page, linear addresses are physical
+            set_result(cpu, hw, Ok(()));
+        }
+        0x0900...0x0902 => {
+            // DPMI 0.9+ - GET AND DISABLE VIRTUAL INTERRUPT STATE (0900h)
+            // DPMI 0.9+ - GET AND ENABLE VIRTUAL INTERRUPT STATE (0901h)
+            // DPMI 0.9+ - GET VIRTUAL INTERRUPT STATE (0902h)
+            // Return:
+            // AL = 00h if virtual interrupts were disabled, 01h if enabled
+            // the virtual interrupt flag is the IF of the client, in the interrupt frame
+            let ax = cpu.get_r16(R::AX);
+            let addr = hw.bios.flags_address.value();
+            let flags = hw.mmu.memory.borrow().read_u16(addr);
+            cpu.set_r8(R::AL, if flags & FLAG_IF != 0 { 1 } else { 0 });
+            match ax {
+                0x0900 => hw.bios.set_flag(&mut hw.mmu, FLAG_IF, false),
+                0x0901 => hw.bios.set_flag(&mut hw.mmu, FLAG_IF, true),
+                _ => {}
+            }
+            hw.bios.set_flag(&mut hw.mmu, FLAG_CF, false);
+        }
+        _ => {
+            println!("int31 error: unsupported function ax={:04X}", cpu.get_r16(R::AX));
+            set_result(cpu, hw, Err(DpmiError::UnsupportedFunction));
+        }
+    }
+}
+
+/// sets CF in the interrupt frame, and AX to the error code on errors
+fn set_result(cpu: &mut CPU, hw: &mut Hardware, res: Result<(), DpmiError>) {
+    match res {
+        Ok(()) => hw.bios.set_flag(&mut hw.mmu, FLAG_CF, false),
+        Err(e) => {
+            cpu.set_r16(R::AX, e.code());
+            hw.bios.set_flag(&mut hw.mmu, FLAG_CF, true);
+        }
+    }
+}
+
+fn set_dos_error(cpu: &mut CPU, hw: &mut Hardware, e: DosError) {
+    cpu.set_r16(R::AX, e.code());
+    hw.bios.set_flag(&mut hw.mmu, FLAG_CF, true);
+}
+
+fn set_memory_block(cpu: &mut CPU, base: u32, handle: u32) {
+    cpu.set_r16(R::BX, (base >> 16) as u16);
+    cpu.set_r16(R::CX, base as u16);
+    cpu.set_r16(R::SI, (handle >> 16) as u16);
+    cpu.set_r16(R::DI, handle as u16);
+}
+
+/// linear address of the buffer at ES:(E)DI
+fn buffer_address(cpu: &CPU, hw: &Hardware) -> u32 {
+    cpu.segment_base(R::ES).wrapping_add(client_offset(cpu, hw, R::DI))
+}
+
+/// changes the descriptor of the selector in BX, and reloads the segment registers holding it
+fn update_descriptor<F>(cpu: &mut CPU, hw: &mut Hardware, update: F)
+    where F: FnOnce(&mut Descriptor) -> Result<(), DpmiError>
+{
+    let selector = cpu.get_r16(R::BX);
+    let res = hw.dos.dpmi.descriptor(&hw.mmu, selector).and_then(|mut desc| {
+        update(&mut desc)?;
+        hw.dos.dpmi.set_descriptor(&mut hw.mmu, selector, desc)
+    });
+    if res.is_ok() {
+        reload_segments(cpu, hw, selector);
+    }
+    set_result(cpu, hw, res);
+}
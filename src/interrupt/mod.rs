@@ -1,6 +1,10 @@
+pub mod int08;
+pub mod int09;
 pub mod int10;
 pub mod int13;
 pub mod int16;
 pub mod int1a;
 pub mod int21;
+pub mod int2f;
+pub mod int31;
 pub mod int33;
@@ -0,0 +1,240 @@
+// Keyboard controller (8042)
+// https://wiki.osdev.org/%228042%22_PS/2_Controller
+//
+// Scancodes from the keyboard are queued and presented one at a time in
+// the output buffer (port 0x60). IRQ 1 is requested each time the output
+// buffer is filled.
+
+use std::collections::VecDeque;
+
+#[cfg(test)]
+#[path = "./keyboard_test.rs"]
+mod keyboard_test;
+
+// status register bits, port 0x64
+const STATUS_OUTPUT_FULL: u8 = 0b0000_0001;
+const STATUS_SYSTEM_FLAG: u8 = 0b0000_0100;
+const STATUS_COMMAND: u8     = 0b0000_1000; // last write was to port 0x64
+const STATUS_UNLOCKED: u8    = 0b0001_0000; // keyboard not inhibited
+
+// command byte bits
+const COMMAND_BYTE_IRQ1: u8     = 0b0000_0001; // enable IRQ 1 on output buffer full
+const COMMAND_BYTE_SYSTEM: u8   = 0b0000_0100;
+const COMMAND_BYTE_DISABLE: u8  = 0b0001_0000; // disable keyboard
+
+// keyboard responses
+const KEYBOARD_ACK: u8 = 0xFA;
+const KEYBOARD_SELF_TEST_PASSED: u8 = 0xAA;
+
+/// size of the keyboard's internal buffer
+const MAX_QUEUE: usize = 16;
+
+#[derive(Clone)]
+pub struct Keyboard {
+    /// scancodes waiting for the output buffer
+    queue: VecDeque<u8>,
+
+    /// current value of the output buffer
+    output: u8,
+    output_full: bool,
+
+    command_byte: u8,
+
+    /// last command written to port 0x64
+    last_write_was_command: bool,
+
+    /// controller command awaiting a data byte on port 0x60
+    pending_controller_command: Option<u8>,
+
+    /// keyboard command awaiting a data byte on port 0x60
+    pending_keyboard_command: Option<u8>,
+
+    /// keyboard scanning is enabled
+    scanning: bool,
+
+    /// an IRQ 1 should be raised
+    irq: bool,
+
+    /// keyboard LED state as set by command EDh
+    pub leds: u8,
+}
+
+impl Keyboard {
+    pub fn default() -> Self {
+        Keyboard {
+            queue: VecDeque::new(),
+            output: 0,
+            output_full: false,
+            command_byte: COMMAND_BYTE_IRQ1 | COMMAND_BYTE_SYSTEM,
+            last_write_was_command: false,
+            pending_controller_command: None,
+            pending_keyboard_command: None,
+            scanning: true,
+            irq: false,
+            leds: 0,
+        }
+    }
+
+    /// a key was pressed. scancode is a scancode set 1 make code
+    pub fn key_down(&mut self, scancode: u8) {
+        self.queue_scancode(scancode & 0x7F);
+    }
+
+    /// a key was released. scancode is a scancode set 1 make code
+    pub fn key_up(&mut self, scancode: u8) {
+        self.queue_scancode(scancode | 0x80);
+    }
+
+    /// queues a raw byte from the keyboard, such as a E0h prefix
+    pub fn queue_scancode(&mut self, val: u8) {
+        if !self.scanning {
+            return;
+        }
+        if self.queue.len() >= MAX_QUEUE {
+            println!("keyboard: buffer overflow, dropping {:02X}", val);
+            return;
+        }
+        self.queue.push_back(val);
+        self.fill_output_buffer();
+    }
+
+    /// returns true once for each time the output buffer was filled with IRQ 1 enabled
+    pub fn take_irq(&mut self) -> bool {
+        let irq = self.irq;
+        self.irq = false;
+        irq
+    }
+
+    /// io read of port 0060
+    pub fn read_data(&mut self) -> u8 {
+        let val = self.output;
+        self.output_full = false;
+        self.fill_output_buffer();
+        val
+    }
+
+    /// io read of port 0064
+    pub fn read_status(&self) -> u8 {
+        let mut status = STATUS_UNLOCKED;
+        if self.output_full {
+            status |= STATUS_OUTPUT_FULL;
+        }
+        if self.command_byte & COMMAND_BYTE_SYSTEM != 0 {
+            status |= STATUS_SYSTEM_FLAG;
+        }
+        if self.last_write_was_command {
+            status |= STATUS_COMMAND;
+        }
+        status
+    }
+
+    /// io write of port 0060
+    pub fn write_data(&mut self, val: u8) {
+        self.last_write_was_command = false;
+        if let Some(cmd) = self.pending_controller_command.take() {
+            match cmd {
+                0x60 => {
+                    // write command byte
+                    self.command_byte = val;
+                    self.fill_output_buffer();
+                }
+                0xD1 => {
+                    // write output port. bit 1 = A20 gate
+                }
+                _ => unreachable!(),
+            }
+            return;
+        }
+        if let Some(cmd) = self.pending_keyboard_command.take() {
+            match cmd {
+                0xED => self.leds = val & 0b111, // set LEDs
+                0xF3 => {}                       // set typematic rate/delay
+                _ => unreachable!(),
+            }
+            self.respond(KEYBOARD_ACK);
+            return;
+        }
+
+        // keyboard commands
+        match val {
+            0xED | 0xF3 => {
+                self.pending_keyboard_command = Some(val);
+                self.respond(KEYBOARD_ACK);
+            }
+            0xEE => self.respond(0xEE), // echo
+            0xF2 => {
+                // identify keyboard: MF2 keyboard with translation
+                self.respond(KEYBOARD_ACK);
+                self.respond(0xAB);
+                self.respond(0x41);
+            }
+            0xF4 => {
+                self.scanning = true;
+                self.respond(KEYBOARD_ACK);
+            }
+            0xF5 => {
+                self.scanning = false;
+                self.queue.clear();
+                self.respond(KEYBOARD_ACK);
+            }
+            0xFF => {
+                self.queue.clear();
+                self.scanning = true;
+                self.respond(KEYBOARD_ACK);
+                self.respond(KEYBOARD_SELF_TEST_PASSED);
+            }
+            _ => {
+                println!("keyboard: unhandled keyboard command {:02X}", val);
+                self.respond(KEYBOARD_ACK);
+            }
+        }
+    }
+
+    /// io write of port 0064
+    pub fn write_command(&mut self, val: u8) {
+        self.last_write_was_command = true;
+        match val {
+            0x20 => {
+                // read command byte
+                let v = self.command_byte;
+                self.set_output(v);
+            }
+            0x60 | 0xD1 => self.pending_controller_command = Some(val),
+            0xAA => self.set_output(0x55), // self test passed
+            0xAB => self.set_output(0x00), // keyboard interface test passed
+            0xAD => self.command_byte |= COMMAND_BYTE_DISABLE,
+            0xAE => {
+                self.command_byte &= !COMMAND_BYTE_DISABLE;
+                self.fill_output_buffer();
+            }
+            0xD0 => self.set_output(0x02), // read output port, A20 enabled
+            0xFE => println!("keyboard: XXX cpu reset requested"),
+            _ => println!("keyboard: unhandled controller command {:02X}", val),
+        }
+    }
+
+    /// queues a response byte from the keyboard
+    fn respond(&mut self, val: u8) {
+        self.queue.push_back(val);
+        self.fill_output_buffer();
+    }
+
+    /// places a controller response directly in the output buffer
+    fn set_output(&mut self, val: u8) {
+        self.output = val;
+        self.output_full = true;
+    }
+
+    fn fill_output_buffer(&mut self) {
+        if self.output_full || self.command_byte & COMMAND_BYTE_DISABLE != 0 {
+            return;
+        }
+        if let Some(val) = self.queue.pop_front() {
+            self.output = val;
+            self.output_full = true;
+            if self.command_byte & COMMAND_BYTE_IRQ1 != 0 {
+                self.irq = true;
+            }
+        }
+    }
+}
@@ -0,0 +1,122 @@
+use machine::Machine;
+use keyboard::Keyboard;
+use cpu::R;
+use bios::BIOS;
+
+#[test]
+fn can_queue_scancodes_in_output_buffer() {
+    let mut kbd = Keyboard::default();
+    assert_eq!(0x00, kbd.read_status() & 0x01);
+
+    kbd.key_down(0x1E); // A
+    kbd.key_up(0x1E);
+    assert_eq!(true, kbd.take_irq());
+    assert_eq!(false, kbd.take_irq());
+    assert_eq!(0x01, kbd.read_status() & 0x01);
+
+    assert_eq!(0x1E, kbd.read_data());
+    assert_eq!(true, kbd.take_irq());
+    assert_eq!(0x9E, kbd.read_data());
+    assert_eq!(false, kbd.take_irq());
+    assert_eq!(0x00, kbd.read_status() & 0x01);
+}
+
+#[test]
+fn can_handle_controller_commands() {
+    let mut kbd = Keyboard::default();
+    kbd.write_command(0xAA); // self test
+    assert_eq!(0x55, kbd.read_data());
+
+    kbd.write_command(0x60); // write command byte
+    kbd.write_data(0x00);    // disable IRQ 1
+    kbd.key_down(0x1E);
+    assert_eq!(false, kbd.take_irq());
+    assert_eq!(0x1E, kbd.read_data());
+
+    kbd.write_data(0xED);    // set LEDs
+    assert_eq!(0xFA, kbd.read_data());
+    kbd.write_data(0x04);
+    assert_eq!(0xFA, kbd.read_data());
+    assert_eq!(0x04, kbd.leds);
+}
+
+#[test]
+fn can_read_keystrokes_with_int16() {
+    let mut machine = Machine::default();
+    let code: Vec<u8> = vec![
+        0xB4, 0x01,       // mov ah,0x01    ; check for keystroke
+        0xCD, 0x16,       // int 0x16
+        0xB4, 0x00,       // mov ah,0x00    ; get keystroke
+        0xCD, 0x16,       // int 0x16
+        0xB4, 0x01,       // mov ah,0x01    ; check for keystroke
+        0xCD, 0x16,       // int 0x16
+    ];
+    machine.load_executable(&code);
+    machine.key_down(0x2A); // left shift
+    machine.key_down(0x1E); // A
+    machine.key_up(0x1E);
+
+    // each scancode is delivered by IRQ 1 to INT 09h
+    machine.execute_instructions(3);
+    assert_eq!(0x0100, machine.cpu.regs.ip);
+    assert_eq!(0x02, machine.hw.mmu.read_u8(BIOS::DATA_SEG, BIOS::DATA_KBD_FLAGS1));
+
+    machine.execute_instructions(3);
+    assert_eq!(false, machine.cpu.regs.flags.zero);
+    assert_eq!(0x1E41, machine.cpu.get_r16(R::AX));
+
+    machine.execute_instructions(3);
+    assert_eq!(0x1E41, machine.cpu.get_r16(R::AX));
+
+    machine.execute_instructions(3);
+    assert_eq!(true, machine.cpu.regs.flags.zero);
+}
+
+#[test]
+fn can_wait_for_keystroke_with_int16() {
+    let mut machine = Machine::default();
+    let code: Vec<u8> = vec![
+        0xB4, 0x10,       // mov ah,0x10    ; get enhanced keystroke
+        0xCD, 0x16,       // int 0x16
+    ];
+    machine.load_executable(&code);
+    machine.execute_instructions(3);
+    // no key available, the handler waits with interrupts enabled
+    assert_eq!(true, machine.cpu.halted);
+    assert_eq!(true, machine.cpu.regs.flags.interrupt);
+    assert_eq!(0xF000, machine.cpu.get_r16(R::CS));
+    assert_eq!(0x0016, machine.cpu.regs.ip);
+
+    machine.key_down(0x1E); // A
+
+    // IRQ 1 runs INT 09h, which returns to the waiting handler
+    machine.execute_instructions(3);
+    assert_eq!(false, machine.cpu.halted);
+    assert_eq!(0x0104, machine.cpu.regs.ip);
+    assert_eq!(0x1E61, machine.cpu.get_r16(R::AX));
+}
+
+#[test]
+fn can_wait_for_keystroke_with_chained_int16() {
+    let mut machine = Machine::default();
+    let code: Vec<u8> = vec![
+        0x31, 0xC0,                   // xor ax,ax
+        0x8E, 0xC0,                   // mov es,ax
+        0xB4, 0x00,                   // mov ah,0x00    ; get keystroke
+        0x9C,                         // pushf
+        0x26, 0xFF, 0x1E, 0x58, 0x00, // call far [es:0x0058]
+    ];
+    machine.load_executable(&code);
+    machine.execute_instructions(6);
+    assert_eq!(true, machine.cpu.halted);
+
+    // the return address of the caller is left alone while waiting
+    let ss = machine.cpu.get_r16(R::SS);
+    let sp = machine.cpu.get_r16(R::SP);
+    assert_eq!(0x010C, machine.hw.mmu.read_u16(ss, sp));
+
+    machine.key_down(0x1E); // A
+    machine.execute_instructions(3);
+    assert_eq!(0x010C, machine.cpu.regs.ip);
+    assert_eq!(0x1E61, machine.cpu.get_r16(R::AX));
+}
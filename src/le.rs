@@ -0,0 +1,413 @@
+// LE and LX linear executables, as used by DOS extenders like DOS/4GW
+//
+// The file starts with a MZ stub, usually the DOS extender itself. The new header
+// located at offset 0x3C describes objects (segments) made of pages, and per page
+// fixups that are applied when the objects are placed in memory.
+// http://www.textfiles.com/programming/FORMATS/lxexe.txt
+
+use machine::LoadError;
+
+#[cfg(test)]
+#[path = "./le_test.rs"]
+mod le_test;
+
+const DEBUG_LE: bool = false;
+
+/// offset of the new header pointer in the MZ header
+const NEW_HEADER_POINTER: usize = 0x3C;
+
+/// size of the LE/LX header
+const HEADER_SIZE: usize = 0xC4;
+
+const OBJECT_ENTRY_SIZE: usize = 24;
+
+// object flags
+const OBJECT_EXECUTABLE: u32 = 0x0004;
+const OBJECT_BIG: u32 = 0x2000; // 32-bit default operand size
+
+// LX object page flags
+const LX_PAGE_LEGAL: u16 = 0x0000;
+const LX_PAGE_ZEROED: u16 = 0x0003;
+
+// fixup source flags
+const FIXUP_SOURCE_TYPE: u8 = 0x0F;
+const FIXUP_SOURCE_LIST: u8 = 0x20;
+
+// fixup target flags
+const FIXUP_TARGET_TYPE: u8 = 0x03;
+const FIXUP_ADDITIVE: u8 = 0x04;
+const FIXUP_TARGET_32: u8 = 0x10;
+const FIXUP_ADDITIVE_32: u8 = 0x20;
+const FIXUP_OBJECT_16: u8 = 0x40;
+
+/// what a fixup writes at its source location
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FixupKind {
+    Byte,
+    Selector16,
+    Pointer1616,
+    Offset16,
+    Pointer1632,
+    Offset32,
+    Relative32,
+}
+
+impl FixupKind {
+    fn from_u8(source: u8) -> Option<Self> {
+        match source & FIXUP_SOURCE_TYPE {
+            0x00 => Some(FixupKind::Byte),
+            0x02 => Some(FixupKind::Selector16),
+            0x03 => Some(FixupKind::Pointer1616),
+            0x05 => Some(FixupKind::Offset16),
+            0x06 => Some(FixupKind::Pointer1632),
+            0x07 => Some(FixupKind::Offset32),
+            0x08 => Some(FixupKind::Relative32),
+            _ => None,
+        }
+    }
+}
+
+/// a reference from a object to a offset in a object
+#[derive(Clone, Debug, PartialEq)]
+pub struct Fixup {
+    pub kind: FixupKind,
+
+    /// offset of the source in the object
+    pub source: i64,
+
+    /// target object index, 0-based
+    pub object: usize,
+
+    /// offset of the target in the object
+    pub offset: u32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LeObject {
+    pub virtual_size: u32,
+
+    /// preferred load address
+    pub base: u32,
+    pub flags: u32,
+
+    /// contents of the loaded pages, zero filled to the virtual size
+    pub data: Vec<u8>,
+    pub fixups: Vec<Fixup>,
+}
+
+impl LeObject {
+    pub fn is_code(&self) -> bool {
+        self.flags & OBJECT_EXECUTABLE != 0
+    }
+
+    /// the object uses 32-bit offsets
+    pub fn is_32bit(&self) -> bool {
+        self.flags & OBJECT_BIG != 0
+    }
+}
+
+#[derive(Debug)]
+pub struct LinearExecutable {
+    /// the LX variant, which differs in the object page table
+    pub lx: bool,
+    pub objects: Vec<LeObject>,
+
+    /// object index (0-based) and offset of the entry point and initial stack
+    pub eip_object: usize,
+    pub eip: u32,
+    pub esp_object: usize,
+    pub esp: u32,
+
+    /// object index of the automatic data object
+    pub auto_data_object: Option<usize>,
+}
+
+/// returns the offset of the LE or LX header if data is a MZ executable with one
+pub fn find_header(data: &[u8]) -> Option<usize> {
+    if data.len() < NEW_HEADER_POINTER + 4 || &data[0..2] != b"MZ" {
+        return None;
+    }
+    // the relocation table offset of new executables is 0x40 or above
+    if read_u16(data, 0x18) < 0x40 {
+        return None;
+    }
+    let offset = read_u32(data, NEW_HEADER_POINTER) as usize;
+    if offset < NEW_HEADER_POINTER + 4 || offset + 2 > data.len() {
+        return None;
+    }
+    match &data[offset..offset + 2] {
+        b"LE" | b"LX" => Some(offset),
+        _ => None,
+    }
+}
+
+impl LinearExecutable {
+    pub fn parse(data: &[u8]) -> Result<Self, LoadError> {
+        let hdr = find_header(data).ok_or_else(|| LoadError::InvalidHeader("no LE/LX header".to_owned()))?;
+        if data.len() < hdr + HEADER_SIZE {
+            return Err(LoadError::Truncated("LE header"));
+        }
+        let lx = &data[hdr..hdr + 2] == b"LX";
+        let h = |offset: usize| read_u32(data, hdr + offset);
+        if data[hdr + 2] != 0 || data[hdr + 3] != 0 {
+            return Err(LoadError::InvalidHeader("big endian LE".to_owned()));
+        }
+        let page_count = h(0x14) as usize;
+        let page_size = h(0x28) as usize;
+        let last_page_size_or_shift = h(0x2C);
+        let object_table = hdr + h(0x40) as usize;
+        let object_count = h(0x44) as usize;
+        let page_table = hdr + h(0x48) as usize;
+        let fixup_page_table = hdr + h(0x68) as usize;
+        let fixup_record_table = hdr + h(0x6C) as usize;
+        let data_pages = h(0x80) as usize;
+        if page_size == 0 {
+            return Err(LoadError::InvalidHeader("page size 0".to_owned()));
+        }
+
+        let object_number = |n: u32, what: &str| -> Result<usize, LoadError> {
+            if n == 0 || n as usize > object_count {
+                Err(LoadError::InvalidHeader(format!("bad {} object {}", what, n)))
+            } else {
+                Ok(n as usize - 1)
+            }
+        };
+        let eip_object = object_number(h(0x18), "entry")?;
+        let esp_object = object_number(h(0x20), "stack")?;
+        let auto_data_object = match h(0x94) {
+            0 => None,
+            n => Some(object_number(n, "automatic data")?),
+        };
+
+        if object_table + object_count * OBJECT_ENTRY_SIZE > data.len() {
+            return Err(LoadError::Truncated("object table"));
+        }
+        let page_entry_size = if lx { 8 } else { 4 };
+        if page_table + page_count * page_entry_size > data.len() {
+            return Err(LoadError::Truncated("object page table"));
+        }
+        if fixup_page_table + (page_count + 1) * 4 > data.len() {
+            return Err(LoadError::Truncated("fixup page table"));
+        }
+
+        let mut objects = Vec::with_capacity(object_count);
+        for i in 0..object_count {
+            let entry = object_table + i * OBJECT_ENTRY_SIZE;
+            let virtual_size = read_u32(data, entry);
+            let first_page = read_u32(data, entry + 12) as usize;
+            let pages = read_u32(data, entry + 16) as usize;
+            if pages > 0 && (first_page == 0 || first_page - 1 + pages > page_count) {
+                return Err(LoadError::InvalidHeader(format!("bad page range of object {}", i + 1)));
+            }
+            let size = (virtual_size as usize).max(pages * page_size);
+            if size > 0x0400_0000 {
+                return Err(LoadError::InvalidHeader(format!("object {} size {:08X}", i + 1, virtual_size)));
+            }
+            let mut obj = LeObject {
+                virtual_size,
+                base: read_u32(data, entry + 4),
+                flags: read_u32(data, entry + 8),
+                data: vec![0; size],
+                fixups: Vec::new(),
+            };
+
+            for p in 0..pages {
+                // object pages are 1-based
+                let page = first_page - 1 + p;
+                let dst = p * page_size;
+                let (offset, size) = if lx {
+                    let entry = page_table + page * 8;
+                    let flags = read_u16(data, entry + 6);
+                    match flags {
+                        LX_PAGE_LEGAL => {
+                            let offset = data_pages + ((read_u32(data, entry) as usize) << last_page_size_or_shift);
+                            (offset, read_u16(data, entry + 4) as usize)
+                        }
+                        LX_PAGE_ZEROED => (0, 0),
+                        _ => return Err(LoadError::Unsupported(format!("LX page type {:04X}", flags))),
+                    }
+                } else {
+                    let entry = page_table + page * 4;
+                    let number = (data[entry] as usize) << 16 | (data[entry + 1] as usize) << 8 | data[entry + 2] as usize;
+                    if number == 0 {
+                        (0, 0)
+                    } else {
+                        let size = if number == page_count { last_page_size_or_shift as usize } else { page_size };
+                        (data_pages + (number - 1) * page_size, size)
+                    }
+                };
+                let size = size.min(page_size);
+                if size > 0 {
+                    if offset + size > data.len() {
+                        return Err(LoadError::Truncated("object pages"));
+                    }
+                    obj.data[dst..dst + size].copy_from_slice(&data[offset..offset + size]);
+                }
+
+                let start = fixup_record_table + read_u32(data, fixup_page_table + page * 4) as usize;
+                let end = fixup_record_table + read_u32(data, fixup_page_table + page * 4 + 4) as usize;
+                if start > end || end > data.len() {
+                    return Err(LoadError::Truncated("fixup records"));
+                }
+                parse_fixups(&data[start..end], (p * page_size) as i64, object_count, &mut obj.fixups)?;
+            }
+            objects.push(obj);
+        }
+
+        if DEBUG_LE {
+            for (i, obj) in objects.iter().enumerate() {
+                println!("le: object {} base {:08X} size {:08X} flags {:04X}, {} fixups", i + 1, obj.base, obj.virtual_size, obj.flags, obj.fixups.len());
+            }
+        }
+        Ok(LinearExecutable {
+            lx,
+            objects,
+            eip_object,
+            eip: h(0x1C),
+            esp_object,
+            esp: h(0x24),
+            auto_data_object,
+        })
+    }
+
+    /// returns the contents of object index with the fixups applied. offsets in object i are
+    /// relocated by bases[i], and selectors of object i are selectors[i]
+    pub fn relocated_object(&self, index: usize, bases: &[u32], selectors: &[u16]) -> Vec<u8> {
+        let obj = &self.objects[index];
+        let mut data = obj.data.clone();
+        for fixup in &obj.fixups {
+            let target = bases[fixup.object].wrapping_add(fixup.offset);
+            let selector = selectors[fixup.object];
+            let mut bytes = Vec::with_capacity(6);
+            match fixup.kind {
+                FixupKind::Byte => bytes.push(target as u8),
+                FixupKind::Selector16 => push_u16(&mut bytes, selector),
+                FixupKind::Pointer1616 => {
+                    push_u16(&mut bytes, target as u16);
+                    push_u16(&mut bytes, selector);
+                }
+                FixupKind::Offset16 => push_u16(&mut bytes, target as u16),
+                FixupKind::Pointer1632 => {
+                    push_u32(&mut bytes, target);
+                    push_u16(&mut bytes, selector);
+                }
+                FixupKind::Offset32 => push_u32(&mut bytes, target),
+                FixupKind::Relative32 => {
+                    let next = bases[index].wrapping_add(fixup.source as u32).wrapping_add(4);
+                    push_u32(&mut bytes, target.wrapping_sub(next));
+                }
+            }
+            // sources crossing a page boundary are listed in both pages, only the part inside the object is written
+            for (i, b) in bytes.iter().enumerate() {
+                let pos = fixup.source + i as i64;
+                if pos >= 0 && (pos as usize) < data.len() {
+                    data[pos as usize] = *b;
+                }
+            }
+        }
+        data
+    }
+}
+
+/// parses the fixup records of the page at page_offset in the object
+fn parse_fixups(records: &[u8], page_offset: i64, object_count: usize, fixups: &mut Vec<Fixup>) -> Result<(), LoadError> {
+    let mut pos = 0;
+    while pos < records.len() {
+        let mut r = RecordReader { data: records, pos };
+        let source = r.u8()?;
+        let flags = r.u8()?;
+        let kind = FixupKind::from_u8(source).ok_or_else(|| LoadError::Unsupported(format!("fixup source type {:02X}", source)))?;
+        if source & 0x10 != 0 {
+            return Err(LoadError::Unsupported("16:16 alias fixups".to_owned()));
+        }
+        let list_count = if source & FIXUP_SOURCE_LIST != 0 { Some(r.u8()? as usize) } else { None };
+        let source_offset = if list_count.is_none() { Some(r.u16()? as i16) } else { None };
+        if flags & FIXUP_TARGET_TYPE != 0 {
+            // XXX imports and references through the entry table are not supported
+            return Err(LoadError::Unsupported(format!("fixup target type {}", flags & FIXUP_TARGET_TYPE)));
+        }
+        let object = if flags & FIXUP_OBJECT_16 != 0 { r.u16()? as usize } else { r.u8()? as usize };
+        if object == 0 || object > object_count {
+            return Err(LoadError::InvalidHeader(format!("fixup to object {}", object)));
+        }
+        let mut offset = if kind == FixupKind::Selector16 {
+            0
+        } else if flags & FIXUP_TARGET_32 != 0 {
+            r.u32()?
+        } else {
+            u32::from(r.u16()?)
+        };
+        if flags & FIXUP_ADDITIVE != 0 {
+            let additive = if flags & FIXUP_ADDITIVE_32 != 0 { r.u32()? } else { u32::from(r.u16()?) };
+            offset = offset.wrapping_add(additive);
+        }
+        let mut sources = Vec::new();
+        match list_count {
+            Some(count) => for _ in 0..count {
+                sources.push(r.u16()? as i16);
+            },
+            None => sources.extend(source_offset),
+        }
+        for src in sources {
+            fixups.push(Fixup {
+                kind,
+                source: page_offset + i64::from(src),
+                object: object - 1,
+                offset,
+            });
+        }
+        pos = r.pos;
+    }
+    Ok(())
+}
+
+struct RecordReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> RecordReader<'a> {
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], LoadError> {
+        if self.pos + n > self.data.len() {
+            return Err(LoadError::Truncated("fixup record"));
+        }
+        let res = &self.data[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(res)
+    }
+
+    fn u8(&mut self) -> Result<u8, LoadError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, LoadError> {
+        Ok(read_u16(self.bytes(2)?, 0))
+    }
+
+    fn u32(&mut self) -> Result<u32, LoadError> {
+        Ok(read_u32(self.bytes(4)?, 0))
+    }
+}
+
+/// reads a little endian word, 0 if outside of data
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    if offset + 2 > data.len() {
+        return 0;
+    }
+    u16::from(data[offset]) | u16::from(data[offset + 1]) << 8
+}
+
+/// reads a little endian dword, 0 if outside of data
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from(read_u16(data, offset)) | u32::from(read_u16(data, offset + 2)) << 16
+}
+
+fn push_u16(bytes: &mut Vec<u8>, val: u16) {
+    bytes.push(val as u8);
+    bytes.push((val >> 8) as u8);
+}
+
+fn push_u32(bytes: &mut Vec<u8>, val: u32) {
+    push_u16(bytes, val as u16);
+    push_u16(bytes, (val >> 16) as u16);
+}
@@ -0,0 +1,197 @@
+use std::fs;
+
+use tempdir::TempDir;
+
+use machine::Machine;
+use cpu::R;
+use le::{LinearExecutable, Fixup, FixupKind, find_header};
+
+fn put_u16(data: &mut [u8], offset: usize, val: u16) {
+    data[offset] = val as u8;
+    data[offset + 1] = (val >> 8) as u8;
+}
+
+fn put_u32(data: &mut [u8], offset: usize, val: u32) {
+    put_u16(data, offset, val as u16);
+    put_u16(data, offset + 2, (val >> 16) as u16);
+}
+
+/// builds a LE executable with a code object and a data object, with object flags `extra_flags` added
+fn build_le(extra_flags: u32) -> Vec<u8> {
+    let mut data = vec![0u8; 0x1210];
+    data[0..2].copy_from_slice(b"MZ");
+    put_u16(&mut data, 0x18, 0x40);
+    put_u32(&mut data, 0x3C, 0x40);
+
+    let hdr = 0x40;
+    data[hdr..hdr + 2].copy_from_slice(b"LE");
+    put_u32(&mut data, hdr + 0x14, 2);      // pages
+    put_u32(&mut data, hdr + 0x18, 1);      // EIP object
+    put_u32(&mut data, hdr + 0x1C, 0);      // EIP
+    put_u32(&mut data, hdr + 0x20, 2);      // ESP object
+    put_u32(&mut data, hdr + 0x24, 0x100);  // ESP
+    put_u32(&mut data, hdr + 0x28, 0x1000); // page size
+    put_u32(&mut data, hdr + 0x2C, 0x10);   // bytes on last page
+    put_u32(&mut data, hdr + 0x40, 0xC4);   // object table
+    put_u32(&mut data, hdr + 0x44, 2);      // objects
+    put_u32(&mut data, hdr + 0x48, 0xF4);   // object page table
+    put_u32(&mut data, hdr + 0x68, 0xFC);   // fixup page table
+    put_u32(&mut data, hdr + 0x6C, 0x108);  // fixup record table
+    put_u32(&mut data, hdr + 0x80, 0x200);  // data pages
+
+    // object table: virtual size, base, flags, first page, pages
+    let objects = hdr + 0xC4;
+    for (i, &(size, base, flags)) in [(8, 0x1_0000, 0x45), (0x100, 0x2_0000, 0x43)].iter().enumerate() {
+        let entry = objects + i * 24;
+        put_u32(&mut data, entry, size);
+        put_u32(&mut data, entry + 4, base);
+        put_u32(&mut data, entry + 8, flags | extra_flags);
+        put_u32(&mut data, entry + 12, i as u32 + 1);
+        put_u32(&mut data, entry + 16, 1);
+    }
+
+    // object page table
+    data[hdr + 0xF4 + 2] = 1;
+    data[hdr + 0xF4 + 6] = 2;
+
+    // fixup page table and records
+    put_u32(&mut data, hdr + 0xFC + 4, 12);
+    put_u32(&mut data, hdr + 0xFC + 8, 12);
+    data[hdr + 0x108..hdr + 0x108 + 12].copy_from_slice(&[
+        0x02, 0x00, 0x01, 0x00, 0x02,             // selector of object 2 at 0001
+        0x05, 0x00, 0x06, 0x00, 0x02, 0x04, 0x00, // offset 0004 of object 2 at 0006
+    ]);
+
+    // data pages
+    data[0x200..0x208].copy_from_slice(&[
+        0xB8, 0x00, 0x00,       // mov ax,0x0
+        0x8E, 0xD8,             // mov ds,ax
+        0xA1, 0x00, 0x00,       // mov ax,[0x0]
+    ]);
+    data[0x1200..0x1206].copy_from_slice(&[0x11, 0x11, 0x22, 0x22, 0x34, 0x12]);
+    data
+}
+
+#[test]
+fn can_parse_le_executables() {
+    let data = build_le(0);
+    assert_eq!(Some(0x40), find_header(&data));
+    assert_eq!(None, find_header(&data[..0x40]));
+
+    let le = LinearExecutable::parse(&data).unwrap();
+    assert_eq!(false, le.lx);
+    assert_eq!(2, le.objects.len());
+    assert_eq!((0, 0, 1, 0x100), (le.eip_object, le.eip, le.esp_object, le.esp));
+    assert_eq!(true, le.objects[0].is_code());
+    assert_eq!(false, le.objects[1].is_code());
+    assert_eq!(0x1000, le.objects[1].data.len());
+    assert_eq!(&[0x11, 0x11, 0x22, 0x22, 0x34, 0x12, 0x00], &le.objects[1].data[..7]);
+    assert_eq!(vec![
+        Fixup { kind: FixupKind::Selector16, source: 1, object: 1, offset: 0 },
+        Fixup { kind: FixupKind::Offset16, source: 6, object: 1, offset: 4 },
+    ], le.objects[0].fixups);
+
+    let code = le.relocated_object(0, &[0, 0x100], &[0x0F, 0x17]);
+    assert_eq!(&[0xB8, 0x17, 0x00, 0x8E, 0xD8, 0xA1, 0x04, 0x01], &code[..8]);
+}
+
+#[test]
+fn can_run_16bit_le_executables() {
+    let mut machine = Machine::default();
+    machine.load_executable(&build_le(0));
+    assert_eq!(false, machine.cpu.fatal_error);
+    assert_eq!(true, machine.cpu.protected_mode());
+    assert_eq!(3, machine.cpu.cpl());
+    assert_eq!(0x0012_0000, machine.cpu.segment_base(R::CS));
+    assert_eq!(0x0012_1000, machine.cpu.segment_base(R::SS));
+    assert_eq!(0x0100, machine.cpu.get_r16(R::SP));
+
+    machine.execute_instructions(3);
+    assert_eq!(0x0012_1000, machine.cpu.segment_base(R::DS));
+    assert_eq!(0x1234, machine.cpu.get_r16(R::AX));
+}
+
+#[test]
+fn can_run_32bit_le_executables() {
+    // 32-bit objects are relocated to their linear address in the flat model
+    let mut data = build_le(0x2000);
+    data[0x40 + 0x108..0x40 + 0x108 + 12].copy_from_slice(&[
+        0x02, 0x00, 0x02, 0x00, 0x02,             // selector of object 2 at 0002
+        0x07, 0x00, 0x07, 0x00, 0x02, 0x04, 0x00, // 32-bit offset 0004 of object 2 at 0007
+    ]);
+    data[0x200..0x221].copy_from_slice(&[
+        0x66, 0xB8, 0x00, 0x00,       // mov ax,0x0
+        0x8E, 0xD8,                   // mov ds,ax
+        0xA1, 0x00, 0x00, 0x00, 0x00, // mov eax,[0x0]
+        0x89, 0xC7,                   // mov edi,eax
+        0xE8, 0x0B, 0x00, 0x00, 0x00, // call 0x1d
+        0x66, 0x8C, 0xDB,             // mov bx,ds
+        0x66, 0xB8, 0x06, 0x00,       // mov ax,0x6
+        0xCD, 0x31,                   // int 0x31
+        0xEB, 0xFE,                   // jmp short 0x1b
+        0x8B, 0x34, 0x24,             // mov esi,[esp]
+        0xC3,                         // ret
+    ]);
+    let mut machine = Machine::default();
+    machine.load_executable(&data);
+    assert_eq!(false, machine.cpu.fatal_error);
+    assert_eq!(true, machine.hw.dos.dpmi.client32);
+    assert_eq!(3, machine.cpu.cpl());
+    assert_eq!(0, machine.cpu.segment_base(R::CS));
+    assert_eq!(0x0012_0000, machine.cpu.regs.ip);
+    assert_eq!(0x0012_1100, machine.cpu.get_r32(R::ESP));
+    assert_eq!(0x0012_1004, machine.hw.mmu.memory.borrow().read_u32(0x0012_0007));
+
+    // the call pushes EIP on the 32-bit stack
+    machine.execute_instructions(6);
+    assert_eq!(0x0012_0020, machine.cpu.regs.ip);
+    assert_eq!(0x0012_10FC, machine.cpu.get_r32(R::ESP));
+    assert_eq!(0x0000_1234, machine.cpu.get_r32(R::EDI));
+    assert_eq!(0x0012_0012, machine.cpu.get_r32(R::ESI));
+
+    // INT 31h goes through a 386 gate and returns with IRETD
+    machine.execute_instructions(6);
+    assert_eq!(false, machine.cpu.fatal_error);
+    assert_eq!(0x0012_001B, machine.cpu.regs.ip);
+    assert_eq!(0x0012_1100, machine.cpu.get_r32(R::ESP));
+    assert_eq!(false, machine.cpu.regs.flags.carry);
+    assert_eq!((0, 0), (machine.cpu.get_r16(R::CX), machine.cpu.get_r16(R::DX)));
+}
+
+#[test]
+fn can_write_files_from_32bit_le_executables() {
+    // DOS calls of 32-bit clients take buffers at DS:EDX, here in the data object above 64k
+    let tmp_dir = TempDir::new("dos").unwrap();
+    let mut data = build_le(0x2000);
+    put_u32(&mut data, 0x40 + 0xFC + 4, 0);
+    put_u32(&mut data, 0x40 + 0xFC + 8, 0);
+    data[0x200..0x21E].copy_from_slice(&[
+        0xBA, 0x08, 0x10, 0x12, 0x00, // mov edx,0x121008
+        0x31, 0xC9,                   // xor ecx,ecx
+        0xB4, 0x3C,                   // mov ah,0x3c      ; create
+        0xCD, 0x21,                   // int 0x21
+        0x66, 0x89, 0xC3,             // mov bx,ax
+        0xBA, 0x00, 0x10, 0x12, 0x00, // mov edx,0x121000
+        0xB9, 0x06, 0x00, 0x00, 0x00, // mov ecx,0x6
+        0xB4, 0x40,                   // mov ah,0x40      ; write
+        0xCD, 0x21,                   // int 0x21
+        0xEB, 0xFE,                   // jmp short 0x1c
+    ]);
+    data[0x1208..0x1210].copy_from_slice(b"OUT.DAT\0");
+
+    let mut machine = Machine::default();
+    machine.mount(tmp_dir.path());
+    machine.load_executable(&data);
+    assert_eq!(true, machine.hw.dos.dpmi.client32);
+
+    machine.execute_instructions(5);
+    assert_eq!(false, machine.cpu.regs.flags.carry);
+    assert_eq!(0x0005, machine.cpu.get_r16(R::AX));
+
+    machine.execute_instructions(6);
+    assert_eq!(false, machine.cpu.fatal_error);
+    assert_eq!(false, machine.cpu.regs.flags.carry);
+    assert_eq!(0x0012_001C, machine.cpu.regs.ip);
+    assert_eq!(6, machine.cpu.get_r32(R::EAX));
+    assert_eq!(vec![0x11, 0x11, 0x22, 0x22, 0x34, 0x12], fs::read(tmp_dir.path().join("OUT.DAT")).unwrap());
+}
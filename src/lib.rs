@@ -29,11 +29,14 @@ pub mod memory;
 pub mod gpu;
 pub mod pic;
 pub mod pit;
+pub mod keyboard;
 pub mod cmos;
 pub mod bios;
+pub mod dos;
 pub mod codepage;
 pub mod tools;
 pub mod hex;
 pub mod ndisasm;
+pub mod le;
 
 mod interrupt;
@@ -1,11 +1,23 @@
-use bincode::deserialize;
+use std::path::Path;
+use std::collections::BTreeMap;
 
-use cpu::{CPU, Op, Invalid, R, RegisterSnapshot, Segment, OperandSize};
+use bincode::{deserialize, Error as BincodeError};
+use time;
+
+use bios::BIOS;
+use cpu::{CPU, CpuModel, Engine, Op, R, RegisterSnapshot, Segment, OperandSize};
+use dos::{write_psp, write_environment, handle_dpmi_entry, handle_dpmi_interrupt, enter_protected_mode, DpmiError,
+    DPMI_ROM_SEG, DPMI_ENTRY_MODE_SWITCH, DPMI_HOST_DATA_PARAGRAPHS};
 use gpu::GPU;
 use hardware::Hardware;
-use hex::hex_bytes;
-use memory::MMU;
-use ndisasm::{ndisasm_bytes, ndisasm_first_instr};
+use le::{LinearExecutable, find_header};
+use memory::{MMU, MemoryAddress};
+use ndisasm::ndisasm_bytes;
+use pit::PIT_HZ;
+
+#[cfg(test)]
+#[path = "./machine_test.rs"]
+mod machine_test;
 
 #[derive(Deserialize, Debug)]
 struct ExeHeader {
@@ -34,11 +46,133 @@ struct ExeReloc {
 struct Exe {
     header: ExeHeader,
     relocs: Vec<ExeReloc>,
+    image: Vec<u8>,
+}
+
+/// size of the formatted part of the MZ header
+const EXE_HEADER_SIZE: usize = 0x1C;
+
+impl Exe {
+    fn parse(data: &[u8]) -> Result<Self, LoadError> {
+        if data.len() < EXE_HEADER_SIZE {
+            return Err(LoadError::Truncated("header"));
+        }
+        let header: ExeHeader = deserialize(&data[..EXE_HEADER_SIZE])?;
+        if header.signature != 0x5A4D {
+            return Err(LoadError::InvalidHeader(format!("bad signature {:04X}", header.signature)));
+        }
+        if header.blocks_in_file == 0 || header.bytes_in_last_block >= 512 {
+            return Err(LoadError::InvalidHeader(format!("bad size {} blocks, {} bytes in last block",
+                header.blocks_in_file, header.bytes_in_last_block)));
+        }
+
+        let image_start = header.header_paragraphs as usize * 16;
+        let mut image_end = header.blocks_in_file as usize * 512;
+        if header.bytes_in_last_block > 0 {
+            image_end -= 512 - header.bytes_in_last_block as usize;
+        }
+        if image_start < EXE_HEADER_SIZE || image_start > image_end {
+            return Err(LoadError::InvalidHeader(format!("bad header size {} paragraphs", header.header_paragraphs)));
+        }
+        if image_end > data.len() {
+            return Err(LoadError::Truncated("program image"));
+        }
+
+        let reloc_start = header.reloc_table_offset as usize;
+        let reloc_end = reloc_start + header.num_relocs as usize * 4;
+        if reloc_end > data.len() {
+            return Err(LoadError::Truncated("relocation table"));
+        }
+        let mut relocs = Vec::with_capacity(header.num_relocs as usize);
+        for chunk in data[reloc_start..reloc_end].chunks(4) {
+            relocs.push(deserialize(chunk)?);
+        }
+
+        Ok(Exe {
+            header,
+            relocs,
+            image: data[image_start..image_end].to_vec(),
+        })
+    }
+}
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum LoadError {
+        Truncated(part: &'static str) {
+            description("truncated executable")
+            display("truncated executable: missing {}", part)
+        }
+        InvalidHeader(reason: String) {
+            description("invalid exe header")
+            display("invalid exe header: {}", reason)
+        }
+        OutOfMemory(needed: u32, available: u32) {
+            description("insufficient memory")
+            display("insufficient memory: need {:04X} paragraphs, {:04X} available", needed, available)
+        }
+        Unsupported(reason: String) {
+            description("unsupported executable")
+            display("unsupported executable: {}", reason)
+        }
+        Dpmi(err: DpmiError) {
+            from()
+            description("dpmi host error")
+            display("dpmi host error: {:?}", err)
+        }
+        Decode(err: BincodeError) {
+            from()
+            description("decode error")
+            display("decode error: {}", err)
+        }
+    }
+}
+
+/// segment of the PSP of the loaded program
+const PSP_SEGMENT: u16 = 0x085F; // is what dosbox used
+
+/// segment of the environment block of the loaded program, located below the PSP
+const ENV_SEGMENT: u16 = 0x0820;
+
+/// first segment after conventional memory
+const MEMORY_END_SEGMENT: u16 = 0xA000;
+
+/// video frames per second run by execute_frame
+const FPS: usize = 60;
+
+/// emulated time of a video frame
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FrameStats {
+    /// cpu cycles run in the frame
+    pub cycles: usize,
+
+    /// cycles the cpu spent halted, waiting for a interrupt
+    pub idle_cycles: usize,
+}
+
+impl FrameStats {
+    /// the part of the frame the cpu was idle, from 0.0 to 1.0. a frontend can sleep for it
+    pub fn idle_ratio(&self) -> f64 {
+        if self.cycles == 0 {
+            0.
+        } else {
+            self.idle_cycles as f64 / self.cycles as f64
+        }
+    }
 }
 
 pub struct Machine {
     pub hw: Hardware,
     pub cpu: CPU,
+
+    /// first segment after the memory allocated to the loaded program
+    pub program_end_segment: u16,
+
+    /// runs instructions one by one, or as basic blocks
+    pub engine: Engine,
+
+    /// total cpu cycles spent halted
+    pub idle_cycles: usize,
 }
 
 impl Machine {
@@ -46,58 +180,179 @@ impl Machine {
         Machine {
             cpu: CPU::default(),
             hw: Hardware::default(),
+            program_end_segment: 0,
+            engine: Engine::Interpreter,
+            idle_cycles: 0,
         }
     }
 
+    /// selects the emulated processor
+    pub fn set_cpu_model(&mut self, model: CpuModel) {
+        self.cpu.set_model(&mut self.hw.mmu, model);
+    }
+
     /// reset the CPU and memory
     pub fn hard_reset(&mut self) {
+        let model = self.cpu.model();
         self.cpu = CPU::default();
+        self.set_cpu_model(model);
     }
 
+    /// mounts a host directory as DOS drive C:
+    pub fn mount(&mut self, path: &Path) {
+        self.hw.dos.fs.mount(path);
+    }
+
+    /// loads a .com or .exe program. errors in the .exe header stops the machine
     pub fn load_executable(&mut self, data: &[u8]) {
-        if data[0] == b'M' && data[1] == b'Z' {
-            self.load_exe(data);
+        if !self.cpu.deterministic {
+            self.init_clock();
+        }
+        if data.len() >= 2 && data[0] == b'M' && data[1] == b'Z' {
+            let res = if find_header(data).is_some() {
+                self.load_le(data)
+            } else {
+                self.load_exe(data)
+            };
+            if let Err(e) = res {
+                println!("load_executable error: {}", e);
+                self.cpu.fatal_error = true;
+            }
         } else {
             self.load_com(data);
         }
     }
 
-    fn load_exe(&mut self, data: &[u8]) {
-        let hdr: ExeHeader = deserialize(data).unwrap();
-        println!("load_exe header: {:?}", hdr);
+    /// sets the BIOS tick counter to the host time of day
+    fn init_clock(&mut self) {
+        let now = time::now();
+        let millis = (u64::from(now.tm_hour as u32) * 3600 + u64::from(now.tm_min as u32) * 60 + u64::from(now.tm_sec as u32)) * 1000
+            + u64::from(now.tm_nsec as u32) / 1_000_000;
+        let ticks = millis * PIT_HZ as u64 / 0x1_0000 / 1000;
+        self.hw.mmu.write_u32(BIOS::DATA_SEG, BIOS::DATA_TIMER_TICKS, ticks as u32);
+    }
+
+    /// loads a MZ executable into memory, applies the relocations and sets CS:IP and SS:SP from the header
+    pub fn load_exe(&mut self, data: &[u8]) -> Result<(), LoadError> {
+        let exe = Exe::parse(data)?;
+        let hdr = &exe.header;
+
+        let psp_segment = PSP_SEGMENT;
+        let image_paragraphs = ((exe.image.len() + 15) / 16) as u32;
+        let available = u32::from(MEMORY_END_SEGMENT - psp_segment);
+        let min_paragraphs = 0x10 + image_paragraphs + u32::from(hdr.min_extra_paragraphs);
+        if min_paragraphs > available {
+            return Err(LoadError::OutOfMemory(min_paragraphs, available));
+        }
+        let max_paragraphs = 0x10 + image_paragraphs + u32::from(hdr.max_extra_paragraphs);
+
+        let load_segment = if hdr.min_extra_paragraphs == 0 && hdr.max_extra_paragraphs == 0 {
+            // load high: the program image is placed at the end of the largest free block
+            (u32::from(MEMORY_END_SEGMENT) - image_paragraphs) as u16
+        } else {
+            psp_segment + 0x10
+        };
+        let allocated = if max_paragraphs > available || load_segment != psp_segment + 0x10 {
+            available
+        } else {
+            max_paragraphs
+        };
+        self.program_end_segment = psp_segment + allocated as u16;
+
+        self.hw.mmu.write(load_segment, 0, &exe.image);
+        for reloc in &exe.relocs {
+            let seg = reloc.segment.wrapping_add(load_segment);
+            let val = self.hw.mmu.read_u16(seg, reloc.offset);
+            self.hw.mmu.write_u16(seg, reloc.offset, val.wrapping_add(load_segment));
+        }
+
+        self.cpu.set_r16(R::CS, hdr.cs.wrapping_add(load_segment));
+        self.cpu.set_r16(R::DS, psp_segment);
+        self.cpu.set_r16(R::ES, psp_segment);
+        self.cpu.set_r16(R::SS, hdr.ss.wrapping_add(load_segment));
+        self.cpu.set_r16(R::SP, hdr.sp);
+        self.cpu.regs.ip = u32::from(hdr.ip);
+        self.cpu.regs.flags.interrupt = true;
 
-        let reloc_from = hdr.reloc_table_offset as usize;
-        let reloc_to = hdr.reloc_table_offset as usize + (hdr.num_relocs as usize * 4);
-        println!("load_exe read relocs from {:04X}-{:04X}", reloc_from, reloc_to);
+        // This is what dosbox initializes the registers to
+        // at program load
+        self.cpu.set_r16(R::BP, 0x091C);
+        self.cpu.set_r16(R::CX, 0x00FF);
+        self.cpu.set_r16(R::DX, psp_segment);
+        self.cpu.set_r16(R::SI, hdr.ip);
+        self.cpu.set_r16(R::DI, hdr.sp);
 
-        // let relocs: Vec<ExeReloc> = deserialize(&data[reloc_from..reloc_to]).unwrap();  // XXX crashes
-        let relocs: ExeReloc = deserialize(&data[reloc_from..reloc_to]).unwrap(); // XXX only reads first reloc
-        println!("XXX relocs: {:?}", relocs);
+        self.cpu.rom_base = MemoryAddress::RealSegmentOffset(load_segment, 0).value();
+        self.cpu.rom_length = exe.image.len() as u32;
+        self.init_memory();
+        self.build_psp("", &BTreeMap::new());
+        Ok(())
+    }
 
-        let code_offset = hdr.header_paragraphs as usize * 16;
-        let mut code_end = hdr.blocks_in_file as usize * 512;
-        if hdr.bytes_in_last_block > 0 {
-            code_end -= 512 - hdr.bytes_in_last_block as usize;
+    /// loads a LE/LX executable of a DOS extender without running its stub. the objects are placed in
+    /// linear memory, and the program starts in protected mode as a client of the DPMI host
+    pub fn load_le(&mut self, data: &[u8]) -> Result<(), LoadError> {
+        let le = LinearExecutable::parse(data)?;
+
+        // the program block holds the PSP and the host data area of the DPMI host
+        let psp_segment = PSP_SEGMENT;
+        let host_data = psp_segment + 0x10;
+        self.program_end_segment = host_data + DPMI_HOST_DATA_PARAGRAPHS;
+        for r in &[R::CS, R::DS, R::ES, R::SS] {
+            self.cpu.set_r16(*r, psp_segment);
         }
-        println!("load exe code from {:04X}:{:04X}", code_offset, code_end);
+        self.init_memory();
+        self.build_psp("", &BTreeMap::new());
+        let client32 = le.objects[le.eip_object].is_32bit();
+        enter_protected_mode(&mut self.cpu, &mut self.hw, psp_segment, 0, host_data, client32)?;
 
-        self.load_com(&data[code_offset..code_end]);
-        self.cpu.set_r16(R::SP, hdr.sp); // confirmed
-        self.cpu.set_r16(R::SS, hdr.ss); // XXX dosbox = 0923
-        
-        // at program start in dosbox-x:
-        // BP = 091C (dustbox ok)
-        // SP = 1000 (from hdr, dustbox ok)
-        // CS = 0920
-        // DS = 0910
-        // ES = 0910
-        // SS = 0923
+        // 32-bit objects use the flat model, 16-bit objects get a selector each
+        let dpmi = &mut self.hw.dos.dpmi;
+        let mmu = &mut self.hw.mmu;
+        let (flat_code, flat_data) = if le.objects.iter().any(|obj| obj.is_32bit()) {
+            (dpmi.allocate_flat_segment(mmu, true)?, dpmi.allocate_flat_segment(mmu, false)?)
+        } else {
+            (0, 0)
+        };
+        let mut linear = Vec::with_capacity(le.objects.len());
+        let mut bases = Vec::with_capacity(le.objects.len());
+        let mut selectors = Vec::with_capacity(le.objects.len());
+        for obj in &le.objects {
+            let size = obj.data.len().max(1) as u32;
+            let block = dpmi.allocate_memory(mmu, size)?;
+            linear.push(block.base);
+            if obj.is_32bit() {
+                bases.push(block.base);
+                selectors.push(if obj.is_code() { flat_code } else { flat_data });
+            } else {
+                bases.push(0);
+                selectors.push(dpmi.allocate_segment(mmu, block.base, obj.virtual_size.max(1) - 1, obj.is_code())?);
+            }
+        }
+        for (i, base) in linear.iter().enumerate() {
+            let image = le.relocated_object(i, &bases, &selectors);
+            mmu.memory.borrow_mut().write(*base, &image);
+        }
+
+        self.cpu.rom_base = linear[le.eip_object];
+        self.cpu.rom_length = le.objects[le.eip_object].data.len() as u32;
+        let data_object = le.auto_data_object.unwrap_or(le.esp_object);
+        for &(r, selector) in &[(R::CS, selectors[le.eip_object]), (R::SS, selectors[le.esp_object]), (R::DS, selectors[data_object])] {
+            if self.cpu.load_segment_register(mmu, r, selector).is_err() {
+                return Err(LoadError::InvalidHeader(format!("can't load {} with object selector {:04X}", r.as_str(), selector)));
+            }
+        }
+        self.cpu.regs.ip = bases[le.eip_object].wrapping_add(le.eip);
+        self.cpu.set_r32(R::ESP, bases[le.esp_object].wrapping_add(le.esp));
+        self.cpu.regs.flags.interrupt = true;
+        Ok(())
     }
 
     /// load .com program into CS:0100 and set IP to program start
     fn load_com(&mut self, data: &[u8]) {
         // CS,DS,ES,SS = PSP segment
-        let psp_segment = 0x085F; // is what dosbox used
+        let psp_segment = PSP_SEGMENT;
+        self.program_end_segment = MEMORY_END_SEGMENT;
         self.cpu.set_r16(R::CS, psp_segment);
         self.cpu.set_r16(R::DS, psp_segment);
         self.cpu.set_r16(R::ES, psp_segment);
@@ -115,12 +370,59 @@ impl Machine {
         self.cpu.set_r16(R::DI, 0xFFFE); // XXX 0x1000 on .exe
 
         self.cpu.regs.ip = 0x0100;
+        self.cpu.regs.flags.interrupt = true;
         let min = self.cpu.get_address();
         self.cpu.rom_base = min;
         self.cpu.rom_length = data.len() as u32;
 
         let cs = self.cpu.get_r16(R::CS);
-        self.hw.mmu.write(cs, self.cpu.regs.ip, data);
+        self.hw.mmu.write(cs, 0x0100, data);
+        self.init_memory();
+        self.build_psp("", &BTreeMap::new());
+    }
+
+    /// sets up the MCB chain with the environment block and the program block
+    fn init_memory(&mut self) {
+        let env_paragraphs = PSP_SEGMENT - 1 - ENV_SEGMENT;
+        let program_paragraphs = self.program_end_segment - PSP_SEGMENT;
+        let mem = &mut self.hw.dos.memory;
+        mem.init(&mut self.hw.mmu, ENV_SEGMENT - 1, MEMORY_END_SEGMENT);
+        let env = mem.allocate(&mut self.hw.mmu, env_paragraphs, PSP_SEGMENT);
+        let psp = mem.allocate(&mut self.hw.mmu, program_paragraphs, PSP_SEGMENT);
+        assert_eq!(Ok(ENV_SEGMENT), env);
+        assert_eq!(Ok(PSP_SEGMENT), psp);
+    }
+
+    /// writes the PSP and environment block of the loaded program. the first word
+    /// of command_line is the program name, the rest is passed as the command tail
+    pub fn build_psp(&mut self, command_line: &str, env: &BTreeMap<String, String>) {
+        let command_line = command_line.trim_start();
+        let (program, tail) = match command_line.find(|c: char| c.is_whitespace()) {
+            Some(pos) => command_line.split_at(pos),
+            None => (command_line, ""),
+        };
+        let mut program_path = program.to_uppercase().replace("/", "\\");
+        if !program_path.is_empty() && !program_path.contains(':') {
+            program_path = format!("C:\\{}", program_path.trim_start_matches('\\'));
+        }
+
+        let env_size = (PSP_SEGMENT - 1 - ENV_SEGMENT) as usize * 16;
+        if !write_environment(&mut self.hw.mmu, ENV_SEGMENT, env_size, env, &program_path) {
+            println!("build_psp: environment don't fit in {} bytes, ignoring", env_size);
+            write_environment(&mut self.hw.mmu, ENV_SEGMENT, env_size, &BTreeMap::new(), &program_path);
+        }
+        write_psp(&mut self.hw.mmu, PSP_SEGMENT, self.program_end_segment, ENV_SEGMENT, tail);
+        self.hw.dos.psp_segment = PSP_SEGMENT;
+    }
+
+    /// presses a key, scancode is a scancode set 1 make code
+    pub fn key_down(&mut self, scancode: u8) {
+        self.hw.key_down(scancode);
+    }
+
+    /// releases a key, scancode is a scancode set 1 make code
+    pub fn key_up(&mut self, scancode: u8) {
+        self.hw.key_up(scancode);
     }
 
     /// returns a copy of register values at a given time
@@ -128,47 +430,150 @@ impl Machine {
         self.cpu.regs.clone()
     }
 
-    /// executes enough instructions that can run for 1 video frame
-    pub fn execute_frame(&mut self) {
-        let fps = 60;
-        let cycles = self.cpu.clock_hz / fps;
+    /// executes enough instructions that can run for 1 video frame. returns how much of the
+    /// frame the cpu was halted
+    pub fn execute_frame(&mut self) -> FrameStats {
+        let cycles = self.frame_cycles();
+        let start_cycles = self.cpu.cycle_count;
+        let start_idle = self.idle_cycles;
         // println!("will execute {} cycles", cycles);
 
         loop {
-            self.execute_instruction();
+            if self.cpu.halted {
+                // fast-forwards to the next interrupt, or the end of the frame
+                let remaining = cycles.saturating_sub(self.cpu.cycle_count) + 1;
+                self.idle(remaining);
+            } else {
+                self.execute_step(usize::MAX);
+            }
             if self.cpu.fatal_error {
                 break;
             }
             if self.cpu.cycle_count > cycles {
-                self.cpu.cycle_count = 0;
                 break;
             }
         }
+        let stats = FrameStats {
+            cycles: self.cpu.cycle_count.saturating_sub(start_cycles),
+            idle_cycles: self.idle_cycles - start_idle,
+        };
+        if !self.cpu.fatal_error {
+            self.cpu.cycle_count = 0;
+        }
+        stats
+    }
+
+    /// cpu cycles of a video frame
+    fn frame_cycles(&self) -> usize {
+        self.cpu.clock_hz / FPS
+    }
+
+    /// lets the halted cpu wait for a interrupt, for at most max_cycles. emulated time is
+    /// fast-forwarded to the next timer interrupt
+    fn idle(&mut self, max_cycles: usize) {
+        self.acknowledge_interrupt();
+        if !self.cpu.halted {
+            return;
+        }
+        let cycles = match self.hw.pit.cycles_to_irq(self.cpu.clock_hz) {
+            Some(cycles) if cycles < max_cycles => cycles,
+            _ => max_cycles,
+        };
+        self.cpu.cycle_count += cycles;
+        self.idle_cycles += cycles;
+        self.hw.advance_timers(cycles, self.cpu.clock_hz);
     }
 
     /// executes n instructions of the cpu. only used in tests
     pub fn execute_instructions(&mut self, count: usize) {
-        for _ in 0..count {
-            self.execute_instruction()
+        let mut done = 0;
+        while done < count {
+            done += self.execute_step(count - done);
         }
     }
 
-    /// returns first line of disassembly
-    fn external_disasm_of_bytes(&self, cs: u16, ip: u16) -> String {
-        let bytes = self.hw.mmu.read(cs, ip, 16);
-        ndisasm_first_instr(&bytes).unwrap().to_owned()
+    /// executes the next instruction, or up to max instructions of a basic block with the threaded engine.
+    /// returns the number of instructions executed, a halted cpu counts as 1
+    pub fn execute_step(&mut self, max: usize) -> usize {
+        if self.engine == Engine::Interpreter || self.cpu.halted {
+            self.execute_instruction();
+            return 1;
+        }
+
+        let start_cycles = self.cpu.cycle_count;
+        self.acknowledge_interrupt();
+
+        // XXX blocks are keyed by linear address, so paged code is interpreted. single stepped
+        // code traps after each instruction
+        if self.hw.mmu.paging_enabled() || self.cpu.regs.flags.trap ||
+                self.cpu.segment_base(R::CS) == u32::from(DPMI_ROM_SEG) << 4 {
+            self.execute_decoded(start_cycles);
+            return 1;
+        }
+        let start_count = self.cpu.instruction_count;
+        let executed = self.cpu.execute_block(&mut self.hw, max);
+        if executed == 0 {
+            // the instruction at CS:IP can't be part of a block
+            self.execute_decoded(start_cycles);
+            return 1;
+        }
+
+        // XXX should be timed by the crtc. progresses as often as the interpreter does
+        for _ in start_count / 100..self.cpu.instruction_count / 100 {
+            self.hw.gpu.progress_scanline();
+        }
+
+        let cycles = self.cpu.cycle_count - start_cycles;
+        self.hw.advance_timers(cycles, self.cpu.clock_hz);
+        executed
     }
 
     pub fn execute_instruction(&mut self) {
+        if self.cpu.halted {
+            let cycles = self.frame_cycles();
+            return self.idle(cycles);
+        }
+        let start_cycles = self.cpu.cycle_count;
+        self.acknowledge_interrupt();
+        self.execute_decoded(start_cycles);
+    }
+
+    /// serves a hardware interrupt request from the PIC
+    fn acknowledge_interrupt(&mut self) {
+        if self.cpu.regs.flags.interrupt {
+            if let Some(vector) = self.hw.acknowledge_interrupt() {
+                // the interrupt returns to the instruction following HLT
+                self.cpu.halted = false;
+                self.cpu.int(&mut self.hw, vector);
+            }
+        }
+    }
+
+    /// decodes and executes the instruction at CS:IP
+    fn execute_decoded(&mut self, start_cycles: usize) {
         let cs = self.cpu.get_r16(R::CS);
         let ip = self.cpu.regs.ip;
-        if cs == 0xF000 {
+        if self.cpu.segment_base(R::CS) == u32::from(DPMI_ROM_SEG) << 4 {
             // we are in interrupt vector code, execute high-level interrupt.
             // the default interrupt vector table has a IRET
-            self.cpu.handle_interrupt(&mut self.hw, ip as u8);
+            if ip >= u32::from(DPMI_ENTRY_MODE_SWITCH) {
+                handle_dpmi_entry(&mut self.cpu, &mut self.hw, ip as u16);
+            } else if self.cpu.protected_mode() {
+                // the IDT of the DPMI host leads all interrupts here
+                handle_dpmi_interrupt(&mut self.cpu, &mut self.hw, ip as u8);
+            } else {
+                self.cpu.handle_interrupt(&mut self.hw, ip as u8);
+            }
+            if self.cpu.get_r16(R::CS) != cs || self.cpu.regs.ip != ip || self.cpu.halted {
+                // the handler transferred control, continue there. a halted handler
+                // waits for a interrupt and runs again when it returns
+                let cycles = self.cpu.cycle_count - start_cycles;
+                self.hw.advance_timers(cycles, self.cpu.clock_hz);
+                return;
+            }
         }
 
-        let op = self.cpu.decoder.get_instruction(&mut self.hw.mmu, cs, ip);
+        let op = self.cpu.decoder.get_shared_instruction(&mut self.hw.mmu, cs, ip);
 
         match op.command {
             Op::Uninitialized => {
@@ -176,36 +581,15 @@ impl Machine {
                 println!("[{:04X}:{:04X}] ERROR: uninitialized op. {} instructions executed",
                          cs, ip, self.cpu.instruction_count);
             }
-            Op::Invalid(bytes, reason) => {
-                let hex = hex_bytes(&bytes);
-                self.cpu.fatal_error = true;
-                match reason {
-                    Invalid::Op => {
-                        println!("[{:04X}:{:04X}] {} ERROR: unhandled opcode", cs, ip, hex);
-                        println!("ndisasm: {}", self.external_disasm_of_bytes(cs, ip));
-                    }
-                    Invalid::FPUOp => {
-                        println!("[{:04X}:{:04X}] {} ERROR: unhandled FPU opcode", cs, ip, hex);
-                        println!("ndisasm: {}", self.external_disasm_of_bytes(cs, ip));
-                    }
-                    Invalid::Reg(reg) => {
-                        println!("[{:04X}:{:04X}] {} ERROR: unhandled reg value {:02X}", cs, ip, hex, reg);
-                        println!("ndisasm: {}", self.external_disasm_of_bytes(cs, ip));
-                    }
-                }
-                println!("{} Instructions executed", self.cpu.instruction_count);
-            }
             _ => self.cpu.execute(&mut self.hw, &op),
         }
 
-        // XXX need instruction timing to do this properly
-        if self.cpu.cycle_count % 100 == 0 {
+        // XXX should be timed by the crtc
+        if self.cpu.instruction_count % 100 == 0 {
             self.hw.gpu.progress_scanline();
         }
 
-        if self.cpu.cycle_count % 100 == 0 {
-            // FIXME: counter should decrement ~18.2 times/sec
-            self.hw.pit.counter0.dec();
-        }
+        let cycles = self.cpu.cycle_count - start_cycles;
+        self.hw.advance_timers(cycles, self.cpu.clock_hz);
     }
 }
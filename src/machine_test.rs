@@ -0,0 +1,222 @@
+use machine::{Machine, LoadError};
+use cpu::R;
+use bios::BIOS;
+
+/// builds a MZ executable with the relocation table directly after the header
+fn build_exe(code: &[u8], relocs: &[(u16, u16)], min_extra: u16, max_extra: u16, ss: u16, sp: u16) -> Vec<u8> {
+    let header_paragraphs = (0x1C + relocs.len() * 4 + 15) / 16;
+    let size = header_paragraphs * 16 + code.len();
+    let words: Vec<u16> = vec![
+        0x5A4D,
+        (size % 512) as u16,
+        ((size + 511) / 512) as u16,
+        relocs.len() as u16,
+        header_paragraphs as u16,
+        min_extra,
+        max_extra,
+        ss,
+        sp,
+        0, // checksum
+        0, // ip
+        0, // cs
+        0x1C, // reloc_table_offset
+        0, // overlay_number
+    ];
+    let mut data = Vec::new();
+    for w in words {
+        data.push(w as u8);
+        data.push((w >> 8) as u8);
+    }
+    for &(offset, segment) in relocs {
+        data.push(offset as u8);
+        data.push((offset >> 8) as u8);
+        data.push(segment as u8);
+        data.push((segment >> 8) as u8);
+    }
+    data.resize(header_paragraphs * 16, 0);
+    data.extend_from_slice(code);
+    data
+}
+
+#[test]
+fn can_load_exe_with_relocations() {
+    let mut code = vec![0u8; 0x30];
+    code[0x00] = 0xB8; // mov ax,0x0000 (relocated)
+    code[0x01] = 0x00;
+    code[0x02] = 0x00;
+    code[0x24] = 0x01; // far pointer segment 0x0001 in segment 0x0002 (relocated)
+    code[0x25] = 0x00;
+    let data = build_exe(&code, &[(0x0001, 0x0000), (0x0004, 0x0002)], 0x0100, 0xFFFF, 0x0003, 0x0100);
+
+    let mut machine = Machine::default();
+    machine.load_exe(&data).unwrap();
+
+    let load_segment = 0x085F + 0x10;
+    let psp_segment = machine.cpu.get_r16(R::DS);
+    assert_eq!(psp_segment + 0x10, load_segment);
+    assert_eq!(psp_segment, machine.cpu.get_r16(R::ES));
+    assert_eq!(load_segment, machine.cpu.get_r16(R::CS));
+    assert_eq!(0x0000, machine.cpu.regs.ip);
+    assert_eq!(load_segment + 3, machine.cpu.get_r16(R::SS));
+    assert_eq!(0x0100, machine.cpu.get_r16(R::SP));
+    assert_eq!(load_segment, machine.hw.mmu.read_u16(load_segment, 0x0001));
+    assert_eq!(load_segment + 1, machine.hw.mmu.read_u16(load_segment + 2, 0x0004));
+    assert_eq!(0xA000, machine.program_end_segment); // max_extra = FFFF claims all memory
+
+    machine.execute_instruction();
+    assert_eq!(load_segment, machine.cpu.get_r16(R::AX));
+}
+
+#[test]
+fn can_load_exe_honoring_max_extra_paragraphs() {
+    let code = vec![0x90; 0x20];
+    let data = build_exe(&code, &[], 0x0010, 0x0020, 0x0000, 0x0100);
+
+    let mut machine = Machine::default();
+    machine.load_exe(&data).unwrap();
+    // PSP + 2 paragraphs image + 0x20 extra
+    assert_eq!(0x085F + 0x10 + 0x02 + 0x20, machine.program_end_segment);
+}
+
+#[test]
+fn can_load_exe_high() {
+    let code = vec![0x90; 0x20];
+    let data = build_exe(&code, &[], 0, 0, 0x0000, 0x0100);
+
+    let mut machine = Machine::default();
+    machine.load_exe(&data).unwrap();
+    assert_eq!(0xA000 - 0x02, machine.cpu.get_r16(R::CS));
+}
+
+#[test]
+fn fails_to_load_malformed_exe() {
+    let mut machine = Machine::default();
+    match machine.load_exe(&[b'M', b'Z', 0x00]) {
+        Err(LoadError::Truncated(_)) => {}
+        res => panic!("unexpected result {:?}", res),
+    }
+
+    let code = vec![0x90; 0x20];
+    let mut data = build_exe(&code, &[], 0, 0xFFFF, 0x0000, 0x0100);
+    data.truncate(data.len() - 1);
+    match machine.load_exe(&data) {
+        Err(LoadError::Truncated(_)) => {}
+        res => panic!("unexpected result {:?}", res),
+    }
+
+    let data = build_exe(&code, &[(0, 0)], 0, 0xFFFF, 0x0000, 0x0100);
+    let mut bad_relocs = data.clone();
+    bad_relocs[0x18] = 0xF0; // reloc_table_offset beyond end of file
+    match machine.load_exe(&bad_relocs) {
+        Err(LoadError::Truncated(_)) => {}
+        res => panic!("unexpected result {:?}", res),
+    }
+
+    let mut bad_size = data.clone();
+    bad_size[0x02] = 0x00; // bytes_in_last_block = 0x200
+    bad_size[0x03] = 0x02;
+    match machine.load_exe(&bad_size) {
+        Err(LoadError::InvalidHeader(_)) => {}
+        res => panic!("unexpected result {:?}", res),
+    }
+
+    let data = build_exe(&code, &[], 0xF000, 0xFFFF, 0x0000, 0x0100);
+    match machine.load_exe(&data) {
+        Err(LoadError::OutOfMemory(_, _)) => {}
+        res => panic!("unexpected result {:?}", res),
+    }
+}
+
+#[test]
+fn stops_machine_on_malformed_exe() {
+    let mut machine = Machine::default();
+    machine.load_executable(&[b'M', b'Z', 0x00]);
+    assert_eq!(true, machine.cpu.fatal_error);
+}
+
+#[test]
+fn can_idle_in_hlt_until_the_timer_interrupt() {
+    let mut machine = Machine::default();
+    machine.cpu.deterministic = true;
+    let code: Vec<u8> = vec![
+        0xFB,       // sti
+        0xF4,       // hlt
+        0x43,       // inc bx
+        0xEB, 0xFC, // jmp short 0x101
+    ];
+    machine.load_executable(&code);
+    machine.execute_instructions(2);
+    assert_eq!(true, machine.cpu.halted);
+    assert_eq!(0x0102, machine.cpu.regs.ip);
+
+    // time is fast-forwarded to the timer interrupt, a frame at a time
+    let cycles = machine.hw.pit.cycles_to_irq(machine.cpu.clock_hz).unwrap();
+    let start_cycles = machine.cpu.cycle_count;
+    while machine.cpu.halted {
+        machine.execute_instruction();
+    }
+    assert_eq!(start_cycles + cycles, machine.cpu.cycle_count);
+    assert_eq!(cycles, machine.idle_cycles);
+    assert_eq!(0x0008, machine.cpu.regs.ip);
+    // the handler, INT 1Ch and the end of interrupt in the ROM, then inc bx
+    machine.execute_instructions(9);
+    assert_eq!(0x0103, machine.cpu.regs.ip);
+    assert_eq!(0x0001, machine.cpu.get_r16(R::BX));
+    assert_eq!(1, machine.hw.mmu.read_u32(BIOS::DATA_SEG, BIOS::DATA_TIMER_TICKS));
+}
+
+#[test]
+fn calls_the_user_timer_tick() {
+    let mut machine = Machine::default();
+    machine.cpu.deterministic = true;
+    let code: Vec<u8> = vec![
+        0xFB,       // sti
+        0xF4,       // hlt
+        0xEB, 0xFD, // jmp short 0x101
+        0x43,       // inc bx       ; INT 1Ch handler
+        0xCF,       // iret
+    ];
+    machine.load_executable(&code);
+    let cs = machine.cpu.get_r16(R::CS);
+    machine.hw.mmu.write_u16(0, 0x1C * 4, 0x0104);
+    machine.hw.mmu.write_u16(0, 0x1C * 4 + 2, cs);
+
+    // the handler is called once per tick, and the interrupt is acknowledged after it returns
+    for _ in 0..10 {
+        machine.execute_frame();
+    }
+    assert_eq!(true, machine.cpu.halted);
+    assert_eq!(3, machine.hw.mmu.read_u32(BIOS::DATA_SEG, BIOS::DATA_TIMER_TICKS));
+    assert_eq!(0x0003, machine.cpu.get_r16(R::BX));
+    assert_eq!(cs, machine.cpu.get_r16(R::CS));
+}
+
+#[test]
+fn reports_idle_time_of_frames() {
+    let mut machine = Machine::default();
+    machine.cpu.deterministic = true;
+    let code: Vec<u8> = vec![
+        0xFB,       // sti
+        0xF4,       // hlt
+        0x43,       // inc bx
+        0xEB, 0xFC, // jmp short 0x101
+    ];
+    machine.load_executable(&code);
+    let mut idle_cycles = 0;
+    for _ in 0..10 {
+        let stats = machine.execute_frame();
+        assert!(stats.idle_ratio() > 0.99, "{:?}", stats);
+        idle_cycles += stats.idle_cycles;
+    }
+    assert_eq!(machine.idle_cycles, idle_cycles);
+
+    // the timer runs at 18.2 Hz
+    let ticks = machine.hw.mmu.read_u32(BIOS::DATA_SEG, BIOS::DATA_TIMER_TICKS);
+    assert_eq!(3, ticks);
+    assert_eq!(ticks as u16, machine.cpu.get_r16(R::BX));
+
+    // busy loops are not idle
+    let mut machine = Machine::default();
+    machine.load_executable(&[0xEB, 0xFE]); // jmp short 0x100
+    assert_eq!(0, machine.execute_frame().idle_cycles);
+}
@@ -1,19 +1,42 @@
+use std::mem;
+
+use gpu::PlanarMemory;
 use hex::hex_bytes_separated;
 
 #[derive(Clone, Default)]
 pub struct FlatMemory {
     pub memory: Vec<u8>,
+
+    /// one bit per byte of cached instructions, allocated on first use
+    code: Vec<u64>,
+
+    /// addresses of code bytes written since last take_modified_code
+    modified_code: Vec<u32>,
+
+    /// the EGA/VGA planes, seen through the A000 window in the planar video modes
+    pub planar: PlanarMemory,
 }
 
 const DEBUG_MEMORY: bool = false;
 
+/// value read from addresses without RAM, as from a floating data bus
+const OPEN_BUS: u8 = 0xFF;
+
 impl FlatMemory {
     pub fn new() -> Self {
-        FlatMemory { memory: vec![0u8; 0x1_0000 * 64] }
+        FlatMemory {
+            memory: vec![0u8; 0x1_0000 * 64],
+            code: Vec::new(),
+            modified_code: Vec::new(),
+            planar: PlanarMemory::default(),
+        }
     }
 
     pub fn read_u8(&self, addr: u32) -> u8 {
-        let val = self.memory[addr as usize];
+        if self.planar.maps(addr) {
+            return self.planar.read_u8(addr);
+        }
+        let val = self.memory.get(addr as usize).cloned().unwrap_or(OPEN_BUS);
         if DEBUG_MEMORY {
             println!("read_u8 from {:06x} = {:02x}", addr, val);
         }
@@ -21,40 +44,125 @@ impl FlatMemory {
     }
 
     pub fn read_u16(&self, addr: u32) -> u16 {
-        u16::from(self.read_u8(addr + 1)) << 8 | u16::from(self.read_u8(addr))
+        if self.planar.overlaps(addr, 2) || !self.in_ram(addr, 2) {
+            return u16::from(self.read_u8(addr.wrapping_add(1))) << 8 | u16::from(self.read_u8(addr));
+        }
+        let addr = addr as usize;
+        u16::from_le_bytes([self.memory[addr], self.memory[addr + 1]])
     }
 
     pub fn write_u8(&mut self, addr: u32, data: u8) {
         if DEBUG_MEMORY {
             println!("write_u8 to {:06x} = {:02x}", addr, data);
         }
+        if self.planar.maps(addr) {
+            self.planar.write_u8(addr, data);
+            return;
+        }
+        if !self.in_ram(addr, 1) {
+            // writes outside of RAM are lost
+            return;
+        }
+        if !self.code.is_empty() {
+            self.check_code_write(addr);
+        }
         self.memory[addr as usize] = data;
     }
 
     pub fn write_u16(&mut self, addr: u32, data: u16) {
-        self.write_u8(addr, data as u8);
-        self.write_u8(addr + 1, (data >> 8) as u8);
+        if self.planar.overlaps(addr, 2) || !self.in_ram(addr, 2) {
+            self.write_u8(addr, data as u8);
+            self.write_u8(addr.wrapping_add(1), (data >> 8) as u8);
+            return;
+        }
+        self.write_slice(addr, &data.to_le_bytes());
     }
 
     pub fn read_u32(&self, addr: u32) -> u32 {
-        u32::from(self.read_u16(addr + 2)) << 16 | u32::from(self.read_u16(addr))
+        if self.planar.overlaps(addr, 4) || !self.in_ram(addr, 4) {
+            return u32::from(self.read_u16(addr.wrapping_add(2))) << 16 | u32::from(self.read_u16(addr));
+        }
+        let addr = addr as usize;
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(&self.memory[addr..addr + 4]);
+        u32::from_le_bytes(bytes)
     }
 
     pub fn write_u32(&mut self, addr: u32, data: u32) {
-        self.write_u16(addr, data as u16);
-        self.write_u16(addr + 2, (data >> 16) as u16);
+        if self.planar.overlaps(addr, 4) || !self.in_ram(addr, 4) {
+            self.write_u16(addr, data as u16);
+            self.write_u16(addr.wrapping_add(2), (data >> 16) as u16);
+            return;
+        }
+        self.write_slice(addr, &data.to_le_bytes());
     }
 
-    pub fn read(&self, addr: u32, length: usize) -> &[u8] {
+    /// reads a sequence of data, through the planes where it overlaps the A000 window
+    pub fn read(&self, addr: u32, length: usize) -> Vec<u8> {
+        if self.planar.overlaps(addr, length) || !self.in_ram(addr, length) {
+            return (0..length as u32).map(|i| self.read_u8(addr.wrapping_add(i))).collect();
+        }
         let addr = addr as usize;
-        &self.memory[addr..addr+length]
+        Vec::from(&self.memory[addr..addr+length])
     }
 
     pub fn write(&mut self, addr: u32, data: &[u8]) {
-        let addr = addr as usize;
         if DEBUG_MEMORY {
             println!("write to {:06x} in {} bytes: {}", addr, data.len(), hex_bytes_separated(data, ' '));
         }
+        if self.planar.overlaps(addr, data.len()) || !self.in_ram(addr, data.len()) {
+            for (i, b) in data.iter().enumerate() {
+                self.write_u8(addr.wrapping_add(i as u32), *b);
+            }
+            return;
+        }
+        self.write_slice(addr, data);
+    }
+
+    /// returns true if all of addr..addr+length is RAM
+    fn in_ram(&self, addr: u32, length: usize) -> bool {
+        addr as usize + length <= self.memory.len()
+    }
+
+    /// writes data to ram, outside of the planar window
+    fn write_slice(&mut self, addr: u32, data: &[u8]) {
+        let addr = addr as usize;
+        if !self.code.is_empty() {
+            for i in addr..addr+data.len() {
+                self.check_code_write(i as u32);
+            }
+        }
         self.memory[addr..addr+data.len()].copy_from_slice(data);
     }
+
+    /// marks bytes as part of a cached instruction, so writes to them are recorded
+    pub fn mark_code(&mut self, addr: u32, length: u32) {
+        if self.code.is_empty() {
+            self.code = vec![0; self.memory.len().div_ceil(64)];
+        }
+        // instructions outside of RAM can't be modified
+        let end = (u64::from(addr) + u64::from(length)).min(self.memory.len() as u64) as u32;
+        for i in addr..end {
+            self.code[i as usize / 64] |= 1 << (i % 64);
+        }
+    }
+
+    /// returns true if marked bytes were written since last take_modified_code
+    pub fn has_modified_code(&self) -> bool {
+        !self.modified_code.is_empty()
+    }
+
+    /// returns the addresses of marked bytes written since last call, they are no longer marked
+    pub fn take_modified_code(&mut self) -> Vec<u32> {
+        mem::take(&mut self.modified_code)
+    }
+
+    fn check_code_write(&mut self, addr: u32) {
+        let word = &mut self.code[addr as usize / 64];
+        let bit = 1 << (addr % 64);
+        if *word & bit != 0 {
+            *word &= !bit;
+            self.modified_code.push(addr);
+        }
+    }
 }
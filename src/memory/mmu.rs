@@ -2,7 +2,9 @@ use memory::FlatMemory;
 use std::cell::RefCell;
 use std::rc::Rc;
 
+use cpu::R;
 use memory::MemoryAddress;
+use memory::paging::{Paging, PageFault};
 
 #[cfg(test)]
 #[path = "./mmu_test.rs"]
@@ -13,40 +15,255 @@ const DEBUG_VEC: bool = false;
 
 #[derive(Clone, Default)]
 pub struct MMU {
-    pub memory: Rc<RefCell<FlatMemory>>
+    pub memory: Rc<RefCell<FlatMemory>>,
+
+    /// selector and base address of ES, CS, SS, DS, FS and GS in protected mode, as loaded by the cpu.
+    /// segment values passed by HLE code are translated with the register holding them, other
+    /// values are translated as real mode segments
+    segments: [Option<(u16, u32)>; 6],
+
+    /// linear addresses are translated by the paging unit (CR0.PG)
+    paging_enabled: bool,
+
+    /// page directory, TLB and the last page fault
+    paging: RefCell<Paging>,
+
+    /// accesses are made at CPL 3, which is checked against the user bit of pages
+    user_mode: bool,
+
+    /// word accesses wrap at the end of the segment, and addresses at 1 MB (8086, 80186)
+    wrap: bool,
 }
 
 impl MMU {
     pub fn default() -> Self{
         MMU {
-            memory: Rc::new(RefCell::new(FlatMemory::new()))
+            memory: Rc::new(RefCell::new(FlatMemory::new())),
+            segments: [None; 6],
+            paging_enabled: false,
+            paging: RefCell::new(Paging::default()),
+            user_mode: false,
+            wrap: false,
+        }
+    }
+
+    /// records the selector and base address loaded in segment register r in protected mode
+    pub fn set_segment(&mut self, r: R, selector: u16, base: u32) {
+        self.segments[r.index()] = Some((selector, base));
+    }
+
+    /// forgets the protected mode segments, when returning to real mode
+    pub fn clear_segments(&mut self) {
+        self.segments = [None; 6];
+    }
+
+    /// enables or disables paging (CR0.PG)
+    pub fn set_paging(&mut self, enabled: bool) {
+        self.paging_enabled = enabled;
+    }
+
+    pub fn paging_enabled(&self) -> bool {
+        self.paging_enabled
+    }
+
+    /// loads the page directory base (CR3) and flushes the TLB
+    pub fn set_page_directory(&mut self, cr3: u32) {
+        self.paging.borrow_mut().set_page_directory(cr3);
+    }
+
+    /// accesses through segment:offset are checked as user accesses while running at CPL 3
+    pub fn set_user_mode(&mut self, user: bool) {
+        self.user_mode = user;
+    }
+
+    /// selects the address wrapping of the 8086 and 80186, accesses by later models
+    /// continue past the end of the segment
+    pub fn set_wrap(&mut self, wrap: bool) {
+        self.wrap = wrap;
+    }
+
+    /// returns true if a access of size bytes at offset wraps at the end of the segment
+    fn wraps(&self, offset: u16, size: u32) -> bool {
+        self.wrap && u32::from(offset) + size > 0x1_0000
+    }
+
+    /// returns and clears the first page fault raised since the last call
+    pub fn take_page_fault(&self) -> Option<PageFault> {
+        self.paging.borrow_mut().fault.take()
+    }
+
+    /// translates seg:offset to a linear address. offsets above 0xFFFF are used by 32-bit code
+    pub fn linear(&self, seg: u16, offset: u32) -> u32 {
+        for &(selector, base) in self.segments.iter().flatten() {
+            if selector == seg {
+                return base.wrapping_add(offset);
+            }
+        }
+        let addr = (u32::from(seg) << 4).wrapping_add(offset);
+        if self.wrap {
+            // the 8086 has 20 address lines
+            return addr & 0xF_FFFF;
+        }
+        addr
+    }
+
+    /// translates a linear address to a physical address. returns None and records the page fault
+    /// if the page is not present or the access is not permitted
+    fn physical(&self, linear: u32, write: bool, user: bool) -> Option<u32> {
+        if !self.paging_enabled {
+            return Some(linear);
+        }
+        let mut paging = self.paging.borrow_mut();
+        match paging.translate(&mut self.memory.borrow_mut(), linear, write, user) {
+            Ok(addr) => Some(addr),
+            Err(fault) => {
+                if paging.fault.is_none() {
+                    paging.fault = Some(fault);
+                }
+                None
+            }
+        }
+    }
+
+    /// reads from a linear address, user accesses are made at CPL 3
+    pub fn load_u8(&self, linear: u32, user: bool) -> u8 {
+        match self.physical(linear, false, user) {
+            Some(addr) => self.memory.borrow().read_u8(addr),
+            None => 0,
+        }
+    }
+
+    pub fn load_u16(&self, linear: u32, user: bool) -> u16 {
+        if self.paging_enabled && linear & 0xFFF > 0xFFE {
+            // the access crosses a page boundary
+            return u16::from(self.load_u8(linear.wrapping_add(1), user)) << 8 | u16::from(self.load_u8(linear, user));
+        }
+        match self.physical(linear, false, user) {
+            Some(addr) => self.memory.borrow().read_u16(addr),
+            None => 0,
+        }
+    }
+
+    pub fn load_u32(&self, linear: u32, user: bool) -> u32 {
+        if self.paging_enabled && linear & 0xFFF > 0xFFC {
+            return u32::from(self.load_u16(linear.wrapping_add(2), user)) << 16 | u32::from(self.load_u16(linear, user));
+        }
+        match self.physical(linear, false, user) {
+            Some(addr) => self.memory.borrow().read_u32(addr),
+            None => 0,
+        }
+    }
+
+    /// writes to a linear address, user accesses are made at CPL 3
+    pub fn store_u8(&self, linear: u32, user: bool, data: u8) {
+        if let Some(addr) = self.physical(linear, true, user) {
+            self.memory.borrow_mut().write_u8(addr, data);
+        }
+    }
+
+    pub fn store_u16(&self, linear: u32, user: bool, data: u16) {
+        if self.paging_enabled && linear & 0xFFF > 0xFFE {
+            self.store_u8(linear, user, data as u8);
+            self.store_u8(linear.wrapping_add(1), user, (data >> 8) as u8);
+            return;
+        }
+        if let Some(addr) = self.physical(linear, true, user) {
+            self.memory.borrow_mut().write_u16(addr, data);
+        }
+    }
+
+    pub fn store_u32(&self, linear: u32, user: bool, data: u32) {
+        if self.paging_enabled && linear & 0xFFF > 0xFFC {
+            self.store_u16(linear, user, data as u16);
+            self.store_u16(linear.wrapping_add(2), user, (data >> 16) as u16);
+            return;
+        }
+        if let Some(addr) = self.physical(linear, true, user) {
+            self.memory.borrow_mut().write_u32(addr, data);
         }
     }
 
+    /// reads from a linear address as a supervisor access, used for descriptor tables
+    pub fn read_linear_u8(&self, addr: u32) -> u8 {
+        self.load_u8(addr, false)
+    }
+
+    pub fn read_linear_u16(&self, addr: u32) -> u16 {
+        self.load_u16(addr, false)
+    }
+
+    pub fn read_linear_u32(&self, addr: u32) -> u32 {
+        self.load_u32(addr, false)
+    }
+
+    /// reads a 8 byte descriptor table entry
+    pub fn read_linear_u64(&self, addr: u32) -> u64 {
+        u64::from(self.read_linear_u32(addr.wrapping_add(4))) << 32 | u64::from(self.read_linear_u32(addr))
+    }
+
+    pub fn write_linear_u8(&mut self, addr: u32, data: u8) {
+        self.store_u8(addr, false, data);
+    }
+
+    pub fn write_linear_u16(&mut self, addr: u32, data: u16) {
+        self.store_u16(addr, false, data);
+    }
+
+    pub fn write_linear_u32(&mut self, addr: u32, data: u32) {
+        self.store_u32(addr, false, data);
+    }
+
     /// reads a sequence of data from memory
     pub fn read(&self, seg: u16, offset: u16, length: usize) -> Vec<u8> {
-        let addr = MemoryAddress::RealSegmentOffset(seg, offset).value();
-        Vec::from(self.memory.borrow().read(addr, length))
+        self.read_linear(self.linear(seg, u32::from(offset)), length)
+    }
+
+    /// reads a sequence of data from a linear address, such as a buffer at a 32-bit offset
+    pub fn read_linear(&self, addr: u32, length: usize) -> Vec<u8> {
+        if self.paging_enabled {
+            return (0..length as u32).map(|i| self.load_u8(addr.wrapping_add(i), self.user_mode)).collect();
+        }
+        self.memory.borrow().read(addr, length)
+    }
+
+    /// reads a sequence of data from a linear address until a NULL byte is found
+    pub fn readz_linear(&self, addr: u32) -> Vec<u8> {
+        let mut res = Vec::new();
+        let mut addr = addr;
+        loop {
+            let b = self.load_u8(addr, self.user_mode);
+            if b == 0 {
+                break;
+            }
+            res.push(b);
+            addr = addr.wrapping_add(1);
+        }
+        res
     }
 
     /// reads a sequence of data until a NULL byte is found
     pub fn readz(&self, seg: u16, offset: u16) -> Vec<u8> {
         let mut res = Vec::new();
-        let mut addr = MemoryAddress::RealSegmentOffset(seg, offset);
+        let mut offset = offset;
         loop {
-            let b = self.memory.borrow().read_u8(addr.value());
+            let b = self.read_u8(seg, offset);
             if b == 0 {
                 break;
             }
             res.push(b);
-            addr.inc_u8();
+            offset = offset.wrapping_add(1);
         }
         res
     }
 
+    /// reads a instruction byte at seg:offset
+    pub fn fetch_u8(&self, seg: u16, offset: u32) -> u8 {
+        self.load_u8(self.linear(seg, offset), self.user_mode)
+    }
+
     pub fn read_u8(&self, seg: u16, offset: u16) -> u8 {
-        let addr = MemoryAddress::RealSegmentOffset(seg, offset).value();
-        let v = self.memory.borrow().read_u8(addr);
+        let addr = self.linear(seg, u32::from(offset));
+        let v = self.load_u8(addr, self.user_mode);
         if DEBUG_MMU {
             println!("mmu.read_u8 from {:06X} = {:02X}", addr, v);
         }
@@ -54,8 +271,11 @@ impl MMU {
     }
 
     pub fn read_u16(&self, seg: u16, offset: u16) -> u16 {
-        let addr = MemoryAddress::RealSegmentOffset(seg, offset).value();
-        let v = self.memory.borrow().read_u16(addr);
+        if self.wraps(offset, 2) {
+            return u16::from(self.read_u8(seg, 0)) << 8 | u16::from(self.read_u8(seg, offset));
+        }
+        let addr = self.linear(seg, u32::from(offset));
+        let v = self.load_u16(addr, self.user_mode);
         if DEBUG_MMU {
             println!("mmu.read_u16 from {:06X} = {:04X}", addr, v);
         }
@@ -63,11 +283,11 @@ impl MMU {
     }
 
     pub fn write_u8(&mut self, seg: u16, offset: u16, data: u8) {
-        let addr = MemoryAddress::RealSegmentOffset(seg, offset).value();
+        let addr = self.linear(seg, u32::from(offset));
         if DEBUG_MMU {
             println!("mmu.write_u8 to {:06X} = {:02X}", addr, data);
         }
-        self.memory.borrow_mut().write_u8(addr, data);
+        self.store_u8(addr, self.user_mode, data);
     }
 
     /// writes and increments offset
@@ -81,16 +301,32 @@ impl MMU {
 
     /// writes a sequence of data to memory
     pub fn write(&mut self, seg: u16, offset: u16, data: &[u8]) {
-        let addr = MemoryAddress::RealSegmentOffset(seg, offset).value();
+        let addr = self.linear(seg, u32::from(offset));
+        self.write_linear(addr, data);
+    }
+
+    /// writes a sequence of data to a linear address
+    pub fn write_linear(&mut self, addr: u32, data: &[u8]) {
+        if self.paging_enabled {
+            for (i, b) in data.iter().enumerate() {
+                self.store_u8(addr.wrapping_add(i as u32), self.user_mode, *b);
+            }
+            return;
+        }
         self.memory.borrow_mut().write(addr, data);
     }
 
     pub fn write_u16(&mut self, seg: u16, offset: u16, data: u16) {
-        let addr = MemoryAddress::RealSegmentOffset(seg, offset).value();
+        if self.wraps(offset, 2) {
+            self.write_u8(seg, offset, data as u8);
+            self.write_u8(seg, 0, (data >> 8) as u8);
+            return;
+        }
+        let addr = self.linear(seg, u32::from(offset));
         if DEBUG_MMU {
             println!("mmu.write_u16 to {:06X} = {:04X}", addr, data);
         }
-        self.memory.borrow_mut().write_u16(addr, data);
+        self.store_u16(addr, self.user_mode, data);
     }
 
     pub fn write_u16_inc(&mut self, addr: &mut MemoryAddress, data: u16) {
@@ -102,8 +338,11 @@ impl MMU {
     }
 
     pub fn read_u32(&self, seg: u16, offset: u16) -> u32 {
-        let addr = MemoryAddress::RealSegmentOffset(seg, offset).value();
-        let v = self.memory.borrow().read_u32(addr);
+        if self.wraps(offset, 4) {
+            return u32::from(self.read_u16(seg, offset.wrapping_add(2))) << 16 | u32::from(self.read_u16(seg, offset));
+        }
+        let addr = self.linear(seg, u32::from(offset));
+        let v = self.load_u32(addr, self.user_mode);
         if DEBUG_MMU {
             println!("mmu.read_u32 from {:06X} = {:04X}", addr, v);
         }
@@ -112,11 +351,16 @@ impl MMU {
 
     pub fn write_u32(&mut self, seg: u16, offset: u16, data: u32) {
         // TODO take MemoryAddress parameter directly
-        let addr = MemoryAddress::RealSegmentOffset(seg, offset).value();
+        if self.wraps(offset, 4) {
+            self.write_u16(seg, offset, data as u16);
+            self.write_u16(seg, offset.wrapping_add(2), (data >> 16) as u16);
+            return;
+        }
+        let addr = self.linear(seg, u32::from(offset));
         if DEBUG_MMU {
             println!("mmu.write_u32 to {:06X} = {:08X}", addr, data);
         }
-        self.memory.borrow_mut().write_u32(addr, data);
+        self.store_u32(addr, self.user_mode, data);
     }
 
     pub fn write_u32_inc(&mut self, addr: &mut MemoryAddress, data: u32) {
@@ -1,4 +1,6 @@
-use memory::mmu::MemoryAddress;
+use cpu::R;
+use memory::mmu::{MMU, MemoryAddress};
+use memory::paging::{PageFault, PF_PROTECTION, PF_WRITE, PF_USER};
 
 #[test]
 fn can_handle_real_mode_addressing() {
@@ -18,3 +20,100 @@ fn can_convert_to_long_pair() {
     assert_eq!(0xC000, ma.segment());
     assert_eq!(0x0000, ma.offset());
 }
+
+#[test]
+fn can_translate_protected_mode_selectors() {
+    let mut mmu = MMU::default();
+    mmu.set_segment(R::DS, 0x0010, 0x0001_2000);
+    mmu.write_u16(0x0010, 0x0004, 0x1234);
+    assert_eq!(0x1234, mmu.read_linear_u16(0x0001_2004));
+    assert_eq!(0x1234, mmu.read_u16(0x1200, 0x0004));
+
+    // each segment register has its own base
+    mmu.set_segment(R::ES, 0x0020, 0x0003_0000);
+    mmu.write_u16(0x0020, 0x0004, 0x4321);
+    assert_eq!(0x4321, mmu.read_linear_u16(0x0003_0004));
+    assert_eq!(0x1234, mmu.read_u16(0x0010, 0x0004));
+
+    // segment values not held by a segment register are real mode segments
+    mmu.write_linear_u16(0x0184, 0x5678);
+    assert_eq!(0x5678, mmu.read_u16(0x0018, 0x0004));
+
+    // leaving protected mode removes the translation
+    mmu.clear_segments();
+    assert_eq!(0x0000, mmu.read_u16(0x0010, 0x0004));
+}
+
+#[test]
+fn can_translate_paged_addresses() {
+    let mut mmu = MMU::default();
+    mmu.write_linear_u32(0x1000, 0x2000 | 0x7);
+    mmu.write_linear_u32(0x2000, 0x0003_0000 | 0x3); // linear 0 -> physical 0x30000, supervisor
+    mmu.write_linear_u32(0x2004, 0x0003_1000 | 0x7); // linear 0x1000 -> physical 0x31000, user
+    mmu.set_page_directory(0x1000);
+    mmu.set_paging(true);
+
+    // a word crossing into the next page
+    mmu.write_u16(0x0000, 0x0FFF, 0x1234);
+    assert_eq!(0x34, mmu.memory.borrow().read_u8(0x0003_0FFF));
+    assert_eq!(0x12, mmu.memory.borrow().read_u8(0x0003_1000));
+    assert_eq!(0x1234, mmu.read_u16(0x0000, 0x0FFF));
+    assert_eq!(None, mmu.take_page_fault());
+
+    // user accesses to supervisor pages fault, and reads return 0
+    mmu.set_user_mode(true);
+    assert_eq!(0, mmu.read_u8(0x0000, 0x0FFF));
+    assert_eq!(Some(PageFault { address: 0x0FFF, error: PF_PROTECTION | PF_USER }), mmu.take_page_fault());
+    assert_eq!(None, mmu.take_page_fault());
+
+    // writes to not present pages are dropped
+    mmu.write_u8(0x0000, 0x2000, 0x55);
+    assert_eq!(Some(PageFault { address: 0x2000, error: PF_WRITE | PF_USER }), mmu.take_page_fault());
+}
+
+#[test]
+fn can_access_the_planar_window() {
+    let mut mmu = MMU::default();
+    {
+        let planar = &mut mmu.memory.borrow_mut().planar;
+        // mode 12h: planes are unchained, all planes written
+        planar.set_sequencer_register(0x02, 0x0F);
+        planar.set_sequencer_register(0x04, 0x06);
+        planar.set_graphics_register(0x06, 0x05);
+        planar.set_graphics_register(0x08, 0xFF);
+    }
+    // a word straddling the start of the window goes to ram and to the planes
+    mmu.write_u16(0x9FFF, 0x000F, 0x1234);
+    mmu.write_u32(0xA000, 0x0010, 0x1122_3344);
+    assert_eq!(0x34, mmu.memory.borrow().memory[0x9_FFFF]);
+    assert_eq!(0x12, mmu.memory.borrow().planar.plane_u8(2, 0));
+    assert_eq!(0x1234, mmu.read_u16(0x9FFF, 0x000F));
+    assert_eq!(0x1122_3344, mmu.read_u32(0xA000, 0x0010));
+    assert_eq!(vec![0x34, 0x12, 0x00], mmu.read(0x9FFF, 0x000F, 3));
+    assert_eq!(vec![0x44, 0x33, 0x22, 0x11], mmu.read(0xA000, 0x0010, 4));
+
+    // the ram behind the window is not touched
+    assert_eq!(0x00, mmu.memory.borrow().memory[0xA_0000]);
+    assert_eq!(0x00, mmu.memory.borrow().memory[0xA_0010]);
+}
+
+#[test]
+fn can_access_addresses_without_ram() {
+    let mut mmu = MMU::default();
+    let end = mmu.memory.borrow().memory.len() as u32;
+
+    // reads outside of RAM return all bits set, writes are lost
+    mmu.write_linear_u32(0x8000_0000, 0x1234_5678);
+    assert_eq!(0xFFFF_FFFF, mmu.read_linear_u32(0x8000_0000));
+    assert_eq!(0xFF, mmu.read_linear_u8(0xFFFF_FFFF));
+    mmu.write_linear_u32(end - 2, 0x1234_5678);
+    assert_eq!(0xFFFF_5678, mmu.read_linear_u32(end - 2));
+    assert_eq!(vec![0x78, 0x56, 0xFF, 0xFF], mmu.read_linear(end - 2, 4));
+
+    // a page directory above RAM translates to addresses without RAM
+    mmu.set_page_directory(0xF000_0000);
+    mmu.set_paging(true);
+    mmu.write_linear_u16(0x0040_0000, 0x1234);
+    assert_eq!(0xFFFF, mmu.read_linear_u16(0x0040_0000));
+    assert_eq!(None, mmu.take_page_fault());
+}
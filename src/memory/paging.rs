@@ -0,0 +1,148 @@
+// 80386 paging unit
+//
+// Linear addresses are translated through a page directory and a page table
+// of 1024 entries each, both 4k in size. Recently used translations are kept
+// in a direct mapped TLB, which is only flushed by reloading CR3.
+
+use memory::FlatMemory;
+
+#[cfg(test)]
+#[path = "./paging_test.rs"]
+mod paging_test;
+
+const DEBUG_PAGING: bool = false;
+
+/// number of TLB entries, the 80386 has 32
+const TLB_SIZE: usize = 32;
+
+// page directory and page table entry bits
+const PAGE_PRESENT: u32 = 1 << 0;
+const PAGE_WRITABLE: u32 = 1 << 1;
+const PAGE_USER: u32 = 1 << 2;
+const PAGE_ACCESSED: u32 = 1 << 5;
+const PAGE_DIRTY: u32 = 1 << 6;
+
+// page fault error code bits
+pub const PF_PROTECTION: u16 = 1 << 0; // clear if the page was not present
+pub const PF_WRITE: u16 = 1 << 1;
+pub const PF_USER: u16 = 1 << 2;
+
+/// a failed translation, delivered as Exception::PF with address in CR2
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PageFault {
+    pub address: u32,
+    pub error: u16,
+}
+
+#[derive(Clone, Copy, Default)]
+struct TlbEntry {
+    valid: bool,
+
+    /// linear page number
+    page: u32,
+
+    /// physical address of the page
+    frame: u32,
+
+    /// combined permissions of the page directory and page table entry
+    writable: bool,
+    user: bool,
+
+    /// the dirty bit is set in the page table entry
+    dirty: bool,
+}
+
+#[derive(Clone, Default)]
+pub struct Paging {
+    /// physical address of the page directory (CR3)
+    page_directory: u32,
+
+    tlb: [TlbEntry; TLB_SIZE],
+
+    /// the first page fault since it was last taken
+    pub fault: Option<PageFault>,
+}
+
+impl Paging {
+    /// loads CR3, which flushes the TLB
+    pub fn set_page_directory(&mut self, cr3: u32) {
+        self.page_directory = cr3 & !0xFFF;
+        self.flush();
+    }
+
+    pub fn flush(&mut self) {
+        self.tlb = [TlbEntry::default(); TLB_SIZE];
+    }
+
+    /// translates a linear address to a physical address. user is true for accesses at CPL 3
+    pub fn translate(&mut self, memory: &mut FlatMemory, linear: u32, write: bool, user: bool) -> Result<u32, PageFault> {
+        let page = linear >> 12;
+        let entry = self.tlb[page as usize % TLB_SIZE];
+        if entry.valid && entry.page == page && (!write || entry.dirty) {
+            check_access(linear, write, user, entry.writable, entry.user)?;
+            return Ok(entry.frame | (linear & 0xFFF));
+        }
+
+        let mut error = 0;
+        if write {
+            error |= PF_WRITE;
+        }
+        if user {
+            error |= PF_USER;
+        }
+        let fault = PageFault { address: linear, error };
+
+        let pde_addr = self.page_directory | ((linear >> 22) << 2);
+        let pde = memory.read_u32(pde_addr);
+        if pde & PAGE_PRESENT == 0 {
+            return Err(fault);
+        }
+        let pte_addr = (pde & !0xFFF) | (((linear >> 12) & 0x3FF) << 2);
+        let pte = memory.read_u32(pte_addr);
+        if pte & PAGE_PRESENT == 0 {
+            return Err(fault);
+        }
+
+        // the most restrictive permission of the two levels applies
+        let writable = pde & pte & PAGE_WRITABLE != 0;
+        let user_page = pde & pte & PAGE_USER != 0;
+        check_access(linear, write, user, writable, user_page)?;
+
+        if pde & PAGE_ACCESSED == 0 {
+            memory.write_u32(pde_addr, pde | PAGE_ACCESSED);
+        }
+        let mut new_pte = pte | PAGE_ACCESSED;
+        if write {
+            new_pte |= PAGE_DIRTY;
+        }
+        if new_pte != pte {
+            memory.write_u32(pte_addr, new_pte);
+        }
+
+        let frame = pte & !0xFFF;
+        if DEBUG_PAGING {
+            println!("paging: {:08X} -> {:08X}", linear, frame | (linear & 0xFFF));
+        }
+        self.tlb[page as usize % TLB_SIZE] = TlbEntry {
+            valid: true,
+            page,
+            frame,
+            writable,
+            user: user_page,
+            dirty: new_pte & PAGE_DIRTY != 0,
+        };
+        Ok(frame | (linear & 0xFFF))
+    }
+}
+
+/// user accesses need a user page, and user writes a writable page. the 80386 lets supervisor writes ignore the R/W bit
+fn check_access(linear: u32, write: bool, user: bool, writable: bool, user_page: bool) -> Result<(), PageFault> {
+    if user && (!user_page || (write && !writable)) {
+        let mut error = PF_PROTECTION | PF_USER;
+        if write {
+            error |= PF_WRITE;
+        }
+        return Err(PageFault { address: linear, error });
+    }
+    Ok(())
+}
@@ -0,0 +1,67 @@
+use memory::FlatMemory;
+use memory::paging::{Paging, PageFault, PF_PROTECTION, PF_WRITE, PF_USER};
+
+/// page directory at 0x1000 with one page table at 0x2000, covering the first 4 MB
+fn setup_page_tables(memory: &mut FlatMemory) -> Paging {
+    memory.write_u32(0x1000, 0x2000 | 0x7); // present, writable, user
+    for page in 0..16 {
+        // identity map the first 64k as supervisor pages
+        memory.write_u32(0x2000 + page * 4, (page << 12) | 0x3);
+    }
+    // linear 0x0001_0000 -> physical 0x0003_0000, user read-only
+    memory.write_u32(0x2000 + 0x10 * 4, 0x0003_0000 | 0x5);
+
+    let mut paging = Paging::default();
+    paging.set_page_directory(0x1000);
+    paging
+}
+
+#[test]
+fn can_translate_linear_addresses() {
+    let mut memory = FlatMemory::new();
+    let mut paging = setup_page_tables(&mut memory);
+
+    assert_eq!(Ok(0x0000_8123), paging.translate(&mut memory, 0x0000_8123, false, false));
+    assert_eq!(Ok(0x0003_0456), paging.translate(&mut memory, 0x0001_0456, false, true));
+
+    // accessed bits are set in the page directory and page table, dirty only on write
+    assert_eq!(0x2027, memory.read_u32(0x1000));
+    assert_eq!(0x8023, memory.read_u32(0x2000 + 8 * 4));
+    assert_eq!(Ok(0x0000_8000), paging.translate(&mut memory, 0x0000_8000, true, false));
+    assert_eq!(0x8063, memory.read_u32(0x2000 + 8 * 4));
+}
+
+#[test]
+fn can_raise_page_faults() {
+    let mut memory = FlatMemory::new();
+    let mut paging = setup_page_tables(&mut memory);
+
+    // not present page table entry
+    assert_eq!(Err(PageFault { address: 0x0002_0000, error: 0 }), paging.translate(&mut memory, 0x0002_0000, false, false));
+
+    // not present page directory entry
+    assert_eq!(Err(PageFault { address: 0x0040_0010, error: PF_WRITE }), paging.translate(&mut memory, 0x0040_0010, true, false));
+
+    // user access to a supervisor page
+    assert_eq!(Err(PageFault { address: 0x0000_1000, error: PF_PROTECTION | PF_USER }), paging.translate(&mut memory, 0x0000_1000, false, true));
+
+    // user write to a read-only page
+    assert_eq!(Err(PageFault { address: 0x0001_0000, error: PF_PROTECTION | PF_WRITE | PF_USER }), paging.translate(&mut memory, 0x0001_0000, true, true));
+
+    // supervisor writes ignore the R/W bit
+    assert_eq!(Ok(0x0003_0000), paging.translate(&mut memory, 0x0001_0000, true, false));
+}
+
+#[test]
+fn can_flush_the_tlb() {
+    let mut memory = FlatMemory::new();
+    let mut paging = setup_page_tables(&mut memory);
+
+    assert_eq!(Ok(0x0000_4000), paging.translate(&mut memory, 0x0000_4000, false, false));
+
+    // the cached translation is used until CR3 is reloaded
+    memory.write_u32(0x2000 + 4 * 4, 0x0005_0000 | 0x3);
+    assert_eq!(Ok(0x0000_4000), paging.translate(&mut memory, 0x0000_4000, false, false));
+    paging.set_page_directory(0x1000);
+    assert_eq!(Ok(0x0005_0000), paging.translate(&mut memory, 0x0000_4000, false, false));
+}
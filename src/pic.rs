@@ -4,6 +4,10 @@
 // The 8259 PIC controls the CPU's interrupt mechanism, by accepting several
 // interrupt requests and feeding them to the processor in order.
 
+#[cfg(test)]
+#[path = "./pic_test.rs"]
+mod pic_test;
+
 #[derive(Clone, Debug)]
 enum OperationMode {
     Clear,                              // 0 rotate in auto EOI mode (clear)
@@ -16,30 +20,143 @@ enum OperationMode {
     RotateOnSpecificEOICommand,         // 7 (WORD_D) rotate on specific EOI command
 }
 
+/// the initialization command word expected next on the data port
+#[derive(Clone, Debug, PartialEq)]
+enum InitState {
+    Ready,
+    ICW2,
+    ICW3,
+    ICW4,
+}
+
 #[derive(Clone)]
 pub struct PIC {
-    command: u8,
-    data: u8,
     operation: OperationMode,
+    init_state: InitState,
+
+    /// interrupt request register, one bit per IRQ line
+    irr: u8,
+
+    /// in-service register
+    isr: u8,
+
+    /// interrupt mask register (OCW1)
+    imr: u8,
+
+    /// interrupt vector of IRQ 0, set by ICW2
+    vector_base: u8,
+
+    /// ICW1: single mode, no ICW3 needed
+    single: bool,
+
+    /// ICW1: ICW4 needed
+    needs_icw4: bool,
+
+    /// ICW3: cascade configuration
+    cascade: u8,
+
+    /// ICW4: automatic end of interrupt
+    auto_eoi: bool,
+
+    /// rotate priorities in automatic EOI mode
+    rotate_on_auto_eoi: bool,
+
+    /// the IRQ with the lowest priority, the following one has the highest
+    lowest_priority: u8,
+
+    /// OCW3: read ISR instead of IRR from command port
+    read_isr: bool,
+
+    /// OCW3: special mask mode
+    special_mask: bool,
+
+    /// OCW3: next read of command port is a poll
+    poll: bool,
 }
 
 impl PIC {
     pub fn default() -> Self {
         PIC {
-            command: 0,
-            data: 0,
             operation: OperationMode::NoOperation, // XXX default?
+            init_state: InitState::Ready,
+            irr: 0,
+            isr: 0,
+            imr: 0,
+            vector_base: 0,
+            single: false,
+            needs_icw4: false,
+            cascade: 0,
+            auto_eoi: false,
+            rotate_on_auto_eoi: false,
+            lowest_priority: 7,
+            read_isr: false,
+            special_mask: false,
+            poll: false,
         }
     }
 
+    /// returns a PIC in the state left by the BIOS POST
+    pub fn new(vector_base: u8, imr: u8, cascade: u8) -> Self {
+        let mut pic = PIC::default();
+        pic.vector_base = vector_base;
+        pic.imr = imr;
+        pic.cascade = cascade;
+        pic
+    }
+
+    /// raises the IRQ line (0-7)
+    pub fn request_irq(&mut self, irq: u8) {
+        self.irr |= 1 << irq;
+    }
+
+    /// lowers the IRQ line (0-7), withdrawing a request not yet acknowledged
+    pub fn clear_irq(&mut self, irq: u8) {
+        self.irr &= !(1 << irq);
+    }
+
+    /// returns the IRQ that would be delivered on acknowledge
+    pub fn pending_irq(&self) -> Option<u8> {
+        for i in 0..8 {
+            let irq = (self.lowest_priority + 1 + i) & 7;
+            let bit = 1 << irq;
+            if self.isr & bit != 0 && !self.special_mask {
+                // a IRQ with equal or higher priority is in service
+                return None;
+            }
+            if self.irr & bit != 0 && self.imr & bit == 0 && self.isr & bit == 0 {
+                return Some(irq);
+            }
+        }
+        None
+    }
+
+    /// interrupt acknowledge cycle for irq, returns the interrupt vector
+    pub fn acknowledge(&mut self, irq: u8) -> u8 {
+        let bit = 1 << irq;
+        self.irr &= !bit;
+        if self.auto_eoi {
+            if self.rotate_on_auto_eoi {
+                self.lowest_priority = irq;
+            }
+        } else {
+            self.isr |= bit;
+        }
+        self.vector_base.wrapping_add(irq)
+    }
+
+    /// returns true if irq is the cascade input from a slave PIC (master only)
+    pub fn is_cascade(&self, irq: u8) -> bool {
+        !self.single && self.cascade & (1 << irq) != 0
+    }
+
     /// io read of port 0021 (pic1) or 00A1 (pic2)
     pub fn get_ocw1(&self) -> u8 {
         // read: PIC master interrupt mask register OCW1
-        0 // XXX
+        self.imr
     }
 
     /// io read of port 0020 (pic1) or 00A0 (pic2)
-    pub fn get_register(&self) -> u8 {
+    pub fn get_register(&mut self) -> u8 {
         /*
         0020  R-  PIC  interrupt request/in-service registers after OCW3
         request register:
@@ -49,84 +166,169 @@ impl PIC {
             bit 7-0 = 0  corresponding line not currently being serviced
                 = 1  corresponding int. line currently being serviced
         */
-        0 // XXX
+        if self.poll {
+            // poll command: bit 7 = interrupt pending, bits 2-0 = highest priority request
+            self.poll = false;
+            return match self.pending_irq() {
+                Some(irq) => {
+                    self.acknowledge(irq);
+                    0x80 | irq
+                }
+                None => 0,
+            };
+        }
+        if self.read_isr {
+            self.isr
+        } else {
+            self.irr
+        }
     }
 
     /// PIC - Command register, port 0x0020
     pub fn set_command(&mut self, val: u8) {
-        self.command = val;
-        println!("PIC COMMAND: {:02x} == {:08b}", val, val);
-        // XXX 0x20 == 0b0010_0000 == EOI - End of interrrupt command code
+        if val & 0x10 != 0 {
+            /*
+            0020  -W  PIC initialization command word ICW1 (see #P0010)
+            Bit(s)	Description	(Table P0010)
+            7-5	0 (only used in 8080/8085 mode)
+            4	ICW1 is being issued
+            3	(LTIM)
+                =0  edge triggered mode
+                =1  level triggered mode
+            2	interrupt vector size
+                =0 successive interrupt vectors use 8 bytes (8080/8085)
+                =1 successive interrupt vectors use 4 bytes (80x86)
+            1	(SNGL)
+                =0  cascade mode
+                =1  single mode, no ICW3 needed
+            0	ICW4 needed
+            SeeAlso: #P0011,#P0012,#P0013
+            */
+            self.single = val & 0b10 != 0;
+            self.needs_icw4 = val & 0b1 != 0;
+            self.imr = 0;
+            self.isr = 0;
+            self.irr = 0;
+            self.auto_eoi = false;
+            self.rotate_on_auto_eoi = false;
+            self.lowest_priority = 7;
+            self.read_isr = false;
+            self.special_mask = false;
+            self.poll = false;
+            self.init_state = InitState::ICW2;
+            return;
+        }
 
-        /*
-        0020  -W  PIC initialization command word ICW1 (see #P0010)
-        Bit(s)	Description	(Table P0010)
-        7-5	0 (only used in 8080/8085 mode)
-        4	ICW1 is being issued
-        3	(LTIM)
-            =0  edge triggered mode
-            =1  level triggered mode
-        2	interrupt vector size
-            =0 successive interrupt vectors use 8 bytes (8080/8085)
-            =1 successive interrupt vectors use 4 bytes (80x86)
-        1	(SNGL)
-            =0  cascade mode
-            =1  single mode, no ICW3 needed
-        0	ICW4 needed
-        SeeAlso: #P0011,#P0012,#P0013
-        */
-        let kind = (val >> 3) & 0b11; // bits 4-3: reserved (00 - signals OCW2)
-        match kind {
-            0 => { // 0020  -W  PIC output control word OCW2
-                // SeeAlso: #P0014,#P0016
-                let operation = (val >> 5) & 0b111; // bits 7-5: operation
-                self.operation = match operation {
-                    0 => OperationMode::Clear,
-                    1 => OperationMode::NonspecificEOI,
-                    2 => OperationMode::NoOperation,
-                    3 => OperationMode::SpecificEOI,
-                    4 => OperationMode::Set,
-                    5 => OperationMode::RotateOnNonspecificEOICommand,
-                    6 => OperationMode::SetPriorityCommand,
-                    7 => OperationMode::RotateOnSpecificEOICommand,
-                    _ => unreachable!(),
-                };
+        if val & 0x08 == 0 {
+            // 0020  -W  PIC output control word OCW2
+            // SeeAlso: #P0014,#P0016
+            let operation = (val >> 5) & 0b111; // bits 7-5: operation
+            self.operation = match operation {
+                0 => OperationMode::Clear,
+                1 => OperationMode::NonspecificEOI,
+                2 => OperationMode::NoOperation,
+                3 => OperationMode::SpecificEOI,
+                4 => OperationMode::Set,
+                5 => OperationMode::RotateOnNonspecificEOICommand,
+                6 => OperationMode::SetPriorityCommand,
+                7 => OperationMode::RotateOnSpecificEOICommand,
+                _ => unreachable!(),
+            };
 
-                let data = val & 0b11; // bits 0-2: interrupt request to which the command applies
-                //     (only used by WORD_B, WORD_D, and WORD_E)
-                println!("XXX: pic ocw2 operation {:?}, data {}", self.operation, data);
+            let level = val & 0b111; // bits 0-2: interrupt request to which the command applies
+            //     (only used by WORD_B, WORD_D, and WORD_E)
+            match self.operation {
+                OperationMode::Clear => self.rotate_on_auto_eoi = false,
+                OperationMode::Set => self.rotate_on_auto_eoi = true,
+                OperationMode::NoOperation => {}
+                OperationMode::NonspecificEOI => {
+                    self.end_of_interrupt();
+                }
+                OperationMode::RotateOnNonspecificEOICommand => {
+                    if let Some(irq) = self.end_of_interrupt() {
+                        self.lowest_priority = irq;
+                    }
+                }
+                OperationMode::SpecificEOI => self.isr &= !(1 << level),
+                OperationMode::RotateOnSpecificEOICommand => {
+                    self.isr &= !(1 << level);
+                    self.lowest_priority = level;
+                }
+                OperationMode::SetPriorityCommand => self.lowest_priority = level,
+            }
+        } else {
+            // 0020  -W  PIC output control word OCW3 (see #P0016)
+            // Bit(s)	Description	(Table P0016)
+            // 7	reserved (0)
+            // 6-5	special mask
+            //     0x  no operation
+            //     10  reset special mask
+            //     11  set special mask mode
+            // 2	poll command
+            // 1-0	function
+            //     0x  no operation
+            //     10  read interrupt request register on next read from PORT 0020h
+            //     11  read interrupt in-service register on next read from PORT 0020h
+            // Note:	the special mask mode permits all other interrupts (even those with
+            //     lower priority) to be processed while an interrupt is already in
+            //     service, but will not re-issue an interrupt for a particular IRQ
+            //     while it remains in service
+            match (val >> 5) & 0b11 {
+                0b10 => self.special_mask = false,
+                0b11 => self.special_mask = true,
+                _ => {}
             }
-            1 => { // 0020  -W  PIC output control word OCW3 (see #P0016)
-                // Bit(s)	Description	(Table P0016)
-                // 7	reserved (0)
-                // 6-5	special mask
-                //     0x  no operation
-                //     10  reset special mask
-                //     11  set special mask mode
-                // 2	poll command
-                // 1-0	function
-                //     0x  no operation
-                //     10  read interrupt request register on next read from PORT 0020h
-                //     11  read interrupt in-service register on next read from PORT 0020h
-                // Note:	the special mask mode permits all other interrupts (even those with
-                //     lower priority) to be processed while an interrupt is already in
-                //     service, but will not re-issue an interrupt for a particular IRQ
-                //     while it remains in service
+            self.poll = val & 0b100 != 0;
+            match val & 0b11 {
+                0b10 => self.read_isr = false,
+                0b11 => self.read_isr = true,
+                _ => {}
             }
-            _ => panic!("unhandled kind {}", kind),
         }
     }
 
     /// Master PIC - Data register, port 0x0021
     pub fn set_data(&mut self, val: u8) {
-        // XXX: one value if written immediately after value to 0020, another otherwise....
-        self.data = val;
-
-        // XXX impl, from https://wiki.osdev.org/8259_PIC#Disabling
-        //If you are going to use the processor local APIC and the IOAPIC, you must first disable the PIC. This is done via:
-        //mov al, 0xff
-        //out 0xa1, al
-        //out 0x21, al
+        // the data port takes the ICW2-ICW4 after a ICW1, otherwise it sets the mask (OCW1)
+        match self.init_state {
+            InitState::ICW2 => {
+                // bits 7-3 of the interrupt vector
+                self.vector_base = val & 0xF8;
+                self.init_state = if !self.single {
+                    InitState::ICW3
+                } else if self.needs_icw4 {
+                    InitState::ICW4
+                } else {
+                    InitState::Ready
+                };
+            }
+            InitState::ICW3 => {
+                // master: bit mask of IRQ lines with a slave. slave: the master IRQ it is connected to
+                self.cascade = val;
+                self.init_state = if self.needs_icw4 {
+                    InitState::ICW4
+                } else {
+                    InitState::Ready
+                };
+            }
+            InitState::ICW4 => {
+                // bit 1 = automatic EOI, bit 0 = 8086 mode
+                self.auto_eoi = val & 0b10 != 0;
+                self.init_state = InitState::Ready;
+            }
+            InitState::Ready => self.imr = val,
+        }
     }
 
+    /// clears the highest priority in-service IRQ, returns the IRQ
+    fn end_of_interrupt(&mut self) -> Option<u8> {
+        for i in 0..8 {
+            let irq = (self.lowest_priority + 1 + i) & 7;
+            if self.isr & (1 << irq) != 0 {
+                self.isr &= !(1 << irq);
+                return Some(irq);
+            }
+        }
+        None
+    }
 }
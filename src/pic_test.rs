@@ -0,0 +1,111 @@
+use machine::Machine;
+use pic::PIC;
+use bios::BIOS;
+
+/// programs pic like the BIOS POST does
+fn init_pic(pic: &mut PIC, vector_base: u8, cascade: u8) {
+    pic.set_command(0x11);      // ICW1: ICW4 needed, cascade mode
+    pic.set_data(vector_base);  // ICW2: vector base
+    pic.set_data(cascade);      // ICW3: cascade configuration
+    pic.set_data(0x01);         // ICW4: 8086 mode
+}
+
+#[test]
+fn can_initialize_and_acknowledge() {
+    let mut pic = PIC::default();
+    init_pic(&mut pic, 0x08, 0x04);
+    assert_eq!(0x00, pic.get_ocw1());
+
+    pic.set_data(0xFE); // OCW1: mask all but IRQ 0
+    assert_eq!(0xFE, pic.get_ocw1());
+
+    pic.request_irq(1);
+    assert_eq!(None, pic.pending_irq());
+    assert_eq!(0x02, pic.get_register()); // IRR
+
+    pic.request_irq(0);
+    assert_eq!(Some(0), pic.pending_irq());
+    assert_eq!(0x08, pic.acknowledge(0));
+
+    pic.set_command(0x0B); // OCW3: read ISR
+    assert_eq!(0x01, pic.get_register());
+}
+
+#[test]
+fn can_prioritize_and_end_interrupts() {
+    let mut pic = PIC::default();
+    init_pic(&mut pic, 0x08, 0x04);
+
+    pic.request_irq(1);
+    assert_eq!(0x09, pic.acknowledge(1));
+
+    // lower priority request is blocked while IRQ 1 is in service
+    pic.request_irq(3);
+    assert_eq!(None, pic.pending_irq());
+
+    // higher priority request is let through
+    pic.request_irq(0);
+    assert_eq!(Some(0), pic.pending_irq());
+    assert_eq!(0x08, pic.acknowledge(0));
+
+    pic.set_command(0x20); // non-specific EOI, ends IRQ 0
+    assert_eq!(None, pic.pending_irq());
+    pic.set_command(0x61); // specific EOI for IRQ 1
+    assert_eq!(Some(3), pic.pending_irq());
+
+    // rotate priority so that IRQ 3 has the lowest priority
+    pic.request_irq(4);
+    pic.set_command(0xC3);
+    assert_eq!(Some(4), pic.pending_irq());
+}
+
+#[test]
+fn can_poll_pic() {
+    let mut pic = PIC::default();
+    init_pic(&mut pic, 0x08, 0x04);
+    pic.request_irq(5);
+    pic.set_command(0x0C); // OCW3: poll
+    assert_eq!(0x85, pic.get_register());
+    pic.set_command(0x0B); // OCW3: read ISR
+    assert_eq!(0x20, pic.get_register());
+}
+
+#[test]
+fn can_acknowledge_cascaded_irq() {
+    let mut machine = Machine::default();
+    machine.hw.pic2.set_data(0x00); // unmask all slave IRQs
+    machine.hw.raise_irq(12);
+    assert_eq!(Some(0x74), machine.hw.acknowledge_interrupt());
+    assert_eq!(None, machine.hw.acknowledge_interrupt());
+
+    // EOI to both slave and master
+    machine.hw.out_u8(0xA0, 0x20);
+    machine.hw.out_u8(0x20, 0x20);
+    machine.hw.raise_irq(12);
+    assert_eq!(Some(0x74), machine.hw.acknowledge_interrupt());
+}
+
+#[test]
+fn can_deliver_keyboard_irq() {
+    let mut machine = Machine::default();
+    let code: Vec<u8> = vec![
+        0xFA,             // cli
+        0x90,             // nop
+        0xFB,             // sti
+        0x90,             // nop
+    ];
+    machine.load_executable(&code);
+    machine.execute_instruction(); // cli
+    machine.key_down(0x2A); // left shift
+
+    // IRQ 1 is held back while interrupts are disabled
+    machine.execute_instruction(); // nop
+    assert_eq!(0x0102, machine.cpu.regs.ip);
+    assert_eq!(0x00, machine.hw.mmu.read_u8(BIOS::DATA_SEG, BIOS::DATA_KBD_FLAGS1));
+
+    machine.execute_instruction(); // sti
+    machine.execute_instruction(); // INT 09h by IRQ 1
+    assert_eq!(0x0103, machine.cpu.regs.ip);
+    assert_eq!(true, machine.cpu.regs.flags.interrupt);
+    assert_eq!(0x02, machine.hw.mmu.read_u8(BIOS::DATA_SEG, BIOS::DATA_KBD_FLAGS1));
+}
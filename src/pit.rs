@@ -4,28 +4,36 @@
 // A 8253/8254 chip that runs at 18.2065 Hz (or an IRQ every 54.9254 ms)
 // with the default divisor of 0x1_0000
 
-use std::num::Wrapping;
-
 #[cfg(test)]
 #[path = "./pit_test.rs"]
 mod pit_test;
 
+/// input clock of the counters
+pub const PIT_HZ: usize = 1_193_182;
+
 #[derive(Clone)]
 pub struct PIT {
     pub counter0: Counter,
     pub counter1: Counter,
     pub counter2: Counter,
-    //divisor: u32, // XXX size?!?!
+
+    /// remainder of emulated time not yet clocked into the counters, in units of 1/clock_hz PIT clocks
+    fraction: u64,
 }
 
 impl PIT {
     pub fn default() -> Self {
-        PIT {
+        let mut pit = PIT {
             counter0: Counter::new(0),
             counter1: Counter::new(1),
             counter2: Counter::new(2),
-            //divisor: 0x1_0000, // XXX
-        }
+            fraction: 0,
+        };
+        // the BIOS programs counter 0 as square wave generator with divisor 0x1_0000
+        pit.set_mode_command(0b0011_0110);
+        pit.counter0.write_reload_part(0);
+        pit.counter0.write_reload_part(0);
+        pit
     }
 
     fn counter(&mut self, n: u8) -> &mut Counter {
@@ -37,15 +45,55 @@ impl PIT {
         }
     }
 
+    /// advances the counters by the emulated time of `cycles` cpu cycles at `clock_hz`.
+    /// returns true if the output of counter 0 (connected to IRQ 0) had a rising edge
+    pub fn advance(&mut self, cycles: usize, clock_hz: usize) -> bool {
+        self.fraction += cycles as u64 * PIT_HZ as u64;
+        let ticks = self.fraction / clock_hz as u64;
+        self.fraction %= clock_hz as u64;
+        if ticks == 0 {
+            return false;
+        }
+        self.counter1.tick(ticks);
+        self.counter2.tick(ticks);
+        self.counter0.tick(ticks)
+    }
+
+    /// returns the cpu cycles at clock_hz until the output of counter 0 has a rising edge,
+    /// None if the counter is stopped
+    pub fn cycles_to_irq(&self, clock_hz: usize) -> Option<usize> {
+        let clocks = self.counter0.clocks_to_edge()?;
+        let time = clocks * clock_hz as u64 - self.fraction;
+        Some(((time + PIT_HZ as u64 - 1) / PIT_HZ as u64) as usize)
+    }
+
     /// port 0043: control word register for counters 0-2
     /// called "8253/8254 PIT mode control word" in the interrupt list
     pub fn set_mode_command(&mut self, val: u8) {
         let channel = (val >> 6) & 0b11; // bits 7-6
         let access_mode = (val >> 4) & 0b11; // bits 5-4
-        let operating_mode = (val >> 1) & 0b11; // bits 3-1
+        let operating_mode = (val >> 1) & 0b111; // bits 3-1
         let bcd_mode = val & 1; // bit 0
         if channel == 3 {
-            panic!("TODO channel == 3: Read-back command (8254 only)");
+            // Read-back command (8254 only)
+            // bit 5 = 0 latch count, bit 4 = 0 latch status, bits 3-1 = counter 2-0 select
+            for n in 0..3 {
+                if val & (2 << n) != 0 {
+                    let counter = self.counter(n);
+                    if val & 0x10 == 0 {
+                        counter.latch_status();
+                    }
+                    if val & 0x20 == 0 {
+                        counter.latch_count();
+                    }
+                }
+            }
+            return;
+        }
+        if access_mode == 0 {
+            // Counter Latch Command
+            self.counter(channel).latch_count();
+            return;
         }
         self.counter(channel).set_mode(access_mode, operating_mode, bcd_mode);
     }
@@ -53,13 +101,41 @@ impl PIT {
 
 #[derive(Clone)]
 pub struct Counter {
-    pub count: u16,
+    /// current count, as a binary number also in BCD mode
+    count: u32,
+
+    /// the value last written to the counter
     pub reload: u16,
-    latch: u16,
-    hi: bool,
+
+    /// the count in use for the running period of mode 2 and 3
+    period: u32,
+
+    /// latched count value, read before the counter
+    latch: Option<u16>,
+
+    /// latched status byte (8254 read-back command), read before the count
+    status: Option<u8>,
+
+    read_hi: bool,
+    write_hi: bool,
+
+    /// the counter is decrementing
+    counting: bool,
+
+    /// terminal count was not yet reached since the count was loaded (modes 0, 1, 4, 5)
+    armed: bool,
+
+    /// output is low for one clock in mode 4 and 5
+    strobe: bool,
+
+    /// a new count was written but not yet loaded into the counter
+    null_count: bool,
+
+    pub output: bool,
+    gate: bool,
     channel: u8, // 0-2, for debugging
 
-    // controlled by write to port 0040:
+    // controlled by write to port 0043:
     access_mode: AccessMode,
     operating_mode: OperatingMode,
     bcd_mode: BcdMode,
@@ -70,54 +146,229 @@ impl Counter {
         Counter {
             count: 0,
             reload: 0, // 0 = 0x10000
-            latch: 0,
-            hi: false,
+            period: 0x1_0000,
+            latch: None,
+            status: None,
+            read_hi: false,
+            write_hi: false,
+            counting: false,
+            armed: false,
+            strobe: false,
+            null_count: true,
+            output: false,
+            gate: true,
             channel,
 
-            access_mode: AccessMode::LoByteHiByte, // XXX default?
-            operating_mode: OperatingMode::Mode0, // XXX default?
-            bcd_mode: BcdMode::SixteenBitBinary, // XXX default?
+            access_mode: AccessMode::LoByteHiByte,
+            operating_mode: OperatingMode::Mode0,
+            bcd_mode: BcdMode::SixteenBitBinary,
         }
     }
 
-    pub fn dec(&mut self) {
-        // XXX channel 0 is connected to interrupt.
-        self.count = (Wrapping(self.count) - Wrapping(1)).0;
-        if self.count == 0 {
-            if self.reload == 0 {
-                self.count = 0xFFFF; // XXX off-by one, how to handle 0 == 0x1_0000 ?
-            } else {
-                self.count = self.reload;
-            }
+    /// number of counts until the counter wraps around
+    fn modulus(&self) -> u32 {
+        match self.bcd_mode {
+            BcdMode::SixteenBitBinary => 0x1_0000,
+            BcdMode::FourDigitBCD => 10_000,
         }
     }
 
-    pub fn get_next_u8(&mut self) -> u8 {
-        match self.access_mode {
-            AccessMode::LatchCountValue => {
-                // Counter Latch Command
-                let res = if self.hi {
-                    (self.latch >> 8) as u8
+    /// the reload value as a binary number, where 0 is the maximum count
+    fn initial_count(&self) -> u32 {
+        let val = match self.bcd_mode {
+            BcdMode::SixteenBitBinary => u32::from(self.reload),
+            BcdMode::FourDigitBCD => bcd_to_u32(self.reload),
+        };
+        if val == 0 {
+            self.modulus()
+        } else {
+            val
+        }
+    }
+
+    /// clocks the counter. returns true if the output had a rising edge
+    pub fn tick(&mut self, ticks: u64) -> bool {
+        if !self.counting || (!self.gate && self.operating_mode.gate_stops_counting()) {
+            return false;
+        }
+        match self.operating_mode {
+            OperatingMode::Mode0 | OperatingMode::Mode1 | OperatingMode::Mode4 | OperatingMode::Mode5 => {
+                let mut edge = false;
+                let mut ticks = ticks;
+                if self.strobe {
+                    // output was low for one clock
+                    self.strobe = false;
+                    self.output = true;
+                    edge = true;
+                }
+                if self.armed && u64::from(self.count) <= ticks {
+                    ticks -= u64::from(self.count);
+                    self.count = 0;
+                    self.armed = false;
+                    match self.operating_mode {
+                        OperatingMode::Mode0 | OperatingMode::Mode1 => {
+                            // output goes high on terminal count
+                            edge = edge || !self.output;
+                            self.output = true;
+                        }
+                        _ => {
+                            self.output = false;
+                            if ticks > 0 {
+                                self.output = true;
+                                edge = true;
+                            } else {
+                                self.strobe = true;
+                            }
+                        }
+                    }
+                }
+                // the counter wraps around after terminal count
+                let modulus = u64::from(self.modulus());
+                self.count = ((u64::from(self.count) + modulus - ticks % modulus) % modulus) as u32;
+                edge
+            }
+            OperatingMode::Mode2 | OperatingMode::Mode3 => {
+                // count holds the clocks remaining in the period, the
+                // output has a rising edge as the next period starts
+                let remaining = u64::from(self.count);
+                let edge = ticks >= remaining;
+                if edge {
+                    self.period = self.initial_count();
+                    self.null_count = false;
+                    let period = u64::from(self.period);
+                    self.count = (period - (ticks - remaining) % period) as u32;
                 } else {
-                    (self.latch & 0xFF) as u8
+                    self.count -= ticks as u32;
+                }
+                self.output = match self.operating_mode {
+                    OperatingMode::Mode2 => self.count != 1,
+                    _ => self.count > self.period / 2,
                 };
-                self.hi = !self.hi;
-                res
+                edge
             }
-            AccessMode::LoByteHiByte => {
-                let res = if self.hi {
-                    (self.count >> 8) as u8
+        }
+    }
+
+    /// returns the clocks until the output has a rising edge, None if no edge follows
+    /// without reprogramming the counter
+    pub fn clocks_to_edge(&self) -> Option<u64> {
+        if !self.counting || (!self.gate && self.operating_mode.gate_stops_counting()) {
+            return None;
+        }
+        let count = u64::from(self.count);
+        match self.operating_mode {
+            OperatingMode::Mode2 | OperatingMode::Mode3 => Some(count.max(1)),
+            _ if self.strobe => Some(1),
+            _ if !self.armed => None,
+            OperatingMode::Mode0 | OperatingMode::Mode1 => if self.output {
+                None
+            } else {
+                Some(count.max(1))
+            },
+            // the output is low for one clock at terminal count
+            _ => Some(count + 1),
+        }
+    }
+
+    /// sets the gate input. counter 2 is gated by port 0061 bit 0, the others are always high
+    pub fn set_gate(&mut self, high: bool) {
+        let rising = high && !self.gate;
+        self.gate = high;
+        match self.operating_mode {
+            OperatingMode::Mode1 | OperatingMode::Mode5 => if rising {
+                // trigger
+                self.count = self.initial_count();
+                self.null_count = false;
+                self.counting = true;
+                self.armed = true;
+                self.strobe = false;
+                self.output = self.operating_mode != OperatingMode::Mode1;
+            },
+            OperatingMode::Mode2 | OperatingMode::Mode3 => if !high {
+                self.output = true;
+            } else if rising && self.counting {
+                self.period = self.initial_count();
+                self.count = self.period;
+            },
+            _ => {}
+        }
+    }
+
+    pub fn gate(&self) -> bool {
+        self.gate
+    }
+
+    /// the value of the counting element as seen when reading the counter
+    fn read_count(&self) -> u16 {
+        let val = match self.operating_mode {
+            OperatingMode::Mode3 => {
+                // the count decrements by two, in each half of the period
+                let half = self.period / 2;
+                let val = if self.count > half {
+                    (self.count - half) * 2
                 } else {
-                    (self.count & 0xFF) as u8
+                    self.count * 2
                 };
-                self.hi = !self.hi;
-                res
+                val & !1
+            }
+            _ => self.count,
+        } % self.modulus();
+        match self.bcd_mode {
+            BcdMode::SixteenBitBinary => val as u16,
+            BcdMode::FourDigitBCD => u32_to_bcd(val),
+        }
+    }
+
+    /// latches the current count until it is read
+    pub fn latch_count(&mut self) {
+        if self.latch.is_none() {
+            self.latch = Some(self.read_count());
+            self.read_hi = false;
+        }
+    }
+
+    /// latches the status byte until it is read
+    pub fn latch_status(&mut self) {
+        if self.status.is_none() {
+            let mut status = (self.access_mode.clone() as u8) << 4
+                | (self.operating_mode.clone() as u8) << 1
+                | self.bcd_mode.clone() as u8;
+            if self.output {
+                status |= 0x80;
             }
+            if self.null_count {
+                status |= 0x40;
+            }
+            self.status = Some(status);
+        }
+    }
+
+    /// io read of port 0040-0042
+    pub fn get_next_u8(&mut self) -> u8 {
+        if let Some(status) = self.status.take() {
+            return status;
+        }
+        let val = match self.latch {
+            Some(latch) => latch,
+            None => self.read_count(),
+        };
+        match self.access_mode {
             AccessMode::LoByteOnly => {
-                panic!("AccessMode::LoByteOnly");
+                self.latch = None;
+                val as u8
             }
             AccessMode::HiByteOnly => {
-                panic!("AccessMode::HiByteOnly");
+                self.latch = None;
+                (val >> 8) as u8
+            }
+            AccessMode::LoByteHiByte => {
+                self.read_hi = !self.read_hi;
+                if self.read_hi {
+                    val as u8
+                } else {
+                    self.latch = None;
+                    (val >> 8) as u8
+                }
             }
         }
     }
@@ -125,38 +376,62 @@ impl Counter {
     /// sets the reload value for the counter
     pub fn write_reload_part(&mut self, val: u8) {
         match self.access_mode {
-            AccessMode::LatchCountValue => {
-                panic!("AccessMode::LatchCountValue");
-            }
             AccessMode::LoByteHiByte => {
-                self.reload = if self.hi {
-                    (self.reload & 0x00FF) | (u16::from(val) << 8)
+                self.write_hi = !self.write_hi;
+                if self.write_hi {
+                    self.reload = (self.reload & 0xFF00) | u16::from(val);
+                    if self.operating_mode == OperatingMode::Mode0 {
+                        // writing the first byte stops the count in mode 0
+                        self.counting = false;
+                    }
                 } else {
-                    (self.reload & 0xFF00) | u16::from(val)
-                };
-                self.hi = !self.hi;
+                    self.reload = (self.reload & 0x00FF) | (u16::from(val) << 8);
+                    self.load();
+                }
             }
             AccessMode::LoByteOnly => {
-                self.reload = (self.reload & 0xFF00) | u16::from(val);
+                self.reload = u16::from(val);
+                self.load();
             }
             AccessMode::HiByteOnly => {
-                self.reload = (self.reload & 0x00FF) | (u16::from(val) << 8);
+                self.reload = u16::from(val) << 8;
+                self.load();
             }
         }
     }
 
+    /// a new count was written
+    fn load(&mut self) {
+        self.null_count = true;
+        match self.operating_mode {
+            OperatingMode::Mode0 | OperatingMode::Mode4 => {
+                self.count = self.initial_count();
+                self.null_count = false;
+                self.counting = true;
+                self.armed = true;
+                self.strobe = false;
+                self.output = self.operating_mode == OperatingMode::Mode4;
+            }
+            OperatingMode::Mode1 | OperatingMode::Mode5 => {
+                // the count is loaded on the next gate trigger
+            }
+            OperatingMode::Mode2 | OperatingMode::Mode3 => if !self.counting {
+                self.period = self.initial_count();
+                self.count = self.period;
+                self.null_count = false;
+                self.counting = true;
+                self.output = true;
+            },
+        }
+    }
+
     pub fn set_mode(&mut self, access_mode: u8, operating_mode: u8, bcd_mode: u8) {
         // println!("pit {}: set_mode_command access {:?}, operating {:?}, bcd {:?}", self.channel, access_mode, operating_mode, bcd_mode);
         self.access_mode = match access_mode {
-            0 => {
-                // prepare current count value in the latch register
-                self.latch = self.count;
-                AccessMode::LatchCountValue
-            },
             1 => AccessMode::LoByteOnly,
             2 => AccessMode::HiByteOnly,
             3 => AccessMode::LoByteHiByte,
-            _ => panic!("TODO Latch count value command"),
+            _ => unreachable!(),
         };
         self.operating_mode = match operating_mode {
             0 => OperatingMode::Mode0,
@@ -169,32 +444,71 @@ impl Counter {
         };
         self.bcd_mode = match bcd_mode {
             0 => BcdMode::SixteenBitBinary,
-            //1 => BcdMode::FourDigitBCD,
-            _ => panic!("TODO BCD mode"),
+            1 => BcdMode::FourDigitBCD,
+            _ => unreachable!(),
         };
+        // writing the control word stops the counter until a new count is written
+        self.output = self.operating_mode != OperatingMode::Mode0;
+        self.counting = false;
+        self.armed = false;
+        self.strobe = false;
+        self.null_count = true;
+        self.latch = None;
+        self.status = None;
+        self.read_hi = false;
+        self.write_hi = false;
     }
 }
 
+/// decodes a four-digit BCD value
+fn bcd_to_u32(val: u16) -> u32 {
+    let mut res = 0;
+    for shift in &[12, 8, 4, 0] {
+        res = res * 10 + u32::from((val >> shift) & 0xF);
+    }
+    res
+}
+
+/// encodes val (0-9999) as four-digit BCD
+fn u32_to_bcd(val: u32) -> u16 {
+    let mut res = 0;
+    let mut val = val;
+    for shift in &[0, 4, 8, 12] {
+        res |= ((val % 10) as u16) << shift;
+        val /= 10;
+    }
+    res
+}
+
 #[derive(Clone, Debug)]
 enum AccessMode {
-    LatchCountValue,
-    LoByteOnly,
-    HiByteOnly,
-    LoByteHiByte,
+    LoByteOnly = 1,
+    HiByteOnly = 2,
+    LoByteHiByte = 3,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 enum OperatingMode {
-    Mode0, // Mode 0 (interrupt on terminal count)
-    Mode1, // Mode 1 (hardware re-triggerable one-shot)
-    Mode2, // Mode 2 (rate generator)
-    Mode3, // Mode 3 (square wave generator)
-    Mode4, // Mode 4 (software triggered strobe)
-    Mode5, // Mode 5 (hardware triggered strobe)
+    Mode0 = 0, // Mode 0 (interrupt on terminal count)
+    Mode1 = 1, // Mode 1 (hardware re-triggerable one-shot)
+    Mode2 = 2, // Mode 2 (rate generator)
+    Mode3 = 3, // Mode 3 (square wave generator)
+    Mode4 = 4, // Mode 4 (software triggered strobe)
+    Mode5 = 5, // Mode 5 (hardware triggered strobe)
+}
+
+impl OperatingMode {
+    /// a low gate input pauses counting in these modes
+    fn gate_stops_counting(&self) -> bool {
+        match *self {
+            OperatingMode::Mode0 | OperatingMode::Mode2 | OperatingMode::Mode3 | OperatingMode::Mode4 => true,
+            _ => false,
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
 enum BcdMode {
-    SixteenBitBinary,   // 16-bit binary
-    FourDigitBCD,       // four-digit BCD
+    SixteenBitBinary = 0,   // 16-bit binary
+    FourDigitBCD = 1,       // four-digit BCD
 }
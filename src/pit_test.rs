@@ -1,4 +1,6 @@
 use machine::Machine;
+use pit::{PIT, PIT_HZ};
+use bios::BIOS;
 
 #[test]
 fn can_execute_pit_set_reload_value() {
@@ -16,3 +18,138 @@ fn can_execute_pit_set_reload_value() {
 
     assert_eq!(0x2244, machine.hw.pit.counter0.reload);
 }
+
+#[test]
+fn can_count_in_all_modes() {
+    let mut pit = PIT::default();
+
+    // mode 0: interrupt on terminal count
+    pit.set_mode_command(0b1011_0000); // counter 2, lobyte/hibyte, mode 0
+    pit.counter2.write_reload_part(10);
+    pit.counter2.write_reload_part(0);
+    assert_eq!(false, pit.counter2.output);
+    assert_eq!(false, pit.counter2.tick(9));
+    assert_eq!(true, pit.counter2.tick(1));
+    assert_eq!(true, pit.counter2.output);
+    assert_eq!(false, pit.counter2.tick(0x1_0000));
+
+    // mode 1: hardware re-triggerable one-shot
+    pit.set_mode_command(0b1011_0010);
+    pit.counter2.write_reload_part(5);
+    pit.counter2.write_reload_part(0);
+    pit.counter2.set_gate(false);
+    assert_eq!(false, pit.counter2.tick(10));
+    pit.counter2.set_gate(true);
+    assert_eq!(false, pit.counter2.output);
+    assert_eq!(true, pit.counter2.tick(5));
+
+    // mode 2: rate generator
+    pit.set_mode_command(0b1011_0100);
+    pit.counter2.write_reload_part(4);
+    pit.counter2.write_reload_part(0);
+    assert_eq!(false, pit.counter2.tick(2));
+    assert_eq!(false, pit.counter2.tick(1));
+    assert_eq!(false, pit.counter2.output);
+    assert_eq!(true, pit.counter2.tick(1));
+    assert_eq!(true, pit.counter2.output);
+    assert_eq!(true, pit.counter2.tick(8));
+
+    // mode 3: square wave generator
+    pit.set_mode_command(0b1011_0110);
+    pit.counter2.write_reload_part(6);
+    pit.counter2.write_reload_part(0);
+    assert_eq!(false, pit.counter2.tick(2));
+    assert_eq!(true, pit.counter2.output);
+    assert_eq!(false, pit.counter2.tick(1));
+    assert_eq!(false, pit.counter2.output);
+    assert_eq!(true, pit.counter2.tick(3));
+    assert_eq!(true, pit.counter2.output);
+
+    // mode 4: software triggered strobe
+    pit.set_mode_command(0b1011_1000);
+    pit.counter2.write_reload_part(3);
+    pit.counter2.write_reload_part(0);
+    assert_eq!(false, pit.counter2.tick(3));
+    assert_eq!(false, pit.counter2.output);
+    assert_eq!(true, pit.counter2.tick(1));
+    assert_eq!(false, pit.counter2.tick(0x1_0000));
+
+    // mode 5: hardware triggered strobe
+    pit.set_mode_command(0b1011_1010);
+    pit.counter2.write_reload_part(3);
+    pit.counter2.write_reload_part(0);
+    pit.counter2.set_gate(false);
+    pit.counter2.set_gate(true);
+    assert_eq!(true, pit.counter2.tick(4));
+}
+
+#[test]
+fn can_latch_and_read_bcd_count() {
+    let mut pit = PIT::default();
+    pit.set_mode_command(0b1011_0001); // counter 2, lobyte/hibyte, mode 0, BCD
+    pit.counter2.write_reload_part(0x00);
+    pit.counter2.write_reload_part(0x10); // 1000
+    pit.counter2.tick(1);
+
+    pit.set_mode_command(0b1000_0000); // latch counter 2
+    pit.counter2.tick(10);
+    assert_eq!(0x99, pit.counter2.get_next_u8());
+    assert_eq!(0x09, pit.counter2.get_next_u8());
+    assert_eq!(0x89, pit.counter2.get_next_u8());
+    assert_eq!(0x09, pit.counter2.get_next_u8());
+
+    // read-back command: latch status of counter 2
+    pit.set_mode_command(0b1110_1000);
+    assert_eq!(0b0011_0001, pit.counter2.get_next_u8());
+}
+
+#[test]
+fn can_raise_timer_irq() {
+    let mut machine = Machine::default();
+    machine.cpu.deterministic = true;
+    let code: Vec<u8> = vec![
+        0xEB, 0xFE,         // jmp short 0x100
+    ];
+    machine.load_executable(&code);
+    assert_eq!(0, machine.hw.mmu.read_u32(BIOS::DATA_SEG, BIOS::DATA_TIMER_TICKS));
+
+    // 0x1_0000 PIT clocks is 1 tick
+    let cycles_per_tick = machine.cpu.clock_hz * 0x1_0000 / PIT_HZ + 1;
+    while machine.cpu.cycle_count < cycles_per_tick {
+        machine.execute_instruction();
+    }
+    machine.execute_instruction();
+    assert_eq!(1, machine.hw.mmu.read_u32(BIOS::DATA_SEG, BIOS::DATA_TIMER_TICKS));
+    // the handler continues in the ROM with INT 1Ch and the end of interrupt
+    machine.execute_instructions(7);
+    assert_eq!(0x0100, machine.cpu.regs.ip);
+
+    while machine.cpu.cycle_count < 2 * cycles_per_tick {
+        machine.execute_instruction();
+    }
+    machine.execute_instruction();
+    assert_eq!(2, machine.hw.mmu.read_u32(BIOS::DATA_SEG, BIOS::DATA_TIMER_TICKS));
+}
+
+#[test]
+fn can_compute_cycles_to_irq() {
+    let clock_hz = 1_000_000;
+    let mut pit = PIT::default();
+    let cycles = pit.cycles_to_irq(clock_hz).unwrap();
+    assert_eq!((0x1_0000 * clock_hz + PIT_HZ - 1) / PIT_HZ, cycles);
+
+    assert_eq!(false, pit.advance(100, clock_hz));
+    let cycles = pit.cycles_to_irq(clock_hz).unwrap();
+    assert_eq!(false, pit.advance(cycles - 1, clock_hz));
+    assert_eq!(Some(1), pit.cycles_to_irq(clock_hz));
+    assert_eq!(true, pit.advance(1, clock_hz));
+
+    // mode 0 has no edge after terminal count
+    pit.set_mode_command(0b0011_0000);
+    pit.counter0.write_reload_part(100);
+    pit.counter0.write_reload_part(0);
+    assert_eq!(Some(100), pit.counter0.clocks_to_edge());
+    pit.counter0.tick(100);
+    assert_eq!(None, pit.counter0.clocks_to_edge());
+    assert_eq!(None, pit.cycles_to_irq(clock_hz));
+}